@@ -0,0 +1,64 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Compression and decompression throughput of every method on the
+//! samples from `campross::bench::corpus`.  Before the measurements,
+//! a table of the compressed sizes is printed.
+//!
+//! Run with `cargo bench --features bench`.  A method name given
+//! after `--` restricts the run to that method, for example
+//! `cargo bench --features bench -- lzss/`.
+
+extern crate campross;
+extern crate criterion;
+
+use criterion::{Criterion, Throughput};
+
+use campross::bench::{self, Sample};
+use campross::method::Method;
+
+fn load() -> Vec<Sample> {
+    bench::corpus().expect("cannot load the samples")
+}
+
+fn ratios(_c: &mut Criterion) {
+    let table = bench::ratio_table(&Method::all(), &load()).unwrap();
+    println!("Compressed size in bits per byte:\n{}", table);
+}
+
+fn compress(c: &mut Criterion) {
+    let samples = load();
+    for m in Method::all() {
+        let mut group = c.benchmark_group(format!("compress/{}", m.name()));
+        for s in &samples {
+            group.throughput(Throughput::Bytes(s.data.len() as u64));
+            group.bench_function(&s.name[..], |b| {
+                b.iter(|| m.compress(&s.data[..], Vec::new()).unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+fn decompress(c: &mut Criterion) {
+    let samples = load();
+    for m in Method::all() {
+        let mut group = c.benchmark_group(format!("decompress/{}", m.name()));
+        for s in &samples {
+            let compressed = m.compress(&s.data[..], Vec::new()).unwrap();
+            group.throughput(Throughput::Bytes(s.data.len() as u64));
+            group.bench_function(&s.name[..], |b| {
+                b.iter(|| m.decompress(&compressed[..], Vec::new()).unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+fn main() {
+    let mut c = Criterion::default().configure_from_args();
+    ratios(&mut c);
+    compress(&mut c);
+    decompress(&mut c);
+    c.final_summary();
+}
@@ -0,0 +1,118 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Throughput of the building blocks shared by the codecs: the match
+//! finders at each compression level, and writing and reading codes
+//! with the bit I/O.
+//!
+//! Run with `cargo bench --features bench`.
+
+extern crate campross;
+extern crate criterion;
+
+use criterion::{black_box, Criterion, Throughput};
+
+use campross::bench;
+use campross::bitfile::{BitReader, BitWriter};
+use campross::window::{MatchFinder, MAX_LEVEL};
+
+const WINDOW_SIZE: usize = 1 << 16;
+const HASH_BITS: usize = 16;
+const MAX_LEN: usize = 258;
+
+/// Run a match finder over `data`, skipping over the matches it
+/// finds like a greedy parser, and return the number of matches.
+fn parse(finder: &mut MatchFinder, window: &mut [u8], data: &[u8]) -> usize {
+    let mask = window.len() - 1;
+    let mut matches = 0;
+    let mut i = 0;
+    let mut filled = 0;
+    while i + MAX_LEN < data.len() {
+        while filled < i + MAX_LEN {
+            window[filled & mask] = data[filled];
+            filled += 1;
+        }
+        let (len, _) = finder.find(window, i & mask, MAX_LEN, mask - MAX_LEN);
+        if len >= 3 {
+            for j in 1..len {
+                finder.skip(window, (i + j) & mask, MAX_LEN, mask - MAX_LEN);
+            }
+            matches += 1;
+            i += len;
+        } else {
+            i += 1;
+        }
+    }
+    matches
+}
+
+fn match_finders(c: &mut Criterion) {
+    let samples = bench::embedded();
+    let mut group = c.benchmark_group("match_finder");
+    for level in 1..MAX_LEVEL + 1 {
+        for s in samples.iter().filter(|s| s.name == "text" || s.name == "records") {
+            group.throughput(Throughput::Bytes(s.data.len() as u64));
+            group.bench_function(format!("level{}/{}", level, s.name), |b| {
+                b.iter(|| {
+                    let mut finder = MatchFinder::with_level(WINDOW_SIZE, HASH_BITS, level);
+                    let mut window = vec![0; WINDOW_SIZE];
+                    parse(&mut finder, &mut window, &s.data)
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Code lengths and values for the bit I/O benchmarks: mostly short
+/// codes, with some long ones.
+fn codes() -> Vec<(u64, usize)> {
+    let mut x: u32 = 1;
+    (0..100000).map(|_| {
+        x = x.wrapping_mul(1103515245).wrapping_add(12345);
+        let len = [3, 5, 7, 9, 12, 17, 25][(x >> 24) as usize % 7];
+        ((x >> 4) as u64 & ((1 << len) - 1), len)
+    }).collect()
+}
+
+fn bit_io(c: &mut Criterion) {
+    let codes = codes();
+    let bits: usize = codes.iter().map(|&(_, len)| len).sum();
+    let mut out = BitWriter::new(Vec::new());
+    for &(code, len) in &codes {
+        out.write_bits(code, len).unwrap();
+    }
+    out.do_flush().unwrap();
+    let written = out.to_inner();
+
+    let mut group = c.benchmark_group("bit_io");
+    group.throughput(Throughput::Bytes((bits / 8) as u64));
+    group.bench_function("write_bits", |b| {
+        b.iter(|| {
+            let mut out = BitWriter::new(Vec::with_capacity(written.len()));
+            for &(code, len) in &codes {
+                out.write_bits(code, len).unwrap();
+            }
+            out.do_flush().unwrap();
+            out.to_inner()
+        })
+    });
+    group.bench_function("read_bits", |b| {
+        b.iter(|| {
+            let mut inp = BitReader::new(&written[..]);
+            let mut sum = 0;
+            for &(_, len) in &codes {
+                sum += inp.read_bits(len).unwrap();
+            }
+            black_box(sum)
+        })
+    });
+    group.finish();
+}
+
+fn main() {
+    let mut c = Criterion::default().configure_from_args();
+    match_finders(&mut c);
+    bit_io(&mut c);
+    c.final_summary();
+}
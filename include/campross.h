@@ -0,0 +1,118 @@
+#ifndef CAMPROSS_H
+#define CAMPROSS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * The method is unknown or not available in this build.
+ */
+#define CAMPROSS_ERR_METHOD -1
+
+/**
+ * A pointer argument is null, or a stream is used after an error or
+ * after it was finished.
+ */
+#define CAMPROSS_ERR_ARGUMENT -2
+
+/**
+ * The output buffer is too small.
+ */
+#define CAMPROSS_ERR_BUFFER -3
+
+/**
+ * The compressed input is invalid or truncated.
+ */
+#define CAMPROSS_ERR_DATA -4
+
+/**
+ * Internal error in a codec.
+ */
+#define CAMPROSS_ERR_INTERNAL -5
+
+/**
+ * Streaming compressor or decompressor.
+ */
+typedef struct campross_stream campross_stream;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Return the id of the method called `name`, or
+ * `CAMPROSS_ERR_METHOD` if there is no such method in this build.
+ */
+int campross_method_id(const char *name);
+
+/**
+ * Compress the `in_len` bytes at `in_ptr` with the method `method`
+ * into the buffer of `out_cap` bytes at `out_ptr`, and return the
+ * compressed length.  When `out_ptr` is null, only the compressed
+ * length is returned.
+ */
+ptrdiff_t campross_compress(int method,
+                            const uint8_t *in_ptr,
+                            size_t in_len,
+                            uint8_t *out_ptr,
+                            size_t out_cap);
+
+/**
+ * Decompress the `in_len` bytes at `in_ptr`, which were compressed
+ * with the method `method`, into the buffer of `out_cap` bytes at
+ * `out_ptr`, and return the decompressed length.  When `out_ptr` is
+ * null, only the decompressed length is returned.
+ */
+ptrdiff_t campross_decompress(int method,
+                              const uint8_t *in_ptr,
+                              size_t in_len,
+                              uint8_t *out_ptr,
+                              size_t out_cap);
+
+/**
+ * Create a stream which compresses with the method `method`, or
+ * return null if the method is not available.  The stream must be
+ * released with `campross_stream_free`.
+ */
+campross_stream *campross_stream_compress(int method);
+
+/**
+ * Create a stream which decompresses a container written by a
+ * compressing stream.  The stream must be released with
+ * `campross_stream_free`.
+ */
+campross_stream *campross_stream_decompress(void);
+
+/**
+ * Pass the `in_len` bytes at `in_ptr` to the stream.  Returns zero
+ * or an error code.  After an error, only the output produced before
+ * can be read.
+ */
+ptrdiff_t campross_stream_update(campross_stream *stream, const uint8_t *in_ptr, size_t in_len);
+
+/**
+ * Signal the end of the input.  A compressing stream writes its last
+ * block and the end of the container; a decompressing stream checks
+ * that the container was complete.  Returns zero or an error code.
+ */
+ptrdiff_t campross_stream_finish(campross_stream *stream);
+
+/**
+ * Move up to `out_cap` bytes of output from the stream to `out_ptr`,
+ * and return their number.  Output is produced by
+ * `campross_stream_update` and `campross_stream_finish`, and can be
+ * taken out at any time.  With a null `out_ptr`, the number of bytes
+ * waiting is returned.
+ */
+ptrdiff_t campross_stream_read(campross_stream *stream, uint8_t *out_ptr, size_t out_cap);
+
+/**
+ * Release a stream.  Null is ignored.
+ */
+void campross_stream_free(campross_stream *stream);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* CAMPROSS_H */
@@ -4,16 +4,33 @@
 //! Simple implementation of an arithmetic coder.
 //!
 //! Implementation based on http://marknelson.us/2014/10/19/data-compression-with-arithmetic-coding/
+//!
+//! The coder uses an adaptive order-0 model by default, but any
+//! implementation of `model::Model` can be used instead with
+//! `compress_with_model` and `decompress_with_model`.
+//!
+//! In semi-static mode (`compress_static` and `decompress_static`),
+//! the symbol frequencies are counted before encoding and stored in
+//! a header in front of the compressed data.  The header starts with
+//! a 32-byte bitmap of the byte values which occur, followed by the
+//! scaled frequency of each of these values as a LEB128 number.
+//!
+//! `inspect` lists the literals of a stream compressed with the
+//! adaptive order-0 model.  The stream has neither a header nor
+//! blocks.
 
-use std::io::{Read, Write};
+use io::{Read, Write};
+use io;
+use alloc::vec::Vec;
 
 use bitfile::{BitReader, BitWriter};
-use error::Error;
+use error::{at_offset, Error};
+use inspect::{Event, Events, Inspect, Recorder};
+use model::{Model, Prob, Symbol, EOF, SYMBOLS, MAX_TOTAL};
+use model::{write_varint, read_varint, read_tag};
+use stats;
 
-type Symbol = u16;
-
-const EOF:  Symbol = 256;
-const SYM_CNT: usize = (EOF + 1) as usize;
+const SYM_CNT: usize = SYMBOLS;
 
 // https://sachingarg.com/compression/entropy_coding/64bit/ says we
 // can use up to 0x3fff_ffff as the maximum frequency on 64 bit
@@ -27,39 +44,74 @@ const ONE_FOURTH: u64    = 0x4000_0000;
 const THREE_FOURTHS: u64 = 0xC000_0000;
 const MAX_CODE: u64      = 0xffff_ffff;
 
-    #[derive(Debug,PartialEq)]
-struct Prob {
-    low:   u64,
-    high:  u64,
+/// Tag of serialized `State` models.
+const MODEL_TAG: u8 = b'A';
+
+/// Largest power of two not greater than `SYM_CNT`, the first step
+/// of the binary search in the frequency tree.
+const TREE_TOP: usize = 256;
+
+/// The adaptive order-0 model used by default.  It keeps a count for
+/// every symbol, and the cumulative counts in a binary indexed tree
+/// (Fenwick tree), so that both updating a count and finding the
+/// symbol for a cumulative count take logarithmic time.
+#[derive(Clone)]
+pub struct State {
+    counts: [u64; SYM_CNT],
+    /// Binary indexed tree over `counts`, indexed from 1:
+    /// `tree[i]` holds the sum of the counts of the `i & -i` symbols
+    /// ending with symbol `i - 1`.
+    tree: [u64; SYM_CNT + 1],
     total: u64,
 }
 
-struct State {
-    freqs: [u64; SYM_CNT + 1],
-}
-
 impl State {
-    // Create a new state of the arithmetic coder.
-    fn new() -> State {
+    /// Create a new state of the arithmetic coder, in which all
+    /// symbols are equally likely.
+    pub fn new() -> State {
         let mut st = State {
-            freqs: [0; (SYM_CNT + 1) as usize],
+            counts: [1; SYM_CNT],
+            tree: [0; SYM_CNT + 1],
+            total: 0,
         };
-        for i in 0..SYM_CNT + 1 {
-            st.freqs[i] = i as u64;
-        }
+        st.rebuild();
         st
     }
 
     fn get_count(&self) -> u64 {
-        self.freqs[SYM_CNT]
+        self.total
+    }
+
+    /// Recompute the tree and the total from the symbol counts.
+    fn rebuild(&mut self) {
+        for i in 1..SYM_CNT + 1 {
+            self.tree[i] = self.counts[i - 1];
+        }
+        for i in 1..SYM_CNT + 1 {
+            let parent = i + (i & i.wrapping_neg());
+            if parent <= SYM_CNT {
+                self.tree[parent] += self.tree[i];
+            }
+        }
+        self.total = self.counts.iter().sum();
+    }
+
+    /// Return the sum of the counts of all symbols below `sym`.
+    fn cumulative(&self, sym: Symbol) -> u64 {
+        let mut sum = 0;
+        let mut i = sym;
+        while i > 0 {
+            sum += self.tree[i];
+            i &= i - 1;
+        }
+        sum
     }
 
+    #[cfg(feature = "std")]
     fn debug_print(&self) {
         for i in 0..SYM_CNT {
             let mut bar = String::new();
-            let low = self.freqs[i as usize];
-            let high = self.freqs[(i + 1) as usize];
-            let range = high - low;
+            let range = self.counts[i];
             for _ in 0..(range) {
                 bar.push_str("#");
             }
@@ -67,37 +119,70 @@ impl State {
         }
     }
     
-    fn preload(&mut self, counts: &[(u8, u64)]) {
-        for &(s, c) in counts {
-            for _ in 0..c {
-                let _ = self.get_prob_and_update(s as Symbol);
+    /// Write the symbol counts to `output`, so that they can be
+    /// installed into another coder with `load_model`.
+    pub fn serialize_model<W: Write>(&self, mut output: W) -> io::Result<()> {
+        try!(output.write_all(&[MODEL_TAG]));
+        for &c in self.counts.iter() {
+            try!(write_varint(&mut output, c));
+        }
+        Ok(())
+    }
+
+    /// Read a model written by `serialize_model`.
+    pub fn load_model<R: Read>(mut input: R) -> io::Result<State> {
+        try!(read_tag(&mut input, MODEL_TAG));
+        let mut st = State::new();
+        for c in st.counts.iter_mut() {
+            *c = try!(read_varint(&mut input));
+            if *c == 0 || *c >= MAX_FREQ {
+                return Err(Error::corrupt("invalid arith model").into());
             }
         }
+        st.rebuild();
+        if st.total >= MAX_FREQ {
+            return Err(Error::corrupt("invalid arith model").into());
+        }
+        Ok(st)
+    }
+
+    /// Add the given symbol counts to the model, as if the symbols
+    /// had been seen that often, and scale the model down until it
+    /// is within the frequency limit again.  The result does not
+    /// depend on the order of `counts`.
+    pub fn preload(&mut self, counts: &[(u8, u64)]) {
+        for &(s, c) in counts {
+            let s = s as usize;
+            self.counts[s] = self.counts[s].saturating_add(c);
+        }
+        while self.counts.iter().fold(0, |t: u64, &c| t.saturating_add(c)) >= MAX_FREQ {
+            self.halve_counts();
+        }
+        self.rebuild();
     }
     
     /// Return the probability range for symbol `sym`. Also update the
     /// symbol frequency of `sym`, adapting the model to the symbols
     /// seen.
+    #[cfg(test)]
     fn get_prob_and_update(&mut self, sym: Symbol) -> Prob {
-        let p = Prob {
-            low: self.freqs[sym as usize],
-            high: self.freqs[(sym+1) as usize],
-            total: self.freqs[SYM_CNT],
-        };
+        let p = self.prob(sym);
         self.update(sym);
         p
     }
 
     /// Increase the count for symbol `sym`, updating the cumulative
     /// frequencies accordingly.
-    fn update(&mut self, sym: Symbol) {
-        // Update all cumulative frequencies for the symbol `sym` and
-        // the following symbols.
-        for i in (sym as usize) + 1..(SYM_CNT + 1) {
-            self.freqs[i] += 1;
+    fn increment(&mut self, sym: Symbol) {
+        self.counts[sym] += 1;
+        self.total += 1;
+        let mut i = sym + 1;
+        while i <= SYM_CNT {
+            self.tree[i] += 1;
+            i += i & i.wrapping_neg();
         }
         // Bound the cumulative frequencies to avoid overflow.
-        if self.freqs[SYM_CNT] >= MAX_FREQ {
+        if self.total >= MAX_FREQ {
             self.downscale();
         }
     }
@@ -105,44 +190,465 @@ impl State {
     /// Scale down all frequencies by a half.  This is needed to avoid
     /// overflow on cumulative character counts.
     fn downscale(&mut self) {
-        // 1. Convert from cumulative frequencies to individual
-        // frequencies.
-        for i in 1..SYM_CNT {
-            self.freqs[SYM_CNT - i] -= self.freqs[SYM_CNT - i - 1];
-        }
-        self.freqs[SYM_CNT] = 1;
-        // 2. Halve each frequency, making sure it never drops below
-        // 1.
-        for i in 1..SYM_CNT {
-            if self.freqs[i] > 1 {
-                self.freqs[i] /= 2;
+        self.halve_counts();
+        self.rebuild();
+    }
+
+    /// Halve each frequency, making sure it never drops below 1.
+    /// The EOF symbol is reset to 1.
+    fn halve_counts(&mut self) {
+        for c in self.counts[..SYM_CNT - 1].iter_mut() {
+            if *c > 1 {
+                *c /= 2;
             }
         }
-        // 3. Convert back to cumulative frequencies.
-        for i in 1..SYM_CNT + 1 {
-            self.freqs[i] += self.freqs[i - 1];
-        }
+        self.counts[EOF] = 1;
     }
 
     /// Determine the next encoded symbol from `scaled_value`, and
     /// return it together with its range bounds.
+    #[cfg(test)]
     fn get_symbol_and_update(&mut self, scaled_value: u64) -> (Prob, Symbol) {
-        for i in 0..SYM_CNT {
-            if scaled_value < self.freqs[i + 1] {
-                let sym = i as Symbol;
-                let prob = Prob {low: self.freqs[i],
-                                 high: self.freqs[i + 1],
-                                 total: self.freqs[SYM_CNT]};
-                self.update(sym);
-                return (prob, sym);
+        let sym = self.symbol_for(scaled_value);
+        let prob = self.prob(sym);
+        self.update(sym);
+        (prob, sym)
+    }
+
+}
+
+impl Model for State {
+    fn prob(&self, sym: Symbol) -> Prob {
+        let low = self.cumulative(sym);
+        Prob {
+            low: low,
+            high: low + self.counts[sym],
+            total: self.total,
+        }
+    }
+
+    fn total(&self) -> u64 {
+        self.get_count()
+    }
+
+    fn symbol_for(&self, scaled_value: u64) -> Symbol {
+        // Descend the tree to find the largest number of leading
+        // symbols whose counts add up to at most `scaled_value`.
+        let mut pos = 0;
+        let mut rest = scaled_value;
+        let mut step = TREE_TOP;
+        while step > 0 {
+            if pos + step <= SYM_CNT && self.tree[pos + step] <= rest {
+                pos += step;
+                rest -= self.tree[pos];
+            }
+            step >>= 1;
+        }
+        debug_assert!(pos < SYM_CNT);
+        pos
+    }
+
+    fn update(&mut self, sym: Symbol) {
+        self.increment(sym);
+    }
+}
+
+/// Limit for the sum of the frequencies of a `StaticModel`.
+const STATIC_TOTAL: u64 = 1 << 16;
+
+/// A model with fixed frequencies, which is not updated while
+/// coding.  The `EOF` symbol always has a frequency of 1.
+#[derive(Clone)]
+pub struct StaticModel {
+    freqs: [u64; SYM_CNT],
+    cum: [u64; SYM_CNT + 1],
+}
+
+impl StaticModel {
+    /// Create a model from the occurrence counts of the byte values,
+    /// which are scaled down when their sum is too large.  Byte
+    /// values with a count of zero cannot be encoded with the model.
+    pub fn from_counts(counts: &[u64; 256]) -> StaticModel {
+        let sum: u64 = counts.iter().sum();
+        // Leave room for rounding up small counts and for EOF.
+        let limit = STATIC_TOTAL - SYM_CNT as u64;
+        let mut freqs = [0; SYM_CNT];
+        for (f, &c) in freqs.iter_mut().zip(counts.iter()) {
+            *f = if c == 0 {
+                0
+            } else if sum <= limit {
+                c
+            } else {
+                ::core::cmp::max(1, c * limit / sum)
+            };
+        }
+        freqs[EOF] = 1;
+        StaticModel::from_freqs(freqs)
+    }
+
+    fn from_freqs(freqs: [u64; SYM_CNT]) -> StaticModel {
+        let mut cum = [0; SYM_CNT + 1];
+        cum.copy_from_slice(&stats::cumulative_freqs(&freqs));
+        StaticModel {
+            freqs: freqs,
+            cum: cum,
+        }
+    }
+
+    /// Count the byte values in `data` and create a model from the
+    /// counts.
+    pub fn from_data(data: &[u8]) -> StaticModel {
+        StaticModel::from_counts(&stats::byte_histogram(data))
+    }
+
+    /// Write the frequency table header to `output`.
+    pub fn write_header<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let mut hdr = vec![0u8; 32];
+        for i in 0..256 {
+            if self.freqs[i] > 0 {
+                hdr[i / 8] |= 1 << (i % 8);
+            }
+        }
+        for i in 0..256 {
+            let mut f = self.freqs[i];
+            while f > 0 {
+                let b = (f & 0x7f) as u8;
+                f >>= 7;
+                hdr.push(if f > 0 { b | 0x80 } else { b });
+            }
+        }
+        output.write_all(&hdr)
+    }
+
+    /// Read a frequency table header, as written by `write_header`,
+    /// from `input`.
+    pub fn read_header<R: Read>(input: &mut R) -> io::Result<StaticModel> {
+        let mut bitmap = [0u8; 32];
+        try!(input.read_exact(&mut bitmap));
+        let mut freqs = [0; SYM_CNT];
+        for i in 0..256 {
+            if bitmap[i / 8] & (1 << (i % 8)) != 0 {
+                let mut f = 0;
+                let mut shift = 0;
+                loop {
+                    let mut b = [0u8; 1];
+                    try!(input.read_exact(&mut b));
+                    f |= ((b[0] & 0x7f) as u64) << shift;
+                    shift += 7;
+                    if b[0] & 0x80 == 0 {
+                        break;
+                    }
+                    if shift > 21 {
+                        return Err(Error::corrupt("invalid frequency in header").into());
+                    }
+                }
+                if f == 0 {
+                    return Err(Error::corrupt("invalid frequency in header").into());
+                }
+                freqs[i] = f;
+            }
+        }
+        freqs[EOF] = 1;
+        if freqs.iter().sum::<u64>() > STATIC_TOTAL {
+            return Err(Error::LimitExceeded("frequency table too large").into());
+        }
+        Ok(StaticModel::from_freqs(freqs))
+    }
+}
+
+impl Model for StaticModel {
+    fn prob(&self, sym: Symbol) -> Prob {
+        Prob {
+            low: self.cum[sym],
+            high: self.cum[sym + 1],
+            total: self.cum[SYM_CNT],
+        }
+    }
+
+    fn total(&self) -> u64 {
+        self.cum[SYM_CNT]
+    }
+
+    fn symbol_for(&self, cum: u64) -> Symbol {
+        // Find the last symbol whose range starts at or below `cum`,
+        // skipping symbols with empty ranges.
+        match self.cum[1..].binary_search(&cum) {
+            Ok(i) => {
+                let mut s = i + 1;
+                while self.freqs[s] == 0 {
+                    s += 1;
+                }
+                s
+            },
+            Err(i) => i,
+        }
+    }
+
+    fn update(&mut self, _sym: Symbol) {
+    }
+}
+
+/// Streaming arithmetic compressor.  All data written to it is
+/// compressed and passed on to the wrapped writer.  Calling `flush`
+/// encodes the end of the stream, so no more data may be written
+/// afterwards.
+#[derive(Clone)]
+pub struct Writer<W, M> {
+    outp: BitWriter<W>,
+    model: M,
+    low: u64,
+    high: u64,
+    pending_bits: usize,
+    finished: bool,
+    total_in: u64,
+}
+
+impl<W: Write> Writer<W, State> {
+    /// Create a new writer with the adaptive order-0 model.
+    pub fn new(output: W) -> Writer<W, State> {
+        Writer::with_model(output, State::new())
+    }
+}
+
+impl<W: Write, M: Model> Writer<W, M> {
+    /// Create a new writer which takes probabilities from `model`.
+    pub fn with_model(output: W, model: M) -> Writer<W, M> {
+        Writer {
+            outp: BitWriter::new(output),
+            model: model,
+            low: 0,
+            high: MAX_CODE,
+            pending_bits: 0,
+            finished: false,
+            total_in: 0,
+        }
+    }
+
+    fn output_bit_plus_pending(&mut self, bit: usize) -> io::Result<()> {
+        try!(self.outp.write_bits(bit as u64, 1));
+        while self.pending_bits > 0 {
+            try!(self.outp.write_bits((1 - bit) as u64, 1));
+            self.pending_bits -= 1;
+        }
+        Ok(())
+    }
+
+    fn encode_symbol(&mut self, c: Symbol) -> io::Result<()> {
+        let p = self.model.prob(c);
+        self.model.update(c);
+        debug_assert!(p.total <= MAX_TOTAL);
+        if p.low >= p.high {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "symbol has zero frequency in model"));
+        }
+
+        let range: u64 = self.high - self.low + 1;
+
+        self.high = self.low + (range * p.high / p.total) - 1;
+        self.low = self.low + (range * p.low / p.total);
+
+        loop {
+            if self.high < ONE_HALF {
+                try!(self.output_bit_plus_pending(0));
+            } else if self.low >= ONE_HALF {
+                try!(self.output_bit_plus_pending(1));
+            } else if self.low >= ONE_FOURTH && self.high < THREE_FOURTHS {
+                self.pending_bits += 1;
+                self.low -= ONE_FOURTH;
+                self.high -= ONE_FOURTH;
+            } else {
+                break;
+            }
+            self.high <<= 1;
+            self.high += 1;
+            self.low <<= 1;
+            self.high &= MAX_CODE;
+            self.low &= MAX_CODE;
+        }
+        Ok(())
+    }
+
+    /// Encode the end of stream and write out all pending bits.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            try!(self.encode_symbol(EOF));
+
+            // Write out two MSB of low to make sure the decoder has
+            // enough precision for decoding the last symbol.
+            self.pending_bits += 1;
+            if self.low < ONE_FOURTH {
+                try!(self.output_bit_plus_pending(0));
+            } else {
+                try!(self.output_bit_plus_pending(1));
             }
+            self.finished = true;
         }
-        unreachable!();
+        self.outp.flush()
     }
 
+    /// Return the number of uncompressed bytes written so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Return the number of compressed bytes written to the wrapped
+    /// writer so far.
+    pub fn total_out(&self) -> u64 {
+        self.outp.bytes_written()
+    }
+
+    /// Move the wrapped writer out of the arithmetic writer.
+    pub fn into_inner(self) -> W {
+        self.outp.to_inner()
+    }
+
+    /// Return the model in its current state.
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+}
+
+impl<W: Write, M: Model> Write for Writer<W, M> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        for b in data {
+            try!(self.encode_symbol(*b as Symbol));
+        }
+        self.total_in += data.len() as u64;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.finish()
+    }
+}
+
+/// Streaming arithmetic decompressor.  Reading from it returns the
+/// decompressed contents of the wrapped reader.
+pub struct Reader<R, M> {
+    inp: BitReader<R>,
+    model: M,
+    low: u64,
+    high: u64,
+    value: u64,
+    eof: bool,
+    total_out: u64,
+    recorder: Recorder,
+}
+
+impl<R: Read> Reader<R, State> {
+    /// Create a new reader with the adaptive order-0 model.  This
+    /// reads the first bits of the input, therefore the result can
+    /// be an error.
+    pub fn new(input: R) -> io::Result<Reader<R, State>> {
+        Reader::with_model(input, State::new())
+    }
+}
+
+impl<R: Read, M: Model> Reader<R, M> {
+    /// Create a new reader which takes probabilities from `model`.
+    pub fn with_model(input: R, model: M) -> io::Result<Reader<R, M>> {
+        // The encoder writes two bits of the final code value, so
+        // that the decoder needs up to 30 zero bits after the end of
+        // the input.  Running out of input before that means the
+        // input is truncated.
+        let mut inp = BitReader::new_with_extra(input, 30);
+        let value = try!(inp.read_bits(32));
+        Ok(Reader {
+            inp: inp,
+            model: model,
+            low: 0,
+            high: MAX_CODE,
+            value: value,
+            eof: false,
+            total_out: 0,
+            recorder: Recorder::new(),
+        })
+    }
+
+    /// Return the model in its current state.
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+
+    /// Return the number of compressed bytes read so far.  The zero
+    /// bits the decoder reads past the end of the input are counted,
+    /// too.
+    pub fn total_in(&self) -> u64 {
+        self.inp.bytes_read()
+    }
+
+    /// Return the number of decompressed bytes read so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Decode the next symbol.
+    fn decode_symbol(&mut self) -> io::Result<Symbol> {
+        let range: u64 = self.high - self.low + 1;
+        let count: u64 = ((self.value - self.low + 1) * self.model.total() - 1) / range;
+
+        let c = self.model.symbol_for(count);
+        let p = self.model.prob(c);
+        self.model.update(c);
+
+        // The end of stream symbol is decoded like any other, so
+        // that the decoder reads all bits the encoder wrote for it.
+        self.high = self.low + (range * p.high) / p.total - 1;
+        self.low = self.low + (range * p.low) / p.total;
+        if self.value < self.low || self.value > self.high {
+            return Err(Error::corrupt("code value outside of interval").into());
+        }
+        loop {
+            if self.high < ONE_HALF {
+                //do nothing, bit is a zero
+            } else if self.low >= ONE_HALF {
+                self.value -= ONE_HALF;  //subtract one half from all three code values
+                self.low -= ONE_HALF;
+                self.high -= ONE_HALF;
+            } else if self.low >= ONE_FOURTH && self.high < THREE_FOURTHS {
+                self.value -= ONE_FOURTH;
+                self.low -= ONE_FOURTH;
+                self.high -= ONE_FOURTH;
+            } else {
+                break;
+            }
+            self.low <<= 1;
+            self.high <<= 1;
+            self.high += 1;
+            self.value <<= 1;
+            self.value += try!(self.inp.read_bits(1));
+        }
+        Ok(c)
+    }
+}
+
+impl<R: Read, M: Model> Read for Reader<R, M> {
+    fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
+        let mut nread = 0;
+        while nread < output.len() && !self.eof {
+            let c = try!(self.decode_symbol().map_err(|e| at_offset(e, self.total_in())));
+            if c == EOF {
+                self.recorder.record(Event::End);
+                self.eof = true;
+            } else {
+                self.recorder.record(Event::Literal(c as u8));
+                output[nread] = c as u8;
+                nread += 1;
+            }
+        }
+        self.total_out += nread as u64;
+        Ok(nread)
+    }
+}
+
+impl<R: Read, M: Model> Inspect for Reader<R, M> {
+    fn recorder(&mut self) -> &mut Recorder {
+        &mut self.recorder
+    }
 }
 
 /// This is an arithmetic encoder.
+#[derive(Clone)]
 pub struct Encoder {
     state: State,
 }
@@ -158,88 +664,29 @@ impl Encoder {
         self.state.preload(counts);
     }
     
-    pub fn debug_print(&self) {
-        self.state.debug_print();
+    /// Write the model to `output`, see `State::serialize_model`.
+    pub fn serialize_model<W: Write>(&self, output: W) -> io::Result<()> {
+        self.state.serialize_model(output)
     }
-    
-    fn output_bit_plus_pending<W: Write>(&mut self, bit: usize, pending_bits: &mut usize, bw: &mut BitWriter<W>) -> Result<(), Error> {
-        try!(bw.write_bits(bit as u64, 1));
-        while *pending_bits > 0 {
-            try!(bw.write_bits((1 - bit) as u64, 1));
-            *pending_bits -= 1;
-        }
+
+    /// Replace the model by one read from `input`, see
+    /// `State::load_model`.
+    pub fn load_model<R: Read>(&mut self, input: R) -> io::Result<()> {
+        self.state = try!(State::load_model(input));
         Ok(())
     }
 
+    #[cfg(feature = "std")]
+    pub fn debug_print(&self) {
+        self.state.debug_print();
+    }
+    
     /// Compress all the data from reader `input` and write the
     /// compressed data to the writer `output`.
-    pub fn compress<R, W>(mut self, mut input: R, output: W) -> Result<W, Error>
+    pub fn compress<R, W>(self, input: R, output: W) -> Result<W, Error>
         where R: Read,
               W: Write {
-
-        let mut outp = BitWriter::new(output);
-        
-        let mut low: u64  = 0;
-        let mut high: u64 = MAX_CODE;
-        let mut pending_bits = 0;
-        
-        let mut cbuf = [0u8; 1];
-
-        let mut nread = try!(input.read(&mut cbuf[..]));
-        loop {
-            // Convert short reads to the EOF symbol.
-            let c = if nread == 0 {
-                EOF
-            } else {
-                cbuf[0] as Symbol
-            };
-            
-            let p = self.state.get_prob_and_update(c);
-            
-            let range: u64 = high - low + 1;
-            
-            high = low + (range * p.high / p.total) - 1;
-            low = low + (range * p.low / p.total);
-            
-            loop {
-                if high < ONE_HALF {
-                    try!(self.output_bit_plus_pending(0, &mut pending_bits, &mut outp));
-                } else if low >= ONE_HALF {
-                    try!(self.output_bit_plus_pending(1, &mut pending_bits, &mut outp));
-                } else if low >= ONE_FOURTH && high < THREE_FOURTHS {
-                    pending_bits += 1;
-                    low -= ONE_FOURTH;  
-                    high -= ONE_FOURTH;  
-                } else {
-                    break;
-                }
-                high <<= 1;
-                high += 1;
-                low <<= 1;
-                high &= MAX_CODE;
-                low &= MAX_CODE;
-            }
-
-            // When EOF is encoded, terminate encoding loop.
-            if c == EOF {
-                break;
-            }
-
-            // Read character for next iteration.
-            nread = try!(input.read(&mut cbuf[..]));
-        }
-        // Write out two MSB of low to make sure the decoder has
-        // enough precision for decoding the last symbol.
-        pending_bits += 1;
-        if low < ONE_FOURTH {
-            try!(self.output_bit_plus_pending(0, &mut pending_bits, &mut outp));
-        } else {
-            try!(self.output_bit_plus_pending(1, &mut pending_bits, &mut outp));
-        }
-
-        // Flush accumulated bits and return the underlying writer.
-        outp.flush().unwrap();
-        Ok(outp.to_inner())
+        compress_with_model(self.state, input, output)
     }
 
 }
@@ -260,66 +707,29 @@ impl Decoder {
         self.state.preload(counts);
     }
     
+    /// Write the model to `output`, see `State::serialize_model`.
+    pub fn serialize_model<W: Write>(&self, output: W) -> io::Result<()> {
+        self.state.serialize_model(output)
+    }
+
+    /// Replace the model by one read from `input`, see
+    /// `State::load_model`.
+    pub fn load_model<R: Read>(&mut self, input: R) -> io::Result<()> {
+        self.state = try!(State::load_model(input));
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
     pub fn debug_print(&self) {
         self.state.debug_print();
     }
     
     /// Decompress all data from the reader `input`, writing the
     /// decompressed data to the writer `output`.
-    pub fn decompress<R, W>(mut self, input: R, mut output: W) -> Result<W, Error>
+    pub fn decompress<R, W>(self, input: R, output: W) -> Result<W, Error>
         where R: Read,
               W: Write {
-
-        let mut inp = BitReader::new_with_extra(input, 32*2);
-        
-        let mut low: u64  = 0;
-        let mut high: u64 = MAX_CODE;
-        let mut value: u64 = try!(inp.read_bits(32));
-
-        loop {
-            let range: u64 = (high as u64) - (low as u64) + 1;
-            let count: u64 = (((value as u64) - (low as u64) + 1) * self.state.get_count() - 1) / range;
-
-            let (p, c) = self.state.get_symbol_and_update(count);
-            
-            if c == EOF {
-                break;
-            }
-
-            let _ = try!(output.write(&[c as u8]));
-            high = low + (range * p.high) / p.total - 1;
-            low = low + (range * p.low) / p.total;
-            loop {
-                if high < ONE_HALF {
-                    //do nothing, bit is a zero
-                } else if low >= ONE_HALF {
-                    value -= ONE_HALF;  //subtract one half from all three code values
-                    low -= ONE_HALF;
-                    high -= ONE_HALF;
-                } else if low >= ONE_FOURTH && high < THREE_FOURTHS {
-                    value -= ONE_FOURTH;
-                    low -= ONE_FOURTH;
-                    high -= ONE_FOURTH;
-                } else {
-                    break;
-                }
-                low <<= 1;
-                high <<= 1;
-                high += 1;
-                value <<= 1;
-                // let in_bit = match inp.read_bit() {
-                //     Ok(true) => 1,
-                //     Ok(false) => 0,
-                //     Err(Error::UnexpectedEof) => break,
-                //     Err(e)=> return Err(e),
-                // };
-                // value += in_bit;
-                value += try!(inp.read_bits(1));
-            }
-        }
-
-        // Return the underlying writer.
-        Ok(output)
+        decompress_with_model(self.state, input, output)
     }
 }
 
@@ -339,12 +749,115 @@ pub fn decompress<R: Read, W: Write>(input: R, output: W) -> Result<W, Error> {
     dec.decompress(input, output)
 }
 
+/// Return an iterator over the literals of the stream in `input`,
+/// which was compressed by `compress`.  The first event is a header
+/// naming the model, which is not stored in the stream.  This reads
+/// the first bits of the input, therefore the result can be an error.
+pub fn inspect<R: Read>(input: R) -> io::Result<Events<Reader<R, State>>> {
+    let mut reader = try!(Reader::new(input));
+    reader.recorder.enable();
+    reader.recorder.record(Event::Header("adaptive order-0 model".into()));
+    Ok(Events::new(reader))
+}
+
+/// Encode all data from `input` using arithmetic compression with
+/// probabilities from `model` and write the compressed stream to
+/// `output`.  On success, the output is returned.
+pub fn compress_with_model<M, R, W>(model: M, mut input: R, output: W) -> Result<W, Error>
+    where M: Model,
+          R: Read,
+          W: Write {
+    let mut cw = Writer::with_model(output, model);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.into_inner())
+}
+
+/// Decode all data from `input` using arithmetic compression with
+/// probabilities from `model` and write the decompressed stream to
+/// `output`.  The model must start out in the same state as the one
+/// used for compression.  On success, the output is returned.
+pub fn decompress_with_model<M, R, W>(model: M, input: R, mut output: W) -> Result<W, Error>
+    where M: Model,
+          R: Read,
+          W: Write {
+    let mut cr = try!(Reader::with_model(input, model));
+    try!(io::copy(&mut cr, &mut output));
+    Ok(output)
+}
+
+/// Encode all data from `input` in semi-static mode: the input is
+/// read completely and its byte frequencies are counted, then the
+/// frequency table and the data compressed with it are written to
+/// `output`.  On success, the output is returned.
+pub fn compress_static<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error> {
+    let mut data = Vec::new();
+    try!(input.read_to_end(&mut data));
+    let model = StaticModel::from_data(&data);
+    compress_with_static_model(model, &data[..], output)
+}
+
+/// Encode all data from `input` in semi-static mode, with the
+/// frequency table derived from the caller-provided byte `counts`
+/// instead of a scan of the input.  Every byte value occurring in the
+/// input must have a non-zero count.  On success, the output is
+/// returned.
+pub fn compress_with_counts<R: Read, W: Write>(input: R, output: W,
+                                               counts: &[u64; 256]) -> Result<W, Error> {
+    compress_with_static_model(StaticModel::from_counts(counts), input, output)
+}
+
+fn compress_with_static_model<R: Read, W: Write>(model: StaticModel, input: R,
+                                                 mut output: W) -> Result<W, Error> {
+    try!(model.write_header(&mut output));
+    compress_with_model(model, input, output)
+}
+
+/// Decode data produced by `compress_static` or
+/// `compress_with_counts`, reading the frequency table from the
+/// stream.  On success, the output is returned.
+pub fn decompress_static<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error> {
+    let model = try!(StaticModel::read_header(&mut input));
+    decompress_with_model(model, input, output)
+}
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod test {
-    use ::std::collections::HashMap;
     use ::std::io::Cursor;
-    use super::{State, Prob, compress, decompress, Encoder, Decoder};
+    use super::{State, Prob, compress, decompress, Encoder, Decoder, MAX_FREQ};
+    use model::train;
+    use stats::symbol_counts;
+    use super::{compress_with_model, decompress_with_model, Writer, Reader};
+    use super::{compress_static, compress_with_counts, decompress_static, StaticModel};
+    use std::io::{Read, Write};
+    use model::{Model, Symbol, SYMBOLS};
+
+    /// Static model in which all symbols are equally likely.
+    struct Uniform;
+
+    impl Model for Uniform {
+        fn prob(&self, sym: Symbol) -> Prob {
+            Prob { low: sym as u64, high: sym as u64 + 1, total: SYMBOLS as u64 }
+        }
+        fn total(&self) -> u64 {
+            SYMBOLS as u64
+        }
+        fn symbol_for(&self, cum: u64) -> Symbol {
+            cum as Symbol
+        }
+        fn update(&mut self, _sym: Symbol) {
+        }
+    }
+
+    #[test]
+    fn inspect_events() {
+        use inspect::Event;
+        let compressed = compress(Cursor::new(b"ab"), vec![]).unwrap();
+        let events: Vec<Event> =
+            super::inspect(Cursor::new(compressed)).unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![Event::Header("adaptive order-0 model".into()), Event::Literal(b'a'),
+                        Event::Literal(b'b'), Event::End], events);
+    }
 
     #[test]
     fn get_prob() {
@@ -449,22 +962,10 @@ mod test {
         assert_eq!(&expected[..], &decompressed[..]);
     }
 
-    fn calc_counts(bytes: &[u8]) -> Vec<(u8, u64)> {
-        let mut hm: HashMap<u8, u64> = HashMap::new();
-        for b in bytes {
-            *hm.entry(*b).or_insert(0) += 1;
-        }
-        let mut ret = Vec::new();
-        for (k, v) in hm.into_iter() {
-            ret.push((k, v));
-        }
-        ret
-    }
-    
     #[test]
     fn compress_preloaded() {
         let input = b"The banana goat in the banana boat can hand bananas to the banana man.";
-        let counts = calc_counts(input);
+        let counts = symbol_counts(input);
 
         let mut enc = Encoder::new();
         enc.preload(&counts);
@@ -478,6 +979,21 @@ mod test {
         assert_eq!(&expected[..], &compressed[..]);
     }
 
+    #[test]
+    fn preload_order() {
+        let input = b"The banana goat in the banana boat can hand bananas to the banana man.";
+        let counts = symbol_counts(input);
+        let mut reversed = counts.clone();
+        reversed.reverse();
+
+        let mut enc = Encoder::new();
+        enc.preload(&counts);
+        let mut enc_reversed = Encoder::new();
+        enc_reversed.preload(&reversed);
+        assert_eq!(enc.compress(Cursor::new(&input[..]), vec![]).unwrap(),
+                   enc_reversed.compress(Cursor::new(&input[..]), vec![]).unwrap());
+    }
+
     #[test]
     fn decompress_preloaded() {
         let input = [77, 112, 63, 170, 109, 243, 149, 47, 92, 146, 19, 121,
@@ -486,7 +1002,7 @@ mod test {
                      93, 100, 84, 239, 217, 198, 246, 13, 184];
         let expected = b"The banana goat in the banana boat can hand bananas to the banana man.";
 
-        let counts = calc_counts(&expected[..]);
+        let counts = symbol_counts(&expected[..]);
         let c = Cursor::new(&input[..]);
         let mut dec = Decoder::new();
         dec.preload(&counts);
@@ -507,5 +1023,198 @@ mod test {
         let decompressed = decompress(c, vec![]).unwrap();
         assert_eq!(&original[..], &decompressed[..]);
     }
-}
 
+    #[test]
+    fn compress_decompress_uniform() {
+        let f = include_bytes!("arith.rs");
+        let original = &f[..];
+
+        let compressed = compress_with_model(Uniform, Cursor::new(&original[..]), vec![]).unwrap();
+        // Each byte costs slightly more than 8 bits.
+        assert!(compressed.len() > original.len());
+        assert!(compressed.len() < original.len() + original.len() / 100);
+
+        let c = Cursor::new(&compressed[..]);
+        let decompressed = decompress_with_model(Uniform, c, vec![]).unwrap();
+        assert_eq!(&original[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn writer_reader() {
+        let input = b"The banana goat in the banana boat can hand bananas to the banana man.";
+        let mut w = Writer::new(vec![]);
+        for chunk in input.chunks(7) {
+            w.write_all(chunk).unwrap();
+        }
+        w.flush().unwrap();
+        let compressed = w.into_inner();
+        assert_eq!(&compress(Cursor::new(&input[..]), vec![]).unwrap()[..], &compressed[..]);
+
+        let mut r = Reader::new(Cursor::new(&compressed[..])).unwrap();
+        let mut decompressed = vec![];
+        let mut buf = [0u8; 5];
+        loop {
+            let n = r.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            decompressed.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(&input[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn tree_matches_counts() {
+        let mut st = State::new();
+        let mut x = 1u32;
+        for _ in 0..50000 {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            let sym = ((x >> 16) % 300) as usize % SYMBOLS;
+            st.update(sym);
+            if x % 97 == 0 {
+                let mut low = 0;
+                for s in 0..SYMBOLS {
+                    let p = st.prob(s);
+                    assert_eq!(low, p.low);
+                    assert_eq!(low + st.counts[s], p.high);
+                    for v in p.low..p.high {
+                        assert_eq!(s, st.symbol_for(v));
+                    }
+                    low = p.high;
+                }
+                assert_eq!(low, st.total());
+            }
+        }
+    }
+
+    #[test]
+    fn static_header() {
+        let model = StaticModel::from_data(b"abracadabra");
+        let mut hdr = vec![];
+        model.write_header(&mut hdr).unwrap();
+        let mut expected = vec![0u8; 32];
+        // a, b, c, d and r.
+        expected[12] = 0b0001_1110;
+        expected[14] = 0b0000_0100;
+        expected.extend_from_slice(&[5, 2, 1, 1, 2]);
+        assert_eq!(expected, hdr);
+
+        let read = StaticModel::read_header(&mut Cursor::new(&hdr[..])).unwrap();
+        for s in 0..SYMBOLS {
+            assert_eq!(model.prob(s), read.prob(s));
+        }
+    }
+
+    #[test]
+    fn static_symbol_for() {
+        let model = StaticModel::from_data(b"abracadabra");
+        for s in 0..SYMBOLS {
+            let p = model.prob(s);
+            for v in p.low..p.high {
+                assert_eq!(s, model.symbol_for(v));
+            }
+        }
+    }
+
+    #[test]
+    fn compress_decompress_static() {
+        let f = include_bytes!("arith.rs");
+        let original = &f[..];
+
+        let compressed = compress_static(Cursor::new(&original[..]), vec![]).unwrap();
+        let decompressed = decompress_static(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(&original[..], &decompressed[..]);
+
+        let empty = compress_static(Cursor::new(&b""[..]), vec![]).unwrap();
+        let decompressed = decompress_static(Cursor::new(&empty[..]), vec![]).unwrap();
+        assert_eq!(0, decompressed.len());
+    }
+
+    #[test]
+    fn compress_with_missing_count() {
+        let mut counts = [0; 256];
+        counts[b'a' as usize] = 10;
+        let compressed = compress_with_counts(Cursor::new(&b"aaaa"[..]), vec![], &counts).unwrap();
+        let decompressed = decompress_static(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(&b"aaaa"[..], &decompressed[..]);
+        assert!(compress_with_counts(Cursor::new(&b"ab"[..]), vec![], &counts).is_err());
+    }
+
+    #[test]
+    fn preload_order_independent() {
+        let input = b"The banana goat in the banana boat can hand bananas to the banana man.";
+        let counts = [(b'a', 20000), (b'n', 9000), (b' ', 5000), (b'b', 100), (b'a', 3000)];
+        let mut reversed = counts.to_vec();
+        reversed.reverse();
+
+        let mut enc = Encoder::new();
+        enc.preload(&counts);
+        let compressed = enc.compress(Cursor::new(&input[..]), vec![]).unwrap();
+
+        let mut enc = Encoder::new();
+        enc.preload(&reversed);
+        let compressed2 = enc.compress(Cursor::new(&input[..]), vec![]).unwrap();
+        assert_eq!(compressed, compressed2);
+
+        let mut dec = Decoder::new();
+        dec.preload(&reversed);
+        let decompressed = dec.decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(&input[..], &decompressed[..]);
+
+        let mut st = State::new();
+        st.preload(&[(0, u64::max_value()), (1, u64::max_value())]);
+        assert!(st.total() < MAX_FREQ);
+    }
+
+    #[test]
+    fn serialize_load_model() {
+        let sample = include_bytes!("arith.rs");
+        let message = b"    /// Return the model in its current state.";
+
+        let mut st = State::new();
+        train(&mut st, &sample[..]);
+        let mut saved = vec![];
+        st.serialize_model(&mut saved).unwrap();
+        assert_eq!(b'A', saved[0]);
+
+        let mut enc = Encoder::new();
+        enc.load_model(Cursor::new(&saved[..])).unwrap();
+        let compressed = enc.compress(Cursor::new(&message[..]), vec![]).unwrap();
+        let untrained = compress(Cursor::new(&message[..]), vec![]).unwrap();
+        assert!(compressed.len() < untrained.len() * 3 / 4);
+
+        let mut dec = Decoder::new();
+        dec.load_model(Cursor::new(&saved[..])).unwrap();
+        let decompressed = dec.decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(&message[..], &decompressed[..]);
+
+        let mut w = Writer::with_model(vec![], State::load_model(Cursor::new(&saved[..])).unwrap());
+        w.write_all(&message[..]).unwrap();
+        let mut saved2 = vec![];
+        w.model().serialize_model(&mut saved2).unwrap();
+        assert!(saved2 != saved);
+
+        saved[0] = b'W';
+        assert!(State::load_model(Cursor::new(&saved[..])).is_err());
+    }
+
+    #[test]
+    fn decompress_truncated() {
+        let input = b"The banana goat in the banana boat can hand bananas to the banana man.";
+        let compressed = compress(Cursor::new(&input[..]), vec![]).unwrap();
+        for len in 0..compressed.len() {
+            assert!(decompress(Cursor::new(&compressed[..len]), vec![]).is_err(),
+                    "truncated to {} bytes", len);
+        }
+        let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(&input[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+        ::testutil::roundtrip((|d: &[u8]| super::compress_static(d, vec![]),
+                               |d: &[u8]| super::decompress_static(d, vec![])));
+    }
+}
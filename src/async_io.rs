@@ -0,0 +1,328 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Asynchronous readers and writers for containers.
+//!
+//! `Writer` and `Reader` implement the `AsyncWrite` and `AsyncRead`
+//! traits from the futures-io crate, so that a server can compress or
+//! decompress a stream without blocking the thread of its runtime.
+//! Tokio users can adapt them with the compatibility layer of
+//! tokio-util.
+//!
+//! The codecs themselves are synchronous, but they only ever work on
+//! memory: the writer pushes the data into a `buffer::Compressor` and
+//! passes the finished blocks on to the wrapped writer whenever it
+//! accepts data, and the reader passes whatever the wrapped reader
+//! delivers to a `buffer::Decompressor` and hands out the decoded
+//! blocks.  Neither blocks, and neither holds more than a block of
+//! output at a time.
+//!
+//! Unlike `container::Writer`, `Writer` finishes the container when it
+//! is closed, not when it is flushed, as required by `AsyncWrite`.
+
+use std::cmp;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_io::{AsyncRead, AsyncWrite};
+
+use buffer::{Compressor, Decompressor};
+use container;
+use method::Method;
+use error::Error;
+
+/// Asynchronous writer for the container format.
+pub struct Writer<W> {
+    inner: W,
+    compressor: Option<Compressor>,
+    pending: Vec<u8>,
+    written: usize,
+}
+
+impl<W: AsyncWrite + Unpin> Writer<W> {
+    /// Create a writer which compresses blocks of the default size
+    /// with `method`.
+    pub fn new(inner: W, method: Method) -> Writer<W> {
+        Writer::with_block_size(inner, method, container::DEFAULT_BLOCK_SIZE)
+    }
+
+    /// Create a writer which compresses blocks of `block_size` bytes
+    /// with `method`.  Panics when `block_size` is zero or larger than
+    /// `container::MAX_BLOCK_SIZE`.
+    pub fn with_block_size(inner: W, method: Method, block_size: usize) -> Writer<W> {
+        Writer {
+            inner: inner,
+            compressor: Some(Compressor::with_block_size(method, block_size)),
+            pending: Vec::new(),
+            written: 0,
+        }
+    }
+
+    /// Write the pending output to the wrapped writer.
+    fn poll_pending(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        while self.written < self.pending.len() {
+            let n = match Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.written..]) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(n)) => n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            };
+            self.written += n;
+        }
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Move the wrapped writer out of the container writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Writer<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match this.poll_pending(cx) {
+            Poll::Ready(Ok(())) => (),
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        }
+        let res = match this.compressor {
+            Some(ref mut c) => c.push(buf),
+            None => return Poll::Ready(Err(io::Error::other("write after end of container"))),
+        };
+        match res {
+            Ok(out) => {
+                this.pending = out;
+                Poll::Ready(Ok(buf.len()))
+            },
+            Err(e) => Poll::Ready(Err(e.into())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_pending(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_flush(cx),
+            p => p,
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_pending(cx) {
+            Poll::Ready(Ok(())) => (),
+            p => return p,
+        }
+        if let Some(c) = this.compressor.take() {
+            match c.finish() {
+                Ok(out) => this.pending = out,
+                Err(e) => return Poll::Ready(Err(e.into())),
+            }
+            match this.poll_pending(cx) {
+                Poll::Ready(Ok(())) => (),
+                p => return p,
+            }
+        }
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+/// Asynchronous reader for the container format.
+pub struct Reader<R> {
+    inner: R,
+    decompressor: Decompressor,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    returned: usize,
+    eof: bool,
+}
+
+impl<R: AsyncRead + Unpin> Reader<R> {
+    /// Create a new reader.  The method is taken from the container
+    /// header.
+    pub fn new(inner: R) -> Reader<R> {
+        Reader {
+            inner: inner,
+            decompressor: Decompressor::new(),
+            input: vec![0; 16 * 1024],
+            output: Vec::new(),
+            returned: 0,
+            eof: false,
+        }
+    }
+
+    /// Move the wrapped reader out of the container reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Reader<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8])
+                 -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        while this.returned == this.output.len() {
+            if this.eof || buf.is_empty() || this.decompressor.is_finished() {
+                return Poll::Ready(Ok(0));
+            }
+            let n = match Pin::new(&mut this.inner).poll_read(cx, &mut this.input) {
+                Poll::Ready(Ok(n)) => n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            };
+            if n == 0 {
+                this.eof = true;
+                return Poll::Ready(Err(Error::UnexpectedEof.into()));
+            }
+            match this.decompressor.push(&this.input[..n]) {
+                Ok(out) => this.output = out,
+                Err(e) => return Poll::Ready(Err(e.into())),
+            }
+            this.returned = 0;
+        }
+        let n = cmp::min(buf.len(), this.output.len() - this.returned);
+        buf[..n].copy_from_slice(&this.output[this.returned..this.returned + n]);
+        this.returned += n;
+        Poll::Ready(Ok(n))
+    }
+}
+
+#[cfg(all(test, feature = "lz"))]
+mod tests {
+    use std::cmp;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+    use futures_io::{AsyncRead, AsyncWrite};
+    use method::Method;
+    use super::{Reader, Writer};
+
+    /// Reader and writer which only transfer a few bytes at a time
+    /// and are not ready every other time they are polled.
+    struct Slow {
+        data: Vec<u8>,
+        pos: usize,
+        ready: bool,
+    }
+
+    impl Slow {
+        fn new(data: Vec<u8>) -> Slow {
+            Slow { data: data, pos: 0, ready: false }
+        }
+
+        fn poll_ready(&mut self, cx: &mut Context) -> bool {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+            }
+            self.ready
+        }
+    }
+
+    impl AsyncRead for Slow {
+        fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8])
+                     -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            if !this.poll_ready(cx) {
+                return Poll::Pending;
+            }
+            let n = cmp::min(cmp::min(buf.len(), 100), this.data.len() - this.pos);
+            buf[..n].copy_from_slice(&this.data[this.pos..this.pos + n]);
+            this.pos += n;
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    impl AsyncWrite for Slow {
+        fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8])
+                      -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            if !this.poll_ready(cx) {
+                return Poll::Pending;
+            }
+            let n = cmp::min(buf.len(), 100);
+            this.data.extend_from_slice(&buf[..n]);
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Poll `f` until it is ready.
+    fn poll_until_ready<T, F>(mut f: F) -> T
+        where F: FnMut(&mut Context) -> Poll<T>
+    {
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(v) = f(&mut cx) {
+                return v;
+            }
+        }
+    }
+
+    fn write_all<W: AsyncWrite + Unpin>(w: &mut W, mut data: &[u8]) {
+        while !data.is_empty() {
+            let n = poll_until_ready(|cx| Pin::new(&mut *w).poll_write(cx, data)).unwrap();
+            data = &data[n..];
+        }
+    }
+
+    fn read_to_end<R: AsyncRead + Unpin>(r: &mut R) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut buf = [0u8; 77];
+        loop {
+            match try!(poll_until_ready(|cx| Pin::new(&mut *r).poll_read(cx, &mut buf))) {
+                0 => return Ok(out),
+                n => out.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    fn compress(input: &[u8]) -> Vec<u8> {
+        let mut w = Writer::with_block_size(Slow::new(Vec::new()), Method::Lzss, 1000);
+        for piece in input.chunks(300) {
+            write_all(&mut w, piece);
+        }
+        poll_until_ready(|cx| Pin::new(&mut w).poll_close(cx)).unwrap();
+        w.into_inner().data
+    }
+
+    #[test]
+    fn compress_decompress() {
+        let input = include_bytes!("async_io.rs");
+        let compressed = compress(input);
+        assert!(compressed.len() < input.len());
+        assert_eq!(&input[..], &::container::decompress(&compressed[..], vec![]).unwrap()[..]);
+        let mut r = Reader::new(Slow::new(compressed));
+        assert_eq!(&input[..], &read_to_end(&mut r).unwrap()[..]);
+    }
+
+    #[test]
+    fn truncated() {
+        let input = include_bytes!("async_io.rs");
+        let compressed = compress(input);
+        let mut r = Reader::new(Slow::new(compressed[..compressed.len() - 1].to_vec()));
+        assert!(read_to_end(&mut r).is_err());
+    }
+
+    #[test]
+    fn pending() {
+        // The first block is waiting for the wrapped writer, which is
+        // not ready, so the second write must not be accepted yet.
+        let mut w = Writer::with_block_size(Slow::new(Vec::new()), Method::Lzss, 10);
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(Pin::new(&mut w).poll_write(&mut cx, &[1; 300]).is_ready());
+        assert!(Pin::new(&mut w).poll_write(&mut cx, &[2; 20]).is_pending());
+        let n = poll_until_ready(|cx| Pin::new(&mut w).poll_write(cx, &[2; 20])).unwrap();
+        assert_eq!(20, n);
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Automatic selection of the compression method.
+//!
+//! `compress` reads a sample from the start of the input and looks at
+//! two numbers: the order-0 entropy of the sample, and the fraction of
+//! the sample which is covered by repeated strings.  Data with many
+//! repeats, like text, is compressed with LZSS2; data without repeats
+//! but with a skewed byte distribution, like the output of an LZ
+//! coder, is compressed with Huffman coding; and data which looks
+//! random is stored.
+//!
+//! The choice only depends on integer arithmetic, so that the same
+//! input gives the same output on every platform.
+//!
+//! The output is written in the block container format, whose header
+//! records the method, so that `decompress` needs no parameters.  As
+//! the container stores blocks which do not get smaller, a wrong
+//! guess costs time but little space.
+//!
+//! The same two numbers give `estimate_ratio`, a rough guess of the
+//! compression ratio a method will achieve, for callers who want to
+//! know whether compressing is worth the time.
+
+use std::io::{Cursor, Read, Write};
+
+use container;
+use error::Error;
+use method::Method;
+use stats::{byte_histogram, entropy, entropy_fixed, FIXED_FRAC_BITS};
+
+/// Number of bytes at the start of the input used for choosing the
+/// method.
+pub const SAMPLE_SIZE: usize = 64 * 1024;
+
+/// Samples with a smaller percentage of bytes covered by repeats
+/// than this are not compressed with an LZ method.
+const MIN_MATCH_PERCENT: usize = 10;
+
+/// Samples with an entropy above this, 7.8 bits per byte in the fixed
+/// point format of `entropy_fixed`, and few repeats are stored.
+const MAX_ENTROPY: u64 = (78 << FIXED_FRAC_BITS) / 10;
+
+const HASH_BITS: usize = 12;
+const MIN_MATCH: usize = 4;
+
+/// Return the number of bytes of `data` which are covered by matches
+/// of at least `MIN_MATCH` bytes, and the number of matches, as found
+/// by a greedy parse with a single hash table entry per hash value.
+fn match_stats(data: &[u8]) -> (usize, usize) {
+    let mut matches = 0;
+    let mut table = vec![usize::max_value(); 1 << HASH_BITS];
+    let mut covered = 0;
+    let mut i = 0;
+    while i + MIN_MATCH <= data.len() {
+        let h = (data[i] as usize) << 16 ^ (data[i + 1] as usize) << 8 ^
+            (data[i + 2] as usize) << 4 ^ data[i + 3] as usize;
+        let h = h.wrapping_mul(2654435761) >> 8 & ((1 << HASH_BITS) - 1);
+        let cand = table[h];
+        table[h] = i;
+        if cand != usize::max_value() {
+            let len = data[cand..].iter().zip(&data[i..]).take_while(|&(a, b)| a == b).count();
+            if len >= MIN_MATCH {
+                covered += len;
+                matches += 1;
+                i += len;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    (covered, matches)
+}
+
+/// Return up to `SAMPLE_SIZE` bytes from `data`: all of it when it is
+/// small enough, otherwise four pieces from evenly spaced positions.
+fn sample(data: &[u8]) -> Vec<u8> {
+    if data.len() <= SAMPLE_SIZE {
+        return data.to_vec();
+    }
+    let piece = SAMPLE_SIZE / 4;
+    let step = (data.len() - piece) / 3;
+    let mut s = Vec::with_capacity(SAMPLE_SIZE);
+    for i in 0..4 {
+        s.extend_from_slice(&data[i * step..i * step + piece]);
+    }
+    s
+}
+
+/// Methods for data with many repeats, in order of preference.
+const LZ_METHODS: [Method; 3] = [Method::Lzss2, Method::Lzss, Method::Lz77];
+
+/// Methods for data with a skewed byte distribution, in order of
+/// preference.
+const ENTROPY_METHODS: [Method; 3] = [Method::Huff, Method::Range, Method::Arith];
+
+/// Return the first of `methods` which is available in this build, or
+/// `Method::Store`.
+fn first_available(methods: &[Method]) -> Method {
+    methods.iter().cloned().find(|m| m.is_available()).unwrap_or(Method::Store)
+}
+
+/// Return the method best suited for data looking like `sample`.
+/// When the preferred method is not compiled in, the next best
+/// available one is returned.
+pub fn choose_method(sample: &[u8]) -> Method {
+    if sample.is_empty() {
+        return Method::Store;
+    }
+    let (covered, _) = match_stats(sample);
+    if covered * 100 >= sample.len() * MIN_MATCH_PERCENT {
+        first_available(&LZ_METHODS)
+    } else if entropy_fixed(&byte_histogram(sample)) <= MAX_ENTROPY {
+        first_available(&ENTROPY_METHODS)
+    } else {
+        Method::Store
+    }
+}
+
+/// Estimate the ratio of compressed to uncompressed size which
+/// `method` achieves on `data`, without compressing it.
+///
+/// The estimate is computed from a sample of at most `SAMPLE_SIZE`
+/// bytes.  Order-0 coders are assumed to reach the entropy of the
+/// sample, and LZ coders are charged a fixed number of bits per
+/// literal and per match, using the matches a simple greedy parse
+/// finds.  The result is only good for comparing methods and for
+/// telling compressible from incompressible data; it can be off by a
+/// large factor for context-modelling coders on structured data.
+pub fn estimate_ratio(data: &[u8], method: Method) -> f64 {
+    let sample = sample(data);
+    if sample.is_empty() {
+        return 1.0;
+    }
+    let h = entropy(&sample);
+    // Bits per literal byte and bits per match.
+    let (literal, mtch) = match method {
+        Method::Store => return 1.0,
+        Method::Arith | Method::WittenArith | Method::WittenArith1 | Method::BinArith |
+        Method::Range | Method::Huff | Method::HuffWhole | Method::AHuff |
+        Method::AHuffVitter => return h / 8.0,
+        Method::Lz77 => (24.0, 24.0),
+        Method::Lzss | Method::LzssBest | Method::LzssLarge | Method::Lzw | Method::LzwZ |
+        Method::Lzp1 => (9.0, 17.0),
+        Method::Lzss2 | Method::Lzss2Best | Method::Zlib | Method::Rolz | Method::Lzp2 |
+        Method::Lzp3 => (h + 1.0, 20.0),
+        Method::Lz4 | Method::Snappy | Method::Lzb => (8.25, 24.0),
+    };
+    let (covered, matches) = match_stats(&sample);
+    let bits = (sample.len() - covered) as f64 * literal + matches as f64 * mtch;
+    bits / (8.0 * sample.len() as f64)
+}
+
+/// Compress all data from `input` into a container written to
+/// `output`, with the method chosen by `choose_method` for the first
+/// `SAMPLE_SIZE` bytes.  On success, the output is returned.
+pub fn compress<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error> {
+    let mut sample = Vec::with_capacity(SAMPLE_SIZE);
+    try!(input.by_ref().take(SAMPLE_SIZE as u64).read_to_end(&mut sample));
+    let method = choose_method(&sample);
+    container::compress(Cursor::new(sample).chain(input), output, method)
+}
+
+/// Decompress the data read from `input`, which was written by
+/// `compress`, and write it to `output`.  On success, the output is
+/// returned.
+pub fn decompress<R: Read, W: Write>(input: R, output: W) -> Result<W, Error> {
+    container::decompress(input, output)
+}
+
+#[cfg(all(test, feature = "lz", feature = "nested"))]
+mod tests {
+    use std::io::Cursor;
+    use container::Reader;
+    use lz4;
+    use method::Method;
+    use super::{choose_method, compress, decompress, estimate_ratio};
+
+    fn noise(len: usize) -> Vec<u8> {
+        let mut x: u32 = 4711;
+        (0..len).map(|_| {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            (x >> 16) as u8
+        }).collect()
+    }
+
+    #[test]
+    fn choose() {
+        let text = include_bytes!("auto.rs");
+        assert_eq!(Method::Lzss2, choose_method(text));
+        assert_eq!(Method::Store, choose_method(&noise(50000)));
+        assert_eq!(Method::Store, choose_method(&[]));
+        let lzed = lz4::compress(Cursor::new(&text[..]), vec![]).unwrap();
+        assert_eq!(Method::Huff, choose_method(&lzed));
+    }
+
+    #[test]
+    fn compress_decompress() {
+        let mut text = include_bytes!("auto.rs").to_vec();
+        while text.len() < 100000 {
+            let t = text.clone();
+            text.extend_from_slice(&t);
+        }
+        for (input, method) in vec![(text, Method::Lzss2), (noise(100000), Method::Store)] {
+            let compressed = compress(Cursor::new(&input[..]), vec![]).unwrap();
+            assert_eq!(method, Reader::new(Cursor::new(&compressed[..])).unwrap().method());
+            let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+            assert_eq!(&input[..], &decompressed[..]);
+        }
+    }
+
+    #[test]
+    fn estimate() {
+        let text = include_bytes!("auto.rs");
+        for m in Method::all() {
+            let compressed = m.compress(Cursor::new(&text[..]), vec![]).unwrap();
+            let ratio = compressed.len() as f64 / text.len() as f64;
+            let estimate = estimate_ratio(text, m);
+            assert!(estimate < ratio * 2.5 && ratio < estimate * 2.5, "method {}", m.name());
+        }
+        let random = noise(200000);
+        assert_eq!(1.0, estimate_ratio(&random, Method::Store));
+        assert!(estimate_ratio(&random, Method::Huff) > 0.99);
+        assert!(estimate_ratio(&random, Method::Lz77) > 2.9);
+        assert_eq!(1.0, estimate_ratio(&[], Method::Lzss));
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Sample data for the benchmarks in `benches/`.
+//!
+//! The embedded samples stand in for the kinds of files in the
+//! Calgary and Canterbury corpora: English text, program source,
+//! binary records, a bitmap image, and the artificial files with a
+//! single repeated byte and with random bytes.  They are built from
+//! the sources of this crate and from fixed generators, so that they
+//! are the same on every run.
+//!
+//! To measure with the real corpora, set the environment variable
+//! `CAMPROSS_CORPUS` to a directory with the files; `corpus` then
+//! loads them instead of the embedded samples.
+//!
+//! This module is compiled with the `bench` feature.
+
+use std::env;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use error::Error;
+use method::Method;
+
+/// Environment variable naming a directory of sample files.
+pub const CORPUS_VAR: &'static str = "CAMPROSS_CORPUS";
+
+const SOURCES: [&'static str; 6] = [include_str!("lzss.rs"), include_str!("window.rs"),
+                                    include_str!("bitfile.rs"), include_str!("arith.rs"),
+                                    include_str!("deflate.rs"), include_str!("container.rs")];
+
+/// A named piece of sample data.
+pub struct Sample {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl Sample {
+    fn new(name: &str, data: Vec<u8>) -> Sample {
+        Sample {
+            name: name.to_string(),
+            data: data,
+        }
+    }
+}
+
+/// Return the text of the comments in the crate's sources, without
+/// the comment markers.
+fn text() -> Vec<u8> {
+    let mut data = Vec::new();
+    for src in SOURCES.iter() {
+        for line in src.lines() {
+            let line = line.trim_start();
+            if line.starts_with("//") {
+                let line = line.trim_start_matches('/').trim_start_matches('!').trim();
+                data.extend_from_slice(line.as_bytes());
+                data.push(b'\n');
+            }
+        }
+    }
+    data
+}
+
+/// Return the crate's sources.
+fn source() -> Vec<u8> {
+    SOURCES.iter().flat_map(|s| s.bytes()).collect()
+}
+
+/// Return a table of little-endian records with an increasing key,
+/// a small count and a code from a short list, like the object files
+/// and geophysical data of the Calgary corpus.
+fn records() -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut x: u32 = 1;
+    for i in 0..8000u32 {
+        x = x.wrapping_mul(1103515245).wrapping_add(12345);
+        let key = i * 37 + (x >> 28);
+        let count = (x >> 16) as u16 % 300;
+        let code = [0x1000u32, 0x1004, 0x2000, 0x8080][(x >> 8) as usize % 4];
+        data.extend_from_slice(&[key as u8, (key >> 8) as u8, (key >> 16) as u8,
+                                 (key >> 24) as u8]);
+        data.extend_from_slice(&[count as u8, (count >> 8) as u8]);
+        data.extend_from_slice(&[code as u8, (code >> 8) as u8, (code >> 16) as u8,
+                                 (code >> 24) as u8]);
+    }
+    data
+}
+
+/// Return a 512 by 256 pixel bitmap of a few filled shapes on an
+/// empty background, one byte per pixel, like the fax image `pic`.
+fn image() -> Vec<u8> {
+    let (width, height) = (512, 256);
+    let mut data = vec![0u8; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let (dx, dy) = (x as i64 - 150, y as i64 - 120);
+            let in_circle = dx * dx + dy * dy < 80 * 80;
+            let in_box = (300..460).contains(&x) && (40..200).contains(&y) &&
+                (x / 8 + y / 8) % 2 == 0;
+            let on_line = y % 32 == 0;
+            if in_circle || in_box || on_line {
+                data[y * width + x] = 0xff;
+            }
+        }
+    }
+    data
+}
+
+/// Return `len` bytes from a linear congruential generator.
+fn random(len: usize) -> Vec<u8> {
+    let mut x: u32 = 1;
+    (0..len).map(|_| {
+        x = x.wrapping_mul(1103515245).wrapping_add(12345);
+        (x >> 16) as u8
+    }).collect()
+}
+
+/// Return the embedded samples.
+pub fn embedded() -> Vec<Sample> {
+    vec![
+        Sample::new("text", text()),
+        Sample::new("source", source()),
+        Sample::new("records", records()),
+        Sample::new("image", image()),
+        Sample::new("aaa", vec![b'a'; 100000]),
+        Sample::new("random", random(100000)),
+    ]
+}
+
+/// Load every file in `dir` as a sample, in the order of the file
+/// names.  Subdirectories are skipped.
+pub fn load_dir<P: AsRef<Path>>(dir: P) -> io::Result<Vec<Sample>> {
+    let mut paths = Vec::new();
+    for entry in try!(fs::read_dir(dir)) {
+        let path = try!(entry).path();
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    let mut samples = Vec::new();
+    for path in paths {
+        let mut data = Vec::new();
+        try!(try!(fs::File::open(&path)).read_to_end(&mut data));
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        samples.push(Sample { name: name, data: data });
+    }
+    Ok(samples)
+}
+
+/// Return the samples from the directory in `CORPUS_VAR`, or the
+/// embedded samples if it is not set.
+pub fn corpus() -> io::Result<Vec<Sample>> {
+    match env::var_os(CORPUS_VAR) {
+        Some(dir) => load_dir(dir),
+        None => Ok(embedded()),
+    }
+}
+
+/// Compress `data` with `method`, check that it decompresses to the
+/// original, and return the compressed size.
+pub fn compressed_size(method: Method, data: &[u8]) -> Result<usize, Error> {
+    let compressed = try!(method.compress(data, Vec::new()));
+    let decompressed = try!(method.decompress(&compressed[..], Vec::new()));
+    if decompressed != data {
+        return Err(Error::corrupt("round trip changed the data"));
+    }
+    Ok(compressed.len())
+}
+
+/// Return a table of the compressed sizes in bits per byte of each
+/// of `methods` on each of `samples`, one line per method.
+pub fn ratio_table(methods: &[Method], samples: &[Sample]) -> Result<String, Error> {
+    let mut table = String::new();
+    let _ = write!(table, "{:<14}", "method");
+    for s in samples {
+        let _ = write!(table, " {:>9}", s.name);
+    }
+    table.push('\n');
+    for &m in methods {
+        let _ = write!(table, "{:<14}", m.name());
+        for s in samples {
+            let size = try!(compressed_size(m, &s.data));
+            let bpb = if s.data.is_empty() {
+                0.0
+            } else {
+                size as f64 * 8.0 / s.data.len() as f64
+            };
+            let _ = write!(table, " {:>9.3}", bpb);
+        }
+        table.push('\n');
+    }
+    Ok(table)
+}
+
+#[cfg(all(test, feature = "lz"))]
+mod tests {
+    use method::Method;
+    use super::{compressed_size, embedded, ratio_table};
+
+    #[test]
+    fn samples() {
+        let samples = embedded();
+        for s in &samples {
+            assert!(s.data.len() > 10000, "sample {}", s.name);
+        }
+        let text = &samples[0].data;
+        assert!(compressed_size(Method::Lzss, text).unwrap() < text.len() / 2);
+        let table = ratio_table(&[Method::Store], &samples[..2]).unwrap();
+        assert_eq!("method              text    source\nstore              8.000     8.000\n",
+                   table);
+    }
+}
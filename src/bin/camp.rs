@@ -1,445 +1,787 @@
 extern crate campross;
 extern crate getopts;
-extern crate ring;
-extern crate mktemp;
 
-use std::time::Instant;
-use std::fs::File;
-use std::io::{Write, Read};
+use std::time::{Duration, Instant};
+use std::fs::{File, OpenOptions};
+use std::io::{self, IsTerminal, Write, Read};
 use std::io::{BufReader, BufWriter};
 use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process;
 
-use ring::digest;
-use getopts::Options;
-use mktemp::Temp;
+use getopts::{Matches, Options};
 
-use campross::arith;
-use campross::witten_arith;
 use campross::lzw;
 use campross::lz77;
 use campross::lzss;
 use campross::lzss2;
-use campross::huff;
 use campross::lzp1;
 use campross::lzp2;
+use campross::lzp3;
+use campross::lzb;
+use campross::arith;
 use campross::binarith;
+use campross::huff;
+use campross::lz4;
+use campross::range;
+use campross::rolz;
+use campross::snappy;
+use campross::witten_arith;
+use campross::zlib;
+use campross::container::{self, Parallel};
+use campross::witten_arith::{AdaptiveModel, Order1Model, Order};
+use campross::error::Error;
+use campross::format::Format;
+use campross::inspect::Event;
+use campross::method::Method;
+use campross::model::{self, Model};
+use campross::compare::Digester;
+use campross::counter::Counter;
+use campross::delta;
+use campross::pipeline::Stage;
+use campross::stats;
+
+/// What `camp` is asked to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Compress,
+    Decompress,
+    Inspect,
+    Test,
+    Compare,
+    Train,
+    Delta,
+    Patch,
+}
+
+/// The subcommands, with the flags which select them when no
+/// subcommand is given, and their descriptions for the usage text.
+const COMMANDS: [(&'static str, &'static str, Command, &'static str); 8] = [
+    ("compress", "c", Command::Compress, "compress the input file"),
+    ("decompress", "d", Command::Decompress, "decompress the input file"),
+    ("inspect", "x", Command::Inspect, "print the structure of a compressed file"),
+    ("test", "t", Command::Test, "compress and decompress a file and check the result"),
+    ("compare", "p", Command::Compare, "compare all compressors on a file"),
+    ("train", "", Command::Train, "train the model of an arithmetic coder on files"),
+    ("delta", "", Command::Delta, "write a delta between a reference and an input file"),
+    ("patch", "", Command::Patch, "apply a delta written by `delta`"),
+];
+
+/// Return the command named `name`.
+fn command_by_name(name: &str) -> Option<Command> {
+    COMMANDS.iter().find(|c| c.0 == name).map(|c| c.2)
+}
+
+/// File name which stands for stdin or stdout.
+const STDIO: &'static str = "-";
+
+/// Default suffix of compressed files.
+const SUFFIX: &'static str = ".cmp";
 
-#[derive(Debug,Clone,Copy)]
-pub enum Method {
-    Arith,
-    WittenArith,
-    Lzw,
-    Lz77,
-    Lzss,
-    Lzss2,
-    Huff,
-    AHuff,
-    Lzp1,
-    Lzp2,
-    BinArith,
+/// Open the file `name` for reading, or stdin for `-`.
+fn open_input(name: &str) -> io::Result<Box<dyn Read>> {
+    if name == STDIO {
+        Ok(Box::new(io::stdin()))
+    } else {
+        Ok(Box::new(BufReader::new(try!(File::open(name)))))
+    }
+}
+
+/// Create the file `name` for writing, or return stdout for `-`.  An
+/// existing file is only replaced with `force`.
+fn create_output(name: &str, force: bool) -> io::Result<Box<dyn Write>> {
+    if name == STDIO {
+        return Ok(Box::new(BufWriter::new(io::stdout())));
+    }
+    let f = if force {
+        File::create(name)
+    } else {
+        OpenOptions::new().write(true).create_new(true).open(name)
+    };
+    match f {
+        Ok(f) => Ok(Box::new(BufWriter::new(f))),
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists =>
+            Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                               format!("{} already exists, use --force to replace it", name))),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reader which keeps a histogram of the bytes read, for the
+/// statistics of `--stats`.
+struct Histogram<R> {
+    inner: R,
+    hist: [u64; 256],
+}
+
+impl<R: Read> Read for Histogram<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.inner.read(buf));
+        stats::update_histogram(&mut self.hist, &buf[..n]);
+        Ok(n)
+    }
 }
 
-fn do_compress(input: &str, output: &str, method: Method, stats: bool) {
-    let _ = compress_with(input, output, method);
+fn do_compress(input: &str, output: &str, method: Method, format: &Format, threads: usize,
+               stats: bool, force: bool) {
+    eprintln!("Compressing {:?}...", method);
+    let report = match compress_with(input, output, method, format, threads, force) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            process::exit(1);
+        },
+    };
 
     if stats {
-        let inf = File::open(input).unwrap();
-        let outf = File::open(output).unwrap();
-        let in_size =inf.metadata().unwrap().len();
-        let out_size = outf.metadata().unwrap().len();
-        println!("Original size: {}", in_size);
-        println!("Compressed size: {}", out_size);
-        println!("Ratio: {:.2}", out_size as f32 / in_size as f32);
-    }
-}
-
-fn do_decompress(input: &str, output: &str, method: Method, _stats: bool) {
-    let _ = decompress_with(input, output, method);
-}
-
-fn do_test(input: &str, method: Method) {
-    let mut temp_dir = Temp::new_dir().unwrap();
-    let mut compressed_name_buf = temp_dir.to_path_buf();
-    compressed_name_buf.push("campross-test.compressed");
-    let compressed_name = compressed_name_buf.as_path();
-    
-    let mut decompressed_name_buf = temp_dir.to_path_buf();
-    decompressed_name_buf.push("campross-test.decompressed");
-    let decompressed_name = decompressed_name_buf.as_path();
-
-    let orig_hash = {
-        println!("Calculating hash for input file {}...", input);
-        let mut buf = [0u8; 1024 * 4];
-        let mut ctx = digest::Context::new(&digest::SHA256);
-        let mut inf = File::open(input).expect("cannot open input file");
-        let mut nread = inf.read(&mut buf[..]).expect("cannot read input file");
-        while nread > 0 {
-            ctx.update(&buf[0..nread]);
-            nread = inf.read(&mut buf[..]).expect("cannot read input file");
+        eprintln!("Original size: {}", report.in_size);
+        eprintln!("Compressed size: {}", report.out_size);
+        eprintln!("Ratio: {:.2}", report.out_size as f32 / report.in_size as f32);
+        eprintln!("Order-0 entropy: {:.3} bits/byte", report.entropy);
+        if let Some((threads, busy)) = report.parallel {
+            eprintln!("Threads: {}", threads);
+            eprintln!("Parallel speedup: {:.2} ({:.3} s of block compression in {:.3} s)",
+                      secs(busy) / secs(report.elapsed), secs(busy), secs(report.elapsed));
         }
-        ctx.finish()
-    };
-    let start_compress = Instant::now();
-    let (orig_size, compressed_size) =
-        compress_with(input, compressed_name.to_str().unwrap(), method);
-    let compress_duration = start_compress.elapsed();
-
-    let decompress_start = Instant::now();
-    let (compressed_size2, decompressed_size) =
-        decompress_with(compressed_name.to_str().unwrap(), decompressed_name.to_str().unwrap(), method);
-    let decompress_duration = decompress_start.elapsed();
-    
-    let decompressed_hash = {
-        println!("Calculating hash for decompressed file {}...", decompressed_name.to_str().unwrap());
-        let mut buf = [0u8; 1024 * 4];
-        let mut ctx = digest::Context::new(&digest::SHA256);
-        let mut inf = File::open(decompressed_name).expect("cannot open input file");
-        let mut nread = inf.read(&mut buf[..]).expect("cannot read input file");
-        while nread > 0 {
-            ctx.update(&buf[0..nread]);
-            nread = inf.read(&mut buf[..]).expect("cannot read input file");
+    }
+}
+
+fn do_decompress(input: &str, output: &str, method: Method, format: &Format, _stats: bool,
+                 force: bool) {
+    eprintln!("Decompressing {:?}...", method);
+    if let Err(e) = decompress_with(input, output, method, format, force) {
+        eprintln!("{}: {}", input, e);
+        process::exit(1);
+    }
+}
+
+fn print_events<I: Iterator<Item = io::Result<Event>>>(events: I) {
+    for event in events {
+        match event {
+            Ok(event) => println!("{}", event),
+            Err(e) => {
+                println!("Error: {}", e);
+                break;
+            },
         }
-        ctx.finish()
-    };
-    assert_eq!(compressed_size, compressed_size2);
-
-    let compress_secs = (compress_duration.as_secs() * 1_000 +
-        compress_duration.subsec_nanos() as u64/ 1_000_000) as f64 / 1000.0;
-    let decompress_secs = (decompress_duration.as_secs() * 1_000 +
-        decompress_duration.subsec_nanos() as u64 / 1_000_000) as f64 / 1000.0;
-    println!("Original size: {}", orig_size);
-    println!("Compressed size: {}", compressed_size);
-    println!("Ratio: {:.2}", compressed_size as f32 / orig_size as f32);
-    println!("Compression speed: {:.3} MB/s", orig_size as f64 / compress_secs / (1024.0*1024.0));
-    println!("Decompression speed: {:.3} MB/s", orig_size as f64 / decompress_secs / (1024.0*1024.0));
-
-
-    if orig_size != decompressed_size {
-        temp_dir.release();
-        println!("ERROR: original and decompressed file differ in size");
-    } else if orig_hash.as_ref() != decompressed_hash.as_ref() {
-        temp_dir.release();
-        println!("ERROR: original and decompressed file hashes differ");
+    }
+}
+
+fn do_inspect(input: &str, method: Method) {
+    let mut inf = open_input(input).expect("cannot open input file");
+    match method {
+        Method::Arith => print_events(arith::inspect(inf).expect("cannot read arith stream")),
+        Method::WittenArith =>
+            print_events(witten_arith::inspect(inf, Order::Zero).expect("cannot read warith stream")),
+        Method::WittenArith1 =>
+            print_events(witten_arith::inspect(inf, Order::One).expect("cannot read warith1 stream")),
+        Method::Lzw => print_events(lzw::inspect(inf).expect("cannot read LZW header")),
+        Method::LzwZ => print_events(lzw::inspect_z(inf).expect("cannot read .Z header")),
+        Method::Lz77 => print_events(lz77::inspect(inf)),
+        Method::Lzss | Method::LzssBest | Method::LzssLarge =>
+            print_events(lzss::inspect(inf)),
+        Method::Lzss2 | Method::Lzss2Best =>
+            print_events(lzss2::inspect(inf).expect("cannot read LZSS2 header")),
+        Method::Lzp1 => print_events(lzp1::inspect(inf)),
+        Method::Lzp2 => print_events(lzp2::inspect(inf).expect("cannot read LZP2 header")),
+        Method::Lzp3 => print_events(lzp3::inspect(inf).expect("cannot read LZP3 stream")),
+        Method::Lzb => print_events(lzb::inspect(inf).expect("cannot read LZB stream")),
+        Method::Huff | Method::HuffWhole => print_events(huff::block::inspect(inf)),
+        Method::AHuff =>
+            print_events(huff::adaptive::inspect(inf, huff::adaptive::Algorithm::Fgk)),
+        Method::AHuffVitter =>
+            print_events(huff::adaptive::inspect(inf, huff::adaptive::Algorithm::Vitter)),
+        Method::BinArith =>
+            print_events(binarith::inspect(inf).expect("cannot read binarith stream")),
+        Method::Zlib => print_events(zlib::inspect(inf).expect("cannot read zlib header")),
+        Method::Lz4 => print_events(lz4::inspect(inf).expect("cannot read LZ4 frame header")),
+        Method::Snappy => print_events(snappy::inspect(inf).expect("cannot read Snappy stream")),
+        Method::Rolz => print_events(rolz::inspect(inf).expect("cannot read ROLZ stream")),
+        Method::Range => print_events(range::inspect(inf).expect("cannot read range stream")),
+        Method::Store => {
+            let mut data = Vec::new();
+            inf.read_to_end(&mut data).expect("cannot read input file");
+            println!("{}", Event::Block(format!("stored {} bytes", data.len())));
+            print_events(data.into_iter().map(Event::Literal).chain(Some(Event::End)).map(Ok));
+        },
+    }
+}
+
+fn do_test(input: &str, method: Method, format: &Format) {
+    let inf = BufReader::new(File::open(input).expect("cannot open input file"));
+    println!("Testing {:?} on {}...", method, input);
+    let res = campross::round_trip(inf, method,
+                                   |r, w| format.compress(method, r, w),
+                                   |r, w| format.decompress(method, r, w).map(|_| ()));
+
+    println!("Original size: {}", res.orig_size);
+    println!("Compressed size: {}", res.compressed_size);
+    println!("Ratio: {:.2}", res.ratio());
+    println!("Compression time: {:.3} s ({:.3} MB/s)",
+             secs(res.compress_time), res.compress_speed());
+    println!("Decompression time: {:.3} s ({:.3} MB/s)",
+             secs(res.decompress_time), res.decompress_speed());
+    println!("Original digest: {:016x}", res.orig_hash);
+    println!("Decompressed digest: {:016x}", res.decompressed_hash);
+
+    if let Some(ref e) = res.error {
+        println!("ERROR: {}", e);
+    } else if res.orig_size != res.decompressed_size {
+        println!("ERROR: original and decompressed data differ in size");
+    } else if res.orig_hash != res.decompressed_hash {
+        println!("ERROR: original and decompressed data digests differ");
     } else {
         println!("OK.");
     }
 }
 
-fn compress_with(input: &str, output: &str, method: Method) -> (u64, u64) {
-    println!("Compressing {:?}...", method);
-    {
-        let inf = BufReader::new(File::open(input).unwrap());
-        let outf = BufWriter::new(File::create(output).unwrap());
+/// Return `d` in seconds.
+fn secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9
+}
 
-        let mut out = match method {
-            Method::Arith => {
-                let enc = arith::Encoder::new();
-                enc.compress(inf, outf).unwrap()
-            },
-            Method::WittenArith => {
-                witten_arith::compress(inf, outf).unwrap()
-            },
-            Method::Lzw => {
-                lzw::compress(inf, outf).unwrap()
-            },
-            Method::Lz77 => {
-                lz77::compress(inf, outf).unwrap()
-            },
-            Method::Lzss => {
-                lzss::compress(inf, outf).unwrap()
-            },
-            Method::Lzss2 => {
-                lzss2::compress(inf, outf).unwrap()
-            },
-            Method::Huff => {
-                huff::block::compress(inf, outf).unwrap()
-            },
-            Method::AHuff => {
-                huff::adaptive::compress(inf, outf).unwrap()
-            },
-            Method::Lzp1 => {
-                lzp1::compress(inf, outf).unwrap()
-            },
-            Method::Lzp2 => {
-                lzp2::compress(inf, outf).unwrap()
-            },
-            Method::BinArith => {
-                binarith::compress(inf, outf).unwrap()
+/// Remove the output file `name` after writing it failed.  Stdout is
+/// left alone.
+fn remove_output(name: &str) {
+    if name != STDIO {
+        let _ = fs::remove_file(name);
+    }
+}
+
+/// What `compress_with` found out about the data and the compression.
+struct Report {
+    in_size: u64,
+    out_size: u64,
+    /// Order-0 entropy of the input.
+    entropy: f64,
+    elapsed: Duration,
+    /// Number of threads and their busy time, see
+    /// `Parallel::busy_time`, if the blocks were compressed in
+    /// parallel.
+    parallel: Option<(usize, Duration)>,
+}
+
+/// Compress the file `input` to `output`, either of which may be
+/// `-`.  The blocks of the container formats are compressed on
+/// `threads` threads, or one per core for 0.  An existing output file
+/// is only replaced with `force`, and a partially written one is
+/// removed.
+fn compress_with(input: &str, output: &str, method: Method, format: &Format, threads: usize,
+                 force: bool) -> Result<Report, Error> {
+    let mut inf = Histogram {
+        inner: try!(open_input(input)),
+        hist: [0; 256],
+    };
+    let outf = Counter::new(try!(create_output(output, force)));
+    let mut parallel = match *format {
+        Format::Blocks if threads != 1 => Some(Parallel::new(method, threads)),
+        Format::Hybrid if threads != 1 => Some(Parallel::hybrid(threads)),
+        _ => None,
+    };
+    let start = Instant::now();
+    let res = match parallel {
+        Some(ref mut p) => p.compress(&mut inf, outf),
+        None => format.compress(method, &mut inf, outf),
+    }.and_then(|mut out| {
+        try!(out.flush());
+        Ok(out.count())
+    });
+    match res {
+        Ok(out_size) => Ok(Report {
+            in_size: inf.hist.iter().sum(),
+            out_size: out_size,
+            entropy: stats::shannon_entropy(&inf.hist),
+            elapsed: start.elapsed(),
+            parallel: parallel.map(|p| (p.threads(), p.busy_time())),
+        }),
+        Err(e) => {
+            remove_output(output);
+            Err(e)
+        },
+    }
+}
+
+/// Decompress the file `input` to `output`, either of which may be
+/// `-`, and return the sizes of the input and output.  Existing and
+/// partially written output files are handled as by `compress_with`.
+fn decompress_with(input: &str, output: &str, method: Method, format: &Format, force: bool)
+                   -> Result<(u64, u64), Error> {
+    let mut inf = Counter::new(try!(open_input(input)));
+    let outf = Counter::new(try!(create_output(output, force)));
+    let res = format.decompress(method, &mut inf, outf).and_then(|mut out| {
+        try!(out.flush());
+        Ok(out.count())
+    });
+    match res {
+        Ok(out_size) => Ok((inf.count(), out_size)),
+        Err(e) => {
+            remove_output(output);
+            Err(e)
+        },
+    }
+}
+
+/// Add the files at `path` to `files`: `path` itself if it is a file,
+/// or, with `recursive`, the files below it if it is a directory.
+fn collect_files(path: &Path, recursive: bool, files: &mut Vec<PathBuf>) {
+    if path.is_dir() {
+        if !recursive {
+            eprintln!("{}: is a directory, skipped", path.display());
+            return;
+        }
+        let mut entries: Vec<PathBuf> = match fs::read_dir(path) {
+            Ok(rd) => rd.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                return;
             },
         };
-        out.flush().unwrap();
-    }
-    
-    let inf = File::open(input).unwrap();
-    let outf = File::open(output).unwrap();
-    let in_size = inf.metadata().unwrap().len();
-    let out_size = outf.metadata().unwrap().len();
-    (in_size, out_size)
-}
-
-fn decompress_with(input: &str, output: &str, method: Method) -> (u64, u64) {
-    println!("Decompressing {:?}...", method);
-    {
-        let inf = BufReader::new(File::open(input).unwrap());
-        let outf = BufWriter::new(File::create(output).unwrap());
-
-        let mut out = match method {
-            Method::Arith => {
-                let enc = arith::Decoder::new();
-                enc.decompress(inf, outf).unwrap()
-            },
-            Method::WittenArith => {
-                witten_arith::decompress(inf, outf).unwrap()
-            },
-            Method::Lzw => {
-                lzw::decompress(inf, outf).unwrap()
-            },
-            Method::Lz77 => {
-                lz77::decompress(inf, outf).unwrap()
-            },
-            Method::Lzss => {
-                lzss::decompress(inf, outf).unwrap()
-            },
-            Method::Lzss2 => {
-                lzss2::decompress(inf, outf).unwrap()
-            },
-            Method::Huff => {
-                huff::block::decompress(inf, outf).unwrap()
-            },
-            Method::AHuff => {
-                huff::adaptive::decompress(inf, outf).unwrap()
+        entries.sort();
+        for entry in entries {
+            collect_files(&entry, recursive, files);
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+}
+
+/// Return the name of the compressed file for `input`: `input` with
+/// `suffix` appended, or `None` if `input` already ends in `suffix`.
+fn compressed_name(input: &Path, suffix: &str) -> Option<PathBuf> {
+    if input.to_string_lossy().ends_with(suffix) {
+        return None;
+    }
+    let mut name = input.as_os_str().to_owned();
+    name.push(suffix);
+    Some(PathBuf::from(name))
+}
+
+/// Return the name of the decompressed file for `input`: `input`
+/// without `suffix`, or `None` if its file name does not end in
+/// `suffix` or is only the suffix.
+fn decompressed_name(input: &Path, suffix: &str) -> Option<PathBuf> {
+    let file_name = match input.file_name() {
+        Some(n) => n.to_string_lossy().into_owned(),
+        None => return None,
+    };
+    if file_name.len() > suffix.len() && file_name.ends_with(suffix) {
+        Some(input.with_file_name(&file_name[..file_name.len() - suffix.len()]))
+    } else {
+        None
+    }
+}
+
+/// Return `name` placed below `out_dir` at its relative path, or
+/// `name` itself without `out_dir`.
+fn in_dir(name: PathBuf, out_dir: Option<&Path>) -> PathBuf {
+    match out_dir {
+        Some(dir) => {
+            let relative: PathBuf = name.components()
+                .filter(|c| match *c { Component::Normal(_) => true, _ => false })
+                .collect();
+            dir.join(relative)
+        },
+        None => name,
+    }
+}
+
+/// Return the ratio of `compressed` to `orig` for the table of
+/// `do_files`, or `-` for empty input.
+fn ratio(orig: u64, compressed: u64) -> String {
+    if orig == 0 {
+        "-".to_string()
+    } else {
+        format!("{:.2}", compressed as f64 / orig as f64)
+    }
+}
+
+/// Check that the compressed file `output` decompresses to the
+/// contents of `input`.
+fn verify_compressed(input: &Path, output: &Path, method: Method, format: &Format)
+                     -> Result<(), Error> {
+    let expected = try!(container::digest(BufReader::new(try!(File::open(input)))));
+    let inf = BufReader::new(try!(File::open(output)));
+    let out = try!(format.decompress(method, inf, Digester::new(io::sink())));
+    let actual = out.digest();
+    if actual != expected {
+        return Err(Error::ChecksumMismatch { expected: expected, actual: actual });
+    }
+    Ok(())
+}
+
+/// Compress or decompress `input` to `output`, sync the output to
+/// disk and, after compressing, check that it decompresses to the
+/// input.  Return the sizes of the input and output.
+fn process_file(command: Command, input: &Path, output: &Path, method: Method, format: &Format,
+                threads: usize, force: bool) -> Result<(u64, u64), Error> {
+    let (in_name, out_name) = (input.to_string_lossy(), output.to_string_lossy());
+    let sizes = if command == Command::Compress {
+        let report = try!(compress_with(&in_name, &out_name, method, format, threads, force));
+        (report.in_size, report.out_size)
+    } else {
+        try!(decompress_with(&in_name, &out_name, method, format, force))
+    };
+    let res = OpenOptions::new().write(true).open(output)
+        .and_then(|f| f.sync_all())
+        .map_err(Error::from)
+        .and_then(|_| if command == Command::Compress {
+            verify_compressed(input, output, method, format)
+        } else {
+            Ok(())
+        });
+    match res {
+        Ok(()) => Ok(sizes),
+        Err(e) => {
+            remove_output(&out_name);
+            Err(e)
+        },
+    }
+}
+
+/// Compress or decompress each of the files and directories
+/// `inputs`, gzip style: `file` is compressed to `file` with `suffix`
+/// appended and decompressed from it, in `out_dir` if given.  Inputs
+/// are removed after their output has been written and verified,
+/// unless `keep` is set.  A table of the ratios is printed at the end.
+fn do_files(command: Command, inputs: &[String], out_dir: Option<&Path>, suffix: &str,
+            recursive: bool, keep: bool, force: bool, method: Method, format: &Format,
+            threads: usize) {
+    let mut files = Vec::new();
+    for input in inputs {
+        collect_files(Path::new(input), recursive, &mut files);
+    }
+
+    if command == Command::Compress {
+        println!("{:40} {:>10} {:>10} {:>8}", "Filename", "Orig.Sz.", "Cmp.Sz.", "Ratio");
+    } else {
+        println!("{:40} {:>10} {:>10} {:>8}", "Filename", "Cmp.Sz.", "Orig.Sz.", "Ratio");
+    }
+    let (mut total_in, mut total_out, mut count, mut failed) = (0, 0, 0, 0);
+    for file in &files {
+        let output = if command == Command::Compress {
+            compressed_name(file, suffix)
+        } else {
+            decompressed_name(file, suffix)
+        };
+        let output = match output {
+            Some(o) => in_dir(o, out_dir),
+            None if command == Command::Compress => {
+                eprintln!("{}: already has {} suffix, skipped", file.display(), suffix);
+                continue;
             },
-            Method::Lzp1 => {
-                lzp1::decompress(inf, outf).unwrap()
+            None => {
+                eprintln!("{}: unknown suffix, skipped", file.display());
+                continue;
             },
-            Method::Lzp2 => {
-                lzp2::decompress(inf, outf).unwrap()
+        };
+        let res = match output.parent() {
+            Some(dir) if out_dir.is_some() => fs::create_dir_all(dir).map_err(Error::from),
+            _ => Ok(()),
+        }.and_then(|_| process_file(command, file, &output, method, format, threads, force));
+        match res {
+            Ok((in_size, out_size)) => {
+                let r = if command == Command::Compress {
+                    ratio(in_size, out_size)
+                } else {
+                    ratio(out_size, in_size)
+                };
+                println!("{:40} {:>10} {:>10} {:>8}", file.display(), in_size, out_size, r);
+                total_in += in_size;
+                total_out += out_size;
+                count += 1;
+                if !keep {
+                    if let Err(e) = fs::remove_file(file) {
+                        eprintln!("{}: {}", file.display(), e);
+                    }
+                }
             },
-            Method::BinArith => {
-                binarith::decompress(inf, outf).unwrap()
+            Err(e) => {
+                println!("{:40} ERROR: {}", file.display(), e);
+                failed += 1;
             },
-        };
-        out.flush().unwrap();
-        
-        let inf = File::open(input).unwrap();
-        let outf = File::open(output).unwrap();
-        let in_size = inf.metadata().unwrap().len();
-        let out_size = outf.metadata().unwrap().len();
-        (in_size, out_size)
-    }
-}
-
-pub struct Result {
-    pub input: String,
-    pub method: Method,
-    pub ratio: f64,
-    pub orig_size: u64,
-    pub compressed_size: u64,
-    pub decompressed_size: u64,
-    pub orig_hash: Vec<u8>,
-    pub decompressed_hash: Vec<u8>,
-    pub size_differ: bool,
-    pub hash_differ: bool,
-    pub compress_throughput: f64,
-    pub decompress_throughput: f64,
-}
-
-fn do_compare(input: &str) {
-    use Method::*;
-    
-    let temp_dir = Temp::new_dir().unwrap();
-    let mut compressed_name_buf = temp_dir.to_path_buf();
-    compressed_name_buf.push("campross-test.compressed");
-    let compressed_name = compressed_name_buf.as_path();
-    
-    let mut decompressed_name_buf = temp_dir.to_path_buf();
-    decompressed_name_buf.push("campross-test.decompressed");
-    let decompressed_name = decompressed_name_buf.as_path();
-
-    let orig_hash = {
-        println!("Calculating hash for input file...");
-        let mut buf = [0u8; 1024 * 4];
-        let mut ctx = digest::Context::new(&digest::SHA256);
-        let mut inf = File::open(input).expect("cannot open input file");
-        let mut nread = inf.read(&mut buf[..]).expect("cannot read input file");
-        while nread > 0 {
-            ctx.update(&buf[0..nread]);
-            nread = inf.read(&mut buf[..]).expect("cannot read input file");
         }
-        ctx.finish()
+    }
+    let r = if command == Command::Compress {
+        ratio(total_in, total_out)
+    } else {
+        ratio(total_out, total_in)
     };
-        let orig_hash_vec = orig_hash.as_ref().to_vec();
-
-    let mut results: Vec<Result> = Vec::new();
-    for method in [Arith, BinArith, WittenArith,
-                   Lzw, Lz77, Lzss, Lzss2,
-                   Lzp1, Lzp2,
-                   Huff, AHuff].iter() {
-        let start_compress = Instant::now();
-        let (orig_size, compressed_size) =
-            compress_with(input, compressed_name.to_str().unwrap(), *method);
-        let compress_duration = start_compress.elapsed();
-
-        let decompress_start = Instant::now();
-        let (compressed_size2, decompressed_size) =
-            decompress_with(compressed_name.to_str().unwrap(),
-                            decompressed_name.to_str().unwrap(),
-                            *method);
-        let decompress_duration = decompress_start.elapsed();
-    
-        let decompressed_hash = {
-            println!("Calculating hash for decompressed file...");
-            let mut buf = [0u8; 1024 * 4];
-            let mut ctx = digest::Context::new(&digest::SHA256);
-            let mut inf = File::open(decompressed_name).expect("cannot open input file");
-            let mut nread = inf.read(&mut buf[..]).expect("cannot read input file");
-            while nread > 0 {
-                ctx.update(&buf[0..nread]);
-                nread = inf.read(&mut buf[..]).expect("cannot read input file");
-            }
-            ctx.finish()
-        };
-        assert_eq!(compressed_size, compressed_size2);
-
-        let compress_secs = (compress_duration.as_secs() * 1_000 +
-                             compress_duration.subsec_nanos() as u64/ 1_000_000) as f64 / 1000.0;
-        let decompress_secs = (decompress_duration.as_secs() * 1_000 +
-                               decompress_duration.subsec_nanos() as u64 / 1_000_000) as f64 / 1000.0;
-
-        let compress_throughput = orig_size as f64 / compress_secs / (1024.0*1024.0);
-        let decompress_throughput = orig_size as f64 / decompress_secs / (1024.0*1024.0);
-        let decompressed_hash_vec = decompressed_hash.as_ref().to_vec();
-        let hash_differ = orig_hash_vec != decompressed_hash_vec;
-        let ratio = compressed_size as f64 / orig_size as f64;
-        let result =
-            Result {
-                input: input.to_string(),
-                method: *method,
-                ratio: ratio,
-                orig_size: orig_size,
-                compressed_size: compressed_size,
-                decompressed_size: decompressed_size,
-                orig_hash: orig_hash_vec.clone(),
-                decompressed_hash: decompressed_hash_vec,
-                size_differ: orig_size != decompressed_size,
-                hash_differ: hash_differ,
-                compress_throughput: compress_throughput,
-                decompress_throughput: decompress_throughput,
-            };
-
-        results.push(result);
+    println!("{:40} {:>10} {:>10} {:>8}", format!("Total ({} files)", count),
+             total_in, total_out, r);
+    if failed > 0 {
+        process::exit(1);
     }
+}
+
+/// Adapt `model` to the contents of the files `inputs`.
+fn train_files<M: Model>(model: &mut M, inputs: &[String]) {
+    for input in inputs {
+        let data = read_file(input).expect("cannot read input file");
+        model::train(model, &data);
+    }
+}
+
+/// Train the model of the arithmetic coder of `method` on the files
+/// `inputs` and save it to `output`.
+fn do_train(inputs: &[String], output: &str, method: Method) {
+    let mut saved = Vec::new();
+    let res = match method {
+        Method::Arith => {
+            let mut m = arith::State::new();
+            train_files(&mut m, inputs);
+            m.serialize_model(&mut saved)
+        },
+        Method::WittenArith => {
+            let mut m = AdaptiveModel::new();
+            train_files(&mut m, inputs);
+            m.serialize_model(&mut saved)
+        },
+        Method::WittenArith1 => {
+            let mut m = Order1Model::new();
+            train_files(&mut m, inputs);
+            m.serialize_model(&mut saved)
+        },
+        _ => {
+            println!("train mode not supported for method {:?}", method);
+            return;
+        },
+    };
+    res.expect("cannot serialize model");
+    let mut outf = File::create(output).expect("cannot create output file");
+    outf.write_all(&saved).expect("cannot write output file");
+    println!("Trained {:?} model on {} files: {} bytes", method, inputs.len(), saved.len());
+}
+
+fn do_compare(input: &str) {
+    let inf = BufReader::new(File::open(input).expect("cannot open input file"));
+    let results = campross::compare(inf, &Method::all());
 
     println!("{:20} {:>8} {:>8} {:>8} {:>8} {:>8} {:11} {:6}",
              "Filename", "Orig.Sz.", "Cmp.Sz.", "Ratio", "Cmp.Spd", "Dec.Spd", "Method", "Check");
     for res in results {
-        let result =
-            if res.size_differ || res.hash_differ {
-                "ERROR"
-            } else {
-                "OK"
-            };
-        print!("{:20} {:8} {:8} {:8.2} {:8.2} {:8.2} {:11} {:6}",
-               input,
-               res.orig_size, res.compressed_size,
-               res.ratio, res.compress_throughput, res.decompress_throughput,
-               format!("{:?}", res.method), result);
-        println!("");
+        let result = if res.is_ok() { "OK" } else { "ERROR" };
+        println!("{:20} {:8} {:8} {:8.2} {:8.2} {:8.2} {:11} {:6}",
+                 input,
+                 res.orig_size, res.compressed_size,
+                 res.ratio(), res.compress_speed(), res.decompress_speed(),
+                 format!("{:?}", res.method), result);
+        if let Some(e) = res.error {
+            println!("  {:?}: {}", res.method, e);
+        }
     }
 }
 
 /// Print a usage summary to stdout that describes the command syntax.
 fn print_usage(program: &str, opts: &Options) {
-    let brief = format!("Usage: {} FILE", program);
+    let brief = format!("Usage: {} COMMAND [OPTIONS] [FILE...]", program);
     print!("{}", opts.usage(&brief));
+    println!("\nCommands:");
+    for &(name, _, _, desc) in COMMANDS.iter() {
+        println!("    {:20}{}", name, desc);
+    }
 }
 
-pub fn main() {
-    let args: Vec<String> = env::args().collect();
-    let program = args[0].clone();
+/// Read the whole file `name`.
+fn read_file(name: &str) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    try!(try!(File::open(name)).read_to_end(&mut data));
+    Ok(data)
+}
 
+/// Run `camp delta` or `camp patch`, which write a delta between a
+/// reference and an input file, or apply one.
+fn delta_command(program: &str, command: &str, args: &[String]) {
     let mut opts = Options::new();
+    opts.optopt("r", "reference", "set reference file", "FILE");
     opts.optopt("i", "input", "set input file", "FILE");
     opts.optopt("o", "output", "set output file", "FILE");
-    opts.optflag("c", "compress", "compress the input file");
-    opts.optflag("d", "decompress", "decompress the input file");
-    opts.optflag("t", "test", "test compressor on a file");
-    opts.optflag("p", "compare", "compare all compressors on a file");
-    opts.optopt("m", "method", "select compression method", "arith|warith|lzw|lz77|lzss|lzss2|lzmg2|huff|ahuff|lzp1|lzp2|binarith");
+    opts.optflag("h", "help", "print this help");
+    let usage = |opts: &Options| {
+        let brief = format!("Usage: {} {} -r REFERENCE -i INPUT -o OUTPUT", program, command);
+        print!("{}", opts.usage(&brief));
+    };
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("Error: {}", e);
+            return usage(&opts);
+        },
+    };
+    let (reference, input, output) =
+        match (matches.opt_str("r"), matches.opt_str("i"), matches.opt_str("o")) {
+            (Some(r), Some(i), Some(o)) if !matches.opt_present("h") => (r, i, o),
+            _ => return usage(&opts),
+        };
+    let reference = read_file(&reference).expect("cannot read reference file");
+    let data = read_file(&input).expect("cannot read input file");
+    let result =
+        if command == "delta" {
+            delta::diff(&reference, &data)
+        } else {
+            match delta::patch(&reference, &data) {
+                Ok(target) => target,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return;
+                },
+            }
+        };
+    let mut outf = File::create(output).expect("cannot create output file");
+    outf.write_all(&result).expect("cannot write output file");
+}
+
+/// Return the options of all commands except `delta` and `patch`.
+/// Without a subcommand, the command is selected with a flag.
+fn options(with_command_flags: bool) -> Options {
+    let mut opts = Options::new();
+    opts.optopt("i", "input", "set input file, - for stdin", "FILE");
+    opts.optopt("o", "output", "set output file, - for stdout", "FILE");
+    if with_command_flags {
+        for &(name, flag, _, desc) in COMMANDS.iter().filter(|c| !c.1.is_empty()) {
+            opts.optflag(flag, name, desc);
+        }
+    }
+    opts.optopt("m", "method", "select compression method", "arith|warith|warith1|lzw|lzw-z|lz77|lzss|lzss-best|lzss-large|lzss2|lzss2-best|huff|huff-whole|ahuff|ahuff-vitter|lzp1|lzp2|lzp3|binarith|zlib|lz4|snappy|lzb|rolz|range|store");
+    opts.optflag("b", "blocks", "use the block container, storing incompressible blocks");
+    opts.optflag("H", "hybrid", "use the block container, choosing the method per block");
+    opts.optmulti("f", "filter", "apply a filter before compressing, may be repeated",
+                  "delta[:N]|delta2|xor:N|mtf|bwt[:N]|x86|transpose:N|dedup");
+    opts.optflag("r", "recursive", "compress the files in directories given as input");
+    opts.optopt("S", "suffix", "set the suffix of compressed files, default .cmp", "SUFFIX");
+    opts.optopt("T", "threads",
+                "compress the blocks of -b and -H on N threads, 0 for one per core", "N");
     opts.optflag("s", "stats", "print statistics");
+    opts.optflag("k", "keep", "keep the input files after compressing or decompressing them");
+    opts.optflag("", "force",
+                 "replace existing output files and write compressed data to a terminal");
     opts.optflag("h", "help", "print this help");
+    opts
+}
 
-    match opts.parse(&args[1..]) {
-        Ok(matches) => {
-            if matches.opt_present("h") {
-                print_usage(&program, &opts);
-            }
-            let method =
-                if let Some(s) = matches.opt_str("m") {
-                    match &s[..] {
-                        "arith"  => Some(Method::Arith),
-                        "warith" => Some(Method::WittenArith),
-                        "lzw"    => Some(Method::Lzw),
-                        "lz77"   => Some(Method::Lz77),
-                        "lzss"   => Some(Method::Lzss),
-                        "lzss2"  => Some(Method::Lzss2),
-                        "huff"   => Some(Method::Huff),
-                        "ahuff"  => Some(Method::AHuff),
-                        "lzp1"   => Some(Method::Lzp1),
-                        "lzp2"   => Some(Method::Lzp2),
-                        "binarith" => Some(Method::BinArith),
-                        _        => None,
-                    }
-                } else {
-                    Some(Method::Arith)
-                };
-            if matches.opt_present("t") {
-                if let Some(m) = method {
-                    match matches.opt_str("i") {
-                        Some(input) => {
-                            do_test(&input, m);
-                        },
-                        None => {
-                            print_usage(&program, &opts);
-                        }
-                    }
-                } else {
-                    print_usage(&program, &opts);
-                }
-            } else if matches.opt_present("p") {
-                match matches.opt_str("i") {
-                    Some(input) => {
-                        do_compare(&input);
-                    },
-                    None => {
-                        print_usage(&program, &opts);
-                    }
-                }
-            } else {
-                match (matches.opt_str("i"), matches.opt_str("o")) {
-                    (Some(input), Some(output)) => {
-                        let stats = matches.opt_present("s");
-                        match (method, matches.opt_present("c"), matches.opt_present("d")) {
-                            (Some(m), true, false) => {
-                                do_compress(&input, &output, m, stats);
-                            },
-                            (Some(m), false, true) => {
-                                do_decompress(&input, &output, m, stats);
-                            },
-                            _ => {
-                                print_usage(&program, &opts);
-                            },
-                        }
-                    },
-                    _ =>
-                        print_usage(&program, &opts),
-                }
-            }
-        },
+/// Return the format selected with `-b`, `-H` and `--filter`, or
+/// `None` if the options conflict or name an unknown filter.
+fn parse_format(matches: &Matches) -> Option<Format> {
+    let stages: Vec<Option<Stage>> =
+        matches.opt_strs("f").iter().map(|f| Stage::from_name(f)).collect();
+    if stages.is_empty() {
+        match (matches.opt_present("b"), matches.opt_present("H")) {
+            (false, false) => Some(Format::Raw),
+            (true, false) => Some(Format::Blocks),
+            (false, true) => Some(Format::Hybrid),
+            (true, true) => None,
+        }
+    } else if matches.opt_present("b") || matches.opt_present("H") ||
+        stages.iter().any(|s| s.is_none()) {
+        None
+    } else {
+        Some(Format::Filtered(stages.into_iter().map(|s| s.unwrap()).collect()))
+    }
+}
+
+pub fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    let (command, rest) = match args.get(1).and_then(|a| command_by_name(a)) {
+        Some(c) => (Some(c), &args[2..]),
+        None => (None, &args[1..]),
+    };
+    if command == Some(Command::Delta) || command == Some(Command::Patch) {
+        return delta_command(&program, &args[1], rest);
+    }
+
+    let opts = options(command.is_none());
+    let matches = match opts.parse(rest) {
+        Ok(m) => m,
         Err(e) => {
             println!("Error: {}", e);
-            print_usage(&program, &opts);
+            return print_usage(&program, &opts);
         },
+    };
+    if matches.opt_present("h") {
+        return print_usage(&program, &opts);
+    }
+    let command = match command {
+        Some(c) => c,
+        None => {
+            let selected: Vec<Command> = COMMANDS.iter()
+                .filter(|c| !c.1.is_empty() && matches.opt_present(c.1))
+                .map(|c| c.2)
+                .collect();
+            if selected.len() != 1 {
+                return print_usage(&program, &opts);
+            }
+            selected[0]
+        },
+    };
+    let method =
+        match matches.opt_str("m") {
+            Some(s) => Method::from_name(&s),
+            None => Some(Method::Arith),
+        };
+    let (method, format) = match (method, parse_format(&matches)) {
+        (Some(m), Some(f)) => (m, f),
+        _ => return print_usage(&program, &opts),
+    };
+    let mut inputs: Vec<String> =
+        matches.opt_str("i").into_iter().chain(matches.free.iter().cloned()).collect();
+    let mut output = matches.opt_str("o");
+    let stats = matches.opt_present("s");
+
+    let force = matches.opt_present("force");
+    let threads = match matches.opt_str("T").map(|t| t.parse::<usize>()) {
+        None => 1,
+        Some(Ok(t)) => t,
+        Some(Err(_)) => return print_usage(&program, &opts),
+    };
+    if command == Command::Compress && threads != 1 && format != Format::Blocks &&
+        format != Format::Hybrid {
+        eprintln!("{}: --threads only applies to -b and -H, compressing on one thread",
+                  program);
+    }
+
+    // Input files without an output file, several inputs and
+    // directories are each compressed or decompressed to a file of
+    // their own, named after the input, with `-o` naming the directory
+    // to put them in.
+    let recursive = matches.opt_present("r");
+    let named_input = inputs.len() == 1 && inputs[0] != STDIO && output.is_none();
+    if (command == Command::Compress || command == Command::Decompress) &&
+        (recursive || inputs.len() > 1 || named_input) {
+        let suffix = matches.opt_str("S").unwrap_or(SUFFIX.to_string());
+        return do_files(command, &inputs, output.as_ref().map(Path::new), &suffix, recursive,
+                        matches.opt_present("k"), force, method, &format, threads);
     }
 
+    // Without an input or output file, the commands which only stream
+    // through their data read stdin and write stdout.
+    if command == Command::Compress || command == Command::Decompress ||
+        command == Command::Inspect {
+        if inputs.is_empty() {
+            inputs.push(STDIO.to_string());
+        }
+        if output.is_none() {
+            output = Some(STDIO.to_string());
+        }
+    }
+    if command == Command::Compress && output.as_ref().map_or(false, |o| o == STDIO) &&
+        io::stdout().is_terminal() && !force {
+        eprintln!("{}: compressed data not written to a terminal, use --force to write it anyway",
+                  program);
+        process::exit(1);
+    }
+
+    match (command, &inputs[..], output) {
+        (Command::Compress, [input], Some(output)) =>
+            do_compress(input, &output, method, &format, threads, stats, force),
+        (Command::Decompress, [input], Some(output)) =>
+            do_decompress(input, &output, method, &format, stats, force),
+        (Command::Inspect, [input], _) => do_inspect(input, method),
+        (Command::Test, [input], _) => do_test(input, method, &format),
+        (Command::Compare, [input], _) => do_compare(input),
+        (Command::Train, inputs, Some(output)) if !inputs.is_empty() =>
+            do_train(inputs, &output, method),
+        _ => print_usage(&program, &opts),
+    }
 }
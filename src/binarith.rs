@@ -5,18 +5,38 @@
 //!
 //! This module exports both a general encoder that can be used to
 //! emit bits with custom probabilities, and a Writer/Reader
-//! combination that is an order-16 adaptive compressor/decompressor
-//! for bits.
+//! combination that is an adaptive compressor/decompressor for bits.
+//! The Writer/Reader predict each bit from the preceding 16 bits by
+//! default; the number of context bits can be chosen between 8 and
+//! 24, trading memory for compression ratio.  The bit counts of
+//! each context are halved regularly, so that the model adapts
+//! quickly when the statistics of the data change.
+//!
+//! Optionally, the Writer/Reader refine the predictions of the model
+//! with secondary symbol estimation (SSE): the probability from the
+//! bit counts is quantized and, together with the bits of the current
+//! byte seen so far, selects an entry of an adaptive table which maps
+//! it to a better estimate.  Neighbouring table entries are
+//! interpolated.  This is similar to the APM stage in Matt Mahoney's
+//! lpaq compressor.
 //!
 //! This is an implentation of Moffat et al.'s binary arithmetic
 //! encoder as presented in: Alistair Moffat, Radford M. Neal and Ian
 //! H. Witten: Arithmetic Coding Revisited, ACM Transactions on
 //! Information Systems, Vol 16, No 3, July 1998, pages 256-294.
+//!
+//! `inspect` lists the literals of a stream compressed with the
+//! default settings.  The stream has neither a header nor blocks.
 
-use std::io::{Read, Write};
-use std::io;
+use io::{Read, Write};
+use io;
+use alloc::vec::Vec;
 
-use error::Error;
+use counter::Counter;
+use error::{at_offset, Error};
+use inspect::{Event, Events, Inspect, Recorder};
+use input::ByteReader;
+use model::{write_varint, read_varint, read_tag};
 
 const B: usize = 60;
 const F: usize = 30;
@@ -26,8 +46,31 @@ pub type Word = u64;
 pub type Count = u32;
 pub type Bit = usize;
 
+/// Tag of serialized Writer/Reader models.
+const MODEL_TAG: u8 = b'B';
+
+/// Smallest number of context bits for the Writer/Reader models.
+pub const MIN_CONTEXT_BITS: usize = 8;
+/// Largest number of context bits for the Writer/Reader models.
+pub const MAX_CONTEXT_BITS: usize = 24;
+/// Number of context bits used by `Writer::new` and `Reader::new`.
+pub const DEFAULT_CONTEXT_BITS: usize = 16;
+
+/// When the two counts of a context add up to this limit, both are
+/// halved.  This lets the model forget old statistics and follow
+/// changes in the data.
+const COUNT_LIMIT: Count = 60;
+
+/// Probabilities in the SSE stage are scaled to this many bits.
+const PROB_BITS: usize = 12;
+/// Number of interpolation points per SSE context.
+const SSE_STEPS: usize = 33;
+/// Adaptation rate of the SSE table, as a shift amount.
+const SSE_RATE: usize = 7;
+
+#[derive(Clone)]
 pub struct Encoder<W> {
-    inner: W,
+    inner: Counter<W>,
 
     out_buf:     u8,
     out_bits:    usize,
@@ -40,7 +83,7 @@ pub struct Encoder<W> {
 impl<W: Write> Encoder<W> {
     pub fn new(writer: W) -> Encoder<W> {
         Encoder{
-            inner: writer,
+            inner: Counter::new(writer),
             out_buf: 0,
             out_bits: 0,
             out_pending: 0,
@@ -129,39 +172,57 @@ impl<W: Write> Encoder<W> {
     /// Finish the encoder by writing all pending output to the
     /// underlying writer.
     pub fn finish(&mut self) -> io::Result<()> {
-        // Output contents of low
-        for _ in 0..B {
-            let bit = ((self.low >> (B - 1)) & 1) as Bit;
+        // Instead of all B bits of low, we only output the shortest
+        // prefix of a value in the interval [low, low + range).  The
+        // decoder reads zeros past the end of the stream, so the
+        // value is completed with zero bits.  Since range is larger
+        // than a quarter, a multiple of a quarter lies in the
+        // interval, so at most two bits are needed.
+        let mut nbits = 0;
+        let mut value = 0;
+        while nbits < B {
+            let step = 1 << (B - nbits);
+            value = (self.low + step - 1) / step * step;
+            if value < self.low + self.range {
+                break;
+            }
+            nbits += 1;
+        }
+        // Pending bits are only written out behind another bit.
+        if nbits == 0 && self.out_pending > 0 {
+            nbits = 1;
+        }
+        for i in 0..nbits {
+            let bit = ((value >> (B - 1 - i)) & 1) as Bit;
             try!(self.out_plus_pending(bit));
-            self.low <<= 1;
         }
-        
-        // Moffat et al.'s paper tells us that flushing the content of
-        // L (self.low in our implementation) should be enough for
-        // proper decoding.  For some reason, it does not work
-        // (decoder sometimes outputs wrong last bit).  Writing two
-        // additional zeros does work for all our tests.  Dunno why.
-        try!(self.out_plus_pending(0));
-        try!(self.out_plus_pending(0));
-        
+
         if self.out_bits > 0 {
+            self.out_buf <<= 8 - self.out_bits;
             try!(self.out_flush());
         }
         try!(self.inner.flush());
         Ok(())
     }
 
+    /// Return the number of bytes written to the contained writer so
+    /// far.
+    pub fn bytes_written(&self) -> u64 {
+        self.inner.count()
+    }
+
     /// Extract the contained writer, consuming `self`.
     pub fn into_inner(self) -> W {
-        self.inner
+        self.inner.into_inner()
     }
 }
 
 pub struct Decoder<R> {
-    inner: R,
+    inner: ByteReader<R>,
 
     in_buf:  [u8; 1],
     in_bits: usize,
+    padding: usize,
 
     range: Word,
     d: Word,
@@ -173,25 +234,48 @@ impl<R: Read> Decoder<R> {
     /// the result can be an error.
     pub fn new(reader: R) -> io::Result<Decoder<R>> {
         let mut d = Decoder{
-            inner: reader,
+            inner: ByteReader::new(reader),
             in_buf: [0; 1],
             in_bits: 0,
+            padding: B,
             d: 0,
             range: 1 << (B - 1),
         };
         for _ in 0..B {
             d.d = (d.d << 1) | (try!(d.get_bit()) as Word);
         }
+        // The code value always lies below the top of the interval,
+        // and `decode` keeps it there.  A stream that starts outside
+        // is corrupt, and decoding it would overflow `d` while
+        // renormalising.
+        if d.d >= d.range {
+            return Err(Error::corrupt("code value outside of interval").into());
+        }
         Ok(d)
     }
 
+    /// Return the number of bytes consumed from the contained reader
+    /// so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.inner.consumed()
+    }
+
     fn get_bit(&mut self) -> io::Result<Bit> {
         if self.in_bits == 0 {
-            let nread = try!(self.inner.read(&mut self.in_buf[..]));
-            self.in_bits = 8;
-            if nread < 1 {
-                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, ""));
+            match try!(self.inner.next_byte()) {
+                Some(b) => self.in_buf[0] = b,
+                None => {
+                    // The encoder leaves off trailing zeros, so we
+                    // supply them.  The decoder never reads more than
+                    // B bits ahead of the encoder's last bit.
+                    if self.padding == 0 {
+                        return Err(Error::UnexpectedEof.into());
+                    }
+                    self.padding -= 1;
+                    return Ok(0);
+                },
             }
+            self.in_bits = 8;
         }
         self.in_bits -= 1;
         let bit = (self.in_buf[0] >> 7) as Bit;
@@ -246,26 +330,252 @@ impl<R: Read> Decoder<R> {
     }
 }
 
+/// Map a value from the logistic domain (-2047..2047) to a
+/// probability in 12 bits.  This is an interpolated table instead of
+/// 4096/(1+exp(-d/256)), so that encoder and decoder compute exactly
+/// the same values on all platforms.
+fn squash(d: i32) -> i32 {
+    const T: [i32; SSE_STEPS] = [
+        1, 2, 3, 6, 10, 16, 27, 45, 73, 120, 194, 310, 488, 747, 1101,
+        1546, 2047, 2549, 2994, 3348, 3607, 3785, 3901, 3975, 4022,
+        4050, 4068, 4079, 4085, 4089, 4092, 4093, 4094];
+    if d > 2047 {
+        return 4095;
+    }
+    if d < -2047 {
+        return 0;
+    }
+    let w = d & 127;
+    let i = ((d >> 7) + 16) as usize;
+    (T[i] * (128 - w) + T[i + 1] * w + 64) >> 7
+}
+
+/// Secondary symbol estimation: adaptive, interpolated mapping from
+/// a probability and a small context to a refined probability.
+#[derive(Clone)]
+struct Sse {
+    /// Inverse of `squash`.
+    stretch: Vec<i16>,
+    /// Probabilities in 16 bits, `SSE_STEPS` for each context.
+    table: Vec<u16>,
+    /// Table entry to be updated after the bit is known.
+    index: usize,
+}
+
+impl Sse {
+    fn new(contexts: usize) -> Sse {
+        let mut stretch = vec![2047; 1 << PROB_BITS];
+        let mut pi = 0;
+        for x in -2047..2048 {
+            let v = squash(x) as usize;
+            for i in pi..v + 1 {
+                stretch[i] = x as i16;
+            }
+            pi = v + 1;
+        }
+        let mut table = Vec::with_capacity(contexts * SSE_STEPS);
+        for _ in 0..contexts {
+            for j in 0..SSE_STEPS {
+                table.push((squash((j as i32 - 16) * 128) * 16) as u16);
+            }
+        }
+        Sse {
+            stretch: stretch,
+            table: table,
+            index: 0,
+        }
+    }
+
+    /// Return the refined probability of a one bit for the
+    /// probability `p` (in `PROB_BITS` bits) in context `ctx`.
+    fn refine(&mut self, p: i32, ctx: usize) -> i32 {
+        let s = self.stretch[p as usize] as i32 + 2048;
+        let w = s & 127;
+        let i = ctx * SSE_STEPS + (s >> 7) as usize;
+        self.index = i + (w >> 6) as usize;
+        (self.table[i] as i32 * (128 - w) + self.table[i + 1] as i32 * w) >> 11
+    }
+
+    /// Move the entry used by the last call of `refine` towards
+    /// `bit`.
+    fn update(&mut self, bit: Bit) {
+        let g = ((bit << 16) + (bit << SSE_RATE) - bit - bit) as i32;
+        let t = self.table[self.index] as i32;
+        self.table[self.index] = (t + ((g - t) >> SSE_RATE)) as u16;
+    }
+}
+
+/// Adaptive model shared by Writer and Reader: a pair of bit counts
+/// for each value of the preceding `bits` bits.
+#[derive(Clone)]
+struct ContextModel {
+    counts: Vec<(Count, Count)>,
+    context: usize,
+    mask: usize,
+    sse: Option<Sse>,
+    /// Bits of the current byte seen so far, with a leading one.
+    partial: usize,
+}
+
+impl ContextModel {
+    fn new(bits: usize) -> ContextModel {
+        assert!(bits >= MIN_CONTEXT_BITS && bits <= MAX_CONTEXT_BITS);
+        let mut counts = Vec::new();
+        counts.resize(1 << bits, (1, 1));
+        ContextModel {
+            counts: counts,
+            context: 0,
+            mask: (1 << bits) - 1,
+            sse: None,
+            partial: 1,
+        }
+    }
+
+    fn enable_sse(&mut self) {
+        self.sse = Some(Sse::new(256));
+    }
+
+    fn bits(&self) -> usize {
+        self.counts.len().trailing_zeros() as usize
+    }
+
+    /// Return the counts of zeros and ones to code the next bit with.
+    /// These are the counts of the current context, or when SSE is
+    /// enabled, the refined probability of a zero and a one.
+    fn predict(&mut self) -> (Count, Count) {
+        let (c0, c1) = self.counts[self.context];
+        match self.sse {
+            None => (c0, c1),
+            Some(ref mut sse) => {
+                let one = 1 << PROB_BITS;
+                let p = ((c1 << PROB_BITS) / (c0 + c1)) as i32;
+                let p = (p + 3 * sse.refine(p, self.partial)) / 4;
+                let p = if p < 1 { 1 } else if p > one - 1 { one - 1 } else { p };
+                ((one - p) as Count, p as Count)
+            }
+        }
+    }
+
+    /// Count `bit` in the current context and shift it into the
+    /// context.
+    fn update(&mut self, bit: Bit) {
+        let c = &mut self.counts[self.context];
+        if bit == 0 {
+            c.0 += 1;
+        } else {
+            c.1 += 1;
+        }
+        if c.0 + c.1 >= COUNT_LIMIT {
+            c.0 = (c.0 + 1) / 2;
+            c.1 = (c.1 + 1) / 2;
+        }
+        self.context = ((self.context << 1) | bit) & self.mask;
+        if let Some(ref mut sse) = self.sse {
+            sse.update(bit);
+        }
+        self.partial = (self.partial << 1) | bit;
+        if self.partial >= 256 {
+            self.partial = 1;
+        }
+    }
+
+    /// Write the number of context bits and the bit counts of all
+    /// contexts to `output`.
+    fn serialize<W: Write>(&self, mut output: W) -> io::Result<()> {
+        try!(output.write_all(&[MODEL_TAG, self.bits() as u8]));
+        for &(c0, c1) in self.counts.iter() {
+            try!(write_varint(&mut output, c0 as u64));
+            try!(write_varint(&mut output, c1 as u64));
+        }
+        Ok(())
+    }
+
+    /// Replace the counts by ones written by `serialize`.  The
+    /// serialized model must use the same number of context bits.
+    fn load<R: Read>(&mut self, mut input: R) -> io::Result<()> {
+        try!(read_tag(&mut input, MODEL_TAG));
+        let mut bits = [0u8; 1];
+        try!(input.read_exact(&mut bits));
+        if bits[0] as usize != self.bits() {
+            return Err(Error::corrupt("binarith model has wrong context size").into());
+        }
+        let mut counts = Vec::with_capacity(self.counts.len());
+        for _ in 0..self.counts.len() {
+            let c0 = try!(read_varint(&mut input));
+            let c1 = try!(read_varint(&mut input));
+            // The coder requires the sum of both counts to fit into F
+            // bits.
+            if c0 == 0 || c1 == 0 || c0 + c1 >= 1 << F {
+                return Err(Error::corrupt("invalid binarith model").into());
+            }
+            counts.push((c0 as Count, c1 as Count));
+        }
+        self.counts = counts;
+        self.context = 0;
+        self.partial = 1;
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
 pub struct Writer<W> {
     encoder: Encoder<W>,
-    model: Vec<(Count, Count)>,
-    context: u16,
+    model: ContextModel,
+    total_in: u64,
 }
 
 impl<W: Write> Writer<W> {
     pub fn new(output: W) -> Writer<W> {
-        let mut model = Vec::new();
-        model.resize(1 << 16, (1, 1));
+        Writer::with_context_bits(output, DEFAULT_CONTEXT_BITS)
+    }
+
+    /// Create a writer which predicts each bit from the preceding
+    /// `bits` bits.  The model needs 8 bytes for each of the `1 <<
+    /// bits` contexts.  The reader must use the same number of bits.
+    ///
+    /// Panics when `bits` is not in the range `MIN_CONTEXT_BITS` to
+    /// `MAX_CONTEXT_BITS`.
+    pub fn with_context_bits(output: W, bits: usize) -> Writer<W> {
         Writer{
             encoder: Encoder::new(output),
-            model: model,
-            context: 0,
+            model: ContextModel::new(bits),
+            total_in: 0,
         }
     }
 
+    /// Enable secondary symbol estimation.  This must be done before
+    /// any data is written, and the reader must enable it, too.
+    pub fn enable_sse(&mut self) {
+        self.model.enable_sse();
+    }
+
+    /// Return the number of uncompressed bytes written so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Return the number of compressed bytes written to the wrapped
+    /// writer so far.
+    pub fn total_out(&self) -> u64 {
+        self.encoder.bytes_written()
+    }
+
     pub fn into_inner(self) -> W {
         self.encoder.into_inner()
     }
+
+    /// Write the bit counts the writer has collected so far to
+    /// `output`.  The SSE table is not included.
+    pub fn serialize_model<O: Write>(&self, output: O) -> io::Result<()> {
+        self.model.serialize(output)
+    }
+
+    /// Replace the writer's model by one written by
+    /// `serialize_model`.  This must be done before any data is
+    /// written, and the reader must load the same model.
+    pub fn load_model<I: Read>(&mut self, input: I) -> io::Result<()> {
+        self.model.load(input)
+    }
 }
 
 impl<W: Write> Write for Writer<W> {
@@ -275,19 +585,13 @@ impl<W: Write> Write for Writer<W> {
             try!(self.encoder.encode(0, 100, 1));
             for _ in 0..8 {
                 let bit = (byte >> 7) as Bit;
-                let c = self.model[self.context as usize];
+                let c = self.model.predict();
                 try!(self.encoder.encode(bit, c.0, c.1));
-
-                if bit == 0 {
-                    self.model[self.context as usize].0 += 1;
-                } else {
-                    self.model[self.context as usize].1 += 1;
-                }
-                self.context = (self.context << 1) | bit as u16;
+                self.model.update(bit);
                 byte <<= 1;
             }
         }
-        
+        self.total_in += output.len() as u64;
         Ok(output.len())
     }
 
@@ -300,23 +604,60 @@ impl<W: Write> Write for Writer<W> {
 
 pub struct Reader<R> {
     decoder: Decoder<R>,
-    model: Vec<(Count, Count)>,
-    context: u16,
+    model: ContextModel,
     eof: bool,
+    total_out: u64,
+    recorder: Recorder,
 }
 
 impl<R: Read> Reader<R> {
     pub fn new(input: R) -> io::Result<Reader<R>> {
+        Reader::with_context_bits(input, DEFAULT_CONTEXT_BITS)
+    }
+
+    /// Create a reader which predicts each bit from the preceding
+    /// `bits` bits, which must be the number of bits the writer used.
+    ///
+    /// Panics when `bits` is not in the range `MIN_CONTEXT_BITS` to
+    /// `MAX_CONTEXT_BITS`.
+    pub fn with_context_bits(input: R, bits: usize) -> io::Result<Reader<R>> {
         let dec = try!(Decoder::new(input));
-        let mut model = Vec::new();
-        model.resize(1 << 16, (1, 1));
         Ok(Reader{
             decoder: dec,
-            model: model,
-            context: 0,
+            model: ContextModel::new(bits),
             eof: false,
+            total_out: 0,
+            recorder: Recorder::new(),
         })
     }
+
+    /// Enable secondary symbol estimation.  This must be done before
+    /// any data is read.
+    pub fn enable_sse(&mut self) {
+        self.model.enable_sse();
+    }
+
+    /// Write the bit counts the reader has collected so far to
+    /// `output`.  The SSE table is not included.
+    pub fn serialize_model<O: Write>(&self, output: O) -> io::Result<()> {
+        self.model.serialize(output)
+    }
+
+    /// Replace the reader's model by one written by
+    /// `serialize_model`.  This must be done before any data is read.
+    pub fn load_model<I: Read>(&mut self, input: I) -> io::Result<()> {
+        self.model.load(input)
+    }
+
+    /// Return the number of compressed bytes consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.decoder.bytes_read()
+    }
+
+    /// Return the number of decompressed bytes read so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
 }
 
 impl<R: Read> Read for Reader<R> {
@@ -327,30 +668,33 @@ impl<R: Read> Read for Reader<R> {
         let mut nread = 0;
         for b in output.iter_mut() {
             let mut byte = 0u8;
-            let eof_flag = try!(self.decoder.decode(100, 1));
+            let eof_flag = try!(self.decoder.decode(100, 1).map_err(|e| at_offset(e, self.total_in())));
             if eof_flag == 1 {
+                self.recorder.record(Event::End);
                 self.eof = true;
                 break;
             }
             for _ in 0..8 {
-                let c = self.model[self.context as usize];
-                let bit = try!(self.decoder.decode(c.0, c.1));
-
-                if bit == 0 {
-                    self.model[self.context as usize].0 += 1;
-                } else {
-                    self.model[self.context as usize].1 += 1;
-                }
-                self.context = (self.context << 1) | bit as u16;
+                let c = self.model.predict();
+                let bit = try!(self.decoder.decode(c.0, c.1).map_err(|e| at_offset(e, self.total_in())));
+                self.model.update(bit);
                 byte = byte << 1 | bit as u8;
             }
+            self.recorder.record(Event::Literal(byte));
             *b = byte;
             nread += 1;
         }
+        self.total_out += nread as u64;
         Ok(nread)
     }
 }
 
+impl<R: Read> Inspect for Reader<R> {
+    fn recorder(&mut self) -> &mut Recorder {
+        &mut self.recorder
+    }
+}
+
 pub fn compress<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error> {
     let mut cw = Writer::new(output);
     try!(io::copy(&mut input, &mut cw));
@@ -364,13 +708,36 @@ pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<W, Error
     Ok(output)
 }
 
+/// Return an iterator over the literals of the stream in `input`,
+/// which was compressed by `compress`.  The first event is a header
+/// naming the number of context bits, which is not stored in the
+/// stream.  This reads the first bytes of the input, therefore the
+/// result can be an error.
+pub fn inspect<R: Read>(input: R) -> io::Result<Events<Reader<R>>> {
+    let mut reader = try!(Reader::new(input));
+    reader.recorder.enable();
+    reader.recorder.record(Event::Header(format!("{} context bits", DEFAULT_CONTEXT_BITS)));
+    Ok(Events::new(reader))
+}
 
 
 
-#[cfg(test)]
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use std::io::{Cursor, Write, Read};
-    use super::{Encoder, Decoder, Writer, Reader};
+    use std::io;
+    use super::{Encoder, Decoder, Writer, Reader, ContextModel, COUNT_LIMIT};
+    use super::{compress, inspect};
+    use inspect::Event;
+
+    #[test]
+    fn inspect_events() {
+        let compressed = compress(Cursor::new(b"ab"), vec![]).unwrap();
+        let events: Vec<Event> = inspect(Cursor::new(compressed)).unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![Event::Header("16 context bits".into()), Event::Literal(b'a'),
+                        Event::Literal(b'b'), Event::End], events);
+    }
 
     #[test]
     fn encode_0() {
@@ -382,13 +749,13 @@ mod tests {
 
         let o = e.into_inner();
 
-        assert_eq!(vec![80, 0, 0, 0, 0, 0, 0, 0, 0], o);
+        assert_eq!(vec![80], o);
     }
 
     #[test]
     fn decode_0() {
         let mut d = Decoder::new(Cursor::new(
-            vec![80, 0, 0, 0, 0, 0, 0, 0, 0])).unwrap();
+            vec![80])).unwrap();
         
         let b = d.decode(1, 1).unwrap();
         assert_eq!(1, b);
@@ -410,13 +777,13 @@ mod tests {
 
         let o = e.into_inner();
 
-        assert_eq!(vec![56, 227, 142, 56, 227, 142, 56, 240], o);
+        assert_eq!(vec![64], o);
     }
 
     #[test]
     fn decode_1() {
         let mut d = Decoder::new(Cursor::new(
-            vec![56, 227, 142, 56, 227, 142, 56, 240])).unwrap();
+            vec![64])).unwrap();
         
         let b = d.decode(2, 1).unwrap();
         assert_eq!(0, b);
@@ -439,13 +806,13 @@ mod tests {
 
         let o = e.into_inner();
 
-        assert_eq!(vec![126, 107, 116, 240, 50, 145, 97, 251, 0], o);
+        assert_eq!(vec![127], o);
     }
 
     #[test]
     fn decode_2() {
         let mut d = Decoder::new(Cursor::new(
-            vec![126, 107, 116, 240, 50, 145, 97, 251, 0])).unwrap();
+            vec![127])).unwrap();
         
         let b = d.decode(2, 1).unwrap();
         assert_eq!(1, b);
@@ -481,8 +848,7 @@ mod tests {
                  39, 34, 243, 174, 18, 176, 28, 87, 111, 96, 65, 73,
                  122, 245, 55, 159, 169, 154, 174, 176, 116, 65, 55,
                  69, 35, 211, 175, 220, 114, 61, 99, 156, 183, 80, 147,
-                 85, 36, 104, 238, 220, 92, 218, 235, 230, 177, 71, 199,
-                 217, 64], o);
+                 85, 36, 104, 238, 220, 92, 224], o);
     }
 
     #[test]
@@ -492,8 +858,7 @@ mod tests {
                  39, 34, 243, 174, 18, 176, 28, 87, 111, 96, 65, 73,
                  122, 245, 55, 159, 169, 154, 174, 176, 116, 65, 55,
                  69, 35, 211, 175, 220, 114, 61, 99, 156, 183, 80, 147,
-                 85, 36, 104, 238, 220, 92, 218, 235, 230, 177, 71, 199,
-                 217, 64])).unwrap();
+                 85, 36, 104, 238, 220, 92, 224])).unwrap();
 
         for _ in 0..100 {
             let b = d.decode(1, 7).unwrap();
@@ -535,6 +900,37 @@ mod tests {
         }
     }
 
+    /// Encode and decode all bit sequences up to 12 bits with
+    /// various probabilities, and check that the output is no longer
+    /// than the information content plus two bits, rounded up to
+    /// whole bytes.
+    #[test]
+    fn termination() {
+        for &(c0, c1) in &[(1, 1), (2, 1), (1, 7), (100, 1), (1, 100), (1000, 3)] {
+            for len in 0..13 {
+                for seq in 0..(1 << len) {
+                    let mut e = Encoder::new(vec![]);
+                    let mut info = 0.0;
+                    for i in 0..len {
+                        let bit = (seq >> i) & 1;
+                        e.encode(bit, c0, c1).unwrap();
+                        let c = if bit == 0 { c0 } else { c1 };
+                        info -= (c as f64 / (c0 + c1) as f64).log2();
+                    }
+                    e.finish().unwrap();
+                    let o = e.into_inner();
+                    assert!(o.len() <= ((info + 2.01) / 8.0).ceil() as usize,
+                            "{} bytes for {:b} ({} bits)", o.len(), seq, info);
+
+                    let mut d = Decoder::new(Cursor::new(o)).unwrap();
+                    for i in 0..len {
+                        assert_eq!((seq >> i) & 1, d.decode(c0, c1).unwrap());
+                    }
+                }
+            }
+        }
+    }
+
     #[test]
     fn compress_empty() {
         let input = b"";
@@ -543,14 +939,14 @@ mod tests {
         c.flush().unwrap();
         let compressed = c.into_inner();
         let expected =
-            [126, 187, 144, 121, 169, 210, 96, 96, 0];            
+            [127];            
         assert_eq!(&expected[..], &compressed[..]);
     }
 
     #[test]
     fn decompress_empty() {
         let input =
-            [126, 187, 144, 121, 169, 210, 96, 96, 0];            
+            [127];            
         let mut d = Reader::new(Cursor::new(input)).unwrap();
         let mut decompressed = Vec::new();
         d.read_to_end(&mut decompressed).unwrap();
@@ -566,14 +962,14 @@ mod tests {
         c.flush().unwrap();
         let compressed = c.into_inner();
         let expected =
-            [53, 66, 117, 134, 245, 8, 246, 61, 63, 160, 94, 186, 160, 0];
+            [53, 66, 117, 134, 245, 9];
         assert_eq!(&expected[..], &compressed[..]);
     }
 
     #[test]
     fn decompress_aaa() {
         let input =
-            [53, 66, 117, 134, 245, 8, 246, 61, 63, 160, 94, 186, 160, 0];
+            [53, 66, 117, 134, 245, 9];
         let mut d = Reader::new(Cursor::new(input)).unwrap();
         let mut decompressed = Vec::new();
         let expected = b"aaaaaaaaa";
@@ -599,4 +995,108 @@ mod tests {
         assert_eq!(&original[..], &decompressed[..]);
     }
 
+    #[test]
+    fn serialize_load_model() {
+        let sample = include_bytes!("binarith.rs");
+        let message = b"    /// Decode a single bit from the input.";
+
+        let mut trainer = Writer::new(io::sink());
+        trainer.write_all(&sample[..]).unwrap();
+        let mut saved = vec![];
+        trainer.serialize_model(&mut saved).unwrap();
+
+        let mut c = Writer::new(vec![]);
+        c.write_all(&message[..]).unwrap();
+        c.flush().unwrap();
+        let untrained = c.into_inner();
+
+        let mut c = Writer::new(vec![]);
+        c.load_model(Cursor::new(&saved[..])).unwrap();
+        c.write_all(&message[..]).unwrap();
+        c.flush().unwrap();
+        let compressed = c.into_inner();
+        assert!(compressed.len() < untrained.len());
+
+        let mut d = Reader::new(Cursor::new(compressed)).unwrap();
+        d.load_model(Cursor::new(&saved[..])).unwrap();
+        let mut decompressed = Vec::new();
+        d.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(&message[..], &decompressed[..]);
+
+        let mut c = Writer::with_context_bits(vec![], 12);
+        assert!(c.load_model(Cursor::new(&saved[..])).is_err());
+        saved[2] = 0;
+        let mut c = Writer::new(vec![]);
+        assert!(c.load_model(Cursor::new(&saved[..])).is_err());
+    }
+
+    #[test]
+    fn count_aging() {
+        let mut m = ContextModel::new(8);
+        for _ in 0..10000 {
+            m.update(0);
+        }
+        let (c0, c1) = m.counts[0];
+        assert!(c0 + c1 < COUNT_LIMIT);
+        assert!(c1 >= 1);
+        for _ in 0..100 {
+            m.update(1);
+        }
+        let (c0, c1) = m.counts[0xff];
+        assert!(c1 > c0);
+    }
+
+    #[test]
+    fn context_bits() {
+        let f = include_bytes!("binarith.rs");
+        let original = &f[..];
+
+        for &bits in &[8, 12, 20, 24] {
+            let mut c = Writer::with_context_bits(vec![], bits);
+            c.write_all(original).unwrap();
+            c.flush().unwrap();
+            let compressed = c.into_inner();
+
+            let mut d = Reader::with_context_bits(Cursor::new(compressed), bits).unwrap();
+            let mut decompressed = Vec::new();
+            d.read_to_end(&mut decompressed).unwrap();
+            assert_eq!(&original[..], &decompressed[..]);
+        }
+    }
+
+    #[test]
+    fn sse() {
+        let f = include_bytes!("binarith.rs");
+        let original = &f[..];
+
+        let mut c = Writer::new(vec![]);
+        c.write_all(original).unwrap();
+        c.flush().unwrap();
+        let plain = c.into_inner();
+
+        let mut c = Writer::new(vec![]);
+        c.enable_sse();
+        c.write_all(original).unwrap();
+        c.flush().unwrap();
+        let compressed = c.into_inner();
+        assert!(compressed.len() < plain.len());
+
+        let mut d = Reader::new(Cursor::new(compressed)).unwrap();
+        d.enable_sse();
+        let mut decompressed = Vec::new();
+        d.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(&original[..], &decompressed[..]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn context_bits_too_large() {
+        Writer::with_context_bits(vec![], 25);
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+    }
 }
@@ -1,135 +1,664 @@
 //! Routines for bitwise input/output.
+//!
+//! By default, bits are packed into bytes starting with the most
+//! significant bit, and values of several bits are written starting
+//! with their most significant bit.  In LSB-first order, which is used
+//! by formats like DEFLATE, both bytes and values are filled starting
+//! with the least significant bit.  Both the reader and the writer keep up to 64 bits in an
+//! accumulator, and exchange whole buffers of bytes with the wrapped
+//! reader or writer, so that reading or writing several bits at once
+//! takes only a few shifts.  As a consequence, the reader may read
+//! ahead of the bits it has returned, and the writer only passes
+//! bytes on when its buffer is full or when it is flushed.
 
-use std::io::Read;
-use std::io::Write;
-use std::io;
+use io::Read;
+use io::Write;
+use io;
+use error::Error;
+use alloc::vec::Vec;
+
+/// Size of the byte buffers of readers and writers.
+const BUF_SIZE: usize = 4096;
+
+/// Order in which bits are packed into bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOrder {
+    /// Most significant bit first.
+    MsbFirst,
+    /// Least significant bit first.
+    LsbFirst,
+}
 
 pub struct BitReader<R> {
     inner: R,
-    buf: u8,
-    mask: u8,
+    order: BitOrder,
+    buf: Vec<u8>,
+    buf_pos: usize,
+    buf_len: usize,
+    /// Bits read from the buffer but not returned yet, in the
+    /// `acc_bits` low bits.  For MSB-first order, the next bit is the
+    /// most significant of these, otherwise the least significant.
+    acc: u64,
+    acc_bits: usize,
     extra_bits: usize,
+    bits_read: u64,
 }
 
 impl<R: Read> BitReader<R> {
     /// Create a new `BitReader` from a `Read` instance.
     pub fn new(inner: R) -> BitReader<R> {
-        BitReader{
-            inner: inner,
-            buf: 0,
-            mask: 0x80,
-            extra_bits: 0,
-        }
+        BitReader::new_with_extra(inner, 0)
     }
 
+    /// Create a new `BitReader` which returns up to `extra` zero bits
+    /// after the end of the input, before reporting the end of file.
     pub fn new_with_extra(inner: R, extra: usize) -> BitReader<R> {
+        BitReader::with_order(inner, BitOrder::MsbFirst, extra)
+    }
+
+    /// Create a new `BitReader` for bits packed in `order`, which
+    /// returns up to `extra` zero bits after the end of the input.
+    pub fn with_order(inner: R, order: BitOrder, extra: usize) -> BitReader<R> {
         BitReader{
             inner: inner,
-            buf: 0,
-            mask: 0x80,
+            order: order,
+            buf: vec![0; BUF_SIZE],
+            buf_pos: 0,
+            buf_len: 0,
+            acc: 0,
+            acc_bits: 0,
             extra_bits: extra,
+            bits_read: 0,
         }
     }
 
-    /// Read the next bit.
-    pub fn read_bit(&mut self) -> io::Result<bool> {
-        if self.mask == 0x80 {
-            let mut b = [0u8; 1];
-            let nread = try!(self.inner.read(&mut b[..]));
-            if nread == 0 {
-                if self.extra_bits > 0 {
-                    self.extra_bits -= 1;
-                    return Ok(false);
-                } else {
-                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, ""));
+    /// Fill the accumulator with as many whole bytes as fit.  At the
+    /// end of the input, fewer bits may be available.
+    fn refill(&mut self) -> io::Result<()> {
+        while self.acc_bits <= 56 {
+            if self.buf_pos == self.buf_len {
+                self.buf_len = try!(self.inner.read(&mut self.buf));
+                self.buf_pos = 0;
+                if self.buf_len == 0 {
+                    break;
                 }
             }
-            self.buf = b[0];
+            let byte = self.buf[self.buf_pos] as u64;
+            self.acc = match self.order {
+                BitOrder::MsbFirst => (self.acc << 8) | byte,
+                BitOrder::LsbFirst => self.acc | (byte << self.acc_bits),
+            };
+            self.buf_pos += 1;
+            self.acc_bits += 8;
         }
-        let result = self.buf & self.mask;
-        self.mask >>= 1;
-        if self.mask == 0 {
-            self.mask = 0x80;
+        Ok(())
+    }
+
+    /// Read the next bit.
+    #[inline]
+    pub fn read_bit(&mut self) -> io::Result<bool> {
+        if self.acc_bits > 0 && self.order == BitOrder::MsbFirst {
+            self.acc_bits -= 1;
+            self.bits_read += 1;
+            return Ok((self.acc >> self.acc_bits) & 1 != 0);
         }
-        Ok(result != 0)
+        Ok(try!(self.read_bits(1)) != 0)
     }
 
     /// Read the next `count` bits, as the least significant bits of
     /// the returned 64-bit value.  Note that the maximum number of
     /// bits to read in one call is 64.
-    pub fn read_bits(&mut self, mut count: usize) -> io::Result<u64> {
-        let mut result = 0;
-        while count > 0 {
-            let b = try!(self.read_bit());
-            result <<= 1;
-            if b {
-                result |= 1;
+    #[inline]
+    pub fn read_bits(&mut self, count: usize) -> io::Result<u64> {
+        if count > 32 {
+            return match self.order {
+                BitOrder::MsbFirst => {
+                    let high = try!(self.read_bits(count - 32));
+                    let low = try!(self.read_bits(32));
+                    Ok((high << 32) | low)
+                },
+                BitOrder::LsbFirst => {
+                    let low = try!(self.read_bits(32));
+                    let high = try!(self.read_bits(count - 32));
+                    Ok((high << 32) | low)
+                },
+            };
+        }
+        if self.acc_bits < count {
+            try!(self.refill());
+            if self.acc_bits < count {
+                let missing = count - self.acc_bits;
+                if missing > self.extra_bits {
+                    return Err(Error::UnexpectedEof.into());
+                }
+                self.extra_bits -= missing;
+                if self.order == BitOrder::MsbFirst {
+                    self.acc <<= missing;
+                }
+                self.acc_bits += missing;
+            }
+        }
+        self.acc_bits -= count;
+        self.bits_read += count as u64;
+        match self.order {
+            BitOrder::MsbFirst =>
+                Ok((self.acc >> self.acc_bits) & ((1 << count) - 1)),
+            BitOrder::LsbFirst => {
+                let result = self.acc & ((1 << count) - 1);
+                self.acc >>= count;
+                Ok(result)
+            },
+        }
+    }
+
+    /// Return the next `count` bits like `read_bits`, but without
+    /// consuming them.  Past the end of the input, zero bits are
+    /// returned, so that table-driven decoders can always look up a
+    /// full table index.  `count` must not be larger than 56.
+    #[inline]
+    pub fn peek_bits(&mut self, count: usize) -> io::Result<u64> {
+        assert!(count <= 56);
+        if self.acc_bits < count {
+            try!(self.refill());
+        }
+        let mask = (1 << count) - 1;
+        match self.order {
+            BitOrder::MsbFirst =>
+                if self.acc_bits >= count {
+                    Ok((self.acc >> (self.acc_bits - count)) & mask)
+                } else {
+                    Ok((self.acc << (count - self.acc_bits)) & mask)
+                },
+            BitOrder::LsbFirst =>
+                Ok(self.acc & mask),
+        }
+    }
+
+    /// Consume `count` bits, usually after looking at them with
+    /// `peek_bits`.  It is an error to consume bits past the end of
+    /// the input, except for the extra bits given on construction.
+    #[inline]
+    pub fn consume_bits(&mut self, count: usize) -> io::Result<()> {
+        try!(self.read_bits(count));
+        Ok(())
+    }
+
+    /// Push back the `count` low bits of `value`, so that they are
+    /// returned again by the next read.  Pushing back the bits of the
+    /// last `read_bits` call always succeeds.  Older bits can only be
+    /// pushed back as long as they still fit into the accumulator, and
+    /// never more bits than have been read, otherwise an error is
+    /// returned.
+    pub fn unread_bits(&mut self, value: u64, count: usize) -> io::Result<()> {
+        if count > 64 - self.acc_bits || count as u64 > self.bits_read {
+            return Err(io::Error::new(io::ErrorKind::Other, "too many bits pushed back"));
+        }
+        if count == 0 {
+            return Ok(());
+        }
+        let value = if count == 64 { value } else { value & ((1 << count) - 1) };
+        self.acc = match self.order {
+            BitOrder::MsbFirst =>
+                (value << self.acc_bits) | (self.acc & ((1 << self.acc_bits) - 1)),
+            BitOrder::LsbFirst =>
+                if count == 64 { value } else { (self.acc << count) | value },
+        };
+        self.acc_bits += count;
+        self.bits_read -= count as u64;
+        Ok(())
+    }
+
+    /// Read a number in Elias gamma code, as written by
+    /// `BitWriter::write_gamma`.
+    pub fn read_gamma(&mut self) -> io::Result<u64> {
+        let mut zeros = 0;
+        while !try!(self.read_bit()) {
+            zeros += 1;
+            if zeros > 63 {
+                return Err(Error::corrupt_at(self.position(), "invalid gamma code").into());
+            }
+        }
+        let rest = try!(self.read_bits(zeros));
+        Ok((1 << zeros) | rest)
+    }
+
+    /// Read a number in Elias delta code, as written by
+    /// `BitWriter::write_delta`.
+    pub fn read_delta(&mut self) -> io::Result<u64> {
+        let len = try!(self.read_gamma());
+        if len > 64 {
+            return Err(Error::corrupt_at(self.position(), "invalid delta code").into());
+        }
+        let n = (len - 1) as usize;
+        let rest = try!(self.read_bits(n));
+        Ok((1 << n) | rest)
+    }
+
+    /// Read a number in Rice code with parameter `k`, as written by
+    /// `BitWriter::write_rice`.
+    pub fn read_rice(&mut self, k: usize) -> io::Result<u64> {
+        let q = try!(self.read_unary());
+        if k > 0 && q >> (64 - k) != 0 {
+            return Err(Error::corrupt_at(self.position(), "invalid rice code").into());
+        }
+        let rest = try!(self.read_bits(k));
+        Ok(q << k | rest)
+    }
+
+    /// Read a number in unary code, as written by
+    /// `BitWriter::write_unary`.  The one bits are counted a whole
+    /// accumulator at a time.
+    pub fn read_unary(&mut self) -> io::Result<u64> {
+        let mut value = 0;
+        loop {
+            if self.acc_bits == 0 {
+                try!(self.refill());
+                if self.acc_bits == 0 {
+                    // Let `read_bit` supply the extra bits or report
+                    // the end of the input.
+                    if !try!(self.read_bit()) {
+                        return Ok(value);
+                    }
+                    value += 1;
+                    continue;
+                }
+            }
+            let ones = match self.order {
+                BitOrder::MsbFirst =>
+                    (!(self.acc << (64 - self.acc_bits))).leading_zeros() as usize,
+                BitOrder::LsbFirst =>
+                    (!self.acc).trailing_zeros() as usize,
+            };
+            if ones < self.acc_bits {
+                try!(self.read_bits(ones + 1));
+                return Ok(value + ones as u64);
+            }
+            let ones = self.acc_bits;
+            try!(self.read_bits(ones));
+            value += ones as u64;
+        }
+    }
+
+    /// Skip the remaining bits of the current byte, so that the next
+    /// bit read is the first bit of a byte.
+    pub fn align_to_byte(&mut self) {
+        let skip = self.acc_bits % 8;
+        self.acc_bits -= skip;
+        self.bits_read += skip as u64;
+        if self.order == BitOrder::LsbFirst {
+            self.acc >>= skip;
+        }
+    }
+
+    /// Fill `output` with the next bytes of the input.  When the
+    /// reader is at a byte boundary, the bytes are copied directly
+    /// from the input, otherwise they are read bit by bit.
+    pub fn read_aligned_bytes(&mut self, output: &mut [u8]) -> io::Result<()> {
+        if self.acc_bits % 8 != 0 {
+            for b in output.iter_mut() {
+                *b = try!(self.read_bits(8)) as u8;
+            }
+            return Ok(());
+        }
+        let mut pos = 0;
+        while pos < output.len() && self.acc_bits > 0 {
+            output[pos] = try!(self.read_bits(8)) as u8;
+            pos += 1;
+        }
+        while pos < output.len() {
+            if self.buf_pos == self.buf_len {
+                if output.len() - pos >= BUF_SIZE {
+                    try!(self.inner.read_exact(&mut output[pos..]));
+                    self.bits_read += (output.len() - pos) as u64 * 8;
+                    return Ok(());
+                }
+                self.buf_len = try!(self.inner.read(&mut self.buf));
+                self.buf_pos = 0;
+                if self.buf_len == 0 {
+                    return Err(Error::UnexpectedEof.into());
+                }
             }
-            count -= 1;
+            let n = ::core::cmp::min(output.len() - pos, self.buf_len - self.buf_pos);
+            output[pos..pos + n].copy_from_slice(&self.buf[self.buf_pos..self.buf_pos + n]);
+            self.buf_pos += n;
+            self.bits_read += n as u64 * 8;
+            pos += n;
         }
-        Ok(result)
+        Ok(())
+    }
+
+    /// Return the number of bits read so far, including skipped
+    /// bits and zero bits returned after the end of the input.
+    pub fn bits_read(&self) -> u64 {
+        self.bits_read
+    }
+
+    /// Return the number of bytes the bits read so far came from,
+    /// counting a partially read byte as a whole one.
+    pub fn bytes_read(&self) -> u64 {
+        (self.bits_read + 7) / 8
+    }
+
+    /// Return the offset of the byte which holds the next unread bit,
+    /// counted from the start of the input.  Decoders report errors
+    /// at this position.
+    pub fn position(&self) -> u64 {
+        self.bits_read / 8
+    }
+
+    /// Return the wrapped reader, together with the bytes which have
+    /// been read from it, but not consumed yet.  The rest of a
+    /// partially read byte is dropped, so the returned bytes start at
+    /// offset `bytes_read()` of the input.  This can be used to
+    /// continue reading data which follows the bit stream, even after
+    /// an error.
+    pub fn into_inner(self) -> (R, Vec<u8>) {
+        let whole_bytes = self.acc_bits / 8;
+        let acc = match self.order {
+            BitOrder::MsbFirst => self.acc,
+            BitOrder::LsbFirst => self.acc >> (self.acc_bits % 8),
+        };
+        let mut rest = Vec::with_capacity(whole_bytes + self.buf_len - self.buf_pos);
+        for i in 0..whole_bytes {
+            let shift = match self.order {
+                BitOrder::MsbFirst => (whole_bytes - 1 - i) * 8,
+                BitOrder::LsbFirst => i * 8,
+            };
+            rest.push((acc >> shift) as u8);
+        }
+        rest.extend_from_slice(&self.buf[self.buf_pos..self.buf_len]);
+        (self.inner, rest)
     }
 }
 
 impl<R: Read> Read for BitReader<R> {
+    /// Read the next bytes, each made up of the next 8 bits.  At the
+    /// end of the input, fewer bytes than requested are returned, and
+    /// then zero.  Bits left over at the end of the input that do not
+    /// make up a whole byte are ignored.
     fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
-        for i in 0..output.len() {
-            let b = try!(self.read_bits(8));
-            output[i] = b as u8;
+        let mut nread = 0;
+        while nread < output.len() {
+            match self.read_bits(8) {
+                Ok(b) => {
+                    output[nread] = b as u8;
+                    nread += 1;
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
         }
-        Ok(output.len())
+        Ok(nread)
     }
 }
 
+/// Estimates the Rice parameter for a stream of values from the
+/// running mean of the values seen so far, as done in LOCO-I: `k` is
+/// the smallest number such that `count << k` is at least the sum of
+/// the values.  The statistics are halved periodically, so that the
+/// estimate adapts to changes in the input.
+#[derive(Clone)]
+pub struct RiceEstimator {
+    sum: u64,
+    count: u64,
+}
+
+const RICE_RESET: u64 = 64;
+const RICE_MAX_K: usize = 32;
+
+impl RiceEstimator {
+    /// Create a new estimator, which starts with `k` = 0.
+    pub fn new() -> RiceEstimator {
+        RiceEstimator {
+            sum: 0,
+            count: 1,
+        }
+    }
+
+    /// Return the parameter to use for the next value.
+    pub fn k(&self) -> usize {
+        let mut k = 0;
+        while k < RICE_MAX_K && (self.count << k) < self.sum {
+            k += 1;
+        }
+        k
+    }
+
+    /// Update the statistics with a coded value.
+    pub fn update(&mut self, value: u64) {
+        self.sum = self.sum.saturating_add(value);
+        self.count += 1;
+        if self.count >= RICE_RESET {
+            self.sum >>= 1;
+            self.count >>= 1;
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct BitWriter<W> {
     inner: W,
-    buf: u8,
-    mask: u8,
+    order: BitOrder,
+    buf: Vec<u8>,
+    /// Bits not yet moved to the buffer, in the `acc_bits` low bits.
+    acc: u64,
+    acc_bits: usize,
+    bits_written: u64,
 }
 
 impl<W: Write> BitWriter<W> {
     /// Create a bit writer from a `Write` instance.
     pub fn new(inner: W) -> BitWriter<W> {
+        BitWriter::with_order(inner, BitOrder::MsbFirst)
+    }
+
+    /// Create a bit writer which packs bits in `order`.
+    pub fn with_order(inner: W, order: BitOrder) -> BitWriter<W> {
         BitWriter{
             inner: inner,
-            buf: 0,
-            mask: 0x80,
+            order: order,
+            buf: Vec::with_capacity(BUF_SIZE),
+            acc: 0,
+            acc_bits: 0,
+            bits_written: 0,
         }
     }
 
     /// Write a bit to the underlying `Write` instance.
     fn write_bit(&mut self, bit: bool) -> io::Result<()> {
-        if bit {
-            self.buf |= self.mask;
+        self.write_bits(bit as u64, 1)
+    }
+
+    /// Write the `count` least significant bits from `value`.  Note
+    /// that the maximum number of bits to write in one call is 64.
+    #[inline]
+    pub fn write_bits(&mut self, value: u64, count: usize) -> io::Result<()> {
+        if count > 32 {
+            return match self.order {
+                BitOrder::MsbFirst => {
+                    try!(self.write_bits(value >> 32, count - 32));
+                    self.write_bits(value, 32)
+                },
+                BitOrder::LsbFirst => {
+                    try!(self.write_bits(value, 32));
+                    self.write_bits(value >> 32, count - 32)
+                },
+            };
         }
-        self.mask >>= 1;
-        if self.mask == 0 {
-            try!(self.inner.write(&[self.buf]));
-            self.mask = 0x80;
-            self.buf = 0;
+        let value = value & ((1 << count) - 1);
+        self.acc = match self.order {
+            BitOrder::MsbFirst => (self.acc << count) | value,
+            BitOrder::LsbFirst => self.acc | (value << self.acc_bits),
+        };
+        self.acc_bits += count;
+        self.bits_written += count as u64;
+        if self.acc_bits >= 8 {
+            try!(self.drain());
         }
         Ok(())
     }
 
-    /// Write the `count` least significant bits from `value`.  Note
-    /// that the maximum number of bits to write in one call is 64.
-    pub fn write_bits(&mut self, value: u64, mut count: usize) -> io::Result<()> {
-        while count > 0 {
-            count -= 1;
-            try!(self.write_bit((value & (1 << count)) != 0));
+    /// Move all whole bytes from the accumulator to the buffer, and
+    /// write the buffer when it is full.
+    fn drain(&mut self) -> io::Result<()> {
+        while self.acc_bits >= 8 {
+            self.acc_bits -= 8;
+            match self.order {
+                BitOrder::MsbFirst => {
+                    self.buf.push((self.acc >> self.acc_bits) as u8);
+                },
+                BitOrder::LsbFirst => {
+                    self.buf.push(self.acc as u8);
+                    self.acc >>= 8;
+                },
+            }
+        }
+        if self.buf.len() >= BUF_SIZE {
+            try!(self.inner.write_all(&self.buf));
+            self.buf.clear();
         }
         Ok(())
     }
 
-    /// Flush any unwritten bits to the underlying `Write` instance
-    /// and return it.
+    /// Write `value` in Elias gamma code: the number of significant
+    /// bits minus one in unary (as zero bits), followed by the
+    /// significant bits.  This takes `2 * floor(log2(value)) + 1`
+    /// bits.  `value` must be at least 1.
+    pub fn write_gamma(&mut self, value: u64) -> io::Result<()> {
+        if value == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "gamma code of zero"));
+        }
+        let n = 63 - value.leading_zeros() as usize;
+        try!(self.write_bits(0, n));
+        try!(self.write_bit(true));
+        self.write_bits(value, n)
+    }
+
+    /// Write `value` in Elias delta code: the number of significant
+    /// bits in gamma code, followed by the significant bits except
+    /// the leading one.  This is shorter than the gamma code for
+    /// values above 31.  `value` must be at least 1.
+    pub fn write_delta(&mut self, value: u64) -> io::Result<()> {
+        if value == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "delta code of zero"));
+        }
+        let n = 63 - value.leading_zeros() as usize;
+        try!(self.write_gamma(n as u64 + 1));
+        self.write_bits(value, n)
+    }
+
+    /// Write `value` in Rice code with parameter `k`: the quotient
+    /// `value >> k` in unary (as one bits, terminated by a zero bit),
+    /// followed by the `k` low bits of `value`.  `k` must be less
+    /// than 64.
+    pub fn write_rice(&mut self, value: u64, k: usize) -> io::Result<()> {
+        try!(self.write_unary(value >> k));
+        self.write_bits(value, k)
+    }
+
+    /// Write `value` in unary code: `value` one bits, followed by a
+    /// zero bit.
+    pub fn write_unary(&mut self, mut value: u64) -> io::Result<()> {
+        while value >= 32 {
+            try!(self.write_bits(0xffff_ffff, 32));
+            value -= 32;
+        }
+        try!(self.write_bits((1 << value) - 1, value as usize));
+        self.write_bit(false)
+    }
+
+    /// Write a prefix code of `code_len` bits, followed by `extra` in
+    /// `extra_bits` bits, as needed for the length and distance codes
+    /// of DEFLATE.  The prefix code is always written starting with
+    /// its most significant bit, so that it can be decoded bit by bit
+    /// or with a table.  The extra bits are written like
+    /// `write_bits` does, which means least significant bit first for
+    /// LSB-first order.  Together, the code and the extra bits must
+    /// not be longer than 64 bits.
+    pub fn write_prefix_extra(&mut self, code: u64, code_len: usize,
+                              extra: u64, extra_bits: usize) -> io::Result<()> {
+        debug_assert!(code_len + extra_bits <= 64);
+        let len = code_len + extra_bits;
+        if len > 32 {
+            try!(self.write_prefix_extra(code, code_len, 0, 0));
+            return self.write_bits(extra, extra_bits);
+        }
+        let extra = extra & ((1 << extra_bits) - 1);
+        let value = match self.order {
+            BitOrder::MsbFirst =>
+                (code << extra_bits) | extra,
+            BitOrder::LsbFirst => {
+                let reversed =
+                    if code_len == 0 { 0 } else { code.reverse_bits() >> (64 - code_len) };
+                reversed | (extra << code_len)
+            },
+        };
+        self.write_bits(value, len)
+    }
+
+    /// Fill the current byte with zero bits, so that the next bit
+    /// written starts a new byte.
+    pub fn align_to_byte(&mut self) -> io::Result<()> {
+        if self.acc_bits > 0 {
+            let pad = 8 - self.acc_bits;
+            try!(self.write_bits(0, pad));
+        }
+        Ok(())
+    }
+
+    /// Write the bytes in `input`.  When the writer is at a byte
+    /// boundary, the bytes are copied directly to the output,
+    /// otherwise they are written bit by bit.
+    pub fn write_aligned_bytes(&mut self, input: &[u8]) -> io::Result<()> {
+        if self.acc_bits > 0 {
+            for &b in input.iter() {
+                try!(self.write_bits(b as u64, 8));
+            }
+            return Ok(());
+        }
+        self.bits_written += input.len() as u64 * 8;
+        if self.buf.len() + input.len() <= BUF_SIZE {
+            self.buf.extend_from_slice(input);
+        } else {
+            try!(self.inner.write_all(&self.buf));
+            self.buf.clear();
+            try!(self.inner.write_all(input));
+        }
+        Ok(())
+    }
+
+    /// Write any unwritten bits to the underlying `Write` instance,
+    /// padding the last byte with zero bits.
     pub fn do_flush(&mut self) -> io::Result<()> {
-        if self.mask != 0x80 {
-            try!(self.inner.write(&[self.buf]));
+        if self.acc_bits > 0 {
+            match self.order {
+                BitOrder::MsbFirst => self.buf.push((self.acc << (8 - self.acc_bits)) as u8),
+                BitOrder::LsbFirst => self.buf.push(self.acc as u8),
+            }
+            self.bits_written += (8 - self.acc_bits) as u64;
+            self.acc = 0;
+            self.acc_bits = 0;
         }
+        try!(self.inner.write_all(&self.buf));
+        self.buf.clear();
         Ok(())
     }
 
+    /// Return the number of bits written so far, including the
+    /// padding added by `align_to_byte` and by flushing.
+    pub fn bits_written(&self) -> u64 {
+        self.bits_written
+    }
+
+    /// Return the number of bytes needed for the bits written so far.
+    /// This is the number of bytes the output will grow by, once the
+    /// writer has been flushed.
+    pub fn bytes_written(&self) -> u64 {
+        (self.bits_written + 7) / 8
+    }
+
+    /// Return the underlying `Write` instance.  Bits which have not
+    /// been flushed are lost.
     pub fn to_inner(self) -> W {
         self.inner
     }
@@ -152,11 +681,13 @@ impl<W: Write> Write for BitWriter<W> {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod test {
-    use std::io::{Cursor, Write};
+    use std::io::{self, Cursor, Read, Write};
     use super::BitReader;
     use super::BitWriter;
+    use super::BitOrder;
+    use super::RiceEstimator;
 
     #[test]
     fn write_bit() {
@@ -229,4 +760,359 @@ mod test {
         assert_eq!(0b1, b);
     }
 
+    #[test]
+    fn write_gamma() {
+        let mut bf = BitWriter::new(vec![]);
+        bf.write_gamma(1).unwrap();
+        bf.write_gamma(2).unwrap();
+        bf.write_gamma(5).unwrap();
+        bf.write_gamma(8).unwrap();
+        bf.flush().unwrap();
+        assert_eq!(vec![0b1010_0010, 0b1000_1000], bf.to_inner());
+    }
+
+    #[test]
+    fn write_delta() {
+        let mut bf = BitWriter::new(vec![]);
+        bf.write_delta(1).unwrap();
+        bf.write_delta(2).unwrap();
+        bf.write_delta(17).unwrap();
+        bf.flush().unwrap();
+        assert_eq!(vec![0b1010_0001, 0b0100_0100], bf.to_inner());
+    }
+
+    #[test]
+    fn write_zero() {
+        let mut bf = BitWriter::new(vec![]);
+        assert!(bf.write_gamma(0).is_err());
+        assert!(bf.write_delta(0).is_err());
+    }
+
+    #[test]
+    fn gamma_delta_roundtrip() {
+        let values = [1, 2, 3, 4, 7, 31, 32, 255, 4096, 65535, 1 << 40, u64::max_value()];
+        let mut bf = BitWriter::new(vec![]);
+        for &v in values.iter() {
+            bf.write_gamma(v).unwrap();
+            bf.write_delta(v).unwrap();
+        }
+        bf.flush().unwrap();
+        let mut bf = BitReader::new(Cursor::new(bf.to_inner()));
+        for &v in values.iter() {
+            assert_eq!(v, bf.read_gamma().unwrap());
+            assert_eq!(v, bf.read_delta().unwrap());
+        }
+    }
+
+    #[test]
+    fn write_rice() {
+        let mut bf = BitWriter::new(vec![]);
+        bf.write_rice(0, 2).unwrap();
+        bf.write_rice(5, 2).unwrap();
+        bf.write_rice(3, 0).unwrap();
+        bf.flush().unwrap();
+        assert_eq!(vec![0b0001_0011, 0b1100_0000], bf.to_inner());
+    }
+
+    #[test]
+    fn rice_roundtrip() {
+        let values = [0, 1, 2, 7, 100, 1000, 4095];
+        let mut bf = BitWriter::new(vec![]);
+        for k in 0..12 {
+            for &v in values.iter() {
+                bf.write_rice(v, k).unwrap();
+            }
+        }
+        bf.flush().unwrap();
+        let mut bf = BitReader::new(Cursor::new(bf.to_inner()));
+        for k in 0..12 {
+            for &v in values.iter() {
+                assert_eq!(v, bf.read_rice(k).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn rice_estimator() {
+        let mut est = RiceEstimator::new();
+        assert_eq!(0, est.k());
+        for _ in 0..100 {
+            est.update(1000);
+        }
+        assert_eq!(10, est.k());
+        for _ in 0..1000 {
+            est.update(3);
+        }
+        assert_eq!(2, est.k());
+    }
+
+    #[test]
+    fn bits_roundtrip() {
+        // Enough data to go through the byte buffers several times.
+        let values: Vec<(u64, usize)> = (0..20000u64)
+            .map(|i| (i.wrapping_mul(0x9e37_79b9_7f4a_7c15), (i % 65) as usize))
+            .collect();
+        let mut bf = BitWriter::new(vec![]);
+        for &(v, n) in values.iter() {
+            bf.write_bits(v, n).unwrap();
+        }
+        bf.flush().unwrap();
+        let mut bf = BitReader::new(Cursor::new(bf.to_inner()));
+        for &(v, n) in values.iter() {
+            let mask = if n == 64 { !0 } else { (1 << n) - 1 };
+            assert_eq!(v & mask, bf.read_bits(n).unwrap());
+        }
+    }
+
+    #[test]
+    fn write_bits_lsb_first() {
+        let mut bf = BitWriter::with_order(vec![], BitOrder::LsbFirst);
+        bf.write_bits(0b1011, 4).unwrap();
+        bf.write_bits(0b000, 3).unwrap();
+        bf.write_bits(0b0010, 4).unwrap();
+        bf.write_bits(0b11111, 5).unwrap();
+        bf.write_bits(0b11, 2).unwrap();
+        bf.write_bits(0b11_0010_1010, 10).unwrap();
+        bf.flush().unwrap();
+        assert_eq!(vec![0b0000_1011, 0b1111_1001, 0b1010_1011, 0b0000_1100], bf.to_inner());
+    }
+
+    #[test]
+    fn read_bits_lsb_first() {
+        let c = Cursor::new(vec![0b1111_0001, 0b0101_1100, 0b1000_0000]);
+        let mut bf = BitReader::with_order(c, BitOrder::LsbFirst, 0);
+        assert_eq!(0b10001, bf.read_bits(5).unwrap());
+        assert_eq!(0b11, bf.read_bits(2).unwrap());
+        assert_eq!(0b1001, bf.read_bits(4).unwrap());
+        assert_eq!(0b0000_0000_1011, bf.read_bits(12).unwrap());
+        assert_eq!(true, bf.read_bit().unwrap());
+        assert!(bf.read_bit().is_err());
+    }
+
+    #[test]
+    fn bits_roundtrip_lsb_first() {
+        let values: Vec<(u64, usize)> = (0..20000u64)
+            .map(|i| (i.wrapping_mul(0x9e37_79b9_7f4a_7c15), (i % 65) as usize))
+            .collect();
+        let mut bf = BitWriter::with_order(vec![], BitOrder::LsbFirst);
+        for &(v, n) in values.iter() {
+            bf.write_bits(v, n).unwrap();
+            bf.write_gamma(n as u64 + 1).unwrap();
+        }
+        bf.flush().unwrap();
+        let mut bf = BitReader::with_order(Cursor::new(bf.to_inner()), BitOrder::LsbFirst, 0);
+        for &(v, n) in values.iter() {
+            let mask = if n == 64 { !0 } else { (1 << n) - 1 };
+            assert_eq!(v & mask, bf.read_bits(n).unwrap());
+            assert_eq!(n as u64 + 1, bf.read_gamma().unwrap());
+        }
+    }
+
+    #[test]
+    fn aligned_bytes() {
+        let big: Vec<u8> = (0..10000).map(|i| (i * 7) as u8).collect();
+        for &order in &[BitOrder::MsbFirst, BitOrder::LsbFirst] {
+            let mut bf = BitWriter::with_order(vec![], order);
+            bf.write_bits(0b101, 3).unwrap();
+            bf.align_to_byte().unwrap();
+            bf.write_aligned_bytes(b"stored").unwrap();
+            bf.write_bits(0b1, 1).unwrap();
+            bf.write_aligned_bytes(b"unaligned").unwrap();
+            bf.align_to_byte().unwrap();
+            bf.align_to_byte().unwrap();
+            bf.write_aligned_bytes(&big).unwrap();
+            bf.write_bits(0b11, 2).unwrap();
+            bf.flush().unwrap();
+            let out = bf.to_inner();
+            assert_eq!(&b"stored"[..], &out[1..7]);
+            assert_eq!(1 + 6 + 10 + big.len() + 1, out.len());
+
+            let mut bf = BitReader::with_order(Cursor::new(out), order, 0);
+            let mut buf = [0; 9];
+            assert_eq!(0b101, bf.read_bits(3).unwrap());
+            bf.align_to_byte();
+            bf.read_aligned_bytes(&mut buf[..6]).unwrap();
+            assert_eq!(b"stored", &buf[..6]);
+            assert_eq!(0b1, bf.read_bits(1).unwrap());
+            bf.read_aligned_bytes(&mut buf).unwrap();
+            assert_eq!(b"unaligned", &buf);
+            bf.align_to_byte();
+            bf.align_to_byte();
+            let mut big_in = vec![0; big.len()];
+            bf.read_aligned_bytes(&mut big_in).unwrap();
+            assert_eq!(big, big_in);
+            assert_eq!(0b11, bf.read_bits(2).unwrap());
+        }
+    }
+
+    #[test]
+    fn bit_counts() {
+        let mut bf = BitWriter::new(vec![]);
+        bf.write_bits(0b101, 3).unwrap();
+        assert_eq!(3, bf.bits_written());
+        assert_eq!(1, bf.bytes_written());
+        bf.write_gamma(5).unwrap();
+        assert_eq!(8, bf.bits_written());
+        bf.write_bits(0, 1).unwrap();
+        bf.align_to_byte().unwrap();
+        assert_eq!(16, bf.bits_written());
+        bf.write_aligned_bytes(b"abc").unwrap();
+        bf.write_bits(0b1, 1).unwrap();
+        assert_eq!(41, bf.bits_written());
+        assert_eq!(6, bf.bytes_written());
+        bf.flush().unwrap();
+        assert_eq!(48, bf.bits_written());
+        let out = bf.to_inner();
+        assert_eq!(6, out.len());
+
+        let mut bf = BitReader::new(Cursor::new(out));
+        bf.read_bits(3).unwrap();
+        assert_eq!(3, bf.bits_read());
+        assert_eq!(1, bf.bytes_read());
+        bf.read_gamma().unwrap();
+        bf.read_bit().unwrap();
+        bf.align_to_byte();
+        assert_eq!(16, bf.bits_read());
+        let mut buf = [0; 3];
+        bf.read_aligned_bytes(&mut buf).unwrap();
+        assert_eq!(40, bf.bits_read());
+        assert_eq!(5, bf.bytes_read());
+    }
+
+    #[test]
+    fn read_bytes() {
+        let mut bf = BitReader::new(Cursor::new(vec![0b1010_0101, 0b1100_0011, 0xff]));
+        assert_eq!(0b1010, bf.read_bits(4).unwrap());
+        let mut buf = [0; 4];
+        assert_eq!(2, bf.read(&mut buf).unwrap());
+        assert_eq!([0b0101_1100, 0b0011_1111], buf[..2]);
+        assert_eq!(0, bf.read(&mut buf).unwrap());
+        assert_eq!(0, bf.read(&mut buf).unwrap());
+
+        let input: Vec<u8> = (0..10000).map(|i| i as u8).collect();
+        let mut bf = BitReader::new(Cursor::new(input.clone()));
+        let mut output = Vec::new();
+        io::copy(&mut bf, &mut output).unwrap();
+        assert_eq!(input, output);
+    }
+
+    #[test]
+    fn into_inner() {
+        let mut trailer = vec![0; 6000];
+        for (i, b) in trailer.iter_mut().enumerate() {
+            *b = (i * 13) as u8;
+        }
+        for &order in &[BitOrder::MsbFirst, BitOrder::LsbFirst] {
+            let mut bf = BitWriter::with_order(vec![], order);
+            bf.write_bits(0x1234_5678_9, 36).unwrap();
+            bf.flush().unwrap();
+            let mut input = bf.to_inner();
+            input.extend_from_slice(&trailer);
+
+            let mut bf = BitReader::with_order(Cursor::new(input), order, 0);
+            assert_eq!(0x1234_5678_9, bf.read_bits(36).unwrap());
+            assert_eq!(5, bf.bytes_read());
+            let (inner, rest) = bf.into_inner();
+            let mut rest_input = Vec::new();
+            Cursor::new(rest).chain(inner).read_to_end(&mut rest_input).unwrap();
+            assert_eq!(trailer, rest_input);
+        }
+    }
+
+    #[test]
+    fn unary() {
+        let values = [0, 1, 2, 7, 31, 32, 33, 63, 64, 65, 200, 1000];
+        for &order in &[BitOrder::MsbFirst, BitOrder::LsbFirst] {
+            let mut bf = BitWriter::with_order(vec![], order);
+            for &v in values.iter() {
+                bf.write_bits(0b101, 3).unwrap();
+                bf.write_unary(v).unwrap();
+            }
+            bf.flush().unwrap();
+            let out = bf.to_inner();
+            let total: u64 = values.iter().map(|&v| v + 4).sum();
+            assert_eq!((total + 7) / 8, out.len() as u64);
+
+            let mut bf = BitReader::with_order(Cursor::new(out), order, 0);
+            for &v in values.iter() {
+                assert_eq!(0b101, bf.read_bits(3).unwrap());
+                assert_eq!(v, bf.read_unary().unwrap());
+            }
+        }
+        let mut bf = BitWriter::new(vec![]);
+        bf.write_unary(3).unwrap();
+        bf.flush().unwrap();
+        assert_eq!(vec![0b1110_0000], bf.to_inner());
+
+        let mut bf = BitReader::new(Cursor::new(vec![0xff, 0xff]));
+        assert!(bf.read_unary().is_err());
+        let mut bf = BitReader::new_with_extra(Cursor::new(vec![0xff]), 1);
+        assert_eq!(8, bf.read_unary().unwrap());
+    }
+
+    #[test]
+    fn prefix_extra() {
+        let mut bf = BitWriter::new(vec![]);
+        bf.write_prefix_extra(0b110, 3, 0b01, 2).unwrap();
+        bf.write_prefix_extra(0b1, 1, 0, 0).unwrap();
+        bf.flush().unwrap();
+        assert_eq!(vec![0b1100_1100], bf.to_inner());
+
+        let mut bf = BitWriter::with_order(vec![], BitOrder::LsbFirst);
+        bf.write_prefix_extra(0b110, 3, 0b01, 2).unwrap();
+        bf.write_prefix_extra(0b1, 1, 0, 0).unwrap();
+        bf.write_prefix_extra(0b1_0000_0000_0000_0000_0001, 21, 0x1234_5678, 32).unwrap();
+        bf.flush().unwrap();
+        let out = bf.to_inner();
+        assert_eq!(0b0110_1011, out[0]);
+
+        let mut bf = BitReader::with_order(Cursor::new(out), BitOrder::LsbFirst, 0);
+        assert_eq!(0b011, bf.read_bits(3).unwrap());
+        assert_eq!(0b01, bf.read_bits(2).unwrap());
+        assert_eq!(0b1, bf.read_bits(1).unwrap());
+        assert_eq!(0b1_0000_0000_0000_0000_0001, bf.read_bits(21).unwrap());
+        assert_eq!(0x1234_5678, bf.read_bits(32).unwrap());
+    }
+
+    #[test]
+    fn peek_unread() {
+        for &order in &[BitOrder::MsbFirst, BitOrder::LsbFirst] {
+            let mut bf = BitWriter::with_order(vec![], order);
+            for i in 0..1000 {
+                bf.write_bits(i, 10).unwrap();
+            }
+            bf.flush().unwrap();
+            let mut bf = BitReader::with_order(Cursor::new(bf.to_inner()), order, 0);
+            for i in 0..1000 {
+                assert_eq!(i, bf.peek_bits(10).unwrap());
+                let b = bf.read_bits(7).unwrap();
+                bf.unread_bits(b, 7).unwrap();
+                assert_eq!(i * 10, bf.bits_read());
+                bf.consume_bits(10).unwrap();
+            }
+            // Past the end, zero bits are peeked, but not consumed.
+            assert_eq!(0, bf.peek_bits(20).unwrap());
+            assert!(bf.consume_bits(1).is_err());
+        }
+
+        let mut bf = BitReader::new(Cursor::new(vec![0b1011_0000]));
+        assert_eq!(0b1011_0000_0000, bf.peek_bits(12).unwrap());
+        assert_eq!(0b1011, bf.read_bits(4).unwrap());
+        bf.unread_bits(0b0110, 4).unwrap();
+        assert_eq!(0b0110_0000, bf.read_bits(8).unwrap());
+        assert!(bf.unread_bits(0, 9).is_err());
+    }
+
+    #[test]
+    fn read_extra_bits() {
+        let mut bf = BitReader::new_with_extra(Cursor::new(vec![0b1010_1010]), 4);
+        assert_eq!(0b1010_1010_0000, bf.read_bits(12).unwrap());
+        assert!(bf.read_bit().is_err());
+    }
+
+    #[test]
+    fn read_gamma_invalid() {
+        let mut bf = BitReader::new(Cursor::new(vec![0u8; 9]));
+        assert!(bf.read_gamma().is_err());
+    }
 }
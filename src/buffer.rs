@@ -0,0 +1,202 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Compression of byte buffers.
+//!
+//! The functions and types here take byte slices and return vectors,
+//! without readers, writers or files, for callers which have their
+//! data in memory anyway, like JavaScript code calling a WebAssembly
+//! build of the crate.
+//!
+//! `compress` and `decompress` convert a whole buffer with one
+//! method.  `Compressor` and `Decompressor` convert data which arrives
+//! in pieces: each piece is pushed in, and the output which can be
+//! produced so far is returned.  They use the block container format,
+//! so that the decompressor can decode each block as soon as it is
+//! complete.
+
+use std::io::{self, Read, Write};
+
+use container::{self, Checkpoint};
+use error::Error;
+use method::Method;
+
+/// Compress `data` with `method`.
+pub fn compress(data: &[u8], method: Method) -> Result<Vec<u8>, Error> {
+    method.compress(data, Vec::new())
+}
+
+/// Decompress `data`, which was compressed with `method`.
+pub fn decompress(data: &[u8], method: Method) -> Result<Vec<u8>, Error> {
+    method.decompress(data, Vec::new())
+}
+
+/// Incremental compressor, producing a container.
+pub struct Compressor {
+    writer: container::Writer<Vec<u8>>,
+}
+
+impl Compressor {
+    /// Create a compressor which compresses blocks of the default
+    /// size with `method`.
+    pub fn new(method: Method) -> Compressor {
+        Compressor::with_block_size(method, container::DEFAULT_BLOCK_SIZE)
+    }
+
+    /// Create a compressor which compresses blocks of `block_size`
+    /// bytes with `method`.  Panics when `block_size` is zero or
+    /// larger than `container::MAX_BLOCK_SIZE`.
+    pub fn with_block_size(method: Method, block_size: usize) -> Compressor {
+        Compressor {
+            writer: container::Writer::with_block_size(Vec::new(), method, block_size),
+        }
+    }
+
+    /// Take the output written so far.
+    fn take(&mut self) -> Vec<u8> {
+        ::std::mem::replace(self.writer.get_mut(), Vec::new())
+    }
+
+    /// Compress `data` and return the output produced so far.  The
+    /// output only grows by whole blocks, so it is often empty.
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        try!(self.writer.write_all(data));
+        Ok(self.take())
+    }
+
+    /// Compress the rest of the data and return the remaining output.
+    pub fn finish(mut self) -> Result<Vec<u8>, Error> {
+        try!(self.writer.flush());
+        Ok(self.take())
+    }
+}
+
+/// Incremental decompressor for containers.
+pub struct Decompressor {
+    /// Received input which is not decoded yet.
+    input: Vec<u8>,
+    /// Position after the last complete block, `None` until the header
+    /// is complete.
+    checkpoint: Option<Checkpoint>,
+    /// Offset of `input` in the container.
+    base: u64,
+    finished: bool,
+}
+
+impl Decompressor {
+    /// Create a decompressor.  The method is taken from the container
+    /// header.
+    pub fn new() -> Decompressor {
+        Decompressor {
+            input: Vec::new(),
+            checkpoint: None,
+            base: 0,
+            finished: false,
+        }
+    }
+
+    /// Add `data` to the input and return the data of all blocks
+    /// which are complete now.  A block which is not complete yet
+    /// makes the reader fail with `UnexpectedEof`; decoding continues
+    /// from the last checkpoint when more input arrives.
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        self.input.extend_from_slice(data);
+        let mut output = Vec::new();
+        let (last, done) = {
+            let mut r = match self.checkpoint {
+                None => match container::Reader::new(&self.input[..]) {
+                    Ok(r) => r,
+                    Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(output),
+                    Err(e) => return Err(Error::Io(e)),
+                },
+                Some(ref cp) => {
+                    let start = (cp.compressed_offset() - self.base) as usize;
+                    try!(container::Reader::resume(&self.input[start..], cp))
+                },
+            };
+            let mut buf = [0u8; 16 * 1024];
+            loop {
+                let cp = r.checkpoint();
+                match r.read(&mut buf) {
+                    Ok(0) => break (cp, true),
+                    Ok(n) => output.extend_from_slice(&buf[..n]),
+                    Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => break (cp, false),
+                    Err(e) => return Err(Error::Io(e)),
+                }
+            }
+        };
+        let consumed = (last.compressed_offset() - self.base) as usize;
+        self.input.drain(..consumed);
+        self.base = last.compressed_offset();
+        self.checkpoint = Some(last);
+        self.finished = done;
+        Ok(output)
+    }
+
+    /// Return true if the end of the container has been decoded and
+    /// its digest checked.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Check that the input ended with the end of the container.
+    pub fn finish(self) -> Result<(), Error> {
+        if self.finished {
+            Ok(())
+        } else {
+            Err(Error::UnexpectedEof)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "lz"))]
+mod tests {
+    use method::Method;
+    use super::{compress, decompress, Compressor, Decompressor};
+
+    #[test]
+    fn whole_buffers() {
+        let input = include_bytes!("buffer.rs");
+        let compressed = compress(input, Method::Lz4).unwrap();
+        assert_eq!(&input[..], &decompress(&compressed, Method::Lz4).unwrap()[..]);
+        assert!(decompress(&compressed[..20], Method::Lz4).is_err());
+    }
+
+    #[test]
+    fn pieces() {
+        let input = include_bytes!("buffer.rs");
+        let mut c = Compressor::with_block_size(Method::Lzss, 1000);
+        let mut compressed = Vec::new();
+        for piece in input.chunks(333) {
+            compressed.extend(c.push(piece).unwrap());
+        }
+        compressed.extend(c.finish().unwrap());
+        assert!(compressed.len() < input.len());
+
+        let mut d = Decompressor::new();
+        let mut decompressed = Vec::new();
+        for piece in compressed.chunks(7) {
+            decompressed.extend(d.push(piece).unwrap());
+        }
+        assert!(d.is_finished());
+        d.finish().unwrap();
+        assert_eq!(&input[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn truncated() {
+        let input = include_bytes!("buffer.rs");
+        let mut c = Compressor::new(Method::Lzss);
+        let mut compressed = c.push(input).unwrap();
+        compressed.extend(c.finish().unwrap());
+        let mut d = Decompressor::new();
+        // The data is complete, but the digest is not.
+        let decompressed = d.push(&compressed[..compressed.len() - 1]).unwrap();
+        assert_eq!(&input[..], &decompressed[..]);
+        assert!(!d.is_finished());
+        assert!(d.finish().is_err());
+
+        let mut d = Decompressor::new();
+        assert!(d.push(b"CAMQ\x01\x07\x80").is_err());
+    }
+}
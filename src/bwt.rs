@@ -0,0 +1,368 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Burrows-Wheeler transform.
+//!
+//! The transform is computed from a suffix array, which is
+//! constructed in linear time with the SA-IS algorithm from: Ge Nong,
+//! Sen Zhang and Wai Hong Chan: Two Efficient Algorithms for Linear
+//! Time Suffix Array Construction, IEEE Transactions on Computers,
+//! Vol 60, No 10, October 2011, pages 1471-1484.
+//!
+//! The input is split into blocks which are transformed separately.
+//! Each transformed block is preceded by a header consisting of the
+//! block length and the primary index, both as 32-bit little-endian
+//! numbers.  The primary index is the position of the (implicit)
+//! end-of-block sentinel in the transformed data, which is needed to
+//! reverse the transform.
+
+use std::io::{Read, Write};
+use std::io;
+
+use error::Error;
+
+/// Default block size for `encode`.
+pub const DEFAULT_BLOCK_SIZE: usize = 1 << 20;
+
+const EMPTY: usize = usize::max_value();
+
+fn bucket_starts(counts: &[usize]) -> Vec<usize> {
+    let mut sum = 0;
+    counts.iter().map(|&c| { let s = sum; sum += c; s }).collect()
+}
+
+fn bucket_ends(counts: &[usize]) -> Vec<usize> {
+    let mut sum = 0;
+    counts.iter().map(|&c| { sum += c; sum }).collect()
+}
+
+#[inline(always)]
+fn is_lms(stype: &[bool], i: usize) -> bool {
+    i > 0 && stype[i] && !stype[i - 1]
+}
+
+/// Sort the L-type suffixes and then the S-type suffixes from the
+/// LMS suffixes already placed in `sa`.
+fn induce(t: &[usize], stype: &[bool], counts: &[usize], sa: &mut [usize]) {
+    let n = t.len();
+    let mut starts = bucket_starts(counts);
+    for i in 0..n {
+        let j = sa[i];
+        if j != EMPTY && j > 0 && !stype[j - 1] {
+            let c = t[j - 1];
+            sa[starts[c]] = j - 1;
+            starts[c] += 1;
+        }
+    }
+    let mut ends = bucket_ends(counts);
+    for i in (0..n).rev() {
+        let j = sa[i];
+        if j != EMPTY && j > 0 && stype[j - 1] {
+            let c = t[j - 1];
+            ends[c] -= 1;
+            sa[ends[c]] = j - 1;
+        }
+    }
+}
+
+/// Compare the LMS substrings starting at `a` and `b`.
+fn lms_substrings_equal(t: &[usize], stype: &[bool], a: usize, b: usize) -> bool {
+    let n = t.len();
+    if a == n - 1 || b == n - 1 {
+        return a == b;
+    }
+    let mut i = 0;
+    loop {
+        if i > 0 {
+            let a_end = is_lms(stype, a + i);
+            let b_end = is_lms(stype, b + i);
+            if a_end && b_end {
+                return true;
+            }
+            if a_end != b_end {
+                return false;
+            }
+        }
+        if t[a + i] != t[b + i] || stype[a + i] != stype[b + i] {
+            return false;
+        }
+        i += 1;
+    }
+}
+
+/// SA-IS for a string over the alphabet `0..k` which ends with a
+/// unique smallest symbol.
+fn sais(t: &[usize], k: usize) -> Vec<usize> {
+    let n = t.len();
+    if n == 1 {
+        return vec![0];
+    }
+
+    let mut stype = vec![false; n];
+    stype[n - 1] = true;
+    for i in (0..n - 1).rev() {
+        stype[i] = t[i] < t[i + 1] || (t[i] == t[i + 1] && stype[i + 1]);
+    }
+    let mut counts = vec![0; k];
+    for &c in t {
+        counts[c] += 1;
+    }
+
+    // Sort the LMS substrings by inducing from the unsorted LMS
+    // positions.
+    let mut sa = vec![EMPTY; n];
+    let mut ends = bucket_ends(&counts);
+    for i in 1..n {
+        if is_lms(&stype, i) {
+            let c = t[i];
+            ends[c] -= 1;
+            sa[ends[c]] = i;
+        }
+    }
+    induce(t, &stype, &counts, &mut sa);
+
+    // Name the sorted LMS substrings and build the reduced string.
+    let mut names = vec![EMPTY; n];
+    let mut name = 0;
+    let mut prev = EMPTY;
+    for &p in sa.iter() {
+        if is_lms(&stype, p) {
+            if prev == EMPTY || !lms_substrings_equal(t, &stype, prev, p) {
+                name += 1;
+            }
+            names[p] = name - 1;
+            prev = p;
+        }
+    }
+    let lms_positions: Vec<usize> = (1..n).filter(|&i| is_lms(&stype, i)).collect();
+    let reduced: Vec<usize> = lms_positions.iter().map(|&p| names[p]).collect();
+
+    // Sort the LMS suffixes, recursing when names are not unique.
+    let sa1 = if name < reduced.len() {
+        sais(&reduced, name)
+    } else {
+        let mut sa1 = vec![0; reduced.len()];
+        for (i, &r) in reduced.iter().enumerate() {
+            sa1[r] = i;
+        }
+        sa1
+    };
+
+    for s in sa.iter_mut() {
+        *s = EMPTY;
+    }
+    let mut ends = bucket_ends(&counts);
+    for &i in sa1.iter().rev() {
+        let p = lms_positions[i];
+        let c = t[p];
+        ends[c] -= 1;
+        sa[ends[c]] = p;
+    }
+    induce(t, &stype, &counts, &mut sa);
+    sa
+}
+
+/// Compute the suffix array of `data` in linear time.
+pub fn suffix_array(data: &[u8]) -> Vec<usize> {
+    let mut t: Vec<usize> = data.iter().map(|&b| b as usize + 1).collect();
+    t.push(0);
+    let mut sa = sais(&t, 257);
+    // The first suffix is the sentinel.
+    sa.remove(0);
+    sa
+}
+
+/// Transform a single block.  Returns the transformed data and the
+/// primary index.
+pub fn encode_block(block: &[u8]) -> (Vec<u8>, usize) {
+    let n = block.len();
+    if n == 0 {
+        return (Vec::new(), 0);
+    }
+    let sa = suffix_array(block);
+    let mut out = Vec::with_capacity(n);
+    // The rotation starting with the sentinel is preceded by the last
+    // byte of the block.
+    out.push(block[n - 1]);
+    let mut primary = 0;
+    for (i, &s) in sa.iter().enumerate() {
+        if s == 0 {
+            primary = i + 1;
+        } else {
+            out.push(block[s - 1]);
+        }
+    }
+    (out, primary)
+}
+
+/// Reverse the transform of a single block, given the transformed
+/// data and the primary index.
+pub fn decode_block(bwt: &[u8], primary: usize) -> io::Result<Vec<u8>> {
+    let n = bwt.len();
+    if n == 0 {
+        return Ok(Vec::new());
+    }
+    if primary < 1 || primary > n {
+        return Err(Error::corrupt("invalid BWT primary index").into());
+    }
+
+    // The last column has the sentinel inserted at the primary index.
+    let last = |r: usize| if r < primary { bwt[r] } else { bwt[r - 1] };
+
+    let mut counts = [0usize; 256];
+    for &b in bwt {
+        counts[b as usize] += 1;
+    }
+    let mut next = [0usize; 256];
+    let mut sum = 1;
+    for c in 0..256 {
+        next[c] = sum;
+        sum += counts[c];
+    }
+    let mut lf = vec![0usize; n + 1];
+    for r in 0..n + 1 {
+        if r != primary {
+            let c = last(r) as usize;
+            lf[r] = next[c];
+            next[c] += 1;
+        }
+    }
+
+    let mut out = vec![0u8; n];
+    let mut r = 0;
+    for i in (0..n).rev() {
+        if r == primary {
+            return Err(Error::corrupt("corrupt BWT block").into());
+        }
+        out[i] = last(r);
+        r = lf[r];
+    }
+    Ok(out)
+}
+
+fn u32_bytes(v: usize) -> [u8; 4] {
+    [v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]
+}
+
+fn read_u32<R: Read>(input: &mut R) -> io::Result<Option<usize>> {
+    let mut b = [0u8; 4];
+    let mut n = 0;
+    while n < 4 {
+        let k = try!(input.read(&mut b[n..]));
+        if k == 0 {
+            if n == 0 {
+                return Ok(None);
+            }
+            return Err(Error::UnexpectedEof.into());
+        }
+        n += k;
+    }
+    Ok(Some(b[0] as usize | (b[1] as usize) << 8 | (b[2] as usize) << 16 | (b[3] as usize) << 24))
+}
+
+/// Transform all data from `input` in blocks of at most `block_size`
+/// bytes and write the result to `output`.  On success, the output
+/// is returned.
+pub fn encode<R: Read, W: Write>(input: R, mut output: W, block_size: usize) -> Result<W, Error> {
+    assert!(block_size > 0 && block_size <= u32::max_value() as usize);
+    let mut input = input.take(0);
+    let mut block = Vec::with_capacity(block_size);
+    loop {
+        block.clear();
+        input.set_limit(block_size as u64);
+        try!(input.read_to_end(&mut block));
+        if block.is_empty() {
+            break;
+        }
+        let (bwt, primary) = encode_block(&block);
+        try!(output.write_all(&u32_bytes(bwt.len())));
+        try!(output.write_all(&u32_bytes(primary)));
+        try!(output.write_all(&bwt));
+    }
+    Ok(output)
+}
+
+/// Reverse the transform on all blocks in `input` and write the
+/// result to `output`.  On success, the output is returned.
+pub fn decode<R: Read, W: Write>(mut input: R, mut output: W) -> Result<W, Error> {
+    let mut bwt = Vec::new();
+    while let Some(len) = try!(read_u32(&mut input)) {
+        let primary = match try!(read_u32(&mut input)) {
+            Some(p) => p,
+            None => return Err(Error::UnexpectedEof),
+        };
+        bwt.clear();
+        try!((&mut input).take(len as u64).read_to_end(&mut bwt));
+        if bwt.len() != len {
+            return Err(Error::UnexpectedEof);
+        }
+        let block = try!(decode_block(&bwt, primary));
+        try!(output.write_all(&block));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::io::Cursor;
+    use super::{suffix_array, encode_block, decode_block, encode, decode};
+
+    fn naive_suffix_array(data: &[u8]) -> Vec<usize> {
+        let mut sa: Vec<usize> = (0..data.len()).collect();
+        sa.sort_by(|&a, &b| data[a..].cmp(&data[b..]));
+        sa
+    }
+
+    #[test]
+    fn suffix_arrays() {
+        assert_eq!(vec![5, 3, 1, 0, 4, 2], suffix_array(b"banana"));
+        let mut x = 1u32;
+        for &k in [2u32, 3, 4, 256].iter() {
+            let data: Vec<u8> = (0..3000).map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345);
+                ((x >> 16) % k) as u8
+            }).collect();
+            assert_eq!(naive_suffix_array(&data), suffix_array(&data));
+        }
+        let data = [7u8; 1000];
+        assert_eq!(naive_suffix_array(&data), suffix_array(&data));
+    }
+
+    #[test]
+    fn encode_banana() {
+        assert_eq!((b"annbaa".to_vec(), 4), encode_block(b"banana"));
+    }
+
+    #[test]
+    fn decode_banana() {
+        assert_eq!(b"banana".to_vec(), decode_block(b"annbaa", 4).unwrap());
+    }
+
+    #[test]
+    fn decode_bad_primary() {
+        assert!(decode_block(b"annbaa", 0).is_err());
+        assert!(decode_block(b"annbaa", 7).is_err());
+    }
+
+    #[test]
+    fn encode_empty() {
+        let transformed = encode(Cursor::new(&b""[..]), vec![], 16).unwrap();
+        assert_eq!(0, transformed.len());
+    }
+
+    #[test]
+    fn encode_decode() {
+        let input = include_bytes!("bwt.rs");
+        for &block_size in [1, 7, 1000, 1 << 20].iter() {
+            let transformed = encode(Cursor::new(&input[..]), vec![], block_size).unwrap();
+            let decoded = decode(Cursor::new(&transformed[..]), vec![]).unwrap();
+            assert_eq!(&input[..], &decoded[..]);
+        }
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::encode(d, vec![], 10000),
+                               |d: &[u8]| super::decode(d, vec![])));
+    }
+}
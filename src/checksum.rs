@@ -0,0 +1,179 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Checksums for the stream formats.
+//!
+//! CRC-32 (the IEEE polynomial in reflected form, as used by gzip,
+//! zip and PNG) and Adler-32 (as used by zlib).  Both are running
+//! checksums, which are updated with the data as it passes through a
+//! writer or reader, and have a function for computing the checksum
+//! of a slice in one go.
+
+const CRC_POLY: u32 = 0xedb8_8320;
+
+/// Build the tables for slicing-by-8: `t[0]` is the usual byte-wise
+/// table, and `t[k][b]` is the CRC of byte `b` followed by `k` zero
+/// bytes.
+const fn crc_tables() -> [[u32; 256]; 8] {
+    let mut t = [[0; 256]; 8];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            c = if c & 1 != 0 { CRC_POLY ^ (c >> 1) } else { c >> 1 };
+            j += 1;
+        }
+        t[0][i] = c;
+        i += 1;
+    }
+    let mut k = 1;
+    while k < 8 {
+        let mut i = 0;
+        while i < 256 {
+            let c = t[k - 1][i];
+            t[k][i] = (c >> 8) ^ t[0][(c & 0xff) as usize];
+            i += 1;
+        }
+        k += 1;
+    }
+    t
+}
+
+static CRC_TABLES: [[u32; 256]; 8] = crc_tables();
+
+/// Running CRC-32 checksum.
+#[derive(Clone)]
+pub struct Crc32 {
+    crc: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        Crc32 { crc: !0 }
+    }
+
+    /// Add `data` to the checksum.  Eight bytes are processed at a
+    /// time, using one table lookup per byte.
+    pub fn update(&mut self, data: &[u8]) {
+        let t = &CRC_TABLES;
+        let mut crc = self.crc;
+        let mut chunks = data.chunks_exact(8);
+        for c in &mut chunks {
+            let lo = crc ^ u32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+            let hi = u32::from_le_bytes([c[4], c[5], c[6], c[7]]);
+            crc = t[7][(lo & 0xff) as usize] ^ t[6][(lo >> 8 & 0xff) as usize] ^
+                t[5][(lo >> 16 & 0xff) as usize] ^ t[4][(lo >> 24) as usize] ^
+                t[3][(hi & 0xff) as usize] ^ t[2][(hi >> 8 & 0xff) as usize] ^
+                t[1][(hi >> 16 & 0xff) as usize] ^ t[0][(hi >> 24) as usize];
+        }
+        for &b in chunks.remainder() {
+            crc = (crc >> 8) ^ t[0][((crc ^ b as u32) & 0xff) as usize];
+        }
+        self.crc = crc;
+    }
+
+    /// Return the checksum of the data added so far.
+    pub fn value(&self) -> u32 {
+        !self.crc
+    }
+}
+
+/// Return the CRC-32 checksum of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut c = Crc32::new();
+    c.update(data);
+    c.value()
+}
+
+const ADLER_MOD: u32 = 65521;
+
+/// Running Adler-32 checksum.
+#[derive(Clone)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+    pub fn new() -> Adler32 {
+        Adler32 { a: 1, b: 0 }
+    }
+
+    /// Add `data` to the checksum.
+    pub fn update(&mut self, data: &[u8]) {
+        // 5552 is the largest number of bytes that can be summed up
+        // before `b` may overflow 32 bits.
+        for chunk in data.chunks(5552) {
+            for &d in chunk {
+                self.a += d as u32;
+                self.b += self.a;
+            }
+            self.a %= ADLER_MOD;
+            self.b %= ADLER_MOD;
+        }
+    }
+
+    /// Return the checksum of the data added so far.
+    pub fn value(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+/// Return the Adler-32 checksum of `data`.
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut a = Adler32::new();
+    a.update(data);
+    a.value()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{adler32, crc32, Adler32, Crc32, CRC_POLY};
+
+    /// Bit-at-a-time CRC-32, for checking the table-driven one.
+    fn slow_crc32(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &b in data {
+            crc ^= b as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 { CRC_POLY ^ (crc >> 1) } else { crc >> 1 };
+            }
+        }
+        !crc
+    }
+
+    #[test]
+    fn crc32_values() {
+        assert_eq!(0, crc32(b""));
+        assert_eq!(0xcbf43926, crc32(b"123456789"));
+        assert_eq!(0x414fa339, crc32(b"The quick brown fox jumps over the lazy dog"));
+        let data = include_bytes!("checksum.rs");
+        for len in 0..100 {
+            assert_eq!(slow_crc32(&data[..len]), crc32(&data[..len]));
+        }
+        assert_eq!(slow_crc32(data), crc32(data));
+    }
+
+    #[test]
+    fn crc32_pieces() {
+        let data = include_bytes!("checksum.rs");
+        let mut c = Crc32::new();
+        for piece in data.chunks(13) {
+            c.update(piece);
+        }
+        assert_eq!(crc32(data), c.value());
+    }
+
+    #[test]
+    fn adler32_values() {
+        assert_eq!(1, adler32(b""));
+        assert_eq!(0x11e60398, adler32(b"Wikipedia"));
+        let data = vec![0xff; 100000];
+        let mut a = Adler32::new();
+        for piece in data.chunks(777) {
+            a.update(piece);
+        }
+        assert_eq!(adler32(&data), a.value());
+    }
+}
@@ -0,0 +1,228 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Comparison of compression methods on the same input.
+//!
+//! `compare` compresses the input with each of the given methods,
+//! decompresses the result again and records sizes, digests and
+//! times in a `CompareResult`.  The input is read once per method,
+//! from the start, and the compressed data is kept in memory.  The
+//! `camp` tool prints these results as a table for `--compare`.
+//!
+//! `round_trip` does the same for one method with any pair of
+//! compression and decompression functions, for formats like the
+//! block container or pipelines; `camp --test` is built on it.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::time::{Duration, Instant};
+
+use error::Error;
+use method::Method;
+use xxhash::Xxh64;
+
+/// Outcome of compressing and decompressing the input with one
+/// method.
+#[derive(Debug)]
+pub struct CompareResult {
+    pub method: Method,
+    pub orig_size: u64,
+    pub compressed_size: u64,
+    pub decompressed_size: u64,
+    /// Digest of the input, as recorded in containers.
+    pub orig_hash: u64,
+    /// Digest of the decompressed data.
+    pub decompressed_hash: u64,
+    pub compress_time: Duration,
+    pub decompress_time: Duration,
+    /// The error which stopped compression or decompression, if any.
+    /// The sizes, digests and times after it are zero.
+    pub error: Option<Error>,
+}
+
+impl CompareResult {
+    fn new(method: Method) -> CompareResult {
+        CompareResult {
+            method: method,
+            orig_size: 0,
+            compressed_size: 0,
+            decompressed_size: 0,
+            orig_hash: 0,
+            decompressed_hash: 0,
+            compress_time: Duration::from_secs(0),
+            decompress_time: Duration::from_secs(0),
+            error: None,
+        }
+    }
+
+    /// Return true if the decompressed data has the size and digest
+    /// of the input.
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none() && self.orig_size == self.decompressed_size &&
+            self.orig_hash == self.decompressed_hash
+    }
+
+    /// Return the ratio of compressed to original size.
+    pub fn ratio(&self) -> f64 {
+        self.compressed_size as f64 / self.orig_size as f64
+    }
+
+    /// Return the compression speed in MB/s of input.
+    pub fn compress_speed(&self) -> f64 {
+        speed(self.orig_size, self.compress_time)
+    }
+
+    /// Return the decompression speed in MB/s of output.
+    pub fn decompress_speed(&self) -> f64 {
+        speed(self.orig_size, self.decompress_time)
+    }
+}
+
+fn speed(size: u64, time: Duration) -> f64 {
+    let secs = time.as_secs() as f64 + time.subsec_nanos() as f64 / 1e9;
+    size as f64 / secs / (1024.0 * 1024.0)
+}
+
+/// Reader or writer which passes the data through and computes its
+/// size and digest.
+pub struct Digester<T> {
+    inner: T,
+    digest: Xxh64,
+    count: u64,
+}
+
+impl<T> Digester<T> {
+    pub fn new(inner: T) -> Digester<T> {
+        Digester {
+            inner: inner,
+            digest: Xxh64::new(),
+            count: 0,
+        }
+    }
+
+    /// Return the 64-bit xxHash of the data passed through so far.
+    pub fn digest(&self) -> u64 {
+        self.digest.value()
+    }
+
+    /// Return the number of bytes passed through so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl<R: Read> Read for Digester<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.inner.read(buf));
+        self.digest.update(&buf[..n]);
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Digester<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = try!(self.inner.write(buf));
+        self.digest.update(&buf[..n]);
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Compress and decompress `input`, filling in `res`.
+fn round_trip_into<R, C, D>(input: &mut R, compress: C, decompress: D,
+                            res: &mut CompareResult) -> Result<(), Error>
+    where R: Read + Seek,
+          C: FnOnce(&mut dyn Read, Vec<u8>) -> Result<Vec<u8>, Error>,
+          D: FnOnce(&[u8], &mut dyn Write) -> Result<(), Error> {
+    try!(input.seek(SeekFrom::Start(0)));
+    let start = Instant::now();
+    let mut inp = Digester::new(input);
+    let compressed = try!(compress(&mut inp, Vec::new()));
+    res.compress_time = start.elapsed();
+    res.orig_size = inp.count;
+    res.orig_hash = inp.digest.value();
+    res.compressed_size = compressed.len() as u64;
+
+    let start = Instant::now();
+    let mut out = Digester::new(io::sink());
+    try!(decompress(&compressed[..], &mut out));
+    res.decompress_time = start.elapsed();
+    res.decompressed_size = out.count;
+    res.decompressed_hash = out.digest.value();
+    Ok(())
+}
+
+/// Compress all data of `input`, starting from its beginning, with
+/// `compress`, which is given the input and an empty output vector,
+/// and decompress the result with `decompress`.  `method` is only
+/// recorded in the result.  Errors are recorded in the result, too.
+pub fn round_trip<R, C, D>(mut input: R, method: Method, compress: C,
+                           decompress: D) -> CompareResult
+    where R: Read + Seek,
+          C: FnOnce(&mut dyn Read, Vec<u8>) -> Result<Vec<u8>, Error>,
+          D: FnOnce(&[u8], &mut dyn Write) -> Result<(), Error> {
+    let mut res = CompareResult::new(method);
+    if let Err(e) = round_trip_into(&mut input, compress, decompress, &mut res) {
+        res.error = Some(e);
+    }
+    res
+}
+
+/// Compress and decompress all data of `input` with each of
+/// `methods`, starting from the beginning of `input` each time, and
+/// return the results in the order of `methods`.  Errors are recorded
+/// in the results, so that one failing method does not hide the
+/// results of the others.
+pub fn compare<R: Read + Seek>(mut input: R, methods: &[Method]) -> Vec<CompareResult> {
+    methods.iter().map(|&method| {
+        round_trip(&mut input, method,
+                   |input, output| method.compress(input, output),
+                   |input, output| method.decompress(input, output).map(|_| ()))
+    }).collect()
+}
+
+#[cfg(all(test, feature = "lz"))]
+mod tests {
+    use std::io::Cursor;
+    use container;
+    use method::Method;
+    use super::{compare, round_trip};
+
+    #[test]
+    fn compare_methods() {
+        let input = include_bytes!("compare.rs");
+        let methods = [Method::Lzss, Method::Store];
+        let results = compare(Cursor::new(&input[..]), &methods);
+        assert_eq!(2, results.len());
+        let digest = container::digest(&input[..]).unwrap();
+        for (res, &m) in results.iter().zip(&methods) {
+            assert_eq!(m, res.method);
+            assert!(res.is_ok());
+            assert_eq!(input.len() as u64, res.orig_size);
+            assert_eq!(digest, res.decompressed_hash);
+        }
+        assert!(results[0].ratio() < 0.7);
+        assert_eq!(1.0, results[1].ratio());
+    }
+
+    #[test]
+    fn round_trip_container() {
+        let input = include_bytes!("compare.rs");
+        let res = round_trip(Cursor::new(&input[..]), Method::Lzss,
+                             |input, output| container::compress(input, output, Method::Lzss),
+                             |input, output| container::decompress(input, output).map(|_| ()));
+        assert!(res.is_ok());
+        assert!(res.compressed_size < res.orig_size);
+
+        // A decompressor which loses the last byte is caught.
+        let res = round_trip(Cursor::new(&input[..]), Method::Store,
+                             |input, output| Method::Store.compress(input, output),
+                             |input, output| Ok(output.write_all(&input[1..]).unwrap()));
+        assert!(res.error.is_none());
+        assert!(!res.is_ok());
+    }
+}
@@ -0,0 +1,1126 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Block container format.
+//!
+//! The container splits its input into blocks of a fixed size and
+//! compresses each block on its own with the method named in the
+//! header.  A block which does not get smaller is stored verbatim
+//! instead, so that incompressible data, which every coder expands,
+//! grows by a few bytes per block at most.
+//!
+//! The format is:
+//!
+//! - the header: the magic bytes `CAMP`, a version byte, the id of the
+//!   method (see `Method::id`) and the block size as a LEB128 number,
+//! - any number of blocks, each starting with a type byte:
+//!   `BLOCK_COMPRESSED` is followed by the uncompressed and the
+//!   compressed length as LEB128 numbers and the compressed data,
+//!   `BLOCK_STORED` is followed by the length and the data itself,
+//!   `BLOCK_METHOD` is like `BLOCK_COMPRESSED`, but with the id of the
+//!   method used for this block before the lengths,
+//! - a `BLOCK_END` byte, followed by the XXH64 digest of the
+//!   uncompressed content as a little-endian 64-bit number.
+//!
+//! A hybrid writer, created with `Writer::hybrid`, picks the method
+//! for each block with `auto::choose_method`, so that text-like blocks
+//! are compressed with LZSS2 and blocks with high entropy are Huffman
+//! coded or stored.  The reader needs no setting for this, as every
+//! block names its method.
+//!
+//! The digest is checked by `Reader` when it reaches the end of the
+//! container, and can be checked without keeping the data with
+//! `verify` and `verify_file`.
+//!
+//! Since blocks are compressed independently, the state of a writer
+//! or reader at a block boundary is small: positions, sizes and the
+//! digest state.  `Writer::checkpoint` and `Reader::checkpoint` return
+//! it as a `Checkpoint`, which can be saved with `to_bytes` and used
+//! after a crash to continue the stream with `Writer::resume` or
+//! `Reader::resume`, without processing the data before it again.
+//!
+//! For the same reason, `Parallel` can compress several blocks at
+//! once on separate threads.  Its output is the same as that of a
+//! `Writer` with the same settings.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use auto;
+use counter::Counter;
+use error::Error;
+use method::Method;
+use model::{read_varint, write_varint};
+use xxhash::{xxh32, Xxh64, XXH64_STATE_LEN};
+
+const MAGIC: [u8; 4] = *b"CAMP";
+const VERSION: u8 = 1;
+
+const BLOCK_END: u8 = 0;
+const BLOCK_COMPRESSED: u8 = 1;
+const BLOCK_STORED: u8 = 2;
+const BLOCK_METHOD: u8 = 3;
+
+const CHECKPOINT_MAGIC: [u8; 4] = *b"CKPT";
+const CHECKPOINT_VERSION: u8 = 1;
+
+const CHECKPOINT_WRITER: u8 = 1;
+const CHECKPOINT_READER: u8 = 2;
+
+/// Block size used when none is given.
+pub const DEFAULT_BLOCK_SIZE: usize = 1 << 20;
+
+/// Largest block size.  Readers refuse larger blocks, so that a
+/// corrupted header cannot make them allocate huge buffers.
+pub const MAX_BLOCK_SIZE: usize = 1 << 26;
+
+fn invalid(msg: &'static str) -> io::Error {
+    Error::corrupt(msg).into()
+}
+
+/// Saved state of a container writer or reader.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    kind: u8,
+    method: Method,
+    per_block: bool,
+    block_size: usize,
+    compressed: u64,
+    uncompressed: u64,
+    skip: u64,
+    digest: Vec<u8>,
+}
+
+impl Checkpoint {
+    /// Return the position in the compressed stream at which the
+    /// stream continues.  The writer or reader passed to `resume` must
+    /// be positioned there.
+    pub fn compressed_offset(&self) -> u64 {
+        self.compressed
+    }
+
+    /// Return the number of uncompressed bytes written or read before
+    /// the checkpoint.
+    pub fn uncompressed_offset(&self) -> u64 {
+        self.uncompressed + self.skip
+    }
+
+    /// Serialize the checkpoint.  The result starts with a magic
+    /// number and a version byte and ends with a checksum, so that
+    /// `from_bytes` can reject anything else.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&CHECKPOINT_MAGIC);
+        out.extend_from_slice(&[CHECKPOINT_VERSION, self.kind, self.method.id(),
+                                self.per_block as u8]);
+        for &v in &[self.block_size as u64, self.compressed, self.uncompressed, self.skip] {
+            write_varint(&mut out, v).unwrap();
+        }
+        out.extend_from_slice(&self.digest);
+        let check = xxh32(&out);
+        out.extend_from_slice(&check.to_le_bytes());
+        out
+    }
+
+    /// Read a checkpoint serialized with `to_bytes`, checking its
+    /// version, checksum and contents.
+    pub fn from_bytes(data: &[u8]) -> io::Result<Checkpoint> {
+        if data.len() < 4 + 4 + 4 + XXH64_STATE_LEN + 4 || data[..4] != CHECKPOINT_MAGIC {
+            return Err(Error::InvalidHeader("not a container checkpoint").into());
+        }
+        if data[4] != CHECKPOINT_VERSION {
+            return Err(Error::UnsupportedVersion(data[4] as u32).into());
+        }
+        let (body, check) = data.split_at(data.len() - 4);
+        let actual = xxh32(body);
+        let expected = u32::from_le_bytes([check[0], check[1], check[2], check[3]]);
+        if actual != expected {
+            return Err(Error::ChecksumMismatch {
+                expected: expected as u64,
+                actual: actual as u64,
+            }.into());
+        }
+        let kind = body[5];
+        if kind != CHECKPOINT_WRITER && kind != CHECKPOINT_READER {
+            return Err(invalid("invalid checkpoint type"));
+        }
+        let method = match Method::from_id(body[6]) {
+            Some(m) => m,
+            None => return Err(invalid("unknown compression method")),
+        };
+        let per_block = match body[7] {
+            0 => false,
+            1 => true,
+            _ => return Err(invalid("invalid checkpoint flags")),
+        };
+        let mut rest = &body[8..];
+        let block_size = try!(read_varint(&mut rest));
+        if block_size == 0 || block_size > MAX_BLOCK_SIZE as u64 {
+            return Err(invalid("invalid container block size"));
+        }
+        let compressed = try!(read_varint(&mut rest));
+        let uncompressed = try!(read_varint(&mut rest));
+        let skip = try!(read_varint(&mut rest));
+        if skip > block_size || (kind == CHECKPOINT_WRITER && skip != 0) {
+            return Err(invalid("invalid checkpoint position"));
+        }
+        let digest = match Xxh64::restore(rest) {
+            Some(_) => rest.to_vec(),
+            None => return Err(invalid("invalid checkpoint digest state")),
+        };
+        Ok(Checkpoint {
+            kind: kind,
+            method: method,
+            per_block: per_block,
+            block_size: block_size as usize,
+            compressed: compressed,
+            uncompressed: uncompressed,
+            skip: skip,
+            digest: digest,
+        })
+    }
+
+    fn check_kind(&self, kind: u8) -> io::Result<()> {
+        if self.kind != kind {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "checkpoint taken from the other direction"));
+        }
+        Ok(())
+    }
+
+    fn digest(&self) -> Xxh64 {
+        Xxh64::restore(&self.digest).unwrap()
+    }
+}
+
+fn save_digest(digest: &Xxh64) -> Vec<u8> {
+    let mut state = Vec::with_capacity(XXH64_STATE_LEN);
+    digest.save(&mut state);
+    state
+}
+
+/// Write the container header for `method` and `block_size`.
+fn write_header<W: Write>(output: &mut W, method: Method, block_size: usize) -> io::Result<()> {
+    try!(output.write_all(&MAGIC));
+    try!(output.write_all(&[VERSION, method.id()]));
+    write_varint(output, block_size as u64)
+}
+
+/// Write `data` as a block of a container whose header names
+/// `method`.  The block is compressed with `method`, or with the
+/// method chosen by `auto::choose_method` if `per_block` is set, and
+/// stored if it does not get smaller.
+fn write_block<W: Write>(output: &mut W, data: &[u8], method: Method, per_block: bool)
+                         -> io::Result<()> {
+    let block_method = if per_block { auto::choose_method(data) } else { method };
+    let compressed = if block_method == Method::Store {
+        Vec::new()
+    } else {
+        try!(block_method.compress(Cursor::new(data), Vec::new()).map_err(io::Error::from))
+    };
+    if block_method != Method::Store && compressed.len() < data.len() {
+        if block_method == method {
+            try!(output.write_all(&[BLOCK_COMPRESSED]));
+        } else {
+            try!(output.write_all(&[BLOCK_METHOD, block_method.id()]));
+        }
+        try!(write_varint(output, data.len() as u64));
+        try!(write_varint(output, compressed.len() as u64));
+        output.write_all(&compressed)
+    } else {
+        try!(output.write_all(&[BLOCK_STORED]));
+        try!(write_varint(output, data.len() as u64));
+        output.write_all(data)
+    }
+}
+
+/// Write the end marker and the digest of the content.
+fn write_end<W: Write>(output: &mut W, digest: &Xxh64) -> io::Result<()> {
+    try!(output.write_all(&[BLOCK_END]));
+    output.write_all(&digest.value().to_le_bytes())
+}
+
+/// Writer for the container format.  Calling `flush` writes the last
+/// block and the end marker, so no more data may be written
+/// afterwards.
+pub struct Writer<W> {
+    inner: Counter<W>,
+    method: Method,
+    per_block: bool,
+    block_size: usize,
+    buf: Vec<u8>,
+    header_written: bool,
+    finished: bool,
+    digest: Xxh64,
+    total_in: u64,
+}
+
+impl<W: Write> Writer<W> {
+    /// Create a writer which compresses blocks of the default size
+    /// with `method`.
+    pub fn new(inner: W, method: Method) -> Writer<W> {
+        Writer::with_block_size(inner, method, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// Create a writer which compresses blocks of `block_size` bytes
+    /// with `method`.  Panics when `block_size` is zero or larger
+    /// than `MAX_BLOCK_SIZE`.
+    pub fn with_block_size(inner: W, method: Method, block_size: usize) -> Writer<W> {
+        assert!(block_size > 0 && block_size <= MAX_BLOCK_SIZE);
+        Writer {
+            inner: Counter::new(inner),
+            method: method,
+            per_block: false,
+            block_size: block_size,
+            buf: Vec::with_capacity(block_size),
+            header_written: false,
+            finished: false,
+            digest: Xxh64::new(),
+            total_in: 0,
+        }
+    }
+
+    /// Create a hybrid writer with blocks of the default size.
+    pub fn hybrid(inner: W) -> Writer<W> {
+        Writer::hybrid_with_block_size(inner, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// Create a hybrid writer, which chooses the method for each block
+    /// of `block_size` bytes.  LZSS2 is recorded in the header, as the
+    /// method expected for most blocks.  Panics when `block_size` is
+    /// zero or larger than `MAX_BLOCK_SIZE`.
+    pub fn hybrid_with_block_size(inner: W, block_size: usize) -> Writer<W> {
+        let mut w = Writer::with_block_size(inner, Method::Lzss2, block_size);
+        w.per_block = true;
+        w
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            try!(write_header(&mut self.inner, self.method, self.block_size));
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    /// Compress the buffered block, or store it if it does not get
+    /// smaller.
+    fn write_block(&mut self) -> io::Result<()> {
+        try!(write_block(&mut self.inner, &self.buf, self.method, self.per_block));
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// Write the last block, the end marker and the digest.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            try!(self.write_header());
+            if !self.buf.is_empty() {
+                try!(self.write_block());
+            }
+            try!(write_end(&mut self.inner, &self.digest));
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    /// Write the buffered data as a block and flush the wrapped
+    /// writer, then return the state needed to continue the container
+    /// with `resume`.  Taking a checkpoint ends the current block
+    /// early, so it costs a few bytes of compression.
+    pub fn checkpoint(&mut self) -> io::Result<Checkpoint> {
+        if self.finished {
+            return Err(io::Error::new(io::ErrorKind::Other, "checkpoint after end of container"));
+        }
+        try!(self.write_header());
+        if !self.buf.is_empty() {
+            try!(self.write_block());
+        }
+        try!(self.inner.flush());
+        Ok(Checkpoint {
+            kind: CHECKPOINT_WRITER,
+            method: self.method,
+            per_block: self.per_block,
+            block_size: self.block_size,
+            compressed: self.inner.count(),
+            uncompressed: self.total_in,
+            skip: 0,
+            digest: save_digest(&self.digest),
+        })
+    }
+
+    /// Create a writer which continues the container at
+    /// `checkpoint`.  `inner` must hold the first
+    /// `checkpoint.compressed_offset()` bytes written before the
+    /// checkpoint, and nothing after them.
+    pub fn resume(inner: W, checkpoint: &Checkpoint) -> io::Result<Writer<W>> {
+        try!(checkpoint.check_kind(CHECKPOINT_WRITER));
+        Ok(Writer {
+            inner: Counter::with_count(inner, checkpoint.compressed),
+            method: checkpoint.method,
+            per_block: checkpoint.per_block,
+            block_size: checkpoint.block_size,
+            buf: Vec::with_capacity(checkpoint.block_size),
+            header_written: true,
+            finished: false,
+            digest: checkpoint.digest(),
+            total_in: checkpoint.uncompressed,
+        })
+    }
+
+    /// Return the number of uncompressed bytes written so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Return the number of bytes written to the wrapped writer so
+    /// far.  The current block is only counted once it is complete.
+    pub fn total_out(&self) -> u64 {
+        self.inner.count()
+    }
+
+    /// Return a mutable reference to the wrapped writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.get_mut()
+    }
+
+    /// Move the wrapped writer out of the container writer.
+    pub fn into_inner(self) -> W {
+        self.inner.into_inner()
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::new(io::ErrorKind::Other, "write after end of container"));
+        }
+        try!(self.write_header());
+        let n = cmp::min(self.block_size - self.buf.len(), buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        self.digest.update(&buf[..n]);
+        if self.buf.len() == self.block_size {
+            try!(self.write_block());
+        }
+        self.total_in += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.finish());
+        self.inner.flush()
+    }
+}
+
+/// Writer for a decompressed block, which fails as soon as more than
+/// the declared length of the block is written, so that a corrupted
+/// block cannot make the reader allocate more than the block size.
+struct BlockBuffer {
+    block: Vec<u8>,
+    len: usize,
+}
+
+impl Write for BlockBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > self.len - self.block.len() {
+            return Err(invalid("container block longer than its length"));
+        }
+        self.block.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reader for the container format.
+pub struct Reader<R> {
+    inner: Counter<R>,
+    method: Method,
+    block_size: usize,
+    block: Vec<u8>,
+    returned: usize,
+    eof: bool,
+    digest: Xxh64,
+    total_out: u64,
+    block_start: u64,
+    block_digest: Xxh64,
+    block_total_out: u64,
+}
+
+impl<R: Read> Reader<R> {
+    /// Create a new reader.  This reads and checks the header,
+    /// therefore the result can be an error.
+    pub fn new(inner: R) -> io::Result<Reader<R>> {
+        let mut inner = Counter::new(inner);
+        let mut hdr = [0u8; 6];
+        try!(inner.read_exact(&mut hdr));
+        if hdr[..4] != MAGIC {
+            return Err(Error::InvalidHeader("not a container stream").into());
+        }
+        if hdr[4] != VERSION {
+            return Err(Error::UnsupportedVersion(hdr[4] as u32).into());
+        }
+        let method = match Method::from_id(hdr[5]) {
+            Some(m) => m,
+            None => return Err(invalid("unknown compression method")),
+        };
+        let block_size = try!(read_varint(&mut inner));
+        if block_size == 0 || block_size > MAX_BLOCK_SIZE as u64 {
+            return Err(invalid("invalid container block size"));
+        }
+        Ok(Reader {
+            inner: inner,
+            method: method,
+            block_size: block_size as usize,
+            block: Vec::new(),
+            returned: 0,
+            eof: false,
+            digest: Xxh64::new(),
+            total_out: 0,
+            block_start: 0,
+            block_digest: Xxh64::new(),
+            block_total_out: 0,
+        })
+    }
+
+    /// Return the method named in the header.  Blocks written by a
+    /// hybrid writer may use other methods.
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    /// Read a block length and check it against the block size.
+    fn read_len(&mut self) -> io::Result<usize> {
+        let len = try!(read_varint(&mut self.inner));
+        if len > self.block_size as u64 {
+            return Err(Error::LimitExceeded("container block too large").into());
+        }
+        Ok(len as usize)
+    }
+
+    /// Read the next block into `block`.
+    fn read_block(&mut self) -> io::Result<()> {
+        self.block_start = self.inner.count();
+        self.block_digest = self.digest.clone();
+        self.block_total_out = self.total_out;
+        let mut kind = [0u8; 1];
+        try!(self.inner.read_exact(&mut kind));
+        self.block.clear();
+        self.returned = 0;
+        match kind[0] {
+            BLOCK_END => {
+                let mut digest = [0u8; 8];
+                try!(self.inner.read_exact(&mut digest));
+                let expected = u64::from_le_bytes(digest);
+                if expected != self.digest.value() {
+                    return Err(Error::ChecksumMismatch {
+                        expected: expected,
+                        actual: self.digest.value(),
+                    }.into());
+                }
+                self.eof = true;
+            },
+            BLOCK_COMPRESSED | BLOCK_METHOD => {
+                let method = if kind[0] == BLOCK_METHOD {
+                    let mut id = [0u8; 1];
+                    try!(self.inner.read_exact(&mut id));
+                    match Method::from_id(id[0]) {
+                        Some(m) => m,
+                        None => return Err(Error::corrupt_at(self.block_start,
+                                                             "unknown compression method").into()),
+                    }
+                } else {
+                    self.method
+                };
+                let len = try!(self.read_len());
+                let compressed_len = try!(self.read_len());
+                let mut compressed = vec![0; compressed_len];
+                try!(self.inner.read_exact(&mut compressed));
+                let block = BlockBuffer {
+                    block: ::std::mem::replace(&mut self.block, Vec::new()),
+                    len: len,
+                };
+                let start = self.inner.count() - compressed_len as u64;
+                self.block = try!(method.decompress(Cursor::new(compressed), block)
+                                  .map_err(|e| e.relocate(start))).block;
+                if self.block.len() != len {
+                    return Err(Error::corrupt_at(self.block_start,
+                                                 "container block has wrong length").into());
+                }
+            },
+            BLOCK_STORED => {
+                let len = try!(self.read_len());
+                self.block.resize(len, 0);
+                try!(self.inner.read_exact(&mut self.block));
+            },
+            _ => return Err(Error::corrupt_at(self.block_start,
+                                              "invalid container block type").into()),
+        }
+        self.digest.update(&self.block);
+        Ok(())
+    }
+
+    /// Return the state needed to continue reading with `resume`.
+    /// When part of a block has been returned, the checkpoint refers to
+    /// the start of that block, which is decompressed again on
+    /// resumption.
+    pub fn checkpoint(&self) -> Checkpoint {
+        let (compressed, uncompressed, skip, digest) =
+            if self.returned == self.block.len() && !self.eof {
+                (self.inner.count(), self.total_out, 0, &self.digest)
+            } else {
+                (self.block_start, self.block_total_out, self.returned as u64,
+                 &self.block_digest)
+            };
+        Checkpoint {
+            kind: CHECKPOINT_READER,
+            method: self.method,
+            per_block: false,
+            block_size: self.block_size,
+            compressed: compressed,
+            uncompressed: uncompressed,
+            skip: skip,
+            digest: save_digest(digest),
+        }
+    }
+
+    /// Create a reader which continues reading at `checkpoint`.
+    /// `inner` must be positioned `checkpoint.compressed_offset()`
+    /// bytes into the container.
+    pub fn resume(inner: R, checkpoint: &Checkpoint) -> io::Result<Reader<R>> {
+        try!(checkpoint.check_kind(CHECKPOINT_READER));
+        let mut r = Reader {
+            inner: Counter::with_count(inner, checkpoint.compressed),
+            method: checkpoint.method,
+            block_size: checkpoint.block_size,
+            block: Vec::new(),
+            returned: 0,
+            eof: false,
+            digest: checkpoint.digest(),
+            total_out: checkpoint.uncompressed,
+            block_start: 0,
+            block_digest: Xxh64::new(),
+            block_total_out: 0,
+        };
+        if checkpoint.skip > 0 {
+            try!(r.read_block());
+            if checkpoint.skip > r.block.len() as u64 {
+                return Err(invalid("checkpoint does not match container"));
+            }
+            r.returned = checkpoint.skip as usize;
+            r.total_out += checkpoint.skip;
+        }
+        Ok(r)
+    }
+
+    /// Return the digest of the data read so far.  At the end of the
+    /// container, this is the checked content digest.
+    pub fn digest(&self) -> u64 {
+        self.digest.value()
+    }
+
+    /// Return the number of compressed bytes read so far.
+    pub fn total_in(&self) -> u64 {
+        self.inner.count()
+    }
+
+    /// Return the number of decompressed bytes read so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
+        while self.returned == self.block.len() {
+            if self.eof || output.is_empty() {
+                return Ok(0);
+            }
+            try!(self.read_block());
+        }
+        let n = cmp::min(output.len(), self.block.len() - self.returned);
+        output[..n].copy_from_slice(&self.block[self.returned..self.returned + n]);
+        self.returned += n;
+        self.total_out += n as u64;
+        Ok(n)
+    }
+}
+
+/// Return the number of threads to use for `threads`: `threads`
+/// itself, or the number of CPU cores if it is zero.
+pub fn available_threads(threads: usize) -> usize {
+    if threads > 0 {
+        threads
+    } else {
+        thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+    }
+}
+
+/// Compressor which compresses the blocks of a container on several
+/// threads.  It reads as many blocks as it has threads, compresses
+/// them at the same time and writes them in order, so it keeps two
+/// buffers of the block size per thread, which are reused for all
+/// blocks.
+pub struct Parallel {
+    method: Method,
+    per_block: bool,
+    block_size: usize,
+    threads: usize,
+    busy: Duration,
+}
+
+impl Parallel {
+    /// Create a compressor which compresses blocks of the default
+    /// size with `method` on `threads` threads, or on one thread per
+    /// CPU core if `threads` is zero.
+    pub fn new(method: Method, threads: usize) -> Parallel {
+        Parallel {
+            method: method,
+            per_block: false,
+            block_size: DEFAULT_BLOCK_SIZE,
+            threads: available_threads(threads),
+            busy: Duration::from_secs(0),
+        }
+    }
+
+    /// Create a compressor which chooses the method for each block,
+    /// like `Writer::hybrid`.
+    pub fn hybrid(threads: usize) -> Parallel {
+        let mut p = Parallel::new(Method::Lzss2, threads);
+        p.per_block = true;
+        p
+    }
+
+    /// Use blocks of `block_size` bytes.  Panics when `block_size` is
+    /// zero or larger than `MAX_BLOCK_SIZE`.
+    pub fn block_size(mut self, block_size: usize) -> Parallel {
+        assert!(block_size > 0 && block_size <= MAX_BLOCK_SIZE);
+        self.block_size = block_size;
+        self
+    }
+
+    /// Return the number of threads.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Return the time the threads spent compressing blocks so far,
+    /// added up over all threads.  Divided by the elapsed time, this
+    /// is the speedup over compressing on one thread.  The times are
+    /// wall-clock times, so with more threads than CPU cores, the
+    /// speedup comes out too high.
+    pub fn busy_time(&self) -> Duration {
+        self.busy
+    }
+
+    /// Compress all data from `input` into a container written to
+    /// `output`.  On success, the output is returned.
+    pub fn compress<R: Read, W: Write>(&mut self, mut input: R, mut output: W)
+                                       -> Result<W, Error> {
+        let (method, per_block) = (self.method, self.per_block);
+        let mut slots: Vec<(Vec<u8>, Vec<u8>)> = (0..self.threads)
+            .map(|_| (Vec::with_capacity(self.block_size), Vec::new()))
+            .collect();
+        let mut digest = Xxh64::new();
+        try!(write_header(&mut output, method, self.block_size));
+        let mut eof = false;
+        while !eof {
+            let mut filled = 0;
+            for slot in slots.iter_mut() {
+                slot.0.clear();
+                try!(input.by_ref().take(self.block_size as u64).read_to_end(&mut slot.0));
+                digest.update(&slot.0);
+                if slot.0.len() < self.block_size {
+                    eof = true;
+                }
+                if !slot.0.is_empty() {
+                    filled += 1;
+                }
+                if eof {
+                    break;
+                }
+            }
+
+            let encode = move |slot: &mut (Vec<u8>, Vec<u8>)| {
+                let start = Instant::now();
+                slot.1.clear();
+                write_block(&mut slot.1, &slot.0, method, per_block).map(|_| start.elapsed())
+            };
+            let times: Vec<io::Result<Duration>> = if filled == 1 {
+                vec![encode(&mut slots[0])]
+            } else {
+                thread::scope(|s| {
+                    let handles: Vec<_> = slots[..filled].iter_mut()
+                        .map(|slot| s.spawn(move || encode(slot)))
+                        .collect();
+                    handles.into_iter().map(|h| h.join().unwrap()).collect()
+                })
+            };
+            for t in times {
+                self.busy += try!(t);
+            }
+            for slot in &slots[..filled] {
+                try!(output.write_all(&slot.1));
+            }
+        }
+        try!(write_end(&mut output, &digest));
+        Ok(output)
+    }
+}
+
+/// Compress all data from `input` into a container written to
+/// `output`, compressing blocks of the default size with `method` on
+/// `threads` threads, or one per CPU core if `threads` is zero.  The
+/// result is the same as that of `compress`.  On success, the output
+/// is returned.
+pub fn compress_parallel<R: Read, W: Write>(input: R, output: W, method: Method, threads: usize)
+                                            -> Result<W, Error> {
+    Parallel::new(method, threads).compress(input, output)
+}
+
+/// Compress all data from `input` into a container written to
+/// `output`, compressing blocks of the default size with `method`.
+/// On success, the output is returned.
+pub fn compress<R: Read, W: Write>(input: R, output: W, method: Method) -> Result<W, Error> {
+    compress_with_block_size(input, output, method, DEFAULT_BLOCK_SIZE)
+}
+
+/// Compress all data from `input` into a container written to
+/// `output`, compressing blocks of `block_size` bytes with `method`.
+/// On success, the output is returned.
+pub fn compress_with_block_size<R: Read, W: Write>(mut input: R, output: W, method: Method,
+                                                   block_size: usize) -> Result<W, Error> {
+    let mut cw = Writer::with_block_size(output, method, block_size);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.into_inner())
+}
+
+/// Compress all data from `input` into a container written to
+/// `output`, choosing the method for each block of the default size.
+/// On success, the output is returned.
+pub fn compress_hybrid<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error> {
+    let mut cw = Writer::hybrid(output);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.into_inner())
+}
+
+/// Decompress the container read from `input` and write the data to
+/// `output`.  The method is taken from the header.  On success, the
+/// output is returned.
+pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<W, Error> {
+    let mut cr = try!(Reader::new(input));
+    try!(io::copy(&mut cr, &mut output));
+    Ok(output)
+}
+
+/// Return the digest of all data from `input`, as recorded in the
+/// containers holding that data.
+pub fn digest<R: Read>(mut input: R) -> io::Result<u64> {
+    let mut d = Xxh64::new();
+    let mut buf = [0u8; 16 * 1024];
+    loop {
+        match input.read(&mut buf) {
+            Ok(0) => return Ok(d.value()),
+            Ok(n) => d.update(&buf[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Decompress the container read from `input` without keeping the
+/// data, and check its block lengths and content digest.  On success,
+/// the digest is returned, for comparing with `digest` of the
+/// original data.
+pub fn verify<R: Read>(input: R) -> io::Result<u64> {
+    let mut cr = try!(Reader::new(input));
+    try!(io::copy(&mut cr, &mut io::sink()));
+    Ok(cr.digest())
+}
+
+/// Verify the container in the file at `path`, see `verify`.
+pub fn verify_file<P: AsRef<Path>>(path: P) -> io::Result<u64> {
+    verify(BufReader::new(try!(File::open(path))))
+}
+
+#[cfg(all(test, feature = "lz", feature = "nested"))]
+mod tests {
+    use std::io::{Cursor, Read, Write};
+    use error::Error;
+    use method::Method;
+    use super::{compress, compress_parallel, compress_with_block_size, decompress, digest, verify,
+                write_header, Checkpoint, Parallel, Reader, Writer, BLOCK_COMPRESSED,
+                BLOCK_METHOD, BLOCK_STORED};
+    use model::write_varint;
+
+    /// Deterministic pseudo-random bytes.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut x: u32 = 12345;
+        (0..len).map(|_| {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            (x >> 16) as u8
+        }).collect()
+    }
+
+    #[test]
+    fn compress_decompress() {
+        let input = include_bytes!("container.rs");
+        let compressed = compress(Cursor::new(&input[..]), vec![], Method::Lzss).unwrap();
+        assert!(compressed.len() < input.len());
+        assert_eq!(Method::Lzss, Reader::new(Cursor::new(&compressed[..])).unwrap().method());
+        let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(&input[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn empty() {
+        let compressed = compress(Cursor::new(&[][..]), vec![], Method::Lz77).unwrap();
+        let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert!(decompressed.is_empty());
+    }
+
+    #[test]
+    fn stored_blocks() {
+        // Random data is stored, at a cost of a few bytes per block.
+        let input = noise(100000);
+        for &m in &[Method::Lz77, Method::Lzss, Method::Huff, Method::Arith] {
+            let compressed = compress_with_block_size(Cursor::new(&input[..]), vec![],
+                                                      m, 4096).unwrap();
+            assert!(compressed.len() <= input.len() + 10 + 25 * 4, "method {}", m.name());
+            let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+            assert_eq!(&input[..], &decompressed[..], "method {}", m.name());
+        }
+    }
+
+    #[test]
+    fn mixed_blocks() {
+        let mut input = include_bytes!("container.rs").to_vec();
+        input.extend_from_slice(&noise(20000));
+        input.extend_from_slice(include_bytes!("method.rs"));
+        let compressed = compress_with_block_size(Cursor::new(&input[..]), vec![],
+                                                  Method::Lzss2, 8192).unwrap();
+        let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(&input[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn bad_header() {
+        assert!(Reader::new(Cursor::new(&b"CAMQ\x01\x07\x80\x80\x40"[..])).is_err());
+        assert!(Reader::new(Cursor::new(&b"CAMP\x02\x07\x80\x80\x40"[..])).is_err());
+        assert!(Reader::new(Cursor::new(&b"CAMP\x01\xff\x80\x80\x40"[..])).is_err());
+        assert!(Reader::new(Cursor::new(&b"CAMP\x01\x07\x00"[..])).is_err());
+        assert!(Reader::new(Cursor::new(&b"CAMP\x01\x07\x80\x80\x40"[..])).is_ok());
+    }
+
+    #[test]
+    fn block_longer_than_length() {
+        let data = Method::Lzss.compress(Cursor::new(vec![0; 100000]), Vec::new()).unwrap();
+        let mut container = Vec::new();
+        write_header(&mut container, Method::Lzss, 4096).unwrap();
+        container.push(BLOCK_COMPRESSED);
+        write_varint(&mut container, 10).unwrap();
+        write_varint(&mut container, data.len() as u64).unwrap();
+        container.extend_from_slice(&data);
+        match decompress(Cursor::new(&container[..]), vec![]) {
+            Err(Error::CorruptInput { detail: "container block longer than its length", .. }) => (),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let input = include_bytes!("container.rs");
+        let compressed = compress(Cursor::new(&input[..]), vec![], Method::Lzss).unwrap();
+        for &len in &[compressed.len() - 1, compressed.len() / 2, 10] {
+            assert!(decompress(Cursor::new(&compressed[..len]), vec![]).is_err());
+        }
+    }
+
+    #[test]
+    fn content_digest() {
+        let input = include_bytes!("container.rs");
+        let compressed = compress_with_block_size(Cursor::new(&input[..]), vec![],
+                                                  Method::Store, 1000).unwrap();
+        assert_eq!(digest(Cursor::new(&input[..])).unwrap(),
+                   verify(Cursor::new(&compressed[..])).unwrap());
+        // Flip a bit in the data of a stored block.
+        let mut corrupted = compressed.clone();
+        corrupted[2000] ^= 1;
+        assert!(verify(Cursor::new(&corrupted[..])).is_err());
+        match decompress(Cursor::new(&corrupted[..]), vec![]) {
+            Err(Error::ChecksumMismatch { .. }) => (),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn structured_errors() {
+        let input = include_bytes!("container.rs");
+        let compressed = compress(Cursor::new(&input[..]), vec![], Method::Lzss).unwrap();
+        let mut wrong_version = compressed.clone();
+        wrong_version[4] = 9;
+        match decompress(Cursor::new(&wrong_version[..]), vec![]) {
+            Err(Error::UnsupportedVersion(9)) => (),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        match decompress(Cursor::new(&compressed[1..]), vec![]) {
+            Err(Error::InvalidHeader(_)) => (),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        match decompress(Cursor::new(&compressed[..compressed.len() / 2]), vec![]) {
+            Err(Error::UnexpectedEof) => (),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn resume_writer() {
+        let input = include_bytes!("container.rs");
+        let mut w = Writer::with_block_size(vec![], Method::Lzss, 4096);
+        w.write_all(&input[..10000]).unwrap();
+        let saved = w.checkpoint().unwrap().to_bytes();
+        // Data written after the checkpoint is lost in the crash.
+        w.write_all(&input[10000..15000]).unwrap();
+        let mut compressed = w.into_inner();
+
+        let cp = Checkpoint::from_bytes(&saved).unwrap();
+        assert_eq!(10000, cp.uncompressed_offset());
+        compressed.truncate(cp.compressed_offset() as usize);
+        let mut w = Writer::resume(compressed, &cp).unwrap();
+        w.write_all(&input[10000..]).unwrap();
+        w.flush().unwrap();
+        assert_eq!(input.len() as u64, w.total_in());
+        let compressed = w.into_inner();
+        let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(&input[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn resume_reader() {
+        let input = include_bytes!("container.rs");
+        let compressed = compress_with_block_size(Cursor::new(&input[..]), vec![],
+                                                  Method::Lzss2, 4096).unwrap();
+        for &split in &[0, 1, 4096, 10000, input.len()] {
+            let mut r = Reader::new(Cursor::new(&compressed[..])).unwrap();
+            let mut first = vec![0; split];
+            r.read_exact(&mut first).unwrap();
+            let saved = r.checkpoint().to_bytes();
+
+            let cp = Checkpoint::from_bytes(&saved).unwrap();
+            assert_eq!(split as u64, cp.uncompressed_offset());
+            let rest = &compressed[cp.compressed_offset() as usize..];
+            let mut r = Reader::resume(Cursor::new(rest), &cp).unwrap();
+            r.read_to_end(&mut first).unwrap();
+            assert_eq!(&input[..], &first[..]);
+            assert_eq!(input.len() as u64, r.total_out());
+            assert_eq!(digest(Cursor::new(&input[..])).unwrap(), r.digest());
+        }
+    }
+
+    #[test]
+    fn bad_checkpoint() {
+        let input = include_bytes!("container.rs");
+        let mut w = Writer::new(vec![], Method::Lz77);
+        w.write_all(&input[..]).unwrap();
+        let cp = w.checkpoint().unwrap();
+        let saved = cp.to_bytes();
+        for i in 0..saved.len() {
+            let mut corrupted = saved.clone();
+            corrupted[i] ^= 0x10;
+            assert!(Checkpoint::from_bytes(&corrupted).is_err());
+        }
+        assert!(Checkpoint::from_bytes(&saved[..saved.len() - 1]).is_err());
+        assert!(Reader::resume(Cursor::new(&[][..]), &cp).is_err());
+    }
+
+    #[test]
+    fn hybrid() {
+        let text = include_bytes!("container.rs");
+        let mut input = text[..8192].to_vec();
+        input.extend_from_slice(&noise(8192));
+        let lzed = Method::Lz4.compress(Cursor::new(&text[..]), vec![]).unwrap();
+        input.extend_from_slice(&lzed[..8192]);
+        let mut w = Writer::hybrid_with_block_size(vec![], 8192);
+        w.write_all(&input).unwrap();
+        w.flush().unwrap();
+        let compressed = w.into_inner();
+
+        // Text is compressed with the header method, noise is stored
+        // and the LZ4 output is Huffman coded.
+        let mut r = Reader::new(Cursor::new(&compressed[..])).unwrap();
+        assert_eq!(Method::Lzss2, r.method());
+        let pos = r.total_in() as usize;
+        assert_eq!(BLOCK_COMPRESSED, compressed[pos]);
+        let mut block = vec![0; 8192];
+        r.read_exact(&mut block).unwrap();
+        let pos = r.total_in() as usize;
+        assert_eq!(BLOCK_STORED, compressed[pos]);
+        r.read_exact(&mut block).unwrap();
+        let pos = r.total_in() as usize;
+        assert_eq!(&[BLOCK_METHOD, Method::Huff.id()][..], &compressed[pos..pos + 2]);
+
+        let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(&input[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn resume_hybrid() {
+        let text = include_bytes!("container.rs");
+        let lzed = Method::Lz4.compress(Cursor::new(&text[..]), vec![]).unwrap();
+        let mut w = Writer::hybrid_with_block_size(vec![], 4096);
+        w.write_all(&text[..5000]).unwrap();
+        let cp = w.checkpoint().unwrap();
+        let cp = Checkpoint::from_bytes(&cp.to_bytes()).unwrap();
+        let mut w = Writer::resume(w.into_inner(), &cp).unwrap();
+        w.write_all(&lzed[..4096]).unwrap();
+        w.flush().unwrap();
+        let compressed = w.into_inner();
+        // The resumed writer still chooses the method per block.
+        let pos = cp.compressed_offset() as usize;
+        assert_eq!(&[BLOCK_METHOD, Method::Huff.id()][..], &compressed[pos..pos + 2]);
+        let mut expected = text[..5000].to_vec();
+        expected.extend_from_slice(&lzed[..4096]);
+        let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(&expected[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress_with_block_size(d, vec![], Method::Lzss,
+                                                                          10000),
+                               |d: &[u8]| super::decompress(d, vec![])));
+    }
+
+    #[test]
+    fn parallel() {
+        let input = include_bytes!("container.rs");
+        for &len in &[0, 1000, 4000, input.len()] {
+            let data = &input[..len];
+            for &threads in &[1, 3, 0] {
+                let mut cw = Writer::with_block_size(Vec::new(), Method::Lzss, 1000);
+                cw.write_all(data).unwrap();
+                cw.flush().unwrap();
+                let mut p = Parallel::new(Method::Lzss, threads).block_size(1000);
+                let compressed = p.compress(data, Vec::new()).unwrap();
+                assert_eq!(cw.into_inner(), compressed);
+                assert!(p.threads() > 0);
+
+                let mut hw = Writer::hybrid_with_block_size(Vec::new(), 1000);
+                hw.write_all(data).unwrap();
+                hw.flush().unwrap();
+                let mut p = Parallel::hybrid(threads).block_size(1000);
+                assert_eq!(hw.into_inner(), p.compress(data, Vec::new()).unwrap());
+            }
+        }
+        let compressed = compress_parallel(&input[..], Vec::new(), Method::Lzss2, 2).unwrap();
+        assert_eq!(&input[..], &decompress(&compressed[..], Vec::new()).unwrap()[..]);
+    }
+}
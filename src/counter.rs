@@ -0,0 +1,74 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Byte counting for the compressed side of the codecs.
+//!
+//! The writers and readers of the codecs wrap the writer or reader
+//! they are given in a `Counter`, which counts the compressed bytes
+//! passing through it.  Together with the number of uncompressed
+//! bytes, which each codec counts itself, this is what `total_in` and
+//! `total_out` report.
+
+use io::{self, Read, Write};
+
+/// Reader or writer adapter which counts the bytes read or written.
+#[derive(Clone)]
+pub struct Counter<T> {
+    inner: T,
+    count: u64,
+}
+
+impl<T> Counter<T> {
+    pub fn new(inner: T) -> Counter<T> {
+        Counter {
+            inner: inner,
+            count: 0,
+        }
+    }
+
+    /// Create a counter which starts at `count`, for continuing a
+    /// stream of which `count` bytes were handled elsewhere.
+    pub fn with_count(inner: T, count: u64) -> Counter<T> {
+        Counter {
+            inner: inner,
+            count: count,
+        }
+    }
+
+    /// Return the number of bytes read or written so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for Counter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.inner.read(buf));
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = try!(self.inner.write(buf));
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,949 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Raw DEFLATE (RFC 1951) compressor and decompressor.
+//!
+//! The compressor finds matches with hash chains over a sliding
+//! window of up to 32 KiB and emits each block either with the fixed
+//! Huffman codes or as a stored block, whichever is smaller.  The
+//! decompressor understands all three block types, so it can read
+//! streams produced by zlib and friends.  `inspect` lists the blocks,
+//! the code lengths of dynamic blocks, and the literals and matches.
+
+use std::io::{Read, Write, Bytes};
+use std::io;
+
+use counter::Counter;
+use error::{at_offset, Error};
+use inspect::{Event, Events, Inspect, Recorder};
+
+const MAX_WINDOW_BITS: usize = 15;
+const MIN_WINDOW_BITS: usize = 8;
+
+const BLOCK_SIZE: usize = 1 << 16;
+const MAX_STORED: usize = 0xffff;
+
+const MIN_MATCH_LEN: usize = 3;
+const MAX_MATCH_LEN: usize = 258;
+
+const HASH_BITS: usize = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+const MAX_CHAIN: usize = 64;
+
+const END_OF_BLOCK: usize = 256;
+
+const MAX_CODE_BITS: usize = 15;
+
+static LENGTH_BASE: [u16; 29] =
+    [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+     35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+static LENGTH_EXTRA: [u8; 29] =
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+     3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+
+static DIST_BASE: [u16; 30] =
+    [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+     257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+     8193, 12289, 16385, 24577];
+static DIST_EXTRA: [u8; 30] =
+    [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+     7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// Order in which the code length code lengths are transmitted in a
+/// dynamic block header.
+static CLEN_ORDER: [usize; 19] =
+    [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn corrupt(msg: &'static str) -> io::Error {
+    Error::corrupt(msg).into()
+}
+
+/// Reverse the `len` least significant bits of `code`.  Huffman codes
+/// are packed starting with their most significant bit, while all
+/// other fields are packed starting with the least significant bit.
+fn reverse_bits(mut code: u32, len: usize) -> u32 {
+    let mut result = 0;
+    for _ in 0..len {
+        result = (result << 1) | (code & 1);
+        code >>= 1;
+    }
+    result
+}
+
+/// Return the fixed Huffman code and its length for literal/length
+/// symbol `sym`.
+fn fixed_lit_code(sym: usize) -> (u32, usize) {
+    match sym {
+        0..=143 => (0x30 + sym as u32, 8),
+        144..=255 => (0x190 + (sym - 144) as u32, 9),
+        256..=279 => ((sym - 256) as u32, 7),
+        _ => (0xc0 + (sym - 280) as u32, 8),
+    }
+}
+
+fn length_code(len: usize) -> usize {
+    let mut code = LENGTH_BASE.len() - 1;
+    while LENGTH_BASE[code] as usize > len {
+        code -= 1;
+    }
+    code
+}
+
+fn dist_code(dist: usize) -> usize {
+    let mut code = DIST_BASE.len() - 1;
+    while DIST_BASE[code] as usize > dist {
+        code -= 1;
+    }
+    code
+}
+
+/// Bit writer packing fields starting at the least significant bit,
+/// as required by DEFLATE.
+struct LsbWriter<W> {
+    inner: Counter<W>,
+    acc: u64,
+    bits: usize,
+}
+
+impl<W: Write> LsbWriter<W> {
+    fn new(inner: W) -> LsbWriter<W> {
+        LsbWriter {
+            inner: Counter::new(inner),
+            acc: 0,
+            bits: 0,
+        }
+    }
+
+    fn write_bits(&mut self, value: u32, count: usize) -> io::Result<()> {
+        debug_assert!(count <= 32);
+        self.acc |= (value as u64 & ((1u64 << count) - 1)) << self.bits;
+        self.bits += count;
+        if self.bits >= 32 {
+            let b = [self.acc as u8, (self.acc >> 8) as u8,
+                     (self.acc >> 16) as u8, (self.acc >> 24) as u8];
+            try!(self.inner.write_all(&b));
+            self.acc >>= 32;
+            self.bits -= 32;
+        }
+        Ok(())
+    }
+
+    fn write_code(&mut self, code: u32, len: usize) -> io::Result<()> {
+        self.write_bits(reverse_bits(code, len), len)
+    }
+
+    /// Write out all complete and partial bytes, padding with zero
+    /// bits to the next byte boundary.
+    fn align(&mut self) -> io::Result<()> {
+        while self.bits > 0 {
+            try!(self.inner.write_all(&[self.acc as u8]));
+            self.acc >>= 8;
+            self.bits = if self.bits > 8 { self.bits - 8 } else { 0 };
+        }
+        self.acc = 0;
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, data: &[u8]) -> io::Result<()> {
+        debug_assert!(self.bits == 0);
+        self.inner.write_all(data)
+    }
+
+    fn to_inner(self) -> W {
+        self.inner.into_inner()
+    }
+}
+
+/// Bit reader matching `LsbWriter`.  Bytes are only pulled from the
+/// underlying reader when needed, so the reader never consumes input
+/// beyond the byte containing the last bit requested.
+struct LsbReader<R> {
+    inner: Bytes<R>,
+    acc: u32,
+    bits: usize,
+    /// Number of bytes read so far.
+    count: u64,
+}
+
+impl<R: Read> LsbReader<R> {
+    fn new(inner: R) -> LsbReader<R> {
+        LsbReader {
+            inner: inner.bytes(),
+            acc: 0,
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        match self.inner.next() {
+            Some(b) => {
+                self.count += 1;
+                b
+            },
+            None => Err(Error::UnexpectedEof.into()),
+        }
+    }
+
+    fn read_bits(&mut self, count: usize) -> io::Result<u32> {
+        debug_assert!(count <= 24);
+        while self.bits < count {
+            let b = try!(self.read_byte());
+            self.acc |= (b as u32) << self.bits;
+            self.bits += 8;
+        }
+        let result = self.acc & ((1 << count) - 1);
+        self.acc >>= count;
+        self.bits -= count;
+        Ok(result)
+    }
+
+    /// Discard the bits remaining in the current byte.
+    fn align(&mut self) {
+        let extra = self.bits % 8;
+        self.acc >>= extra;
+        self.bits -= extra;
+    }
+
+    fn read_aligned_byte(&mut self) -> io::Result<u8> {
+        if self.bits >= 8 {
+            let b = self.acc as u8;
+            self.acc >>= 8;
+            self.bits -= 8;
+            Ok(b)
+        } else {
+            self.read_byte()
+        }
+    }
+}
+
+/// Canonical Huffman decoding table, stored as the number of codes
+/// per length and the symbols ordered by code.
+struct Huffman {
+    counts: [u16; MAX_CODE_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Huffman> {
+        let mut h = Huffman {
+            counts: [0; MAX_CODE_BITS + 1],
+            symbols: vec![0; lengths.len()],
+        };
+        for &l in lengths {
+            h.counts[l as usize] += 1;
+        }
+        let mut left: isize = 1;
+        for len in 1..MAX_CODE_BITS + 1 {
+            left <<= 1;
+            left -= h.counts[len] as isize;
+            if left < 0 {
+                return Err(corrupt("over-subscribed Huffman code"));
+            }
+        }
+        let mut offs = [0u16; MAX_CODE_BITS + 2];
+        for len in 1..MAX_CODE_BITS + 1 {
+            offs[len + 1] = offs[len] + h.counts[len];
+        }
+        for (sym, &l) in lengths.iter().enumerate() {
+            if l != 0 {
+                h.symbols[offs[l as usize] as usize] = sym as u16;
+                offs[l as usize] += 1;
+            }
+        }
+        Ok(h)
+    }
+
+    fn decode<R: Read>(&self, inp: &mut LsbReader<R>) -> io::Result<usize> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..MAX_CODE_BITS + 1 {
+            code |= try!(inp.read_bits(1)) as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + (code - first)) as usize] as usize);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(corrupt("invalid Huffman code"))
+    }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288 + 30];
+    for i in 0..288 {
+        lengths[i] = fixed_lit_code(i).1 as u8;
+    }
+    for i in 288..288 + 30 {
+        lengths[i] = 5;
+    }
+    (Huffman::new(&lengths[..288]).unwrap(), Huffman::new(&lengths[288..]).unwrap())
+}
+
+#[derive(Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match(usize, usize),
+}
+
+/// Writer for raw DEFLATE streams.
+pub struct Writer<W> {
+    inner: LsbWriter<W>,
+    window_size: usize,
+    buf: Vec<u8>,
+    history: usize,
+    head: Vec<usize>,
+    prev: Vec<usize>,
+    tokens: Vec<Token>,
+    finished: bool,
+    total_in: u64,
+}
+
+fn hash_at(data: &[u8], pos: usize) -> usize {
+    let h = ((data[pos] as usize) << 10) ^ ((data[pos + 1] as usize) << 5) ^
+        (data[pos + 2] as usize);
+    h & (HASH_SIZE - 1)
+}
+
+impl<W: Write> Writer<W> {
+    /// Create a new DEFLATE writer with a 32 KiB window.
+    pub fn new(inner: W) -> Writer<W> {
+        Writer::with_window_bits(inner, MAX_WINDOW_BITS)
+    }
+
+    /// Create a new DEFLATE writer which never emits distances
+    /// larger than `1 << window_bits`.  `window_bits` is clamped to
+    /// the range 8 to 15.
+    pub fn with_window_bits(inner: W, window_bits: usize) -> Writer<W> {
+        let bits = ::std::cmp::max(MIN_WINDOW_BITS,
+                                   ::std::cmp::min(MAX_WINDOW_BITS, window_bits));
+        Writer {
+            inner: LsbWriter::new(inner),
+            window_size: 1 << bits,
+            buf: Vec::with_capacity((1 << bits) + BLOCK_SIZE),
+            history: 0,
+            head: vec![usize::max_value(); HASH_SIZE],
+            prev: vec![usize::max_value(); (1 << bits) + BLOCK_SIZE],
+            tokens: Vec::new(),
+            finished: false,
+            total_in: 0,
+        }
+    }
+
+    /// Return the window size in bits used by this writer.
+    pub fn window_bits(&self) -> usize {
+        self.window_size.trailing_zeros() as usize
+    }
+
+    fn insert(&mut self, pos: usize) {
+        let h = hash_at(&self.buf, pos);
+        self.prev[pos] = self.head[h];
+        self.head[h] = pos;
+    }
+
+    fn longest_match(&self, pos: usize) -> (usize, usize) {
+        let max_len = ::std::cmp::min(MAX_MATCH_LEN, self.buf.len() - pos);
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        let mut cand = self.head[hash_at(&self.buf, pos)];
+        let mut chain = 0;
+        while cand != usize::max_value() && chain < MAX_CHAIN {
+            let dist = pos - cand;
+            if dist > self.window_size {
+                break;
+            }
+            if self.buf[cand + best_len] == self.buf[pos + best_len] {
+                let mut len = 0;
+                while len < max_len && self.buf[cand + len] == self.buf[pos + len] {
+                    len += 1;
+                }
+                if len > best_len {
+                    best_len = len;
+                    best_dist = dist;
+                    if len == max_len {
+                        break;
+                    }
+                }
+            }
+            cand = self.prev[cand];
+            chain += 1;
+        }
+        (best_len, best_dist)
+    }
+
+    /// Parse the data following the history part of the buffer into
+    /// literals and matches.
+    fn parse(&mut self) {
+        for h in self.head.iter_mut() {
+            *h = usize::max_value();
+        }
+        let mut pos = 0;
+        while pos + MIN_MATCH_LEN <= self.history {
+            self.insert(pos);
+            pos += 1;
+        }
+        pos = self.history;
+        self.tokens.clear();
+        while pos < self.buf.len() {
+            if pos + MIN_MATCH_LEN > self.buf.len() {
+                self.tokens.push(Token::Literal(self.buf[pos]));
+                pos += 1;
+                continue;
+            }
+            let (len, dist) = self.longest_match(pos);
+            if len >= MIN_MATCH_LEN {
+                self.tokens.push(Token::Match(len, dist));
+                for p in pos..pos + len {
+                    if p + MIN_MATCH_LEN <= self.buf.len() {
+                        self.insert(p);
+                    }
+                }
+                pos += len;
+            } else {
+                self.tokens.push(Token::Literal(self.buf[pos]));
+                self.insert(pos);
+                pos += 1;
+            }
+        }
+    }
+
+    fn fixed_cost(&self) -> usize {
+        let mut bits = 3 + 7;
+        for t in &self.tokens {
+            bits += match *t {
+                Token::Literal(l) => fixed_lit_code(l as usize).1,
+                Token::Match(len, dist) => {
+                    let lc = length_code(len);
+                    let dc = dist_code(dist);
+                    fixed_lit_code(257 + lc).1 + LENGTH_EXTRA[lc] as usize +
+                        5 + DIST_EXTRA[dc] as usize
+                },
+            };
+        }
+        bits
+    }
+
+    fn stored_cost(&self) -> usize {
+        let len = self.buf.len() - self.history;
+        let chunks = ::std::cmp::max(1, (len + MAX_STORED - 1) / MAX_STORED);
+        chunks * (3 + 7 + 32) + len * 8
+    }
+
+    fn write_fixed_block(&mut self, final_block: bool) -> io::Result<()> {
+        try!(self.inner.write_bits(final_block as u32, 1));
+        try!(self.inner.write_bits(1, 2));
+        for i in 0..self.tokens.len() {
+            match self.tokens[i] {
+                Token::Literal(l) => {
+                    let (code, len) = fixed_lit_code(l as usize);
+                    try!(self.inner.write_code(code, len));
+                },
+                Token::Match(len, dist) => {
+                    let lc = length_code(len);
+                    let (code, code_len) = fixed_lit_code(257 + lc);
+                    try!(self.inner.write_code(code, code_len));
+                    try!(self.inner.write_bits((len - LENGTH_BASE[lc] as usize) as u32,
+                                               LENGTH_EXTRA[lc] as usize));
+                    let dc = dist_code(dist);
+                    try!(self.inner.write_code(dc as u32, 5));
+                    try!(self.inner.write_bits((dist - DIST_BASE[dc] as usize) as u32,
+                                               DIST_EXTRA[dc] as usize));
+                },
+            }
+        }
+        let (code, len) = fixed_lit_code(END_OF_BLOCK);
+        self.inner.write_code(code, len)
+    }
+
+    fn write_stored_blocks(&mut self, final_block: bool) -> io::Result<()> {
+        let mut start = self.history;
+        loop {
+            let end = ::std::cmp::min(self.buf.len(), start + MAX_STORED);
+            let last = end == self.buf.len();
+            try!(self.inner.write_bits((final_block && last) as u32, 1));
+            try!(self.inner.write_bits(0, 2));
+            try!(self.inner.align());
+            let len = end - start;
+            try!(self.inner.write_bytes(&[len as u8, (len >> 8) as u8,
+                                          !len as u8, (!len >> 8) as u8]));
+            try!(self.inner.write_bytes(&self.buf[start..end]));
+            start = end;
+            if last {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn process_block(&mut self, final_block: bool) -> io::Result<()> {
+        self.parse();
+        if self.stored_cost() < self.fixed_cost() {
+            try!(self.write_stored_blocks(final_block));
+        } else {
+            try!(self.write_fixed_block(final_block));
+        }
+        // Keep only as much history as can be referenced by the next
+        // block.
+        if self.buf.len() > self.window_size {
+            let drop = self.buf.len() - self.window_size;
+            self.buf.drain(..drop);
+        }
+        self.history = self.buf.len();
+        Ok(())
+    }
+
+    /// Compress all buffered data and terminate the DEFLATE stream
+    /// with a final block.  Further writes are an error.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            try!(self.process_block(true));
+            try!(self.inner.align());
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    /// Get a mutable reference to the wrapped writer.  Writing to it
+    /// directly is only safe at the start or after `finish`.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.inner.get_mut()
+    }
+
+    /// Return the number of uncompressed bytes written so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Return the number of compressed bytes written to the wrapped
+    /// writer so far.
+    pub fn total_out(&self) -> u64 {
+        self.inner.inner.count()
+    }
+
+    /// Move the wrapped writer out of the DEFLATE writer.
+    pub fn into_inner(self) -> W {
+        self.inner.to_inner()
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::new(io::ErrorKind::Other,
+                                      "write after end of deflate stream"));
+        }
+        let mut written = 0;
+        while written < buf.len() {
+            let space = self.history + BLOCK_SIZE - self.buf.len();
+            let n = ::std::cmp::min(space, buf.len() - written);
+            self.buf.extend_from_slice(&buf[written..written + n]);
+            written += n;
+            if self.buf.len() == self.history + BLOCK_SIZE {
+                try!(self.process_block(false));
+            }
+        }
+        self.total_in += written as u64;
+        Ok(written)
+    }
+
+    /// Terminate the stream and flush the underlying writer.
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.finish());
+        self.inner.inner.flush()
+    }
+}
+
+/// Reader for raw DEFLATE streams.
+pub struct Reader<R> {
+    inner: LsbReader<R>,
+    window_size: usize,
+    window: Vec<u8>,
+    returned: usize,
+    last_block: bool,
+    eof: bool,
+    total_out: u64,
+    recorder: Recorder,
+}
+
+impl<R: Read> Reader<R> {
+    /// Create a new DEFLATE reader accepting distances up to 32 KiB.
+    pub fn new(inner: R) -> Reader<R> {
+        Reader::with_window_bits(inner, MAX_WINDOW_BITS)
+    }
+
+    /// Create a new DEFLATE reader which rejects distances larger
+    /// than `1 << window_bits` as corrupt input.
+    pub fn with_window_bits(inner: R, window_bits: usize) -> Reader<R> {
+        let bits = ::std::cmp::max(MIN_WINDOW_BITS,
+                                   ::std::cmp::min(MAX_WINDOW_BITS, window_bits));
+        Reader {
+            inner: LsbReader::new(inner),
+            window_size: 1 << bits,
+            window: Vec::new(),
+            returned: 0,
+            last_block: false,
+            eof: false,
+            total_out: 0,
+            recorder: Recorder::new(),
+        }
+    }
+
+    /// Return the number of compressed bytes read so far.
+    pub fn total_in(&self) -> u64 {
+        self.inner.count
+    }
+
+    /// Return the number of decompressed bytes read so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Read raw bytes following the end of the DEFLATE stream, such
+    /// as the trailer of a zlib or gzip member.  Must only be called
+    /// after the reader has returned end of file.
+    pub fn read_trailer(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        debug_assert!(self.eof);
+        self.inner.align();
+        for b in buf.iter_mut() {
+            *b = try!(self.inner.read_aligned_byte());
+        }
+        Ok(())
+    }
+
+    fn copy_match(&mut self, len: usize, dist: usize) -> io::Result<()> {
+        if dist > self.window.len() || dist > self.window_size {
+            return Err(corrupt("distance too far back"));
+        }
+        let start = self.window.len() - dist;
+        for i in 0..len {
+            let b = self.window[start + i];
+            self.window.push(b);
+        }
+        Ok(())
+    }
+
+    fn read_stored(&mut self) -> io::Result<()> {
+        self.inner.align();
+        let mut hdr = [0u8; 4];
+        for b in hdr.iter_mut() {
+            *b = try!(self.inner.read_aligned_byte());
+        }
+        let len = hdr[0] as usize | ((hdr[1] as usize) << 8);
+        let nlen = hdr[2] as usize | ((hdr[3] as usize) << 8);
+        if len != !nlen & 0xffff {
+            return Err(corrupt("stored block length mismatch"));
+        }
+        self.recorder.record(Event::Block(format!("stored {} bytes{}", len, self.final_note())));
+        for _ in 0..len {
+            let b = try!(self.inner.read_aligned_byte());
+            self.recorder.record(Event::Literal(b));
+            self.window.push(b);
+        }
+        Ok(())
+    }
+
+    fn read_codes(&mut self, lit: &Huffman, dist: &Huffman) -> io::Result<()> {
+        loop {
+            let sym = try!(lit.decode(&mut self.inner));
+            if sym < 256 {
+                self.recorder.record(Event::Literal(sym as u8));
+                self.window.push(sym as u8);
+            } else if sym == END_OF_BLOCK {
+                return Ok(());
+            } else {
+                let lc = sym - 257;
+                if lc >= LENGTH_BASE.len() {
+                    return Err(corrupt("invalid length code"));
+                }
+                let len = LENGTH_BASE[lc] as usize +
+                    try!(self.inner.read_bits(LENGTH_EXTRA[lc] as usize)) as usize;
+                let dc = try!(dist.decode(&mut self.inner));
+                if dc >= DIST_BASE.len() {
+                    return Err(corrupt("invalid distance code"));
+                }
+                let d = DIST_BASE[dc] as usize +
+                    try!(self.inner.read_bits(DIST_EXTRA[dc] as usize)) as usize;
+                self.recorder.record(Event::Match { offset: d, len: len });
+                try!(self.copy_match(len, d));
+            }
+        }
+    }
+
+    fn read_dynamic_tables(&mut self) -> io::Result<(Huffman, Huffman)> {
+        let nlen = try!(self.inner.read_bits(5)) as usize + 257;
+        let ndist = try!(self.inner.read_bits(5)) as usize + 1;
+        let ncode = try!(self.inner.read_bits(4)) as usize + 4;
+        if nlen > 286 || ndist > 30 {
+            return Err(corrupt("bad dynamic block counts"));
+        }
+        let mut clens = [0u8; 19];
+        for i in 0..ncode {
+            clens[CLEN_ORDER[i]] = try!(self.inner.read_bits(3)) as u8;
+        }
+        let clcode = try!(Huffman::new(&clens));
+        let mut lengths = vec![0u8; nlen + ndist];
+        let mut i = 0;
+        while i < nlen + ndist {
+            let sym = try!(clcode.decode(&mut self.inner));
+            if sym < 16 {
+                lengths[i] = sym as u8;
+                i += 1;
+            } else {
+                let (value, repeat) = match sym {
+                    16 => {
+                        if i == 0 {
+                            return Err(corrupt("repeat without previous length"));
+                        }
+                        (lengths[i - 1], 3 + try!(self.inner.read_bits(2)) as usize)
+                    },
+                    17 => (0, 3 + try!(self.inner.read_bits(3)) as usize),
+                    _ => (0, 11 + try!(self.inner.read_bits(7)) as usize),
+                };
+                if i + repeat > nlen + ndist {
+                    return Err(corrupt("too many code lengths"));
+                }
+                for _ in 0..repeat {
+                    lengths[i] = value;
+                    i += 1;
+                }
+            }
+        }
+        if lengths[END_OF_BLOCK] == 0 {
+            return Err(corrupt("missing end-of-block code"));
+        }
+        let lit = try!(Huffman::new(&lengths[..nlen]));
+        let dist = try!(Huffman::new(&lengths[nlen..]));
+        self.recorder.record(Event::CodeLengths(
+            lengths[..nlen].iter().map(|&l| l as usize).collect()));
+        self.recorder.record(Event::CodeLengths(
+            lengths[nlen..].iter().map(|&l| l as usize).collect()));
+        Ok((lit, dist))
+    }
+
+    /// Return the note added to the description of the last block.
+    fn final_note(&self) -> &'static str {
+        if self.last_block { ", final" } else { "" }
+    }
+
+    /// Decode the next block, appending its output to the window.
+    fn read_block(&mut self) -> io::Result<()> {
+        self.last_block = try!(self.inner.read_bits(1)) == 1;
+        match try!(self.inner.read_bits(2)) {
+            0 => self.read_stored(),
+            1 => {
+                self.recorder.record(Event::Block(format!("fixed codes{}", self.final_note())));
+                let (lit, dist) = fixed_tables();
+                self.read_codes(&lit, &dist)
+            },
+            2 => {
+                self.recorder.record(Event::Block(format!("dynamic codes{}", self.final_note())));
+                let (lit, dist) = try!(self.read_dynamic_tables());
+                self.read_codes(&lit, &dist)
+            },
+            _ => Err(corrupt("invalid block type")),
+        }
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
+        while self.returned == self.window.len() && !self.eof {
+            if self.last_block {
+                self.recorder.record(Event::End);
+                self.eof = true;
+                break;
+            }
+            // Discard history that can no longer be referenced.
+            if self.window.len() > 2 * self.window_size {
+                let drop = self.window.len() - self.window_size;
+                self.window.drain(..drop);
+                self.returned -= drop;
+            }
+            try!(self.read_block().map_err(|e| at_offset(e, self.total_in())));
+        }
+        let n = ::std::cmp::min(output.len(), self.window.len() - self.returned);
+        output[..n].copy_from_slice(&self.window[self.returned..self.returned + n]);
+        self.returned += n;
+        self.total_out += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read> Inspect for Reader<R> {
+    fn recorder(&mut self) -> &mut Recorder {
+        &mut self.recorder
+    }
+}
+
+/// Compress all data from `input` into a raw DEFLATE stream written
+/// to `output`.  On success, the output is returned.
+pub fn compress<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error> {
+    let mut cw = Writer::new(output);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.into_inner())
+}
+
+/// Decompress the raw DEFLATE stream in `input` and write the result
+/// to `output`.  On success, the output is returned.
+pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<W, Error> {
+    let mut cr = Reader::new(input);
+    try!(io::copy(&mut cr, &mut output));
+    Ok(output)
+}
+
+/// Return an iterator over the blocks, code lengths, literals and
+/// matches of the raw DEFLATE stream in `input`.  Dynamic blocks are
+/// followed by the code lengths of the literal/length code and of the
+/// distance code.
+pub fn inspect<R: Read>(input: R) -> Events<Reader<R>> {
+    Events::new(Reader::new(input))
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::io::Cursor;
+    use super::{compress, decompress, Reader, Writer};
+    use ::std::io::{Read, Write};
+
+    fn cmp_test(input: &[u8], expected_output: &[u8]) {
+        let compressed = compress(Cursor::new(input), vec![]).unwrap();
+        assert_eq!(&expected_output[..], &compressed[..]);
+    }
+
+    fn decmp_test(compressed: &[u8], expected_output: &[u8]) {
+        let decompressed = decompress(Cursor::new(compressed), vec![]).unwrap();
+        assert_eq!(&expected_output[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn compress_empty() {
+        cmp_test(b"", &[3, 0]);
+    }
+
+    #[test]
+    fn compress_aaa() {
+        cmp_test(b"aaaaaaaaa", &[75, 132, 1, 0]);
+    }
+
+    #[test]
+    fn decompress_empty() {
+        decmp_test(&[3, 0], b"");
+    }
+
+    #[test]
+    fn decompress_stored() {
+        decmp_test(&[1, 3, 0, 252, 255, b'a', b'b', b'c'], b"abc");
+    }
+
+    #[test]
+    fn decompress_fixed() {
+        // Produced by zlib's raw deflate at level 9.
+        let input = [11, 201, 72, 85, 72, 74, 204, 3, 66, 133, 244, 252, 196, 18,
+                     133, 204, 60, 133, 18, 132, 80, 18, 72, 40, 57, 49, 79, 33,
+                     35, 49, 47, 5, 42, 88, 172, 80, 146, 143, 172, 38, 55, 49,
+                     79, 79, 33, 132, 42, 198, 0, 0];
+        let expected = b"The banana goat in the banana boat can hand bananas to the banana man. \
+                         The banana goat in the banana boat can hand bananas to the banana man.";
+        decmp_test(&input, &expected[..]);
+    }
+
+    #[test]
+    fn decompress_dynamic() {
+        // Produced by zlib's raw deflate at level 9.
+        let input = [229, 205, 55, 22, 130, 64, 0, 5, 192, 171, 252, 35, 152, 67,
+                     41, 8, 130, 40, 8, 130, 160, 221, 146, 243, 194, 146, 57,
+                     189, 239, 121, 13, 235, 41, 134, 184, 158, 31, 132, 81, 156,
+                     164, 89, 94, 148, 180, 170, 89, 211, 118, 253, 48, 78, 243,
+                     98, 185, 90, 111, 182, 187, 253, 225, 136, 19, 199, 159, 5,
+                     241, 34, 201, 87, 229, 118, 87, 181, 135, 110, 60, 77, 235,
+                     101, 59, 239, 15, 218, 56, 64, 221, 37, 94, 6, 151, 209, 161,
+                     68, 72, 71, 164, 93, 81, 53, 160, 125, 192, 126, 156, 147,
+                     121, 130, 79, 35, 152, 146, 0, 221, 146, 121, 5, 156, 161,
+                     217, 42, 68, 205, 33, 127, 221, 127, 1];
+        let line = b"abcdefghijklmnopqrstuvwxyz0123456789 ABCDEFGHIJKLMNOPQRSTUVWXYZ \
+                     the quick brown fox jumps over the lazy dog THE QUICK BROWN FOX";
+        let mut expected = Vec::new();
+        for _ in 0..3 {
+            expected.extend_from_slice(&line[..]);
+        }
+        decmp_test(&input, &expected[..]);
+    }
+
+    #[test]
+    fn inspect_events() {
+        use inspect::Event;
+        let stored = [0, 1, 0, 254, 255, b'a', 3, 0];
+        let events: Vec<Event> =
+            super::inspect(Cursor::new(&stored[..])).map(|e| e.unwrap()).collect();
+        assert_eq!(vec![Event::Block("stored 1 bytes".to_string()), Event::Literal(b'a'),
+                        Event::Block("fixed codes, final".to_string()), Event::End],
+                   events);
+
+        let compressed = super::compress(Cursor::new(vec![b'a'; 100]), vec![]).unwrap();
+        let events: Vec<Event> =
+            super::inspect(Cursor::new(&compressed[..])).map(|e| e.unwrap()).collect();
+        assert_eq!(vec![Event::Block("fixed codes, final".to_string()), Event::Literal(b'a'),
+                        Event::Match { offset: 1, len: 99 }, Event::End],
+                   events);
+    }
+
+    #[test]
+    fn window_too_small() {
+        let mut input = Vec::new();
+        let mut x: u32 = 1;
+        for _ in 0..4096 {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            input.push((x >> 24) as u8);
+        }
+        input.extend_from_slice(&input.clone()[..1024]);
+        let compressed = compress(Cursor::new(&input[..]), vec![]).unwrap();
+        let mut cr = Reader::with_window_bits(Cursor::new(&compressed[..]), 9);
+        let mut decompressed = Vec::new();
+        assert!(cr.read_to_end(&mut decompressed).is_err());
+    }
+
+    fn roundtrip(input: &[u8], window_bits: usize) {
+        let mut cw = Writer::with_window_bits(vec![], window_bits);
+        cw.write_all(input).unwrap();
+        cw.flush().unwrap();
+        let compressed = cw.into_inner();
+
+        let mut cr = Reader::with_window_bits(Cursor::new(&compressed[..]), window_bits);
+        let mut decompressed = Vec::new();
+        cr.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(&input[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn compress_decompress() {
+        let input = include_bytes!("deflate.rs");
+        roundtrip(input, 15);
+        roundtrip(input, 9);
+    }
+
+    #[test]
+    fn compress_decompress_large() {
+        let mut input = Vec::new();
+        let mut x: u32 = 1;
+        for i in 0..300000 {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            if i % 3 == 0 {
+                input.push((x >> 24) as u8);
+            } else {
+                input.push(b'a' + (i % 17) as u8);
+            }
+        }
+        roundtrip(&input, 15);
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+    }
+}
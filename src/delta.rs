@@ -0,0 +1,387 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Delta compression of a file against a reference file.
+//!
+//! `diff` encodes a target as a sequence of instructions which build
+//! it from a reference, in the manner of VCDIFF (RFC 3284), and
+//! `patch` applies them.  When the target is a new version of the
+//! reference, the delta is usually much smaller than the target
+//! compressed on its own.
+//!
+//! The target is processed in windows of `window_size` bytes.  For
+//! each window, a segment of the reference of at most twice that size
+//! around the same position is chosen as the source, so that copies
+//! can be found in data which has moved by up to half a window.  The
+//! window is built from three kinds of instructions:
+//!
+//! - `ADD`: a LEB128 length and that many literal bytes,
+//! - `COPY`: a LEB128 address and length; the bytes are copied from
+//!   the source segment followed by the part of the window built so
+//!   far, where the address counts from the start of the segment,
+//! - `RUN`: a LEB128 length and a byte which is repeated.
+//!
+//! Copies are found with the match finder of the LZ compressors, run
+//! over the source segment followed by the target window.
+//!
+//! The delta starts with a header: the magic bytes `CDLT`, a version
+//! byte, the window size, the length of the reference and its XXH64
+//! digest, and the length of the target and its digest.  Lengths are
+//! LEB128 numbers, digests are little-endian 64-bit numbers.  Each
+//! window starts with the position and length of its source segment.
+//! `patch` checks the reference digest, so that a delta applied to the
+//! wrong reference is detected, and the target digest.
+
+use std::cmp;
+use std::io;
+
+use model::{read_varint, write_varint};
+use window::{self, MatchFinder};
+use xxhash::xxh64;
+use error::Error;
+
+const MAGIC: [u8; 4] = *b"CDLT";
+const VERSION: u8 = 1;
+
+const ADD: u8 = 0;
+const COPY: u8 = 1;
+const RUN: u8 = 2;
+
+/// Window size used by `diff`.
+pub const DEFAULT_WINDOW_SIZE: usize = 1 << 20;
+/// Smallest window size.
+pub const MIN_WINDOW_SIZE: usize = 1 << 10;
+/// Largest window size.
+pub const MAX_WINDOW_SIZE: usize = 1 << 22;
+
+/// Shorter copies cost more than adding the bytes.
+const MIN_COPY: usize = 5;
+/// Longest copy found by the match finder at a time.
+const MAX_COPY: usize = 1 << 16;
+/// Shortest run encoded with `RUN`.
+const MIN_RUN: usize = 8;
+
+const HASH_BITS: usize = 16;
+
+/// Return a `CorruptInput` error at the position in `delta` where
+/// `rest` starts.
+fn invalid(delta: &[u8], rest: &[u8], msg: &'static str) -> io::Error {
+    Error::corrupt_at((delta.len() - rest.len()) as u64, msg).into()
+}
+
+fn truncated() -> io::Error {
+    Error::UnexpectedEof.into()
+}
+
+/// Return the position and length of the source segment for the
+/// target window starting at `pos` with `len` bytes: a piece of the
+/// reference of at most `2 * window_size` bytes, centered on the
+/// window.
+fn source_segment(ref_len: usize, pos: usize, len: usize, window_size: usize) -> (usize, usize) {
+    let seg_len = cmp::min(ref_len, 2 * window_size);
+    let center = pos + len / 2;
+    let start = cmp::min(center.saturating_sub(seg_len / 2), ref_len - seg_len);
+    (start, seg_len)
+}
+
+/// Instructions for one window.
+struct Encoder<'a> {
+    out: &'a mut Vec<u8>,
+    literals: Vec<u8>,
+}
+
+impl<'a> Encoder<'a> {
+    fn flush(&mut self) {
+        if !self.literals.is_empty() {
+            self.out.push(ADD);
+            write_varint(self.out, self.literals.len() as u64).unwrap();
+            self.out.extend_from_slice(&self.literals);
+            self.literals.clear();
+        }
+    }
+
+    fn copy(&mut self, addr: usize, len: usize) {
+        self.flush();
+        self.out.push(COPY);
+        write_varint(self.out, addr as u64).unwrap();
+        write_varint(self.out, len as u64).unwrap();
+    }
+
+    fn run(&mut self, len: usize, byte: u8) {
+        self.flush();
+        self.out.push(RUN);
+        write_varint(self.out, len as u64).unwrap();
+        self.out.push(byte);
+    }
+}
+
+/// Append the instructions building `target` from `source` to `out`.
+/// `buf` must be a power of two in size, and at least as large as
+/// `source` and `target` together.
+fn diff_window(out: &mut Vec<u8>, source: &[u8], target: &[u8], buf: &mut [u8]) {
+    let slen = source.len();
+    let n = slen + target.len();
+    buf[..slen].copy_from_slice(source);
+    buf[slen..n].copy_from_slice(target);
+    let buf = &*buf;
+    let mut finder = MatchFinder::with_level(buf.len(), HASH_BITS, window::DEFAULT_LEVEL);
+    for pos in 0..slen {
+        finder.skip(buf, pos, cmp::min(MAX_COPY, n - pos), pos);
+    }
+
+    let mut enc = Encoder {
+        out: out,
+        literals: Vec::new(),
+    };
+    let mut pos = slen;
+    while pos < n {
+        let max_len = cmp::min(MAX_COPY, n - pos);
+        let run = buf[pos..pos + max_len].iter().take_while(|&&b| b == buf[pos]).count();
+        if run >= MIN_RUN {
+            enc.run(run, buf[pos]);
+            for p in pos..pos + run {
+                finder.skip(buf, p, cmp::min(MAX_COPY, n - p), p);
+            }
+            pos += run;
+            continue;
+        }
+        let (len, dist) = finder.find(buf, pos, max_len, pos);
+        if len >= MIN_COPY {
+            enc.copy(pos - dist, len);
+            for p in pos + 1..pos + len {
+                finder.skip(buf, p, cmp::min(MAX_COPY, n - p), p);
+            }
+            pos += len;
+        } else {
+            enc.literals.push(buf[pos]);
+            pos += 1;
+        }
+    }
+    enc.flush();
+}
+
+/// Return the delta which builds `target` from `reference`, using
+/// windows of the default size.
+pub fn diff(reference: &[u8], target: &[u8]) -> Vec<u8> {
+    diff_with_window_size(reference, target, DEFAULT_WINDOW_SIZE)
+}
+
+/// Return the delta which builds `target` from `reference`, using
+/// windows of `window_size` bytes.  Larger windows find copies which
+/// have moved further, but need more memory.  Panics if
+/// `window_size` is not a power of two between `MIN_WINDOW_SIZE`
+/// and `MAX_WINDOW_SIZE`.
+pub fn diff_with_window_size(reference: &[u8], target: &[u8], window_size: usize) -> Vec<u8> {
+    assert!(window_size.is_power_of_two());
+    assert!(window_size >= MIN_WINDOW_SIZE && window_size <= MAX_WINDOW_SIZE);
+    let mut out = Vec::new();
+    out.extend_from_slice(&MAGIC);
+    out.push(VERSION);
+    write_varint(&mut out, window_size as u64).unwrap();
+    write_varint(&mut out, reference.len() as u64).unwrap();
+    out.extend_from_slice(&xxh64(reference).to_le_bytes());
+    write_varint(&mut out, target.len() as u64).unwrap();
+    out.extend_from_slice(&xxh64(target).to_le_bytes());
+
+    let mut buf = vec![0; 4 * window_size];
+    let mut pos = 0;
+    for window in target.chunks(window_size) {
+        let (start, len) = source_segment(reference.len(), pos, window.len(), window_size);
+        write_varint(&mut out, start as u64).unwrap();
+        write_varint(&mut out, len as u64).unwrap();
+        diff_window(&mut out, &reference[start..start + len], window, &mut buf);
+        pos += window.len();
+    }
+    out
+}
+
+fn read_u64(input: &mut &[u8]) -> io::Result<u64> {
+    if input.len() < 8 {
+        return Err(truncated());
+    }
+    let mut b = [0u8; 8];
+    b.copy_from_slice(&input[..8]);
+    *input = &input[8..];
+    Ok(u64::from_le_bytes(b))
+}
+
+fn read_byte(input: &mut &[u8]) -> io::Result<u8> {
+    match input.first() {
+        Some(&b) => {
+            *input = &input[1..];
+            Ok(b)
+        },
+        None => Err(truncated()),
+    }
+}
+
+/// Apply `delta`, as returned by `diff`, to `reference`, and return
+/// the target.  Fails with `InvalidInput` when `reference` is not the
+/// file the delta was made against.
+pub fn patch(reference: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    let mut input = delta;
+    if input.len() < 5 || input[..4] != MAGIC {
+        return Err(Error::InvalidHeader("not a delta").into());
+    }
+    if input[4] != VERSION {
+        return Err(Error::UnsupportedVersion(input[4] as u32).into());
+    }
+    input = &input[5..];
+    let window_size = try!(read_varint(&mut input));
+    if !window_size.is_power_of_two() || window_size < MIN_WINDOW_SIZE as u64 ||
+        window_size > MAX_WINDOW_SIZE as u64 {
+        return Err(invalid(delta, input, "invalid delta window size"));
+    }
+    let window_size = window_size as usize;
+    let ref_len = try!(read_varint(&mut input));
+    let ref_digest = try!(read_u64(&mut input));
+    if ref_len != reference.len() as u64 || ref_digest != xxh64(reference) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "reference does not match the delta"));
+    }
+    let target_len = try!(read_varint(&mut input));
+    let target_digest = try!(read_u64(&mut input));
+
+    let mut target = Vec::new();
+    let mut buf = Vec::with_capacity(3 * window_size);
+    while (target.len() as u64) < target_len {
+        let wlen = cmp::min(window_size as u64, target_len - target.len() as u64) as usize;
+        let start = try!(read_varint(&mut input));
+        let slen = try!(read_varint(&mut input));
+        if slen > 2 * window_size as u64 ||
+            start.checked_add(slen).map_or(true, |end| end > reference.len() as u64) {
+            return Err(invalid(delta, input, "invalid delta source segment"));
+        }
+        let (start, slen) = (start as usize, slen as usize);
+        buf.clear();
+        buf.extend_from_slice(&reference[start..start + slen]);
+        let end = slen + wlen;
+        while buf.len() < end {
+            let op = try!(read_byte(&mut input));
+            let addr = if op == COPY { try!(read_varint(&mut input)) } else { 0 };
+            let len = try!(read_varint(&mut input));
+            if len == 0 || len > (end - buf.len()) as u64 {
+                return Err(invalid(delta, input, "invalid delta instruction length"));
+            }
+            let len = len as usize;
+            match op {
+                ADD => {
+                    if input.len() < len {
+                        return Err(truncated());
+                    }
+                    buf.extend_from_slice(&input[..len]);
+                    input = &input[len..];
+                },
+                COPY => {
+                    if addr >= buf.len() as u64 {
+                        return Err(invalid(delta, input, "invalid delta copy address"));
+                    }
+                    // The copy may overlap the bytes it produces.
+                    for i in addr as usize..addr as usize + len {
+                        let b = buf[i];
+                        buf.push(b);
+                    }
+                },
+                RUN => {
+                    let b = try!(read_byte(&mut input));
+                    let new_len = buf.len() + len;
+                    buf.resize(new_len, b);
+                },
+                _ => return Err(invalid(delta, input, "invalid delta instruction")),
+            }
+        }
+        target.extend_from_slice(&buf[slen..]);
+    }
+    if !input.is_empty() {
+        return Err(invalid(delta, input, "trailing data after delta"));
+    }
+    let actual = xxh64(&target);
+    if actual != target_digest {
+        return Err(Error::ChecksumMismatch {
+            expected: target_digest,
+            actual: actual,
+        }.into());
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use super::{diff, diff_with_window_size, patch, MIN_WINDOW_SIZE};
+
+    fn noise(len: usize, seed: u32) -> Vec<u8> {
+        let mut x = seed;
+        (0..len).map(|_| {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            (x >> 16) as u8
+        }).collect()
+    }
+
+    fn roundtrip(reference: &[u8], target: &[u8], window_size: usize) -> usize {
+        let delta = diff_with_window_size(reference, target, window_size);
+        assert_eq!(target, &patch(reference, &delta).unwrap()[..]);
+        delta.len()
+    }
+
+    #[test]
+    fn edits() {
+        let reference = noise(100000, 1);
+        let mut target = reference[..30000].to_vec();
+        target.extend_from_slice(b"inserted text");
+        target.extend_from_slice(&reference[30000..60000]);
+        target.extend_from_slice(&reference[61000..]);
+        target.extend_from_slice(&[7; 500]);
+        let len = roundtrip(&reference, &target, super::DEFAULT_WINDOW_SIZE);
+        assert!(len < 100, "delta of {} bytes", len);
+    }
+
+    #[test]
+    fn windows() {
+        // Several windows, with the edits moving data by less than a
+        // window.
+        let reference = noise(50000, 2);
+        let mut target = noise(300, 3);
+        target.extend_from_slice(&reference[..20000]);
+        target.extend_from_slice(&reference[20100..]);
+        let len = roundtrip(&reference, &target, MIN_WINDOW_SIZE * 4);
+        assert!(len < 2000, "delta of {} bytes", len);
+    }
+
+    #[test]
+    fn empty() {
+        let data = include_bytes!("delta.rs");
+        roundtrip(b"", b"", MIN_WINDOW_SIZE);
+        roundtrip(data, b"", MIN_WINDOW_SIZE);
+        roundtrip(b"", data, MIN_WINDOW_SIZE);
+        assert!(roundtrip(data, data, MIN_WINDOW_SIZE) < 200);
+    }
+
+    #[test]
+    fn wrong_reference() {
+        let reference = include_bytes!("delta.rs");
+        let mut target = reference.to_vec();
+        target[100] = b'!';
+        let delta = diff(reference, &target);
+        let e = patch(&reference[1..], &delta).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, e.kind());
+    }
+
+    #[test]
+    fn corrupted() {
+        let reference = include_bytes!("delta.rs");
+        let mut target = reference.to_vec();
+        target.extend_from_slice(b"appended");
+        let delta = diff(reference, &target);
+        for len in 0..delta.len() {
+            assert!(patch(reference, &delta[..len]).is_err());
+        }
+        let mut bad = delta.clone();
+        let n = bad.len();
+        bad[n - 2] ^= 1;
+        assert!(patch(reference, &bad).is_err());
+        bad = delta.clone();
+        bad.push(0);
+        assert!(patch(reference, &bad).is_err());
+    }
+}
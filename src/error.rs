@@ -1,34 +1,255 @@
-use std::io;
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Errors of the codecs.
+//!
+//! The readers and writers implement `io::Read` and `io::Write` and
+//! therefore report `io::Error`s.  Errors which describe bad input are
+//! created as `Error`s and converted into `io::Error`s, which carry
+//! the original `Error` along when the standard library is available.
+//! Converting such an `io::Error` back into an `Error`, as the `?`
+//! operator and `try!` do in the `compress` and `decompress`
+//! functions, recovers it, so that callers can match on the variants
+//! below.  Any other `io::Error` of kind `UnexpectedEof`, like the one
+//! `read_exact` returns for truncated input, becomes
+//! `Error::UnexpectedEof`.
+
+#[cfg(feature = "std")]
 use std::error;
-use std::fmt;
+use core::fmt;
+
+use io;
 
 #[derive(Debug)]
 pub enum Error {
+    /// The input ended in the middle of a stream.
     UnexpectedEof,
+    /// The compressed data is invalid.  `offset` is the position in
+    /// the compressed stream at which the problem was found, if the
+    /// decoder knows it.
+    CorruptInput { offset: Option<u64>, detail: &'static str },
+    /// The input does not start with the expected header.
+    InvalidHeader(&'static str),
+    /// The header has a format version this build cannot read.
+    UnsupportedVersion(u32),
+    /// A size or count in the input exceeds what the format or the
+    /// decoder allows.
+    LimitExceeded(&'static str),
+    /// The checksum stored in the input does not match the data.
+    ChecksumMismatch { expected: u64, actual: u64 },
     Io(io::Error),
 }
 
+impl Error {
+    /// Create a `CorruptInput` error without an offset.
+    pub fn corrupt(detail: &'static str) -> Error {
+        Error::CorruptInput {
+            offset: None,
+            detail: detail,
+        }
+    }
+
+    /// Create a `CorruptInput` error found at `offset`.
+    pub fn corrupt_at(offset: u64, detail: &'static str) -> Error {
+        Error::CorruptInput {
+            offset: Some(offset),
+            detail: detail,
+        }
+    }
+
+    /// Return the error with `base` added to the offset of a
+    /// `CorruptInput` error, for errors found in a part of the input
+    /// which starts at offset `base`.  A `CorruptInput` error without
+    /// an offset is placed at `base`.
+    pub fn relocate(self, base: u64) -> Error {
+        match self {
+            Error::CorruptInput { offset, detail } => Error::CorruptInput {
+                offset: Some(base + offset.unwrap_or(0)),
+                detail: detail,
+            },
+            err => err,
+        }
+    }
+
+    /// Return the kind of `io::Error` this error is reported as.
+    pub fn kind(&self) -> io::ErrorKind {
+        match *self {
+            Error::UnexpectedEof => io::ErrorKind::UnexpectedEof,
+            Error::CorruptInput { .. } |
+            Error::InvalidHeader(_) |
+            Error::UnsupportedVersion(_) |
+            Error::LimitExceeded(_) |
+            Error::ChecksumMismatch { .. } => io::ErrorKind::InvalidData,
+            Error::Io(ref err) => err.kind(),
+        }
+    }
+
+    /// Return a short description without the details.
+    fn summary(&self) -> &'static str {
+        match *self {
+            Error::UnexpectedEof => "unexpected end of file",
+            Error::CorruptInput { detail, .. } => detail,
+            Error::InvalidHeader(detail) => detail,
+            Error::UnsupportedVersion(_) => "unsupported format version",
+            Error::LimitExceeded(detail) => detail,
+            Error::ChecksumMismatch { .. } => "checksum mismatch",
+            Error::Io(_) => "I/O error",
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::UnexpectedEof => write!(f, "unexpected end of file"),
+            Error::CorruptInput { offset: Some(offset), detail } =>
+                write!(f, "corrupt input at byte {}: {}", offset, detail),
+            Error::CorruptInput { offset: None, detail } =>
+                write!(f, "corrupt input: {}", detail),
+            Error::InvalidHeader(detail) => write!(f, "invalid header: {}", detail),
+            Error::UnsupportedVersion(version) =>
+                write!(f, "unsupported format version {}", version),
+            Error::LimitExceeded(detail) => write!(f, "limit exceeded: {}", detail),
+            Error::ChecksumMismatch { expected, actual } =>
+                write!(f, "checksum mismatch: expected {:#x}, got {:#x}", expected, actual),
             Error::Io(ref err) => err.fmt(f),
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
-            Error::UnexpectedEof => "unexpected end of file",
             Error::Io(ref err) => err.description(),
+            _ => self.summary(),
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        if err.get_ref().map_or(false, |e| e.is::<Error>()) {
+            match err.into_inner().unwrap().downcast::<Error>() {
+                Ok(e) => *e,
+                Err(_) => unreachable!(),
+            }
+        } else if err.kind() == io::ErrorKind::UnexpectedEof {
+            Error::UnexpectedEof
+        } else {
+            Error::Io(err)
         }
     }
 }
 
+#[cfg(not(feature = "std"))]
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error {
-        Error::Io(err)
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            Error::UnexpectedEof
+        } else {
+            Error::Io(err)
+        }
+    }
+}
+
+/// Fill in `offset` if `err` carries a `CorruptInput` error without
+/// an offset.  The readers call this with their position in the
+/// compressed stream when a decoding step fails.
+#[cfg(feature = "std")]
+pub(crate) fn at_offset(err: io::Error, offset: u64) -> io::Error {
+    let missing = match err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+        Some(&Error::CorruptInput { offset: None, .. }) => true,
+        _ => false,
+    };
+    if missing {
+        Error::from(err).relocate(offset).into()
+    } else {
+        err
+    }
+}
+
+/// Without the standard library, `io::Error` cannot carry an `Error`,
+/// so there is no offset to fill in.
+#[cfg(not(feature = "std"))]
+pub(crate) fn at_offset(err: io::Error, _offset: u64) -> io::Error {
+    err
+}
+
+/// Relocate a `CorruptInput` error carried by `err`, see
+/// `Error::relocate`.  This is for readers which decode a part of
+/// their input with another reader.
+#[cfg(feature = "std")]
+pub(crate) fn relocated(err: io::Error, base: u64) -> io::Error {
+    if err.get_ref().map_or(false, |e| e.is::<Error>()) {
+        Error::from(err).relocate(base).into()
+    } else {
+        err
+    }
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn relocated(err: io::Error, _base: u64) -> io::Error {
+    err
+}
+
+#[cfg(feature = "std")]
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        match err {
+            Error::Io(err) => err,
+            err => io::Error::new(err.kind(), err),
+        }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        match err {
+            Error::Io(err) => err,
+            err => io::Error::new(err.kind(), err.summary()),
+        }
     }
 }
 
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::error::Error as StdError;
+    use std::io;
+    use super::{at_offset, Error};
+
+    #[test]
+    fn round_trip() {
+        let err: io::Error = Error::corrupt_at(17, "bad match").into();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!("corrupt input at byte 17: bad match", err.to_string());
+        match Error::from(err) {
+            Error::CorruptInput { offset: Some(17), detail: "bad match" } => (),
+            e => panic!("unexpected error {:?}", e),
+        }
+
+        let err: io::Error = Error::UnexpectedEof.into();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+
+        let err = at_offset(Error::corrupt("bad code").into(), 5);
+        let err = at_offset(err, 9);
+        assert_eq!("corrupt input at byte 5: bad code", err.to_string());
+        match Error::from(err).relocate(100) {
+            Error::CorruptInput { offset: Some(105), .. } => (),
+            e => panic!("unexpected error {:?}", e),
+        }
+
+        let err = Error::from(io::Error::new(io::ErrorKind::Other, "disk full"));
+        assert!(err.source().is_some());
+        assert!(Error::UnsupportedVersion(3).source().is_none());
+    }
+}
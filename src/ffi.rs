@@ -0,0 +1,405 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! C interface.
+//!
+//! The functions in this module are exported unmangled, so that the
+//! codecs can be called from C, C++ or Python's ctypes when the crate
+//! is built as a C library, for example with `cargo rustc --release
+//! --lib --crate-type cdylib`.  The declarations are in
+//! `include/campross.h`, which can be regenerated with cbindgen using
+//! `cbindgen.toml`.
+//!
+//! Methods are given by their id, see `Method::id`, which can be
+//! looked up with `campross_method_id`.  Functions returning `isize`
+//! return a length or zero on success and one of the negative
+//! `CAMPROSS_ERR_*` codes on failure.
+//!
+//! `campross_compress` and `campross_decompress` convert a whole
+//! buffer with one method.  A stream, created with
+//! `campross_stream_compress` or `campross_stream_decompress`,
+//! converts data given in pieces: the input is passed to
+//! `campross_stream_update`, the end of the input is signalled with
+//! `campross_stream_finish`, and the output is taken out with
+//! `campross_stream_read` whenever convenient.  Streams are built on
+//! `buffer::Compressor` and `buffer::Decompressor`.
+//!
+//! Panics inside the codecs do not cross the interface, they are
+//! reported as `CAMPROSS_ERR_INTERNAL`.
+
+use std::cmp;
+use std::ffi::CStr;
+use std::io;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use buffer::{Compressor, Decompressor};
+use error::Error;
+use method::Method;
+
+/// The method is unknown or not available in this build.
+pub const CAMPROSS_ERR_METHOD: isize = -1;
+/// A pointer argument is null, or a stream is used after an error or
+/// after it was finished.
+pub const CAMPROSS_ERR_ARGUMENT: isize = -2;
+/// The output buffer is too small.
+pub const CAMPROSS_ERR_BUFFER: isize = -3;
+/// The compressed input is invalid or truncated.
+pub const CAMPROSS_ERR_DATA: isize = -4;
+/// Internal error in a codec.
+pub const CAMPROSS_ERR_INTERNAL: isize = -5;
+
+fn method(id: c_int) -> Option<Method> {
+    if id < 0 || id > 255 {
+        return None;
+    }
+    Method::from_id(id as u8).and_then(|m| if m.is_available() { Some(m) } else { None })
+}
+
+fn error_code(err: &Error) -> isize {
+    match err.kind() {
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => CAMPROSS_ERR_DATA,
+        _ => CAMPROSS_ERR_INTERNAL,
+    }
+}
+
+/// Run `f`, turning a panic into `CAMPROSS_ERR_INTERNAL`.
+fn guard<F: FnOnce() -> isize>(f: F) -> isize {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(CAMPROSS_ERR_INTERNAL)
+}
+
+/// Return the input buffer given by `ptr` and `len`.  A null pointer
+/// is allowed for an empty buffer.
+unsafe fn input<'a>(ptr: *const u8, len: usize) -> Option<&'a [u8]> {
+    if len == 0 {
+        Some(&[])
+    } else if ptr.is_null() {
+        None
+    } else {
+        Some(slice::from_raw_parts(ptr, len))
+    }
+}
+
+/// Copy `data` to the buffer given by `ptr` and `cap` and return its
+/// length.  With a null `ptr`, only the length is returned.
+unsafe fn output(data: &[u8], ptr: *mut u8, cap: usize) -> isize {
+    if ptr.is_null() {
+        return data.len() as isize;
+    }
+    if data.len() > cap {
+        return CAMPROSS_ERR_BUFFER;
+    }
+    ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len());
+    data.len() as isize
+}
+
+/// Return the id of the method called `name`, or
+/// `CAMPROSS_ERR_METHOD` if there is no such method in this build.
+#[no_mangle]
+pub unsafe extern "C" fn campross_method_id(name: *const c_char) -> c_int {
+    if name.is_null() {
+        return CAMPROSS_ERR_ARGUMENT as c_int;
+    }
+    let name = match CStr::from_ptr(name).to_str() {
+        Ok(s) => s,
+        Err(_) => return CAMPROSS_ERR_METHOD as c_int,
+    };
+    match Method::from_name(name) {
+        Some(m) if m.is_available() => m.id() as c_int,
+        _ => CAMPROSS_ERR_METHOD as c_int,
+    }
+}
+
+unsafe fn convert(id: c_int, in_ptr: *const u8, in_len: usize, out_ptr: *mut u8,
+                  out_cap: usize, compress: bool) -> isize {
+    let m = match method(id) {
+        Some(m) => m,
+        None => return CAMPROSS_ERR_METHOD,
+    };
+    let data = match input(in_ptr, in_len) {
+        Some(d) => d,
+        None => return CAMPROSS_ERR_ARGUMENT,
+    };
+    guard(|| {
+        let result = if compress {
+            m.compress(data, Vec::new())
+        } else {
+            m.decompress(data, Vec::new())
+        };
+        match result {
+            Ok(out) => output(&out, out_ptr, out_cap),
+            Err(ref e) => error_code(e),
+        }
+    })
+}
+
+/// Compress the `in_len` bytes at `in_ptr` with the method `method`
+/// into the buffer of `out_cap` bytes at `out_ptr`, and return the
+/// compressed length.  When `out_ptr` is null, only the compressed
+/// length is returned.
+#[no_mangle]
+pub unsafe extern "C" fn campross_compress(method: c_int, in_ptr: *const u8, in_len: usize,
+                                           out_ptr: *mut u8, out_cap: usize) -> isize {
+    convert(method, in_ptr, in_len, out_ptr, out_cap, true)
+}
+
+/// Decompress the `in_len` bytes at `in_ptr`, which were compressed
+/// with the method `method`, into the buffer of `out_cap` bytes at
+/// `out_ptr`, and return the decompressed length.  When `out_ptr` is
+/// null, only the decompressed length is returned.
+#[no_mangle]
+pub unsafe extern "C" fn campross_decompress(method: c_int, in_ptr: *const u8, in_len: usize,
+                                             out_ptr: *mut u8, out_cap: usize) -> isize {
+    convert(method, in_ptr, in_len, out_ptr, out_cap, false)
+}
+
+enum State {
+    Compress(Compressor),
+    Decompress(Decompressor),
+    /// After an error or `finish`, only the output can be read.
+    Closed,
+}
+
+/// Streaming compressor or decompressor.
+pub struct Stream {
+    state: State,
+    output: Vec<u8>,
+    returned: usize,
+}
+
+impl Stream {
+    fn update(&mut self, data: &[u8]) -> Result<(), Error> {
+        let out = match self.state {
+            State::Compress(ref mut c) => try!(c.push(data)),
+            State::Decompress(ref mut d) => try!(d.push(data)),
+            State::Closed => unreachable!(),
+        };
+        self.output.extend_from_slice(&out);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        match ::std::mem::replace(&mut self.state, State::Closed) {
+            State::Compress(c) => {
+                let out = try!(c.finish());
+                self.output.extend_from_slice(&out);
+                Ok(())
+            },
+            State::Decompress(d) => d.finish(),
+            State::Closed => unreachable!(),
+        }
+    }
+}
+
+fn new_stream(state: State) -> *mut Stream {
+    Box::into_raw(Box::new(Stream {
+        state: state,
+        output: Vec::new(),
+        returned: 0,
+    }))
+}
+
+/// Create a stream which compresses with the method `method`, or
+/// return null if the method is not available.  The stream must be
+/// released with `campross_stream_free`.
+#[no_mangle]
+pub extern "C" fn campross_stream_compress(method: c_int) -> *mut Stream {
+    match self::method(method) {
+        Some(m) => new_stream(State::Compress(Compressor::new(m))),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Create a stream which decompresses a container written by a
+/// compressing stream.  The stream must be released with
+/// `campross_stream_free`.
+#[no_mangle]
+pub extern "C" fn campross_stream_decompress() -> *mut Stream {
+    new_stream(State::Decompress(Decompressor::new()))
+}
+
+/// Run `f` on the stream, and put the stream into the failed state
+/// when `f` fails.
+unsafe fn with_stream<F>(stream: *mut Stream, f: F) -> isize
+    where F: FnOnce(&mut Stream) -> Result<(), Error>
+{
+    if stream.is_null() {
+        return CAMPROSS_ERR_ARGUMENT;
+    }
+    let stream = &mut *stream;
+    if let State::Closed = stream.state {
+        return CAMPROSS_ERR_ARGUMENT;
+    }
+    let code = guard(|| match f(stream) {
+        Ok(()) => 0,
+        Err(ref e) => error_code(e),
+    });
+    if code < 0 {
+        stream.state = State::Closed;
+    }
+    code
+}
+
+/// Pass the `in_len` bytes at `in_ptr` to the stream.  Returns zero
+/// or an error code.  After an error, only the output produced before
+/// can be read.
+#[no_mangle]
+pub unsafe extern "C" fn campross_stream_update(stream: *mut Stream, in_ptr: *const u8,
+                                                in_len: usize) -> isize {
+    let data = match input(in_ptr, in_len) {
+        Some(d) => d,
+        None => return CAMPROSS_ERR_ARGUMENT,
+    };
+    with_stream(stream, |s| s.update(data))
+}
+
+/// Signal the end of the input.  A compressing stream writes its last
+/// block and the end of the container; a decompressing stream checks
+/// that the container was complete.  Returns zero or an error code.
+#[no_mangle]
+pub unsafe extern "C" fn campross_stream_finish(stream: *mut Stream) -> isize {
+    with_stream(stream, |s| s.finish())
+}
+
+/// Move up to `out_cap` bytes of output from the stream to `out_ptr`,
+/// and return their number.  Output is produced by
+/// `campross_stream_update` and `campross_stream_finish`, and can be
+/// taken out at any time.  With a null `out_ptr`, the number of bytes
+/// waiting is returned.
+#[no_mangle]
+pub unsafe extern "C" fn campross_stream_read(stream: *mut Stream, out_ptr: *mut u8,
+                                              out_cap: usize) -> isize {
+    if stream.is_null() {
+        return CAMPROSS_ERR_ARGUMENT;
+    }
+    let stream = &mut *stream;
+    let waiting = stream.output.len() - stream.returned;
+    if out_ptr.is_null() {
+        return waiting as isize;
+    }
+    let n = cmp::min(waiting, out_cap);
+    ptr::copy_nonoverlapping(stream.output[stream.returned..].as_ptr(), out_ptr, n);
+    stream.returned += n;
+    if stream.returned == stream.output.len() {
+        stream.output.clear();
+        stream.returned = 0;
+    }
+    n as isize
+}
+
+/// Release a stream.  Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn campross_stream_free(stream: *mut Stream) {
+    if !stream.is_null() {
+        drop(Box::from_raw(stream));
+    }
+}
+
+#[cfg(all(test, feature = "lz"))]
+mod tests {
+    use std::ptr;
+    use method::Method;
+    use super::{campross_compress, campross_decompress, campross_method_id,
+                campross_stream_compress, campross_stream_decompress, campross_stream_finish,
+                campross_stream_free, campross_stream_read, campross_stream_update, Stream,
+                CAMPROSS_ERR_ARGUMENT, CAMPROSS_ERR_BUFFER, CAMPROSS_ERR_DATA,
+                CAMPROSS_ERR_METHOD};
+
+    fn read_all(s: *mut Stream, out: &mut Vec<u8>) {
+        let mut buf = [0u8; 100];
+        loop {
+            let n = unsafe { campross_stream_read(s, buf.as_mut_ptr(), buf.len()) };
+            assert!(n >= 0);
+            if n == 0 {
+                return;
+            }
+            out.extend_from_slice(&buf[..n as usize]);
+        }
+    }
+
+    #[test]
+    fn method_id() {
+        let id = unsafe { campross_method_id(b"lzss\0".as_ptr() as *const _) };
+        assert_eq!(Method::Lzss.id() as i32, id);
+        let id = unsafe { campross_method_id(b"lzmg1\0".as_ptr() as *const _) };
+        assert_eq!(CAMPROSS_ERR_METHOD as i32, id);
+    }
+
+    #[test]
+    fn buffers() {
+        let input = include_bytes!("ffi.rs");
+        let m = Method::Lzss.id() as i32;
+        unsafe {
+            let len = campross_compress(m, input.as_ptr(), input.len(), ptr::null_mut(), 0);
+            assert!(len > 0 && (len as usize) < input.len());
+            let mut compressed = vec![0; len as usize];
+            assert_eq!(CAMPROSS_ERR_BUFFER,
+                       campross_compress(m, input.as_ptr(), input.len(),
+                                         compressed.as_mut_ptr(), compressed.len() - 1));
+            assert_eq!(len, campross_compress(m, input.as_ptr(), input.len(),
+                                              compressed.as_mut_ptr(), compressed.len()));
+            let mut decompressed = vec![0; input.len()];
+            assert_eq!(input.len() as isize,
+                       campross_decompress(m, compressed.as_ptr(), compressed.len(),
+                                           decompressed.as_mut_ptr(), decompressed.len()));
+            assert_eq!(&input[..], &decompressed[..]);
+            assert_eq!(CAMPROSS_ERR_METHOD,
+                       campross_compress(0, input.as_ptr(), input.len(), ptr::null_mut(), 0));
+            assert_eq!(CAMPROSS_ERR_ARGUMENT,
+                       campross_compress(m, ptr::null(), 10, ptr::null_mut(), 0));
+        }
+    }
+
+    #[test]
+    fn streams() {
+        let input = include_bytes!("ffi.rs");
+        let mut compressed = Vec::new();
+        unsafe {
+            let s = campross_stream_compress(Method::Lzss.id() as i32);
+            for piece in input.chunks(1000) {
+                assert_eq!(0, campross_stream_update(s, piece.as_ptr(), piece.len()));
+                read_all(s, &mut compressed);
+            }
+            assert_eq!(0, campross_stream_finish(s));
+            read_all(s, &mut compressed);
+            campross_stream_free(s);
+        }
+        assert!(compressed.len() < input.len());
+
+        // Feed the container in small pieces, so that blocks and the
+        // header are split.
+        let mut decompressed = Vec::new();
+        unsafe {
+            let s = campross_stream_decompress();
+            for piece in compressed.chunks(3) {
+                assert_eq!(0, campross_stream_update(s, piece.as_ptr(), piece.len()));
+                read_all(s, &mut decompressed);
+            }
+            assert_eq!(0, campross_stream_finish(s));
+            read_all(s, &mut decompressed);
+            campross_stream_free(s);
+        }
+        assert_eq!(&input[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn bad_streams() {
+        let input = include_bytes!("ffi.rs");
+        let compressed = ::container::compress(&input[..], vec![], Method::Lzss).unwrap();
+        unsafe {
+            let s = campross_stream_decompress();
+            let half = compressed.len() / 2;
+            assert_eq!(0, campross_stream_update(s, compressed.as_ptr(), half));
+            assert_eq!(CAMPROSS_ERR_DATA, campross_stream_finish(s));
+            assert_eq!(CAMPROSS_ERR_ARGUMENT, campross_stream_update(s, compressed.as_ptr(), 1));
+            campross_stream_free(s);
+
+            let s = campross_stream_decompress();
+            assert_eq!(CAMPROSS_ERR_DATA, campross_stream_update(s, b"CAMQ\x01\x07\x80".as_ptr(), 7));
+            campross_stream_free(s);
+            assert!(campross_stream_compress(0).is_null());
+        }
+    }
+}
@@ -0,0 +1,253 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Long-range deduplication.
+//!
+//! The LZ coders in this crate only find repetitions within a window
+//! of at most a few megabytes.  This filter finds repeated chunks
+//! anywhere in the data and replaces them by references to the
+//! earlier copy, so that the coder which runs afterwards only sees
+//! the first copy.  Archives and disk images, which contain the same
+//! files far apart, benefit most.
+//!
+//! The data is split into chunks at positions determined by the
+//! content (content-defined chunking with a Gear rolling hash), so
+//! that an insertion only changes the chunks near it, and repeated
+//! content yields the same chunks wherever it occurs.  Chunks are
+//! between `MIN_CHUNK` and `MAX_CHUNK` bytes long, 8 KiB on average.
+//!
+//! The output is a sequence of records, each starting with a type
+//! byte: `LITERAL` is followed by the length as a LEB128 number and
+//! the bytes, `COPY` by the position of the earlier copy and the
+//! length, both as LEB128 numbers.
+
+use std::collections::HashMap;
+use std::io;
+
+use model::{read_varint, write_varint};
+use xxhash::xxh64;
+use error::Error;
+
+/// Smallest chunk, except for the last one.
+pub const MIN_CHUNK: usize = 2 * 1024;
+/// Largest chunk.
+pub const MAX_CHUNK: usize = 64 * 1024;
+/// A chunk boundary is placed where the hash has these bits clear,
+/// giving chunks of about 8 KiB.
+const CHUNK_MASK: u64 = (1 << 13) - 1;
+
+const LITERAL: u8 = 0;
+const COPY: u8 = 1;
+
+/// Build the table of random values for the Gear hash, using an
+/// xorshift generator with a fixed seed.
+const fn gear_table() -> [u64; 256] {
+    let mut t = [0; 256];
+    let mut x: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut i = 0;
+    while i < 256 {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        t[i] = x;
+        i += 1;
+    }
+    t
+}
+
+static GEAR: [u64; 256] = gear_table();
+
+/// Return the length of the chunk starting at the beginning of
+/// `data`.
+fn chunk_len(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK {
+        return data.len();
+    }
+    let end = if data.len() < MAX_CHUNK { data.len() } else { MAX_CHUNK };
+    let mut h: u64 = 0;
+    for i in MIN_CHUNK..end {
+        // Bytes shift out of the hash after 64 steps, so the hash
+        // only depends on a window of the last 64 bytes.
+        h = (h << 1).wrapping_add(GEAR[data[i] as usize]);
+        if h & CHUNK_MASK == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// Output of the encoder: records are appended as chunks are
+/// classified, with adjacent literals and contiguous copies merged.
+struct Records {
+    out: Vec<u8>,
+    literal: Vec<u8>,
+    copy: Option<(usize, usize)>,
+}
+
+impl Records {
+    fn flush(&mut self) {
+        if !self.literal.is_empty() {
+            self.out.push(LITERAL);
+            write_varint(&mut self.out, self.literal.len() as u64).unwrap();
+            self.out.extend_from_slice(&self.literal);
+            self.literal.clear();
+        }
+        if let Some((pos, len)) = self.copy.take() {
+            self.out.push(COPY);
+            write_varint(&mut self.out, pos as u64).unwrap();
+            write_varint(&mut self.out, len as u64).unwrap();
+        }
+    }
+
+    fn literal(&mut self, data: &[u8]) {
+        if self.copy.is_some() {
+            self.flush();
+        }
+        self.literal.extend_from_slice(data);
+    }
+
+    fn copy(&mut self, pos: usize, len: usize) {
+        match self.copy {
+            Some((p, ref mut l)) if p + *l == pos => {
+                *l += len;
+                return;
+            },
+            _ => (),
+        }
+        self.flush();
+        self.copy = Some((pos, len));
+    }
+}
+
+/// Replace repeated chunks in `data` by references.
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut seen: HashMap<u64, usize> = HashMap::new();
+    let mut records = Records {
+        out: Vec::with_capacity(data.len() / 2),
+        literal: Vec::new(),
+        copy: None,
+    };
+    let mut pos = 0;
+    while pos < data.len() {
+        let len = chunk_len(&data[pos..]);
+        let chunk = &data[pos..pos + len];
+        let h = xxh64(chunk);
+        match seen.get(&h) {
+            Some(&prev) if data[prev..prev + len] == *chunk && len >= MIN_CHUNK =>
+                records.copy(prev, len),
+            Some(_) => records.literal(chunk),
+            None => {
+                seen.insert(h, pos);
+                records.literal(chunk);
+            },
+        }
+        pos += len;
+    }
+    records.flush();
+    records.out
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    Error::corrupt(msg).into()
+}
+
+/// Reverse `encode`, resolving the references.
+pub fn decode(mut input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() * 2);
+    while !input.is_empty() {
+        let kind = input[0];
+        input = &input[1..];
+        match kind {
+            LITERAL => {
+                let len = try!(read_varint(&mut input));
+                if len > input.len() as u64 {
+                    return Err(Error::UnexpectedEof.into());
+                }
+                out.extend_from_slice(&input[..len as usize]);
+                input = &input[len as usize..];
+            },
+            COPY => {
+                let pos = try!(read_varint(&mut input));
+                let len = try!(read_varint(&mut input));
+                if pos.checked_add(len).map_or(true, |end| end > out.len() as u64) {
+                    return Err(invalid("invalid dedup reference"));
+                }
+                let (pos, len) = (pos as usize, len as usize);
+                // The source may overlap the end of the output.
+                for i in pos..pos + len {
+                    let b = out[i];
+                    out.push(b);
+                }
+            },
+            _ => return Err(invalid("invalid dedup record")),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "lz")]
+    use std::io::Cursor;
+    #[cfg(feature = "lz")]
+    use lzss;
+    use super::{decode, encode, MAX_CHUNK, MIN_CHUNK};
+
+    fn noise(len: usize, seed: u32) -> Vec<u8> {
+        let mut x = seed;
+        (0..len).map(|_| {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            (x >> 16) as u8
+        }).collect()
+    }
+
+    #[test]
+    fn chunk_lengths() {
+        let data = noise(1 << 20, 1);
+        let mut pos = 0;
+        let mut count = 0;
+        while pos < data.len() {
+            let len = super::chunk_len(&data[pos..]);
+            assert!(len >= MIN_CHUNK && len <= MAX_CHUNK || pos + len == data.len());
+            pos += len;
+            count += 1;
+        }
+        // About 8 KiB on average, plus the minimum size.
+        assert!(count > 60 && count < 200, "{} chunks", count);
+    }
+
+    #[cfg(feature = "lz")]
+    #[test]
+    fn far_repeats() {
+        let a = noise(300000, 1);
+        let b = noise(500000, 2);
+        let mut data = a.clone();
+        data.extend_from_slice(&b);
+        data.extend_from_slice(b"some bytes in between");
+        data.extend_from_slice(&a);
+        let encoded = encode(&data);
+        // The second copy of `a` is all references, apart from the
+        // chunks around its start and end.
+        assert!(encoded.len() < a.len() + b.len() + 3 * MAX_CHUNK);
+        assert_eq!(data, decode(&encoded).unwrap());
+        // LZSS alone cannot see repetitions this far apart.
+        let plain = lzss::compress(Cursor::new(&data[..]), vec![]).unwrap();
+        assert!(plain.len() > data.len());
+    }
+
+    #[test]
+    fn roundtrip() {
+        for data in vec![vec![], b"short".to_vec(), vec![0; 1000000],
+                         include_bytes!("dedup.rs").to_vec()] {
+            assert_eq!(data, decode(&encode(&data)).unwrap());
+        }
+    }
+
+    #[test]
+    fn bad_input() {
+        assert!(decode(&[0, 5, 1, 2]).is_err());
+        assert!(decode(&[0, 2, 1, 2, 1, 1, 2]).is_err());
+        assert!(decode(&[0, 2, 1, 2, 1, 0, 2]).is_ok());
+        assert!(decode(&[7]).is_err());
+    }
+}
@@ -0,0 +1,348 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Delta and XOR filters.
+//!
+//! These filters replace each byte by its difference (or XOR) to an
+//! earlier byte, which turns slowly changing sampled data, such as
+//! audio or tables of integers, into runs of small values that
+//! compress much better.  A stride of 2, 3 or 4 treats the data as
+//! 16, 24 or 32-bit records and takes the difference between the
+//! corresponding bytes of consecutive records.
+//!
+//! The `Writer` starts its output with a two-byte header recording
+//! the filter and stride, so that the `Reader` can reverse the
+//! filter without being told which one was used.
+
+use std::io::{Read, Write};
+use std::io;
+
+use error::Error;
+
+const MAX_STRIDE: usize = 255;
+const HISTORY_SIZE: usize = 256;
+
+const KIND_DELTA: u8 = 1;
+const KIND_DELTA2: u8 = 2;
+const KIND_XOR: u8 = 3;
+
+/// Selects a filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// Difference to the byte `stride` positions back.  A stride of
+    /// 1 is an order-1 delta.
+    Delta(usize),
+    /// Order-2 delta: the difference to the linear prediction from
+    /// the two previous bytes.
+    Delta2,
+    /// XOR with the byte `stride` positions back.
+    Xor(usize),
+}
+
+impl Filter {
+    pub(crate) fn header(&self) -> [u8; 2] {
+        match *self {
+            Filter::Delta(stride) => [KIND_DELTA, stride as u8],
+            Filter::Delta2 => [KIND_DELTA2, 1],
+            Filter::Xor(stride) => [KIND_XOR, stride as u8],
+        }
+    }
+
+    pub(crate) fn from_header(hdr: [u8; 2]) -> io::Result<Filter> {
+        let stride = hdr[1] as usize;
+        let filter = match hdr[0] {
+            KIND_DELTA => Filter::Delta(stride),
+            KIND_DELTA2 => Filter::Delta2,
+            KIND_XOR => Filter::Xor(stride),
+            _ => return Err(Error::InvalidHeader("unknown filter").into()),
+        };
+        try!(filter.check());
+        Ok(filter)
+    }
+
+    pub(crate) fn check(&self) -> io::Result<()> {
+        match *self {
+            Filter::Delta(stride) | Filter::Xor(stride) if stride < 1 || stride > MAX_STRIDE => {
+                Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                   "filter stride must be between 1 and 255"))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Filter state: the filter and the most recent unfiltered bytes.
+struct State {
+    filter: Filter,
+    history: [u8; HISTORY_SIZE],
+    pos: usize,
+}
+
+impl State {
+    fn new(filter: Filter) -> State {
+        State {
+            filter: filter,
+            history: [0; HISTORY_SIZE],
+            pos: 0,
+        }
+    }
+
+    #[inline(always)]
+    fn back(&self, n: usize) -> u8 {
+        self.history[(self.pos + HISTORY_SIZE - n) % HISTORY_SIZE]
+    }
+
+    #[inline(always)]
+    fn push(&mut self, b: u8) {
+        self.history[self.pos] = b;
+        self.pos = (self.pos + 1) % HISTORY_SIZE;
+    }
+
+    fn encode(&mut self, data: &mut [u8]) {
+        for d in data.iter_mut() {
+            let b = *d;
+            *d = match self.filter {
+                Filter::Delta(stride) => b.wrapping_sub(self.back(stride)),
+                Filter::Delta2 => {
+                    let pred = self.back(1).wrapping_mul(2).wrapping_sub(self.back(2));
+                    b.wrapping_sub(pred)
+                },
+                Filter::Xor(stride) => b ^ self.back(stride),
+            };
+            self.push(b);
+        }
+    }
+
+    fn decode(&mut self, data: &mut [u8]) {
+        for d in data.iter_mut() {
+            let b = match self.filter {
+                Filter::Delta(stride) => d.wrapping_add(self.back(stride)),
+                Filter::Delta2 => {
+                    let pred = self.back(1).wrapping_mul(2).wrapping_sub(self.back(2));
+                    d.wrapping_add(pred)
+                },
+                Filter::Xor(stride) => *d ^ self.back(stride),
+            };
+            *d = b;
+            self.push(b);
+        }
+    }
+}
+
+/// Apply `filter` to `data` in place.  No header is added.
+pub fn encode(filter: Filter, data: &mut [u8]) {
+    filter.check().unwrap();
+    State::new(filter).encode(data)
+}
+
+/// Reverse `filter` on `data` in place.
+pub fn decode(filter: Filter, data: &mut [u8]) {
+    filter.check().unwrap();
+    State::new(filter).decode(data)
+}
+
+/// Writer which filters all data written to it and passes the result
+/// on, preceded by a header naming the filter.
+pub struct Writer<W> {
+    inner: W,
+    state: State,
+    buf: Vec<u8>,
+    header_written: bool,
+    total: u64,
+}
+
+impl<W: Write> Writer<W> {
+    /// Create a new filtering writer.  Panics if the stride of the
+    /// filter is not between 1 and 255.
+    pub fn new(inner: W, filter: Filter) -> Writer<W> {
+        filter.check().unwrap();
+        Writer {
+            inner: inner,
+            state: State::new(filter),
+            buf: Vec::new(),
+            header_written: false,
+            total: 0,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            try!(self.inner.write_all(&self.state.filter.header()));
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    /// Return the number of bytes written to the filter so far.
+    pub fn total_in(&self) -> u64 {
+        self.total
+    }
+
+    /// Return the number of bytes written to the wrapped writer so
+    /// far, including the header.
+    pub fn total_out(&self) -> u64 {
+        if self.header_written { 2 + self.total } else { 0 }
+    }
+
+    /// Move the wrapped writer out of the filter.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        try!(self.write_header());
+        self.buf.clear();
+        self.buf.extend_from_slice(buf);
+        self.state.encode(&mut self.buf);
+        try!(self.inner.write_all(&self.buf));
+        self.total += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.write_header());
+        self.inner.flush()
+    }
+}
+
+/// Reader which reverses the filter named in the header of its input.
+pub struct Reader<R> {
+    inner: R,
+    state: State,
+    total: u64,
+}
+
+impl<R: Read> Reader<R> {
+    /// Create a new reader.  This reads the filter header, therefore
+    /// the result can be an error.
+    pub fn new(mut inner: R) -> io::Result<Reader<R>> {
+        let mut hdr = [0u8; 2];
+        try!(inner.read_exact(&mut hdr));
+        let filter = try!(Filter::from_header(hdr));
+        Ok(Reader {
+            inner: inner,
+            state: State::new(filter),
+            total: 0,
+        })
+    }
+
+    /// Return the filter named in the header.
+    pub fn filter(&self) -> Filter {
+        self.state.filter
+    }
+
+    /// Return the number of bytes read from the wrapped reader so
+    /// far, including the header.
+    pub fn total_in(&self) -> u64 {
+        2 + self.total
+    }
+
+    /// Return the number of bytes read from the filter so far.
+    pub fn total_out(&self) -> u64 {
+        self.total
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.inner.read(output));
+        self.state.decode(&mut output[..n]);
+        self.total += n as u64;
+        Ok(n)
+    }
+}
+
+/// Filter all data from `input` and write it to `output`, preceded by
+/// the filter header.  On success, the output is returned.
+pub fn compress<R: Read, W: Write>(mut input: R, output: W, filter: Filter) -> Result<W, Error> {
+    let mut fw = Writer::new(output, filter);
+    try!(io::copy(&mut input, &mut fw));
+    try!(fw.flush());
+    Ok(fw.into_inner())
+}
+
+/// Reverse the filter on `input` and write the result to `output`.
+/// On success, the output is returned.
+pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<W, Error> {
+    let mut fr = try!(Reader::new(input));
+    try!(io::copy(&mut fr, &mut output));
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::io::Cursor;
+    use super::{compress, decompress, encode, decode, Filter, Reader};
+
+    #[test]
+    fn delta1() {
+        let mut data = [1, 2, 3, 5, 8, 13];
+        encode(Filter::Delta(1), &mut data);
+        assert_eq!([1, 1, 1, 2, 3, 5], data);
+        decode(Filter::Delta(1), &mut data);
+        assert_eq!([1, 2, 3, 5, 8, 13], data);
+    }
+
+    #[test]
+    fn delta2() {
+        let mut data = [10, 20, 30, 40, 50, 70];
+        encode(Filter::Delta2, &mut data);
+        assert_eq!([10, 0, 0, 0, 0, 10], data);
+        decode(Filter::Delta2, &mut data);
+        assert_eq!([10, 20, 30, 40, 50, 70], data);
+    }
+
+    #[test]
+    fn delta_stride() {
+        // Little-endian 16-bit samples 0x0100, 0x0101, 0x0102.
+        let mut data = [0, 1, 1, 1, 2, 1];
+        encode(Filter::Delta(2), &mut data);
+        assert_eq!([0, 1, 1, 0, 1, 0], data);
+        decode(Filter::Delta(2), &mut data);
+        assert_eq!([0, 1, 1, 1, 2, 1], data);
+    }
+
+    #[test]
+    fn xor_stride() {
+        let mut data = [1, 2, 3, 1, 2, 7];
+        encode(Filter::Xor(3), &mut data);
+        assert_eq!([1, 2, 3, 0, 0, 4], data);
+        decode(Filter::Xor(3), &mut data);
+        assert_eq!([1, 2, 3, 1, 2, 7], data);
+    }
+
+    #[test]
+    fn header() {
+        let filtered = compress(Cursor::new(&[5, 6, 7][..]), vec![], Filter::Delta(4)).unwrap();
+        assert_eq!(&[1, 4, 5, 6, 7][..], &filtered[..]);
+        let r = Reader::new(Cursor::new(&filtered[..])).unwrap();
+        assert_eq!(Filter::Delta(4), r.filter());
+    }
+
+    #[test]
+    fn bad_header() {
+        assert!(Reader::new(Cursor::new(&[9, 1][..])).is_err());
+        assert!(Reader::new(Cursor::new(&[1, 0][..])).is_err());
+    }
+
+    #[test]
+    fn roundtrip() {
+        let input = include_bytes!("delta.rs");
+        for filter in [Filter::Delta(1), Filter::Delta(3), Filter::Delta2,
+                       Filter::Xor(4), Filter::Xor(255)].iter() {
+            let filtered = compress(Cursor::new(&input[..]), vec![], *filter).unwrap();
+            let unfiltered = decompress(Cursor::new(&filtered[..]), vec![]).unwrap();
+            assert_eq!(&input[..], &unfiltered[..]);
+        }
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![], Filter::Delta(1)),
+                               |d: &[u8]| super::decompress(d, vec![])));
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![], Filter::Delta2),
+                               |d: &[u8]| super::decompress(d, vec![])));
+    }
+}
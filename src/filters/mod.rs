@@ -0,0 +1,11 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Reversible transforms which can be applied to data before
+//! compressing it with any of the coders in this crate.
+
+pub mod delta;
+pub mod mtf;
+pub mod x86;
+pub mod transpose;
+pub mod dedup;
@@ -0,0 +1,93 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Move-to-front transform.
+//!
+//! Each byte is replaced by its position in a list of all byte
+//! values, and then moved to the front of the list.  Recently seen
+//! bytes get small numbers, so the output of the Burrows-Wheeler
+//! transform, which consists of runs of few distinct bytes, becomes
+//! mostly zeros and other small values, which an order-0 coder
+//! compresses well.
+
+/// Move-to-front state: the list of byte values, most recently used
+/// first.
+#[derive(Clone)]
+pub struct Mtf {
+    order: [u8; 256],
+}
+
+impl Mtf {
+    pub fn new() -> Mtf {
+        let mut order = [0; 256];
+        for (i, o) in order.iter_mut().enumerate() {
+            *o = i as u8;
+        }
+        Mtf { order: order }
+    }
+
+    /// Transform `data` in place.
+    pub fn encode(&mut self, data: &mut [u8]) {
+        for d in data.iter_mut() {
+            let b = *d;
+            let mut i = 0;
+            while self.order[i] != b {
+                i += 1;
+            }
+            self.order.copy_within(0..i, 1);
+            self.order[0] = b;
+            *d = i as u8;
+        }
+    }
+
+    /// Reverse the transform on `data` in place.
+    pub fn decode(&mut self, data: &mut [u8]) {
+        for d in data.iter_mut() {
+            let i = *d as usize;
+            let b = self.order[i];
+            self.order.copy_within(0..i, 1);
+            self.order[0] = b;
+            *d = b;
+        }
+    }
+}
+
+/// Transform `data` in place, starting with the byte values in
+/// order.
+pub fn encode(data: &mut [u8]) {
+    Mtf::new().encode(data)
+}
+
+/// Reverse the transform done by `encode` on `data` in place.
+pub fn decode(data: &mut [u8]) {
+    Mtf::new().decode(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, Mtf};
+
+    #[test]
+    fn banana() {
+        let mut data = *b"bananaaa";
+        encode(&mut data);
+        assert_eq!([98, 98, 110, 1, 1, 1, 0, 0], data);
+        decode(&mut data);
+        assert_eq!(*b"bananaaa", data);
+    }
+
+    #[test]
+    fn pieces() {
+        let input = include_bytes!("mtf.rs");
+        let mut whole = input.to_vec();
+        encode(&mut whole);
+        let mut pieces = input.to_vec();
+        let mut m = Mtf::new();
+        for p in pieces.chunks_mut(100) {
+            m.encode(p);
+        }
+        assert_eq!(whole, pieces);
+        decode(&mut whole);
+        assert_eq!(&input[..], &whole[..]);
+    }
+}
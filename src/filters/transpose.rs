@@ -0,0 +1,83 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Record transposition.
+//!
+//! Tables of fixed-size records, such as arrays of 32-bit samples or
+//! of C structs, store the bytes of each field interleaved with the
+//! other fields.  This filter treats the data as records of `width`
+//! bytes and writes the first byte of every record, then the second
+//! byte of every record and so on, so that each field becomes one
+//! contiguous stream of similar values.  Bytes after the last
+//! complete record are passed through unchanged.
+
+/// Largest record width.
+pub const MAX_WIDTH: usize = 1 << 16;
+
+fn check(width: usize) {
+    assert!(width > 0 && width <= MAX_WIDTH);
+}
+
+/// Return `data` split into columns of records of `width` bytes.
+/// Panics if `width` is zero or larger than `MAX_WIDTH`.
+pub fn encode(width: usize, data: &[u8]) -> Vec<u8> {
+    check(width);
+    let records = data.len() / width;
+    let mut out = Vec::with_capacity(data.len());
+    for col in 0..width {
+        out.extend((0..records).map(|r| data[r * width + col]));
+    }
+    out.extend_from_slice(&data[records * width..]);
+    out
+}
+
+/// Reverse the transposition done by `encode`.
+pub fn decode(width: usize, data: &[u8]) -> Vec<u8> {
+    check(width);
+    let records = data.len() / width;
+    let mut out = vec![0; data.len()];
+    for col in 0..width {
+        for r in 0..records {
+            out[r * width + col] = data[col * records + r];
+        }
+    }
+    out[records * width..].copy_from_slice(&data[records * width..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "lz")]
+    use std::io::Cursor;
+    #[cfg(feature = "lz")]
+    use lzss;
+    use super::{decode, encode};
+
+    #[test]
+    fn records() {
+        let data = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+        let t = encode(3, &data);
+        assert_eq!(vec![1, 4, 7, 2, 5, 8, 3, 6, 9, 10, 11], t);
+        assert_eq!(&data[..], &decode(3, &t)[..]);
+        assert_eq!(&data[..], &encode(1, &data)[..]);
+        assert_eq!(&data[..], &encode(20, &data)[..]);
+    }
+
+    #[cfg(feature = "lz")]
+    #[test]
+    fn samples() {
+        // Slowly rising 32-bit big-endian counters with noisy low bytes.
+        let mut data = Vec::new();
+        let mut x: u32 = 1;
+        for i in 0..10000u32 {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            let v = (i << 8) | (x >> 24);
+            data.extend_from_slice(&[(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]);
+        }
+        let t = encode(4, &data);
+        assert_eq!(data, decode(4, &t));
+        let plain = lzss::compress(Cursor::new(&data[..]), vec![]).unwrap();
+        let transposed = lzss::compress(Cursor::new(&t[..]), vec![]).unwrap();
+        assert!(transposed.len() < plain.len() / 2);
+    }
+}
@@ -0,0 +1,119 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Branch target filter for x86 machine code.
+//!
+//! The x86 `call` (opcode E8) and `jmp` (opcode E9) instructions
+//! take a 32-bit displacement relative to the end of the instruction.
+//! Calls to the same function from different places therefore have
+//! different operands.  This filter replaces the displacements by
+//! absolute positions, so that repeated calls become repeated byte
+//! strings which the LZ coders can match.
+//!
+//! Only displacements whose most significant byte is 0x00 or 0xff,
+//! that is, targets within 16 MiB, are converted.  The filter
+//! changes the low three bytes of the displacement and leaves the
+//! opcode and the most significant byte alone, so that decoding sees
+//! the same candidates as encoding.  Data which is not machine code
+//! passes through the filter unharmed, only slightly scrambled.
+
+/// Size of a call or jump instruction with a 32-bit operand.
+const INSN_LEN: usize = 5;
+
+#[inline(always)]
+fn is_candidate(data: &[u8], i: usize) -> bool {
+    data[i] & 0xfe == 0xe8 && (data[i + 4] == 0x00 || data[i + 4] == 0xff)
+}
+
+fn convert(data: &mut [u8], encode: bool) {
+    if data.len() < INSN_LEN {
+        return;
+    }
+    let mut i = 0;
+    while i <= data.len() - INSN_LEN {
+        if is_candidate(data, i) {
+            let v = data[i + 1] as u32 | (data[i + 2] as u32) << 8 | (data[i + 3] as u32) << 16;
+            let pos = (i + INSN_LEN) as u32;
+            let v = if encode { v.wrapping_add(pos) } else { v.wrapping_sub(pos) };
+            data[i + 1] = v as u8;
+            data[i + 2] = (v >> 8) as u8;
+            data[i + 3] = (v >> 16) as u8;
+            i += INSN_LEN;
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// Convert the call and jump displacements in `data` to absolute
+/// positions, in place.  Positions are counted from the start of
+/// `data`.
+pub fn encode(data: &mut [u8]) {
+    convert(data, true)
+}
+
+/// Reverse the conversion done by `encode` on `data` in place.
+pub fn decode(data: &mut [u8]) {
+    convert(data, false)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "lz")]
+    use std::io::Cursor;
+    #[cfg(feature = "lz")]
+    use lzss;
+    use super::{decode, encode};
+
+    /// Fake machine code: runs of filler bytes with calls to a few
+    /// functions in between.
+    fn code() -> Vec<u8> {
+        let targets = [0x1000u32, 0x2345, 0x8000, 0x10];
+        let mut code = Vec::new();
+        let mut x: u32 = 1;
+        for i in 0..2000 {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            for k in 0..(x >> 16) % 7 {
+                code.push(0x40 + (k as u8));
+            }
+            let t = targets[i % targets.len()];
+            let rel = t.wrapping_sub((code.len() + 5) as u32);
+            code.push(if i % 3 == 0 { 0xe9 } else { 0xe8 });
+            code.extend_from_slice(&[rel as u8, (rel >> 8) as u8, (rel >> 16) as u8,
+                                     (rel >> 24) as u8]);
+        }
+        code
+    }
+
+    #[test]
+    fn calls() {
+        // call -5 at position 5 calls position 5, call +0x100 at
+        // position 0 calls position 0x105.
+        let mut data = [0xe8, 0x00, 0x01, 0x00, 0x00, 0xe8, 0xfb, 0xff, 0xff, 0xff, 0xe8];
+        encode(&mut data);
+        assert_eq!([0xe8, 0x05, 0x01, 0x00, 0x00, 0xe8, 0x05, 0x00, 0x00, 0xff, 0xe8], data);
+        decode(&mut data);
+        assert_eq!([0xe8, 0x00, 0x01, 0x00, 0x00, 0xe8, 0xfb, 0xff, 0xff, 0xff, 0xe8], data);
+    }
+
+    #[test]
+    fn roundtrip() {
+        for input in vec![code(), include_bytes!("x86.rs").to_vec(), vec![0xe8; 1000]] {
+            let mut data = input.clone();
+            encode(&mut data);
+            decode(&mut data);
+            assert_eq!(input, data);
+        }
+    }
+
+    #[cfg(feature = "lz")]
+    #[test]
+    fn improves_compression() {
+        let input = code();
+        let mut filtered = input.clone();
+        encode(&mut filtered);
+        let plain = lzss::compress(Cursor::new(&input[..]), vec![]).unwrap();
+        let better = lzss::compress(Cursor::new(&filtered[..]), vec![]).unwrap();
+        assert!(better.len() < plain.len() * 3 / 4);
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Ways of storing the output of a compression method.
+//!
+//! The stream of a method can be stored as it is, in the block
+//! container of `container`, or after the filters of a `Pipeline`.
+//! A `Format` names one of these, so that tools can compress and
+//! decompress with any method in any of them without dispatching on
+//! the format themselves.
+
+use std::io::{Read, Write};
+
+use container;
+use error::Error;
+use method::Method;
+use pipeline::{self, Pipeline, Stage};
+
+/// How the compressed data is stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// The stream produced by the method.
+    Raw,
+    /// The block container, storing incompressible blocks.
+    Blocks,
+    /// The block container with a method chosen per block.
+    Hybrid,
+    /// A pipeline with the given filters before the method.
+    Filtered(Vec<Stage>),
+}
+
+impl Format {
+    /// Compress all data from `input` with `method` and write it to
+    /// `output` in this format.  `Hybrid` ignores `method`.  On
+    /// success, the output is returned.
+    pub fn compress<R: Read, W: Write>(&self, method: Method, input: R, output: W)
+                                       -> Result<W, Error> {
+        match *self {
+            Format::Raw => method.compress(input, output),
+            Format::Blocks => container::compress(input, output, method),
+            Format::Hybrid => container::compress_hybrid(input, output),
+            Format::Filtered(ref stages) => {
+                let p = stages.iter().fold(Pipeline::new(method), |p, &s| p.stage(s));
+                p.compress(input, output)
+            },
+        }
+    }
+
+    /// Decompress data written by `compress` in this format.  Only
+    /// `Raw` needs to be told the method; the other formats record
+    /// it.  On success, the output is returned.
+    pub fn decompress<R: Read, W: Write>(&self, method: Method, input: R, output: W)
+                                         -> Result<W, Error> {
+        match *self {
+            Format::Raw => method.decompress(input, output),
+            Format::Blocks | Format::Hybrid => container::decompress(input, output),
+            Format::Filtered(_) => pipeline::decompress(input, output),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "lz"))]
+mod tests {
+    use method::Method;
+    use pipeline::Stage;
+    use super::Format;
+
+    #[test]
+    fn compress_decompress() {
+        let input = include_bytes!("format.rs");
+        let formats = [Format::Raw, Format::Blocks, Format::Hybrid,
+                       Format::Filtered(vec![Stage::Mtf, Stage::Dedup])];
+        for f in formats.iter() {
+            let compressed = f.compress(Method::Lzss, &input[..], Vec::new()).unwrap();
+            let decompressed = f.decompress(Method::Lzss, &compressed[..], Vec::new()).unwrap();
+            assert!(&input[..] == &decompressed[..], "{:?}", f);
+        }
+    }
+}
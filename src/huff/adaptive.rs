@@ -3,19 +3,36 @@
 
 //! Simple adaptive Huffman coder.  Based on Mark Nelson, Jean-Loup
 //! Gailly: The Data Compression Book, 2nd Edition, M&T Books, 1996.
+//!
+//! The tree is updated either with the FGK algorithm, or with
+//! Vitter's algorithm Λ, which is selected when creating the tree.
+//!
+//! The Huffman tree is available on its own as `Tree`, for an
+//! alphabet of any size.  Several trees can share one `BitWriter` or
+//! `BitReader`, for example to code the literals, match lengths and
+//! distances of an LZ compressor with separate models.  `Writer` and
+//! `Reader` use a tree for the 256 byte values.
+//!
+//! Besides the symbols of its alphabet, each tree has an end-of-file
+//! symbol and an escape symbol.  A symbol which has not been seen
+//! before is coded as the escape symbol, followed by the symbol
+//! itself in as many bits as needed for the alphabet size.
+//!
+//! `inspect` lists the literals of a stream.  The codes change with
+//! every symbol, and the stream has no header, so there are no code
+//! lengths to show.
 
-use std::io;
-use std::io::{Read, Write};
+use alloc::collections::VecDeque;
+use io;
+use io::{Read, Write};
+use alloc::vec::Vec;
 
 use bitfile::{BitReader, BitWriter};
-use error::Error;
+use error::{at_offset, Error};
+use inspect::{Event, Events, Inspect, Recorder};
 
-type Symbol = usize;
+pub type Symbol = usize;
 
-const EOF: usize = 256;
-const ESCAPE: usize = 257;
-const SYMBOL_COUNT: usize = 258;
-const NODE_TABLE_COUNT: usize = SYMBOL_COUNT * 2 - 1;
 const ROOT_NODE: usize = 0;
 const MAX_WEIGHT: usize = 0x8000;
 
@@ -38,41 +55,113 @@ impl Node {
     }
 }
 
-struct Tree {
-    leaf: [Option<usize>; SYMBOL_COUNT],
+/// Tree updated with the FGK algorithm.  The nodes are stored in
+/// order of non-increasing weight, with the two children of a node
+/// next to each other.  Escape and end-of-file are leaves of the
+/// initial tree.
+#[derive(Clone)]
+struct FgkTree {
+    leaf: Vec<Option<usize>>,
     next_free_node: usize,
-    nodes: [Node; NODE_TABLE_COUNT],
+    nodes: Vec<Node>,
+    eof: Symbol,
+    escape: Symbol,
+    escape_bits: usize,
 }
 
-impl Tree {
-    fn new() -> Self {
-        let mut tree = Tree {
-            leaf: [None; SYMBOL_COUNT],
+impl FgkTree {
+    fn new(symbols: usize) -> Self {
+        let eof = symbols;
+        let escape = symbols + 1;
+        let mut escape_bits = 0;
+        while (1 << escape_bits) < symbols {
+            escape_bits += 1;
+        }
+        let mut tree = FgkTree {
+            leaf: vec![None; symbols + 2],
             next_free_node: 0,
-            nodes: [Node::new(); NODE_TABLE_COUNT],
+            nodes: vec![Node::new(); (symbols + 2) * 2 - 1],
+            eof: eof,
+            escape: escape,
+            escape_bits: escape_bits,
         };
         tree.nodes[ROOT_NODE].child = ROOT_NODE + 1;
         tree.nodes[ROOT_NODE].child_is_leaf = false;
         tree.nodes[ROOT_NODE].weight = 2;
         tree.nodes[ROOT_NODE].parent = None;
         
-        tree.nodes[ROOT_NODE + 1].child = EOF;
+        tree.nodes[ROOT_NODE + 1].child = eof;
         tree.nodes[ROOT_NODE + 1].child_is_leaf = true;
         tree.nodes[ROOT_NODE + 1].weight = 1;
         tree.nodes[ROOT_NODE + 1].parent = Some(ROOT_NODE);
-        tree.leaf[EOF] = Some(ROOT_NODE + 1);
+        tree.leaf[eof] = Some(ROOT_NODE + 1);
 
-        tree.nodes[ROOT_NODE + 2].child = ESCAPE;
+        tree.nodes[ROOT_NODE + 2].child = escape;
         tree.nodes[ROOT_NODE + 2].child_is_leaf = true;
         tree.nodes[ROOT_NODE + 2].weight = 1;
         tree.nodes[ROOT_NODE + 2].parent = Some(ROOT_NODE);
-        tree.leaf[ESCAPE] = Some(ROOT_NODE + 2);
+        tree.leaf[escape] = Some(ROOT_NODE + 2);
 
         tree.next_free_node = ROOT_NODE + 3;
         
         tree
     }
 
+    fn encode<W: Write>(&mut self, output: &mut BitWriter<W>, sym: Symbol) -> io::Result<()> {
+        let mut code = 0;
+        let mut code_size = 0;
+        let mut current_bit = 1;
+        
+        let mut mb_current_node = self.leaf[sym];
+        
+        if mb_current_node.is_none() {
+            mb_current_node = self.leaf[self.escape];
+        }
+        
+        while let Some(current_node) = mb_current_node {
+            if current_node == ROOT_NODE {
+                break;
+            }
+            if current_node & 1 == 0 {
+                code |= current_bit;
+            }
+            current_bit <<= 1;
+            code_size += 1;
+            mb_current_node = self.nodes[current_node].parent;
+        }
+
+        try!(output.write_bits(code, code_size));
+        if self.leaf[sym].is_none() {
+            try!(output.write_bits(sym as u64, self.escape_bits));
+            self.add_new_node(sym);
+        }
+        if sym != self.eof {
+            self.update_model(sym);
+        }
+        Ok(())
+    }
+
+    fn decode<R: Read>(&mut self, input: &mut BitReader<R>) -> io::Result<Symbol> {
+        let mut current_node = ROOT_NODE;
+
+        while !self.nodes[current_node].child_is_leaf {
+            current_node = self.nodes[current_node].child;
+            current_node += try!(input.read_bits(1)) as usize;
+        }
+        let mut c = self.nodes[current_node].child;
+        if c == self.escape {
+            c = try!(input.read_bits(self.escape_bits)) as usize;
+            if c >= self.eof || self.leaf[c].is_some() {
+                return Err(Error::corrupt("invalid escaped symbol").into());
+            }
+            self.add_new_node(c);
+        }
+        if c != self.eof {
+            self.update_model(c);
+        }
+        Ok(c)
+    }
+
     // fn dump_tree(&self, node: usize, nesting: usize) {
     //     for _ in 0..nesting*2 {
     //         print!(" ");
@@ -92,7 +181,7 @@ impl Tree {
     
     // fn dump(&self) {
     //     self.dump_tree(ROOT_NODE, 1);
-    //     for i in 0..ESCAPE + 1 {
+    //     for i in 0..self.escape + 1 {
     //         if let Some(idx) = self.leaf[i] {
     //             println!(" {:?} ({:?}) -> {}", i, (i as u8) as char, idx);
     //         }
@@ -227,48 +316,457 @@ impl Tree {
     }
 }
 
-pub struct Writer<W> {
-    inner: BitWriter<W>,
-    tree: Tree,
+#[derive(Copy, Clone)]
+struct VitterNode {
+    weight: usize,
+    parent: Option<usize>,
+    is_leaf: bool,
+    symbol: Symbol,
+    left: usize,
+    right: usize,
 }
 
-impl<W: Write> Writer<W> {
-    pub fn new(output: W) -> Self {
-        Writer{
-            inner: BitWriter::new(output),
-            tree: Tree::new(),
+impl VitterNode {
+    fn leaf(symbol: Symbol, parent: Option<usize>) -> Self {
+        VitterNode {
+            weight: 0,
+            parent: parent,
+            is_leaf: true,
+            symbol: symbol,
+            left: 0,
+            right: 0,
         }
     }
+}
 
-    pub fn encode_symbol(&mut self, sym: Symbol) -> io::Result<()> {
+/// Tree updated with Vitter's algorithm Λ.  The nodes are stored in
+/// order of non-increasing weight, with the root at index 0, and
+/// among nodes of the same weight, internal nodes come before leaves.
+/// Keeping this order makes the tree a Huffman tree of minimal
+/// height for the current weights after each update.
+///
+/// The NYT ("not yet transmitted") node is a leaf of weight zero,
+/// which is always the last node.  New symbols are sent as the code
+/// of the NYT node followed by the symbol.  Initially, the tree has
+/// only the NYT node and the leaf for end-of-file, with weight one.
+///
+/// See Jeffrey S. Vitter: Design and Analysis of Dynamic Huffman
+/// Codes, Journal of the ACM 34(4), 1987.
+#[derive(Clone)]
+struct VitterTree {
+    leaf: Vec<Option<usize>>,
+    nodes: Vec<VitterNode>,
+    nyt: usize,
+    eof: Symbol,
+    escape_bits: usize,
+}
+
+impl VitterTree {
+    fn new(symbols: usize) -> Self {
+        let eof = symbols;
+        let mut escape_bits = 0;
+        while (1 << escape_bits) < symbols {
+            escape_bits += 1;
+        }
+        let mut nodes = Vec::with_capacity((symbols + 1) * 2 - 1);
+        nodes.push(VitterNode {
+            weight: 1,
+            parent: None,
+            is_leaf: false,
+            symbol: 0,
+            left: ROOT_NODE + 2,
+            right: ROOT_NODE + 1,
+        });
+        nodes.push(VitterNode::leaf(eof, Some(ROOT_NODE)));
+        nodes[ROOT_NODE + 1].weight = 1;
+        nodes.push(VitterNode::leaf(0, Some(ROOT_NODE)));
+        let mut leaf = vec![None; symbols + 1];
+        leaf[eof] = Some(ROOT_NODE + 1);
+        VitterTree {
+            leaf: leaf,
+            nodes: nodes,
+            nyt: ROOT_NODE + 2,
+            eof: eof,
+            escape_bits: escape_bits,
+        }
+    }
+
+    fn code(&self, node: usize) -> (u64, usize) {
         let mut code = 0;
         let mut code_size = 0;
-        let mut current_bit = 1;
-        
-        let mut mb_current_node = self.tree.leaf[sym];
-        
-        if mb_current_node.is_none() {
-            mb_current_node = self.tree.leaf[ESCAPE];
+        let mut current_node = node;
+        while let Some(parent) = self.nodes[current_node].parent {
+            if self.nodes[parent].right == current_node {
+                code |= 1 << code_size;
+            }
+            code_size += 1;
+            current_node = parent;
         }
-        
-        while let Some(current_node) = mb_current_node {
-            if current_node == ROOT_NODE {
+        (code, code_size)
+    }
+
+    fn encode<W: Write>(&mut self, output: &mut BitWriter<W>, sym: Symbol) -> io::Result<()> {
+        match self.leaf[sym] {
+            Some(node) => {
+                let (code, code_size) = self.code(node);
+                try!(output.write_bits(code, code_size));
+            },
+            None => {
+                let (code, code_size) = self.code(self.nyt);
+                try!(output.write_bits(code, code_size));
+                try!(output.write_bits(sym as u64, self.escape_bits));
+            },
+        }
+        if sym != self.eof {
+            self.update(sym);
+        }
+        Ok(())
+    }
+
+    fn decode<R: Read>(&mut self, input: &mut BitReader<R>) -> io::Result<Symbol> {
+        let mut current_node = ROOT_NODE;
+        while !self.nodes[current_node].is_leaf {
+            current_node =
+                if try!(input.read_bits(1)) == 0 {
+                    self.nodes[current_node].left
+                } else {
+                    self.nodes[current_node].right
+                };
+        }
+        let c =
+            if current_node == self.nyt {
+                let c = try!(input.read_bits(self.escape_bits)) as usize;
+                if c >= self.eof || self.leaf[c].is_some() {
+                    return Err(Error::corrupt("invalid escaped symbol").into());
+                }
+                c
+            } else {
+                self.nodes[current_node].symbol
+            };
+        if c != self.eof {
+            self.update(c);
+        }
+        Ok(c)
+    }
+
+    /// Exchange the subtrees at positions `i` and `j`, neither of
+    /// which may be an ancestor of the other.
+    fn swap_nodes(&mut self, i: usize, j: usize) {
+        let parent_i = self.nodes[i].parent;
+        let parent_j = self.nodes[j].parent;
+        self.nodes.swap(i, j);
+        self.nodes[i].parent = parent_i;
+        self.nodes[j].parent = parent_j;
+        for &k in &[i, j] {
+            if self.nodes[k].is_leaf {
+                self.leaf[self.nodes[k].symbol] = Some(k);
+            } else {
+                let (left, right) = (self.nodes[k].left, self.nodes[k].right);
+                self.nodes[left].parent = Some(k);
+                self.nodes[right].parent = Some(k);
+            }
+        }
+    }
+
+    /// Return the first node of the block of `node`, that is, of the
+    /// nodes with the same weight and of the same kind.
+    fn leader(&self, node: usize) -> usize {
+        let weight = self.nodes[node].weight;
+        let is_leaf = self.nodes[node].is_leaf;
+        let mut leader = node;
+        while leader > ROOT_NODE && self.nodes[leader - 1].weight == weight &&
+            self.nodes[leader - 1].is_leaf == is_leaf {
+            leader -= 1;
+        }
+        leader
+    }
+
+    /// Move `node` in front of the block which follows its own block
+    /// in the node order, increment its weight and return the next
+    /// node to be updated.  A leaf of weight w moves in front of the
+    /// internal nodes of weight w, and an internal node of weight w
+    /// in front of the leaves of weight w + 1.
+    fn slide_and_increment(&mut self, node: usize) -> Option<usize> {
+        let weight = self.nodes[node].weight;
+        let is_leaf = self.nodes[node].is_leaf;
+        let previous_parent = self.nodes[node].parent;
+        let mut current_node = node;
+        while current_node > ROOT_NODE {
+            let next = &self.nodes[current_node - 1];
+            let slide =
+                if is_leaf {
+                    !next.is_leaf && next.weight == weight
+                } else {
+                    next.is_leaf && next.weight == weight + 1
+                };
+            if !slide {
                 break;
             }
-            if current_node & 1 == 0 {
-                code |= current_bit;
+            self.swap_nodes(current_node, current_node - 1);
+            current_node -= 1;
+        }
+        self.nodes[current_node].weight += 1;
+        if is_leaf {
+            self.nodes[current_node].parent
+        } else {
+            previous_parent
+        }
+    }
+
+    fn update(&mut self, sym: Symbol) {
+        if self.nodes[ROOT_NODE].weight == MAX_WEIGHT {
+            self.rebuild_tree();
+        }
+        let mut leaf_to_increment = None;
+        let mut current_node = match self.leaf[sym] {
+            None => {
+                // The NYT node becomes an internal node with the new
+                // NYT node and the leaf for `sym` as children.
+                let parent = self.nyt;
+                let new_leaf = parent + 1;
+                let new_nyt = parent + 2;
+                self.nodes.push(VitterNode::leaf(sym, Some(parent)));
+                self.nodes.push(VitterNode::leaf(0, Some(parent)));
+                self.nodes[parent].is_leaf = false;
+                self.nodes[parent].left = new_nyt;
+                self.nodes[parent].right = new_leaf;
+                self.leaf[sym] = Some(new_leaf);
+                self.nyt = new_nyt;
+                leaf_to_increment = Some(new_leaf);
+                Some(parent)
+            },
+            Some(node) => {
+                let leader = self.leader(node);
+                if leader != node {
+                    self.swap_nodes(node, leader);
+                }
+                let parent = self.nodes[leader].parent;
+                match parent {
+                    Some(p) if self.nodes[p].left == self.nyt || self.nodes[p].right == self.nyt => {
+                        leaf_to_increment = Some(leader);
+                        parent
+                    },
+                    _ =>
+                        Some(leader),
+                }
+            },
+        };
+        while let Some(node) = current_node {
+            current_node = self.slide_and_increment(node);
+        }
+        if let Some(node) = leaf_to_increment {
+            self.slide_and_increment(node);
+        }
+    }
+
+    /// Halve the weights of all symbols and build a new Huffman tree
+    /// from them.  Nodes are numbered in the order in which they are
+    /// taken from the queues of the two-queue construction, which
+    /// results in the node order required by algorithm Λ when leaves
+    /// are preferred over internal nodes of the same weight.
+    fn rebuild_tree(&mut self) {
+        let mut leaves: Vec<(usize, Symbol)> = self.nodes.iter().enumerate()
+            .filter(|&(i, n)| n.is_leaf && i != self.nyt)
+            .map(|(_, n)| ((n.weight + 1) / 2, n.symbol))
+            .collect();
+        leaves.sort();
+
+        let node_count = leaves.len() * 2 + 1;
+        let index = |order: usize| node_count - 1 - order;
+
+        let mut nodes = vec![VitterNode::leaf(0, None); node_count];
+        // Queue entries are weights and the nodes to be numbered:
+        // leaves with their symbols, internal nodes with the indices
+        // of their children.
+        let mut leaf_queue: VecDeque<(usize, Symbol)> = VecDeque::with_capacity(leaves.len());
+        let mut internal_queue: VecDeque<(usize, usize, usize)> = VecDeque::with_capacity(leaves.len());
+        leaf_queue.extend(leaves.into_iter());
+        let mut taken = [0; 2];
+        // The NYT node has weight zero, so it is always taken first.
+        let mut order = 1;
+        let mut pending = Some(index(0));
+        loop {
+            for t in taken.iter_mut() {
+                if let Some(p) = pending.take() {
+                    *t = p;
+                    continue;
+                }
+                let take_leaf = match (leaf_queue.front(), internal_queue.front()) {
+                    (Some(&(lw, _)), Some(&(iw, _, _))) => lw <= iw,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                };
+                let i = index(order);
+                order += 1;
+                if take_leaf {
+                    let (weight, sym) = leaf_queue.pop_front().unwrap();
+                    nodes[i] = VitterNode::leaf(sym, None);
+                    nodes[i].weight = weight;
+                } else {
+                    let (weight, left, right) = internal_queue.pop_front().unwrap();
+                    nodes[i] = VitterNode {
+                        weight: weight,
+                        parent: None,
+                        is_leaf: false,
+                        symbol: 0,
+                        left: left,
+                        right: right,
+                    };
+                }
+                *t = i;
             }
-            current_bit <<= 1;
-            code_size += 1;
-            mb_current_node = self.tree.nodes[current_node].parent;
+            let weight = nodes[taken[0]].weight + nodes[taken[1]].weight;
+            if leaf_queue.is_empty() && internal_queue.is_empty() {
+                nodes[ROOT_NODE] = VitterNode {
+                    weight: weight,
+                    parent: None,
+                    is_leaf: false,
+                    symbol: 0,
+                    left: taken[0],
+                    right: taken[1],
+                };
+                break;
+            }
+            internal_queue.push_back((weight, taken[0], taken[1]));
         }
 
-        try!(self.inner.write_bits(code, code_size));
-        if self.tree.leaf[sym].is_none() {
-            try!(self.inner.write_bits(sym as u64, 8));
-            self.tree.add_new_node(sym);
+        for i in 0..node_count {
+            if nodes[i].is_leaf {
+                if i != index(0) {
+                    self.leaf[nodes[i].symbol] = Some(i);
+                }
+            } else {
+                let (left, right) = (nodes[i].left, nodes[i].right);
+                nodes[left].parent = Some(i);
+                nodes[right].parent = Some(i);
+            }
         }
-        Ok(())
+        self.nodes = nodes;
+        self.nyt = index(0);
+    }
+}
+
+/// Algorithm for updating the adaptive Huffman tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Algorithm of Faller, Gallager and Knuth, as described by
+    /// Nelson and Gailly.
+    Fgk,
+    /// Vitter's algorithm Λ, which also keeps the height of the tree
+    /// minimal, but updates the tree more slowly.
+    Vitter,
+}
+
+#[derive(Clone)]
+enum TreeImpl {
+    Fgk(FgkTree),
+    Vitter(VitterTree),
+}
+
+/// Adaptive Huffman tree for an alphabet of symbols `0` up to, but
+/// not including, the alphabet size.
+#[derive(Clone)]
+pub struct Tree {
+    inner: TreeImpl,
+    eof: Symbol,
+}
+
+impl Tree {
+    /// Create a new FGK tree for an alphabet of `symbols` symbols.
+    pub fn new(symbols: usize) -> Self {
+        Tree::with_algorithm(symbols, Algorithm::Fgk)
+    }
+
+    /// Create a new tree for an alphabet of `symbols` symbols, which
+    /// is updated with `algorithm`.  Encoder and decoder must use the
+    /// same algorithm.
+    pub fn with_algorithm(symbols: usize, algorithm: Algorithm) -> Self {
+        assert!(symbols > 0);
+        let inner = match algorithm {
+            Algorithm::Fgk => TreeImpl::Fgk(FgkTree::new(symbols)),
+            Algorithm::Vitter => TreeImpl::Vitter(VitterTree::new(symbols)),
+        };
+        Tree {
+            inner: inner,
+            eof: symbols,
+        }
+    }
+
+    /// Return the algorithm used for updating the tree.
+    pub fn algorithm(&self) -> Algorithm {
+        match self.inner {
+            TreeImpl::Fgk(_) => Algorithm::Fgk,
+            TreeImpl::Vitter(_) => Algorithm::Vitter,
+        }
+    }
+
+    /// Return the number of symbols in the alphabet.
+    pub fn symbols(&self) -> usize {
+        self.eof
+    }
+
+    /// Return the end-of-file symbol, which is coded like the other
+    /// symbols, but does not update the tree.
+    pub fn eof(&self) -> Symbol {
+        self.eof
+    }
+
+    /// Write the code for `sym` to `output` and update the tree.
+    /// `sym` must be either a symbol of the alphabet or the
+    /// end-of-file symbol.
+    pub fn encode<W: Write>(&mut self, output: &mut BitWriter<W>, sym: Symbol) -> io::Result<()> {
+        assert!(sym <= self.eof);
+        match self.inner {
+            TreeImpl::Fgk(ref mut t) => t.encode(output, sym),
+            TreeImpl::Vitter(ref mut t) => t.encode(output, sym),
+        }
+    }
+
+    /// Read the code of the next symbol from `input` and update the
+    /// tree.
+    pub fn decode<R: Read>(&mut self, input: &mut BitReader<R>) -> io::Result<Symbol> {
+        match self.inner {
+            TreeImpl::Fgk(ref mut t) => t.decode(input),
+            TreeImpl::Vitter(ref mut t) => t.decode(input),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Writer<W> {
+    inner: BitWriter<W>,
+    tree: Tree,
+    total_in: u64,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(output: W) -> Self {
+        Writer::with_algorithm(output, Algorithm::Fgk)
+    }
+
+    /// Create a writer whose tree is updated with `algorithm`.
+    pub fn with_algorithm(output: W, algorithm: Algorithm) -> Self {
+        Writer{
+            inner: BitWriter::new(output),
+            tree: Tree::with_algorithm(256, algorithm),
+            total_in: 0,
+        }
+    }
+
+    pub fn encode_symbol(&mut self, sym: Symbol) -> io::Result<()> {
+        self.tree.encode(&mut self.inner, sym)
+    }
+
+
+    /// Return the number of uncompressed bytes written so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Return the number of compressed bytes written to the wrapped
+    /// writer so far.
+    pub fn total_out(&self) -> u64 {
+        self.inner.bytes_written()
     }
 
     pub fn into_inner(self) -> W {
@@ -281,45 +779,54 @@ pub struct Reader<R> {
     inner: BitReader<R>,
     tree: Tree,
     eof: bool,
+    total_out: u64,
+    recorder: Recorder,
 }
 
 impl<R: Read> Reader<R> {
-    pub fn new(output: R) -> Self {
+    pub fn new(input: R) -> Self {
+        Reader::with_algorithm(input, Algorithm::Fgk)
+    }
+
+    /// Create a reader whose tree is updated with `algorithm`, which
+    /// must be the algorithm used for compression.
+    pub fn with_algorithm(input: R, algorithm: Algorithm) -> Self {
         Reader{
-            inner: BitReader::new(output),
-            tree: Tree::new(),
+            inner: BitReader::new(input),
+            tree: Tree::with_algorithm(256, algorithm),
             eof: false,
+            total_out: 0,
+            recorder: Recorder::new(),
         }
     }
 
     fn decode_symbol(&mut self) -> io::Result<Symbol> {
-        let mut current_node = ROOT_NODE;
+        self.tree.decode(&mut self.inner)
+    }
 
-        while !self.tree.nodes[current_node].child_is_leaf {
-            current_node = self.tree.nodes[current_node].child;
-            current_node += try!(self.inner.read_bits(1)) as usize;
-        }
-        let mut c = self.tree.nodes[current_node].child;
-        if c == ESCAPE {
-            c = try!(self.inner.read_bits(8)) as usize;
-            self.tree.add_new_node(c);
-        }
-        Ok(c)
+    /// Return the number of compressed bytes read so far.
+    pub fn total_in(&self) -> u64 {
+        self.inner.bytes_read()
+    }
+
+    /// Return the number of decompressed bytes read so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
     }
 }
 
 impl<W: Write> Write for Writer<W> {
     fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
         for b in buffer {
-            let sym = *b as Symbol;
-            try!(self.encode_symbol(sym));
-            self.tree.update_model(sym);
+            try!(self.encode_symbol(*b as Symbol));
         }
+        self.total_in += buffer.len() as u64;
         Ok(buffer.len())
     }
     
     fn flush(&mut self) -> io::Result<()> {
-        try!(self.encode_symbol(EOF));
+        let eof = self.tree.eof();
+        try!(self.encode_symbol(eof));
         self.inner.flush()
     }
 }
@@ -332,37 +839,86 @@ impl<R: Read> Read for Reader<R> {
 
         let mut written = 0;
         for p in buffer.iter_mut() {
-            let s = try!(self.decode_symbol());
-            if s == EOF {
+            let s = try!(self.decode_symbol().map_err(|e| at_offset(e, self.total_in())));
+            if s == self.tree.eof() {
+                self.recorder.record(Event::End);
                 self.eof = true;
                 break;
             }
+            self.recorder.record(Event::Literal(s as u8));
             *p = s as u8;
             written += 1;
-            self.tree.update_model(s);
         }
+        self.total_out += written as u64;
         Ok(written)
     }
 }
 
-pub fn compress<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error> {
-    let mut cw = Writer::new(output);
+impl<R: Read> Inspect for Reader<R> {
+    fn recorder(&mut self) -> &mut Recorder {
+        &mut self.recorder
+    }
+}
+
+pub fn compress<R: Read, W: Write>(input: R, output: W) -> Result<W, Error> {
+    compress_with_algorithm(input, output, Algorithm::Fgk)
+}
+
+pub fn decompress<R: Read, W: Write>(input: R, output: W) -> Result<W, Error> {
+    decompress_with_algorithm(input, output, Algorithm::Fgk)
+}
+
+pub fn compress_with_algorithm<R: Read, W: Write>(mut input: R, output: W,
+                                                  algorithm: Algorithm) -> Result<W, Error> {
+    let mut cw = Writer::with_algorithm(output, algorithm);
     try!(io::copy(&mut input, &mut cw));
     try!(cw.flush());
     Ok(cw.into_inner())
 }
 
-pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<W, Error> {
-    let mut cr = Reader::new(input);
+pub fn decompress_with_algorithm<R: Read, W: Write>(input: R, mut output: W,
+                                                    algorithm: Algorithm) -> Result<W, Error> {
+    let mut cr = Reader::with_algorithm(input, algorithm);
     try!(io::copy(&mut cr, &mut output));
     Ok(output)
 }
 
+/// Return an iterator over the literals of the stream in `input`,
+/// which was compressed with `algorithm`.  The algorithm is not
+/// stored in the stream, so the first event is a header naming the
+/// algorithm assumed.
+pub fn inspect<R: Read>(input: R, algorithm: Algorithm) -> Events<Reader<R>> {
+    let mut reader = Reader::with_algorithm(input, algorithm);
+    reader.recorder.enable();
+    reader.recorder.record(Event::Header(match algorithm {
+        Algorithm::Fgk => "FGK tree updates".into(),
+        Algorithm::Vitter => "Vitter tree updates".into(),
+    }));
+    Events::new(reader)
+}
+
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod test {
     use std::io::{Cursor, Write, Read};
-    use super::{Writer, Reader};
+    use super::{Writer, Reader, Tree, Algorithm, VitterTree, MAX_WEIGHT};
+    use bitfile::{BitWriter, BitReader};
+
+    #[test]
+    fn inspect_events() {
+        use inspect::Event;
+        for &algorithm in &[Algorithm::Fgk, Algorithm::Vitter] {
+            let mut e = Writer::with_algorithm(vec![], algorithm);
+            e.write_all(b"aba").unwrap();
+            e.flush().unwrap();
+            let compressed = e.into_inner();
+            let events: Vec<Event> = super::inspect(Cursor::new(compressed), algorithm)
+                .map(|e| e.unwrap()).collect();
+            assert_eq!(5, events.len());
+            assert_eq!(&[Event::Literal(b'a'), Event::Literal(b'b'), Event::Literal(b'a'),
+                         Event::End][..], &events[1..]);
+        }
+    }
 
     #[test]
     fn compress_empty() {
@@ -441,4 +997,101 @@ mod test {
         
         assert_eq!(&input[..], &decompressed[..]);
     }
+
+    #[test]
+    fn large_alphabets() {
+        let symbols: Vec<(usize, usize)> =
+            (0..5000).map(|i| ((i * i) % 600, i % 3)).collect();
+        let mut bw = BitWriter::new(vec![]);
+        let mut lengths = Tree::new(600);
+        let mut small = Tree::new(3);
+        for &(l, s) in symbols.iter() {
+            lengths.encode(&mut bw, l).unwrap();
+            small.encode(&mut bw, s).unwrap();
+        }
+        let eof = lengths.eof();
+        lengths.encode(&mut bw, eof).unwrap();
+        bw.flush().unwrap();
+        let compressed = bw.to_inner();
+
+        let mut br = BitReader::new(Cursor::new(compressed));
+        let mut lengths = Tree::new(600);
+        let mut small = Tree::new(3);
+        for &(l, s) in symbols.iter() {
+            assert_eq!(l, lengths.decode(&mut br).unwrap());
+            assert_eq!(s, small.decode(&mut br).unwrap());
+        }
+        assert_eq!(600, lengths.decode(&mut br).unwrap());
+    }
+
+    #[test]
+    fn decompress_invalid_escape() {
+        // Escape code followed by the symbol 3, which is outside the
+        // alphabet.
+        let mut bw = BitWriter::new(vec![]);
+        bw.write_bits(0b1, 1).unwrap();
+        bw.write_bits(3, 2).unwrap();
+        bw.flush().unwrap();
+        let compressed = bw.to_inner();
+        let mut br = BitReader::new(Cursor::new(compressed));
+        assert!(Tree::new(3).decode(&mut br).is_err());
+    }
+
+    #[test]
+    fn compress_decompress_vitter() {
+        let input = include_bytes!("adaptive.rs");
+        let mut cw = Writer::with_algorithm(vec![], Algorithm::Vitter);
+        cw.write_all(&input[..]).unwrap();
+        cw.flush().unwrap();
+        let compressed = cw.into_inner();
+
+        let mut cr = Reader::with_algorithm(Cursor::new(&compressed[..]), Algorithm::Vitter);
+        let mut decompressed = Vec::new();
+        cr.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(&input[..], &decompressed[..]);
+    }
+
+    fn check_vitter_tree(tree: &VitterTree) {
+        let nodes = &tree.nodes;
+        assert_eq!(nodes.len() - 1, tree.nyt);
+        for i in 1..nodes.len() {
+            assert!(nodes[i - 1].weight > nodes[i].weight ||
+                    (nodes[i - 1].weight == nodes[i].weight &&
+                     (nodes[i].is_leaf || !nodes[i - 1].is_leaf)));
+        }
+        for i in 0..nodes.len() {
+            if !nodes[i].is_leaf {
+                let (left, right) = (nodes[i].left, nodes[i].right);
+                assert_eq!(Some(i), nodes[left].parent);
+                assert_eq!(Some(i), nodes[right].parent);
+                assert_eq!(nodes[i].weight, nodes[left].weight + nodes[right].weight);
+            } else if i != tree.nyt {
+                assert_eq!(Some(i), tree.leaf[nodes[i].symbol]);
+            }
+        }
+    }
+
+    #[test]
+    fn vitter_invariant() {
+        let mut tree = VitterTree::new(256);
+        let mut bw = BitWriter::new(vec![]);
+        let input = include_bytes!("adaptive.rs");
+        let mut rebuilt = false;
+        for &b in input.iter().cycle().take(MAX_WEIGHT + 1000) {
+            let weight = tree.nodes[0].weight;
+            tree.encode(&mut bw, b as usize).unwrap();
+            check_vitter_tree(&tree);
+            rebuilt |= tree.nodes[0].weight < weight;
+        }
+        assert!(rebuilt);
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+        let vitter = Algorithm::Vitter;
+        ::testutil::roundtrip((|d: &[u8]| super::compress_with_algorithm(d, vec![], vitter),
+                               |d: &[u8]| super::decompress_with_algorithm(d, vec![], vitter)));
+    }
 }
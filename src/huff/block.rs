@@ -3,27 +3,91 @@
 
 //! Simple implementation of a Huffman encoder.
 //!
-//! This is a block encoder that groups incoming data in 64 KiB blocks
-//! and generates Huffman codes for each block independently.  The
-//! information for reconstructing the Huffman tree in the decoder is
-//! encoded at the start of each block.
+//! This is a block encoder that groups incoming data in blocks and
+//! generates Huffman codes for each block independently.  Blocks are
+//! 64 KiB by default, and the block size can be chosen when creating
+//! the writer.  In whole-file mode, all input is collected in a
+//! single block, so that only one set of codes is computed from the
+//! frequencies of the whole file.  This saves the per-block headers
+//! and is better for large files with uniform statistics, at the cost
+//! of keeping the whole input in memory.  The codes are canonical
+//! Huffman codes, which are completely determined by the code length
+//! of each symbol.  Only the code lengths are transmitted at the start
+//! of each block.
+//!
+//! The code lengths of the 256 byte values and the end-of-block and
+//! end-of-file symbols are written in symbol order as 5-bit numbers.
+//! Runs of at least three zero lengths are written as `ZERO_RUN`
+//! followed by the run length minus three in 8 bits, and runs of at
+//! least three repetitions of the previous length as `REPEAT`
+//! followed by the repeat count minus three in 4 bits.
+//!
+//! The decoder looks up the symbol for the next `PRIMARY_BITS` bits
+//! of input in a table, with a second lookup in a subtable for longer
+//! codes, instead of walking the code tree bit by bit.
+//!
+//! `inspect` lists the blocks of a stream, with the code lengths of
+//! each block, and the literals.
 //!
 //! Based on the static Huffman encoder in Mark Nelson, Jean-Loup
 //! Gailly: The Data Compression Book, 2nd Edition, M&T Books, 1996.
 
-use std::io::{Read, Write};
-use std::io;
-use error::Error;
+use io::{Read, Write};
+use io;
+use error::{at_offset, Error};
 use bitfile::{BitWriter, BitReader};
+use inspect::{Event, Events, Inspect, Recorder};
+use alloc::vec::Vec;
 
-const BLOCK_SIZE: usize = 1024 * 64;
+/// Block size used by `Writer::new`.
+pub const DEFAULT_BLOCK_SIZE: usize = 1024 * 64;
 const EOB: usize = 256;
 const EOF: usize = 257;
 
 const MAX_COUNT: usize = 0x1fff;
 
+/// Limiting the counts to `MAX_COUNT` keeps the code lengths below
+/// this limit.
+const MAX_CODE_LEN: usize = 19;
+
+/// Code length header: number of bits per entry, and the two entries
+/// which start runs.
+const LEN_BITS: usize = 5;
+const ZERO_RUN: u64 = 30;
+const REPEAT: u64 = 31;
+const MIN_RUN: usize = 3;
+const MAX_ZERO_RUN: usize = MIN_RUN + 255;
+const MAX_REPEAT: usize = MIN_RUN + 15;
+
+/// Number of code bits resolved by the first lookup in the decoding
+/// table.  Longer codes continue in a subtable.
+const PRIMARY_BITS: usize = 10;
+
 type Symbol = u32;
 
+/// Assign canonical codes to the symbols with the code lengths
+/// `lengths`: shorter codes come first, and codes of the same length
+/// are in symbol order.
+fn canonical_codes(lengths: &[usize; EOF + 1]) -> [(u64, usize); EOF + 1] {
+    let mut count = [0u64; MAX_CODE_LEN + 1];
+    for &l in lengths.iter() {
+        count[l] += 1;
+    }
+    count[0] = 0;
+    let mut next_code = [0u64; MAX_CODE_LEN + 2];
+    for len in 1..MAX_CODE_LEN + 1 {
+        next_code[len + 1] = (next_code[len] + count[len]) << 1;
+    }
+    let mut codes = [(0, 0); EOF + 1];
+    for (sym, &l) in lengths.iter().enumerate() {
+        if l > 0 {
+            codes[sym] = (next_code[l], l);
+            next_code[l] += 1;
+        }
+    }
+    codes
+}
+
 #[derive(Clone, Copy)]
 struct Node {
     weight: usize,
@@ -35,26 +99,44 @@ struct Node {
 
 pub struct Writer<W> {
     inner: BitWriter<W>,
-    block: [u8; BLOCK_SIZE],
-    fill:  usize,
+    block: Vec<u8>,
+    block_size: usize,
     freqs: [usize; EOF + 1],
     tree:  [Node; 2 * (EOF + 1) + 1],
+    lengths: [usize; EOF + 1],
     codes: [(u64, usize); EOF + 1],
+    total_in: u64,
 }
 
 impl<W: Write> Writer<W> {
+    /// Create a new writer with the default block size.
     pub fn new(inner: W) -> Self {
+        Writer::with_block_size(inner, DEFAULT_BLOCK_SIZE)
+    }
+
+    /// Create a new writer which generates new codes after every
+    /// `block_size` bytes of input.
+    pub fn with_block_size(inner: W, block_size: usize) -> Self {
+        assert!(block_size > 0);
         Writer {
             inner: BitWriter::new(inner),
-            block: [0; BLOCK_SIZE],
-            fill:  0,
+            block: Vec::with_capacity(::core::cmp::min(block_size, DEFAULT_BLOCK_SIZE)),
+            block_size: block_size,
             freqs: [0; EOF + 1],
             tree: [Node{weight: 0, child0: 0, child1: 0, parent: 0, active: false};
                    2 * (EOF + 1) + 1],
+            lengths: [0; EOF + 1],
             codes: [(0, 0); EOF + 1],
+            total_in: 0,
         }
     }
 
+    /// Create a new writer which collects all input until it is
+    /// flushed, and codes it as a single block.
+    pub fn whole_file(inner: W) -> Self {
+        Writer::with_block_size(inner, usize::max_value())
+    }
+
     fn reset(&mut self) {
         for i in 0..self.freqs.len() {
             self.freqs[i] = 0;
@@ -67,6 +149,7 @@ impl<W: Write> Writer<W> {
             self.tree[i].active = false;
         }
         for i in 0..self.codes.len() {
+            self.lengths[i] = 0;
             self.codes[i].0 = 0;
             self.codes[i].1 = 0;
         }
@@ -78,8 +161,8 @@ impl<W: Write> Writer<W> {
         }
         self.freqs[EOF] = 1;
         self.freqs[EOB] = 1;
-        for i in 0..self.fill {
-            self.freqs[self.block[i] as usize] += 1;
+        for &c in self.block.iter() {
+            self.freqs[c as usize] += 1;
         }
         let mut max_count = 0;
         for i in 0..EOF {
@@ -108,7 +191,7 @@ impl<W: Write> Writer<W> {
             }
         }
         let last_node_idx = 2 * (EOF + 1);
-        self.tree[last_node_idx].weight = 0xffff;
+        self.tree[last_node_idx].weight = usize::max_value();
         let mut next_free = EOF + 1;
         loop {
             let mut min1 = last_node_idx;
@@ -172,66 +255,49 @@ impl<W: Write> Writer<W> {
     //     }
     // }
 
-    fn calc_code(&self, root: usize, sym: Symbol) -> (u64, usize) {
+    fn calc_code_len(&self, root: usize, sym: Symbol) -> usize {
         let mut node = sym as usize;
-        let mut code = 0u64;
         let mut code_len = 0;
         while node != root {
-            code >>= 1;
-            if self.tree[self.tree[node].parent].child1 == node {
-                code |= 1 << 63;
-            }
             code_len += 1;
             node = self.tree[node].parent;
         }
-        code >>= 64 - code_len;
-        assert!(code_len < 20);
-        (code, code_len)
+        assert!(code_len <= MAX_CODE_LEN);
+        code_len
     }
 
     fn calc_codes(&mut self, root: usize) {
         for i in 0..EOF+1 {
             if self.tree[i].weight > 0 {
-                self.codes[i] = self.calc_code(root, i as Symbol);
+                self.lengths[i] = self.calc_code_len(root, i as Symbol);
             }
         }
+        self.codes = canonical_codes(&self.lengths);
     }
 
-    fn write_freqs(&mut self) -> io::Result<()> {
-        let mut first = 0;
-        while first < 255 && self.freqs[first] == 0 {
-            first += 1;
-        }
-            
-        while first < 256 {
-            let mut last = first + 1;
-            let mut next;
-            loop {
-                while last < 256 && self.freqs[last] != 0 {
-                    last += 1;
-                }
-                last -= 1;
-                next = last + 1;
-                while next < 256 && self.freqs[next] == 0 {
-                    next += 1;
-                }
-                if next == 256 {
-                    break;
-                }
-                if next - last > 3 {
-                    break;
-                }
-                last = next;
+    fn write_lengths(&mut self) -> io::Result<()> {
+        let mut i = 0;
+        while i <= EOF {
+            let l = self.lengths[i];
+            let mut run = 1;
+            while i + run <= EOF && self.lengths[i + run] == l {
+                run += 1;
             }
-            try!(self.inner.write_bits(first as u64, 8));
-            try!(self.inner.write_bits(last as u64, 8));
-            for i in first..last + 1 {
-                try!(self.inner.write_bits(self.freqs[i] as u64, 16));
+            if l == 0 && run >= MIN_RUN {
+                let run = ::core::cmp::min(run, MAX_ZERO_RUN);
+                try!(self.inner.write_bits(ZERO_RUN, LEN_BITS));
+                try!(self.inner.write_bits((run - MIN_RUN) as u64, 8));
+                i += run;
+            } else if i > 0 && self.lengths[i - 1] == l && run >= MIN_RUN {
+                let run = ::core::cmp::min(run, MAX_REPEAT);
+                try!(self.inner.write_bits(REPEAT, LEN_BITS));
+                try!(self.inner.write_bits((run - MIN_RUN) as u64, 4));
+                i += run;
+            } else {
+                try!(self.inner.write_bits(l as u64, LEN_BITS));
+                i += 1;
             }
-            first = next;
         }
-        try!(self.inner.write_bits(0, 8));
-        
         Ok(())
     }
     
@@ -242,31 +308,27 @@ impl<W: Write> Writer<W> {
 
         self.calc_codes(root);
 
-        try!(self.write_freqs());
+        try!(self.write_lengths());
         
-        for i in 0..self.fill {
-            let c = self.block[i];
+        for &c in self.block.iter() {
             let (code, code_len) = self.codes[c as usize];
             try!(self.inner.write_bits(code, code_len));
         }
         let marker = if final_block { EOF } else { EOB };
         let (code, code_len) = self.codes[marker as usize];
         try!(self.inner.write_bits(code, code_len));
-        self.fill = 0;
+        self.block.clear();
         Ok(())
     }
     
     fn process(&mut self, input: &[u8]) -> io::Result<usize> {
         let mut input_ptr = 0;
         while input_ptr < input.len() {
-            let space = BLOCK_SIZE - self.fill;
-            let cp = ::std::cmp::min(space, input.len() - input_ptr);
-            for i in 0..cp {
-                self.block[self.fill + i] = input[input_ptr + i];
-            }
-            self.fill += cp;
+            let space = self.block_size - self.block.len();
+            let cp = ::core::cmp::min(space, input.len() - input_ptr);
+            self.block.extend_from_slice(&input[input_ptr..input_ptr + cp]);
             input_ptr += cp;
-            if self.fill == BLOCK_SIZE {
+            if self.block.len() == self.block_size {
                 try!(self.process_block(false));
             }
         }
@@ -274,6 +336,17 @@ impl<W: Write> Writer<W> {
         Ok(input_ptr)
     }
 
+    /// Return the number of uncompressed bytes written so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Return the number of compressed bytes written to the wrapped
+    /// writer so far.
+    pub fn total_out(&self) -> u64 {
+        self.inner.bytes_written()
+    }
+
     pub fn into_inner(self) -> W {
         self.inner.to_inner()
     }
@@ -281,7 +354,9 @@ impl<W: Write> Writer<W> {
 
 impl<W: Write> Write for Writer<W> {
     fn write(&mut self, input: &[u8]) -> io::Result<usize> {
-        self.process(input)
+        let n = try!(self.process(input));
+        self.total_in += n as u64;
+        Ok(n)
     }
 
     /// Flush the compression writer.  This will cause all not-yet
@@ -293,128 +368,174 @@ impl<W: Write> Write for Writer<W> {
     }
 }
 
+/// Entry of the decoding table.
+#[derive(Clone, Copy)]
+enum Entry {
+    /// No code starts with these bits.
+    Invalid,
+    /// Decoded symbol, and the number of bits of its code covered by
+    /// this table.
+    Symbol(Symbol, usize),
+    /// The codes continue in the subtable at the given offset, which
+    /// is indexed by the given number of bits.
+    Link(usize, usize),
+}
+
 pub struct Reader<R> {
     inner: BitReader<R>,
-    freqs: [usize; EOF + 1],
-    tree:  [Node; 2 * (EOF + 1) + 1],
-    root: usize,
+    lengths: [usize; EOF + 1],
+    /// Primary decoding table, indexed by the next `PRIMARY_BITS`
+    /// bits, followed by the subtables.
+    table: Vec<Entry>,
     in_block: bool,
     eof: bool,
+    total_out: u64,
+    recorder: Recorder,
 }
 
 impl<R: Read> Reader<R> {
     pub fn new(inner: R) -> Reader<R> {
         Reader {
             inner: BitReader::new(inner),
-            freqs: [0; EOF + 1],
-            tree: [Node{weight: 0, child0: 0, child1: 0, parent: 0, active: false};
-                   2 * (EOF + 1) + 1],
-            root: 0,
+            lengths: [0; EOF + 1],
+            table: Vec::new(),
             in_block: false,
             eof: false,
+            total_out: 0,
+            recorder: Recorder::new(),
         }
     }
 
-    fn reset(&mut self) {
-        for i in self.freqs.iter_mut() {
-            *i = 0;
-        }
-        for i in self.tree.iter_mut() {
-            i.weight = 0;
-            i.child0 = 0;
-            i.child1 = 0;
-            i.parent = 0;
-            i.active = false;
+    /// Return the number of compressed bytes read so far.
+    pub fn total_in(&self) -> u64 {
+        self.inner.bytes_read()
+    }
+
+    /// Return the number of decompressed bytes read so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    fn read_lengths(&mut self) -> io::Result<()> {
+        let mut i = 0;
+        while i <= EOF {
+            let l = try!(self.inner.read_bits(LEN_BITS));
+            let (len, run) =
+                if l == ZERO_RUN {
+                    (0, try!(self.inner.read_bits(8)) as usize + MIN_RUN)
+                } else if l == REPEAT && i > 0 {
+                    (self.lengths[i - 1], try!(self.inner.read_bits(4)) as usize + MIN_RUN)
+                } else if l as usize <= MAX_CODE_LEN {
+                    (l as usize, 1)
+                } else {
+                    return Err(Error::corrupt_at(self.inner.position(),
+                                                 "invalid code length").into());
+                };
+            if i + run > EOF + 1 {
+                return Err(Error::corrupt_at(self.inner.position(),
+                                             "too many code lengths").into());
+            }
+            for j in i..i + run {
+                self.lengths[j] = len;
+            }
+            i += run;
         }
+        Ok(())
     }
-    
-    fn build_tree(&mut self) -> usize {
-        for i in 0..EOF + 1 {
-            if self.freqs[i] > 0 {
-                self.tree[i].weight = self.freqs[i];
-                self.tree[i].active = true;
-            } else {
-                self.tree[i].active = false;
+
+    /// Build the decoding table for the canonical code given by the
+    /// code lengths.  A code of at most `PRIMARY_BITS` bits fills all
+    /// primary entries starting with it.  Longer codes are grouped by
+    /// their first `PRIMARY_BITS` bits, and each group gets a
+    /// subtable just large enough for its longest code.
+    fn build_table(&mut self) -> io::Result<()> {
+        let mut count = [0; MAX_CODE_LEN + 1];
+        for &l in self.lengths.iter() {
+            count[l] += 1;
+        }
+        count[0] = 0;
+
+        // Check that the code is not over-subscribed.
+        let mut left = 1;
+        for len in 1..MAX_CODE_LEN + 1 {
+            left <<= 1;
+            if count[len] > left {
+                return Err(Error::corrupt_at(self.inner.position(),
+                                             "invalid Huffman code").into());
             }
+            left -= count[len];
         }
-        let last_node_idx = 2 * (EOF + 1);
-        self.tree[last_node_idx].weight = 0xffff;
-        let mut next_free = EOF + 1;
-        loop {
-            let mut min1 = last_node_idx;
-            let mut min2 = last_node_idx;
 
-            for i in 0..next_free {
-                if self.tree[i].active {
-                    if self.tree[i].weight < self.tree[min1].weight {
-                        min2 = min1;
-                        min1 = i;
-                    } else if self.tree[i].weight < self.tree[min2].weight {
-                        min2 = i;
-                    }
+        let codes = canonical_codes(&self.lengths);
+
+        let mut sub_bits = [0; 1 << PRIMARY_BITS];
+        for &(code, len) in codes.iter() {
+            if len > PRIMARY_BITS {
+                let prefix = (code >> (len - PRIMARY_BITS)) as usize;
+                if len - PRIMARY_BITS > sub_bits[prefix] {
+                    sub_bits[prefix] = len - PRIMARY_BITS;
                 }
             }
-            if min2 == last_node_idx {
-                break;
-            }
-            self.tree[next_free].weight =
-                self.tree[min1].weight + self.tree[min2].weight;
-            self.tree[next_free].active = true;
-            self.tree[min1].active = false;
-            self.tree[min2].active = false;
-            self.tree[min1].parent = next_free;
-            self.tree[min2].parent = next_free;
-            self.tree[next_free].child0 = min1;
-            self.tree[next_free].child1 = min2;
-            next_free += 1;
         }
-        next_free - 1
-    }
 
-    fn decode(&mut self, root: usize) -> io::Result<Symbol> {
-        let mut n = root;
-        while n > EOF {
-            let b = try!(self.inner.read_bits(1)) as usize;
-            if b == 0 {
-                n = self.tree[n].child0;
-            } else {
-                n = self.tree[n].child1;
+        self.table.clear();
+        self.table.resize(1 << PRIMARY_BITS, Entry::Invalid);
+        for prefix in 0..1 << PRIMARY_BITS {
+            if sub_bits[prefix] > 0 {
+                self.table[prefix] = Entry::Link(self.table.len(), sub_bits[prefix]);
+                let size = self.table.len() + (1 << sub_bits[prefix]);
+                self.table.resize(size, Entry::Invalid);
             }
         }
-        Ok(n as Symbol)
-    }
 
-    // fn dump_freqs(&self) {
-    //     for i in 0..EOF + 1 {
-    //         if self.freqs[i] > 0 {
-    //             match i {
-    //                 EOB => println!("EOB: {}", self.freqs[i]),
-    //                 EOF => println!("EOF: {}", self.freqs[i]),
-    //                 _ =>
-    //                     println!("{:3} {:?}: {}", i, (i as u8) as char, self.freqs[i]),
-    //             }
-    //         }
-    //     }
-    // }
-    
-    fn read_freqs(&mut self) -> io::Result<()> {
-        let mut first = try!(self.inner.read_bits(8)) as usize;
-        let mut last  = try!(self.inner.read_bits(8)) as usize;
-        loop {
-            for i in first..last + 1 {
-                let freq  = try!(self.inner.read_bits(16)) as usize;
-                self.freqs[i] = freq;
+        for (sym, &(code, len)) in codes.iter().enumerate() {
+            if len == 0 {
+                continue;
             }
-            first = try!(self.inner.read_bits(8)) as usize;
-            if first == 0 {
-                break;
+            let code = code as usize;
+            let (start, fill, entry_len) =
+                if len <= PRIMARY_BITS {
+                    (code << (PRIMARY_BITS - len), PRIMARY_BITS - len, len)
+                } else {
+                    let sub_len = len - PRIMARY_BITS;
+                    match self.table[code >> sub_len] {
+                        Entry::Link(offset, bits) => {
+                            let low = code & ((1 << sub_len) - 1);
+                            (offset + (low << (bits - sub_len)), bits - sub_len, sub_len)
+                        },
+                        _ => unreachable!(),
+                    }
+                };
+            for e in &mut self.table[start..start + (1 << fill)] {
+                *e = Entry::Symbol(sym as Symbol, entry_len);
             }
-            last  = try!(self.inner.read_bits(8)) as usize;
         }
-        self.freqs[EOB] = 1;
-        self.freqs[EOF] = 1;
         Ok(())
     }
+
+    /// Decode one symbol with one lookup in the primary table, and
+    /// for long codes, a second one in a subtable.
+    fn decode(&mut self) -> io::Result<Symbol> {
+        let bits = try!(self.inner.peek_bits(PRIMARY_BITS)) as usize;
+        let (sym, len) =
+            match self.table[bits] {
+                Entry::Symbol(sym, len) => (sym, len),
+                Entry::Link(offset, sub_bits) => {
+                    try!(self.inner.consume_bits(PRIMARY_BITS));
+                    let bits = try!(self.inner.peek_bits(sub_bits)) as usize;
+                    match self.table[offset + bits] {
+                        Entry::Symbol(sym, len) => (sym, len),
+                        _ => return Err(Error::corrupt_at(self.inner.position(),
+                                                          "invalid Huffman code").into()),
+                    }
+                },
+                Entry::Invalid =>
+                    return Err(Error::corrupt_at(self.inner.position(),
+                                                 "invalid Huffman code").into()),
+            };
+        try!(self.inner.consume_bits(len));
+        Ok(sym)
+    }
     
     fn process(&mut self, output: &mut [u8]) -> io::Result<usize> {
 
@@ -426,22 +547,25 @@ impl<R: Read> Reader<R> {
         'outer:
         while written < output.len() {
             if !self.in_block {
-                self.reset();
-                try!(self.read_freqs());
-                self.root = self.build_tree();
+                let start = self.inner.position();
+                try!(self.read_lengths());
+                try!(self.build_table());
+                self.recorder.record(Event::Block(format!("at byte {}", start)));
+                self.recorder.record(Event::CodeLengths(self.lengths.to_vec()));
                 self.in_block = true;
             }
             'inner:
             loop {
-                let root = self.root;
-                let b = try!(self.decode(root));
+                let b = try!(self.decode());
                 if b as usize == EOF {
+                    self.recorder.record(Event::End);
                     self.eof = true;
                     break 'outer;
                 } else if b as usize == EOB {
                     self.in_block = false;
                     break 'inner;
                 } else {
+                    self.recorder.record(Event::Literal(b as u8));
                     output[written] = b as u8;
                     written += 1;
                     if written == output.len() {
@@ -456,7 +580,15 @@ impl<R: Read> Reader<R> {
 
 impl<R: Read> Read for Reader<R> {
     fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
-        self.process(output)
+        let n = try!(self.process(output).map_err(|e| at_offset(e, self.total_in())));
+        self.total_out += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read> Inspect for Reader<R> {
+    fn recorder(&mut self) -> &mut Recorder {
+        &mut self.recorder
     }
 }
 
@@ -467,16 +599,61 @@ pub fn compress<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error>
     Ok(cw.into_inner())
 }
 
+/// Compress `input` with a single set of codes for the whole input.
+/// The result is decompressed with `decompress`.
+pub fn compress_whole_file<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error> {
+    let mut cw = Writer::whole_file(output);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.into_inner())
+}
+
 pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<W, Error> {
     let mut cr = Reader::new(input);
     try!(io::copy(&mut cr, &mut output));
     Ok(output)
 }
 
-#[cfg(test)]
+/// Return an iterator over the blocks, their code lengths and the
+/// literals of the stream in `input`.  The code lengths are indexed
+/// by symbol, where 256 is the end-of-block and 257 the end-of-file
+/// symbol.
+pub fn inspect<R: Read>(input: R) -> Events<Reader<R>> {
+    Events::new(Reader::new(input))
+}
+
+#[cfg(all(test, feature = "std"))]
 mod test {
     use ::std::io::{Cursor, Write, Read};
-    use super::{Writer, Reader};
+    use super::{Writer, Reader, canonical_codes, compress_whole_file, decompress,
+                EOF, LEN_BITS, ZERO_RUN};
+    use bitfile::BitWriter;
+
+    #[test]
+    fn canonical() {
+        let mut lengths = [0; EOF + 1];
+        lengths[..8].copy_from_slice(&[3, 3, 3, 3, 3, 2, 4, 4]);
+        let codes = canonical_codes(&lengths);
+        assert_eq!([(0b010, 3), (0b011, 3), (0b100, 3), (0b101, 3), (0b110, 3),
+                    (0b00, 2), (0b1110, 4), (0b1111, 4)],
+                   codes[..8]);
+        assert_eq!((0, 0), codes[8]);
+    }
+
+    #[test]
+    fn decompress_oversubscribed() {
+        let mut bw = BitWriter::new(vec![]);
+        for _ in 0..3 {
+            bw.write_bits(1, LEN_BITS).unwrap();
+        }
+        bw.write_bits(ZERO_RUN, LEN_BITS).unwrap();
+        bw.write_bits(252, 8).unwrap();
+        bw.flush().unwrap();
+        let input = bw.to_inner();
+        let mut cr = Reader::new(Cursor::new(input));
+        let mut decompressed = Vec::new();
+        assert!(cr.read_to_end(&mut decompressed).is_err());
+    }
     
     #[test]
     fn compress_empty() {
@@ -485,7 +662,7 @@ mod test {
         cw.write(&input[..]).unwrap();
         cw.flush().unwrap();
         let compressed = cw.into_inner();
-        let expected = [255, 255, 0, 0, 0, 128];
+        let expected = [247, 232, 67];
         assert_eq!(&expected[..], &compressed[..]);
     }
 
@@ -496,7 +673,7 @@ mod test {
         cw.write(&input[..]).unwrap();
         cw.flush().unwrap();
         let compressed = cw.into_inner();
-        let expected = [97, 97, 0, 1, 0, 128];
+        let expected = [242, 240, 189, 54, 32, 192];
         assert_eq!(&expected[..], &compressed[..]);
     }
 
@@ -507,7 +684,7 @@ mod test {
         cw.write(&input[..]).unwrap();
         cw.flush().unwrap();
         let compressed = cw.into_inner();
-        let expected = [97, 97, 0, 9, 0, 255, 160];
+        let expected = [242, 240, 125, 54, 33, 0, 48];
         assert_eq!(&expected[..], &compressed[..]);
     }
 
@@ -533,38 +710,37 @@ mod test {
         cw.flush().unwrap();
         let compressed = cw.into_inner();
         let expected =
-            [32, 32, 0, 99, 44, 46, 0, 8, 0, 0, 0, 6, 65, 65, 0, 2, 76, 76, 0,
-             4, 83, 83, 0, 2, 97, 121, 0, 44, 0, 6, 0, 12, 0, 26, 0, 56, 0, 0,
-             0, 8, 0, 0, 0, 30, 0, 2, 0, 4, 0, 18, 0, 32, 0, 20, 0, 42, 0, 10,
-             0, 2, 0, 32, 0, 36, 0, 50, 0, 30, 0, 6, 0, 0, 0, 0, 0, 4, 0, 207,
-             166, 11, 207, 8, 69, 229, 82, 166, 240, 123, 237, 70, 185, 241,
-             169, 192, 209, 168, 222, 44, 143, 8, 196, 230, 239, 18, 61, 103,
-             60, 2, 228, 118, 190, 117, 52, 87, 188, 27, 45, 23, 208, 184, 83,
-             115, 158, 99, 36, 158, 244, 223, 43, 203, 76, 56, 222, 85, 42, 97,
-             214, 221, 157, 251, 145, 191, 163, 219, 94, 26, 245, 207, 0, 185,
-             29, 175, 205, 82, 76, 53, 47, 39, 124, 223, 152, 53, 113, 81, 198,
-             251, 199, 20, 139, 94, 55, 191, 36, 109, 114, 74, 229, 82, 166,
-             17, 198, 241, 125, 134, 84, 92, 157, 247, 70, 252, 100, 123, 125,
-             229, 193, 119, 83, 40, 103, 88, 77, 207, 58, 240, 47, 237, 188,
-             53, 189, 191, 23, 60, 117, 35, 136, 223, 159, 76, 23, 158, 16,
-             139, 202, 165, 77, 224, 247, 218, 141, 201, 243, 233, 130, 243,
-             194, 17, 121, 84, 169, 188, 30, 251, 81, 174, 124, 106, 112, 52,
-             106, 55, 139, 35, 194, 49, 57, 187, 196, 143, 89, 207, 0, 185,
-             29, 175, 157, 77, 21, 239, 6, 203, 69, 244, 46, 20, 220, 231,
-             152, 201, 39, 189, 55, 202, 242, 211, 14, 55, 149, 74, 152, 117,
-             183, 103, 126, 228, 111, 232, 246, 215, 134, 189, 115, 192, 46,
-             71, 107, 243, 84, 147, 13, 75, 201, 223, 55, 230, 13, 92, 84,
-             113, 190, 241, 197, 34, 215, 141, 239, 201, 27, 92, 146, 185,
-             84, 169, 132, 113, 188, 95, 97, 149, 23, 39, 125, 209, 191, 25,
-             30, 223, 121, 112, 93, 212, 202, 25, 214, 19, 115, 206, 188, 11,
-             251, 111, 13, 111, 111, 197, 207, 29, 72, 226, 55, 231, 211, 5,
-             231, 132, 34, 242, 169, 83, 120, 61, 246, 163, 114, 103, 64];
+            [240, 232, 252, 16, 96, 31, 193, 232, 240, 58, 60, 6, 143, 5, 16,
+             230, 32, 192, 96, 17, 7, 41, 10, 67, 32, 159, 3, 128, 7, 244,
+             26, 83, 247, 18, 92, 55, 117, 142, 22, 53, 18, 41, 172, 35, 155,
+             224, 115, 27, 163, 102, 242, 69, 34, 183, 117, 203, 111, 160,
+             116, 215, 60, 10, 42, 21, 145, 198, 227, 120, 255, 1, 105, 120,
+             81, 101, 251, 137, 13, 191, 108, 185, 187, 25, 99, 73, 230, 36,
+             131, 97, 99, 81, 36, 58, 117, 180, 9, 166, 254, 207, 136, 224,
+             202, 95, 2, 138, 133, 100, 113, 238, 53, 157, 222, 39, 129, 245,
+             99, 217, 152, 6, 40, 27, 9, 207, 57, 72, 224, 216, 253, 202,
+             184, 11, 144, 22, 53, 18, 104, 27, 5, 4, 159, 56, 254, 7, 229,
+             155, 28, 233, 173, 7, 164, 165, 29, 103, 147, 61, 73, 223, 3,
+             112, 40, 160, 180, 244, 206, 150, 130, 147, 124, 222, 80, 53,
+             99, 238, 36, 184, 110, 235, 28, 44, 106, 36, 83, 88, 71, 55,
+             224, 125, 196, 151, 13, 221, 99, 133, 141, 68, 138, 107, 8, 230,
+             248, 28, 198, 232, 217, 188, 145, 72, 173, 221, 114, 219, 232,
+             29, 53, 207, 2, 138, 133, 100, 113, 184, 222, 63, 192, 90, 94,
+             20, 89, 126, 226, 67, 111, 219, 46, 110, 198, 88, 210, 121, 137,
+             32, 216, 88, 212, 73, 14, 157, 109, 2, 105, 191, 179, 226, 56,
+             50, 151, 192, 162, 161, 89, 28, 123, 141, 103, 119, 137, 224,
+             125, 88, 246, 102, 1, 138, 6, 194, 115, 206, 82, 56, 54, 63,
+             114, 174, 2, 228, 5, 141, 68, 154, 6, 193, 65, 39, 206, 63, 129,
+             249, 102, 199, 58, 107, 65, 233, 41, 71, 89, 228, 207, 82, 119,
+             192, 220, 10, 40, 45, 61, 51, 165, 160, 164, 223, 55, 148, 13,
+             88, 251, 137, 46, 27, 186, 199, 11, 26, 137, 20, 214, 17, 205,
+             248, 255, 128];
         assert_eq!(&expected[..], &compressed[..]);
     }
 
     #[test]
     fn decompress_empty() {
-        let input = [255, 255, 0, 0, 0, 128];
+        let input = [247, 232, 67];
         let mut cr = Reader::new(Cursor::new(input));
         let mut decompressed = Vec::new();
         let _ = cr.read_to_end(&mut decompressed).unwrap();
@@ -574,7 +750,7 @@ mod test {
 
     #[test]
     fn decompress_a() {
-        let input = [97, 97, 0, 1, 0, 128];
+        let input = [242, 240, 189, 54, 32, 192];
         let mut cr = Reader::new(Cursor::new(input));
         let mut decompressed = Vec::new();
         let _ = cr.read_to_end(&mut decompressed).unwrap();
@@ -584,7 +760,7 @@ mod test {
 
     #[test]
     fn decompress_aaa() {
-        let input = [97, 97, 0, 9, 0, 255, 160];
+        let input = [242, 240, 125, 54, 33, 0, 48];
         let mut cr = Reader::new(Cursor::new(input));
         let mut decompressed = Vec::new();
         let _ = cr.read_to_end(&mut decompressed).unwrap();
@@ -595,32 +771,31 @@ mod test {
     #[test]
     fn decompress_lorem() {
         let input =
-            [32, 32, 0, 99, 44, 46, 0, 8, 0, 0, 0, 6, 65, 65, 0, 2, 76, 76, 0,
-             4, 83, 83, 0, 2, 97, 121, 0, 44, 0, 6, 0, 12, 0, 26, 0, 56, 0, 0,
-             0, 8, 0, 0, 0, 30, 0, 2, 0, 4, 0, 18, 0, 32, 0, 20, 0, 42, 0, 10,
-             0, 2, 0, 32, 0, 36, 0, 50, 0, 30, 0, 6, 0, 0, 0, 0, 0, 4, 0, 207,
-             166, 11, 207, 8, 69, 229, 82, 166, 240, 123, 237, 70, 185, 241,
-             169, 192, 209, 168, 222, 44, 143, 8, 196, 230, 239, 18, 61, 103,
-             60, 2, 228, 118, 190, 117, 52, 87, 188, 27, 45, 23, 208, 184, 83,
-             115, 158, 99, 36, 158, 244, 223, 43, 203, 76, 56, 222, 85, 42, 97,
-             214, 221, 157, 251, 145, 191, 163, 219, 94, 26, 245, 207, 0, 185,
-             29, 175, 205, 82, 76, 53, 47, 39, 124, 223, 152, 53, 113, 81, 198,
-             251, 199, 20, 139, 94, 55, 191, 36, 109, 114, 74, 229, 82, 166,
-             17, 198, 241, 125, 134, 84, 92, 157, 247, 70, 252, 100, 123, 125,
-             229, 193, 119, 83, 40, 103, 88, 77, 207, 58, 240, 47, 237, 188,
-             53, 189, 191, 23, 60, 117, 35, 136, 223, 159, 76, 23, 158, 16,
-             139, 202, 165, 77, 224, 247, 218, 141, 201, 243, 233, 130, 243,
-             194, 17, 121, 84, 169, 188, 30, 251, 81, 174, 124, 106, 112, 52,
-             106, 55, 139, 35, 194, 49, 57, 187, 196, 143, 89, 207, 0, 185,
-             29, 175, 157, 77, 21, 239, 6, 203, 69, 244, 46, 20, 220, 231,
-             152, 201, 39, 189, 55, 202, 242, 211, 14, 55, 149, 74, 152, 117,
-             183, 103, 126, 228, 111, 232, 246, 215, 134, 189, 115, 192, 46,
-             71, 107, 243, 84, 147, 13, 75, 201, 223, 55, 230, 13, 92, 84,
-             113, 190, 241, 197, 34, 215, 141, 239, 201, 27, 92, 146, 185,
-             84, 169, 132, 113, 188, 95, 97, 149, 23, 39, 125, 209, 191, 25,
-             30, 223, 121, 112, 93, 212, 202, 25, 214, 19, 115, 206, 188, 11,
-             251, 111, 13, 111, 111, 197, 207, 29, 72, 226, 55, 231, 211, 5,
-             231, 132, 34, 242, 169, 83, 120, 61, 246, 163, 114, 103, 64];
+            [240, 232, 252, 16, 96, 31, 193, 232, 240, 58, 60, 6, 143, 5, 16,
+             230, 32, 192, 96, 17, 7, 41, 10, 67, 32, 159, 3, 128, 7, 244,
+             26, 83, 247, 18, 92, 55, 117, 142, 22, 53, 18, 41, 172, 35, 155,
+             224, 115, 27, 163, 102, 242, 69, 34, 183, 117, 203, 111, 160,
+             116, 215, 60, 10, 42, 21, 145, 198, 227, 120, 255, 1, 105, 120,
+             81, 101, 251, 137, 13, 191, 108, 185, 187, 25, 99, 73, 230, 36,
+             131, 97, 99, 81, 36, 58, 117, 180, 9, 166, 254, 207, 136, 224,
+             202, 95, 2, 138, 133, 100, 113, 238, 53, 157, 222, 39, 129, 245,
+             99, 217, 152, 6, 40, 27, 9, 207, 57, 72, 224, 216, 253, 202,
+             184, 11, 144, 22, 53, 18, 104, 27, 5, 4, 159, 56, 254, 7, 229,
+             155, 28, 233, 173, 7, 164, 165, 29, 103, 147, 61, 73, 223, 3,
+             112, 40, 160, 180, 244, 206, 150, 130, 147, 124, 222, 80, 53,
+             99, 238, 36, 184, 110, 235, 28, 44, 106, 36, 83, 88, 71, 55,
+             224, 125, 196, 151, 13, 221, 99, 133, 141, 68, 138, 107, 8, 230,
+             248, 28, 198, 232, 217, 188, 145, 72, 173, 221, 114, 219, 232,
+             29, 53, 207, 2, 138, 133, 100, 113, 184, 222, 63, 192, 90, 94,
+             20, 89, 126, 226, 67, 111, 219, 46, 110, 198, 88, 210, 121, 137,
+             32, 216, 88, 212, 73, 14, 157, 109, 2, 105, 191, 179, 226, 56,
+             50, 151, 192, 162, 161, 89, 28, 123, 141, 103, 119, 137, 224,
+             125, 88, 246, 102, 1, 138, 6, 194, 115, 206, 82, 56, 54, 63,
+             114, 174, 2, 228, 5, 141, 68, 154, 6, 193, 65, 39, 206, 63, 129,
+             249, 102, 199, 58, 107, 65, 233, 41, 71, 89, 228, 207, 82, 119,
+             192, 220, 10, 40, 45, 61, 51, 165, 160, 164, 223, 55, 148, 13,
+             88, 251, 137, 46, 27, 186, 199, 11, 26, 137, 20, 214, 17, 205,
+             248, 255, 128];
         let mut cr = Reader::new(Cursor::new(&input[..]));
         let mut decompressed = Vec::new();
         let _ = cr.read_to_end(&mut decompressed).unwrap();
@@ -656,4 +831,115 @@ mod test {
         
         assert_eq!(&input[..], &decompressed[..]);
     }
+
+    #[test]
+    fn compress_decompress_long_codes() {
+        // Fibonacci frequencies give the longest possible codes, so
+        // that the subtables of the decoding table get used.
+        let mut input = Vec::new();
+        let (mut a, mut b) = (1, 1);
+        for sym in 0..20 {
+            for _ in 0..a {
+                input.push(sym as u8);
+            }
+            let c = a + b;
+            a = b;
+            b = c;
+        }
+        let mut cw = Writer::new(vec![]);
+        cw.write_all(&input[..]).unwrap();
+        cw.flush().unwrap();
+        let compressed = cw.into_inner();
+
+        let mut cr = Reader::new(Cursor::new(&compressed[..]));
+        let mut decompressed = Vec::new();
+        cr.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(input, decompressed);
+    }
+
+    #[test]
+    fn block_size() {
+        let input = include_bytes!("block.rs");
+        let mut sizes = Vec::new();
+        for &block_size in &[1, 100, 4096] {
+            let mut cw = Writer::with_block_size(vec![], block_size);
+            cw.write_all(&input[..]).unwrap();
+            cw.flush().unwrap();
+            let compressed = cw.into_inner();
+            sizes.push(compressed.len());
+
+            let mut cr = Reader::new(Cursor::new(&compressed[..]));
+            let mut decompressed = Vec::new();
+            cr.read_to_end(&mut decompressed).unwrap();
+            assert_eq!(&input[..], &decompressed[..]);
+        }
+        assert!(sizes[0] > sizes[1]);
+        assert!(sizes[1] > sizes[2]);
+    }
+
+    #[test]
+    fn whole_file() {
+        let mut input = Vec::new();
+        while input.len() < 300 * 1024 {
+            input.extend_from_slice(&include_bytes!("block.rs")[..]);
+        }
+        let blocks = super::compress(Cursor::new(&input[..]), vec![]).unwrap();
+        let whole = compress_whole_file(Cursor::new(&input[..]), vec![]).unwrap();
+        assert!(whole.len() < blocks.len());
+
+        let decompressed = decompress(Cursor::new(&whole[..]), vec![]).unwrap();
+        assert_eq!(input, decompressed);
+    }
+
+    #[test]
+    fn inspect_events() {
+        use inspect::Event;
+        let mut cw = Writer::with_block_size(vec![], 2);
+        cw.write_all(b"aab").unwrap();
+        cw.flush().unwrap();
+        let compressed = cw.into_inner();
+        let events: Vec<Event> =
+            super::inspect(Cursor::new(&compressed[..])).map(|e| e.unwrap()).collect();
+        assert_eq!(8, events.len());
+        match (&events[0], &events[1]) {
+            (&Event::Block(_), &Event::CodeLengths(ref lengths)) => {
+                assert!(lengths[b'a' as usize] > 0);
+                assert_eq!(0, lengths[b'b' as usize]);
+            },
+            _ => panic!("unexpected events {:?}", events),
+        }
+        assert_eq!(&[Event::Literal(b'a'), Event::Literal(b'a')][..], &events[2..4]);
+        match events[5] {
+            Event::CodeLengths(ref lengths) => assert!(lengths[b'b' as usize] > 0),
+            _ => panic!("unexpected events {:?}", events),
+        }
+        assert_eq!(&[Event::Literal(b'b'), Event::End][..], &events[6..]);
+    }
+
+    #[test]
+    fn large_blocks() {
+        let mut rng = ::testutil::Rng::new(1);
+        let input = ::testutil::random(&mut rng, 200 * 1024);
+
+        let whole = compress_whole_file(Cursor::new(&input[..]), vec![]).unwrap();
+        assert!(whole.len() < input.len() + 1024);
+        let decompressed = decompress(Cursor::new(&whole[..]), vec![]).unwrap();
+        assert_eq!(input, decompressed);
+
+        let mut cw = Writer::with_block_size(vec![], 128 * 1024);
+        cw.write_all(&input[..]).unwrap();
+        cw.flush().unwrap();
+        let compressed = cw.into_inner();
+        assert!(compressed.len() < input.len() + 1024);
+        let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(input, decompressed);
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+        ::testutil::roundtrip((|d: &[u8]| super::compress_whole_file(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+    }
 }
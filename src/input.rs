@@ -0,0 +1,142 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Buffered byte input for decoders.
+//!
+//! Many decoders consume their input one byte at a time.  Reading
+//! each byte with a separate `read` call is slow when the underlying
+//! reader is not buffered, for example a `File`, so `ByteReader`
+//! reads ahead in blocks.  Decoders which stop at an end marker use
+//! `ByteReader::exact`, which never reads ahead, so that the data
+//! following the stream stays unread, unless the caller owns the
+//! whole input and asks for a buffered reader.
+
+use io::{self, Read};
+use alloc::vec::Vec;
+
+const BUF_SIZE: usize = 4096;
+
+/// Byte-at-a-time reader with an internal buffer.
+pub struct ByteReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+    /// Number of bytes read into the buffer so far.
+    total: u64,
+}
+
+impl<R: Read> ByteReader<R> {
+    pub fn new(inner: R) -> ByteReader<R> {
+        ByteReader::with_capacity(inner, BUF_SIZE)
+    }
+
+    /// Create a reader which reads exactly the bytes that are
+    /// consumed, one `read` call per byte.
+    pub fn exact(inner: R) -> ByteReader<R> {
+        ByteReader::with_capacity(inner, 1)
+    }
+
+    fn with_capacity(inner: R, capacity: usize) -> ByteReader<R> {
+        ByteReader {
+            inner: inner,
+            buf: vec![0; capacity],
+            pos: 0,
+            len: 0,
+            total: 0,
+        }
+    }
+
+    /// Return the next byte, or `None` at the end of the input.
+    #[inline]
+    pub fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.pos == self.len && !try!(self.fill()) {
+            return Ok(None);
+        }
+        let b = self.buf[self.pos];
+        self.pos += 1;
+        Ok(Some(b))
+    }
+
+    /// Refill the buffer.  Returns false at the end of the input.
+    fn fill(&mut self) -> io::Result<bool> {
+        loop {
+            match self.inner.read(&mut self.buf) {
+                Ok(n) => {
+                    self.pos = 0;
+                    self.len = n;
+                    self.total += n as u64;
+                    return Ok(n > 0);
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Return the number of bytes consumed so far.  Bytes read ahead
+    /// into the buffer are not counted.
+    pub fn consumed(&self) -> u64 {
+        self.total - (self.len - self.pos) as u64
+    }
+
+    /// Return the offset of the next byte to be consumed, counted from
+    /// the start of the input.  This is the same as `consumed`, under
+    /// the name the decoders use for error positions.
+    pub fn position(&self) -> u64 {
+        self.consumed()
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+}
+
+impl<R: Read> Iterator for ByteReader<R> {
+    type Item = io::Result<u8>;
+
+    /// Like `Bytes`, return the next byte, `None` at the end of the
+    /// input, or an error.
+    fn next(&mut self) -> Option<io::Result<u8>> {
+        match self.next_byte() {
+            Ok(Some(b)) => Some(Ok(b)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use ::std::io::Cursor;
+    use super::ByteReader;
+
+    #[test]
+    fn read_ahead() {
+        let input: Vec<u8> = (0..10000).map(|i| i as u8).collect();
+        let mut br = ByteReader::new(Cursor::new(&input[..]));
+        for i in 0..5000 {
+            assert_eq!(Some(i as u8), br.next_byte().unwrap());
+        }
+        assert_eq!(5000, br.consumed());
+        assert_eq!(8192, br.get_ref().position());
+    }
+
+    #[test]
+    fn read_exact() {
+        let input: Vec<u8> = (0..10000).map(|i| i as u8).collect();
+        let mut br = ByteReader::exact(Cursor::new(&input[..]));
+        for i in 0..5000 {
+            assert_eq!(Some(i as u8), br.next_byte().unwrap());
+        }
+        assert_eq!(5000, br.consumed());
+        assert_eq!(5000, br.get_ref().position());
+    }
+
+    #[test]
+    fn end_of_input() {
+        let mut br = ByteReader::new(Cursor::new(&[1u8, 2][..]));
+        assert_eq!(vec![1, 2], br.by_ref().map(|b| b.unwrap()).collect::<Vec<u8>>());
+        assert_eq!(None, br.next_byte().unwrap());
+    }
+}
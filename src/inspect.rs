@@ -0,0 +1,170 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Structured inspection of compressed streams.
+//!
+//! Codecs which support inspection have an `inspect` function, which
+//! returns an iterator over the `Event`s of a compressed stream in
+//! stream order: headers, blocks, Huffman code lengths, literals,
+//! matches, dictionary codes and so on.
+//! Most of them are implemented by the codec's reader, which records
+//! events while decompressing once its `Recorder` is enabled.
+//! `Events` drives such a reader and hands out the recorded events.
+
+use alloc::collections::VecDeque;
+use core::fmt;
+use io::{self, Read};
+use alloc::vec::Vec;
+use alloc::string::String;
+
+/// Item of a compressed stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// Parameters taken from a stream header.
+    Header(String),
+    /// The start of a block, with its parameters.
+    Block(String),
+    /// The code lengths of a Huffman code, indexed by symbol.  Unused
+    /// symbols have length zero.
+    CodeLengths(Vec<usize>),
+    /// A literal byte.
+    Literal(u8),
+    /// A match of `len` bytes, copied from `offset` bytes back.
+    Match { offset: usize, len: usize },
+    /// A dictionary code and the string it stands for.
+    Code { code: u64, string: Vec<u8> },
+    /// The dictionary was cleared.
+    Clear,
+    /// The end of stream marker.
+    End,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Event::Header(ref s) => write!(f, "header {}", s),
+            Event::Block(ref s) => write!(f, "block {}", s),
+            Event::CodeLengths(ref lengths) => {
+                try!(write!(f, "code lengths"));
+                for (sym, &len) in lengths.iter().enumerate() {
+                    if len > 0 {
+                        try!(write!(f, " {}:{}", sym, len));
+                    }
+                }
+                Ok(())
+            },
+            Event::Literal(b) => write!(f, "literal {:?}", b as char),
+            Event::Match { offset, len } => write!(f, "match offset {} length {}", offset, len),
+            Event::Code { code, ref string } =>
+                write!(f, "code {:4} {:?}", code, String::from_utf8_lossy(string)),
+            Event::Clear => write!(f, "clear"),
+            Event::End => write!(f, "end"),
+        }
+    }
+}
+
+/// Queue of the events decoded by a reader.  Nothing is recorded
+/// unless the recorder is enabled.
+pub struct Recorder {
+    events: Option<VecDeque<Event>>,
+}
+
+#[cfg_attr(not(any(feature = "lz", feature = "arith", feature = "huffman")), allow(dead_code))]
+impl Recorder {
+    pub(crate) fn new() -> Recorder {
+        Recorder {
+            events: None,
+        }
+    }
+
+    /// Create a recorder which is already enabled, for readers which
+    /// decode a header before they are handed to `Events`.
+    #[cfg(all(feature = "std", feature = "lz"))]
+    pub(crate) fn enabled() -> Recorder {
+        Recorder {
+            events: Some(VecDeque::new()),
+        }
+    }
+
+    pub(crate) fn enable(&mut self) {
+        if self.events.is_none() {
+            self.events = Some(VecDeque::new());
+        }
+    }
+
+    #[inline]
+    pub(crate) fn record(&mut self, event: Event) {
+        if let Some(ref mut events) = self.events {
+            events.push_back(event);
+        }
+    }
+
+    /// Return the recorded events, for codecs which decode all their
+    /// input at once.
+    #[cfg(all(feature = "std", feature = "lz"))]
+    pub(crate) fn into_events(self) -> Vec<Event> {
+        match self.events {
+            Some(events) => events.into_iter().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn pop(&mut self) -> Option<Event> {
+        match self.events {
+            Some(ref mut events) => events.pop_front(),
+            None => None,
+        }
+    }
+}
+
+/// Readers which record the events they decode.
+pub trait Inspect: Read {
+    fn recorder(&mut self) -> &mut Recorder;
+}
+
+/// Size of the buffer for the data decompressed while inspecting.
+/// It is kept small, so that few events are queued at a time.
+const BUFFER_SIZE: usize = 256;
+
+/// Iterator over the events of the stream read by an `Inspect`
+/// reader.  The decompressed data is discarded.
+pub struct Events<R> {
+    reader: R,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Inspect> Events<R> {
+    /// Create an iterator over the events decoded by `reader`.
+    pub fn new(mut reader: R) -> Events<R> {
+        reader.recorder().enable();
+        Events {
+            reader: reader,
+            buf: vec![0; BUFFER_SIZE],
+            done: false,
+        }
+    }
+}
+
+impl<R: Inspect> Iterator for Events<R> {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<io::Result<Event>> {
+        loop {
+            if let Some(event) = self.reader.recorder().pop() {
+                return Some(Ok(event));
+            }
+            if self.done {
+                return None;
+            }
+            match self.reader.read(&mut self.buf) {
+                Ok(0) => self.done = true,
+                Ok(_) => (),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,251 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Byte-oriented I/O for the codecs.
+//!
+//! With the `std` feature, which is on by default, this module simply
+//! re-exports the types from `std::io`, so the codecs work with any
+//! standard reader or writer.
+//!
+//! Without it, the crate is `no_std` and needs only `alloc`.  This
+//! module then provides a minimal `Read` and `Write` with the methods
+//! the codecs use, an `Error` carrying a kind and a static message,
+//! and implementations for byte slices and `Vec<u8>`.  On a
+//! microcontroller, implement `Read` and `Write` for the device
+//! buffers to stream through the codecs.
+
+#[cfg(feature = "std")]
+pub use std::io::{copy, BufRead, Bytes, Error, ErrorKind, Read, Result, Write};
+
+#[cfg(not(feature = "std"))]
+pub use self::imp::*;
+
+#[cfg(not(feature = "std"))]
+mod imp {
+    use core::cmp;
+    use core::fmt;
+    use core::result;
+    use alloc::vec::Vec;
+
+    /// Result of an I/O operation.
+    pub type Result<T> = result::Result<T, Error>;
+
+    /// The kinds of errors reported by the codecs.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ErrorKind {
+        InvalidInput,
+        InvalidData,
+        UnexpectedEof,
+        WriteZero,
+        Interrupted,
+        Other,
+    }
+
+    /// I/O error, with a kind and a static message.
+    #[derive(Debug)]
+    pub struct Error {
+        kind: ErrorKind,
+        msg: &'static str,
+    }
+
+    impl Error {
+        /// Create an error of the given kind.
+        pub fn new(kind: ErrorKind, msg: &'static str) -> Error {
+            Error {
+                kind: kind,
+                msg: msg,
+            }
+        }
+
+        /// Return the kind of the error.
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Error {
+            Error::new(kind, "")
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            if self.msg.is_empty() {
+                write!(f, "{:?}", self.kind)
+            } else {
+                write!(f, "{}", self.msg)
+            }
+        }
+    }
+
+    /// Source of bytes.
+    pub trait Read {
+        /// Read some bytes into `buf` and return how many were read.
+        /// Zero means the end of the input.
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+        /// Fill `buf` completely, or fail with `UnexpectedEof`.
+        fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.read(buf) {
+                    Ok(0) => return Err(Error::new(ErrorKind::UnexpectedEof,
+                                                   "failed to fill whole buffer")),
+                    Ok(n) => {
+                        let tmp = buf;
+                        buf = &mut tmp[n..];
+                    },
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        }
+
+        /// Append all remaining bytes to `buf` and return how many
+        /// were read.
+        fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+            let mut chunk = [0u8; 256];
+            let mut total = 0;
+            loop {
+                match self.read(&mut chunk) {
+                    Ok(0) => return Ok(total),
+                    Ok(n) => {
+                        buf.extend_from_slice(&chunk[..n]);
+                        total += n;
+                    },
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => (),
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        /// Return an iterator over the remaining bytes.
+        fn bytes(self) -> Bytes<Self> where Self: Sized {
+            Bytes { inner: self }
+        }
+
+        /// Borrow the reader.
+        fn by_ref(&mut self) -> &mut Self where Self: Sized {
+            self
+        }
+    }
+
+    /// Sink for bytes.
+    pub trait Write {
+        /// Write some bytes from `buf` and return how many were
+        /// written.
+        fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+        /// Write out any buffered data.
+        fn flush(&mut self) -> Result<()>;
+
+        /// Write all of `buf`, or fail with `WriteZero`.
+        fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.write(buf) {
+                    Ok(0) => return Err(Error::new(ErrorKind::WriteZero,
+                                                   "failed to write whole buffer")),
+                    Ok(n) => buf = &buf[n..],
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        }
+
+        /// Borrow the writer.
+        fn by_ref(&mut self) -> &mut Self where Self: Sized {
+            self
+        }
+    }
+
+    /// Iterator over the bytes of a reader, see `Read::bytes`.
+    pub struct Bytes<R> {
+        inner: R,
+    }
+
+    impl<R: Read> Iterator for Bytes<R> {
+        type Item = Result<u8>;
+
+        fn next(&mut self) -> Option<Result<u8>> {
+            let mut b = [0u8; 1];
+            loop {
+                return match self.inner.read(&mut b) {
+                    Ok(0) => None,
+                    Ok(_) => Some(Ok(b[0])),
+                    Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => Some(Err(e)),
+                };
+            }
+        }
+    }
+
+    impl<'a, R: Read + ?Sized> Read for &'a mut R {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            (**self).read(buf)
+        }
+    }
+
+    impl<'a, W: Write + ?Sized> Write for &'a mut W {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            (**self).write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+    }
+
+    impl<'a> Read for &'a [u8] {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let n = cmp::min(buf.len(), self.len());
+            buf[..n].copy_from_slice(&self[..n]);
+            *self = &self[n..];
+            Ok(n)
+        }
+    }
+
+    impl Write for Vec<u8> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> Write for &'a mut [u8] {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            let n = cmp::min(buf.len(), self.len());
+            let (head, tail) = ::core::mem::replace(self, &mut []).split_at_mut(n);
+            head.copy_from_slice(&buf[..n]);
+            *self = tail;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Copy all bytes from `reader` to `writer` and return how many
+    /// were copied.
+    pub fn copy<R: Read + ?Sized, W: Write + ?Sized>(reader: &mut R, writer: &mut W)
+                                                     -> Result<u64> {
+        let mut buf = [0u8; 1024];
+        let mut total = 0;
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => return Ok(total),
+                Ok(n) => n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            try!(writer.write_all(&buf[..n]));
+            total += n as u64;
+        }
+    }
+}
@@ -1,16 +1,124 @@
 // Copyright 2016 Martin Grabmueller. See the LICENSE file at the
 // top-level directory of this distribution for license information.
 
+//! Compression experiments.
+//!
+//! The codecs are grouped into cargo features, which are all enabled
+//! by default: `lz`, `huffman`, `arith`, `nested`, `deflate`, `bwt`
+//! and `filters`.  A build with only some of them still has every
+//! `Method`, but the methods of the missing codecs report an error,
+//! and `Method::all` lists only the available ones.
+//!
+//! The `std` feature, also on by default, links the standard library.
+//! Without it, the crate is `no_std` and needs only `alloc`; the bit
+//! I/O, the LZSS and LZW coders, Huffman coding and the arithmetic and
+//! range coders are then available, and read from and write to the
+//! minimal traits in `io`.  The other modules need `std`.
+//!
+//! The `ffi` feature, on by default, exports a C interface, see `ffi`.
+//! The `cli` feature builds the `camp` tool; leaving it out, as in
+//! `cargo build --lib --no-default-features --features std,lz,huffman`,
+//! drops the dependencies of the tool, for example for a
+//! `wasm32-unknown-unknown` build using the slice interface in
+//! `buffer`.  The `async` feature, off by default, adds readers and
+//! writers for asynchronous runtimes in `async_io`, and the `bench`
+//! feature the sample data for the benchmarks in `benches/`.
+//!
+//! The writers of the LZ77 and LZSS compressors, the adaptive models
+//! and the entropy coders implement `Clone` when the wrapped writer
+//! does.  A compressor can thus be forked to find out how large the
+//! output would be if some data were appended, without compressing
+//! the data before it again.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+extern crate core;
+#[macro_use]
+extern crate alloc;
+#[cfg(feature = "async")]
+extern crate futures_io;
+
+pub mod io;
 pub mod error;
 pub mod bitfile;
+pub mod window;
+pub mod inspect;
+pub mod input;
+pub mod counter;
+pub mod checksum;
+pub mod xxhash;
 
+#[cfg(all(feature = "std", feature = "lz"))]
 pub mod lz77;
+#[cfg(feature = "lz")]
 pub mod lzss;
+#[cfg(all(feature = "std", feature = "nested"))]
 pub mod lzss2;
+#[cfg(all(feature = "std", feature = "lz"))]
 pub mod lzp1;
+#[cfg(all(feature = "std", feature = "nested"))]
 pub mod lzp2;
+#[cfg(all(feature = "std", feature = "arith"))]
+pub mod lzp3;
+#[cfg(feature = "lz")]
 pub mod lzw;
+#[cfg(feature = "huffman")]
 pub mod huff;
+pub mod model;
+pub mod stats;
+#[cfg(feature = "arith")]
 pub mod arith;
+#[cfg(feature = "arith")]
 pub mod witten_arith;
+#[cfg(feature = "arith")]
 pub mod binarith;
+#[cfg(feature = "arith")]
+pub mod range;
+#[cfg(all(feature = "std", feature = "nested"))]
+pub mod nested;
+#[cfg(all(feature = "std", feature = "arith"))]
+pub mod message;
+#[cfg(all(feature = "std", feature = "deflate"))]
+pub mod deflate;
+#[cfg(all(feature = "std", feature = "deflate"))]
+pub mod zlib;
+#[cfg(all(feature = "std", feature = "lz"))]
+pub mod lz4;
+#[cfg(all(feature = "std", feature = "lz"))]
+pub mod snappy;
+#[cfg(all(feature = "std", feature = "arith"))]
+pub mod lzb;
+#[cfg(all(feature = "std", feature = "arith"))]
+pub mod rolz;
+#[cfg(all(feature = "std", feature = "bwt"))]
+pub mod bwt;
+#[cfg(all(feature = "std", feature = "filters"))]
+pub mod filters;
+#[cfg(feature = "std")]
+pub mod method;
+#[cfg(feature = "std")]
+pub mod container;
+#[cfg(feature = "std")]
+pub mod auto;
+#[cfg(all(feature = "std", feature = "filters", feature = "bwt"))]
+pub mod pipeline;
+#[cfg(all(feature = "std", feature = "filters", feature = "bwt"))]
+pub mod format;
+#[cfg(feature = "std")]
+pub mod delta;
+#[cfg(feature = "std")]
+pub mod buffer;
+#[cfg(feature = "std")]
+pub mod compare;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "async")]
+pub mod async_io;
+#[cfg(feature = "bench")]
+pub mod bench;
+#[cfg(all(test, feature = "std"))]
+mod testutil;
+
+#[cfg(feature = "std")]
+pub use compare::{compare, round_trip, CompareResult};
@@ -0,0 +1,756 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! LZ4 block and frame format compressor and decompressor.
+//!
+//! The output is wire compatible with the reference LZ4
+//! implementation: each sequence in a block consists of a token
+//! holding the literal run length and match length in its two
+//! nibbles, optional length extension bytes, the literals and a
+//! little-endian 16-bit match offset.  `compress_block` and
+//! `decompress_block` work on single blocks, while the `Writer`,
+//! `Reader`, `compress` and `decompress` handle the LZ4 frame format
+//! as written by the `lz4` command line tool.  `inspect` lists the
+//! frame headers, blocks, literals and matches of a stream.
+
+use std::io::{Read, Write};
+use std::io;
+
+use counter::Counter;
+use error::{relocated, Error};
+use inspect::{Event, Events, Inspect, Recorder};
+use xxhash::{xxh32, Xxh32};
+
+const MIN_MATCH_LEN: usize = 4;
+
+/// The last match must start at least this many bytes before the
+/// end of the block.
+const MF_LIMIT: usize = 12;
+
+/// The last bytes of a block are always literals.
+const LAST_LITERALS: usize = 5;
+
+const MAX_DISTANCE: usize = 0xffff;
+
+const HASH_BITS: usize = 16;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+
+const NO_POS: usize = usize::max_value();
+
+/// Return a `CorruptInput` error at offset `pos` of the block.
+fn corrupt(pos: usize, msg: &'static str) -> io::Error {
+    Error::corrupt_at(pos as u64, msg).into()
+}
+
+#[inline(always)]
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    (data[pos] as u32) | (data[pos + 1] as u32) << 8 |
+    (data[pos + 2] as u32) << 16 | (data[pos + 3] as u32) << 24
+}
+
+#[inline(always)]
+fn hash(v: u32) -> usize {
+    (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Write a length which did not fit into its token nibble as a
+/// sequence of 255 bytes followed by the remainder.
+fn write_length_ext(output: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        output.push(255);
+        len -= 255;
+    }
+    output.push(len as u8);
+}
+
+fn emit_sequence(output: &mut Vec<u8>, literals: &[u8], match_info: Option<(usize, usize)>) {
+    let lit_len = literals.len();
+    let ml_code = match match_info {
+        Some((_, len)) => len - MIN_MATCH_LEN,
+        None => 0,
+    };
+    let token = (::std::cmp::min(lit_len, 15) << 4) | ::std::cmp::min(ml_code, 15);
+    output.push(token as u8);
+    if lit_len >= 15 {
+        write_length_ext(output, lit_len - 15);
+    }
+    output.extend_from_slice(literals);
+    if let Some((offset, _)) = match_info {
+        output.push(offset as u8);
+        output.push((offset >> 8) as u8);
+        if ml_code >= 15 {
+            write_length_ext(output, ml_code - 15);
+        }
+    }
+}
+
+/// Compress `input` as a single LZ4 block and append it to `output`.
+pub fn compress_block(input: &[u8], output: &mut Vec<u8>) {
+    let len = input.len();
+    let mut anchor = 0;
+
+    if len > MF_LIMIT {
+        let mut table = vec![NO_POS; HASH_SIZE];
+        let match_limit = len - LAST_LITERALS;
+        let mut pos = 0;
+        while pos + MF_LIMIT <= len {
+            let seq = read_u32(input, pos);
+            let h = hash(seq);
+            let cand = table[h];
+            table[h] = pos;
+            if cand == NO_POS || pos - cand > MAX_DISTANCE || read_u32(input, cand) != seq {
+                pos += 1;
+                continue;
+            }
+
+            let mut start = pos;
+            let mut cand_start = cand;
+            while start > anchor && cand_start > 0 && input[start - 1] == input[cand_start - 1] {
+                start -= 1;
+                cand_start -= 1;
+            }
+            let mut end = pos + MIN_MATCH_LEN;
+            while end < match_limit && input[end] == input[cand + (end - pos)] {
+                end += 1;
+            }
+
+            emit_sequence(output, &input[anchor..start], Some((start - cand_start, end - start)));
+            pos = end;
+            anchor = pos;
+        }
+    }
+    emit_sequence(output, &input[anchor..], None);
+}
+
+fn read_length_ext(input: &[u8], ip: &mut usize) -> io::Result<usize> {
+    let mut len = 0;
+    loop {
+        if *ip >= input.len() {
+            return Err(corrupt(*ip, "truncated length in LZ4 block"));
+        }
+        let b = input[*ip];
+        *ip += 1;
+        len += b as usize;
+        if b != 255 {
+            return Ok(len);
+        }
+    }
+}
+
+/// Decompress the LZ4 block `input`, appending the result to
+/// `output`.  Matches may refer back into data already present in
+/// `output`, which allows decoding of linked blocks.  At most
+/// `max_size` bytes are decoded; blocks producing more data are
+/// rejected as corrupt.
+pub fn decompress_block(input: &[u8], output: &mut Vec<u8>, max_size: usize) -> io::Result<()> {
+    decode_block(input, output, max_size, &mut Recorder::new())
+}
+
+/// Decompress a block like `decompress_block`, recording its literals
+/// and matches in `recorder`.
+fn decode_block(input: &[u8], output: &mut Vec<u8>, max_size: usize, recorder: &mut Recorder)
+                -> io::Result<()> {
+    let limit = output.len() + max_size;
+    let mut ip = 0;
+    loop {
+        if ip >= input.len() {
+            return Err(corrupt(ip, "truncated LZ4 block"));
+        }
+        let token = input[ip];
+        ip += 1;
+
+        let mut lit_len = (token >> 4) as usize;
+        if lit_len == 15 {
+            lit_len += try!(read_length_ext(input, &mut ip));
+        }
+        if ip + lit_len > input.len() || output.len() + lit_len > limit {
+            return Err(corrupt(ip, "literal run exceeds LZ4 block"));
+        }
+        for &b in &input[ip..ip + lit_len] {
+            recorder.record(Event::Literal(b));
+        }
+        output.extend_from_slice(&input[ip..ip + lit_len]);
+        ip += lit_len;
+
+        if ip == input.len() {
+            // The last sequence of a block has no match part.
+            return Ok(());
+        }
+
+        if ip + 2 > input.len() {
+            return Err(corrupt(ip, "truncated LZ4 match offset"));
+        }
+        let offset = input[ip] as usize | (input[ip + 1] as usize) << 8;
+        ip += 2;
+        if offset == 0 || offset > output.len() {
+            return Err(corrupt(ip, "invalid LZ4 match offset"));
+        }
+
+        let mut match_len = (token & 0x0f) as usize;
+        if match_len == 15 {
+            match_len += try!(read_length_ext(input, &mut ip));
+        }
+        match_len += MIN_MATCH_LEN;
+        if output.len() + match_len > limit {
+            return Err(corrupt(ip, "match exceeds LZ4 block size"));
+        }
+        recorder.record(Event::Match { offset: offset, len: match_len });
+        let start = output.len() - offset;
+        for i in 0..match_len {
+            let b = output[start + i];
+            output.push(b);
+        }
+    }
+}
+
+const FRAME_MAGIC: u32 = 0x184d2204;
+const SKIPPABLE_MAGIC: u32 = 0x184d2a50;
+const SKIPPABLE_MASK: u32 = 0xfffffff0;
+
+const FLG_VERSION: u8 = 0x40;
+const FLG_BLOCK_INDEPENDENT: u8 = 0x20;
+const FLG_BLOCK_CHECKSUM: u8 = 0x10;
+const FLG_CONTENT_SIZE: u8 = 0x08;
+const FLG_CONTENT_CHECKSUM: u8 = 0x04;
+const FLG_DICT_ID: u8 = 0x01;
+
+/// Block size identifier for 64 KiB blocks.
+const BLOCK_SIZE_ID: u8 = 4;
+const BLOCK_SIZE: usize = 1 << 16;
+
+/// Flag in the block size field marking a stored block.
+const UNCOMPRESSED_BIT: u32 = 0x80000000;
+
+/// Linked blocks may refer back up to this many bytes into previous
+/// blocks.
+const DICT_SIZE: usize = 1 << 16;
+
+
+fn u32_bytes(v: u32) -> [u8; 4] {
+    [v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]
+}
+
+/// Writer for LZ4 frames.  The data is split into independent blocks
+/// of 64 KiB, and the frame ends with a checksum of the uncompressed
+/// content.
+pub struct Writer<W> {
+    inner: Counter<W>,
+    buf: Vec<u8>,
+    compressed: Vec<u8>,
+    checksum: Xxh32,
+    header_written: bool,
+    finished: bool,
+    total_in: u64,
+}
+
+impl<W: Write> Writer<W> {
+    /// Create a new LZ4 frame writer.
+    pub fn new(inner: W) -> Writer<W> {
+        Writer {
+            inner: Counter::new(inner),
+            buf: Vec::with_capacity(BLOCK_SIZE),
+            compressed: Vec::with_capacity(BLOCK_SIZE),
+            checksum: Xxh32::new(),
+            header_written: false,
+            finished: false,
+            total_in: 0,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            let flg = FLG_VERSION | FLG_BLOCK_INDEPENDENT | FLG_CONTENT_CHECKSUM;
+            let bd = BLOCK_SIZE_ID << 4;
+            let hc = (xxh32(&[flg, bd]) >> 8) as u8;
+            try!(self.inner.write_all(&u32_bytes(FRAME_MAGIC)));
+            try!(self.inner.write_all(&[flg, bd, hc]));
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        self.compressed.clear();
+        compress_block(&self.buf, &mut self.compressed);
+        if self.compressed.len() < self.buf.len() {
+            try!(self.inner.write_all(&u32_bytes(self.compressed.len() as u32)));
+            try!(self.inner.write_all(&self.compressed));
+        } else {
+            try!(self.inner.write_all(&u32_bytes(self.buf.len() as u32 | UNCOMPRESSED_BIT)));
+            try!(self.inner.write_all(&self.buf));
+        }
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// Write out pending data, the end mark and the content checksum.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            try!(self.write_header());
+            try!(self.write_block());
+            try!(self.inner.write_all(&[0, 0, 0, 0]));
+            let v = self.checksum.value();
+            try!(self.inner.write_all(&u32_bytes(v)));
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    /// Return the number of uncompressed bytes written so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Return the number of compressed bytes written to the wrapped
+    /// writer so far.
+    pub fn total_out(&self) -> u64 {
+        self.inner.count()
+    }
+
+    /// Move the wrapped writer out of the LZ4 writer.
+    pub fn into_inner(self) -> W {
+        self.inner.into_inner()
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        try!(self.write_header());
+        let n = ::std::cmp::min(BLOCK_SIZE - self.buf.len(), buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        self.checksum.update(&buf[..n]);
+        if self.buf.len() == BLOCK_SIZE {
+            try!(self.write_block());
+        }
+        self.total_in += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.finish());
+        self.inner.flush()
+    }
+}
+
+/// Read a little-endian 32-bit value.  Returns `None` when the input
+/// ends before the first byte.
+fn read_u32_or_eof<R: Read>(inner: &mut R) -> io::Result<Option<u32>> {
+    let mut b = [0u8; 4];
+    let mut n = 0;
+    while n < 4 {
+        match inner.read(&mut b[n..]) {
+            Ok(0) => {
+                if n == 0 {
+                    return Ok(None);
+                }
+                return Err(Error::UnexpectedEof.into());
+            },
+            Ok(k) => n += k,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Some(read_u32(&b, 0)))
+}
+
+fn read_u32_le<R: Read>(inner: &mut R) -> io::Result<u32> {
+    let mut b = [0u8; 4];
+    try!(inner.read_exact(&mut b));
+    Ok(read_u32(&b, 0))
+}
+
+/// Reader for LZ4 frames.  Concatenated frames are decoded one after
+/// the other, and skippable frames are ignored.
+pub struct Reader<R> {
+    inner: Counter<R>,
+    block_independent: bool,
+    block_checksum: bool,
+    content_checksum: bool,
+    max_block_size: usize,
+    checksum: Xxh32,
+    compressed: Vec<u8>,
+    output: Vec<u8>,
+    returned: usize,
+    eof: bool,
+    total_out: u64,
+    recorder: Recorder,
+}
+
+impl<R: Read> Reader<R> {
+    /// Create a new LZ4 frame reader.  This reads and validates the
+    /// first frame header, therefore the result can be an error.
+    pub fn new(inner: R) -> io::Result<Reader<R>> {
+        Reader::with_recorder(inner, Recorder::new())
+    }
+
+    fn with_recorder(inner: R, recorder: Recorder) -> io::Result<Reader<R>> {
+        let mut inner = Counter::new(inner);
+        let mut magic = try!(read_u32_le(&mut inner));
+        let mut rd = Reader {
+            inner: inner,
+            block_independent: true,
+            block_checksum: false,
+            content_checksum: false,
+            max_block_size: BLOCK_SIZE,
+            checksum: Xxh32::new(),
+            compressed: Vec::new(),
+            output: Vec::new(),
+            returned: 0,
+            eof: false,
+            total_out: 0,
+            recorder: recorder,
+        };
+        while !try!(rd.start_frame(magic)) {
+            magic = try!(read_u32_le(&mut rd.inner));
+        }
+        Ok(rd)
+    }
+
+    /// Return the number of compressed bytes read so far.
+    pub fn total_in(&self) -> u64 {
+        self.inner.count()
+    }
+
+    /// Return the number of decompressed bytes read so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Process the frame starting with `magic`.  Skippable frames are
+    /// consumed and `Ok(false)` is returned, otherwise the frame
+    /// descriptor is read and validated.
+    fn start_frame(&mut self, magic: u32) -> io::Result<bool> {
+        if magic & SKIPPABLE_MASK == SKIPPABLE_MAGIC {
+            let len = try!(read_u32_le(&mut self.inner)) as u64;
+            let skipped = try!(io::copy(&mut (&mut self.inner).take(len), &mut io::sink()));
+            if skipped != len {
+                return Err(Error::UnexpectedEof.into());
+            }
+            return Ok(false);
+        }
+        if magic != FRAME_MAGIC {
+            return Err(Error::InvalidHeader("not an LZ4 frame").into());
+        }
+
+        let mut desc = [0u8; 15];
+        try!(self.inner.read_exact(&mut desc[..2]));
+        let (flg, bd) = (desc[0], desc[1]);
+        if flg & 0xc0 != FLG_VERSION || flg & 0x02 != 0 || bd & 0x8f != 0 {
+            return Err(Error::UnsupportedVersion((flg >> 6) as u32).into());
+        }
+        let block_size_id = (bd >> 4) & 0x07;
+        if block_size_id < 4 {
+            return Err(Error::corrupt_at(self.inner.count(), "invalid LZ4 block size").into());
+        }
+        let mut len = 2;
+        if flg & FLG_CONTENT_SIZE != 0 {
+            try!(self.inner.read_exact(&mut desc[len..len + 8]));
+            len += 8;
+        }
+        if flg & FLG_DICT_ID != 0 {
+            try!(self.inner.read_exact(&mut desc[len..len + 4]));
+            len += 4;
+        }
+        try!(self.inner.read_exact(&mut desc[len..len + 1]));
+        let actual = (xxh32(&desc[..len]) >> 8) as u8;
+        if actual != desc[len] {
+            return Err(Error::ChecksumMismatch {
+                expected: desc[len] as u64,
+                actual: actual as u64,
+            }.into());
+        }
+        if flg & FLG_DICT_ID != 0 {
+            return Err(Error::corrupt_at(self.inner.count(),
+                                         "LZ4 dictionaries are not supported").into());
+        }
+
+        self.block_independent = flg & FLG_BLOCK_INDEPENDENT != 0;
+        self.block_checksum = flg & FLG_BLOCK_CHECKSUM != 0;
+        self.content_checksum = flg & FLG_CONTENT_CHECKSUM != 0;
+        self.max_block_size = 1 << (8 + 2 * block_size_id as usize);
+        self.recorder.record(Event::Header(format!(
+            "block size {} KiB, {} blocks{}{}", self.max_block_size >> 10,
+            if self.block_independent { "independent" } else { "linked" },
+            if self.block_checksum { ", block checksums" } else { "" },
+            if self.content_checksum { ", content checksum" } else { "" })));
+        self.checksum = Xxh32::new();
+        self.output.clear();
+        self.returned = 0;
+        Ok(true)
+    }
+
+    fn end_frame(&mut self) -> io::Result<()> {
+        self.recorder.record(Event::End);
+        if self.content_checksum {
+            let expected = try!(read_u32_le(&mut self.inner));
+            if expected != self.checksum.value() {
+                return Err(Error::ChecksumMismatch {
+                    expected: expected as u64,
+                    actual: self.checksum.value() as u64,
+                }.into());
+            }
+        }
+        loop {
+            match try!(read_u32_or_eof(&mut self.inner)) {
+                None => {
+                    self.eof = true;
+                    return Ok(());
+                },
+                Some(magic) => {
+                    if try!(self.start_frame(magic)) {
+                        return Ok(());
+                    }
+                },
+            }
+        }
+    }
+
+    /// Decode the next block into the output buffer.
+    fn read_block(&mut self) -> io::Result<()> {
+        let size = try!(read_u32_le(&mut self.inner));
+        if size == 0 {
+            return self.end_frame();
+        }
+        let stored = size & UNCOMPRESSED_BIT != 0;
+        let size = (size & !UNCOMPRESSED_BIT) as usize;
+        if size > self.max_block_size {
+            return Err(Error::LimitExceeded("LZ4 block too large").into());
+        }
+
+        self.compressed.resize(size, 0);
+        try!(self.inner.read_exact(&mut self.compressed));
+        if self.block_checksum {
+            let expected = try!(read_u32_le(&mut self.inner));
+            let actual = xxh32(&self.compressed);
+            if expected != actual {
+                return Err(Error::ChecksumMismatch {
+                    expected: expected as u64,
+                    actual: actual as u64,
+                }.into());
+            }
+        }
+
+        // Keep the tail of the previous blocks around as dictionary
+        // for linked blocks.
+        if self.block_independent {
+            self.output.clear();
+        } else if self.output.len() > DICT_SIZE {
+            let drop = self.output.len() - DICT_SIZE;
+            self.output.drain(..drop);
+        }
+        self.returned = self.output.len();
+
+        if stored {
+            self.recorder.record(Event::Block(format!("stored {} bytes", size)));
+            for &b in &self.compressed {
+                self.recorder.record(Event::Literal(b));
+            }
+            self.output.extend_from_slice(&self.compressed);
+        } else {
+            self.recorder.record(Event::Block(format!("compressed {} bytes", size)));
+            let start = self.inner.count() - self.compressed.len() as u64 -
+                if self.block_checksum { 4 } else { 0 };
+            try!(decode_block(&self.compressed, &mut self.output, self.max_block_size,
+                              &mut self.recorder)
+                 .map_err(|e| relocated(e, start)));
+        }
+        self.checksum.update(&self.output[self.returned..]);
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
+        while self.returned == self.output.len() {
+            if self.eof || output.is_empty() {
+                return Ok(0);
+            }
+            try!(self.read_block());
+        }
+        let n = ::std::cmp::min(output.len(), self.output.len() - self.returned);
+        output[..n].copy_from_slice(&self.output[self.returned..self.returned + n]);
+        self.returned += n;
+        self.total_out += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read> Inspect for Reader<R> {
+    fn recorder(&mut self) -> &mut Recorder {
+        &mut self.recorder
+    }
+}
+
+/// Compress all data from `input` into an LZ4 frame written to
+/// `output`.  On success, the output is returned.
+pub fn compress<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error> {
+    let mut cw = Writer::new(output);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.into_inner())
+}
+
+/// Decompress the LZ4 frames in `input` and write the result to
+/// `output`.  On success, the output is returned.
+pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<W, Error> {
+    let mut cr = try!(Reader::new(input));
+    try!(io::copy(&mut cr, &mut output));
+    Ok(output)
+}
+
+/// Return an iterator over the frame headers, blocks, literals and
+/// matches of the LZ4 frames in `input`.  This reads the first frame
+/// header, therefore the result can be an error.
+pub fn inspect<R: Read>(input: R) -> io::Result<Events<Reader<R>>> {
+    Ok(Events::new(try!(Reader::with_recorder(input, Recorder::enabled()))))
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::io::{Cursor, Read};
+    use super::{compress, compress_block, decompress, decompress_block, Reader};
+
+    fn cmp_test(input: &[u8], expected_output: &[u8]) {
+        let mut compressed = Vec::new();
+        compress_block(input, &mut compressed);
+        assert_eq!(&expected_output[..], &compressed[..]);
+    }
+
+    fn decmp_test(compressed: &[u8], expected_output: &[u8]) {
+        let mut decompressed = Vec::new();
+        decompress_block(compressed, &mut decompressed, 1 << 20).unwrap();
+        assert_eq!(&expected_output[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn compress_empty() {
+        cmp_test(b"", &[0]);
+    }
+
+    #[test]
+    fn compress_a() {
+        cmp_test(b"a", &[16, b'a']);
+    }
+
+    #[test]
+    fn compress_aaa() {
+        cmp_test(b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+                 &[31, 97, 1, 0, 7, 80, 97, 97, 97, 97, 97]);
+    }
+
+    #[test]
+    fn decompress_empty() {
+        decmp_test(&[0], b"");
+    }
+
+    #[test]
+    fn decompress_aaa() {
+        decmp_test(&[31, 97, 1, 0, 7, 80, 97, 97, 97, 97, 97],
+                   b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+    }
+
+    #[test]
+    fn decompress_bad_offset() {
+        let mut decompressed = Vec::new();
+        assert!(decompress_block(&[16, b'a', 2, 0, 0], &mut decompressed, 100).is_err());
+    }
+
+    #[test]
+    fn decompress_too_large() {
+        let mut decompressed = Vec::new();
+        assert!(decompress_block(&[31, 97, 1, 0, 7, 80, 97, 97, 97, 97, 97],
+                                 &mut decompressed, 16).is_err());
+    }
+
+    #[test]
+    fn decompress_with_dictionary() {
+        let mut decompressed = b"abcd".to_vec();
+        decompress_block(&[0, 4, 0, 16, b'x'], &mut decompressed, 100).unwrap();
+        assert_eq!(&b"abcdabcdx"[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn compress_decompress() {
+        let input = include_bytes!("lz4.rs");
+        let mut compressed = Vec::new();
+        compress_block(input, &mut compressed);
+        assert!(compressed.len() < input.len());
+        decmp_test(&compressed, input);
+    }
+
+    #[test]
+    fn compress_frame_empty() {
+        let compressed = compress(Cursor::new(&b""[..]), vec![]).unwrap();
+        assert_eq!(&[4, 34, 77, 24, 100, 64, 167, 0, 0, 0, 0, 5, 93, 204, 2][..],
+                   &compressed[..]);
+    }
+
+    #[test]
+    fn decompress_frame() {
+        // Produced by the lz4 tool with linked blocks and block
+        // checksums.
+        let input = [4, 34, 77, 24, 116, 64, 189, 16, 0, 0, 0, 111, 104, 101, 108,
+                     108, 111, 32, 6, 0, 0, 80, 101, 108, 108, 111, 10, 143, 241,
+                     45, 79, 0, 0, 0, 0, 45, 130, 3, 57];
+        let decompressed = decompress(Cursor::new(&input[..]), vec![]).unwrap();
+        assert_eq!(&b"hello hello hello hello hello\n"[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn inspect_events() {
+        use inspect::Event;
+        let input = [4, 34, 77, 24, 116, 64, 189, 16, 0, 0, 0, 111, 104, 101, 108,
+                     108, 111, 32, 6, 0, 0, 80, 101, 108, 108, 111, 10, 143, 241,
+                     45, 79, 0, 0, 0, 0, 45, 130, 3, 57];
+        let events: Vec<Event> =
+            super::inspect(Cursor::new(&input[..])).unwrap().map(|e| e.unwrap()).collect();
+        let mut expected = vec![
+            Event::Header("block size 64 KiB, independent blocks, block checksums, \
+                           content checksum".to_string()),
+            Event::Block("compressed 16 bytes".to_string())];
+        expected.extend(b"hello ".iter().map(|&b| Event::Literal(b)));
+        expected.push(Event::Match { offset: 6, len: 19 });
+        expected.extend(b"ello\n".iter().map(|&b| Event::Literal(b)));
+        expected.push(Event::End);
+        assert_eq!(expected, events);
+    }
+
+    #[test]
+    fn decompress_concatenated() {
+        let mut input = vec![0x50, 0x2a, 0x4d, 0x18, 3, 0, 0, 0, 1, 2, 3];
+        input.extend(compress(Cursor::new(&b"abc"[..]), vec![]).unwrap());
+        input.extend(compress(Cursor::new(&b"def"[..]), vec![]).unwrap());
+        let decompressed = decompress(Cursor::new(&input[..]), vec![]).unwrap();
+        assert_eq!(&b"abcdef"[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn bad_content_checksum() {
+        let mut compressed = compress(Cursor::new(&b"hello"[..]), vec![]).unwrap();
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        let mut cr = Reader::new(Cursor::new(compressed)).unwrap();
+        let mut decompressed = Vec::new();
+        assert!(cr.read_to_end(&mut decompressed).is_err());
+    }
+
+    #[test]
+    fn compress_decompress_frame() {
+        let mut input = Vec::new();
+        for _ in 0..10 {
+            input.extend_from_slice(include_bytes!("lz4.rs"));
+        }
+        let compressed = compress(Cursor::new(&input[..]), vec![]).unwrap();
+        let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(&input[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+    }
+}
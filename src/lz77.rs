@@ -2,11 +2,44 @@
 // top-level directory of this distribution for license information.
 
 //! Simple implementation of an LZ77 compressor.
-
-use std::io::{Read, Write};
+//!
+//! The four most recently used match offsets are remembered by both
+//! compressor and decompressor.  Matches at one of these offsets are
+//! coded with an offset field value of 0xff0 or above, which does not
+//! occur for ordinary matches: two bits of the offset field select
+//! the repeat offset and the remaining two extend the match length.
+//!
+//! Every match is followed by one literal byte.  Literals which are
+//! not part of a match are sent in runs: a token with offset 0 and
+//! length field `l` is followed by `l + 1` literals, and when `l` is
+//! 15, by extension bytes which add their values to the run length
+//! until one of them is below 255 (as in LZ4).  A single literal is
+//! therefore coded as `0, 0, lit`, which is also how streams written
+//! before literal runs were introduced code them, and incompressible
+//! data expands by less than one percent.
+//!
+//! The stream ends with a match token at offset `EOS_OFFSET`, which
+//! the compressor never uses for matches, so that the decompressor
+//! stops reading there and compressed streams can be embedded in
+//! other data.  Streams without this marker end at the end of the
+//! input.
+//!
+//! From compression level `LAZY_LEVEL` on, matching is lazy: when the
+//! match at the next position is longer than the one at the current
+//! position, the current byte is sent as a literal and the longer
+//! match is taken instead.  Because every match carries the
+//! following byte, and the rest of a much longer match is usually
+//! found again after the shorter one, this is only done when the
+//! longer match is less than `MIN_MATCH_LEN` bytes longer.
+
+use std::io::{BufRead, Read, Write};
 use std::io;
 
-use error::Error;
+use counter::Counter;
+use error::{at_offset, Error};
+use input::ByteReader;
+use inspect::{Event, Events, Inspect, Recorder};
+use window::{self, MatchFinder, SlidingWindow};
 
 const WINDOW_BITS: usize = 12;
 const LENGTH_BITS: usize = 4;
@@ -14,82 +47,208 @@ const LENGTH_BITS: usize = 4;
 const MIN_MATCH_LEN: usize = 4;
 const MAX_MATCH_LEN: usize = ((1 << LENGTH_BITS) - 1) + MIN_MATCH_LEN - 1;
 
-const LOOK_AHEAD_BYTES: usize = MAX_MATCH_LEN + 1;
+/// Number of recently used offsets available for repeat matches.
+const REP_COUNT: usize = 4;
+const REP_BASE: usize = 0xff0;
+const REP_MAX_MATCH_LEN: usize = ((1 << (LENGTH_BITS + 2)) - 1) + MIN_MATCH_LEN;
+
+const LOOK_AHEAD_BYTES: usize = REP_MAX_MATCH_LEN + 1;
 
 const WINDOW_SIZE: usize = 1 << WINDOW_BITS;
 
-const HASHTAB_SIZE: usize = 1 << 10;
+/// Largest match offset.  Data further back may already be
+/// overwritten by the look-ahead buffer.
+const MAX_OFFSET: usize = WINDOW_SIZE - LOOK_AHEAD_BYTES - 1;
+
+const HASH_BITS: usize = 12;
+
+/// Offset field value marking the end of the stream.  It is above
+/// `MAX_OFFSET` and below the repeat offsets.
+const EOS_OFFSET: usize = REP_BASE - 1;
+
+/// Shortest literal run which needs extension bytes.
+const LITERAL_RUN_EXT: usize = 16;
+
+/// Longest run of literals in one token.  The decoder collects a run
+/// in its window before returning it, so runs must fit into it.
+const MAX_LITERAL_RUN: usize = 1024;
 
+/// Lowest compression level which uses lazy matching.
+pub const LAZY_LEVEL: u32 = 4;
+
+#[derive(Clone)]
 pub struct Writer<W> {
-    inner:  W,
-    window: [u8; WINDOW_SIZE],
-    hashtab: [usize; HASHTAB_SIZE],
-    position: usize,
-    look_ahead_bytes: usize,
+    inner:  Counter<W>,
+    window: SlidingWindow,
+    matcher: MatchFinder,
+    reps: [usize; REP_COUNT],
+    lazy: bool,
+    /// Match already found for the current position while deciding
+    /// lazily about the previous one.
+    next_match: Option<(usize, usize)>,
+    /// Literals waiting to be sent as a run.
+    literals: Vec<u8>,
+    finished: bool,
+    total_in: u64,
 }
 
-#[inline(always)]
-fn mod_window(x: usize) -> usize {
-    x % WINDOW_SIZE
+/// Move the repeat offset at index `i` to the front of the history.
+fn promote_rep(reps: &mut [usize; REP_COUNT], i: usize) {
+    let ofs = reps[i];
+    for j in (0..i).rev() {
+        reps[j + 1] = reps[j];
+    }
+    reps[0] = ofs;
+}
+
+/// Enter a newly used offset into the repeat offset history.
+fn push_rep(reps: &mut [usize; REP_COUNT], ofs: usize) {
+    reps[REP_COUNT - 1] = ofs;
+    promote_rep(reps, REP_COUNT - 1);
 }
 
 impl<W: Write> Writer<W> {
     /// Create a new LZP writer that wraps the given Writer.
     pub fn new(inner: W) -> Writer<W>{
+        Writer::with_level(inner, window::DEFAULT_LEVEL)
+    }
+
+    /// Create a new LZ77 writer which searches for matches as
+    /// selected by compression `level`, from 1 (fastest) to
+    /// `window::MAX_LEVEL` (best compression).
+    pub fn with_level(inner: W, level: u32) -> Writer<W>{
+        assert!(level >= 1 && level <= window::MAX_LEVEL);
         Writer {
-            inner:  inner,
-            window: [0; WINDOW_SIZE],
-            hashtab: [0; HASHTAB_SIZE],
-            position: 0,
-            look_ahead_bytes: 0,
+            inner:  Counter::new(inner),
+            window: SlidingWindow::new(WINDOW_SIZE),
+            matcher: MatchFinder::with_level(WINDOW_SIZE, HASH_BITS, level),
+            reps: [0; REP_COUNT],
+            lazy: level >= LAZY_LEVEL,
+            next_match: None,
+            literals: Vec::with_capacity(MAX_LITERAL_RUN),
+            finished: false,
+            total_in: 0,
         }
     }
 
-    fn hash_at(&self, pos: usize) -> usize {
-        // This might go over the data actually in the window, but as
-        // long as the compressor and decompressor maintain the same
-        // window contents, it should not matter.
-        let h1 = self.window[pos] as usize;
-        let h2 = self.window[mod_window(pos + 1)] as usize;
-        let h3 = self.window[mod_window(pos + 2)] as usize;
+    /// Queue the literal `lit`, to be sent in a run in front of the
+    /// next match.
+    fn emit_lit(&mut self, lit: u8) -> io::Result<()> {
+        self.literals.push(lit);
+        if self.literals.len() == MAX_LITERAL_RUN {
+            try!(self.emit_literal_run());
+        }
+        Ok(())
+    }
 
-        let h = (h1 >> 5) ^ ((h2 << 8) + h3);
+    /// Output the queued literals as one run.
+    fn emit_literal_run(&mut self) -> io::Result<()> {
+        let n = self.literals.len();
+        if n == 0 {
+            return Ok(());
+        }
+        if n < LITERAL_RUN_EXT {
+            try!(self.inner.write_all(&[((n - 1) << 4) as u8, 0]));
+        } else {
+            try!(self.inner.write_all(&[((LITERAL_RUN_EXT - 1) << 4) as u8, 0]));
+            let mut rest = n - LITERAL_RUN_EXT;
+            while rest >= 255 {
+                try!(self.inner.write_all(&[255]));
+                rest -= 255;
+            }
+            try!(self.inner.write_all(&[rest as u8]));
+        }
+        try!(self.inner.write_all(&self.literals));
+        self.literals.clear();
+        Ok(())
+    }
 
-        h % HASHTAB_SIZE
+    /// Output a match, encoded in `m1` and `m2`, and the literal
+    /// `follow` after it.
+    fn emit_match(&mut self, m1: u8, m2: u8, follow: u8) -> io::Result<()> {
+        try!(self.emit_literal_run());
+        self.inner.write_all(&[m1, m2, follow])
     }
 
-    fn find_longest_match(&self, match_pos: usize, search_pos: usize) -> usize {
-        if self.look_ahead_bytes > MIN_MATCH_LEN - 1 && match_pos != search_pos {
-            let mut match_len = 0;
-            for i in 0..::std::cmp::min(self.look_ahead_bytes - 1, MAX_MATCH_LEN) {
-                if self.window[mod_window(match_pos + i)] != self.window[mod_window(search_pos + i)] {
-                    break;
-                }
-                match_len += 1;
+    /// Find the longest match for the look-ahead buffer and enter
+    /// the current position into the match finder.  Returns the
+    /// match length and offset.  The byte following the match is
+    /// always sent as a literal, so the match must leave at least one
+    /// byte of the look-ahead buffer.
+    fn find_longest_match(&mut self) -> (usize, usize) {
+        if let Some(m) = self.next_match.take() {
+            return m;
+        }
+        let max_len = ::std::cmp::min(self.window.buffered() - 1, MAX_MATCH_LEN);
+        self.matcher.find(self.window.data(), self.window.position(), max_len, MAX_OFFSET)
+    }
+
+    /// Enter the positions from `start` to `count - 1` after the
+    /// current position into the match finder.
+    fn skip_positions(&mut self, start: usize, count: usize) {
+        for i in start..count {
+            let max_len = ::std::cmp::min(self.window.buffered() - i - 1, MAX_MATCH_LEN);
+            let pos = self.window.wrap(self.window.position() + i);
+            self.matcher.skip(self.window.data(), pos, max_len, MAX_OFFSET);
+        }
+    }
+
+    /// Find the longest match at one of the repeat offsets.  Returns
+    /// the index of the repeat offset and the match length.
+    fn find_rep_match(&self) -> (usize, usize) {
+        let mut best = (0, 0);
+        if self.window.buffered() == 0 {
+            return best;
+        }
+        let max_len = ::std::cmp::min(self.window.buffered() - 1, REP_MAX_MATCH_LEN);
+        for (i, &ofs) in self.reps.iter().enumerate() {
+            if ofs == 0 {
+                continue;
+            }
+            let match_len = self.window.match_len(0, ofs, max_len);
+            if match_len > best.1 {
+                best = (i, match_len);
             }
-            match_len
-        } else {
-            0
         }
+        best
     }
 
     fn process(&mut self) -> io::Result<()> {
-        let search_pos = self.position;
-        
-        let hsh = self.hash_at(search_pos);
-        let match_pos = self.hashtab[hsh];
-        
-        let ofs =
-            if match_pos < self.position {
-                self.position - match_pos
-            } else {
-                self.position + (WINDOW_SIZE - match_pos)
-            };
-        
-        let match_len = self.find_longest_match(match_pos, search_pos);
-        
-        if ofs < WINDOW_SIZE - MAX_MATCH_LEN && match_len >= MIN_MATCH_LEN {
-            let follow = self.window[mod_window(self.position + match_len)];
+        let (match_len, ofs) = self.find_longest_match();
+        let (rep_idx, rep_len) = self.find_rep_match();
+
+        // Number of positions entered into the match finder.
+        let mut searched = 1;
+        let best_len = ::std::cmp::max(match_len, rep_len);
+        if self.lazy && best_len >= MIN_MATCH_LEN && self.window.buffered() > 2 {
+            let max_len = ::std::cmp::min(self.window.buffered() - 2, MAX_MATCH_LEN);
+            let pos = self.window.wrap(self.window.position() + 1);
+            let next = self.matcher.find(self.window.data(), pos, max_len, MAX_OFFSET);
+            // When the next match is much longer, its remainder will
+            // be found after the current match anyway.
+            if next.0 > best_len && next.0 < best_len + MIN_MATCH_LEN {
+                let lit = self.window.get(0);
+                try!(self.emit_lit(lit));
+                self.next_match = Some(next);
+                self.window.consume(1);
+                return Ok(());
+            }
+            searched = 2;
+        }
+
+        if rep_len >= MIN_MATCH_LEN && rep_len >= match_len {
+            let follow = self.window.get(rep_len);
+            let l = rep_len - MIN_MATCH_LEN;
+            let m1 = (((l & 0x0f) as u8) << 4) | ((REP_BASE >> 8) as u8);
+            let m2 = ((REP_BASE | (rep_idx << 2) | (l >> 4)) & 0xff) as u8;
+
+            try!(self.emit_match(m1, m2, follow));
+            promote_rep(&mut self.reps, rep_idx);
+
+            self.skip_positions(searched, rep_len + 1);
+            self.window.consume(rep_len + 1);
+        } else if match_len >= MIN_MATCH_LEN {
+            let follow = self.window.get(match_len);
 
             assert!(ofs != 0);
             assert!((match_len - MIN_MATCH_LEN) < 16);
@@ -97,22 +256,33 @@ impl<W: Write> Writer<W> {
             let m1 = (((match_len - MIN_MATCH_LEN) as u8) << 4) | (((ofs >> 8) as u8) & 0x0f);
             let m2 = (ofs & 0xff) as u8;
             
-            try!(self.inner.write_all(&[m1, m2, follow]));
-            
-            self.position = mod_window(self.position + match_len + 1);
-            self.look_ahead_bytes -= match_len + 1;
+            try!(self.emit_match(m1, m2, follow));
+            push_rep(&mut self.reps, ofs);
+
+            self.skip_positions(searched, match_len + 1);
+            self.window.consume(match_len + 1);
         } else {
-            try!(self.inner.write_all(&[0, 0, self.window[self.position]]));
-            self.position = mod_window(self.position + 1);
-            self.look_ahead_bytes -= 1;
+            let lit = self.window.get(0);
+            try!(self.emit_lit(lit));
+            self.window.consume(1);
         }
-        self.hashtab[hsh] = search_pos;
         Ok(())
     }
 
+    /// Return the number of uncompressed bytes written so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Return the number of compressed bytes written to the wrapped
+    /// writer so far.
+    pub fn total_out(&self) -> u64 {
+        self.inner.count()
+    }
+
     /// Move the wrapped writer out of the LZP writer.
     pub fn to_inner(self) -> W {
-        self.inner
+        self.inner.into_inner()
     }
 }
 
@@ -120,107 +290,233 @@ impl<W: Write> Write for Writer<W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let mut written = 0;
         while written < buf.len() {
-            while written < buf.len() && self.look_ahead_bytes < LOOK_AHEAD_BYTES {
-                self.window[mod_window(self.position + self.look_ahead_bytes)] = buf[written];
-                self.look_ahead_bytes += 1;
-                written += 1;
-            }
-            if self.look_ahead_bytes == LOOK_AHEAD_BYTES {
+            written += self.window.fill(&buf[written..], LOOK_AHEAD_BYTES);
+            if self.window.buffered() == LOOK_AHEAD_BYTES {
                 try!(self.process());
             }
         }
+        self.total_in += written as u64;
         Ok(written)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        while self.look_ahead_bytes > 0 {
+        while self.window.buffered() > 0 {
             try!(self.process());
         }
+        try!(self.emit_literal_run());
+        if !self.finished {
+            try!(self.inner.write_all(&[(EOS_OFFSET >> 8) as u8, (EOS_OFFSET & 0xff) as u8]));
+            self.finished = true;
+        }
         self.inner.flush()
     }
 }
 
 pub struct Reader<R> {
-    inner: R,
-    window: [u8; WINDOW_SIZE],
-    position: usize,
-    returned: usize,
+    inner: ByteReader<R>,
+    window: SlidingWindow,
+    reps: [usize; REP_COUNT],
+    eof: bool,
+    recorder: Recorder,
+    total_out: u64,
 }
 
 impl<R: Read> Reader<R> {
-    /// Create a new LZP reader that wraps another reader.
+    /// Create a new LZP reader that wraps another reader.  The
+    /// reader reads no further than the end of the stream, so that
+    /// data following it can be read from `inner` afterwards.
     pub fn new(inner: R) -> Reader<R> {
+        Reader::with_window(inner, vec![0; WINDOW_SIZE])
+    }
+
+    /// Create a reader which reads ahead from `inner` in blocks.  This
+    /// is faster than `new` for unbuffered readers, but consumes input
+    /// beyond the end of the stream.
+    pub fn buffered(inner: R) -> Reader<R> {
+        Reader::from_input(ByteReader::new(inner), vec![0; WINDOW_SIZE])
+    }
+
+    /// Create a reader which decodes into `window` instead of
+    /// allocating its own window.  The length of `window` must be a
+    /// power of two and at least 4096 bytes.  Together with
+    /// `decode` and `into_window`, this lets a caller reuse one
+    /// buffer for many streams.
+    pub fn with_window(inner: R, window: Vec<u8>) -> Reader<R> {
+        Reader::from_input(ByteReader::exact(inner), window)
+    }
+
+    fn from_input(inner: ByteReader<R>, window: Vec<u8>) -> Reader<R> {
+        assert!(window.len() >= WINDOW_SIZE);
         Reader {
             inner: inner,
-            window: [0; WINDOW_SIZE],
-            position: 0,
-            returned: 0,
+            window: SlidingWindow::from_vec(window),
+            reps: [0; REP_COUNT],
+            eof: false,
+            recorder: Recorder::new(),
+            total_out: 0,
         }
     }
 
     fn getc(&mut self) -> io::Result<Option<u8>> {
-        let mut buf = [0u8];
-        let n = try!(self.inner.read(&mut buf));
-        if n == 1 {
-            Ok(Some(buf[0]))
-        } else {
-            Ok(None)
+        self.inner.next_byte()
+    }
+
+    /// Return the number of compressed bytes consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.inner.consumed()
+    }
+
+    /// Return the number of decompressed bytes read so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Decode the next piece of the stream and return it.  The data
+    /// is returned directly from the window, without copying it into
+    /// an output buffer, and stays valid until the next call.  An
+    /// empty slice means the end of the stream.
+    pub fn decode(&mut self) -> io::Result<&[u8]> {
+        try!(self.fill());
+        let data = self.window.take_buffered();
+        self.total_out += data.len() as u64;
+        Ok(data)
+    }
+
+    /// Return the window, for reuse with `with_window`.
+    pub fn into_window(self) -> Vec<u8> {
+        self.window.into_vec()
+    }
+
+    /// Read a byte which continues the current token.
+    fn next_byte(&mut self) -> io::Result<u8> {
+        match try!(self.getc()) {
+            Some(b) => Ok(b),
+            None => Err(Error::UnexpectedEof.into()),
         }
     }
 
-    fn copy_out(&mut self, output: &mut [u8], written: &mut usize) {
-        while *written < output.len() && self.returned != self.position {
-            output[*written] = self.window[self.returned];
-            *written += 1;
-            self.returned = mod_window(self.returned + 1);
+    /// Read a run of literals into the window, with `l` from the
+    /// length field of the token.
+    fn read_literal_run(&mut self, l: usize) -> io::Result<()> {
+        let mut n = l + 1;
+        if n == LITERAL_RUN_EXT {
+            loop {
+                let ext = try!(self.next_byte());
+                n += ext as usize;
+                if n > MAX_LITERAL_RUN {
+                    return Err(Error::corrupt_at(self.inner.position(),
+                                                 "literal run too long").into());
+                }
+                if ext < 255 {
+                    break;
+                }
+            }
+        }
+        for _ in 0..n {
+            let lit = try!(self.next_byte());
+            self.recorder.record(Event::Literal(lit));
+            self.window.push(lit);
         }
+        Ok(())
+    }
+
+    fn copy_out(&mut self, output: &mut [u8], written: &mut usize) {
+        *written += self.window.read(&mut output[*written..]);
     }
     
     fn process(&mut self, output: &mut [u8]) -> io::Result<usize> {
         let mut written = 0;
-        while written < output.len() {
-            if let Some(m1) = try!(self.getc()) {
-                let mbm2 = try!(self.getc());
-                let mblit = try!(self.getc());
-                match (mbm2, mblit) {
-                    (Some(m2), Some(lit)) => {
-                        let len = ((m1 >> 4) as usize) + MIN_MATCH_LEN;
-                        let ofs = (((m1 as usize) & 0xf) << 8) | (m2 as usize);
-                        if ofs > 0 {
-                            let pos =
-                                if ofs < self.position {
-                                    self.position - ofs
-                                } else {
-                                    WINDOW_SIZE - (ofs - self.position)
-                                };
-                            for i in 0..len {
-                                self.window[mod_window(self.position + i)] = self.window[mod_window(pos + i)];
-                            }
-                            self.window[mod_window(self.position + len)] = lit;
-                            self.position = mod_window(self.position + len + 1);
-                        } else {
-                            self.window[mod_window(self.position)] = lit;
-                            self.position = mod_window(self.position + 1);
-                        }
-
-                        self.copy_out(output, &mut written);
-                    },
-                    _ => {
-                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "cannot read match/lit pair"));
-                    },
-                }
+
+        // Copy out data that already was decompressed but did not fit
+        // into output last time.
+        self.copy_out(output, &mut written);
+
+        while written < output.len() && !self.eof {
+            try!(self.process_token());
+            self.copy_out(output, &mut written);
+        }
+        Ok(written)
+    }
+
+    /// Decode tokens until there is data in the window or the stream
+    /// has ended.
+    fn fill(&mut self) -> io::Result<()> {
+        while self.window.buffered() == 0 && !self.eof {
+            try!(self.process_token().map_err(|e| at_offset(e, self.total_in())));
+        }
+        Ok(())
+    }
+
+    /// Decode the next token into the window, or note the end of the
+    /// stream.
+    fn process_token(&mut self) -> io::Result<()> {
+        if let Some(m1) = try!(self.getc()) {
+            let m2 = try!(self.next_byte());
+            let mut len = ((m1 >> 4) as usize) + MIN_MATCH_LEN;
+            let mut ofs = (((m1 as usize) & 0xf) << 8) | (m2 as usize);
+            if ofs == EOS_OFFSET {
+                // Leave the input after the end marker unread.
+                self.recorder.record(Event::End);
+                self.eof = true;
+            } else if ofs == 0 && len > MIN_MATCH_LEN {
+                try!(self.read_literal_run(len - MIN_MATCH_LEN));
             } else {
-                self.copy_out(output, &mut written);
-                break;
+                let lit = try!(self.next_byte());
+                if ofs >= REP_BASE {
+                    let rep_idx = (ofs >> 2) & (REP_COUNT - 1);
+                    len += (ofs & 3) << LENGTH_BITS;
+                    ofs = self.reps[rep_idx];
+                    if ofs == 0 {
+                        return Err(Error::corrupt_at(self.inner.position(),
+                                                     "repeat match without offset").into());
+                    }
+                    promote_rep(&mut self.reps, rep_idx);
+                } else if ofs > 0 {
+                    push_rep(&mut self.reps, ofs);
+                }
+                if ofs > self.window.filled() {
+                    return Err(Error::corrupt_at(self.inner.position(),
+                                                 "match before start of data").into());
+                }
+                if ofs > 0 {
+                    self.recorder.record(Event::Match { offset: ofs, len: len });
+                    self.window.copy_match(ofs, len);
+                }
+                self.recorder.record(Event::Literal(lit));
+                self.window.push(lit);
             }
+        } else {
+            self.eof = true;
         }
-        Ok(written)
+        Ok(())
     }
 }
 
 impl<R: Read> Read for Reader<R> {
     fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
-        self.process(output)
+        let n = try!(self.process(output).map_err(|e| at_offset(e, self.total_in())));
+        self.total_out += n as u64;
+        Ok(n)
+    }
+}
+
+/// The decoded data which has not been read yet is returned directly
+/// from the window.
+impl<R: Read> BufRead for Reader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        try!(self.fill());
+        Ok(self.window.buffered_data())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.window.consume(amt);
+        self.total_out += amt as u64;
+    }
+}
+
+impl<R: Read> Inspect for Reader<R> {
+    fn recorder(&mut self) -> &mut Recorder {
+        &mut self.recorder
     }
 }
 
@@ -231,12 +527,28 @@ pub fn compress<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error>
     Ok(cw.to_inner())
 }
 
+/// Compress `input` like `compress`, but at compression `level`.
+pub fn compress_with_level<R: Read, W: Write>(mut input: R, output: W, level: u32) -> Result<W, Error> {
+    let mut cw = Writer::with_level(output, level);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.to_inner())
+}
+
+/// Decompress `input` into `output`.  The input is read ahead in
+/// blocks, so use `Reader::new` for streams followed by other data.
 pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<W, Error> {
-    let mut cr = Reader::new(input);
+    let mut cr = Reader::buffered(input);
     try!(io::copy(&mut cr, &mut output));
     Ok(output)
 }
 
+/// Return an iterator over the literals and matches of the stream in
+/// `input`.
+pub fn inspect<R: Read>(input: R) -> Events<Reader<R>> {
+    Events::new(Reader::new(input))
+}
+
 #[cfg(test)]
 mod tests {
     use ::std::io::Cursor;
@@ -256,25 +568,29 @@ mod tests {
 
     #[test]
     fn compress_empty() {
-        cmp_test(b"", &[]);
+        cmp_test(b"", &[15, 239]);
     }
 
     #[test]
     fn compress_a() {
-        cmp_test(b"a", &[0, 0, b'a']);
+        cmp_test(b"a", &[0, 0, b'a', 15, 239]);
     }
 
     #[test]
     fn compress_aaa() {
-        cmp_test(b"aaaaaaaaa", &[0, 0, 97, 48, 1, 97]);
+        cmp_test(b"aaaaaaaaa", &[0, 0, 97, 48, 1, 97, 15, 239]);
     }
 
     #[test]
     fn compress_abc() {
         cmp_test(b"abcdefgabcdefgabcabcabcdefg",
-                 &[0, 0, 97, 0, 0, 98, 0, 0, 99, 0, 0, 100, 0, 0, 101, 0,
-                   0, 102, 0, 0, 103, 96, 7, 97, 0, 0, 98, 0, 0, 99, 32, 13,
-                   103]);
+                 &[96, 0, 97, 98, 99, 100, 101, 102, 103, 96, 7, 97, 16, 3,
+                   100, 32, 0, 101, 102, 103, 15, 239]);
+    }
+
+    #[test]
+    fn compress_rep() {
+        cmp_test(&[b'a'; 100], &[0, 0, 97, 224, 1, 97, 255, 243, 97, 127, 240, 97, 15, 239]);
     }
 
     fn decmp_test(compressed: &[u8], expected_output: &[u8]) {
@@ -306,16 +622,73 @@ mod tests {
     fn decompress_abc() {
         decmp_test(
             &[0, 0, 97, 0, 0, 98, 0, 0, 99, 0, 0, 100, 0, 0, 101, 0,
-              0, 102, 0, 0, 103, 96, 7, 97, 0, 0, 98, 0, 0, 99, 32, 13,
-              103],
+              0, 102, 0, 0, 103, 96, 7, 97, 16, 3, 100, 0, 0, 101, 0,
+              0, 102, 0, 0, 103],
             b"abcdefgabcdefgabcabcabcdefg");
     }
 
-    fn roundtrip(input: &[u8]) {
-        let mut cw = Writer::new(vec![]);
+    #[test]
+    fn decompress_literal_run() {
+        decmp_test(&[96, 0, 97, 98, 99, 100, 101, 102, 103, 96, 7, 97, 16, 3,
+                     100, 32, 0, 101, 102, 103],
+                   b"abcdefgabcdefgabcabcabcdefg");
+    }
+
+    #[test]
+    fn decompress_embedded() {
+        let mut input = Cursor::new(vec![0, 0, 97, 48, 1, 97, 15, 239, 1, 2, 3]);
+        let mut decompressed = Vec::new();
+        Reader::new(&mut input).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(b"aaaaaaaaa", &decompressed[..]);
+        assert_eq!(8, input.position());
+    }
+
+    #[test]
+    fn decompress_buffered() {
+        let mut input = Cursor::new(vec![0, 0, 97, 48, 1, 97, 15, 239, 1, 2, 3]);
+        let mut decompressed = Vec::new();
+        Reader::buffered(&mut input).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(b"aaaaaaaaa", &decompressed[..]);
+        assert_eq!(11, input.position());
+    }
+
+    #[test]
+    fn inspect_events() {
+        use inspect::Event;
+        let input = [0, 0, 97, 48, 1, 97, 15, 239];
+        let events: Vec<Event> = super::inspect(Cursor::new(&input[..])).map(|e| e.unwrap()).collect();
+        assert_eq!(vec![Event::Literal(b'a'), Event::Match { offset: 1, len: 7 },
+                        Event::Literal(b'a'), Event::End],
+                   events);
+    }
+
+    #[test]
+    fn decompress_literal_run_too_long() {
+        let mut input = vec![0xf0, 0, 255, 255, 255, 255, 0];
+        input.extend_from_slice(&[0; 1100]);
+        let mut cr = Reader::new(Cursor::new(input));
+        let mut decompressed = Vec::new();
+        assert!(cr.read_to_end(&mut decompressed).is_err());
+    }
+
+    #[test]
+    fn decompress_rep() {
+        decmp_test(&[0, 0, 97, 224, 1, 97, 255, 243, 97, 127, 240, 97], &[b'a'; 100]);
+    }
+
+    fn roundtrip(input: &[u8]) -> usize {
+        roundtrip_level(input, ::window::DEFAULT_LEVEL)
+    }
+
+    fn roundtrip_level(input: &[u8], level: u32) -> usize {
+        let mut cw = Writer::with_level(vec![], level);
         cw.write_all(&input[..]).unwrap();
         cw.flush().unwrap();
+        let total_out = cw.total_out();
+        assert_eq!(input.len() as u64, cw.total_in());
         let compressed = cw.to_inner();
+        let compressed_len = compressed.len();
+        assert_eq!(compressed_len as u64, total_out);
 
         let mut cr = Reader::new(Cursor::new(compressed));
         let mut decompressed = Vec::new();
@@ -323,6 +696,9 @@ mod tests {
 
         assert_eq!(input.len(), nread);
         assert_eq!(&input[..], &decompressed[..]);
+        assert_eq!(compressed_len as u64, cr.total_in());
+        assert_eq!(input.len() as u64, cr.total_out());
+        compressed_len
     }
 
     #[test]
@@ -330,4 +706,96 @@ mod tests {
         let input = include_bytes!("lz77.rs");
         roundtrip(input);
     }
+
+    #[test]
+    fn compress_incompressible() {
+        let mut input = Vec::new();
+        let mut x: u32 = 1;
+        for _ in 0..10000 {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            input.push((x >> 16) as u8);
+        }
+        let compressed_len = roundtrip(&input);
+        assert!(compressed_len < input.len() + input.len() / 100);
+    }
+
+    #[test]
+    fn compress_levels() {
+        let input = include_bytes!("lz77.rs");
+        let fast = roundtrip_level(input, 1);
+        let best = roundtrip_level(input, ::window::MAX_LEVEL);
+        assert!(best < fast);
+    }
+
+    #[test]
+    fn compress_lazy() {
+        let input = b"abcd1bcdefg2abcdefg3";
+        let greedy = roundtrip_level(input, super::LAZY_LEVEL - 1);
+        let lazy = roundtrip_level(input, super::LAZY_LEVEL);
+        assert!(lazy < greedy);
+    }
+
+    #[test]
+    fn buf_read() {
+        use ::std::io::BufRead;
+        let input = include_bytes!("lz77.rs");
+        let mut cw = Writer::new(vec![]);
+        cw.write_all(&input[..]).unwrap();
+        cw.flush().unwrap();
+        let compressed = cw.to_inner();
+
+        let mut cr = Reader::new(&compressed[..]);
+        let mut lines = 0;
+        let mut output = Vec::new();
+        while cr.read_until(b'\n', &mut output).unwrap() > 0 {
+            lines += 1;
+        }
+        assert_eq!(input.iter().filter(|&&b| b == b'\n').count(), lines);
+        assert_eq!(&input[..], &output[..]);
+        assert_eq!(input.len() as u64, cr.total_out());
+    }
+
+    #[test]
+    fn decode_with_window() {
+        let input = include_bytes!("lz77.rs");
+        let mut cw = Writer::new(vec![]);
+        cw.write_all(&input[..]).unwrap();
+        cw.flush().unwrap();
+        let compressed = cw.to_inner();
+
+        // Decode twice with the same window.
+        let mut window = vec![0; 8192];
+        for _ in 0..2 {
+            let mut cr = Reader::with_window(&compressed[..], window);
+            let mut output = Vec::new();
+            loop {
+                let data = cr.decode().unwrap();
+                if data.is_empty() {
+                    break;
+                }
+                output.extend_from_slice(data);
+            }
+            assert_eq!(&input[..], &output[..]);
+            window = cr.into_window();
+        }
+        assert_eq!(8192, window.len());
+    }
+
+    #[test]
+    fn match_before_start() {
+        use error::Error;
+        // A match with offset 2 after a single literal.
+        let mut cr = Reader::new(Cursor::new(&[0u8, 0, 97, 48, 2, 97][..]));
+        let mut output = Vec::new();
+        match Error::from(cr.read_to_end(&mut output).unwrap_err()) {
+            Error::CorruptInput { .. } => (),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+    }
 }
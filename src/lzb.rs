@@ -0,0 +1,666 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! LZ77 compressor with binary arithmetic coded symbols.
+//!
+//! This combines a hash chain match finder over a 1 MiB window with
+//! the binary arithmetic coder from the `binarith` module, similar in
+//! spirit to LZMA.  Every decision is coded as a bit with an adaptive
+//! model selected by a context:
+//!
+//! - literal/match flags by the previous token kind and the lowest
+//!   two bits of the position,
+//!
+//! - literals bitwise along a binary tree, with the high three bits of
+//!   the previous byte as context,
+//!
+//! - match lengths by a three-level length coder with the position as
+//!   context,
+//!
+//! - match distances as a slot (roughly the logarithm of the
+//!   distance, using the match length as context) followed by extra
+//!   bits, the lowest four of which are modelled as well.
+//!
+//! The end of the stream is marked by a match with a reserved
+//! distance slot.
+
+use std::io::{Read, Write};
+use std::io;
+
+use error::{at_offset, Error};
+use binarith::{Encoder, Decoder, Bit, Count};
+use inspect::{Event, Events, Inspect, Recorder};
+
+const WINDOW_BITS: usize = 20;
+const WINDOW_SIZE: usize = 1 << WINDOW_BITS;
+const WINDOW_MASK: usize = WINDOW_SIZE - 1;
+
+const MIN_MATCH_LEN: usize = 3;
+const LEN_LOW_SYMBOLS: usize = 8;
+const LEN_MID_SYMBOLS: usize = 8;
+const LEN_HIGH_SYMBOLS: usize = 256;
+const MAX_MATCH_LEN: usize =
+    MIN_MATCH_LEN + LEN_LOW_SYMBOLS + LEN_MID_SYMBOLS + LEN_HIGH_SYMBOLS - 1;
+
+/// Matches of minimal length are not worth it beyond this distance.
+const MAX_SHORT_DISTANCE: usize = 1 << 12;
+
+const HASH_BITS: usize = 16;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+const MAX_CHAIN: usize = 48;
+
+const POS_BITS: usize = 2;
+const POS_STATES: usize = 1 << POS_BITS;
+const LIT_CONTEXT_BITS: usize = 3;
+const LEN_STATES: usize = 4;
+const DIST_SLOT_BITS: usize = 6;
+const END_SLOT: usize = (1 << DIST_SLOT_BITS) - 1;
+const ALIGN_BITS: usize = 4;
+
+/// Counts of an adaptive bit model are halved when their sum exceeds
+/// this limit, so that the model follows changing statistics.
+const MODEL_LIMIT: Count = 1 << 10;
+const MODEL_INC: Count = 24;
+
+#[derive(Clone, Copy)]
+struct BitModel {
+    c0: Count,
+    c1: Count,
+}
+
+impl BitModel {
+    fn new() -> BitModel {
+        BitModel { c0: 1, c1: 1 }
+    }
+
+    fn update(&mut self, bit: Bit) {
+        if bit == 0 {
+            self.c0 += MODEL_INC;
+        } else {
+            self.c1 += MODEL_INC;
+        }
+        if self.c0 + self.c1 > MODEL_LIMIT {
+            self.c0 = (self.c0 + 1) / 2;
+            self.c1 = (self.c1 + 1) / 2;
+        }
+    }
+}
+
+fn encode_bit<W: Write>(enc: &mut Encoder<W>, model: &mut BitModel, bit: Bit) -> io::Result<()> {
+    try!(enc.encode(bit, model.c0, model.c1));
+    model.update(bit);
+    Ok(())
+}
+
+fn decode_bit<R: Read>(dec: &mut Decoder<R>, model: &mut BitModel) -> io::Result<Bit> {
+    let bit = try!(dec.decode(model.c0, model.c1));
+    model.update(bit);
+    Ok(bit)
+}
+
+/// Encode the lowest `bits` bits of `value`, most significant bit
+/// first, along a binary tree of models.
+fn encode_tree<W: Write>(enc: &mut Encoder<W>, models: &mut [BitModel], bits: usize,
+                         value: usize) -> io::Result<()> {
+    let mut node = 1;
+    for i in (0..bits).rev() {
+        let bit = (value >> i) & 1;
+        try!(encode_bit(enc, &mut models[node], bit));
+        node = (node << 1) | bit;
+    }
+    Ok(())
+}
+
+fn decode_tree<R: Read>(dec: &mut Decoder<R>, models: &mut [BitModel],
+                        bits: usize) -> io::Result<usize> {
+    let mut node = 1;
+    for _ in 0..bits {
+        let bit = try!(decode_bit(dec, &mut models[node]));
+        node = (node << 1) | bit;
+    }
+    Ok(node - (1 << bits))
+}
+
+fn encode_direct<W: Write>(enc: &mut Encoder<W>, bits: usize, value: usize) -> io::Result<()> {
+    for i in (0..bits).rev() {
+        try!(enc.encode((value >> i) & 1, 1, 1));
+    }
+    Ok(())
+}
+
+fn decode_direct<R: Read>(dec: &mut Decoder<R>, bits: usize) -> io::Result<usize> {
+    let mut value = 0;
+    for _ in 0..bits {
+        value = (value << 1) | try!(dec.decode(1, 1));
+    }
+    Ok(value)
+}
+
+/// Return the distance slot for the zero-based distance `d`.
+fn dist_slot(d: usize) -> usize {
+    if d < 4 {
+        d
+    } else {
+        let n = (0usize.leading_zeros() - d.leading_zeros()) as usize - 1;
+        2 * n + ((d >> (n - 1)) & 1)
+    }
+}
+
+/// Number of extra bits following distance slot `slot`.
+fn dist_footer_bits(slot: usize) -> usize {
+    (slot >> 1) - 1
+}
+
+fn dist_base(slot: usize) -> usize {
+    (2 | (slot & 1)) << dist_footer_bits(slot)
+}
+
+/// Complete adaptive model shared by compressor and decompressor.
+struct Model {
+    prev_match: usize,
+    is_match: Vec<BitModel>,
+    literal: Vec<BitModel>,
+    len_choice: BitModel,
+    len_choice2: BitModel,
+    len_low: Vec<BitModel>,
+    len_mid: Vec<BitModel>,
+    len_high: Vec<BitModel>,
+    dist_slot: Vec<BitModel>,
+    dist_footer: Vec<BitModel>,
+    dist_align: Vec<BitModel>,
+}
+
+impl Model {
+    fn new() -> Model {
+        Model {
+            prev_match: 0,
+            is_match: vec![BitModel::new(); 2 * POS_STATES],
+            literal: vec![BitModel::new(); 256 << LIT_CONTEXT_BITS],
+            len_choice: BitModel::new(),
+            len_choice2: BitModel::new(),
+            len_low: vec![BitModel::new(); LEN_LOW_SYMBOLS * POS_STATES],
+            len_mid: vec![BitModel::new(); LEN_MID_SYMBOLS * POS_STATES],
+            len_high: vec![BitModel::new(); LEN_HIGH_SYMBOLS],
+            dist_slot: vec![BitModel::new(); LEN_STATES << DIST_SLOT_BITS],
+            dist_footer: vec![BitModel::new(); (END_SLOT + 1) << ALIGN_BITS],
+            dist_align: vec![BitModel::new(); 1 << ALIGN_BITS],
+        }
+    }
+
+    fn is_match_index(&self, pos: u64) -> usize {
+        self.prev_match * POS_STATES + (pos as usize & (POS_STATES - 1))
+    }
+
+    fn literal_models(&mut self, prev_byte: u8) -> &mut [BitModel] {
+        let ctx = (prev_byte >> (8 - LIT_CONTEXT_BITS)) as usize;
+        &mut self.literal[ctx << 8..(ctx + 1) << 8]
+    }
+
+    fn encode_literal<W: Write>(&mut self, enc: &mut Encoder<W>, pos: u64,
+                                prev_byte: u8, lit: u8) -> io::Result<()> {
+        let idx = self.is_match_index(pos);
+        try!(encode_bit(enc, &mut self.is_match[idx], 0));
+        try!(encode_tree(enc, self.literal_models(prev_byte), 8, lit as usize));
+        self.prev_match = 0;
+        Ok(())
+    }
+
+    fn encode_len<W: Write>(&mut self, enc: &mut Encoder<W>, pos: u64,
+                            len: usize) -> io::Result<()> {
+        let pos_state = pos as usize & (POS_STATES - 1);
+        let l = len - MIN_MATCH_LEN;
+        if l < LEN_LOW_SYMBOLS {
+            try!(encode_bit(enc, &mut self.len_choice, 0));
+            let models = &mut self.len_low[pos_state * LEN_LOW_SYMBOLS..];
+            encode_tree(enc, models, 3, l)
+        } else if l < LEN_LOW_SYMBOLS + LEN_MID_SYMBOLS {
+            try!(encode_bit(enc, &mut self.len_choice, 1));
+            try!(encode_bit(enc, &mut self.len_choice2, 0));
+            let models = &mut self.len_mid[pos_state * LEN_MID_SYMBOLS..];
+            encode_tree(enc, models, 3, l - LEN_LOW_SYMBOLS)
+        } else {
+            try!(encode_bit(enc, &mut self.len_choice, 1));
+            try!(encode_bit(enc, &mut self.len_choice2, 1));
+            encode_tree(enc, &mut self.len_high, 8, l - LEN_LOW_SYMBOLS - LEN_MID_SYMBOLS)
+        }
+    }
+
+    fn decode_len<R: Read>(&mut self, dec: &mut Decoder<R>, pos: u64) -> io::Result<usize> {
+        let pos_state = pos as usize & (POS_STATES - 1);
+        let l = if try!(decode_bit(dec, &mut self.len_choice)) == 0 {
+            let models = &mut self.len_low[pos_state * LEN_LOW_SYMBOLS..];
+            try!(decode_tree(dec, models, 3))
+        } else if try!(decode_bit(dec, &mut self.len_choice2)) == 0 {
+            let models = &mut self.len_mid[pos_state * LEN_MID_SYMBOLS..];
+            LEN_LOW_SYMBOLS + try!(decode_tree(dec, models, 3))
+        } else {
+            LEN_LOW_SYMBOLS + LEN_MID_SYMBOLS + try!(decode_tree(dec, &mut self.len_high, 8))
+        };
+        Ok(l + MIN_MATCH_LEN)
+    }
+
+    fn encode_dist<W: Write>(&mut self, enc: &mut Encoder<W>, len: usize,
+                             slot: usize, d: usize) -> io::Result<()> {
+        let len_state = ::std::cmp::min(len - MIN_MATCH_LEN, LEN_STATES - 1);
+        {
+            let models = &mut self.dist_slot[len_state << DIST_SLOT_BITS..];
+            try!(encode_tree(enc, models, DIST_SLOT_BITS, slot));
+        }
+        if slot >= 4 && slot != END_SLOT {
+            let footer = dist_footer_bits(slot);
+            let extra = d - dist_base(slot);
+            if footer <= ALIGN_BITS {
+                let models = &mut self.dist_footer[slot << ALIGN_BITS..];
+                try!(encode_tree(enc, models, footer, extra));
+            } else {
+                try!(encode_direct(enc, footer - ALIGN_BITS, extra >> ALIGN_BITS));
+                try!(encode_tree(enc, &mut self.dist_align, ALIGN_BITS,
+                                 extra & ((1 << ALIGN_BITS) - 1)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Decode a zero-based distance.  Returns `None` for the end of
+    /// stream marker.
+    fn decode_dist<R: Read>(&mut self, dec: &mut Decoder<R>,
+                            len: usize) -> io::Result<Option<usize>> {
+        let len_state = ::std::cmp::min(len - MIN_MATCH_LEN, LEN_STATES - 1);
+        let slot = {
+            let models = &mut self.dist_slot[len_state << DIST_SLOT_BITS..];
+            try!(decode_tree(dec, models, DIST_SLOT_BITS))
+        };
+        if slot == END_SLOT {
+            return Ok(None);
+        }
+        if slot < 4 {
+            return Ok(Some(slot));
+        }
+        let footer = dist_footer_bits(slot);
+        let extra = if footer <= ALIGN_BITS {
+            let models = &mut self.dist_footer[slot << ALIGN_BITS..];
+            try!(decode_tree(dec, models, footer))
+        } else {
+            let high = try!(decode_direct(dec, footer - ALIGN_BITS));
+            (high << ALIGN_BITS) | try!(decode_tree(dec, &mut self.dist_align, ALIGN_BITS))
+        };
+        Ok(Some(dist_base(slot) + extra))
+    }
+
+    fn encode_match<W: Write>(&mut self, enc: &mut Encoder<W>, pos: u64,
+                              len: usize, dist: usize) -> io::Result<()> {
+        let idx = self.is_match_index(pos);
+        try!(encode_bit(enc, &mut self.is_match[idx], 1));
+        try!(self.encode_len(enc, pos, len));
+        let d = dist - 1;
+        try!(self.encode_dist(enc, len, dist_slot(d), d));
+        self.prev_match = 1;
+        Ok(())
+    }
+
+    fn encode_end<W: Write>(&mut self, enc: &mut Encoder<W>, pos: u64) -> io::Result<()> {
+        let idx = self.is_match_index(pos);
+        try!(encode_bit(enc, &mut self.is_match[idx], 1));
+        try!(self.encode_len(enc, pos, MIN_MATCH_LEN));
+        self.encode_dist(enc, MIN_MATCH_LEN, END_SLOT, 0)
+    }
+}
+
+/// Writer for LZB compression.
+pub struct Writer<W> {
+    encoder: Encoder<W>,
+    model: Model,
+    buf: Vec<u8>,
+    pos: usize,
+    /// Number of bytes dropped from the front of `buf`.
+    offset: u64,
+    head: Vec<u32>,
+    prev: Vec<u32>,
+    finished: bool,
+}
+
+#[inline(always)]
+fn hash_at(buf: &[u8], pos: usize) -> usize {
+    let v = (buf[pos] as u32) << 16 | (buf[pos + 1] as u32) << 8 | buf[pos + 2] as u32;
+    (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+impl<W: Write> Writer<W> {
+    /// Create a new LZB writer.
+    pub fn new(inner: W) -> Writer<W> {
+        Writer {
+            encoder: Encoder::new(inner),
+            model: Model::new(),
+            buf: Vec::new(),
+            pos: 0,
+            offset: 0,
+            head: vec![0; HASH_SIZE],
+            prev: vec![0; WINDOW_SIZE],
+            finished: false,
+        }
+    }
+
+    /// Enter position `pos` into the hash chains.  Positions are
+    /// stored off by one, so that 0 means "no position".
+    fn insert(&mut self, pos: usize) {
+        if pos + MIN_MATCH_LEN <= self.buf.len() {
+            let h = hash_at(&self.buf, pos);
+            self.prev[pos & WINDOW_MASK] = self.head[h];
+            self.head[h] = (pos + 1) as u32;
+        }
+    }
+
+    fn find_match(&self, pos: usize) -> (usize, usize) {
+        let max_len = ::std::cmp::min(MAX_MATCH_LEN, self.buf.len() - pos);
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        if max_len < MIN_MATCH_LEN {
+            return (0, 0);
+        }
+        let mut cand = self.head[hash_at(&self.buf, pos)] as usize;
+        let mut chain = 0;
+        while cand > 0 && chain < MAX_CHAIN {
+            let c = cand - 1;
+            if c >= pos || pos - c >= WINDOW_SIZE {
+                break;
+            }
+            if self.buf[c + best_len] == self.buf[pos + best_len] {
+                let mut len = 0;
+                while len < max_len && self.buf[c + len] == self.buf[pos + len] {
+                    len += 1;
+                }
+                if len > best_len && (len > MIN_MATCH_LEN || pos - c <= MAX_SHORT_DISTANCE) {
+                    best_len = len;
+                    best_dist = pos - c;
+                    if len == max_len {
+                        break;
+                    }
+                }
+            }
+            cand = self.prev[c & WINDOW_MASK] as usize;
+            chain += 1;
+        }
+        (best_len, best_dist)
+    }
+
+    /// Drop the oldest window's worth of data from the buffer once
+    /// enough data has been processed.
+    fn slide(&mut self) {
+        if self.pos >= 2 * WINDOW_SIZE {
+            self.buf.drain(..WINDOW_SIZE);
+            self.pos -= WINDOW_SIZE;
+            self.offset += WINDOW_SIZE as u64;
+            for h in self.head.iter_mut().chain(self.prev.iter_mut()) {
+                *h = h.saturating_sub(WINDOW_SIZE as u32);
+            }
+        }
+    }
+
+    /// Parse and encode input while at least `lookahead` bytes are
+    /// available, using one step of lazy matching.
+    fn process(&mut self, lookahead: usize) -> io::Result<()> {
+        while self.pos < self.buf.len() && self.buf.len() - self.pos >= lookahead {
+            let pos = self.pos;
+            let (len, dist) = self.find_match(pos);
+            self.insert(pos);
+            let abs = self.offset + pos as u64;
+            let prev_byte = if pos > 0 { self.buf[pos - 1] } else { 0 };
+            if len >= MIN_MATCH_LEN {
+                let (next_len, _) = if pos + 1 < self.buf.len() {
+                    self.find_match(pos + 1)
+                } else {
+                    (0, 0)
+                };
+                if next_len > len + 1 {
+                    let lit = self.buf[pos];
+                    try!(self.model.encode_literal(&mut self.encoder, abs, prev_byte, lit));
+                    self.pos += 1;
+                } else {
+                    try!(self.model.encode_match(&mut self.encoder, abs, len, dist));
+                    for p in pos + 1..pos + len {
+                        self.insert(p);
+                    }
+                    self.pos += len;
+                }
+            } else {
+                let lit = self.buf[pos];
+                try!(self.model.encode_literal(&mut self.encoder, abs, prev_byte, lit));
+                self.pos += 1;
+            }
+            self.slide();
+        }
+        Ok(())
+    }
+
+    /// Encode all pending data and the end of stream marker.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            try!(self.process(1));
+            let abs = self.offset + self.pos as u64;
+            try!(self.model.encode_end(&mut self.encoder, abs));
+            try!(self.encoder.finish());
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    /// Return the number of uncompressed bytes written so far.
+    pub fn total_in(&self) -> u64 {
+        self.offset + self.buf.len() as u64
+    }
+
+    /// Return the number of compressed bytes written to the wrapped
+    /// writer so far.
+    pub fn total_out(&self) -> u64 {
+        self.encoder.bytes_written()
+    }
+
+    /// Move the wrapped writer out of the LZB writer.
+    pub fn into_inner(self) -> W {
+        self.encoder.into_inner()
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        try!(self.process(MAX_MATCH_LEN + 1));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.finish()
+    }
+}
+
+/// Reader for LZB compressed streams.
+pub struct Reader<R> {
+    decoder: Decoder<R>,
+    model: Model,
+    window: Vec<u8>,
+    returned: usize,
+    /// Number of bytes dropped from the front of `window`.
+    offset: u64,
+    eof: bool,
+    recorder: Recorder,
+}
+
+impl<R: Read> Reader<R> {
+    /// Create a new LZB reader.  This starts decoding, therefore the
+    /// result can be an error.
+    pub fn new(inner: R) -> io::Result<Reader<R>> {
+        Ok(Reader {
+            decoder: try!(Decoder::new(inner)),
+            model: Model::new(),
+            window: Vec::new(),
+            returned: 0,
+            offset: 0,
+            eof: false,
+            recorder: Recorder::new(),
+        })
+    }
+
+    /// Return the number of compressed bytes consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.decoder.bytes_read()
+    }
+
+    /// Return the number of decompressed bytes read so far.
+    pub fn total_out(&self) -> u64 {
+        self.offset + self.returned as u64
+    }
+
+    fn decode_token(&mut self) -> io::Result<()> {
+        let pos = self.offset + self.window.len() as u64;
+        let idx = self.model.is_match_index(pos);
+        if try!(decode_bit(&mut self.decoder, &mut self.model.is_match[idx])) == 0 {
+            let prev_byte = self.window.last().cloned().unwrap_or(0);
+            let lit = try!(decode_tree(&mut self.decoder,
+                                       self.model.literal_models(prev_byte), 8));
+            self.recorder.record(Event::Literal(lit as u8));
+            self.window.push(lit as u8);
+            self.model.prev_match = 0;
+            return Ok(());
+        }
+
+        let len = try!(self.model.decode_len(&mut self.decoder, pos));
+        let d = match try!(self.model.decode_dist(&mut self.decoder, len)) {
+            Some(d) => d,
+            None => {
+                self.recorder.record(Event::End);
+                self.eof = true;
+                return Ok(());
+            },
+        };
+        if d >= self.window.len() {
+            return Err(Error::corrupt("LZB match distance out of range").into());
+        }
+        self.recorder.record(Event::Match { offset: d + 1, len: len });
+        let start = self.window.len() - d - 1;
+        for i in 0..len {
+            let b = self.window[start + i];
+            self.window.push(b);
+        }
+        self.model.prev_match = 1;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
+        while !self.eof && self.window.len() - self.returned < output.len() {
+            try!(self.decode_token().map_err(|e| at_offset(e, self.total_in())));
+        }
+        let n = ::std::cmp::min(output.len(), self.window.len() - self.returned);
+        output[..n].copy_from_slice(&self.window[self.returned..self.returned + n]);
+        self.returned += n;
+        if self.returned >= 2 * WINDOW_SIZE {
+            self.window.drain(..WINDOW_SIZE);
+            self.returned -= WINDOW_SIZE;
+            self.offset += WINDOW_SIZE as u64;
+        }
+        Ok(n)
+    }
+}
+
+impl<R: Read> Inspect for Reader<R> {
+    fn recorder(&mut self) -> &mut Recorder {
+        &mut self.recorder
+    }
+}
+
+/// Compress all data from `input` into `output`.  On success, the
+/// output is returned.
+pub fn compress<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error> {
+    let mut cw = Writer::new(output);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.into_inner())
+}
+
+/// Decompress the LZB stream in `input` and write the result to
+/// `output`.  On success, the output is returned.
+pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<W, Error> {
+    let mut cr = try!(Reader::new(input));
+    try!(io::copy(&mut cr, &mut output));
+    Ok(output)
+}
+
+/// Return an iterator over the literals and matches of the LZB
+/// stream in `input`.  This starts decoding, therefore the result can
+/// be an error.
+pub fn inspect<R: Read>(input: R) -> io::Result<Events<Reader<R>>> {
+    Ok(Events::new(try!(Reader::new(input))))
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::io::Cursor;
+    use super::{compress, decompress, dist_slot, dist_base, dist_footer_bits};
+
+    fn roundtrip(input: &[u8]) {
+        let compressed = compress(Cursor::new(input), vec![]).unwrap();
+        let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(input.len(), decompressed.len());
+        assert_eq!(&input[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn dist_slots() {
+        for d in 4..100000 {
+            let slot = dist_slot(d);
+            assert!(d >= dist_base(slot));
+            assert!(d - dist_base(slot) < 1 << dist_footer_bits(slot));
+        }
+        assert_eq!(39, dist_slot((1 << 20) - 1));
+    }
+
+    #[test]
+    fn compress_empty() {
+        roundtrip(b"");
+    }
+
+    #[test]
+    fn compress_a() {
+        roundtrip(b"a");
+    }
+
+    #[test]
+    fn compress_aaa() {
+        let input = [b'a'; 1000];
+        let compressed = compress(Cursor::new(&input[..]), vec![]).unwrap();
+        assert!(compressed.len() < 30);
+        roundtrip(&input);
+    }
+
+    #[test]
+    fn compress_decompress() {
+        roundtrip(include_bytes!("lzb.rs"));
+    }
+
+    #[test]
+    fn compress_decompress_large() {
+        let f = include_bytes!("lzb.rs");
+        let mut input = Vec::new();
+        let mut x = 1u32;
+        while input.len() < 5 * (1 << 20) / 2 {
+            input.extend_from_slice(&f[..]);
+            for _ in 0..5000 {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345);
+                input.push((x >> 24) as u8);
+            }
+        }
+        roundtrip(&input);
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+    }
+
+    #[test]
+    fn decompress_garbage() {
+        ::testutil::garbage(|d: &[u8]| super::decompress(d, vec![]));
+    }
+}
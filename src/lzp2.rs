@@ -2,14 +2,18 @@
 // top-level directory of this distribution for license information.
 
 //! Simple implementation of an LZP compressor, combining the approach
-//! from lzp1.rs and a following adaptive Huffman coder.
+//! from lzp1.rs and a following adaptive Huffman coder.  Instead of
+//! the adaptive Huffman coder, any of the coders from the `nested`
+//! module can be used.
 
-use std::io::{Read, Write, Bytes};
+use std::io::{Read, Write};
 use std::io;
 
-use huff::adaptive as nested;
+use nested::{self, NestedCoder};
 
-use error::Error;
+use error::{at_offset, Error};
+use input::ByteReader;
+use inspect::{Event, Events, Inspect, Recorder};
 
 const WINDOW_BITS: usize = 12;
 const LENGTH_BITS: usize = 8;
@@ -21,22 +25,42 @@ const LOOK_AHEAD_BYTES: usize = MAX_MATCH_LEN;
 
 const WINDOW_SIZE: usize = 1 << WINDOW_BITS;
 
-const HASHTAB_SIZE: usize = 1 << 10;
+/// Number of unflagged literals after which the output is written
+/// out, when no group of flags is open.
+const MAX_UNFLAGGED_RUN: usize = 1024;
 
-const MAX_CONTEXT: usize = 3;
+/// Smallest and largest context order, in bytes.
+pub const MIN_ORDER: usize = 2;
+pub const MAX_ORDER: usize = 5;
+
+/// Largest hash table size in bits.
+pub const MAX_TABLE_BITS: usize = 24;
+
+/// Context order and hash table size in bits used when none are
+/// given.
+pub const DEFAULT_ORDER: usize = 2;
+pub const DEFAULT_TABLE_BITS: usize = 16;
 
 /// Writer for LZSS compressed streams.
 pub struct Writer<W> {
     inner:  nested::Writer<W>,
     window: [u8; WINDOW_SIZE],
-    hashtab: [usize; HASHTAB_SIZE],
+    /// Window position following each context, plus one, so that 0
+    /// marks an empty entry.
+    hashtab: Vec<usize>,
+    checks: Vec<u8>,
+    table_bits: usize,
     position: usize,
     look_ahead_bytes: usize,
-    context: [u8; MAX_CONTEXT],
+    context: [u8; MAX_ORDER],
+    order: usize,
     out_flags: u8,
     out_count: usize,
-    out_data:  [u8; 1 + 8*2],
-    out_len:   usize,
+    /// Output of the current group, with the flag byte at index
+    /// `out_flag_pos` once the group has its first flag.
+    out_data:  Vec<u8>,
+    out_flag_pos: usize,
+    total_in: u64,
 }
 
 #[inline(always)]
@@ -44,86 +68,151 @@ fn mod_window(x: usize) -> usize {
     x % WINDOW_SIZE
 }
 
+/// Check the parameters given to the constructors.
+fn check_params(order: usize, table_bits: usize) {
+    assert!(order >= MIN_ORDER && order <= MAX_ORDER);
+    assert!(table_bits >= 1 && table_bits <= MAX_TABLE_BITS);
+}
+
+/// Hash the `context` bytes into a table index of `table_bits` bits
+/// and a check value, which is stored with the table entry to detect
+/// collisions.
+fn hash_context(context: &[u8], table_bits: usize) -> (usize, u8) {
+    let mut h: u64 = 0;
+    for b in context.iter() {
+        h = (h << 8) | *b as u64;
+    }
+    let h = h.wrapping_mul(0x9e3779b97f4a7c15);
+    ((h >> (64 - table_bits)) as usize, (h >> (56 - table_bits)) as u8)
+}
+
+/// Return the offset from window position `position` back to
+/// `match_pos`, between 1 and `WINDOW_SIZE`.
+fn offset(position: usize, match_pos: usize) -> usize {
+    if match_pos < position {
+        position - match_pos
+    } else {
+        position + (WINDOW_SIZE - match_pos)
+    }
+}
+
+/// Copy the `context.len()` bytes before window position `position`
+/// into `context`.
+fn load_context(context: &mut [u8], window: &[u8], position: usize) {
+    let start = position + WINDOW_SIZE - context.len();
+    for i in 0..context.len() {
+        context[i] = window[mod_window(start + i)];
+    }
+}
+
 impl<W: Write> Writer<W> {
-    /// Create a new LZSS writer that wraps the given Writer.
+    /// Create a new LZSS writer that wraps the given Writer.  The
+    /// output is coded with adaptive Huffman coding.
     pub fn new(inner: W) -> Writer<W>{
+        Writer::with_coder(inner, NestedCoder::AdaptiveHuffman)
+    }
+
+    /// Create a new LZSS writer whose output is coded with `coder`.
+    pub fn with_coder(inner: W, coder: NestedCoder) -> Writer<W>{
+        Writer::with_coder_and_params(inner, coder, DEFAULT_ORDER, DEFAULT_TABLE_BITS)
+    }
+
+    /// Create a new LZP writer which predicts from contexts of
+    /// `order` bytes, from `MIN_ORDER` to `MAX_ORDER`, with a hash
+    /// table of `1 << table_bits` entries.  The output is coded with
+    /// adaptive Huffman coding.  The reader has to be created with
+    /// the same parameters.
+    pub fn with_params(inner: W, order: usize, table_bits: usize) -> Writer<W>{
+        Writer::with_coder_and_params(inner, NestedCoder::AdaptiveHuffman, order, table_bits)
+    }
+
+    /// Create a new LZP writer with the given context order and hash
+    /// table size, whose output is coded with `coder`.
+    pub fn with_coder_and_params(inner: W, coder: NestedCoder, order: usize,
+                                 table_bits: usize) -> Writer<W>{
+        check_params(order, table_bits);
         Writer {
-            inner:  nested::Writer::new(inner),
+            inner:  nested::Writer::new(inner, coder),
             window: [0; WINDOW_SIZE],
-            hashtab: [0; HASHTAB_SIZE],
+            hashtab: vec![0; 1 << table_bits],
+            checks: vec![0; 1 << table_bits],
+            table_bits: table_bits,
             position: 0,
             look_ahead_bytes: 0,
-            context: [0; MAX_CONTEXT],
+            context: [0; MAX_ORDER],
+            order: order,
             out_flags: 0,
             out_count: 0,
-            out_data: [0; 1 + 8*2],
-            out_len:  1,
+            out_data: Vec::with_capacity(MAX_UNFLAGGED_RUN + 1 + 8*2),
+            out_flag_pos: 0,
+            total_in: 0,
         }
     }
 
-    /// Output all buffered match/length pairs and literals.
+    /// Write out the buffered output, with the flags of the last
+    /// group padded with zero bits.
     fn emit_flush(&mut self) -> io::Result<()> {
         if self.out_count > 0 {
-            if self.out_count < 8 {
-                self.out_flags <<= 8 - self.out_count;
-            }
-            self.out_data[0] = self.out_flags;
-            try!(self.inner.write_all(&self.out_data[..self.out_len]));
-            
+            self.out_flags <<= 8 - self.out_count;
+            self.out_data[self.out_flag_pos] = self.out_flags;
             self.out_flags = 0;
             self.out_count = 0;
-            self.out_len = 1;
         }
+        try!(self.inner.write_all(&self.out_data));
+        self.out_data.clear();
         Ok(())
     }
 
-    /// Emit the literal byte `lit`.
-    fn emit_lit(&mut self, lit: u8) -> io::Result<()> {
+    /// Add `flag` to the flags of the current group, starting a new
+    /// group if necessary, and buffer `data` for it.  Once the group
+    /// has eight flags, it is written out.
+    fn emit_flagged(&mut self, flag: u8, data: u8) -> io::Result<()> {
+        if self.out_count == 0 {
+            self.out_flag_pos = self.out_data.len();
+            self.out_data.push(0);
+        }
+        self.out_count += 1;
+        self.out_flags = (self.out_flags << 1) | flag;
+        self.out_data.push(data);
         if self.out_count == 8 {
             try!(self.emit_flush());
         }
-        self.out_count += 1;
-        self.out_flags = (self.out_flags << 1) | 1;
-        self.out_data[self.out_len] = lit;
-        self.out_len += 1;
         Ok(())
     }
 
+    /// Emit the literal byte `lit` at a position with a prediction.
+    fn emit_lit(&mut self, lit: u8) -> io::Result<()> {
+        self.emit_flagged(1, lit)
+    }
+
     /// Emit a match, which just contains the match length.
     pub fn emit_match(&mut self, len: u8) -> io::Result<()> {
-        if self.out_count == 8 {
+        self.emit_flagged(0, len)
+    }
+
+    /// Emit the literal byte `lit` at a position without a
+    /// prediction, where no flag is needed.
+    fn emit_unflagged_lit(&mut self, lit: u8) -> io::Result<()> {
+        self.out_data.push(lit);
+        if self.out_count == 0 && self.out_data.len() >= MAX_UNFLAGGED_RUN {
             try!(self.emit_flush());
         }
-        self.out_count += 1;
-        self.out_flags = self.out_flags << 1;
-        self.out_data[self.out_len] = len;
-        self.out_len += 1;
         Ok(())
     }
 
     fn update_context(&mut self) {
-        let start =
-            if self.position >= MAX_CONTEXT {
-                self.position - MAX_CONTEXT
-            } else {
-                WINDOW_SIZE - (MAX_CONTEXT - self.position)
-            };
-        for i in 0..MAX_CONTEXT {
-            self.context[i] = self.window[mod_window(start + i)];
-        }
+        load_context(&mut self.context[..self.order], &self.window, self.position);
     }
 
-    /// Calculate a hash of the next 3 bytes in the look-ahead buffer.
-    /// This hash is used to look up earlier occurences of the data we
-    /// are looking at.  Because hash table entries are overwritten
-    /// blindly, we have to validate whatever we take out of the table
-    /// when calculating the match length.
-    fn hash_context(&self) -> usize {
-        let mut h = 0;
-        for b in self.context.iter() {
-            h = (h << 8) + *b as usize;
-        }
-        h % HASHTAB_SIZE
+    /// Calculate a hash of the `order` bytes before the look-ahead
+    /// buffer.  This hash is used to look up earlier occurences of
+    /// the data we are looking at.  Because hash table entries are
+    /// overwritten blindly, we have to validate whatever we take out
+    /// of the table when calculating the match length.  A check value
+    /// stored with each entry filters out most hash collisions, so
+    /// that no flag has to be spent on them.
+    fn hash_context(&self) -> (usize, u8) {
+        hash_context(&self.context[..self.order], self.table_bits)
     }
 
     fn find_longest_match(&self, match_pos: usize, search_pos: usize) -> usize {
@@ -144,32 +233,34 @@ impl<W: Write> Writer<W> {
     fn process(&mut self) -> io::Result<()> {
         let search_pos = self.position;
 
-        let hsh = self.hash_context();
-        let match_pos = self.hashtab[hsh];
-        
-        let ofs =
-            if match_pos < self.position {
-                self.position - match_pos
+        let (hsh, check) = self.hash_context();
+        let entry = self.hashtab[hsh];
+        let predicted = entry != 0 && self.checks[hsh] == check &&
+            offset(self.position, entry - 1) < WINDOW_SIZE - MAX_MATCH_LEN;
+        self.hashtab[hsh] = search_pos + 1;
+        self.checks[hsh] = check;
+
+        let match_len =
+            if predicted {
+                self.find_longest_match(entry - 1, search_pos)
             } else {
-                self.position + (WINDOW_SIZE - match_pos)
+                0
             };
-        
-        let match_len = self.find_longest_match(match_pos, search_pos);
-//        println!("pos: {}, context: {:?}, hash: {}, match_pos: {}, match_len: {}",
-//                 self.position, &self.context[..], hsh, match_pos, match_len);
-        
-        if ofs < WINDOW_SIZE - MAX_MATCH_LEN && match_len >= MIN_MATCH_LEN {
-            assert!(ofs != 0);
+
+        if match_len >= MIN_MATCH_LEN {
             assert!((match_len - MIN_MATCH_LEN) < 256);
-            
+
             try!(self.emit_match((match_len - MIN_MATCH_LEN) as u8));
-            
+
             self.position = mod_window(self.position + match_len);
             self.look_ahead_bytes -= match_len;
-            self.hashtab[hsh] = search_pos;
         } else {
             let lit = self.window[self.position];
-            try!(self.emit_lit(lit));
+            if predicted {
+                try!(self.emit_lit(lit));
+            } else {
+                try!(self.emit_unflagged_lit(lit));
+            }
 
             self.position = mod_window(self.position + 1);
             self.look_ahead_bytes -= 1;
@@ -178,6 +269,17 @@ impl<W: Write> Writer<W> {
         Ok(())
     }
 
+    /// Return the number of uncompressed bytes written so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Return the number of compressed bytes written to the wrapped
+    /// writer so far.
+    pub fn total_out(&self) -> u64 {
+        self.inner.total_out()
+    }
+
     /// Move the wrapped writer out of the LZSS writer.
     pub fn to_inner(self) -> W {
         self.inner.into_inner()
@@ -198,6 +300,7 @@ impl<W: Write> Write for Writer<W> {
                 try!(self.process());
             }
         }
+        self.total_in += written as u64;
         Ok(written)
     }
 
@@ -212,47 +315,82 @@ impl<W: Write> Write for Writer<W> {
 
 /// Reader for LZSS compressed streams.
 pub struct Reader<R> {
-    inner: Bytes<nested::Reader<R>>,
+    inner: ByteReader<nested::Reader<R>>,
     window: [u8; WINDOW_SIZE],
-    hashtab: [usize; HASHTAB_SIZE],
-    context: [u8; MAX_CONTEXT],
+    hashtab: Vec<usize>,
+    checks: Vec<u8>,
+    table_bits: usize,
+    context: [u8; MAX_ORDER],
+    order: usize,
     position: usize,
     returned: usize,
+    /// Flag bits not used yet, and their number.
+    flags: u8,
+    flag_count: usize,
     eof: bool,
+    recorder: Recorder,
+    total_out: u64,
 }
 
 impl<R: Read> Reader<R> {
-    /// Create a new LZSS reader that wraps another reader.
-    pub fn new(inner: R) -> Reader<R> {
-        Reader {
-            inner: nested::Reader::new(inner).bytes(),
+    /// Create a new LZSS reader that wraps another reader.  This
+    /// reads the header of the nested coder, therefore the result can
+    /// be an error.
+    pub fn new(inner: R) -> io::Result<Reader<R>> {
+        Reader::with_params(inner, DEFAULT_ORDER, DEFAULT_TABLE_BITS)
+    }
+
+    /// Create a new LZP reader for streams written with the given
+    /// context order and hash table size.
+    pub fn with_params(inner: R, order: usize, table_bits: usize) -> io::Result<Reader<R>> {
+        check_params(order, table_bits);
+        Ok(Reader {
+            inner: ByteReader::new(try!(nested::Reader::new(inner))),
             window: [0; WINDOW_SIZE],
-            hashtab: [0; HASHTAB_SIZE],
-            context: [0; MAX_CONTEXT],
+            hashtab: vec![0; 1 << table_bits],
+            checks: vec![0; 1 << table_bits],
+            table_bits: table_bits,
+            context: [0; MAX_ORDER],
+            order: order,
             position: 0,
             returned: 0,
+            flags: 0,
+            flag_count: 0,
             eof: false,
-        }
+            recorder: Recorder::new(),
+            total_out: 0,
+        })
+    }
+
+    /// Return the number of compressed bytes read so far.
+    pub fn total_in(&self) -> u64 {
+        self.inner.get_ref().total_in()
+    }
+
+    /// Return the number of decompressed bytes read so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
     }
 
     fn update_context(&mut self) {
-        let start =
-            if (self.position) >= MAX_CONTEXT {
-                (self.position) - MAX_CONTEXT
-            } else {
-                WINDOW_SIZE - (MAX_CONTEXT - self.position)
-            };
-        for i in 0..MAX_CONTEXT {
-            self.context[i] = self.window[mod_window(start + i)];
-        }
+        load_context(&mut self.context[..self.order], &self.window, self.position);
     }
-    
-    fn hash_context(&self) -> usize {
-        let mut h = 0;
-        for b in self.context.iter() {
-            h = (h << 8) + *b as usize;
-        }
-        h % HASHTAB_SIZE
+
+    fn hash_context(&self) -> (usize, u8) {
+        hash_context(&self.context[..self.order], self.table_bits)
+    }
+
+    /// Look up the prediction for the current position and replace
+    /// it by the current position, like the writer does.  Returns the
+    /// predicted position, if any.
+    fn predict(&mut self) -> Option<usize> {
+        let (hsh, check) = self.hash_context();
+        let entry = self.hashtab[hsh];
+        let predicted = entry != 0 && self.checks[hsh] == check &&
+            offset(self.position, entry - 1) < WINDOW_SIZE - MAX_MATCH_LEN;
+        self.hashtab[hsh] = self.position + 1;
+        self.checks[hsh] = check;
+        if predicted { Some(entry - 1) } else { None }
     }
 
     /// Copy all decompressed data from the window to the output
@@ -265,53 +403,66 @@ impl<R: Read> Reader<R> {
         }
     }
 
-    /// Process a group of 8 literals or match/length pairs.  The
-    /// given token is contains the flag bits.
-    fn process_group(&mut self, token: u8) -> io::Result<()> {
-        for i in 0..8 {
-            if token & 0x80 >> i == 0 {
-                // Zero bit indicates a match/length pair. Decode the
-                // next two bytes into a 4-bit length and a 12-bit
-                // offset.
-                let mblen = self.inner.next();
-                match mblen {
-                    None => {
-                        self.eof = true;
-                        return Ok(());
+    /// Decode the literal or match at the current position.  Only
+    /// positions with a prediction have a flag, which tells a match
+    /// (zero) from a literal.  The end of the input at a flag or a
+    /// match is the end of the stream, because the last flag byte is
+    /// padded with zero bits.
+    fn process_item(&mut self) -> io::Result<()> {
+        let predicted = self.predict();
+        let is_match =
+            if predicted.is_some() {
+                if self.flag_count == 0 {
+                    match self.inner.next() {
+                        Some(flags) => {
+                            self.flags = try!(flags);
+                            self.flag_count = 8;
+                        },
+                        None => {
+                            self.eof = true;
+                            return Ok(());
+                        },
                     }
-                    Some(alen) => {
-                        let len = try!(alen) as usize + MIN_MATCH_LEN;
-                        let hsh = self.hash_context();
-                        let pos = self.hashtab[hsh];
-//                        println!("pos: {}, context: {:?}, hash: {}, match_pos: {}, match_len: {}",
-//                                 self.position, &self.context[..], hsh, pos, len);
+                }
+                let flag = self.flags & 0x80;
+                self.flags <<= 1;
+                self.flag_count -= 1;
+                flag == 0
+            } else {
+                false
+            };
+        match self.inner.next() {
+            Some(b) => {
+                let b = try!(b);
+                match predicted {
+                    Some(pos) if is_match => {
+                        let len = b as usize + MIN_MATCH_LEN;
+                        self.recorder.record(Event::Match { offset: offset(self.position, pos),
+                                                            len: len });
                         for i in 0..len {
                             self.window[mod_window(self.position + i)] =
                                 self.window[mod_window(pos + i)];
                         }
-                        self.hashtab[hsh] = self.position;
                         self.position = mod_window(self.position + len);
                     },
+                    _ => {
+                        self.recorder.record(Event::Literal(b));
+                        self.window[self.position] = b;
+                        self.position = mod_window(self.position + 1);
+                    },
                 }
-            } else {
-                // A 1-bit in the token indicates a literal.  Just
-                // take the next byte from the input and add it to the
-                // window.
-                if let Some(lit) = self.inner.next() {
-                    let lit = try!(lit);
-                    self.window[self.position] = lit;
-                    self.position = mod_window(self.position + 1);
-                } else {
-                    // EOF here means corrupted input, because the
-                    // encoder does not put a 1-bit into the token
-                    // when the stream ends.
-                    self.eof = true;
-                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
-                                              "cannot read literal"));
+            },
+            None => {
+                self.eof = true;
+                if predicted.is_some() && !is_match {
+                    // A 1-bit flag announces a literal, so EOF here
+                    // means corrupted input.
+                    return Err(Error::UnexpectedEof.into());
                 }
-            }
-            self.update_context();
+                return Ok(());
+            },
         }
+        self.update_context();
         Ok(())
     }
 
@@ -320,21 +471,14 @@ impl<R: Read> Reader<R> {
     /// decompressed, it stays in the window for later processing.
     fn process(&mut self, output: &mut [u8]) -> io::Result<usize> {
         let mut written = 0;
-        
+
         // Copy out data that already was decompressed but did not fit
         // into output last time.
         self.copy_out(output, &mut written);
-        
-        'outer:
-        while written < output.len() {
-            if let Some(token) = self.inner.next() {
-                let token = try!(token);
-                try!(self.process_group(token));
-                self.copy_out(output, &mut written);
-            } else {
-                self.eof = true;
-                break;
-            }
+
+        while written < output.len() && !self.eof {
+            try!(self.process_item());
+            self.copy_out(output, &mut written);
         }
         Ok(written)
     }
@@ -342,11 +486,15 @@ impl<R: Read> Reader<R> {
 
 impl<R: Read> Read for Reader<R> {
     fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
-        if self.eof {
-            Ok(0)
-        } else {
-            self.process(output)
-        }
+        let n = try!(self.process(output).map_err(|e| at_offset(e, self.total_in())));
+        self.total_out += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read> Inspect for Reader<R> {
+    fn recorder(&mut self) -> &mut Recorder {
+        &mut self.recorder
     }
 }
 
@@ -357,8 +505,44 @@ pub fn compress<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error>
     Ok(cw.to_inner())
 }
 
+/// Compress all data from `input` and code the result with `coder`.
+pub fn compress_with_coder<R: Read, W: Write>(mut input: R, output: W,
+                                              coder: NestedCoder) -> Result<W, Error> {
+    let mut cw = Writer::with_coder(output, coder);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.to_inner())
+}
+
+/// Compress all data from `input` with the given context order and
+/// hash table size.
+pub fn compress_with_params<R: Read, W: Write>(mut input: R, output: W, order: usize,
+                                               table_bits: usize) -> Result<W, Error> {
+    let mut cw = Writer::with_params(output, order, table_bits);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.to_inner())
+}
+
 pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<W, Error> {
-    let mut cr = Reader::new(input);
+    let mut cr = try!(Reader::new(input));
+    try!(io::copy(&mut cr, &mut output));
+    Ok(output)
+}
+
+/// Return an iterator over the literals and matches of the stream in
+/// `input`, where match offsets are the distances to the predicted
+/// positions.  This reads the header of the nested coder, therefore
+/// the result can be an error.
+pub fn inspect<R: Read>(input: R) -> io::Result<Events<Reader<R>>> {
+    Ok(Events::new(try!(Reader::new(input))))
+}
+
+/// Decompress a stream written with the given context order and hash
+/// table size.
+pub fn decompress_with_params<R: Read, W: Write>(input: R, mut output: W, order: usize,
+                                                 table_bits: usize) -> Result<W, Error> {
+    let mut cr = try!(Reader::with_params(input, order, table_bits));
     try!(io::copy(&mut cr, &mut output));
     Ok(output)
 }
@@ -367,7 +551,9 @@ pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<W, Error
 mod tests {
     use ::std::io::Cursor;
 
-    use super::{Writer, Reader};
+    use super::{Writer, Reader, compress_with_coder, decompress, compress_with_params,
+                decompress_with_params};
+    use nested::NestedCoder;
     use ::std::io::{Read, Write};
 
     fn cmp_test(input: &[u8], expected_output: &[u8]) {
@@ -382,27 +568,27 @@ mod tests {
 
     #[test]
     fn compress_empty() {
-        cmp_test(&[], &[0]);
+        cmp_test(&[], &[0, 0]);
     }
 
     #[test]
     fn compress_a() {
-        cmp_test(b"a", &[192, 12, 40]);
+        cmp_test(b"a", &[0, 176, 192]);
     }
 
     #[test]
     fn compress_aaa() {
-        cmp_test(b"aaaaaaaaa", &[192, 12, 32, 58]);
+        cmp_test(b"aaaaaaaaa", &[0, 176, 184, 3, 5, 0]);
     }
 
     #[test]
     fn compress_abc() {
         cmp_test(b"abcdefgabcdefgabcabcabcdefg",
-                 &[255, 12, 35, 22, 199, 178, 108, 181, 154, 179, 208, 154, 121, 64, 167, 1, 34, 0]);
+                 &[0, 176, 140, 67, 30, 201, 178, 236, 213, 157, 250, 145, 65, 202, 160, 18, 4, 250, 160]);
     }
 
     fn decmp_test(compressed: &[u8], expected_output: &[u8]) {
-        let mut cr = Reader::new(Cursor::new(compressed));
+        let mut cr = Reader::new(Cursor::new(compressed)).unwrap();
 
         let mut decompressed = Vec::new();
         let nread = cr.read_to_end(&mut decompressed).unwrap();
@@ -414,23 +600,23 @@ mod tests {
 
     #[test]
     fn decompress_empty() {
-        decmp_test(&[0], &[]);
+        decmp_test(&[0, 0], &[]);
     }
 
     #[test]
     fn decompress_a() {
-        decmp_test(&[192, 12, 40], b"a");
+        decmp_test(&[0, 176, 192], b"a");
     }
 
     #[test]
     fn decompress_aaa() {
-        decmp_test(&[192, 12, 32, 58], b"aaaaaaaaa");
+        decmp_test(&[0, 176, 184, 3, 5, 0], b"aaaaaaaaa");
     }
 
     #[test]
     fn decompress_abc() {
         decmp_test(
-            &[255, 12, 35, 22, 199, 178, 108, 181, 154, 179, 208, 154, 121, 64, 167, 1, 34, 0],
+            &[0, 176, 140, 67, 30, 201, 178, 236, 213, 157, 250, 145, 65, 202, 160, 18, 4, 250, 160],
 //            &[254, 97, 98, 99, 100, 101, 102, 103, 128,
 //              7, 0, 16, 10, 16, 3, 32, 20],
             b"abcdefgabcdefgabcabcabcdefg");
@@ -442,7 +628,7 @@ mod tests {
         cw.flush().unwrap();
         let compressed = cw.to_inner();
 
-        let mut cr = Reader::new(Cursor::new(compressed));
+        let mut cr = Reader::new(Cursor::new(compressed)).unwrap();
         let mut decompressed = Vec::new();
         let nread = cr.read_to_end(&mut decompressed).unwrap();
 
@@ -455,4 +641,34 @@ mod tests {
         let input = include_bytes!("lzp1.rs");
         roundtrip(input);
     }
+
+    #[test]
+    fn nested_coders() {
+        let input = include_bytes!("lzp2.rs");
+        for &coder in &[NestedCoder::BinArith, NestedCoder::Range] {
+            let compressed = compress_with_coder(Cursor::new(&input[..]), vec![], coder).unwrap();
+            let decompressed = decompress(Cursor::new(compressed), vec![]).unwrap();
+            assert_eq!(&input[..], &decompressed[..]);
+        }
+    }
+
+    #[test]
+    fn compress_params() {
+        let input = include_bytes!("lzp2.rs");
+        for order in super::MIN_ORDER..super::MAX_ORDER + 1 {
+            for &table_bits in &[8, 12, 20] {
+                let compressed = compress_with_params(Cursor::new(&input[..]), vec![],
+                                                      order, table_bits).unwrap();
+                let decompressed = decompress_with_params(Cursor::new(compressed), vec![],
+                                                          order, table_bits).unwrap();
+                assert_eq!(&input[..], &decompressed[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+    }
 }
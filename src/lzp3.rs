@@ -0,0 +1,670 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! LZP compressor with binary arithmetic coded flags and lengths.
+//!
+//! Matches are predicted like in lzp1.rs: the hash of the bytes
+//! before each position selects a table entry holding the position
+//! which followed the same context last time, together with a check
+//! value.  Instead of flag bytes and length bytes, all symbols are
+//! coded with adaptive models and the binary arithmetic coder from
+//! the `binarith` module:
+//!
+//! - match flags, with the existence of a prediction and the two
+//!   previous flags as context,
+//!
+//! - match lengths by a three-level length coder, like in lzb.rs,
+//!
+//! - literals bitwise along a binary tree, with the high three bits of
+//!   the previous byte as context.
+//!
+//! A flag is coded at every position, but at positions without a
+//! prediction, it can only announce the end of the stream, which is
+//! marked by a match with a reserved length.  Since such flags are
+//! almost always zero, they cost very little.
+
+use std::io::{Read, Write};
+use std::io;
+
+use error::{at_offset, Error};
+use binarith::{Encoder, Decoder, Bit, Count};
+use inspect::{Event, Events, Inspect, Recorder};
+
+const WINDOW_BITS: usize = 16;
+const WINDOW_SIZE: usize = 1 << WINDOW_BITS;
+
+const MIN_MATCH_LEN: usize = 1;
+const LEN_LOW_SYMBOLS: usize = 8;
+const LEN_MID_SYMBOLS: usize = 8;
+const LEN_HIGH_SYMBOLS: usize = 256;
+/// Length code of the end of stream marker, the largest one.
+const END_LEN_CODE: usize = LEN_LOW_SYMBOLS + LEN_MID_SYMBOLS + LEN_HIGH_SYMBOLS - 1;
+const MAX_MATCH_LEN: usize = MIN_MATCH_LEN + END_LEN_CODE - 1;
+
+const LOOK_AHEAD_BYTES: usize = MAX_MATCH_LEN;
+
+const LIT_CONTEXT_BITS: usize = 3;
+/// Number of previous match flags in the context of a match flag.
+const FLAG_HISTORY_BITS: usize = 2;
+
+/// Smallest and largest context order, in bytes.
+pub const MIN_ORDER: usize = 2;
+pub const MAX_ORDER: usize = 5;
+
+/// Largest hash table size in bits.
+pub const MAX_TABLE_BITS: usize = 24;
+
+/// Context order and hash table size in bits used when none are
+/// given.
+pub const DEFAULT_ORDER: usize = 3;
+pub const DEFAULT_TABLE_BITS: usize = 18;
+
+/// Counts of an adaptive bit model are halved when their sum exceeds
+/// this limit, so that the model follows changing statistics.
+const MODEL_LIMIT: Count = 1 << 10;
+const MODEL_INC: Count = 24;
+
+#[derive(Clone, Copy)]
+struct BitModel {
+    c0: Count,
+    c1: Count,
+}
+
+impl BitModel {
+    fn new() -> BitModel {
+        BitModel { c0: 1, c1: 1 }
+    }
+
+    fn update(&mut self, bit: Bit) {
+        if bit == 0 {
+            self.c0 += MODEL_INC;
+        } else {
+            self.c1 += MODEL_INC;
+        }
+        if self.c0 + self.c1 > MODEL_LIMIT {
+            self.c0 = (self.c0 + 1) / 2;
+            self.c1 = (self.c1 + 1) / 2;
+        }
+    }
+}
+
+fn encode_bit<W: Write>(enc: &mut Encoder<W>, model: &mut BitModel, bit: Bit) -> io::Result<()> {
+    try!(enc.encode(bit, model.c0, model.c1));
+    model.update(bit);
+    Ok(())
+}
+
+fn decode_bit<R: Read>(dec: &mut Decoder<R>, model: &mut BitModel) -> io::Result<Bit> {
+    let bit = try!(dec.decode(model.c0, model.c1));
+    model.update(bit);
+    Ok(bit)
+}
+
+/// Encode the lowest `bits` bits of `value`, most significant bit
+/// first, along a binary tree of models.
+fn encode_tree<W: Write>(enc: &mut Encoder<W>, models: &mut [BitModel], bits: usize,
+                         value: usize) -> io::Result<()> {
+    let mut node = 1;
+    for i in (0..bits).rev() {
+        let bit = (value >> i) & 1;
+        try!(encode_bit(enc, &mut models[node], bit));
+        node = (node << 1) | bit;
+    }
+    Ok(())
+}
+
+fn decode_tree<R: Read>(dec: &mut Decoder<R>, models: &mut [BitModel],
+                        bits: usize) -> io::Result<usize> {
+    let mut node = 1;
+    for _ in 0..bits {
+        let bit = try!(decode_bit(dec, &mut models[node]));
+        node = (node << 1) | bit;
+    }
+    Ok(node - (1 << bits))
+}
+
+#[inline(always)]
+fn mod_window(x: usize) -> usize {
+    x & (WINDOW_SIZE - 1)
+}
+
+/// Check the parameters given to the constructors.
+fn check_params(order: usize, table_bits: usize) {
+    assert!(order >= MIN_ORDER && order <= MAX_ORDER);
+    assert!(table_bits >= 1 && table_bits <= MAX_TABLE_BITS);
+}
+
+/// Hash the `context` bytes into a table index of `table_bits` bits
+/// and a check value, which is stored with the table entry to detect
+/// collisions.
+fn hash_context(context: &[u8], table_bits: usize) -> (usize, u8) {
+    let mut h: u64 = 0;
+    for b in context.iter() {
+        h = (h << 8) | *b as u64;
+    }
+    let h = h.wrapping_mul(0x9e3779b97f4a7c15);
+    ((h >> (64 - table_bits)) as usize, (h >> (56 - table_bits)) as u8)
+}
+
+/// Return the offset from window position `position` back to
+/// `match_pos`, between 1 and `WINDOW_SIZE`.
+fn offset(position: usize, match_pos: usize) -> usize {
+    if match_pos < position {
+        position - match_pos
+    } else {
+        position + (WINDOW_SIZE - match_pos)
+    }
+}
+
+/// Prediction state shared by compressor and decompressor: the
+/// window and the hash table of context positions.
+struct Predictor {
+    window: Vec<u8>,
+    /// Window position following each context, plus one, so that 0
+    /// marks an empty entry.
+    hashtab: Vec<usize>,
+    checks: Vec<u8>,
+    table_bits: usize,
+    order: usize,
+    position: usize,
+}
+
+impl Predictor {
+    fn new(order: usize, table_bits: usize) -> Predictor {
+        check_params(order, table_bits);
+        Predictor {
+            window: vec![0; WINDOW_SIZE],
+            hashtab: vec![0; 1 << table_bits],
+            checks: vec![0; 1 << table_bits],
+            table_bits: table_bits,
+            order: order,
+            position: 0,
+        }
+    }
+
+    /// Look up the prediction for the current position and replace
+    /// it by the current position.  Returns the predicted position,
+    /// if any.
+    fn predict(&mut self) -> Option<usize> {
+        let mut context = [0; MAX_ORDER];
+        let start = self.position + WINDOW_SIZE - self.order;
+        for i in 0..self.order {
+            context[i] = self.window[mod_window(start + i)];
+        }
+        let (hsh, check) = hash_context(&context[..self.order], self.table_bits);
+        let entry = self.hashtab[hsh];
+        let predicted = entry != 0 && self.checks[hsh] == check &&
+            offset(self.position, entry - 1) < WINDOW_SIZE - MAX_MATCH_LEN;
+        self.hashtab[hsh] = self.position + 1;
+        self.checks[hsh] = check;
+        if predicted { Some(entry - 1) } else { None }
+    }
+
+    fn prev_byte(&self) -> u8 {
+        self.window[mod_window(self.position + WINDOW_SIZE - 1)]
+    }
+}
+
+/// Adaptive model shared by compressor and decompressor.
+struct Model {
+    /// The most recent match flags at positions with a prediction.
+    history: usize,
+    is_match: Vec<BitModel>,
+    literal: Vec<BitModel>,
+    len_choice: BitModel,
+    len_choice2: BitModel,
+    len_low: Vec<BitModel>,
+    len_mid: Vec<BitModel>,
+    len_high: Vec<BitModel>,
+}
+
+impl Model {
+    fn new() -> Model {
+        Model {
+            history: 0,
+            is_match: vec![BitModel::new(); 2 << FLAG_HISTORY_BITS],
+            literal: vec![BitModel::new(); 256 << LIT_CONTEXT_BITS],
+            len_choice: BitModel::new(),
+            len_choice2: BitModel::new(),
+            len_low: vec![BitModel::new(); LEN_LOW_SYMBOLS],
+            len_mid: vec![BitModel::new(); LEN_MID_SYMBOLS],
+            len_high: vec![BitModel::new(); LEN_HIGH_SYMBOLS],
+        }
+    }
+
+    fn is_match_index(&self, predicted: bool) -> usize {
+        if predicted {
+            (1 << FLAG_HISTORY_BITS) | self.history
+        } else {
+            0
+        }
+    }
+
+    /// Record the match flag of a position with a prediction.
+    fn update_history(&mut self, bit: Bit) {
+        self.history = ((self.history << 1) | bit) & ((1 << FLAG_HISTORY_BITS) - 1);
+    }
+
+    fn literal_models(&mut self, prev_byte: u8) -> &mut [BitModel] {
+        let ctx = (prev_byte >> (8 - LIT_CONTEXT_BITS)) as usize;
+        &mut self.literal[ctx << 8..(ctx + 1) << 8]
+    }
+
+    fn encode_flag<W: Write>(&mut self, enc: &mut Encoder<W>, predicted: bool,
+                             bit: Bit) -> io::Result<()> {
+        let idx = self.is_match_index(predicted);
+        try!(encode_bit(enc, &mut self.is_match[idx], bit));
+        if predicted {
+            self.update_history(bit);
+        }
+        Ok(())
+    }
+
+    fn decode_flag<R: Read>(&mut self, dec: &mut Decoder<R>, predicted: bool) -> io::Result<Bit> {
+        let idx = self.is_match_index(predicted);
+        let bit = try!(decode_bit(dec, &mut self.is_match[idx]));
+        if predicted {
+            self.update_history(bit);
+        }
+        Ok(bit)
+    }
+
+    /// Encode a length code, which is either a match length minus
+    /// `MIN_MATCH_LEN` or `END_LEN_CODE`.
+    fn encode_len<W: Write>(&mut self, enc: &mut Encoder<W>, l: usize) -> io::Result<()> {
+        if l < LEN_LOW_SYMBOLS {
+            try!(encode_bit(enc, &mut self.len_choice, 0));
+            encode_tree(enc, &mut self.len_low, 3, l)
+        } else if l < LEN_LOW_SYMBOLS + LEN_MID_SYMBOLS {
+            try!(encode_bit(enc, &mut self.len_choice, 1));
+            try!(encode_bit(enc, &mut self.len_choice2, 0));
+            encode_tree(enc, &mut self.len_mid, 3, l - LEN_LOW_SYMBOLS)
+        } else {
+            try!(encode_bit(enc, &mut self.len_choice, 1));
+            try!(encode_bit(enc, &mut self.len_choice2, 1));
+            encode_tree(enc, &mut self.len_high, 8, l - LEN_LOW_SYMBOLS - LEN_MID_SYMBOLS)
+        }
+    }
+
+    fn decode_len<R: Read>(&mut self, dec: &mut Decoder<R>) -> io::Result<usize> {
+        if try!(decode_bit(dec, &mut self.len_choice)) == 0 {
+            decode_tree(dec, &mut self.len_low, 3)
+        } else if try!(decode_bit(dec, &mut self.len_choice2)) == 0 {
+            Ok(LEN_LOW_SYMBOLS + try!(decode_tree(dec, &mut self.len_mid, 3)))
+        } else {
+            Ok(LEN_LOW_SYMBOLS + LEN_MID_SYMBOLS + try!(decode_tree(dec, &mut self.len_high, 8)))
+        }
+    }
+}
+
+/// Writer for LZP3 compressed streams.
+pub struct Writer<W> {
+    encoder: Encoder<W>,
+    model: Model,
+    predictor: Predictor,
+    look_ahead_bytes: usize,
+    finished: bool,
+    total_in: u64,
+}
+
+impl<W: Write> Writer<W> {
+    /// Create a new LZP3 writer that wraps the given Writer.
+    pub fn new(inner: W) -> Writer<W> {
+        Writer::with_params(inner, DEFAULT_ORDER, DEFAULT_TABLE_BITS)
+    }
+
+    /// Create a new LZP3 writer which predicts from contexts of
+    /// `order` bytes, from `MIN_ORDER` to `MAX_ORDER`, with a hash
+    /// table of `1 << table_bits` entries.  The reader has to be
+    /// created with the same parameters.
+    pub fn with_params(inner: W, order: usize, table_bits: usize) -> Writer<W> {
+        Writer {
+            encoder: Encoder::new(inner),
+            model: Model::new(),
+            predictor: Predictor::new(order, table_bits),
+            look_ahead_bytes: 0,
+            finished: false,
+            total_in: 0,
+        }
+    }
+
+    fn find_longest_match(&self, match_pos: usize) -> usize {
+        let window = &self.predictor.window;
+        let search_pos = self.predictor.position;
+        let max_len = ::std::cmp::min(self.look_ahead_bytes, MAX_MATCH_LEN);
+        let mut match_len = 0;
+        while match_len < max_len &&
+            window[mod_window(match_pos + match_len)] == window[mod_window(search_pos + match_len)] {
+            match_len += 1;
+        }
+        match_len
+    }
+
+    fn process(&mut self) -> io::Result<()> {
+        let predicted = self.predictor.predict();
+        let match_len =
+            match predicted {
+                Some(match_pos) => self.find_longest_match(match_pos),
+                None => 0,
+            };
+
+        if match_len >= MIN_MATCH_LEN {
+            try!(self.model.encode_flag(&mut self.encoder, true, 1));
+            try!(self.model.encode_len(&mut self.encoder, match_len - MIN_MATCH_LEN));
+        } else {
+            let prev_byte = self.predictor.prev_byte();
+            let lit = self.predictor.window[self.predictor.position];
+            try!(self.model.encode_flag(&mut self.encoder, predicted.is_some(), 0));
+            try!(encode_tree(&mut self.encoder, self.model.literal_models(prev_byte),
+                             8, lit as usize));
+        }
+        let len = ::std::cmp::max(match_len, 1);
+        self.predictor.position = mod_window(self.predictor.position + len);
+        self.look_ahead_bytes -= len;
+        Ok(())
+    }
+
+    /// Encode all pending data and the end of stream marker.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            while self.look_ahead_bytes > 0 {
+                try!(self.process());
+            }
+            let predicted = self.predictor.predict().is_some();
+            try!(self.model.encode_flag(&mut self.encoder, predicted, 1));
+            try!(self.model.encode_len(&mut self.encoder, END_LEN_CODE));
+            try!(self.encoder.finish());
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    /// Return the number of uncompressed bytes written so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Return the number of compressed bytes written to the wrapped
+    /// writer so far.
+    pub fn total_out(&self) -> u64 {
+        self.encoder.bytes_written()
+    }
+
+    /// Move the wrapped writer out of the LZP3 writer.
+    pub fn to_inner(self) -> W {
+        self.encoder.into_inner()
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            while written < buf.len() && self.look_ahead_bytes < LOOK_AHEAD_BYTES {
+                let p = mod_window(self.predictor.position + self.look_ahead_bytes);
+                self.predictor.window[p] = buf[written];
+                self.look_ahead_bytes += 1;
+                written += 1;
+            }
+            if self.look_ahead_bytes == LOOK_AHEAD_BYTES {
+                try!(self.process());
+            }
+        }
+        self.total_in += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.finish()
+    }
+}
+
+/// Reader for LZP3 compressed streams.
+pub struct Reader<R> {
+    decoder: Decoder<R>,
+    model: Model,
+    predictor: Predictor,
+    returned: usize,
+    eof: bool,
+    recorder: Recorder,
+    total_out: u64,
+}
+
+impl<R: Read> Reader<R> {
+    /// Create a new LZP3 reader that wraps another reader.  This
+    /// starts decoding, therefore the result can be an error.
+    pub fn new(inner: R) -> io::Result<Reader<R>> {
+        Reader::with_params(inner, DEFAULT_ORDER, DEFAULT_TABLE_BITS)
+    }
+
+    /// Create a new LZP3 reader for streams written with the given
+    /// context order and hash table size.
+    pub fn with_params(inner: R, order: usize, table_bits: usize) -> io::Result<Reader<R>> {
+        let predictor = Predictor::new(order, table_bits);
+        Ok(Reader {
+            decoder: try!(Decoder::new(inner)),
+            model: Model::new(),
+            predictor: predictor,
+            returned: 0,
+            eof: false,
+            recorder: Recorder::new(),
+            total_out: 0,
+        })
+    }
+
+    /// Return the number of compressed bytes consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.decoder.bytes_read()
+    }
+
+    /// Return the number of decompressed bytes read so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Copy all decompressed data from the window to the output
+    /// buffer.
+    fn copy_out(&mut self, output: &mut [u8], written: &mut usize) {
+        while *written < output.len() && self.returned != self.predictor.position {
+            output[*written] = self.predictor.window[self.returned];
+            *written += 1;
+            self.returned = mod_window(self.returned + 1);
+        }
+    }
+
+    /// Decode the literal or match at the current position.
+    fn process_item(&mut self) -> io::Result<()> {
+        let predicted = self.predictor.predict();
+        if try!(self.model.decode_flag(&mut self.decoder, predicted.is_some())) == 0 {
+            let prev_byte = self.predictor.prev_byte();
+            let lit = try!(decode_tree(&mut self.decoder,
+                                       self.model.literal_models(prev_byte), 8));
+            let position = self.predictor.position;
+            self.recorder.record(Event::Literal(lit as u8));
+            self.predictor.window[position] = lit as u8;
+            self.predictor.position = mod_window(position + 1);
+            return Ok(());
+        }
+
+        let l = try!(self.model.decode_len(&mut self.decoder));
+        if l == END_LEN_CODE {
+            self.recorder.record(Event::End);
+            self.eof = true;
+            return Ok(());
+        }
+        let pos = match predicted {
+            Some(pos) => pos,
+            None => return Err(Error::corrupt("match without prediction").into()),
+        };
+        let len = l + MIN_MATCH_LEN;
+        let position = self.predictor.position;
+        self.recorder.record(Event::Match { offset: offset(position, pos), len: len });
+        for i in 0..len {
+            self.predictor.window[mod_window(position + i)] =
+                self.predictor.window[mod_window(pos + i)];
+        }
+        self.predictor.position = mod_window(position + len);
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
+        let mut written = 0;
+
+        // Copy out data that already was decompressed but did not fit
+        // into output last time.
+        self.copy_out(output, &mut written);
+
+        while written < output.len() && !self.eof {
+            try!(self.process_item().map_err(|e| at_offset(e, self.total_in())));
+            self.copy_out(output, &mut written);
+        }
+        self.total_out += written as u64;
+        Ok(written)
+    }
+}
+
+impl<R: Read> Inspect for Reader<R> {
+    fn recorder(&mut self) -> &mut Recorder {
+        &mut self.recorder
+    }
+}
+
+pub fn compress<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error> {
+    let mut cw = Writer::new(output);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.to_inner())
+}
+
+/// Compress all data from `input` with the given context order and
+/// hash table size.
+pub fn compress_with_params<R: Read, W: Write>(mut input: R, output: W, order: usize,
+                                               table_bits: usize) -> Result<W, Error> {
+    let mut cw = Writer::with_params(output, order, table_bits);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.to_inner())
+}
+
+pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<W, Error> {
+    let mut cr = try!(Reader::new(input));
+    try!(io::copy(&mut cr, &mut output));
+    Ok(output)
+}
+
+/// Return an iterator over the literals and matches of the stream in
+/// `input`, where match offsets are the distances to the predicted
+/// positions.  This starts decoding, therefore the result can be an
+/// error.
+pub fn inspect<R: Read>(input: R) -> io::Result<Events<Reader<R>>> {
+    Ok(Events::new(try!(Reader::new(input))))
+}
+
+/// Decompress a stream written with the given context order and hash
+/// table size.
+pub fn decompress_with_params<R: Read, W: Write>(input: R, mut output: W, order: usize,
+                                                 table_bits: usize) -> Result<W, Error> {
+    let mut cr = try!(Reader::with_params(input, order, table_bits));
+    try!(io::copy(&mut cr, &mut output));
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::io::Cursor;
+
+    use super::{compress, decompress, compress_with_params, decompress_with_params};
+    #[cfg(feature = "lz")]
+    use lzp1;
+
+    fn roundtrip(input: &[u8]) {
+        let compressed = compress(Cursor::new(input), vec![]).unwrap();
+        let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(input.len(), decompressed.len());
+        assert_eq!(&input[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn compress_empty() {
+        roundtrip(b"");
+    }
+
+    #[test]
+    fn compress_a() {
+        roundtrip(b"a");
+    }
+
+    #[test]
+    fn compress_aaa() {
+        let input = [b'a'; 1000];
+        let compressed = compress(Cursor::new(&input[..]), vec![]).unwrap();
+        assert!(compressed.len() < 20);
+        roundtrip(&input);
+    }
+
+    #[test]
+    fn compress_decompress() {
+        roundtrip(include_bytes!("lzp3.rs"));
+    }
+
+    #[test]
+    fn compress_decompress_large() {
+        // Longer than the window, with stretches that cannot be
+        // predicted.
+        let f = include_bytes!("lzp3.rs");
+        let mut input = Vec::new();
+        let mut x = 1u32;
+        while input.len() < 300000 {
+            input.extend_from_slice(&f[..]);
+            for _ in 0..5000 {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345);
+                input.push((x >> 24) as u8);
+            }
+        }
+        roundtrip(&input);
+    }
+
+    #[cfg(feature = "lz")]
+    #[test]
+    fn better_than_lzp1() {
+        let input = include_bytes!("lzp3.rs");
+        let c1 = lzp1::compress(Cursor::new(&input[..]), vec![]).unwrap();
+        let c3 = compress(Cursor::new(&input[..]), vec![]).unwrap();
+        assert!(c3.len() < c1.len());
+    }
+
+    #[test]
+    fn decompress_truncated() {
+        let input = include_bytes!("lzp3.rs");
+        let compressed = compress(Cursor::new(&input[..]), vec![]).unwrap();
+        let truncated = &compressed[..compressed.len() / 2];
+        assert!(decompress(Cursor::new(truncated), vec![]).is_err());
+    }
+
+    #[test]
+    fn compress_params() {
+        let input = include_bytes!("lzp3.rs");
+        for order in super::MIN_ORDER..super::MAX_ORDER + 1 {
+            for &table_bits in &[8, 12, 20] {
+                let compressed = compress_with_params(Cursor::new(&input[..]), vec![],
+                                                      order, table_bits).unwrap();
+                let decompressed = decompress_with_params(Cursor::new(compressed), vec![],
+                                                          order, table_bits).unwrap();
+                assert_eq!(&input[..], &decompressed[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+    }
+
+    #[test]
+    fn decompress_garbage() {
+        ::testutil::garbage(|d: &[u8]| super::decompress(d, vec![]));
+    }
+}
@@ -2,11 +2,46 @@
 // top-level directory of this distribution for license information.
 
 //! Simple implementation of an LZSS compressor.
+//!
+//! The four most recently used match offsets are remembered by both
+//! compressor and decompressor.  Matches at one of these offsets are
+//! coded with an offset field value of 0xff0 or above, which does not
+//! occur for ordinary matches: two bits of the offset field select
+//! the repeat offset and the remaining two extend the match length.
+//!
+//! At the highest compression level, the compressor does not take
+//! the longest match at each position, but chooses the cheapest
+//! sequence of literals and matches for the whole look-ahead buffer
+//! (an optimal parse), taking the repeat offsets along each candidate
+//! sequence into account.  This is considerably slower.
+//!
+//! Besides the original format with its 4 KiB window, there is a
+//! large-window format with windows of 2^15 to 2^22 bytes.  Such a
+//! stream starts with a header byte holding the window size in bits.
+//! The first flag byte of a small-window stream either has its top
+//! bit set, because the first item is a literal, or is zero when the
+//! stream is empty, so the reader can tell both formats apart.  In the large-window format, the first
+//! byte of a match holds the number of offset bytes (1 to 3, or 0 for
+//! a repeat match) and the length, possibly followed by a length
+//! extension byte, and then the offset minus one in little-endian
+//! order.
+//!
+//! Both formats end with a match at an offset which the compressor
+//! never uses otherwise (`EOS_OFFSET`, or `LARGE_EOS_OFFSET` coded in
+//! three bytes).  The decompressor stops reading there, so that
+//! compressed streams can be embedded in other data.  Streams
+//! without this marker end at the end of the input.
 
-use std::io::{Read, Write, Bytes};
-use std::io;
+use alloc::collections::VecDeque;
+use io::{Read, Write};
+use io;
+use alloc::vec::Vec;
 
-use error::Error;
+use counter::Counter;
+use error::{at_offset, Error};
+use input::ByteReader;
+use inspect::{Event, Events, Inspect, Recorder};
+use window::{self, MatchFinder, SlidingWindow};
 
 const WINDOW_BITS: usize = 12;
 const LENGTH_BITS: usize = 4;
@@ -14,57 +49,252 @@ const LENGTH_BITS: usize = 4;
 const MIN_MATCH_LEN: usize = 2;
 const MAX_MATCH_LEN: usize = ((1 << LENGTH_BITS) - 1) + MIN_MATCH_LEN;
 
-const LOOK_AHEAD_BYTES: usize = MAX_MATCH_LEN;
+/// Number of recently used offsets available for repeat matches.
+const REP_COUNT: usize = 4;
+const REP_BASE: usize = 0xff0;
+const REP_MAX_MATCH_LEN: usize = ((1 << (LENGTH_BITS + 2)) - 1) + MIN_MATCH_LEN;
+
+const LOOK_AHEAD_BYTES: usize = REP_MAX_MATCH_LEN;
 
 const WINDOW_SIZE: usize = 1 << WINDOW_BITS;
 
-const HASHTAB_SIZE: usize = 1 << 10;
+/// Largest match offset.  Data further back may already be
+/// overwritten by the look-ahead buffer.
+const MAX_OFFSET: usize = WINDOW_SIZE - LOOK_AHEAD_BYTES - 1;
+
+const HASH_BITS: usize = 12;
+
+/// Offset field value marking the end of the stream.  It is above
+/// `MAX_OFFSET` and below the repeat offsets.
+const EOS_OFFSET: usize = REP_BASE - 1;
+
+/// Smallest and largest window size in bits of the large-window
+/// format.
+pub const MIN_LARGE_WINDOW_BITS: usize = 15;
+pub const MAX_LARGE_WINDOW_BITS: usize = 22;
+
+/// Length field sizes of the large-window format for ordinary and
+/// repeat matches.  The largest field value is followed by a length
+/// extension byte.
+const LARGE_LENGTH_BITS: usize = 6;
+const LARGE_REP_LENGTH_BITS: usize = 4;
+const LARGE_MAX_MATCH_LEN: usize = ((1 << LARGE_LENGTH_BITS) - 1) + 255 + MIN_MATCH_LEN;
+const LARGE_REP_MAX_MATCH_LEN: usize = ((1 << LARGE_REP_LENGTH_BITS) - 1) + 255 + MIN_MATCH_LEN;
+
+const LARGE_HASH_BITS: usize = 16;
+
+/// Offset marking the end of a large-window stream, which is larger
+/// than any window.
+const LARGE_EOS_OFFSET: usize = 1 << 24;
+
+/// Longest coded match of both formats.
+const MAX_MATCH_BYTES: usize = 5;
+
+/// Size in bits of a literal and of a match in the compressed
+/// stream, including the flag bit.
+const LITERAL_COST: usize = 9;
+const MATCH_COST: usize = 17;
+
+/// Item of the compressed stream.
+#[derive(Debug, Clone, Copy)]
+enum Token {
+    Literal,
+    /// Match with length and offset.
+    Match(usize, usize),
+    /// Match with length at the repeat offset with the given index.
+    Rep(usize, usize),
+}
+
+/// Cheapest way found by the optimal parser to code the look-ahead
+/// buffer up to some position: the cost in bits, the position where
+/// the last token starts, the last token and the repeat offsets
+/// after it.
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    cost: usize,
+    from: usize,
+    token: Token,
+    reps: [usize; REP_COUNT],
+}
 
 /// Writer for LZSS compressed streams.
+#[derive(Clone)]
 pub struct Writer<W> {
-    inner:  W,
-    window: [u8; WINDOW_SIZE],
-    hashtab: [usize; HASHTAB_SIZE],
-    position: usize,
-    look_ahead_bytes: usize,
+    inner:  Counter<W>,
+    window: SlidingWindow,
+    matcher: MatchFinder,
+    reps: [usize; REP_COUNT],
+
+    /// Whether the large-window format is written.
+    large: bool,
+    max_match_len: usize,
+    rep_max_match_len: usize,
+    look_ahead_size: usize,
+    max_offset: usize,
+    /// Header of the large-window format, until it is written.
+    header: Option<u8>,
+    finished: bool,
+    total_in: u64,
+
+    optimal: bool,
+    /// Longest matches found for the first look-ahead positions, used
+    /// by the optimal parser.
+    found: VecDeque<(usize, usize)>,
+    /// Number of positions before the current position which have
+    /// not been entered into the match finder yet.
+    pending: usize,
+    nodes: Vec<Node>,
 
     out_flags: u8,
     out_count: usize,
-    out_data:  [u8; 1 + 8*2],
+    out_data:  [u8; 1 + 8*MAX_MATCH_BYTES],
     out_len:   usize,
 }
 
-#[inline(always)]
-fn mod_window(x: usize) -> usize {
-    x % WINDOW_SIZE
+/// Move the repeat offset at index `i` to the front of the history.
+fn promote_rep(reps: &mut [usize; REP_COUNT], i: usize) {
+    let ofs = reps[i];
+    for j in (0..i).rev() {
+        reps[j + 1] = reps[j];
+    }
+    reps[0] = ofs;
+}
+
+/// Enter a newly used offset into the repeat offset history.
+fn push_rep(reps: &mut [usize; REP_COUNT], ofs: usize) {
+    reps[REP_COUNT - 1] = ofs;
+    promote_rep(reps, REP_COUNT - 1);
+}
+
+/// Record `token` from look-ahead index `from` to `to` in the optimal
+/// parse, if it is cheaper than the best way found so far.
+fn relax(nodes: &mut [Node], from: usize, to: usize, cost: usize, token: Token,
+         reps: [usize; REP_COUNT]) {
+    if cost < nodes[to].cost {
+        nodes[to] = Node {
+            cost: cost,
+            from: from,
+            token: token,
+            reps: reps,
+        };
+    }
+}
+
+/// Number of bytes needed for offset `ofs` in the large-window
+/// format.
+fn large_offset_bytes(ofs: usize) -> usize {
+    if ofs <= 1 << 8 {
+        1
+    } else if ofs <= 1 << 16 {
+        2
+    } else {
+        3
+    }
+}
+
+/// Encode the first byte of a large-window match with the tag in the
+/// upper bits and the length `l` (minus `MIN_MATCH_LEN`) in a field
+/// of `bits` bits, plus the length extension byte if necessary.
+/// Returns the number of bytes stored in `buf`.
+fn encode_large_len(buf: &mut [u8], tag: usize, l: usize, bits: usize) -> usize {
+    let field_max = (1 << bits) - 1;
+    if l < field_max {
+        buf[0] = ((tag << bits) | l) as u8;
+        1
+    } else {
+        buf[0] = ((tag << bits) | field_max) as u8;
+        buf[1] = (l - field_max) as u8;
+        2
+    }
 }
 
 impl<W: Write> Writer<W> {
     /// Create a new LZSS writer that wraps the given Writer.
     pub fn new(inner: W) -> Writer<W>{
+        Writer::with_level(inner, window::DEFAULT_LEVEL)
+    }
+
+    /// Create a new LZSS writer which searches for matches as
+    /// selected by compression `level`, from 1 (fastest) to
+    /// `window::MAX_LEVEL` (best compression).  The highest level
+    /// also selects the optimal parser.
+    pub fn with_level(inner: W, level: u32) -> Writer<W>{
+        Writer::create(inner, WINDOW_BITS, level)
+    }
+
+    /// Create a new LZSS writer which writes the large-window format
+    /// with a window of 2^`window_bits` bytes, where `window_bits`
+    /// ranges from `MIN_LARGE_WINDOW_BITS` to `MAX_LARGE_WINDOW_BITS`.
+    pub fn with_window_bits(inner: W, window_bits: usize) -> Writer<W>{
+        Writer::with_window_bits_and_level(inner, window_bits, window::DEFAULT_LEVEL)
+    }
+
+    /// Create a new LZSS writer for the large-window format at
+    /// compression `level`.  The optimal parser is only available
+    /// for the small-window format, the highest level only selects
+    /// the most thorough match search here.
+    pub fn with_window_bits_and_level(inner: W, window_bits: usize, level: u32) -> Writer<W>{
+        assert!(window_bits >= MIN_LARGE_WINDOW_BITS && window_bits <= MAX_LARGE_WINDOW_BITS);
+        Writer::create(inner, window_bits, level)
+    }
+
+    fn create(inner: W, window_bits: usize, level: u32) -> Writer<W> {
+        assert!(level >= 1 && level <= window::MAX_LEVEL);
+        let large = window_bits != WINDOW_BITS;
+        let window_size = 1 << window_bits;
+        let (max_match_len, rep_max_match_len, hash_bits) =
+            if large {
+                (LARGE_MAX_MATCH_LEN, LARGE_REP_MAX_MATCH_LEN, LARGE_HASH_BITS)
+            } else {
+                (MAX_MATCH_LEN, REP_MAX_MATCH_LEN, HASH_BITS)
+            };
+        let look_ahead_size = ::core::cmp::max(max_match_len, rep_max_match_len);
         Writer {
-            inner:  inner,
-            window: [0; WINDOW_SIZE],
-            hashtab: [0; HASHTAB_SIZE],
-            position: 0,
-            look_ahead_bytes: 0,
+            inner:  Counter::new(inner),
+            window: SlidingWindow::new(window_size),
+            matcher: MatchFinder::with_level(window_size, hash_bits, level),
+            reps: [0; REP_COUNT],
+
+            large: large,
+            max_match_len: max_match_len,
+            rep_max_match_len: rep_max_match_len,
+            look_ahead_size: look_ahead_size,
+            max_offset: window_size - look_ahead_size - 1,
+            header: if large { Some(window_bits as u8) } else { None },
+            finished: false,
+            total_in: 0,
+
+            optimal: level == window::MAX_LEVEL && !large,
+            found: VecDeque::with_capacity(look_ahead_size),
+            pending: 0,
+            nodes: Vec::with_capacity(look_ahead_size + 1),
 
             out_flags: 0,
             out_count: 0,
-            out_data: [0; 1 + 8*2],
+            out_data: [0; 1 + 8*MAX_MATCH_BYTES],
             out_len:  1,
         }
     }
 
+    /// Write the header of the large-window format, if it has not
+    /// been written yet.
+    fn emit_header(&mut self) -> io::Result<()> {
+        if let Some(header) = self.header.take() {
+            try!(self.inner.write_all(&[header]));
+        }
+        Ok(())
+    }
+
     /// Output all buffered match/length pairs and literals.
     fn emit_flush(&mut self) -> io::Result<()> {
         if self.out_count > 0 {
+            try!(self.emit_header());
             if self.out_count < 8 {
                 self.out_flags <<= 8 - self.out_count;
             }
             self.out_data[0] = self.out_flags;
             try!(self.inner.write_all(&self.out_data[..self.out_len]));
-            
+
             self.out_flags = 0;
             self.out_count = 0;
             self.out_len = 1;
@@ -87,91 +317,294 @@ impl<W: Write> Writer<W> {
     /// Emit a match/length pair, which is already encoded in `m1` and
     /// `m2`.
     pub fn emit_match(&mut self, m1: u8, m2: u8) -> io::Result<()> {
+        self.emit_match_bytes(&[m1, m2])
+    }
+
+    /// Emit a match which is already encoded in `bytes`.
+    fn emit_match_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
         if self.out_count == 8 {
             try!(self.emit_flush());
         }
         self.out_count += 1;
         self.out_flags = self.out_flags << 1;
-        self.out_data[self.out_len] = m1;
-        self.out_data[self.out_len + 1] = m2;
-        self.out_len += 2;
+        self.out_data[self.out_len..self.out_len + bytes.len()].copy_from_slice(bytes);
+        self.out_len += bytes.len();
         Ok(())
     }
 
-    /// Calculate a hash of the next 3 bytes in the look-ahead buffer.
-    /// This hash is used to look up earlier occurences of the data we
-    /// are looking at.  Because hash table entries are overwritten
-    /// blindly, we have to validate whatever we take out of the table
-    /// when calculating the match length.
-    fn hash_at(&self, pos: usize) -> usize {
-        // This might go over the data actually in the window, but as
-        // long as the compressor and decompressor maintain the same
-        // window contents, it should not matter.
-        let h1 = self.window[pos] as usize;
-        let h2 = self.window[mod_window(pos + 1)] as usize;
-        let h3 = self.window[mod_window(pos + 2)] as usize;
+    /// Emit the end of stream marker.
+    fn emit_eos(&mut self) -> io::Result<()> {
+        if self.large {
+            let ofs = LARGE_EOS_OFFSET - 1;
+            self.emit_match_bytes(&[3 << LARGE_LENGTH_BITS, ofs as u8, (ofs >> 8) as u8,
+                                    (ofs >> 16) as u8])
+        } else {
+            self.emit_match((EOS_OFFSET >> 8) as u8, (EOS_OFFSET & 0xff) as u8)
+        }
+    }
 
-        let h = (h1 >> 5) ^ ((h2 << 8) + h3);
+    /// Find the longest match for the look-ahead buffer in the window
+    /// and enter the current position into the match finder.
+    /// Returns the match length and offset.  The match finder hashes
+    /// three bytes, so that matches of length `MIN_MATCH_LEN` are
+    /// only found when the byte following them matches, too.
+    fn find_longest_match(&mut self) -> (usize, usize) {
+        let max_len = ::core::cmp::min(self.window.buffered(), self.max_match_len);
+        self.matcher.find(self.window.data(), self.window.position(), max_len, self.max_offset)
+    }
 
-        h % HASHTAB_SIZE
+    /// Enter the `count - 1` positions following the current position
+    /// into the match finder.
+    fn skip_positions(&mut self, count: usize) {
+        for i in 1..count {
+            let max_len = ::core::cmp::min(self.window.buffered() - i, self.max_match_len);
+            let pos = self.window.wrap(self.window.position() + i);
+            self.matcher.skip(self.window.data(), pos, max_len, self.max_offset);
+        }
     }
 
-    fn find_longest_match(&self, match_pos: usize, search_pos: usize) -> usize {
-        if self.look_ahead_bytes > MIN_MATCH_LEN && match_pos != search_pos {
-            let mut match_len = 0;
-            for i in 0..::std::cmp::min(self.look_ahead_bytes, MAX_MATCH_LEN) {
-                if self.window[mod_window(match_pos + i)] != self.window[mod_window(search_pos + i)] {
-                    break;
-                }
-                match_len += 1;
+    /// Find the longest match at one of the repeat offsets.  Returns
+    /// the index of the repeat offset and the match length.
+    fn find_rep_match(&self) -> (usize, usize) {
+        let mut best = (0, 0);
+        let max_len = ::core::cmp::min(self.window.buffered(), self.rep_max_match_len);
+        for (i, &ofs) in self.reps.iter().enumerate() {
+            if ofs == 0 {
+                continue;
             }
-            match_len
-        } else {
-            0
+            let match_len = self.window.match_len(0, ofs, max_len);
+            if match_len > best.1 {
+                best = (i, match_len);
+            }
+        }
+        best
+    }
+
+    /// Return the size of `token` in bits.
+    fn token_cost(&self, token: Token) -> usize {
+        if !self.large {
+            return match token {
+                Token::Literal => LITERAL_COST,
+                _ => MATCH_COST,
+            };
+        }
+        match token {
+            Token::Literal => LITERAL_COST,
+            Token::Match(len, ofs) => {
+                let ext = len - MIN_MATCH_LEN >= (1 << LARGE_LENGTH_BITS) - 1;
+                1 + 8 * (1 + ext as usize + large_offset_bytes(ofs))
+            },
+            Token::Rep(len, _) => {
+                let ext = len - MIN_MATCH_LEN >= (1 << LARGE_REP_LENGTH_BITS) - 1;
+                1 + 8 * (1 + ext as usize)
+            },
+        }
+    }
+
+    /// Emit `token` for the data at the current position and update
+    /// the repeat offsets.  Returns the number of bytes covered by
+    /// the token.
+    fn emit_token(&mut self, token: Token) -> io::Result<usize> {
+        match token {
+            Token::Literal => {
+                let lit = self.window.get(0);
+                try!(self.emit_lit(lit));
+                Ok(1)
+            },
+            Token::Match(len, ofs) if self.large => {
+                assert!(ofs != 0 && ofs <= self.max_offset);
+                assert!(len >= MIN_MATCH_LEN && len <= self.max_match_len);
+
+                let n = large_offset_bytes(ofs);
+                let mut buf = [0; MAX_MATCH_BYTES];
+                let mut k = encode_large_len(&mut buf, n, len - MIN_MATCH_LEN,
+                                             LARGE_LENGTH_BITS);
+                for j in 0..n {
+                    buf[k] = ((ofs - 1) >> (8 * j)) as u8;
+                    k += 1;
+                }
+
+                try!(self.emit_match_bytes(&buf[..k]));
+                push_rep(&mut self.reps, ofs);
+                Ok(len)
+            },
+            Token::Rep(len, rep_idx) if self.large => {
+                let mut buf = [0; 2];
+                let k = encode_large_len(&mut buf, rep_idx, len - MIN_MATCH_LEN,
+                                         LARGE_REP_LENGTH_BITS);
+
+                try!(self.emit_match_bytes(&buf[..k]));
+                promote_rep(&mut self.reps, rep_idx);
+                Ok(len)
+            },
+            Token::Match(len, ofs) => {
+                assert!(ofs != 0 && ofs <= MAX_OFFSET);
+                assert!((len - MIN_MATCH_LEN) < 16);
+
+                let m1 = (((len - MIN_MATCH_LEN) as u8) << 4)
+                    | (((ofs >> 8) as u8) & 0x0f);
+                let m2 = (ofs & 0xff) as u8;
+
+                try!(self.emit_match(m1, m2));
+                push_rep(&mut self.reps, ofs);
+                Ok(len)
+            },
+            Token::Rep(len, rep_idx) => {
+                let l = len - MIN_MATCH_LEN;
+                let m1 = (((l & 0x0f) as u8) << 4) | ((REP_BASE >> 8) as u8);
+                let m2 = ((REP_BASE | (rep_idx << 2) | (l >> 4)) & 0xff) as u8;
+
+                try!(self.emit_match(m1, m2));
+                promote_rep(&mut self.reps, rep_idx);
+                Ok(len)
+            },
         }
     }
 
+    /// Return whether `token` covering `len` bytes is cheaper than
+    /// coding them as literals.
+    fn pays_off(&self, token: Token, len: usize) -> bool {
+        len >= MIN_MATCH_LEN && self.token_cost(token) < len * LITERAL_COST
+    }
+
     fn process(&mut self) -> io::Result<()> {
-        let search_pos = self.position;
-        
-        let hsh = self.hash_at(search_pos);
-        let match_pos = self.hashtab[hsh];
-        
-        let ofs =
-            if match_pos < self.position {
-                self.position - match_pos
+        let (match_len, ofs) = self.find_longest_match();
+        let (rep_idx, rep_len) = self.find_rep_match();
+
+        let rep = Token::Rep(rep_len, rep_idx);
+        let mat = Token::Match(match_len, ofs);
+        let token =
+            if self.pays_off(rep, rep_len) && rep_len >= match_len {
+                rep
+            } else if self.pays_off(mat, match_len) {
+                mat
+            } else if self.pays_off(rep, rep_len) {
+                rep
             } else {
-                self.position + (WINDOW_SIZE - match_pos)
+                Token::Literal
             };
-        
-        let match_len = self.find_longest_match(match_pos, search_pos);
-        
-        if ofs < WINDOW_SIZE - MAX_MATCH_LEN && match_len >= MIN_MATCH_LEN {
-            assert!(ofs != 0);
-            assert!((match_len - MIN_MATCH_LEN) < 16);
-            
-            let m1 = (((match_len - MIN_MATCH_LEN) as u8) << 4)
-                | (((ofs >> 8) as u8) & 0x0f);
-            let m2 = (ofs & 0xff) as u8;
-
-            try!(self.emit_match(m1, m2));
-            
-            self.position = mod_window(self.position + match_len);
-            self.look_ahead_bytes -= match_len;
-        } else {
-            let lit = self.window[self.position];
-            try!(self.emit_lit(lit));
+        let len = try!(self.emit_token(token));
+        self.skip_positions(len);
+        self.window.consume(len);
+        Ok(())
+    }
+
+    /// Run the match finder for the look-ahead positions up to index
+    /// `end` which have not been searched yet.
+    fn search_ahead(&mut self, end: usize) {
+        while self.found.len() < end {
+            let i = self.found.len();
+            let max_len = ::core::cmp::min(self.window.buffered() - i, self.max_match_len);
+            let pos = self.window.wrap(self.window.position() + i);
+            let m = self.matcher.find(self.window.data(), pos, max_len, self.max_offset);
+            self.found.push_back(m);
+        }
+    }
 
-            self.position = mod_window(self.position + 1);
-            self.look_ahead_bytes -= 1;
+    /// Find the cheapest sequence of tokens for the look-ahead
+    /// buffer and emit its beginning.  Unless `last` is set, more
+    /// data will follow, so tokens near the end of the look-ahead
+    /// buffer are not emitted yet, because they may be chosen
+    /// differently once the following data is known.  Only used for
+    /// the small-window format, where all matches cost the same.
+    fn process_optimal(&mut self, last: bool) -> io::Result<()> {
+        // Enter the positions covered by tokens which extended past
+        // the searched positions last time.  Their distance limit is
+        // reduced, because the window has moved on since.
+        for k in (1..self.pending + 1).rev() {
+            let max_len = ::core::cmp::min(self.window.buffered() + k, MAX_MATCH_LEN);
+            let pos = self.window.wrap(self.window.position() + WINDOW_SIZE - k);
+            self.matcher.skip(self.window.data(), pos, max_len, MAX_OFFSET - k);
         }
-        self.hashtab[hsh] = search_pos;
+        self.pending = 0;
+
+        let n = self.window.buffered();
+        // Only search positions for which a match of maximum length
+        // is possible, the others are searched when more data is
+        // available.
+        let searchable = if last { n } else { n + 1 - MAX_MATCH_LEN };
+        self.search_ahead(searchable);
+
+        let mut nodes = ::core::mem::replace(&mut self.nodes, Vec::new());
+        nodes.clear();
+        nodes.resize(n + 1, Node {
+            cost: usize::max_value(),
+            from: 0,
+            token: Token::Literal,
+            reps: self.reps,
+        });
+        nodes[0].cost = 0;
+        for i in 0..n {
+            let node = nodes[i];
+            relax(&mut nodes, i, i + 1, node.cost + LITERAL_COST, Token::Literal, node.reps);
+
+            let max_rep_len = ::core::cmp::min(n - i, REP_MAX_MATCH_LEN);
+            for (r, &ofs) in node.reps.iter().enumerate() {
+                if ofs == 0 {
+                    continue;
+                }
+                let rep_len = self.window.match_len(i, ofs, max_rep_len);
+                let mut reps = node.reps;
+                promote_rep(&mut reps, r);
+                for len in MIN_MATCH_LEN..rep_len + 1 {
+                    relax(&mut nodes, i, i + len, node.cost + MATCH_COST, Token::Rep(len, r), reps);
+                }
+            }
+
+            if i < self.found.len() {
+                let (match_len, ofs) = self.found[i];
+                let match_len = ::core::cmp::min(match_len, n - i);
+                let mut reps = node.reps;
+                push_rep(&mut reps, ofs);
+                for len in MIN_MATCH_LEN..match_len + 1 {
+                    relax(&mut nodes, i, i + len, node.cost + MATCH_COST, Token::Match(len, ofs), reps);
+                }
+            }
+        }
+
+        let mut ends = Vec::new();
+        let mut i = n;
+        while i > 0 {
+            ends.push(i);
+            i = nodes[i].from;
+        }
+        // Matches are only known for the first part of the buffer, so
+        // the end of the parse is not reliable.  Only the tokens
+        // starting within one maximum match length are emitted.
+        let horizon = if last { n } else { MAX_MATCH_LEN };
+        let mut start = 0;
+        for &end in ends.iter().rev() {
+            if start > 0 && start >= horizon {
+                break;
+            }
+            let len = try!(self.emit_token(nodes[end].token));
+            self.window.consume(len);
+            start = end;
+        }
+        if start <= self.found.len() {
+            self.found.drain(..start);
+        } else {
+            self.pending = start - self.found.len();
+            self.found.clear();
+        }
+        self.nodes = nodes;
         Ok(())
     }
 
+    /// Return the number of uncompressed bytes written so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Return the number of compressed bytes written to the wrapped
+    /// writer so far.
+    pub fn total_out(&self) -> u64 {
+        self.inner.count()
+    }
+
     /// Move the wrapped writer out of the LZSS writer.
     pub fn into_inner(self) -> W {
-        self.inner
+        self.inner.into_inner()
     }
 }
 
@@ -179,132 +612,316 @@ impl<W: Write> Write for Writer<W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let mut written = 0;
         while written < buf.len() {
-            while written < buf.len() && self.look_ahead_bytes < LOOK_AHEAD_BYTES {
-                self.window[mod_window(self.position + self.look_ahead_bytes)] =
-                    buf[written];
-                self.look_ahead_bytes += 1;
-                written += 1;
-            }
-            if self.look_ahead_bytes == LOOK_AHEAD_BYTES {
-                try!(self.process());
+            written += self.window.fill(&buf[written..], self.look_ahead_size);
+            if self.window.buffered() == self.look_ahead_size {
+                if self.optimal {
+                    try!(self.process_optimal(false));
+                } else {
+                    try!(self.process());
+                }
             }
         }
+        self.total_in += written as u64;
         Ok(written)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        while self.look_ahead_bytes > 0 {
+        if self.optimal {
+            try!(self.process_optimal(true));
+        }
+        while self.window.buffered() > 0 {
             try!(self.process());
         }
+        if !self.finished {
+            try!(self.emit_eos());
+            self.finished = true;
+        }
         try!(self.emit_flush());
         self.inner.flush()
     }
 }
 
-/// Reader for LZSS compressed streams.
+/// Reader for LZSS compressed streams.  The format is detected from
+/// the first byte of the stream.
 pub struct Reader<R> {
-    inner: Bytes<R>,
-    window: [u8; WINDOW_SIZE],
-    position: usize,
-    returned: usize,
+    inner: ByteReader<R>,
+    window: SlidingWindow,
+    reps: [usize; REP_COUNT],
+    /// Whether the stream uses the large-window format.
+    large: bool,
+    started: bool,
     eof: bool,
+    recorder: Recorder,
+    total_out: u64,
 }
 
 impl<R: Read> Reader<R> {
-    /// Create a new LZSS reader that wraps another reader.
+    /// Create a new LZSS reader that wraps another reader.  The
+    /// reader reads no further than the end of the stream, so that
+    /// data following it can be read from `inner` afterwards.
     pub fn new(inner: R) -> Reader<R> {
+        Reader::with_window(inner, vec![0; WINDOW_SIZE])
+    }
+
+    /// Create a reader which reads ahead from `inner` in blocks.  This
+    /// is faster than `new` for unbuffered readers, but consumes input
+    /// beyond the end of the stream.
+    pub fn buffered(inner: R) -> Reader<R> {
+        Reader::from_input(ByteReader::new(inner), vec![0; WINDOW_SIZE])
+    }
+
+    /// Create a reader which decodes into `window` instead of
+    /// allocating its own window.  The length of `window` must be a
+    /// power of two and at least 4096 bytes.  Streams in the
+    /// large-window format which need a larger window allocate one.
+    /// Together with `decode` and `into_window`, this lets a caller
+    /// reuse one buffer for many streams.
+    pub fn with_window(inner: R, window: Vec<u8>) -> Reader<R> {
+        Reader::from_input(ByteReader::exact(inner), window)
+    }
+
+    fn from_input(inner: ByteReader<R>, window: Vec<u8>) -> Reader<R> {
+        assert!(window.len() >= WINDOW_SIZE);
         Reader {
-            inner: inner.bytes(),
-            window: [0; WINDOW_SIZE],
-            position: 0,
-            returned: 0,
+            inner: inner,
+            window: SlidingWindow::from_vec(window),
+            reps: [0; REP_COUNT],
+            large: false,
+            started: false,
             eof: false,
+            recorder: Recorder::new(),
+            total_out: 0,
         }
     }
 
+    /// Decode the next piece of the stream and return it.  The data
+    /// is returned directly from the window, without copying it into
+    /// an output buffer, and stays valid until the next call.  An
+    /// empty slice means the end of the stream.
+    pub fn decode(&mut self) -> io::Result<&[u8]> {
+        try!(self.fill());
+        let data = self.window.take_buffered();
+        self.total_out += data.len() as u64;
+        Ok(data)
+    }
+
+    /// Return the window, for reuse with `with_window`.
+    pub fn into_window(self) -> Vec<u8> {
+        self.window.into_vec()
+    }
+
     /// Copy all decompressed data from the window to the output
     /// buffer.
     fn copy_out(&mut self, output: &mut [u8], written: &mut usize) {
-        while *written < output.len() && self.returned != self.position {
-            output[*written] = self.window[self.returned];
-            *written += 1;
-            self.returned = mod_window(self.returned + 1);
+        *written += self.window.read(&mut output[*written..]);
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        self.inner.next_byte()
+    }
+
+    /// Return the number of compressed bytes consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.inner.consumed()
+    }
+
+    /// Return the number of decompressed bytes read so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Read a byte which continues a match.
+    fn match_byte(&mut self) -> io::Result<u8> {
+        match try!(self.next_byte()) {
+            Some(b) => Ok(b),
+            None => Err(Error::UnexpectedEof.into()),
         }
     }
 
+    /// Return the next flag byte.  At the start of the stream, this
+    /// reads the header of the large-window format, if present.
+    fn next_token(&mut self) -> io::Result<Option<u8>> {
+        let token = try!(self.next_byte());
+        if self.started {
+            return Ok(token);
+        }
+        self.started = true;
+        match token {
+            Some(header) if header != 0 && header & 0x80 == 0 => {
+                let window_bits = header as usize;
+                if window_bits < MIN_LARGE_WINDOW_BITS || window_bits > MAX_LARGE_WINDOW_BITS {
+                    return Err(Error::InvalidHeader("invalid window size").into());
+                }
+                self.large = true;
+                if self.window.size() < 1 << window_bits {
+                    self.window = SlidingWindow::new(1 << window_bits);
+                }
+                self.recorder.record(Event::Header(format!("window {} bits", window_bits)));
+                self.next_byte()
+            },
+            _ => Ok(token),
+        }
+    }
+
+    /// Decode a match of the small-window format: a 4-bit length and
+    /// a 12-bit offset, where offsets from `REP_BASE` select a repeat
+    /// offset.  Returns `None` at the end of the stream.
+    fn read_small_match(&mut self) -> io::Result<Option<Token>> {
+        let m1 = match try!(self.next_byte()) {
+            Some(m1) => m1,
+            None => return Ok(None),
+        };
+        let m2 = try!(self.match_byte());
+        let len = ((m1 >> 4) as usize) + MIN_MATCH_LEN;
+        let ofs = (((m1 as usize) & 0xf) << 8) | (m2 as usize);
+        if ofs == EOS_OFFSET {
+            Ok(None)
+        } else if ofs == 0 {
+            Err(Error::corrupt_at(self.inner.position(), "match offset is zero").into())
+        } else if ofs >= REP_BASE {
+            let rep_idx = (ofs >> 2) & (REP_COUNT - 1);
+            Ok(Some(Token::Rep(len + ((ofs & 3) << LENGTH_BITS), rep_idx)))
+        } else {
+            Ok(Some(Token::Match(len, ofs)))
+        }
+    }
+
+    /// Decode the length field of a large-window match, which may be
+    /// followed by an extension byte.
+    fn read_large_len(&mut self, m1: u8, bits: usize) -> io::Result<usize> {
+        let field_max = (1 << bits) - 1;
+        let mut len = (m1 as usize) & field_max;
+        if len == field_max {
+            len += try!(self.match_byte()) as usize;
+        }
+        Ok(len + MIN_MATCH_LEN)
+    }
+
+    /// Decode a match of the large-window format.  Returns `None` at
+    /// the end of the stream.
+    fn read_large_match(&mut self) -> io::Result<Option<Token>> {
+        let m1 = match try!(self.next_byte()) {
+            Some(m1) => m1,
+            None => return Ok(None),
+        };
+        let n = (m1 >> LARGE_LENGTH_BITS) as usize;
+        if n == 0 {
+            let rep_idx = (m1 as usize >> LARGE_REP_LENGTH_BITS) & (REP_COUNT - 1);
+            let len = try!(self.read_large_len(m1, LARGE_REP_LENGTH_BITS));
+            return Ok(Some(Token::Rep(len, rep_idx)));
+        }
+        let len = try!(self.read_large_len(m1, LARGE_LENGTH_BITS));
+        let mut ofs = 0;
+        for j in 0..n {
+            ofs |= (try!(self.match_byte()) as usize) << (8 * j);
+        }
+        ofs += 1;
+        if ofs == LARGE_EOS_OFFSET {
+            return Ok(None);
+        }
+        if ofs >= self.window.size() {
+            return Err(Error::corrupt_at(self.inner.position(),
+                                         "match offset exceeds window").into());
+        }
+        Ok(Some(Token::Match(len, ofs)))
+    }
+
+    /// Copy the data of a decoded match into the window and update
+    /// the repeat offsets.
+    fn copy_match(&mut self, token: Token) -> io::Result<()> {
+        let (len, ofs) = match token {
+            Token::Rep(len, rep_idx) => {
+                let ofs = self.reps[rep_idx];
+                if ofs == 0 {
+                    return Err(Error::corrupt_at(self.inner.position(),
+                                                 "repeat match without offset").into());
+                }
+                promote_rep(&mut self.reps, rep_idx);
+                (len, ofs)
+            },
+            Token::Match(len, ofs) => {
+                push_rep(&mut self.reps, ofs);
+                (len, ofs)
+            },
+            Token::Literal => unreachable!(),
+        };
+        debug_assert!(ofs > 0);
+        if ofs > self.window.filled() {
+            return Err(Error::corrupt_at(self.inner.position(),
+                                         "match before start of data").into());
+        }
+        self.recorder.record(Event::Match { offset: ofs, len: len });
+        self.window.copy_match(ofs, len);
+        Ok(())
+    }
+
     /// Process a group of 8 literals or match/length pairs.  The
     /// given token is contains the flag bits.
     fn process_group(&mut self, token: u8) -> io::Result<()> {
         for i in 0..8 {
             if token & 0x80 >> i == 0 {
-                // Zero bit indicates a match/length pair. Decode the
-                // next two bytes into a 4-bit length and a 12-bit
-                // offset.
-                let mbm1 = self.inner.next();
-                let mbm2 = self.inner.next();
-                match (mbm1, mbm2) {
-                    (None, None) => {
+                // Zero bit indicates a match/length pair.  The end
+                // marker or the end of the input here is the end of
+                // the stream; the last flag byte is padded with zero
+                // bits.
+                let m =
+                    if self.large {
+                        try!(self.read_large_match())
+                    } else {
+                        try!(self.read_small_match())
+                    };
+                match m {
+                    Some(token) => try!(self.copy_match(token)),
+                    None => {
+                        self.recorder.record(Event::End);
                         self.eof = true;
                         return Ok(());
-                    }
-                    (Some(m1), Some(m2)) => {
-                        let m1 = try!(m1);
-                        let m2 = try!(m2);
-                        let len = ((m1 >> 4) as usize) + MIN_MATCH_LEN;
-                        let ofs = (((m1 as usize) & 0xf) << 8) | (m2 as usize);
-                        debug_assert!(ofs > 0);
-                        let pos =
-                            if ofs < self.position {
-                                self.position - ofs
-                            } else {
-                                WINDOW_SIZE - (ofs - self.position)
-                            };
-                        for i in 0..len {
-                            self.window[mod_window(self.position + i)] =
-                                self.window[mod_window(pos + i)];
-                        }
-                        self.position = mod_window(self.position + len);
-                    },
-                    _ => {
-                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
-                                                  "cannot read match/lit pair"));
                     },
                 }
             } else {
                 // A 1-bit in the token indicates a literal.  Just
                 // take the next byte from the input and add it to the
                 // window.
-                if let Some(lit) = self.inner.next() {
-                    let lit = try!(lit);
-                    self.window[self.position] = lit;
-                    self.position = mod_window(self.position + 1);
+                if let Some(lit) = try!(self.next_byte()) {
+                    self.recorder.record(Event::Literal(lit));
+                    self.window.push(lit);
                 } else {
                     // EOF here means corrupted input, because the
                     // encoder does not put a 1-bit into the token
                     // when the stream ends.
                     self.eof = true;
-                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
-                                              "cannot read literal"));
+                    return Err(Error::UnexpectedEof.into());
                 }
             }
         }
         Ok(())
     }
 
+    /// Decode groups until there is data in the window or the stream
+    /// has ended.
+    fn fill(&mut self) -> io::Result<()> {
+        while self.window.buffered() == 0 && !self.eof {
+            match try!(self.next_token()) {
+                Some(token) => try!(self.process_group(token)
+                                    .map_err(|e| at_offset(e, self.total_in()))),
+                None => self.eof = true,
+            }
+        }
+        Ok(())
+    }
+
     /// Process as much from the underlying input as necessary to fill
     /// the output buffer.  When more data than necessary is
     /// decompressed, it stays in the window for later processing.
     fn process(&mut self, output: &mut [u8]) -> io::Result<usize> {
         let mut written = 0;
-        
+
         // Copy out data that already was decompressed but did not fit
         // into output last time.
         self.copy_out(output, &mut written);
-        
-        'outer:
-        while written < output.len() {
-            if let Some(token) = self.inner.next() {
-                let token = try!(token);
+
+        while written < output.len() && !self.eof {
+            if let Some(token) = try!(self.next_token()) {
                 try!(self.process_group(token));
                 self.copy_out(output, &mut written);
             } else {
@@ -318,11 +935,38 @@ impl<R: Read> Reader<R> {
 
 impl<R: Read> Read for Reader<R> {
     fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
-        if self.eof {
-            Ok(0)
+        let n = if self.eof {
+            // The last group may have produced more data than fit
+            // into the previous output buffer.
+            let mut written = 0;
+            self.copy_out(output, &mut written);
+            written
         } else {
-            self.process(output)
-        }
+            try!(self.process(output).map_err(|e| at_offset(e, self.total_in())))
+        };
+        self.total_out += n as u64;
+        Ok(n)
+    }
+}
+
+/// The decoded data which has not been read yet is returned directly
+/// from the window.
+#[cfg(feature = "std")]
+impl<R: Read> io::BufRead for Reader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        try!(self.fill());
+        Ok(self.window.buffered_data())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.window.consume(amt);
+        self.total_out += amt as u64;
+    }
+}
+
+impl<R: Read> Inspect for Reader<R> {
+    fn recorder(&mut self) -> &mut Recorder {
+        &mut self.recorder
     }
 }
 
@@ -333,13 +977,39 @@ pub fn compress<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error>
     Ok(cw.into_inner())
 }
 
+/// Compress `input` like `compress`, but at compression `level`.
+pub fn compress_with_level<R: Read, W: Write>(mut input: R, output: W, level: u32) -> Result<W, Error> {
+    let mut cw = Writer::with_level(output, level);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.into_inner())
+}
+
+/// Compress `input` in the large-window format with a window of
+/// 2^`window_bits` bytes.
+pub fn compress_with_window_bits<R: Read, W: Write>(mut input: R, output: W, window_bits: usize) -> Result<W, Error> {
+    let mut cw = Writer::with_window_bits(output, window_bits);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.into_inner())
+}
+
+/// Decompress `input`, which may be in either format.  The input is
+/// read ahead in blocks, so use `Reader::new` for streams followed
+/// by other data.
 pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<W, Error> {
-    let mut cr = Reader::new(input);
+    let mut cr = Reader::buffered(input);
     try!(io::copy(&mut cr, &mut output));
     Ok(output)
 }
 
-#[cfg(test)]
+/// Return an iterator over the header, literals and matches of the
+/// stream in `input`, which may be in either format.
+pub fn inspect<R: Read>(input: R) -> Events<Reader<R>> {
+    Events::new(Reader::new(input))
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use ::std::io::Cursor;
 
@@ -358,24 +1028,29 @@ mod tests {
 
     #[test]
     fn compress_empty() {
-        cmp_test(b"", &[]);
+        cmp_test(b"", &[0, 15, 239]);
     }
 
     #[test]
     fn compress_a() {
-        cmp_test(b"a", &[128, b'a']);
+        cmp_test(b"a", &[128, b'a', 15, 239]);
     }
 
     #[test]
     fn compress_aaa() {
-        cmp_test(b"aaaaaaaaa", &[128, 97, 96, 1]);
+        cmp_test(b"aaaaaaaaa", &[128, 97, 96, 1, 15, 239]);
     }
 
     #[test]
     fn compress_abc() {
         cmp_test(b"abcdefgabcdefgabcabcabcdefg",
                  &[254, 97, 98, 99, 100, 101, 102, 103, 128,
-                   7, 0, 16, 10, 16, 3, 32, 20]);
+                   7, 0, 64, 3, 32, 13, 15, 239]);
+    }
+
+    #[test]
+    fn compress_rep() {
+        cmp_test(&[b'a'; 100], &[128, 97, 240, 1, 255, 243, 255, 240, 15, 239]);
     }
 
     fn decmp_test(compressed: &[u8], expected_output: &[u8]) {
@@ -407,15 +1082,61 @@ mod tests {
     fn decompress_abc() {
         decmp_test(
             &[254, 97, 98, 99, 100, 101, 102, 103, 128,
-              7, 0, 16, 10, 16, 3, 32, 20],
+              7, 0, 64, 3, 32, 13],
             b"abcdefgabcdefgabcabcabcdefg");
     }
 
-    fn roundtrip(input: &[u8]) {
-        let mut cw = Writer::new(vec![]);
+    #[test]
+    fn decompress_rep() {
+        decmp_test(&[128, 97, 240, 1, 255, 243, 255, 240], &[b'a'; 100]);
+    }
+
+    #[test]
+    fn decompress_embedded() {
+        for &(format, ref compressed) in &[("small", vec![128, 97, 96, 1, 15, 239]),
+                                           ("large", vec![16, 128, 97, 0x46, 0, 0xc0,
+                                                          255, 255, 255])] {
+            let mut input = Cursor::new(compressed.clone());
+            input.get_mut().extend_from_slice(&[1, 2, 3]);
+            let mut decompressed = Vec::new();
+            Reader::new(&mut input).read_to_end(&mut decompressed).unwrap();
+            assert_eq!(b"aaaaaaaaa", &decompressed[..], "{} window", format);
+            assert_eq!(compressed.len() as u64, input.position());
+        }
+    }
+
+    #[test]
+    fn inspect_events() {
+        use inspect::Event;
+        let body = vec![Event::Literal(b'a'), Event::Match { offset: 1, len: 8 }, Event::End];
+        let mut large = vec![Event::Header("window 16 bits".to_string())];
+        large.extend_from_slice(&body);
+        for &(ref compressed, ref expected) in &[(vec![128, 97, 96, 1, 15, 239], body.clone()),
+                                                 (vec![16, 128, 97, 0x46, 0, 0xc0,
+                                                       255, 255, 255], large)] {
+            let events: Vec<Event> =
+                super::inspect(Cursor::new(compressed)).map(|e| e.unwrap()).collect();
+            assert_eq!(expected, &events);
+        }
+    }
+
+    #[test]
+    fn decompress_rep_without_offset() {
+        let mut cr = Reader::new(Cursor::new(&[0, 0x0f, 0xf0][..]));
+        let mut decompressed = Vec::new();
+        assert!(cr.read_to_end(&mut decompressed).is_err());
+    }
+
+    fn roundtrip(input: &[u8]) -> usize {
+        roundtrip_level(input, ::window::DEFAULT_LEVEL)
+    }
+
+    fn roundtrip_level(input: &[u8], level: u32) -> usize {
+        let mut cw = Writer::with_level(vec![], level);
         cw.write_all(&input[..]).unwrap();
         cw.flush().unwrap();
         let compressed = cw.into_inner();
+        let compressed_len = compressed.len();
 
         let mut cr = Reader::new(Cursor::new(compressed));
         let mut decompressed = Vec::new();
@@ -423,6 +1144,7 @@ mod tests {
 
         assert_eq!(input.len(), nread);
         assert_eq!(&input[..], &decompressed[..]);
+        compressed_len
     }
 
     #[test]
@@ -430,4 +1152,185 @@ mod tests {
         let input = include_bytes!("lzss.rs");
         roundtrip(input);
     }
+
+    #[test]
+    fn compress_levels() {
+        let input = include_bytes!("lzss.rs");
+        let fast = roundtrip_level(input, 1);
+        let best = roundtrip_level(input, ::window::MAX_LEVEL);
+        assert!(best < fast);
+    }
+
+    fn roundtrip_large(input: &[u8], window_bits: usize) -> usize {
+        let mut cw = Writer::with_window_bits(vec![], window_bits);
+        cw.write_all(&input[..]).unwrap();
+        cw.flush().unwrap();
+        let compressed = cw.into_inner();
+        let compressed_len = compressed.len();
+
+        let mut cr = Reader::new(Cursor::new(compressed));
+        let mut decompressed = Vec::new();
+        cr.read_to_end(&mut decompressed).unwrap();
+
+        assert_eq!(&input[..], &decompressed[..]);
+        compressed_len
+    }
+
+    #[test]
+    fn compress_large_empty() {
+        let mut cw = Writer::with_window_bits(vec![], 16);
+        cw.flush().unwrap();
+        assert_eq!(&[16, 0, 192, 255, 255, 255][..], &cw.into_inner()[..]);
+        decmp_test(&[16, 0, 192, 255, 255, 255], &[]);
+        decmp_test(&[16], &[]);
+    }
+
+    #[test]
+    fn compress_decompress_large() {
+        let input = include_bytes!("lzss.rs");
+        for &bits in &[super::MIN_LARGE_WINDOW_BITS, 18, super::MAX_LARGE_WINDOW_BITS] {
+            roundtrip_large(input, bits);
+        }
+        roundtrip_large(&[b'a'; 1000], 16);
+    }
+
+    #[test]
+    fn compress_large_window() {
+        // A block of pseudo-random data which repeats beyond the
+        // reach of the small window.
+        let mut block = Vec::new();
+        let mut x: u32 = 1;
+        for _ in 0..10000 {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            block.push((x >> 16) as u8);
+        }
+        let mut input = block.clone();
+        input.extend_from_slice(&block);
+
+        let small = roundtrip(&input);
+        let large = roundtrip_large(&input, 16);
+        assert!(large < small * 2 / 3);
+    }
+
+    #[test]
+    fn decompress_invalid_window_bits() {
+        for &header in &[0, 14, 23, 0x7f] {
+            let mut cr = Reader::new(Cursor::new(vec![header, 0x80, 0]));
+            let mut decompressed = Vec::new();
+            assert!(cr.read_to_end(&mut decompressed).is_err());
+        }
+    }
+
+    #[test]
+    fn decompress_large_offset_outside_window() {
+        let mut cr = Reader::new(Cursor::new(vec![15, 0x40, 0xc0, 0xff, 0xff, 0x00]));
+        let mut decompressed = Vec::new();
+        assert!(cr.read_to_end(&mut decompressed).is_err());
+    }
+
+    #[test]
+    fn buf_read() {
+        use ::std::io::BufRead;
+        let input = include_bytes!("lzss.rs");
+        let mut cw = Writer::new(vec![]);
+        cw.write_all(&input[..]).unwrap();
+        cw.flush().unwrap();
+        let compressed = cw.into_inner();
+
+        let mut cr = Reader::new(&compressed[..]);
+        let mut lines = 0;
+        let mut output = Vec::new();
+        while cr.read_until(b'\n', &mut output).unwrap() > 0 {
+            lines += 1;
+        }
+        assert_eq!(input.iter().filter(|&&b| b == b'\n').count(), lines);
+        assert_eq!(&input[..], &output[..]);
+        assert_eq!(input.len() as u64, cr.total_out());
+    }
+
+    #[test]
+    fn decode_with_window() {
+        let input = include_bytes!("lzss.rs");
+        let mut cw = Writer::new(vec![]);
+        cw.write_all(&input[..]).unwrap();
+        cw.flush().unwrap();
+        let compressed = cw.into_inner();
+
+        // Decode twice with the same window.
+        let mut window = vec![0; 8192];
+        for _ in 0..2 {
+            let mut cr = Reader::with_window(&compressed[..], window);
+            let mut output = Vec::new();
+            loop {
+                let data = cr.decode().unwrap();
+                if data.is_empty() {
+                    break;
+                }
+                output.extend_from_slice(data);
+            }
+            assert_eq!(&input[..], &output[..]);
+            window = cr.into_window();
+        }
+        assert_eq!(8192, window.len());
+    }
+
+    #[test]
+    fn fork() {
+        let input = include_bytes!("lzss.rs");
+        let (prefix, suffix) = input.split_at(input.len() / 2);
+        let mut cw = Writer::new(vec![]);
+        cw.write_all(prefix).unwrap();
+
+        // Finishing a copy leaves the original writer untouched.
+        let mut fork = cw.clone();
+        fork.write_all(b"appended").unwrap();
+        fork.flush().unwrap();
+        let mut expected = Writer::new(vec![]);
+        expected.write_all(prefix).unwrap();
+        expected.write_all(b"appended").unwrap();
+        expected.flush().unwrap();
+        assert_eq!(expected.into_inner(), fork.into_inner());
+
+        cw.write_all(suffix).unwrap();
+        cw.flush().unwrap();
+        let mut cr = Reader::new(Cursor::new(cw.into_inner()));
+        let mut output = Vec::new();
+        cr.read_to_end(&mut output).unwrap();
+        assert_eq!(&input[..], &output[..]);
+    }
+
+    #[test]
+    fn corrupt_offset() {
+        use error::Error;
+        // A match with offset zero, found after three bytes.
+        let mut cr = Reader::new(&[0u8, 0, 0, 0][..]);
+        let mut output = Vec::new();
+        match Error::from(cr.read_to_end(&mut output).unwrap_err()) {
+            Error::CorruptInput { offset: Some(3), .. } => (),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn match_before_start() {
+        use error::Error;
+        // A match with offset one, before any data.
+        let mut cr = Reader::new(&[0u8, 0, 1, 0][..]);
+        let mut output = Vec::new();
+        match Error::from(cr.read_to_end(&mut output).unwrap_err()) {
+            Error::CorruptInput { offset: Some(3), .. } => (),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+        let best = ::window::MAX_LEVEL;
+        ::testutil::roundtrip((|d: &[u8]| super::compress_with_level(d, vec![], best),
+                               |d: &[u8]| super::decompress(d, vec![])));
+        ::testutil::roundtrip((|d: &[u8]| super::compress_with_window_bits(d, vec![], 20),
+                               |d: &[u8]| super::decompress(d, vec![])));
+    }
 }
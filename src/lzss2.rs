@@ -2,13 +2,24 @@
 // top-level directory of this distribution for license information.
 
 //! Simple implementation of an LZSS compressor.
-
-use std::io::{Read, Write, Bytes};
+//!
+//! The output of the compressor is passed through a nested entropy
+//! coder, adaptive Huffman coding by default.  See the `nested`
+//! module for the alternatives.
+//!
+//! As in `lzss`, the highest compression level selects an optimal
+//! parse of the look-ahead buffer instead of greedy matching.
+
+use std::collections::VecDeque;
+use std::io::{BufRead, Read, Write};
 use std::io;
 
-use huff::adaptive as nested;
+use nested::{self, NestedCoder};
 
-use error::Error;
+use error::{at_offset, Error};
+use input::ByteReader;
+use inspect::{Event, Events, Inspect, Recorder};
+use window::{self, MatchFinder, SlidingWindow};
 
 const WINDOW_BITS: usize = 12;
 const LENGTH_BITS: usize = 4;
@@ -16,19 +27,57 @@ const LENGTH_BITS: usize = 4;
 const MIN_MATCH_LEN: usize = 2;
 const MAX_MATCH_LEN: usize = ((1 << LENGTH_BITS) - 1) + MIN_MATCH_LEN;
 
-const LOOK_AHEAD_BYTES: usize = MAX_MATCH_LEN;
+/// The look-ahead buffer holds several matches, so that the optimal
+/// parser can see ahead.
+const LOOK_AHEAD_BYTES: usize = 4 * MAX_MATCH_LEN;
 
 const WINDOW_SIZE: usize = 1 << WINDOW_BITS;
 
-const HASHTAB_SIZE: usize = 1 << 10;
+/// Largest match offset.  Data further back may already be
+/// overwritten by the look-ahead buffer.
+const MAX_OFFSET: usize = WINDOW_SIZE - LOOK_AHEAD_BYTES - 1;
+
+const HASH_BITS: usize = 12;
+
+/// Approximate size in bits of a literal and of a match, before
+/// entropy coding.
+const LITERAL_COST: usize = 9;
+const MATCH_COST: usize = 17;
+
+/// Item of the compressed stream.
+#[derive(Debug, Clone, Copy)]
+enum Token {
+    Literal,
+    /// Match with length and offset.
+    Match(usize, usize),
+}
+
+/// Cheapest way found by the optimal parser to code the look-ahead
+/// buffer up to some position: the cost in bits, the position where
+/// the last token starts and the last token.
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    cost: usize,
+    from: usize,
+    token: Token,
+}
 
 /// Writer for LZSS compressed streams.
+#[derive(Clone)]
 pub struct Writer<W> {
     inner:  nested::Writer<W>,
-    window: [u8; WINDOW_SIZE],
-    hashtab: [usize; HASHTAB_SIZE],
-    position: usize,
-    look_ahead_bytes: usize,
+    window: SlidingWindow,
+    matcher: MatchFinder,
+    total_in: u64,
+
+    optimal: bool,
+    /// Longest matches found for the first look-ahead positions, used
+    /// by the optimal parser.
+    found: VecDeque<(usize, usize)>,
+    /// Number of positions before the current position which have
+    /// not been entered into the match finder yet.
+    pending: usize,
+    nodes: Vec<Node>,
 
     out_flags: u8,
     out_count: usize,
@@ -36,20 +85,53 @@ pub struct Writer<W> {
     out_len:   usize,
 }
 
-#[inline(always)]
-fn mod_window(x: usize) -> usize {
-    x % WINDOW_SIZE
+/// Record `token` from look-ahead index `from` to `to` in the optimal
+/// parse, if it is cheaper than the best way found so far.
+fn relax(nodes: &mut [Node], from: usize, to: usize, cost: usize, token: Token) {
+    if cost < nodes[to].cost {
+        nodes[to] = Node {
+            cost: cost,
+            from: from,
+            token: token,
+        };
+    }
 }
 
 impl<W: Write> Writer<W> {
-    /// Create a new LZSS writer that wraps the given Writer.
+    /// Create a new LZSS writer that wraps the given Writer.  The
+    /// output is coded with adaptive Huffman coding.
     pub fn new(inner: W) -> Writer<W>{
+        Writer::with_coder(inner, NestedCoder::AdaptiveHuffman)
+    }
+
+    /// Create a new LZSS writer whose output is coded with `coder`.
+    pub fn with_coder(inner: W, coder: NestedCoder) -> Writer<W>{
+        Writer::with_coder_and_level(inner, coder, window::DEFAULT_LEVEL)
+    }
+
+    /// Create a new LZSS writer which searches for matches as
+    /// selected by compression `level`, from 1 (fastest) to
+    /// `window::MAX_LEVEL` (best compression).  The highest level
+    /// also selects the optimal parser.  The output is coded with
+    /// adaptive Huffman coding.
+    pub fn with_level(inner: W, level: u32) -> Writer<W>{
+        Writer::with_coder_and_level(inner, NestedCoder::AdaptiveHuffman, level)
+    }
+
+    /// Create a new LZSS writer with compression `level`, whose
+    /// output is coded with `coder`.
+    pub fn with_coder_and_level(inner: W, coder: NestedCoder, level: u32) -> Writer<W>{
+        assert!(level >= 1 && level <= window::MAX_LEVEL);
         Writer {
-            inner:  nested::Writer::new(inner),
-            window: [0; WINDOW_SIZE],
-            hashtab: [0; HASHTAB_SIZE],
-            position: 0,
-            look_ahead_bytes: 0,
+            inner:  nested::Writer::new(inner, coder),
+            total_in: 0,
+            window: SlidingWindow::new(WINDOW_SIZE),
+            matcher: MatchFinder::with_level(WINDOW_SIZE, HASH_BITS, level),
+
+            optimal: level == window::MAX_LEVEL,
+            found: VecDeque::with_capacity(LOOK_AHEAD_BYTES),
+            pending: 0,
+            nodes: Vec::with_capacity(LOOK_AHEAD_BYTES + 1),
 
             out_flags: 0,
             out_count: 0,
@@ -100,77 +182,150 @@ impl<W: Write> Writer<W> {
         Ok(())
     }
 
-    /// Calculate a hash of the next 3 bytes in the look-ahead buffer.
-    /// This hash is used to look up earlier occurences of the data we
-    /// are looking at.  Because hash table entries are overwritten
-    /// blindly, we have to validate whatever we take out of the table
-    /// when calculating the match length.
-    fn hash_at(&self, pos: usize) -> usize {
-        // This might go over the data actually in the window, but as
-        // long as the compressor and decompressor maintain the same
-        // window contents, it should not matter.
-        let h1 = self.window[pos] as usize;
-        let h2 = self.window[mod_window(pos + 1)] as usize;
-        let h3 = self.window[mod_window(pos + 2)] as usize;
-
-        let h = (h1 >> 5) ^ ((h2 << 8) + h3);
+    /// Find the longest match for the look-ahead buffer in the window
+    /// and enter the current position into the match finder.
+    /// Returns the match length and offset.
+    fn find_longest_match(&mut self) -> (usize, usize) {
+        let max_len = ::std::cmp::min(self.window.buffered(), MAX_MATCH_LEN);
+        self.matcher.find(self.window.data(), self.window.position(), max_len, MAX_OFFSET)
+    }
 
-        h % HASHTAB_SIZE
+    /// Enter the `count - 1` positions following the current position
+    /// into the match finder.
+    fn skip_positions(&mut self, count: usize) {
+        for i in 1..count {
+            let max_len = ::std::cmp::min(self.window.buffered() - i, MAX_MATCH_LEN);
+            let pos = self.window.wrap(self.window.position() + i);
+            self.matcher.skip(self.window.data(), pos, max_len, MAX_OFFSET);
+        }
     }
 
-    fn find_longest_match(&self, match_pos: usize, search_pos: usize) -> usize {
-        if self.look_ahead_bytes > MIN_MATCH_LEN && match_pos != search_pos {
-            let mut match_len = 0;
-            for i in 0..::std::cmp::min(self.look_ahead_bytes, MAX_MATCH_LEN) {
-                if self.window[mod_window(match_pos + i)] != self.window[mod_window(search_pos + i)] {
-                    break;
-                }
-                match_len += 1;
-            }
-            match_len
-        } else {
-            0
+    /// Emit `token` for the data at the current position.  Returns
+    /// the number of bytes covered by the token.
+    fn emit_token(&mut self, token: Token) -> io::Result<usize> {
+        match token {
+            Token::Literal => {
+                let lit = self.window.get(0);
+                try!(self.emit_lit(lit));
+                Ok(1)
+            },
+            Token::Match(len, ofs) => {
+                assert!(ofs != 0 && ofs <= MAX_OFFSET);
+                assert!((len - MIN_MATCH_LEN) < 16);
+
+                let m1 = (((len - MIN_MATCH_LEN) as u8) << 4)
+                    | (((ofs >> 8) as u8) & 0x0f);
+                let m2 = (ofs & 0xff) as u8;
+
+                try!(self.emit_match(m1, m2));
+                Ok(len)
+            },
         }
     }
 
     fn process(&mut self) -> io::Result<()> {
-        let search_pos = self.position;
-        
-        let hsh = self.hash_at(search_pos);
-        let match_pos = self.hashtab[hsh];
-        
-        let ofs =
-            if match_pos < self.position {
-                self.position - match_pos
+        let (match_len, ofs) = self.find_longest_match();
+
+        let token =
+            if match_len >= MIN_MATCH_LEN {
+                Token::Match(match_len, ofs)
             } else {
-                self.position + (WINDOW_SIZE - match_pos)
+                Token::Literal
             };
-        
-        let match_len = self.find_longest_match(match_pos, search_pos);
-        
-        if ofs < WINDOW_SIZE - MAX_MATCH_LEN && match_len >= MIN_MATCH_LEN {
-            assert!(ofs != 0);
-            assert!((match_len - MIN_MATCH_LEN) < 16);
-            
-            let m1 = (((match_len - MIN_MATCH_LEN) as u8) << 4)
-                | (((ofs >> 8) as u8) & 0x0f);
-            let m2 = (ofs & 0xff) as u8;
+        let len = try!(self.emit_token(token));
+        self.skip_positions(len);
+        self.window.consume(len);
+        Ok(())
+    }
 
-            try!(self.emit_match(m1, m2));
-            
-            self.position = mod_window(self.position + match_len);
-            self.look_ahead_bytes -= match_len;
-        } else {
-            let lit = self.window[self.position];
-            try!(self.emit_lit(lit));
+    /// Run the match finder for the look-ahead positions up to index
+    /// `end` which have not been searched yet.
+    fn search_ahead(&mut self, end: usize) {
+        while self.found.len() < end {
+            let i = self.found.len();
+            let max_len = ::std::cmp::min(self.window.buffered() - i, MAX_MATCH_LEN);
+            let pos = self.window.wrap(self.window.position() + i);
+            let m = self.matcher.find(self.window.data(), pos, max_len, MAX_OFFSET);
+            self.found.push_back(m);
+        }
+    }
 
-            self.position = mod_window(self.position + 1);
-            self.look_ahead_bytes -= 1;
+    /// Find the cheapest sequence of tokens for the look-ahead
+    /// buffer and emit its beginning.  Unless `last` is set, more
+    /// data will follow, so tokens near the end of the look-ahead
+    /// buffer are not emitted yet.
+    fn process_optimal(&mut self, last: bool) -> io::Result<()> {
+        // Enter the positions covered by matches which extended past
+        // the searched positions last time, with a reduced distance
+        // limit because the window has moved on since.
+        for k in (1..self.pending + 1).rev() {
+            let max_len = ::std::cmp::min(self.window.buffered() + k, MAX_MATCH_LEN);
+            let pos = self.window.wrap(self.window.position() + WINDOW_SIZE - k);
+            self.matcher.skip(self.window.data(), pos, max_len, MAX_OFFSET - k);
         }
-        self.hashtab[hsh] = search_pos;
+        self.pending = 0;
+
+        let n = self.window.buffered();
+        let searchable = if last { n } else { n + 1 - MAX_MATCH_LEN };
+        self.search_ahead(searchable);
+
+        let mut nodes = ::std::mem::replace(&mut self.nodes, Vec::new());
+        nodes.clear();
+        nodes.resize(n + 1, Node {
+            cost: usize::max_value(),
+            from: 0,
+            token: Token::Literal,
+        });
+        nodes[0].cost = 0;
+        for i in 0..n {
+            let cost = nodes[i].cost;
+            relax(&mut nodes, i, i + 1, cost + LITERAL_COST, Token::Literal);
+            if i < self.found.len() {
+                let (match_len, ofs) = self.found[i];
+                let match_len = ::std::cmp::min(match_len, n - i);
+                for len in MIN_MATCH_LEN..match_len + 1 {
+                    relax(&mut nodes, i, i + len, cost + MATCH_COST, Token::Match(len, ofs));
+                }
+            }
+        }
+
+        let mut ends = Vec::new();
+        let mut i = n;
+        while i > 0 {
+            ends.push(i);
+            i = nodes[i].from;
+        }
+        let horizon = if last { n } else { MAX_MATCH_LEN };
+        let mut start = 0;
+        for &end in ends.iter().rev() {
+            if start > 0 && start >= horizon {
+                break;
+            }
+            let len = try!(self.emit_token(nodes[end].token));
+            self.window.consume(len);
+            start = end;
+        }
+        if start <= self.found.len() {
+            self.found.drain(..start);
+        } else {
+            self.pending = start - self.found.len();
+            self.found.clear();
+        }
+        self.nodes = nodes;
         Ok(())
     }
 
+    /// Return the number of uncompressed bytes written so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Return the number of compressed bytes written to the wrapped
+    /// writer so far.
+    pub fn total_out(&self) -> u64 {
+        self.inner.total_out()
+    }
+
     /// Move the wrapped writer out of the LZSS writer.
     pub fn into_inner(self) -> W {
         self.inner.into_inner()
@@ -181,21 +336,24 @@ impl<W: Write> Write for Writer<W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let mut written = 0;
         while written < buf.len() {
-            while written < buf.len() && self.look_ahead_bytes < LOOK_AHEAD_BYTES {
-                self.window[mod_window(self.position + self.look_ahead_bytes)] =
-                    buf[written];
-                self.look_ahead_bytes += 1;
-                written += 1;
-            }
-            if self.look_ahead_bytes == LOOK_AHEAD_BYTES {
-                try!(self.process());
+            written += self.window.fill(&buf[written..], LOOK_AHEAD_BYTES);
+            if self.window.buffered() == LOOK_AHEAD_BYTES {
+                if self.optimal {
+                    try!(self.process_optimal(false));
+                } else {
+                    try!(self.process());
+                }
             }
         }
+        self.total_in += written as u64;
         Ok(written)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        while self.look_ahead_bytes > 0 {
+        if self.optimal {
+            try!(self.process_optimal(true));
+        }
+        while self.window.buffered() > 0 {
             try!(self.process());
         }
         try!(self.emit_flush());
@@ -205,33 +363,67 @@ impl<W: Write> Write for Writer<W> {
 
 /// Reader for LZSS compressed streams.
 pub struct Reader<R> {
-    inner: Bytes<nested::Reader<R>>,
-    window: [u8; WINDOW_SIZE],
-    position: usize,
-    returned: usize,
+    inner: ByteReader<nested::Reader<R>>,
+    window: SlidingWindow,
     eof: bool,
+    recorder: Recorder,
+    total_out: u64,
 }
 
 impl<R: Read> Reader<R> {
-    /// Create a new LZSS reader that wraps another reader.
-    pub fn new(inner: R) -> Reader<R> {
-        Reader {
-            inner: nested::Reader::new(inner).bytes(),
-            window: [0; WINDOW_SIZE],
-            position: 0,
-            returned: 0,
+    /// Create a new LZSS reader that wraps another reader.  This
+    /// reads the header of the nested coder, therefore the result can
+    /// be an error.
+    pub fn new(inner: R) -> io::Result<Reader<R>> {
+        Reader::with_window(inner, vec![0; WINDOW_SIZE])
+    }
+
+    /// Create a reader which decodes into `window` instead of
+    /// allocating its own window.  The length of `window` must be a
+    /// power of two and at least 4096 bytes.  Together with `decode`
+    /// and `into_window`, this lets a caller reuse one buffer for
+    /// many streams.
+    pub fn with_window(inner: R, window: Vec<u8>) -> io::Result<Reader<R>> {
+        assert!(window.len() >= WINDOW_SIZE);
+        Ok(Reader {
+            inner: ByteReader::new(try!(nested::Reader::new(inner))),
+            window: SlidingWindow::from_vec(window),
             eof: false,
-        }
+            recorder: Recorder::new(),
+            total_out: 0,
+        })
+    }
+
+    /// Decode the next piece of the stream and return it.  The data
+    /// is returned directly from the window, without copying it into
+    /// an output buffer, and stays valid until the next call.  An
+    /// empty slice means the end of the stream.
+    pub fn decode(&mut self) -> io::Result<&[u8]> {
+        try!(self.fill());
+        let data = self.window.take_buffered();
+        self.total_out += data.len() as u64;
+        Ok(data)
+    }
+
+    /// Return the window, for reuse with `with_window`.
+    pub fn into_window(self) -> Vec<u8> {
+        self.window.into_vec()
+    }
+
+    /// Return the number of compressed bytes read so far.
+    pub fn total_in(&self) -> u64 {
+        self.inner.get_ref().total_in()
+    }
+
+    /// Return the number of decompressed bytes read so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
     }
 
     /// Copy all decompressed data from the window to the output
     /// buffer.
     fn copy_out(&mut self, output: &mut [u8], written: &mut usize) {
-        while *written < output.len() && self.returned != self.position {
-            output[*written] = self.window[self.returned];
-            *written += 1;
-            self.returned = mod_window(self.returned + 1);
-        }
+        *written += self.window.read(&mut output[*written..]);
     }
 
     /// Process a group of 8 literals or match/length pairs.  The
@@ -254,22 +446,19 @@ impl<R: Read> Reader<R> {
                         let m2 = try!(m2);
                         let len = ((m1 >> 4) as usize) + MIN_MATCH_LEN;
                         let ofs = (((m1 as usize) & 0xf) << 8) | (m2 as usize);
-                        debug_assert!(ofs > 0);
-                        let pos =
-                            if ofs < self.position {
-                                self.position - ofs
-                            } else {
-                                WINDOW_SIZE - (ofs - self.position)
-                            };
-                        for i in 0..len {
-                            self.window[mod_window(self.position + i)] =
-                                self.window[mod_window(pos + i)];
+                        if ofs == 0 {
+                            return Err(Error::corrupt_at(self.total_in(),
+                                                         "match offset is zero").into());
+                        }
+                        if ofs > self.window.filled() {
+                            return Err(Error::corrupt_at(self.total_in(),
+                                                         "match before start of data").into());
                         }
-                        self.position = mod_window(self.position + len);
+                        self.recorder.record(Event::Match { offset: ofs, len: len });
+                        self.window.copy_match(ofs, len);
                     },
                     _ => {
-                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
-                                                  "cannot read match/lit pair"));
+                        return Err(Error::UnexpectedEof.into());
                     },
                 }
             } else {
@@ -278,21 +467,35 @@ impl<R: Read> Reader<R> {
                 // window.
                 if let Some(lit) = self.inner.next() {
                     let lit = try!(lit);
-                    self.window[self.position] = lit;
-                    self.position = mod_window(self.position + 1);
+                    self.recorder.record(Event::Literal(lit));
+                    self.window.push(lit);
                 } else {
                     // EOF here means corrupted input, because the
                     // encoder does not put a 1-bit into the token
                     // when the stream ends.
                     self.eof = true;
-                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
-                                              "cannot read literal"));
+                    return Err(Error::UnexpectedEof.into());
                 }
             }
         }
         Ok(())
     }
 
+    /// Decode groups until there is data in the window or the stream
+    /// has ended.
+    fn fill(&mut self) -> io::Result<()> {
+        while self.window.buffered() == 0 && !self.eof {
+            match self.inner.next() {
+                Some(token) => {
+                    let token = try!(token);
+                    try!(self.process_group(token).map_err(|e| at_offset(e, self.total_in())));
+                },
+                None => self.eof = true,
+            }
+        }
+        Ok(())
+    }
+
     /// Process as much from the underlying input as necessary to fill
     /// the output buffer.  When more data than necessary is
     /// decompressed, it stays in the window for later processing.
@@ -320,11 +523,37 @@ impl<R: Read> Reader<R> {
 
 impl<R: Read> Read for Reader<R> {
     fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
-        if self.eof {
-            Ok(0)
+        let n = if self.eof {
+            // The last group may have produced more data than fit
+            // into the previous output buffer.
+            let mut written = 0;
+            self.copy_out(output, &mut written);
+            written
         } else {
-            self.process(output)
-        }
+            try!(self.process(output).map_err(|e| at_offset(e, self.total_in())))
+        };
+        self.total_out += n as u64;
+        Ok(n)
+    }
+}
+
+/// The decoded data which has not been read yet is returned directly
+/// from the window.
+impl<R: Read> BufRead for Reader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        try!(self.fill());
+        Ok(self.window.buffered_data())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.window.consume(amt);
+        self.total_out += amt as u64;
+    }
+}
+
+impl<R: Read> Inspect for Reader<R> {
+    fn recorder(&mut self) -> &mut Recorder {
+        &mut self.recorder
     }
 }
 
@@ -335,17 +564,43 @@ pub fn compress<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error>
     Ok(cw.into_inner())
 }
 
+/// Compress all data from `input` at compression `level`.
+pub fn compress_with_level<R: Read, W: Write>(mut input: R, output: W,
+                                              level: u32) -> Result<W, Error> {
+    let mut cw = Writer::with_level(output, level);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.into_inner())
+}
+
+/// Compress all data from `input` and code the result with `coder`.
+pub fn compress_with_coder<R: Read, W: Write>(mut input: R, output: W,
+                                              coder: NestedCoder) -> Result<W, Error> {
+    let mut cw = Writer::with_coder(output, coder);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.into_inner())
+}
+
 pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<W, Error> {
-    let mut cr = Reader::new(input);
+    let mut cr = try!(Reader::new(input));
     try!(io::copy(&mut cr, &mut output));
     Ok(output)
 }
 
+/// Return an iterator over the literals and matches of the stream in
+/// `input`.  This reads the header of the nested coder, therefore the
+/// result can be an error.
+pub fn inspect<R: Read>(input: R) -> io::Result<Events<Reader<R>>> {
+    Ok(Events::new(try!(Reader::new(input))))
+}
+
 #[cfg(test)]
 mod tests {
     use ::std::io::Cursor;
 
-    use super::{Writer, Reader};
+    use super::{Writer, Reader, compress_with_coder, compress_with_level, decompress};
+    use nested::NestedCoder;
     use ::std::io::{Read, Write};
 
     fn cmp_test(input: &[u8], expected_output: &[u8]) {
@@ -360,27 +615,27 @@ mod tests {
 
     #[test]
     fn compress_empty() {
-        cmp_test(b"", &[0]);
+        cmp_test(b"", &[0, 0]);
     }
 
     #[test]
     fn compress_a() {
-        cmp_test(b"a", &[192, 12, 40]);
+        cmp_test(b"a", &[0, 192, 12, 40]);
     }
 
     #[test]
     fn compress_aaa() {
-        cmp_test(b"aaaaaaaaa", &[192, 12, 35, 6, 2, 64]);
+        cmp_test(b"aaaaaaaaa", &[0, 192, 12, 35, 6, 2, 64]);
     }
 
     #[test]
     fn compress_abc() {
         cmp_test(b"abcdefgabcdefgabcabcabcdefg",
-                 &[255, 12, 35, 22, 199, 178, 108, 181, 154, 179, 216, 10, 15, 64, 40, 132, 133, 100, 129, 201, 4, 138, 4]);
+                 &[0, 255, 12, 35, 22, 199, 178, 108, 181, 154, 179, 216, 10, 15, 64, 42, 4, 129, 201, 4, 134, 136]);
     }
 
     fn decmp_test(compressed: &[u8], expected_output: &[u8]) {
-        let mut cr = Reader::new(Cursor::new(compressed));
+        let mut cr = Reader::new(Cursor::new(compressed)).unwrap();
 
         let mut decompressed = Vec::new();
         let nread = cr.read_to_end(&mut decompressed).unwrap();
@@ -391,23 +646,23 @@ mod tests {
 
     #[test]
     fn decompress_empty() {
-        decmp_test(&[0], &[]);
+        decmp_test(&[0, 0], &[]);
     }
 
     #[test]
     fn decompress_a() {
-        decmp_test(&[192, 12, 40], b"a");
+        decmp_test(&[0, 192, 12, 40], b"a");
     }
 
     #[test]
     fn decompress_aaa() {
-        decmp_test(&[192, 12, 35, 6, 2, 64], b"aaaaaaaaa");
+        decmp_test(&[0, 192, 12, 35, 6, 2, 64], b"aaaaaaaaa");
     }
 
     #[test]
     fn decompress_abc() {
         decmp_test(
-            &[255, 12, 35, 22, 199, 178, 108, 181, 154, 179, 216, 10, 15, 64, 40, 132, 133, 100, 129, 201, 4, 138, 4],
+            &[0, 255, 12, 35, 22, 199, 178, 108, 181, 154, 179, 216, 10, 15, 64, 42, 4, 129, 201, 4, 134, 136],
             b"abcdefgabcdefgabcabcabcdefg");
     }
 
@@ -415,14 +670,20 @@ mod tests {
         let mut cw = Writer::new(vec![]);
         cw.write_all(&input[..]).unwrap();
         cw.flush().unwrap();
+        let total_out = cw.total_out();
+        assert_eq!(input.len() as u64, cw.total_in());
         let compressed = cw.into_inner();
+        let compressed_len = compressed.len() as u64;
+        assert_eq!(compressed_len, total_out);
 
-        let mut cr = Reader::new(Cursor::new(compressed));
+        let mut cr = Reader::new(Cursor::new(compressed)).unwrap();
         let mut decompressed = Vec::new();
         let nread = cr.read_to_end(&mut decompressed).unwrap();
 
         assert_eq!(input.len(), nread);
         assert_eq!(&input[..], &decompressed[..]);
+        assert_eq!(compressed_len, cr.total_in());
+        assert_eq!(input.len() as u64, cr.total_out());
     }
 
     #[test]
@@ -430,4 +691,113 @@ mod tests {
         let input = include_bytes!("lzss2.rs");
         roundtrip(input);
     }
+
+    #[test]
+    fn nested_coders() {
+        let input = include_bytes!("lzss2.rs");
+        for &coder in &[NestedCoder::BinArith, NestedCoder::Range] {
+            let compressed = compress_with_coder(Cursor::new(&input[..]), vec![], coder).unwrap();
+            let decompressed = decompress(Cursor::new(compressed), vec![]).unwrap();
+            assert_eq!(&input[..], &decompressed[..]);
+        }
+    }
+
+    #[test]
+    fn compress_levels() {
+        let input = include_bytes!("lzss2.rs");
+        let fast = compress_with_level(Cursor::new(&input[..]), vec![], 1).unwrap();
+        let best = compress_with_level(Cursor::new(&input[..]), vec![], ::window::MAX_LEVEL).unwrap();
+        assert!(best.len() < fast.len());
+        let decompressed = decompress(Cursor::new(best), vec![]).unwrap();
+        assert_eq!(&input[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn buf_read() {
+        use ::std::io::BufRead;
+        let input = include_bytes!("lzss2.rs");
+        let mut cw = Writer::new(vec![]);
+        cw.write_all(&input[..]).unwrap();
+        cw.flush().unwrap();
+        let compressed = cw.into_inner();
+
+        let mut cr = Reader::new(&compressed[..]).unwrap();
+        let mut lines = 0;
+        let mut output = Vec::new();
+        while cr.read_until(b'\n', &mut output).unwrap() > 0 {
+            lines += 1;
+        }
+        assert_eq!(input.iter().filter(|&&b| b == b'\n').count(), lines);
+        assert_eq!(&input[..], &output[..]);
+        assert_eq!(input.len() as u64, cr.total_out());
+    }
+
+    #[test]
+    fn decode_with_window() {
+        let input = include_bytes!("lzss2.rs");
+        let mut cw = Writer::new(vec![]);
+        cw.write_all(&input[..]).unwrap();
+        cw.flush().unwrap();
+        let compressed = cw.into_inner();
+
+        // Decode twice with the same window.
+        let mut window = vec![0; 8192];
+        for _ in 0..2 {
+            let mut cr = Reader::with_window(&compressed[..], window).unwrap();
+            let mut output = Vec::new();
+            loop {
+                let data = cr.decode().unwrap();
+                if data.is_empty() {
+                    break;
+                }
+                output.extend_from_slice(data);
+            }
+            assert_eq!(&input[..], &output[..]);
+            window = cr.into_window();
+        }
+        assert_eq!(8192, window.len());
+    }
+
+    #[test]
+    fn fork() {
+        let input = include_bytes!("lzss2.rs");
+        let (prefix, suffix) = input.split_at(input.len() / 2);
+        let mut cw = Writer::new(vec![]);
+        cw.write_all(prefix).unwrap();
+
+        // Finishing a copy leaves the original writer untouched.
+        let mut fork = cw.clone();
+        fork.write_all(b"appended").unwrap();
+        fork.flush().unwrap();
+        let mut expected = Writer::new(vec![]);
+        expected.write_all(prefix).unwrap();
+        expected.write_all(b"appended").unwrap();
+        expected.flush().unwrap();
+        assert_eq!(expected.into_inner(), fork.into_inner());
+
+        cw.write_all(suffix).unwrap();
+        cw.flush().unwrap();
+        let mut cr = Reader::new(Cursor::new(cw.into_inner())).unwrap();
+        let mut output = Vec::new();
+        cr.read_to_end(&mut output).unwrap();
+        assert_eq!(&input[..], &output[..]);
+    }
+
+    #[test]
+    fn match_before_start() {
+        use error::Error;
+        // The first token is a match, but nothing has been decoded yet.
+        let mut cr = Reader::new(Cursor::new(&[0u8, 128, 32, 64][..])).unwrap();
+        let mut output = Vec::new();
+        match Error::from(cr.read_to_end(&mut output).unwrap_err()) {
+            Error::CorruptInput { offset: Some(4), .. } => (),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+    }
 }
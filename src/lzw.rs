@@ -2,14 +2,58 @@
 // top-level directory of this distribution for license information.
 
 //! Simple implementation of an LZW compressor.
+//!
+//! The native format starts with a byte giving the maximum code
+//! width, between 12 and 24 bits, 16 by default.  Codes start out
+//! with 9 bits and grow up to that width.  Larger dictionaries take
+//! more memory but compress big files better.  Once the dictionary
+//! is full, the compression ratio is monitored, and when it drops,
+//! the highest code is sent as a CLEAR code and the dictionary starts
+//! over.
+//!
+//! The decompressor grows its dictionary as codes arrive instead of
+//! sizing it from the header, so that a small stream declaring a
+//! large code width does not allocate a large dictionary.  It needs
+//! at most 5 bytes per code read, in addition to the output.
+//!
+//! Besides the native format, this module reads and writes the `.Z`
+//! format of the Unix `compress(1)` utility, see `compress_z`,
+//! `decompress_z` and `inspect_z`, and the LZW dialects with CLEAR and
+//! END-OF-INFORMATION codes used in GIF, TIFF and PDF, see
+//! `compress_dialect` and `decompress_dialect`.
 
-use std::collections::HashMap;
-use std::io::{Read, Write};
+use alloc::vec::Vec;
+use io::{Read, Write};
+use io;
 use error::Error;
 use bitfile::{BitWriter, BitReader};
+use input::ByteReader;
+use inspect::Event;
 
 const EOF: u64 = 256;
 
+/// Smallest and largest maximum code width of the native format.
+pub const MIN_CODE_BITS: usize = 12;
+pub const MAX_CODE_BITS: usize = 24;
+/// Maximum code width used by `compress`.
+pub const DEFAULT_CODE_BITS: usize = 16;
+
+const Z_MAGIC: [u8; 2] = [0x1f, 0x9d];
+const Z_BLOCK_MODE: u8 = 0x80;
+const Z_BITS_MASK: u8 = 0x1f;
+const Z_INIT_BITS: usize = 9;
+const Z_MIN_BITS: usize = 9;
+const Z_MAX_BITS: usize = 16;
+const Z_CLEAR: u64 = 256;
+
+/// Number of input bytes between checks whether the dictionary should
+/// be cleared because the compression ratio dropped.
+const CHECK_GAP: u64 = 10000;
+
+/// Maximum code width of the GIF/TIFF dialects.
+const DIALECT_MAX_BITS: usize = 12;
+const DIALECT_TABLE_SIZE: u64 = 1 << DIALECT_MAX_BITS;
+
 struct SharedState {
     max_code: u64,
     code_len: usize,
@@ -17,8 +61,7 @@ struct SharedState {
 }
 
 impl SharedState {
-    fn new() -> SharedState {
-        let max_code_len = 16;
+    fn new(max_code_len: usize) -> SharedState {
         let st = SharedState {
             max_code: (1 << max_code_len) - 1,
             code_len: 9,
@@ -26,86 +69,248 @@ impl SharedState {
         };
         st
     }
+
+    /// The highest code is reserved for clearing the dictionary.
+    fn clear_code(&self) -> u64 {
+        self.max_code
+    }
+
+    /// Start over with an empty dictionary.
+    fn reset(&mut self) {
+        self.code_len = 9;
+        self.next_code = 257;
+    }
 }
 
-struct CompressState {
-    dict: HashMap<Vec<u8>, u64>,
+/// Compression ratio monitor, which decides when the dictionary
+/// should be cleared.  Every `CHECK_GAP` input bytes, the ratio so
+/// far is compared to the ratio at the last check.
+struct RatioMonitor {
+    checkpoint: u64,
+    ratio: u64,
 }
 
-impl CompressState {
-    fn new() -> CompressState {
-        let mut st = CompressState {
-            dict: HashMap::new(),
-        };
-        for c in 0..256 {
-            let mut s = Vec::new();
-            s.push(c as u8);
-            st.dict.insert(s, c);
+impl RatioMonitor {
+    fn new() -> RatioMonitor {
+        RatioMonitor {
+            checkpoint: CHECK_GAP,
+            ratio: 0,
+        }
+    }
+
+    /// Return true when the dictionary should be cleared, after
+    /// `in_count` bytes have been compressed into `out_count` bytes.
+    fn degraded(&mut self, in_count: u64, out_count: u64) -> bool {
+        if in_count < self.checkpoint {
+            return false;
+        }
+        self.checkpoint = in_count + CHECK_GAP;
+        let rat = (in_count << 8) / ::core::cmp::max(out_count, 1);
+        if rat > self.ratio {
+            self.ratio = rat;
+            false
+        } else {
+            self.ratio = 0;
+            true
+        }
+    }
+}
+
+/// Dictionary of the compressors.  Each string in the dictionary is
+/// a shorter string plus one byte, so it is identified by the code of
+/// that prefix and the byte.  The table maps these pairs to codes by
+/// open addressing in fixed arrays, so that looking up the next byte
+/// never allocates.  Single bytes are their own codes and are not
+/// stored.
+struct CodeTable {
+    /// Prefix code and byte of each entry, as `prefix << 8 | byte`.
+    keys: Vec<u32>,
+    /// Code of each entry, where 0 marks an empty entry.
+    codes: Vec<u32>,
+    mask: usize,
+}
+
+impl CodeTable {
+    /// Create a table for codes of up to `max_bits` bits.  The table
+    /// has twice as many entries as there are codes, so that probe
+    /// sequences stay short.
+    fn new(max_bits: usize) -> CodeTable {
+        let size = 1 << (max_bits + 1);
+        CodeTable {
+            keys: vec![0; size],
+            codes: vec![0; size],
+            mask: size - 1,
+        }
+    }
+
+    /// Return the index of the entry for `key`, or of the empty entry
+    /// where it would be inserted.
+    fn find(&self, key: u32) -> usize {
+        let mut i = (key.wrapping_mul(2654435761) >> 8) as usize & self.mask;
+        while self.codes[i] != 0 && self.keys[i] != key {
+            i = (i + 1) & self.mask;
+        }
+        i
+    }
+
+    /// Return the code of the string `prefix` followed by `c`.
+    fn get(&self, prefix: u64, c: u8) -> Option<u64> {
+        let i = self.find((prefix as u32) << 8 | c as u32);
+        if self.codes[i] != 0 {
+            Some(self.codes[i] as u64)
+        } else {
+            None
+        }
+    }
+
+    fn insert(&mut self, prefix: u64, c: u8, code: u64) {
+        let key = (prefix as u32) << 8 | c as u32;
+        let i = self.find(key);
+        self.keys[i] = key;
+        self.codes[i] = code as u32;
+    }
+
+    fn clear(&mut self) {
+        for code in self.codes.iter_mut() {
+            *code = 0;
         }
-        st
     }
 }
 
+/// Dictionary of the native decoder.  Like the compressor's, it
+/// stores each string as the code of its prefix and its last byte.
+/// The tables grow as entries are added, up to the maximum code
+/// width.
 struct DecompressState {
-    dict: HashMap<u64, Vec<u8>>,
+    prefix: Vec<u32>,
+    suffix: Vec<u8>,
+    /// Code of the previous string, or `None` after a reset.
+    previous: Option<u64>,
 }
 
 impl DecompressState {
     fn new() -> DecompressState {
         let mut st = DecompressState {
-            dict: HashMap::new(),
+            prefix: vec![0; EOF as usize + 1],
+            suffix: vec![0; EOF as usize + 1],
+            previous: None,
         };
         for c in 0..256 {
-            let mut s = Vec::new();
-            s.push(c as u8);
-            st.dict.insert(c, s);
+            st.suffix[c] = c as u8;
         }
         st
     }
-}
 
-pub fn compress<R, W>(mut input: R, output: W) -> Result<W, Error>
-    where R: Read, W: Write {
-    let mut state = SharedState::new();
-    let mut cstate = CompressState::new();
-    
-    let mut current_string: Vec<u8> = Vec::new();
+    /// Remove all strings but the single bytes from the dictionary.
+    fn reset(&mut self) {
+        self.previous = None;
+    }
 
-    let mut out = BitWriter::new(output);
-    let mut buf = [0u8; 1];
-    
-    let mut nread = try!(input.read(&mut buf));
-    while nread == 1 {
-        let c = buf[0];
-
-        current_string.push(c);
-        if let None = cstate.dict.get(&current_string) {
-            if state.next_code <= state.max_code {
-                cstate.dict.insert(current_string.clone(), state.next_code);
-                state.next_code += 1;
-            }
-            let _ = current_string.pop();
-            if let Some(code) = cstate.dict.get(&current_string) {
-                try!(out.write_bits(*code, state.code_len));
+    /// Append the string for `code`, which must be in the dictionary,
+    /// to `out`.
+    fn expand(&self, mut code: u64, out: &mut Vec<u8>) {
+        let start = out.len();
+        while code >= 256 {
+            out.push(self.suffix[code as usize]);
+            code = self.prefix[code as usize] as u64;
+        }
+        out.push(code as u8);
+        out[start..].reverse();
+    }
+
+    /// Store the string for `code`, which follows the previous
+    /// string, in `string`, and add the new dictionary entry.  `code`
+    /// may be the code of the entry being added, otherwise it must
+    /// already be in the dictionary.
+    fn decode(&mut self, state: &mut SharedState, code: u64, string: &mut Vec<u8>)
+              -> io::Result<()> {
+        let can_add = self.previous.is_some() && state.next_code < state.clear_code();
+        string.clear();
+        if code < 256 || (code > EOF && code < state.next_code) {
+            self.expand(code, string);
+        } else if code == state.next_code && can_add {
+            self.expand(self.previous.unwrap(), string);
+            let first = string[0];
+            string.push(first);
+        } else {
+            return Err(Error::corrupt("LZW code not in dictionary").into());
+        }
+        if can_add {
+            // Entries are added in code order, so a new code is at
+            // most one past the end of the tables.
+            let next = state.next_code as usize;
+            let prefix = self.previous.unwrap() as u32;
+            if next < self.prefix.len() {
+                self.prefix[next] = prefix;
+                self.suffix[next] = string[0];
             } else {
-                unreachable!();
-            }
-            current_string.truncate(0);
-            current_string.push(c);
-            if state.next_code < state.max_code && state.next_code >= (1 << state.code_len) {
-                state.code_len += 1;
+                self.prefix.push(prefix);
+                self.suffix.push(string[0]);
             }
+            state.next_code += 1;
         }
-            
-        nread = try!(input.read(&mut buf));
+        self.previous = Some(code);
+        Ok(())
     }
-    
-    if current_string.len() > 0 {
-        if let Some(code) = cstate.dict.get(&current_string) {
-            try!(out.write_bits(*code, state.code_len));
-        } else {
-            unreachable!();
+}
+
+pub fn compress<R, W>(input: R, output: W) -> Result<W, Error>
+    where R: Read, W: Write {
+    compress_with_bits(input, output, DEFAULT_CODE_BITS)
+}
+
+/// Compress `input` using codes of at most `max_bits` bits, where
+/// `max_bits` is between `MIN_CODE_BITS` and `MAX_CODE_BITS`.
+pub fn compress_with_bits<R, W>(input: R, mut output: W, max_bits: usize) -> Result<W, Error>
+    where R: Read, W: Write {
+    assert!(max_bits >= MIN_CODE_BITS && max_bits <= MAX_CODE_BITS);
+    try!(output.write_all(&[max_bits as u8]));
+
+    let mut state = SharedState::new(max_bits);
+    let mut dict = CodeTable::new(max_bits);
+
+    let mut out = BitWriter::new(output);
+    let mut monitor = RatioMonitor::new();
+    let mut in_count = 0u64;
+    // Code of the longest dictionary string matching the input so
+    // far.
+    let mut ent: Option<u64> = None;
+
+    for b in input.bytes() {
+        let c = try!(b);
+        in_count += 1;
+        let e = match ent {
+            None => {
+                ent = Some(c as u64);
+                continue;
+            },
+            Some(e) => e,
+        };
+        if let Some(code) = dict.get(e, c) {
+            ent = Some(code);
+            continue;
+        }
+
+        let full = state.next_code >= state.clear_code();
+        if !full {
+            dict.insert(e, c, state.next_code);
+            state.next_code += 1;
+        }
+        try!(out.write_bits(e, state.code_len));
+        ent = Some(c as u64);
+        if state.next_code < state.max_code && state.next_code >= (1 << state.code_len) {
+            state.code_len += 1;
         }
+
+        if full && monitor.degraded(in_count, out.bytes_written()) {
+            try!(out.write_bits(state.clear_code(), state.code_len));
+            dict.clear();
+            state.reset();
+        }
+    }
+
+    if let Some(e) = ent {
+        try!(out.write_bits(e, state.code_len));
     }
 
     try!(out.write_bits(EOF, state.code_len));
@@ -113,46 +318,38 @@ pub fn compress<R, W>(mut input: R, output: W) -> Result<W, Error>
     Ok(out.to_inner())
 }
 
-pub fn decompress<R, W>(input: R, mut output: W) -> Result<W, Error>
+/// Read the header of the native format and return the maximum code
+/// width.
+fn read_header<R: Read>(input: &mut R) -> io::Result<usize> {
+    let mut hdr = [0u8; 1];
+    try!(input.read_exact(&mut hdr));
+    let max_bits = hdr[0] as usize;
+    if max_bits < MIN_CODE_BITS || max_bits > MAX_CODE_BITS {
+        return Err(Error::LimitExceeded("unsupported LZW code size").into());
+    }
+    Ok(max_bits)
+}
+
+pub fn decompress<R, W>(mut input: R, mut output: W) -> Result<W, Error>
     where R: Read, W: Write {
-    let mut state = SharedState::new();
+    let max_bits = try!(read_header(&mut input));
+    let mut state = SharedState::new(max_bits);
     let mut dstate = DecompressState::new();
-    
-    // let max_code_len = 16;
-    // let max_code = (1 << max_code_len) - 1;
-    // let mut code_len = 9;
-    // let mut next_code = 257;
-    // let mut dict: HashMap<u64, Vec<u8>> = HashMap::new();
-    // for c in 0..256 {
-    //     let mut s = Vec::new();
-    //     s.push(c as u8);
-    //     dict.insert(c, s);
-    // }
-
-    let mut previous_string: Vec<u8> = Vec::new();
+
+    let mut string: Vec<u8> = Vec::new();
 
     let mut inp = BitReader::new(input);
 
     let mut code = try!(inp.read_bits(state.code_len));
     while code != EOF {
-        if let None = dstate.dict.get(&code) {
-            let mut s = Vec::new();
-            s.extend_from_slice(&previous_string[..]);
-            s.extend_from_slice(&previous_string[0..1]);
-            dstate.dict.insert(code, s);
+        if code == state.clear_code() {
+            dstate.reset();
+            state.reset();
+            code = try!(inp.read_bits(state.code_len));
+            continue;
         }
-
-        let str_code = dstate.dict.get(&code).unwrap().clone();
-        let _ = try!(output.write(&str_code[..]));
-        
-        if previous_string.len() > 0 && state.next_code <= state.max_code {
-            let mut ns = Vec::new();
-            ns.extend_from_slice(&previous_string[..]);
-            ns.extend_from_slice(&str_code[0..1]);
-            dstate.dict.insert(state.next_code, ns);
-            state.next_code += 1;
-        }
-        previous_string = str_code;
+        try!(dstate.decode(&mut state, code, &mut string));
+        try!(output.write_all(&string[..]));
 
         if state.next_code < state.max_code && state.next_code + 1 >= (1 << state.code_len) {
             state.code_len += 1;
@@ -164,61 +361,716 @@ pub fn decompress<R, W>(input: R, mut output: W) -> Result<W, Error>
     Ok(output)
 }
 
-pub fn inspect<R>(input: R) -> Result<(), Error>
+/// Iterator over the codes of a native LZW stream, see `inspect`.
+pub struct Inspector<R> {
+    state: SharedState,
+    dstate: DecompressState,
+    string: Vec<u8>,
+    inp: BitReader<R>,
+    header: Option<Event>,
+    done: bool,
+}
+
+/// Return an iterator over the header, the codes and the CLEAR codes
+/// of the native LZW stream in `input`.  Each code comes with the
+/// string it stands for.
+pub fn inspect<R>(mut input: R) -> io::Result<Inspector<R>>
     where R: Read {
+    let max_bits = try!(read_header(&mut input));
+    Ok(Inspector {
+        state: SharedState::new(max_bits),
+        dstate: DecompressState::new(),
+        string: Vec::new(),
+        inp: BitReader::new(input),
+        header: Some(Event::Header(format!("max code width {} bits", max_bits))),
+        done: false,
+    })
+}
 
-    let mut state = SharedState::new();
-    let mut dstate = DecompressState::new();
-    
-    let mut previous_string: Vec<u8> = Vec::new();
+impl<R: Read> Inspector<R> {
+    fn next_event(&mut self) -> io::Result<Event> {
+        let state = &mut self.state;
+        let dstate = &mut self.dstate;
+        let code = try!(self.inp.read_bits(state.code_len));
+        if code == EOF {
+            self.done = true;
+            return Ok(Event::End);
+        }
+        if code == state.clear_code() {
+            dstate.reset();
+            state.reset();
+            return Ok(Event::Clear);
+        }
+        try!(dstate.decode(state, code, &mut self.string));
 
-    let mut inp = BitReader::new(input);
+        if state.next_code < state.max_code && state.next_code + 1 >= (1 << state.code_len) {
+            state.code_len += 1;
+        }
+        Ok(Event::Code { code: code, string: self.string.clone() })
+    }
+}
 
-    let mut code = try!(inp.read_bits(state.code_len));
-    while code != EOF {
-        if let None = dstate.dict.get(&code) {
-            let mut s = Vec::new();
-            s.extend_from_slice(&previous_string[..]);
-            s.extend_from_slice(&previous_string[0..1]);
-            dstate.dict.insert(code, s);
-        }
-
-        let str_code = dstate.dict.get(&code).unwrap().clone();
-        let as_string =
-            match String::from_utf8(str_code.clone()) {
-                Ok(s) => s,
-                Err(_) => "<binary>".to_string(),
+impl<R: Read> Iterator for Inspector<R> {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<io::Result<Event>> {
+        if let Some(header) = self.header.take() {
+            return Some(Ok(header));
+        }
+        if self.done {
+            return None;
+        }
+        let event = self.next_event();
+        if event.is_err() {
+            self.done = true;
+        }
+        Some(event)
+    }
+}
+
+/// Order in which the bits of codes are packed into bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOrder {
+    /// Least significant bit first, as in `.Z` and GIF files.
+    Lsb,
+    /// Most significant bit first, as in TIFF and PDF.
+    Msb,
+}
+
+/// Variable-width code writer.  `compress(1)` emits codes in groups
+/// of eight, and whenever the code width changes the rest of the
+/// current group is padded, so the number of codes in the current
+/// group is tracked as well.
+struct CodeWriter<W> {
+    inner: W,
+    order: BitOrder,
+    acc: u64,
+    bits: usize,
+    count: usize,
+    bytes_out: u64,
+}
+
+impl<W: Write> CodeWriter<W> {
+    fn new(inner: W, order: BitOrder) -> CodeWriter<W> {
+        CodeWriter {
+            inner: inner,
+            order: order,
+            acc: 0,
+            bits: 0,
+            count: 0,
+            bytes_out: 0,
+        }
+    }
+
+    fn write_code(&mut self, code: u64, len: usize) -> io::Result<()> {
+        match self.order {
+            BitOrder::Lsb => self.acc |= code << self.bits,
+            BitOrder::Msb => self.acc = (self.acc << len) | code,
+        }
+        self.bits += len;
+        self.count += 1;
+        while self.bits >= 8 {
+            let b = match self.order {
+                BitOrder::Lsb => {
+                    let b = self.acc as u8;
+                    self.acc >>= 8;
+                    b
+                },
+                BitOrder::Msb => (self.acc >> (self.bits - 8)) as u8,
             };
-        println!("{:4} {:?}", code, as_string);
-        
-        if previous_string.len() > 0 && state.next_code <= state.max_code {
-            let mut ns = Vec::new();
-            ns.extend_from_slice(&previous_string[..]);
-            ns.extend_from_slice(&str_code[0..1]);
-            dstate.dict.insert(state.next_code, ns);
-            state.next_code += 1;
+            try!(self.inner.write_all(&[b]));
+            self.bits -= 8;
+            self.bytes_out += 1;
+        }
+        if self.order == BitOrder::Msb {
+            self.acc &= (1 << self.bits) - 1;
         }
-        previous_string = str_code;
+        Ok(())
+    }
 
-        if state.next_code < state.max_code && state.next_code + 1 >= (1 << state.code_len) {
-            state.code_len += 1;
+    fn pad_group(&mut self, len: usize) -> io::Result<()> {
+        while self.count % 8 != 0 {
+            try!(self.write_code(0, len));
         }
-        code = try!(inp.read_bits(state.code_len));
+        self.count = 0;
+        Ok(())
+    }
 
+    fn flush(&mut self) -> io::Result<()> {
+        if self.bits > 0 {
+            let b = match self.order {
+                BitOrder::Lsb => self.acc as u8,
+                BitOrder::Msb => (self.acc << (8 - self.bits)) as u8,
+            };
+            try!(self.inner.write_all(&[b]));
+            self.acc = 0;
+            self.bits = 0;
+            self.bytes_out += 1;
+        }
+        self.inner.flush()
+    }
+
+    fn to_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Variable-width code reader, see `CodeWriter`.
+struct CodeReader<R> {
+    inner: ByteReader<R>,
+    order: BitOrder,
+    acc: u64,
+    bits: usize,
+    count: usize,
+}
+
+impl<R: Read> CodeReader<R> {
+    fn new(inner: R, order: BitOrder) -> CodeReader<R> {
+        CodeReader {
+            inner: ByteReader::new(inner),
+            order: order,
+            acc: 0,
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    /// Read a code of `len` bits.  Returns `None` at the end of the
+    /// input; trailing bits which do not form a complete code are
+    /// ignored.
+    fn read_code(&mut self, len: usize) -> io::Result<Option<u64>> {
+        while self.bits < len {
+            let b = match self.inner.next() {
+                Some(b) => try!(b) as u64,
+                None => return Ok(None),
+            };
+            match self.order {
+                BitOrder::Lsb => self.acc |= b << self.bits,
+                BitOrder::Msb => self.acc = (self.acc << 8) | b,
+            }
+            self.bits += 8;
+        }
+        let code = match self.order {
+            BitOrder::Lsb => {
+                let code = self.acc & ((1 << len) - 1);
+                self.acc >>= len;
+                code
+            },
+            BitOrder::Msb => (self.acc >> (self.bits - len)) & ((1 << len) - 1),
+        };
+        self.bits -= len;
+        if self.order == BitOrder::Msb {
+            self.acc &= (1 << self.bits) - 1;
+        }
+        self.count += 1;
+        Ok(Some(code))
+    }
+
+    fn skip_group(&mut self, len: usize) -> io::Result<()> {
+        while self.count % 8 != 0 {
+            if try!(self.read_code(len)).is_none() {
+                break;
+            }
+        }
+        self.count = 0;
+        Ok(())
+    }
+}
+
+fn z_max_code(n_bits: usize, max_bits: usize) -> u64 {
+    if n_bits == max_bits {
+        1 << max_bits
+    } else {
+        (1 << n_bits) - 1
+    }
+}
+
+/// Compress `input` into the `.Z` format of `compress(1)` using up
+/// to 16-bit codes.
+pub fn compress_z<R, W>(input: R, output: W) -> Result<W, Error>
+    where R: Read, W: Write {
+    compress_z_with_bits(input, output, Z_MAX_BITS)
+}
+
+/// Compress `input` into the `.Z` format of `compress(1)` using codes
+/// of at most `max_bits` bits, where `max_bits` is between 9 and 16.
+/// The dictionary is cleared with a CLEAR code when it is full and
+/// the compression ratio starts to drop.
+pub fn compress_z_with_bits<R, W>(input: R, mut output: W, max_bits: usize) -> Result<W, Error>
+    where R: Read, W: Write {
+    assert!(max_bits >= Z_MIN_BITS && max_bits <= Z_MAX_BITS);
+    try!(output.write_all(&[Z_MAGIC[0], Z_MAGIC[1], max_bits as u8 | Z_BLOCK_MODE]));
+
+    let max_max_code = 1u64 << max_bits;
+    let mut dict = CodeTable::new(max_bits);
+    let mut n_bits = Z_INIT_BITS;
+    let mut max_code = z_max_code(n_bits, max_bits);
+    let mut free_ent = Z_CLEAR + 1;
+    let mut in_count = 0u64;
+    let mut monitor = RatioMonitor::new();
+
+    let mut out = CodeWriter::new(output, BitOrder::Lsb);
+    let mut ent: Option<u64> = None;
+
+    for b in input.bytes() {
+        let c = try!(b);
+        in_count += 1;
+        let e = match ent {
+            None => {
+                ent = Some(c as u64);
+                continue;
+            },
+            Some(e) => e,
+        };
+        if let Some(code) = dict.get(e, c) {
+            ent = Some(code);
+            continue;
+        }
+
+        try!(out.write_code(e, n_bits));
+        if free_ent > max_code {
+            try!(out.pad_group(n_bits));
+            n_bits += 1;
+            max_code = z_max_code(n_bits, max_bits);
+        }
+        ent = Some(c as u64);
+
+        if free_ent < max_max_code {
+            dict.insert(e, c, free_ent);
+            free_ent += 1;
+        } else if monitor.degraded(in_count, out.bytes_out) {
+            dict.clear();
+            free_ent = Z_CLEAR + 1;
+            try!(out.write_code(Z_CLEAR, n_bits));
+            try!(out.pad_group(n_bits));
+            n_bits = Z_INIT_BITS;
+            max_code = z_max_code(n_bits, max_bits);
+        }
+    }
+
+    if let Some(e) = ent {
+        try!(out.write_code(e, n_bits));
+    }
+    try!(out.flush());
+    Ok(out.to_inner())
+}
+
+/// Step of a `.Z` decoder.
+enum ZStep {
+    /// A code, whose string was decoded.
+    Code(u64),
+    /// A CLEAR code.
+    Clear,
+    /// The end of the input.
+    End,
+}
+
+/// Decoder for the codes of a `.Z` file, shared by `decompress_z`
+/// and `inspect_z`.
+struct ZDecoder<R> {
+    inp: CodeReader<R>,
+    max_bits: usize,
+    block_mode: bool,
+    prefix: Vec<u16>,
+    suffix: Vec<u8>,
+    n_bits: usize,
+    max_code: u64,
+    free_ent: u64,
+    old_code: Option<u64>,
+    fin_char: u8,
+}
+
+impl<R: Read> ZDecoder<R> {
+    /// Read the header of a `.Z` file and create a decoder for the
+    /// codes following it.
+    fn new(mut input: R) -> io::Result<ZDecoder<R>> {
+        let mut hdr = [0u8; 3];
+        try!(input.read_exact(&mut hdr));
+        if hdr[0] != Z_MAGIC[0] || hdr[1] != Z_MAGIC[1] {
+            return Err(Error::InvalidHeader("not a .Z file").into());
+        }
+        let max_bits = (hdr[2] & Z_BITS_MASK) as usize;
+        let block_mode = hdr[2] & Z_BLOCK_MODE != 0;
+        if max_bits < Z_MIN_BITS || max_bits > Z_MAX_BITS {
+            return Err(Error::LimitExceeded("unsupported .Z code size").into());
+        }
+
+        let max_max_code = 1usize << max_bits;
+        let mut suffix = vec![0u8; max_max_code];
+        for c in 0..256 {
+            suffix[c] = c as u8;
+        }
+        Ok(ZDecoder {
+            inp: CodeReader::new(input, BitOrder::Lsb),
+            max_bits: max_bits,
+            block_mode: block_mode,
+            prefix: vec![0u16; max_max_code],
+            suffix: suffix,
+            n_bits: Z_INIT_BITS,
+            max_code: z_max_code(Z_INIT_BITS, max_bits),
+            free_ent: if block_mode { Z_CLEAR + 1 } else { Z_CLEAR },
+            old_code: None,
+            fin_char: 0,
+        })
+    }
+
+    /// Read the next code.  For codes which are not CLEAR codes, the
+    /// string the code stands for is left in `string`.
+    fn next(&mut self, string: &mut Vec<u8>) -> io::Result<ZStep> {
+        if self.free_ent > self.max_code {
+            try!(self.inp.skip_group(self.n_bits));
+            self.n_bits += 1;
+            self.max_code = z_max_code(self.n_bits, self.max_bits);
+        }
+        let code = match try!(self.inp.read_code(self.n_bits)) {
+            Some(code) => code,
+            None => return Ok(ZStep::End),
+        };
+        let old = match self.old_code {
+            None => {
+                if code >= 256 {
+                    return Err(Error::corrupt("corrupt .Z input").into());
+                }
+                self.fin_char = code as u8;
+                string.clear();
+                string.push(self.fin_char);
+                self.old_code = Some(code);
+                return Ok(ZStep::Code(code));
+            },
+            Some(old) => old,
+        };
+        if code == Z_CLEAR && self.block_mode {
+            // The next code does not create a usable entry, so the
+            // dictionary restarts one below the first free code.
+            self.free_ent = Z_CLEAR;
+            try!(self.inp.skip_group(self.n_bits));
+            self.n_bits = Z_INIT_BITS;
+            self.max_code = z_max_code(self.n_bits, self.max_bits);
+            return Ok(ZStep::Clear);
+        }
+
+        string.clear();
+        let mut c = code;
+        if c >= self.free_ent {
+            if c > self.free_ent {
+                return Err(Error::corrupt("corrupt .Z input").into());
+            }
+            string.push(self.fin_char);
+            c = old;
+        }
+        while c >= 256 {
+            string.push(self.suffix[c as usize]);
+            c = self.prefix[c as usize] as u64;
+        }
+        self.fin_char = c as u8;
+        string.push(self.fin_char);
+        string.reverse();
+
+        if self.free_ent < 1 << self.max_bits {
+            self.prefix[self.free_ent as usize] = old as u16;
+            self.suffix[self.free_ent as usize] = self.fin_char;
+            self.free_ent += 1;
+        }
+        self.old_code = Some(code);
+        Ok(ZStep::Code(code))
+    }
+}
+
+/// Decompress a `.Z` file as written by `compress(1)`.  Both block
+/// mode and the old format without CLEAR codes are accepted.
+pub fn decompress_z<R, W>(input: R, mut output: W) -> Result<W, Error>
+    where R: Read, W: Write {
+    let mut dec = try!(ZDecoder::new(input));
+    let mut string = Vec::new();
+    loop {
+        match try!(dec.next(&mut string)) {
+            ZStep::Code(_) => try!(output.write_all(&string)),
+            ZStep::Clear => (),
+            ZStep::End => break,
+        }
+    }
+    Ok(output)
+}
+
+/// Iterator over the codes of a `.Z` file, see `inspect_z`.
+pub struct ZInspector<R> {
+    dec: ZDecoder<R>,
+    string: Vec<u8>,
+    header: Option<Event>,
+    done: bool,
+}
+
+/// Return an iterator over the header, the codes and the CLEAR codes
+/// of the `.Z` file in `input`.  Each code comes with the string it
+/// stands for.
+pub fn inspect_z<R>(input: R) -> io::Result<ZInspector<R>>
+    where R: Read {
+    let dec = try!(ZDecoder::new(input));
+    let header = Event::Header(format!("max code width {} bits{}", dec.max_bits,
+                                       if dec.block_mode { ", block mode" } else { "" }));
+    Ok(ZInspector {
+        dec: dec,
+        string: Vec::new(),
+        header: Some(header),
+        done: false,
+    })
+}
+
+impl<R: Read> Iterator for ZInspector<R> {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<io::Result<Event>> {
+        if let Some(header) = self.header.take() {
+            return Some(Ok(header));
+        }
+        if self.done {
+            return None;
+        }
+        let event = match self.dec.next(&mut self.string) {
+            Ok(ZStep::Code(code)) => Ok(Event::Code { code: code, string: self.string.clone() }),
+            Ok(ZStep::Clear) => Ok(Event::Clear),
+            Ok(ZStep::End) => {
+                self.done = true;
+                Ok(Event::End)
+            },
+            Err(e) => {
+                self.done = true;
+                Err(e)
+            },
+        };
+        Some(event)
+    }
+}
+
+/// Parameters of the LZW dialects found in GIF, TIFF and PDF.  Codes
+/// start out one bit wider than `min_code_size` and grow up to 12
+/// bits.  The two codes following the literals are CLEAR and
+/// END-OF-INFORMATION.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dialect {
+    /// Number of bits of the literal symbols, between 2 and 8.
+    pub min_code_size: usize,
+    /// Bit order of the packed codes.
+    pub bit_order: BitOrder,
+    /// When set, the code width is increased one code earlier than
+    /// necessary, as done by TIFF and PDF (`EarlyChange` 1).
+    pub early_change: bool,
+}
+
+impl Dialect {
+    /// The dialect used for GIF image data with the given minimum
+    /// code size.
+    pub fn gif(min_code_size: usize) -> Dialect {
+        Dialect {
+            min_code_size: min_code_size,
+            bit_order: BitOrder::Lsb,
+            early_change: false,
+        }
+    }
+
+    /// The dialect used by TIFF and by the PDF `LZWDecode` filter.
+    pub fn tiff() -> Dialect {
+        Dialect {
+            min_code_size: 8,
+            bit_order: BitOrder::Msb,
+            early_change: true,
+        }
+    }
+
+    fn clear_code(&self) -> u64 {
+        1 << self.min_code_size
+    }
+
+    fn first_code(&self) -> u64 {
+        self.clear_code() + 2
+    }
+
+    /// Code width to use for the next code, given the next free code
+    /// as seen by the decoder.
+    fn code_width(&self, width: usize, next_code: u64) -> usize {
+        let early = if self.early_change { 1 } else { 0 };
+        if width < DIALECT_MAX_BITS && next_code + early >= 1 << width {
+            width + 1
+        } else {
+            width
+        }
+    }
+}
+
+fn check_dialect(dialect: &Dialect) -> io::Result<()> {
+    if dialect.min_code_size < 2 || dialect.min_code_size > 8 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "LZW minimum code size must be between 2 and 8"));
     }
-    
     Ok(())
 }
 
-#[cfg(test)]
+/// Compress `input` using the given LZW dialect.  The output starts
+/// with a CLEAR code and ends with END-OF-INFORMATION; the dictionary
+/// is cleared whenever it is full.
+pub fn compress_dialect<R, W>(input: R, output: W, dialect: Dialect) -> Result<W, Error>
+    where R: Read, W: Write {
+    try!(check_dialect(&dialect));
+    let clear = dialect.clear_code();
+    let eoi = clear + 1;
+    let init_width = dialect.min_code_size + 1;
+    // TIFF encoders clear the table two codes early, so that decoders
+    // which increase the code width early never see 13-bit codes.
+    let table_limit = if dialect.early_change {
+        DIALECT_TABLE_SIZE - 2
+    } else {
+        DIALECT_TABLE_SIZE
+    };
+
+    let mut out = CodeWriter::new(output, dialect.bit_order);
+    let mut dict = CodeTable::new(DIALECT_MAX_BITS);
+    let mut width = init_width;
+    let mut next_code = dialect.first_code();
+    // Next free code of the decoder, which lags one code behind.
+    let mut dec_next = next_code;
+    let mut dec_primed = false;
+    let mut ent: Option<u64> = None;
+
+    try!(out.write_code(clear, width));
+
+    for b in input.bytes() {
+        let c = try!(b);
+        if c as u64 >= clear {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "symbol exceeds LZW minimum code size").into());
+        }
+        let e = match ent {
+            None => {
+                ent = Some(c as u64);
+                continue;
+            },
+            Some(e) => e,
+        };
+        if let Some(code) = dict.get(e, c) {
+            ent = Some(code);
+            continue;
+        }
+
+        try!(out.write_code(e, width));
+        if dec_primed && dec_next < DIALECT_TABLE_SIZE {
+            dec_next += 1;
+        }
+        dec_primed = true;
+        width = dialect.code_width(width, dec_next);
+        ent = Some(c as u64);
+
+        dict.insert(e, c, next_code);
+        next_code += 1;
+        if next_code >= table_limit {
+            try!(out.write_code(clear, width));
+            dict.clear();
+            width = init_width;
+            next_code = dialect.first_code();
+            dec_next = next_code;
+            dec_primed = false;
+        }
+    }
+
+    if let Some(e) = ent {
+        try!(out.write_code(e, width));
+        if dec_primed && dec_next < DIALECT_TABLE_SIZE {
+            dec_next += 1;
+        }
+        width = dialect.code_width(width, dec_next);
+    }
+    try!(out.write_code(eoi, width));
+    try!(out.flush());
+    Ok(out.to_inner())
+}
+
+/// Decompress `input` using the given LZW dialect.  Decoding stops at
+/// the END-OF-INFORMATION code or at the end of the input, whichever
+/// comes first.
+pub fn decompress_dialect<R, W>(input: R, mut output: W, dialect: Dialect) -> Result<W, Error>
+    where R: Read, W: Write {
+    try!(check_dialect(&dialect));
+    let clear = dialect.clear_code();
+    let eoi = clear + 1;
+    let init_width = dialect.min_code_size + 1;
+
+    let mut prefix = vec![0u16; DIALECT_TABLE_SIZE as usize];
+    let mut suffix = vec![0u8; DIALECT_TABLE_SIZE as usize];
+    for c in 0..clear {
+        suffix[c as usize] = c as u8;
+    }
+    let mut inp = CodeReader::new(input, dialect.bit_order);
+    let mut width = init_width;
+    let mut next_code = dialect.first_code();
+    let mut old_code: Option<u64> = None;
+    let mut fin_char = 0u8;
+    let mut stack = Vec::new();
+
+    while let Some(code) = try!(inp.read_code(width)) {
+        if code == clear {
+            width = init_width;
+            next_code = dialect.first_code();
+            old_code = None;
+            continue;
+        }
+        if code == eoi {
+            break;
+        }
+        let old = match old_code {
+            None => {
+                if code >= clear {
+                    return Err(Error::corrupt("corrupt LZW input").into());
+                }
+                fin_char = code as u8;
+                try!(output.write_all(&[fin_char]));
+                old_code = Some(code);
+                continue;
+            },
+            Some(old) => old,
+        };
+
+        stack.clear();
+        let mut c = code;
+        if c >= next_code {
+            if c > next_code {
+                return Err(Error::corrupt("corrupt LZW input").into());
+            }
+            stack.push(fin_char);
+            c = old;
+        }
+        while c >= clear {
+            stack.push(suffix[c as usize]);
+            c = prefix[c as usize] as u64;
+        }
+        fin_char = c as u8;
+        stack.push(fin_char);
+        stack.reverse();
+        try!(output.write_all(&stack));
+
+        if next_code < DIALECT_TABLE_SIZE {
+            prefix[next_code as usize] = old as u16;
+            suffix[next_code as usize] = fin_char;
+            next_code += 1;
+        }
+        width = dialect.code_width(width, next_code);
+        old_code = Some(code);
+    }
+
+    Ok(output)
+}
+
+#[cfg(all(test, feature = "std"))]
 mod test {
     use ::std::io::Cursor;
-    use super::{compress, decompress};
+    use super::{compress, decompress, compress_with_bits, compress_z, compress_z_with_bits,
+                decompress_z};
+    use super::{compress_dialect, decompress_dialect, Dialect};
+    use super::{DEFAULT_CODE_BITS, MIN_CODE_BITS, MAX_CODE_BITS, EOF};
+    use super::DecompressState;
 
     #[test]
     fn compress_empty() {
         let input = b"";
-        let expected = [128, 0];
+        let expected = [16, 128, 0];
         let compressed = compress(Cursor::new(&input[..]), vec![]).unwrap();
         assert_eq!(&expected[..], &compressed[..]);
     }
@@ -226,7 +1078,7 @@ mod test {
     #[test]
     fn compress_a() {
         let input = b"A";
-        let expected = [32, 192, 0];
+        let expected = [16, 32, 192, 0];
         let compressed = compress(Cursor::new(&input[..]), vec![]).unwrap();
         assert_eq!(&expected[..], &compressed[..]);
     }
@@ -234,14 +1086,14 @@ mod test {
     #[test]
     fn compress_aaa() {
         let input = b"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
-        let expected = [32, 192, 96, 80, 56, 36, 22, 13, 7, 130, 192, 0];
+        let expected = [16, 32, 192, 96, 80, 56, 36, 22, 13, 7, 130, 192, 0];
         let compressed = compress(Cursor::new(&input[..]), vec![]).unwrap();
         assert_eq!(&expected[..], &compressed[..]);
     }
 
     #[test]
     fn decompress_empty() {
-        let input = [128, 0];
+        let input = [16, 128, 0];
         let expected = b"";
         let decompressed = decompress(Cursor::new(&input[..]), vec![]).unwrap();
         assert_eq!(&expected[..], &decompressed[..]);
@@ -249,7 +1101,7 @@ mod test {
 
     #[test]
     fn decompress_a() {
-        let input = [32, 192, 0];
+        let input = [16, 32, 192, 0];
         let expected = b"A";
         let decompressed = decompress(Cursor::new(&input[..]), vec![]).unwrap();
         assert_eq!(&expected[..], &decompressed[..]);
@@ -257,7 +1109,7 @@ mod test {
 
     #[test]
     fn decompress_aaa() {
-        let input = [32, 192, 96, 80, 56, 36, 22, 13, 7, 130, 192, 0];
+        let input = [16, 32, 192, 96, 80, 56, 36, 22, 13, 7, 130, 192, 0];
         let expected = b"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
         let decompressed = decompress(Cursor::new(&input[..]), vec![]).unwrap();
         assert_eq!(&expected[..], &decompressed[..]);
@@ -275,4 +1127,247 @@ mod test {
         assert_eq!(original.len(), decompressed.len());
         assert_eq!(&original[..], &decompressed[..]);
     }
+
+    #[test]
+    fn inspect_codes() {
+        use inspect::Event;
+        let compressed = compress(Cursor::new(&b"ababab"[..]), vec![]).unwrap();
+        let events: Vec<Event> =
+            super::inspect(Cursor::new(compressed)).unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![Event::Header("max code width 16 bits".to_string()),
+                        Event::Code { code: 97, string: b"a".to_vec() },
+                        Event::Code { code: 98, string: b"b".to_vec() },
+                        Event::Code { code: 257, string: b"ab".to_vec() },
+                        Event::Code { code: 257, string: b"ab".to_vec() },
+                        Event::End],
+                   events);
+    }
+
+    #[test]
+    fn compress_decompress_clear() {
+        // Random data fills the dictionary with useless strings, so
+        // that it is cleared when the text follows.
+        let f = include_bytes!("lzw.rs");
+        let mut original = Vec::new();
+        let mut x = 1u32;
+        for _ in 0..300000 {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            original.push((x >> 24) as u8);
+        }
+        let random_len = original.len();
+        for _ in 0..20 {
+            original.extend_from_slice(&f[..]);
+        }
+
+        let compressed = compress(Cursor::new(&original[..]), vec![]).unwrap();
+        let random_only = compress(Cursor::new(&original[..random_len]), vec![]).unwrap();
+        assert!(compressed.len() - random_only.len() < 10 * f.len());
+
+        let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(&original[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn compress_decompress_bits() {
+        let f = include_bytes!("lzw.rs");
+        let mut original = Vec::new();
+        for i in 0..20 {
+            original.extend_from_slice(&f[..]);
+            original.extend((0..2000).map(|j| ((i * 7919 + j * 104729) % 251) as u8));
+        }
+        for &max_bits in &[12, 13, 16, 20, 24] {
+            let compressed =
+                compress_with_bits(Cursor::new(&original[..]), vec![], max_bits).unwrap();
+            assert_eq!(max_bits as u8, compressed[0]);
+            let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+            assert_eq!(&original[..], &decompressed[..]);
+        }
+    }
+
+    #[test]
+    fn decompress_bad_bits() {
+        assert!(decompress(Cursor::new(&[11, 128, 0][..]), vec![]).is_err());
+        assert!(decompress(Cursor::new(&[25, 128, 0][..]), vec![]).is_err());
+    }
+
+    #[test]
+    fn decompress_max_bits_small() {
+        use super::SharedState;
+        let compressed =
+            compress_with_bits(Cursor::new(&b"abababab"[..]), vec![], MAX_CODE_BITS).unwrap();
+        assert_eq!(MAX_CODE_BITS as u8, compressed[0]);
+        let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(&b"abababab"[..], &decompressed[..]);
+
+        // The dictionary holds only the entries added so far, not
+        // one per code of the declared width.
+        let mut state = SharedState::new(MAX_CODE_BITS);
+        let mut dstate = DecompressState::new();
+        let mut string = Vec::new();
+        for &code in &[97, 98, 257, 258] {
+            dstate.decode(&mut state, code, &mut string).unwrap();
+        }
+        assert_eq!(260, state.next_code);
+        assert_eq!(state.next_code as usize, dstate.prefix.len());
+        assert!(dstate.prefix.capacity() < 1 << 12);
+        assert!(dstate.suffix.capacity() < 1 << 12);
+        assert_eq!(EOF as usize + 4, dstate.suffix.len());
+    }
+
+    #[test]
+    fn decompress_bad_code() {
+        use std::io::Write;
+        use bitfile::BitWriter;
+        use error::Error;
+        // The first code cannot be a new entry, and later codes can
+        // be at most the code of the entry being added.
+        for codes in [&[257u64, 256][..], &[97, 300, 256][..]].iter() {
+            let mut out = BitWriter::new(vec![12]);
+            for &code in codes.iter() {
+                out.write_bits(code, 9).unwrap();
+            }
+            out.flush().unwrap();
+            match decompress(Cursor::new(out.to_inner()), vec![]) {
+                Err(Error::CorruptInput { .. }) => (),
+                r => panic!("unexpected result {:?}", r.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn zero_run() {
+        // Each code of a run of zeros stands for a string one byte
+        // longer than the one before, until the dictionary is full.
+        let input = vec![0u8; 12 << 20];
+        for &bits in &[MIN_CODE_BITS, DEFAULT_CODE_BITS] {
+            let compressed = compress_with_bits(Cursor::new(&input[..]), vec![], bits).unwrap();
+            let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+            assert!(input == decompressed, "{} bits", bits);
+        }
+    }
+
+    #[test]
+    fn compress_z_empty() {
+        let compressed = compress_z(Cursor::new(&b""[..]), vec![]).unwrap();
+        assert_eq!(&[0x1f, 0x9d, 0x90][..], &compressed[..]);
+    }
+
+    #[test]
+    fn compress_z_aaa() {
+        let input = b"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+        let expected = [0x1f, 0x9d, 0x90, 65, 2, 10, 28, 72, 176, 160, 193, 131, 5, 1];
+        let compressed = compress_z(Cursor::new(&input[..]), vec![]).unwrap();
+        assert_eq!(&expected[..], &compressed[..]);
+    }
+
+    #[test]
+    fn decompress_z_aaa() {
+        let input = [0x1f, 0x9d, 0x90, 65, 2, 10, 28, 72, 176, 160, 193, 131, 5, 1];
+        let expected = b"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+        let decompressed = decompress_z(Cursor::new(&input[..]), vec![]).unwrap();
+        assert_eq!(&expected[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn inspect_z_codes() {
+        use inspect::Event;
+        let compressed = compress_z(Cursor::new(&b"ababab"[..]), vec![]).unwrap();
+        let events: Vec<Event> =
+            super::inspect_z(Cursor::new(compressed)).unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![Event::Header("max code width 16 bits, block mode".to_string()),
+                        Event::Code { code: 97, string: b"a".to_vec() },
+                        Event::Code { code: 98, string: b"b".to_vec() },
+                        Event::Code { code: 257, string: b"ab".to_vec() },
+                        Event::Code { code: 257, string: b"ab".to_vec() },
+                        Event::End],
+                   events);
+    }
+
+    #[test]
+    fn decompress_z_bad_magic() {
+        let input = [0x1f, 0x8b, 0x90, 65];
+        assert!(decompress_z(Cursor::new(&input[..]), vec![]).is_err());
+    }
+
+    #[test]
+    fn compress_decompress_z() {
+        let f = include_bytes!("lzw.rs");
+        let mut original = Vec::new();
+        for i in 0..20 {
+            original.extend_from_slice(&f[..]);
+            original.extend((0..2000).map(|j| ((i * 7919 + j * 104729) % 251) as u8));
+        }
+        for max_bits in 9..17 {
+            let compressed =
+                compress_z_with_bits(Cursor::new(&original[..]), vec![], max_bits).unwrap();
+            let decompressed = decompress_z(Cursor::new(&compressed[..]), vec![]).unwrap();
+            assert_eq!(&original[..], &decompressed[..]);
+        }
+    }
+
+    #[test]
+    fn compress_gif() {
+        let input = [0, 0, 0, 0, 1, 1, 1, 1];
+        let expected = [132, 17, 25, 5];
+        let compressed = compress_dialect(Cursor::new(&input[..]), vec![], Dialect::gif(2)).unwrap();
+        assert_eq!(&expected[..], &compressed[..]);
+    }
+
+    #[test]
+    fn decompress_gif() {
+        let input = [132, 17, 25, 5];
+        let expected = [0, 0, 0, 0, 1, 1, 1, 1];
+        let decompressed =
+            decompress_dialect(Cursor::new(&input[..]), vec![], Dialect::gif(2)).unwrap();
+        assert_eq!(&expected[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn decompress_tiff() {
+        let input = [128, 1, 224, 64, 128, 68, 8, 12, 6, 128, 128];
+        let expected = [7, 7, 7, 8, 8, 7, 7, 6, 6];
+        let decompressed =
+            decompress_dialect(Cursor::new(&input[..]), vec![], Dialect::tiff()).unwrap();
+        assert_eq!(&expected[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn compress_gif_symbol_too_large() {
+        let input = [0, 4];
+        assert!(compress_dialect(Cursor::new(&input[..]), vec![], Dialect::gif(2)).is_err());
+    }
+
+    #[test]
+    fn compress_decompress_dialects() {
+        let f = include_bytes!("lzw.rs");
+        let mut original = Vec::new();
+        for i in 0..10 {
+            original.extend_from_slice(&f[..]);
+            original.extend((0..3000).map(|j| ((i * 7919 + j * 104729) % 251) as u8));
+        }
+        for dialect in [Dialect::gif(8), Dialect::tiff()].iter() {
+            let compressed =
+                compress_dialect(Cursor::new(&original[..]), vec![], *dialect).unwrap();
+            let decompressed =
+                decompress_dialect(Cursor::new(&compressed[..]), vec![], *dialect).unwrap();
+            assert_eq!(&original[..], &decompressed[..]);
+        }
+        let small: Vec<u8> = original.iter().map(|b| b & 3).collect();
+        let compressed = compress_dialect(Cursor::new(&small[..]), vec![], Dialect::gif(2)).unwrap();
+        let decompressed =
+            decompress_dialect(Cursor::new(&compressed[..]), vec![], Dialect::gif(2)).unwrap();
+        assert_eq!(&small[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+        ::testutil::roundtrip((|d: &[u8]| super::compress_z(d, vec![]),
+                               |d: &[u8]| super::decompress_z(d, vec![])));
+        ::testutil::roundtrip((|d: &[u8]| super::compress_dialect(d, vec![], Dialect::gif(8)),
+                               |d: &[u8]| super::decompress_dialect(d, vec![], Dialect::gif(8))));
+        ::testutil::roundtrip((|d: &[u8]| super::compress_dialect(d, vec![], Dialect::tiff()),
+                               |d: &[u8]| super::decompress_dialect(d, vec![], Dialect::tiff())));
+    }
 }
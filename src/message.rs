@@ -0,0 +1,152 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Compression of many small messages.
+//!
+//! Compressing each message of an RPC or telemetry stream on its own
+//! wastes most of the messages on teaching a fresh adaptive model
+//! what the data looks like.  A `MessageCompressor` instead keeps its
+//! model between calls to `compress_msg`, so that each message is
+//! coded with the statistics of all messages before it.  A
+//! `MessageDecompressor` mirrors the model on the receiving side.
+//!
+//! Messages are arithmetic coded with the `arith` coder.  By default,
+//! the order-1 model from `witten_arith` is used, whose context also
+//! carries over from the end of one message to the start of the
+//! next.  A pre-trained model, for example one loaded with
+//! `load_model`, can be installed with `with_model`.
+//!
+//! Because both sides adapt their models, the decompressor must see
+//! exactly the messages the compressor produced, in the same order.
+//! When a message is lost or fails to decompress, both sides have to
+//! start over with fresh contexts.
+
+use std::io::{Read, Write};
+
+use arith::{Writer, Reader};
+use error::Error;
+use model::Model;
+use witten_arith::Order1Model;
+
+/// Compressor which keeps its model across messages.
+pub struct MessageCompressor<M> {
+    model: M,
+}
+
+impl MessageCompressor<Order1Model> {
+    /// Create a new compressor with a fresh order-1 model.
+    pub fn new() -> MessageCompressor<Order1Model> {
+        MessageCompressor::with_model(Order1Model::new())
+    }
+}
+
+impl<M: Model> MessageCompressor<M> {
+    /// Create a new compressor which starts out with `model`.  The
+    /// decompressor must be created with the same model.
+    pub fn with_model(model: M) -> MessageCompressor<M> {
+        MessageCompressor {
+            model: model,
+        }
+    }
+
+    /// Compress `msg` and return the compressed message.  The model
+    /// is updated with the contents of `msg`.
+    pub fn compress_msg(&mut self, msg: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut cw = Writer::with_model(Vec::with_capacity(msg.len() / 2 + 8),
+                                        &mut self.model);
+        try!(cw.write_all(msg));
+        try!(cw.flush());
+        Ok(cw.into_inner())
+    }
+
+    /// Return the model in its current state.
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+}
+
+/// Decompressor for the messages produced by a `MessageCompressor`.
+pub struct MessageDecompressor<M> {
+    model: M,
+}
+
+impl MessageDecompressor<Order1Model> {
+    /// Create a new decompressor with a fresh order-1 model.
+    pub fn new() -> MessageDecompressor<Order1Model> {
+        MessageDecompressor::with_model(Order1Model::new())
+    }
+}
+
+impl<M: Model> MessageDecompressor<M> {
+    /// Create a new decompressor which starts out with `model`.
+    pub fn with_model(model: M) -> MessageDecompressor<M> {
+        MessageDecompressor {
+            model: model,
+        }
+    }
+
+    /// Decompress the compressed message `msg` and return the
+    /// original message.
+    pub fn decompress_msg(&mut self, msg: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut cr = try!(Reader::with_model(msg, &mut self.model));
+        let mut result = Vec::new();
+        try!(cr.read_to_end(&mut result));
+        Ok(result)
+    }
+
+    /// Return the model in its current state.
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::{MessageCompressor, MessageDecompressor};
+    use arith::State;
+    use witten_arith;
+
+    fn messages() -> Vec<Vec<u8>> {
+        (0..200).map(|i| format!("{{\"id\":{},\"temp\":{},\"status\":\"ok\"}}",
+                                 i, 20 + i % 7).into_bytes()).collect()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut c = MessageCompressor::new();
+        let mut d = MessageDecompressor::new();
+        for m in messages() {
+            let compressed = c.compress_msg(&m).unwrap();
+            let decompressed = d.decompress_msg(&compressed).unwrap();
+            assert_eq!(m, decompressed);
+        }
+    }
+
+    #[test]
+    fn empty_messages() {
+        let mut c = MessageCompressor::with_model(State::new());
+        let mut d = MessageDecompressor::with_model(State::new());
+        for m in &[&b""[..], b"a", b"", b"abc", b""] {
+            let compressed = c.compress_msg(m).unwrap();
+            let decompressed = d.decompress_msg(&compressed).unwrap();
+            assert_eq!(&m[..], &decompressed[..]);
+        }
+    }
+
+    #[test]
+    fn state_pays_off() {
+        let msgs = messages();
+        let mut c = MessageCompressor::new();
+        let mut total = 0;
+        let mut independent = 0;
+        for m in &msgs[..100] {
+            c.compress_msg(m).unwrap();
+        }
+        for m in &msgs[100..] {
+            total += c.compress_msg(m).unwrap().len();
+            independent += witten_arith::compress(Cursor::new(&m[..]), vec![]).unwrap().len();
+        }
+        assert!(total * 2 < independent);
+    }
+}
@@ -0,0 +1,394 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Registry of the compression methods in this crate.
+//!
+//! A `Method` names one of the coders together with its parameters,
+//! so that it can be selected at run time, for example from the
+//! command line, and recorded in a stream header.  Each method has a
+//! name, as used by the `camp` tool, and a one-byte id, which is
+//! stable across versions and used by the container format.
+//!
+//! All methods are known in every build, so that streams can be
+//! identified, but the codecs of methods whose cargo feature is
+//! disabled are not compiled in.  Compressing or decompressing with
+//! such a method returns an error.
+
+use std::io::{self, Read, Write};
+
+use error::Error;
+#[cfg(feature = "arith")]
+use arith;
+#[cfg(feature = "arith")]
+use binarith;
+#[cfg(feature = "huffman")]
+use huff;
+#[cfg(feature = "lz")]
+use lz4;
+#[cfg(feature = "lz")]
+use lz77;
+#[cfg(feature = "arith")]
+use lzb;
+#[cfg(feature = "lz")]
+use lzp1;
+#[cfg(feature = "nested")]
+use lzp2;
+#[cfg(feature = "arith")]
+use lzp3;
+#[cfg(feature = "lz")]
+use lzss;
+#[cfg(feature = "nested")]
+use lzss2;
+#[cfg(feature = "lz")]
+use lzw;
+#[cfg(feature = "arith")]
+use range;
+#[cfg(feature = "arith")]
+use rolz;
+#[cfg(feature = "lz")]
+use snappy;
+#[cfg(any(feature = "lz", feature = "nested"))]
+use window;
+#[cfg(feature = "arith")]
+use witten_arith;
+#[cfg(feature = "deflate")]
+use zlib;
+
+/// Compression method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Arith,
+    WittenArith,
+    WittenArith1,
+    Lzw,
+    LzwZ,
+    Lz77,
+    Lzss,
+    LzssBest,
+    LzssLarge,
+    Lzss2,
+    Lzss2Best,
+    Huff,
+    HuffWhole,
+    AHuff,
+    AHuffVitter,
+    Lzp1,
+    Lzp2,
+    Lzp3,
+    BinArith,
+    Zlib,
+    Lz4,
+    Snappy,
+    Lzb,
+    Rolz,
+    Range,
+    Store,
+}
+
+/// All methods with their ids and names.  Ids must never be reused.
+const METHODS: [(Method, u8, &'static str); 26] = [
+    (Method::Arith, 1, "arith"),
+    (Method::WittenArith, 2, "warith"),
+    (Method::WittenArith1, 3, "warith1"),
+    (Method::Lzw, 4, "lzw"),
+    (Method::LzwZ, 5, "lzw-z"),
+    (Method::Lz77, 6, "lz77"),
+    (Method::Lzss, 7, "lzss"),
+    (Method::LzssBest, 8, "lzss-best"),
+    (Method::LzssLarge, 9, "lzss-large"),
+    (Method::Lzss2, 10, "lzss2"),
+    (Method::Lzss2Best, 11, "lzss2-best"),
+    (Method::Huff, 12, "huff"),
+    (Method::HuffWhole, 13, "huff-whole"),
+    (Method::AHuff, 14, "ahuff"),
+    (Method::AHuffVitter, 15, "ahuff-vitter"),
+    (Method::Lzp1, 16, "lzp1"),
+    (Method::Lzp2, 17, "lzp2"),
+    (Method::Lzp3, 18, "lzp3"),
+    (Method::BinArith, 19, "binarith"),
+    (Method::Zlib, 20, "zlib"),
+    (Method::Lz4, 21, "lz4"),
+    (Method::Snappy, 22, "snappy"),
+    (Method::Lzb, 23, "lzb"),
+    (Method::Rolz, 24, "rolz"),
+    (Method::Range, 25, "range"),
+    (Method::Store, 26, "store"),
+];
+
+impl Method {
+    /// Return all methods available in this build, in the order of
+    /// their ids.
+    pub fn all() -> Vec<Method> {
+        METHODS.iter().map(|&(m, _, _)| m).filter(|m| m.is_available()).collect()
+    }
+
+    /// Return true if the codec for this method is compiled in.
+    pub fn is_available(&self) -> bool {
+        match *self {
+            Method::Lzw | Method::LzwZ | Method::Lz77 | Method::Lzss | Method::LzssBest |
+            Method::LzssLarge | Method::Lzp1 | Method::Lz4 | Method::Snappy =>
+                cfg!(feature = "lz"),
+            Method::Lzss2 | Method::Lzss2Best | Method::Lzp2 => cfg!(feature = "nested"),
+            Method::Huff | Method::HuffWhole | Method::AHuff | Method::AHuffVitter =>
+                cfg!(feature = "huffman"),
+            Method::Arith | Method::WittenArith | Method::WittenArith1 | Method::BinArith |
+            Method::Range | Method::Lzp3 | Method::Lzb | Method::Rolz => cfg!(feature = "arith"),
+            Method::Zlib => cfg!(feature = "deflate"),
+            Method::Store => true,
+        }
+    }
+
+    fn entry(&self) -> &'static (Method, u8, &'static str) {
+        METHODS.iter().find(|e| e.0 == *self).unwrap()
+    }
+
+    /// Return the name of the method.
+    pub fn name(&self) -> &'static str {
+        self.entry().2
+    }
+
+    /// Return the method called `name`, whether it is available or
+    /// not.
+    pub fn from_name(name: &str) -> Option<Method> {
+        METHODS.iter().find(|e| e.2 == name).map(|e| e.0)
+    }
+
+    /// Return the id of the method.
+    pub fn id(&self) -> u8 {
+        self.entry().1
+    }
+
+    /// Return the method with id `id`, whether it is available or not.
+    pub fn from_id(id: u8) -> Option<Method> {
+        METHODS.iter().find(|e| e.1 == id).map(|e| e.0)
+    }
+
+    /// Compress all data from `input` with this method and write the
+    /// compressed stream to `output`.  On success, the output is
+    /// returned.
+    pub fn compress<R: Read, W: Write>(&self, input: R, output: W) -> Result<W, Error> {
+        match *self {
+            #[cfg(feature = "arith")]
+            Method::Arith => arith::compress(input, output),
+            #[cfg(feature = "arith")]
+            Method::WittenArith => witten_arith::compress(input, output),
+            #[cfg(feature = "arith")]
+            Method::WittenArith1 =>
+                witten_arith::compress_with_order(input, output, witten_arith::Order::One),
+            #[cfg(feature = "lz")]
+            Method::Lzw => lzw::compress(input, output),
+            #[cfg(feature = "lz")]
+            Method::LzwZ => lzw::compress_z(input, output),
+            #[cfg(feature = "lz")]
+            Method::Lz77 => lz77::compress(input, output),
+            #[cfg(feature = "lz")]
+            Method::Lzss => lzss::compress(input, output),
+            #[cfg(feature = "lz")]
+            Method::LzssBest => lzss::compress_with_level(input, output, window::MAX_LEVEL),
+            #[cfg(feature = "lz")]
+            Method::LzssLarge => lzss::compress_with_window_bits(input, output, 20),
+            #[cfg(feature = "nested")]
+            Method::Lzss2 => lzss2::compress(input, output),
+            #[cfg(feature = "nested")]
+            Method::Lzss2Best => lzss2::compress_with_level(input, output, window::MAX_LEVEL),
+            #[cfg(feature = "huffman")]
+            Method::Huff => huff::block::compress(input, output),
+            #[cfg(feature = "huffman")]
+            Method::HuffWhole => huff::block::compress_whole_file(input, output),
+            #[cfg(feature = "huffman")]
+            Method::AHuff => huff::adaptive::compress(input, output),
+            #[cfg(feature = "huffman")]
+            Method::AHuffVitter =>
+                huff::adaptive::compress_with_algorithm(input, output,
+                                                        huff::adaptive::Algorithm::Vitter),
+            #[cfg(feature = "lz")]
+            Method::Lzp1 => lzp1::compress(input, output),
+            #[cfg(feature = "nested")]
+            Method::Lzp2 => lzp2::compress(input, output),
+            #[cfg(feature = "arith")]
+            Method::Lzp3 => lzp3::compress(input, output),
+            #[cfg(feature = "arith")]
+            Method::BinArith => binarith::compress(input, output),
+            #[cfg(feature = "deflate")]
+            Method::Zlib => zlib::compress(input, output),
+            #[cfg(feature = "lz")]
+            Method::Lz4 => lz4::compress(input, output),
+            #[cfg(feature = "lz")]
+            Method::Snappy => snappy::compress(input, output),
+            #[cfg(feature = "arith")]
+            Method::Lzb => lzb::compress(input, output),
+            #[cfg(feature = "arith")]
+            Method::Rolz => rolz::compress(input, output),
+            #[cfg(feature = "arith")]
+            Method::Range => range::compress(input, output),
+            Method::Store => store(input, output),
+            #[allow(unreachable_patterns)]
+            _ => Err(unavailable(*self)),
+        }
+    }
+
+    /// Decompress all data from `input`, which must have been
+    /// compressed with this method, and write the decompressed data
+    /// to `output`.  On success, the output is returned.
+    pub fn decompress<R: Read, W: Write>(&self, input: R, output: W) -> Result<W, Error> {
+        match *self {
+            #[cfg(feature = "arith")]
+            Method::Arith => arith::decompress(input, output),
+            #[cfg(feature = "arith")]
+            Method::WittenArith => witten_arith::decompress(input, output),
+            #[cfg(feature = "arith")]
+            Method::WittenArith1 =>
+                witten_arith::decompress_with_order(input, output, witten_arith::Order::One),
+            #[cfg(feature = "lz")]
+            Method::Lzw => lzw::decompress(input, output),
+            #[cfg(feature = "lz")]
+            Method::LzwZ => lzw::decompress_z(input, output),
+            #[cfg(feature = "lz")]
+            Method::Lz77 => lz77::decompress(input, output),
+            #[cfg(feature = "lz")]
+            Method::Lzss | Method::LzssBest | Method::LzssLarge => lzss::decompress(input, output),
+            #[cfg(feature = "nested")]
+            Method::Lzss2 | Method::Lzss2Best => lzss2::decompress(input, output),
+            #[cfg(feature = "huffman")]
+            Method::Huff | Method::HuffWhole => huff::block::decompress(input, output),
+            #[cfg(feature = "huffman")]
+            Method::AHuff => huff::adaptive::decompress(input, output),
+            #[cfg(feature = "huffman")]
+            Method::AHuffVitter =>
+                huff::adaptive::decompress_with_algorithm(input, output,
+                                                          huff::adaptive::Algorithm::Vitter),
+            #[cfg(feature = "lz")]
+            Method::Lzp1 => lzp1::decompress(input, output),
+            #[cfg(feature = "nested")]
+            Method::Lzp2 => lzp2::decompress(input, output),
+            #[cfg(feature = "arith")]
+            Method::Lzp3 => lzp3::decompress(input, output),
+            #[cfg(feature = "arith")]
+            Method::BinArith => binarith::decompress(input, output),
+            #[cfg(feature = "deflate")]
+            Method::Zlib => zlib::decompress(input, output),
+            #[cfg(feature = "lz")]
+            Method::Lz4 => lz4::decompress(input, output),
+            #[cfg(feature = "lz")]
+            Method::Snappy => snappy::decompress(input, output),
+            #[cfg(feature = "arith")]
+            Method::Lzb => lzb::decompress(input, output),
+            #[cfg(feature = "arith")]
+            Method::Rolz => rolz::decompress(input, output),
+            #[cfg(feature = "arith")]
+            Method::Range => range::decompress(input, output),
+            Method::Store => store(input, output),
+            #[allow(unreachable_patterns)]
+            _ => Err(unavailable(*self)),
+        }
+    }
+}
+
+/// Return the error for using a method which is not compiled in.
+#[allow(dead_code)]
+fn unavailable(method: Method) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::Other,
+                             format!("compression method {} is not available in this build",
+                                     method.name())))
+}
+
+/// Copy the input unchanged, for `Method::Store`.
+fn store<R: Read, W: Write>(mut input: R, mut output: W) -> Result<W, Error> {
+    try!(io::copy(&mut input, &mut output));
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::Method;
+
+    #[test]
+    fn names_and_ids() {
+        for m in Method::all() {
+            assert_eq!(Some(m), Method::from_name(m.name()));
+            assert_eq!(Some(m), Method::from_id(m.id()));
+        }
+        assert_eq!(None, Method::from_name("lzmg1"));
+        assert_eq!(None, Method::from_id(0));
+    }
+
+    #[test]
+    fn compress_decompress_all() {
+        let input = include_bytes!("method.rs");
+        for m in Method::all() {
+            let compressed = m.compress(Cursor::new(&input[..]), vec![]).unwrap();
+            let decompressed = m.decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+            assert_eq!(&input[..], &decompressed[..], "method {}", m.name());
+        }
+    }
+
+    #[test]
+    fn unavailable() {
+        let input = include_bytes!("method.rs");
+        for m in (1..255).filter_map(Method::from_id).filter(|m| !m.is_available()) {
+            assert!(m.compress(Cursor::new(&input[..]), vec![]).is_err());
+            assert!(m.decompress(Cursor::new(&input[..]), vec![]).is_err());
+        }
+    }
+
+    /// Input for `golden`: text with numbers, followed by bytes from a
+    /// linear congruential generator.
+    fn golden_input() -> Vec<u8> {
+        let mut data = Vec::new();
+        for i in 0..200 {
+            data.extend_from_slice(b"The banana goat in the banana boat can hand bananas. ");
+            data.extend_from_slice(i.to_string().as_bytes());
+        }
+        let mut x: u32 = 1;
+        for _ in 0..2000 {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            data.push((x >> 16) as u8);
+        }
+        data
+    }
+
+    /// Length and XXH64 digest of the output of each method for
+    /// `golden_input`.
+    const GOLDEN: [(&'static str, usize, u64); 26] = [
+        ("arith", 7851, 0x107a19004adc019d),
+        ("warith", 7851, 0x569dadd5065b4324),
+        ("warith1", 5357, 0xc5f6fe48e9156b22),
+        ("lzw", 4433, 0xce3008273adf5848),
+        ("lzw-z", 4433, 0x0009fb952cd41da6),
+        ("lz77", 2730, 0xc9ae55b8a4e36799),
+        ("lzss", 2997, 0x6aafee883e243247),
+        ("lzss-best", 2971, 0xf627c9f4b1753d71),
+        ("lzss-large", 3040, 0x4aced9db660a5ce6),
+        ("lzss2", 3546, 0x5e68eb79e7289010),
+        ("lzss2-best", 3505, 0x44bfef14acb0b81d),
+        ("huff", 7896, 0x7810d00b6f70d6e9),
+        ("huff-whole", 7896, 0x7810d00b6f70d6e9),
+        ("ahuff", 8050, 0x5c5eb45fdd0a7f6d),
+        ("ahuff-vitter", 8056, 0x7db68cc4b8afb805),
+        ("lzp1", 2930, 0x1994a8e433e493b2),
+        ("lzp2", 2947, 0xc7394ccb6204bebf),
+        ("lzp3", 2930, 0xea94b655f7660644),
+        ("binarith", 3759, 0xe82fa954c568cff7),
+        ("zlib", 2937, 0xda11ad1a7f63d0a5),
+        ("lz4", 3067, 0x97bd4beda0af838d),
+        ("snappy", 3032, 0xfcd88842a7d33211),
+        ("lzb", 3025, 0xcbee6fb775c75701),
+        ("rolz", 3080, 0xeeb340bcbdbe2f88),
+        ("range", 7850, 0x710a02d3117977b1),
+        ("store", 13090, 0x7d150877ca851079),
+    ];
+
+    /// The compressed output must be the same on every platform and
+    /// must only change together with the format.
+    #[test]
+    fn golden() {
+        let input = golden_input();
+        for m in Method::all() {
+            let compressed = m.compress(Cursor::new(&input[..]), vec![]).unwrap();
+            let &(_, len, digest) = GOLDEN.iter().find(|g| g.0 == m.name()).unwrap();
+            assert_eq!(len, compressed.len(), "method {}", m.name());
+            assert_eq!(digest, ::xxhash::xxh64(&compressed), "method {}", m.name());
+        }
+    }
+}
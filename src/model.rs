@@ -0,0 +1,165 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Probability models for arithmetic coding.
+//!
+//! The arithmetic coders in the `arith` and `witten_arith` modules do
+//! not care where symbol probabilities come from.  They only need a
+//! cumulative frequency range for each symbol they encode, and the
+//! symbol belonging to a cumulative frequency when decoding.  This
+//! module defines the `Model` trait which provides exactly that, so
+//! that order-N, static or domain-specific models can be plugged into
+//! the coders.
+//!
+//! Symbols are the byte values 0 to 255, plus the end-of-file symbol
+//! `EOF`, which every model must assign a non-zero frequency.
+//!
+//! The adaptive models of the coders can be trained with `train` and
+//! saved with their `serialize_model` methods.  A serialized model
+//! starts with a one-byte tag naming the model type, followed by the
+//! model's counts as LEB128 numbers.
+
+use io::{Read, Write};
+use io;
+use alloc::boxed::Box;
+use error::Error;
+
+/// A symbol to be coded: a byte value or `EOF`.
+pub type Symbol = usize;
+
+/// End-of-file symbol, terminating every arithmetic coded stream.
+pub const EOF: Symbol = 256;
+
+/// Number of symbols in the alphabet, including `EOF`.
+pub const SYMBOLS: usize = EOF + 1;
+
+/// Largest total frequency a model may report.  This keeps every
+/// symbol's share of the coder's range at least one unit wide.
+pub const MAX_TOTAL: u64 = 1 << 24;
+
+/// Cumulative frequency range of a symbol: the symbol occupies the
+/// range `low..high` of the interval `0..total`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prob {
+    pub low: u64,
+    pub high: u64,
+    pub total: u64,
+}
+
+/// A probability model over the symbols `0..SYMBOLS`.
+///
+/// The encoder calls `prob` and then `update` for each symbol; the
+/// decoder calls `total`, `symbol_for`, `prob` and `update`.  Both
+/// sides must therefore see the same sequence of calls to `update`,
+/// which makes sure that the model evolves identically.
+pub trait Model {
+    /// Return the cumulative frequency range of `sym`.  The range
+    /// must not be empty.
+    fn prob(&self, sym: Symbol) -> Prob;
+
+    /// Return the sum of all frequencies, which must not exceed
+    /// `MAX_TOTAL`.
+    fn total(&self) -> u64;
+
+    /// Return the symbol whose range contains the cumulative
+    /// frequency `cum`, which is less than `total()`.
+    fn symbol_for(&self, cum: u64) -> Symbol;
+
+    /// Adapt the model after `sym` has been coded.
+    fn update(&mut self, sym: Symbol);
+}
+
+/// A mutable reference to a model is a model itself, so that coders
+/// can borrow a model which outlives them.
+impl<'a, M: Model + ?Sized> Model for &'a mut M {
+    fn prob(&self, sym: Symbol) -> Prob {
+        (**self).prob(sym)
+    }
+
+    fn total(&self) -> u64 {
+        (**self).total()
+    }
+
+    fn symbol_for(&self, cum: u64) -> Symbol {
+        (**self).symbol_for(cum)
+    }
+
+    fn update(&mut self, sym: Symbol) {
+        (**self).update(sym)
+    }
+}
+
+/// A boxed model is a model, too, so that a coder can choose its
+/// model at run time.
+impl<M: Model + ?Sized> Model for Box<M> {
+    fn prob(&self, sym: Symbol) -> Prob {
+        (**self).prob(sym)
+    }
+
+    fn total(&self) -> u64 {
+        (**self).total()
+    }
+
+    fn symbol_for(&self, cum: u64) -> Symbol {
+        (**self).symbol_for(cum)
+    }
+
+    fn update(&mut self, sym: Symbol) {
+        (**self).update(sym)
+    }
+}
+
+/// Adapt `model` to `data` as if it had been coded.  A model trained
+/// on representative data can be saved and installed into fresh
+/// coders, which then compress similar data better right from the
+/// start.
+pub fn train<M: Model>(model: &mut M, data: &[u8]) {
+    for &b in data {
+        model.update(b as Symbol);
+    }
+}
+
+/// Write `value` as a LEB128 number, as used by the model
+/// serialization formats.
+#[cfg_attr(not(any(feature = "std", feature = "arith")), allow(dead_code))]
+pub(crate) fn write_varint<W: Write>(output: &mut W, mut value: u64) -> io::Result<()> {
+    loop {
+        let b = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return output.write_all(&[b]);
+        }
+        try!(output.write_all(&[b | 0x80]));
+    }
+}
+
+/// Read a LEB128 number written by `write_varint`.
+#[cfg_attr(not(any(feature = "std", feature = "arith")), allow(dead_code))]
+pub(crate) fn read_varint<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let mut b = [0u8; 1];
+        try!(input.read_exact(&mut b));
+        if shift > 63 {
+            return Err(Error::corrupt("invalid number in model").into());
+        }
+        value |= ((b[0] & 0x7f) as u64) << shift;
+        if b[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+/// Read the one-byte tag at the start of a serialized model and
+/// check that it is `tag`.
+#[cfg_attr(not(feature = "arith"), allow(dead_code))]
+pub(crate) fn read_tag<R: Read>(input: &mut R, tag: u8) -> io::Result<()> {
+    let mut b = [0u8; 1];
+    try!(input.read_exact(&mut b));
+    if b[0] != tag {
+        return Err(Error::InvalidHeader("wrong model type").into());
+    }
+    Ok(())
+}
@@ -0,0 +1,258 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Entropy coders for the output of other compressors.
+//!
+//! Compressors like `lzss2` and `lzp2` produce a byte stream which
+//! still contains a lot of redundancy, so they pass it through a
+//! nested entropy coder.  This module lets them choose the nested
+//! coder at construction time.  The choice is recorded in a one-byte
+//! header in front of the coded data, so that the reader can select
+//! the matching decoder.
+
+use std::io::{Read, Write};
+use std::io;
+
+use binarith;
+use huff::adaptive;
+use range;
+use arith::State;
+use error::{relocated, Error};
+
+/// Entropy coder used for the output of a compressor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NestedCoder {
+    /// Adaptive Huffman coder from `huff::adaptive`.
+    AdaptiveHuffman,
+    /// Adaptive binary arithmetic coder from `binarith`.
+    BinArith,
+    /// Range coder with an adaptive order-0 model from `range`.
+    Range,
+}
+
+impl NestedCoder {
+    fn tag(&self) -> u8 {
+        match *self {
+            NestedCoder::AdaptiveHuffman => 0,
+            NestedCoder::BinArith => 1,
+            NestedCoder::Range => 2,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<NestedCoder> {
+        match tag {
+            0 => Some(NestedCoder::AdaptiveHuffman),
+            1 => Some(NestedCoder::BinArith),
+            2 => Some(NestedCoder::Range),
+            _ => None,
+        }
+    }
+}
+
+/// Writer which puts the header byte in front of the first data
+/// written.
+#[derive(Clone)]
+struct Tagged<W> {
+    inner: W,
+    tag: Option<u8>,
+}
+
+impl<W: Write> Tagged<W> {
+    fn write_tag(&mut self) -> io::Result<()> {
+        if let Some(tag) = self.tag.take() {
+            try!(self.inner.write_all(&[tag]));
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Tagged<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        try!(self.write_tag());
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.write_tag());
+        self.inner.flush()
+    }
+}
+
+#[derive(Clone)]
+enum Encoder<W> {
+    AdaptiveHuffman(adaptive::Writer<Tagged<W>>),
+    BinArith(binarith::Writer<Tagged<W>>),
+    Range(range::Writer<Tagged<W>, State>),
+}
+
+/// Writer for the nested coder.  As with the coders themselves,
+/// `flush` finishes the coded stream.
+#[derive(Clone)]
+pub struct Writer<W> {
+    encoder: Encoder<W>,
+}
+
+impl<W: Write> Writer<W> {
+    /// Create a writer which codes its input with `coder`.
+    pub fn new(output: W, coder: NestedCoder) -> Writer<W> {
+        let output = Tagged {
+            inner: output,
+            tag: Some(coder.tag()),
+        };
+        let encoder = match coder {
+            NestedCoder::AdaptiveHuffman => Encoder::AdaptiveHuffman(adaptive::Writer::new(output)),
+            NestedCoder::BinArith => Encoder::BinArith(binarith::Writer::new(output)),
+            NestedCoder::Range => Encoder::Range(range::Writer::new(output)),
+        };
+        Writer {
+            encoder: encoder,
+        }
+    }
+
+    /// Return the number of uncompressed bytes written so far.
+    pub fn total_in(&self) -> u64 {
+        match self.encoder {
+            Encoder::AdaptiveHuffman(ref w) => w.total_in(),
+            Encoder::BinArith(ref w) => w.total_in(),
+            Encoder::Range(ref w) => w.total_in(),
+        }
+    }
+
+    /// Return the number of coded bytes written so far, including
+    /// the header byte.
+    pub fn total_out(&self) -> u64 {
+        1 + match self.encoder {
+            Encoder::AdaptiveHuffman(ref w) => w.total_out(),
+            Encoder::BinArith(ref w) => w.total_out(),
+            Encoder::Range(ref w) => w.total_out(),
+        }
+    }
+
+    /// Move the wrapped writer out of the nested writer.
+    pub fn into_inner(self) -> W {
+        match self.encoder {
+            Encoder::AdaptiveHuffman(w) => w.into_inner().inner,
+            Encoder::BinArith(w) => w.into_inner().inner,
+            Encoder::Range(w) => w.into_inner().inner,
+        }
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.encoder {
+            Encoder::AdaptiveHuffman(ref mut w) => w.write(buf),
+            Encoder::BinArith(ref mut w) => w.write(buf),
+            Encoder::Range(ref mut w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.encoder {
+            Encoder::AdaptiveHuffman(ref mut w) => w.flush(),
+            Encoder::BinArith(ref mut w) => w.flush(),
+            Encoder::Range(ref mut w) => w.flush(),
+        }
+    }
+}
+
+enum Decoder<R> {
+    AdaptiveHuffman(adaptive::Reader<R>),
+    BinArith(binarith::Reader<R>),
+    Range(range::Reader<R, State>),
+}
+
+/// Reader for the nested coder, which selects the decoder from the
+/// header.
+pub struct Reader<R> {
+    decoder: Decoder<R>,
+}
+
+impl<R: Read> Reader<R> {
+    /// Create a new reader.  This reads the header and possibly the
+    /// start of the coded data, therefore the result can be an
+    /// error.
+    pub fn new(mut input: R) -> io::Result<Reader<R>> {
+        let mut tag = [0u8; 1];
+        try!(input.read_exact(&mut tag));
+        let decoder = match NestedCoder::from_tag(tag[0]) {
+            Some(NestedCoder::AdaptiveHuffman) =>
+                Decoder::AdaptiveHuffman(adaptive::Reader::new(input)),
+            Some(NestedCoder::BinArith) =>
+                Decoder::BinArith(try!(binarith::Reader::new(input))),
+            Some(NestedCoder::Range) =>
+                Decoder::Range(try!(range::Reader::new(input))),
+            None =>
+                return Err(Error::InvalidHeader("unknown nested coder").into()),
+        };
+        Ok(Reader {
+            decoder: decoder,
+        })
+    }
+
+    /// Return the coder used for the stream.
+    pub fn coder(&self) -> NestedCoder {
+        match self.decoder {
+            Decoder::AdaptiveHuffman(_) => NestedCoder::AdaptiveHuffman,
+            Decoder::BinArith(_) => NestedCoder::BinArith,
+            Decoder::Range(_) => NestedCoder::Range,
+        }
+    }
+
+    /// Return the number of coded bytes read so far, including the
+    /// header byte.
+    pub fn total_in(&self) -> u64 {
+        1 + match self.decoder {
+            Decoder::AdaptiveHuffman(ref r) => r.total_in(),
+            Decoder::BinArith(ref r) => r.total_in(),
+            Decoder::Range(ref r) => r.total_in(),
+        }
+    }
+
+    /// Return the number of decoded bytes read so far.
+    pub fn total_out(&self) -> u64 {
+        match self.decoder {
+            Decoder::AdaptiveHuffman(ref r) => r.total_out(),
+            Decoder::BinArith(ref r) => r.total_out(),
+            Decoder::Range(ref r) => r.total_out(),
+        }
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.decoder {
+            Decoder::AdaptiveHuffman(ref mut r) => r.read(buf),
+            Decoder::BinArith(ref mut r) => r.read(buf),
+            Decoder::Range(ref mut r) => r.read(buf),
+        }.map_err(|e| relocated(e, 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Write};
+    use super::{NestedCoder, Writer, Reader};
+
+    #[test]
+    fn roundtrip() {
+        let input = include_bytes!("nested.rs");
+        for &coder in &[NestedCoder::AdaptiveHuffman, NestedCoder::BinArith, NestedCoder::Range] {
+            let mut w = Writer::new(vec![], coder);
+            w.write_all(&input[..]).unwrap();
+            w.flush().unwrap();
+            let compressed = w.into_inner();
+
+            let mut r = Reader::new(Cursor::new(compressed)).unwrap();
+            assert_eq!(coder, r.coder());
+            let mut decompressed = Vec::new();
+            r.read_to_end(&mut decompressed).unwrap();
+            assert_eq!(&input[..], &decompressed[..]);
+        }
+    }
+
+    #[test]
+    fn unknown_coder() {
+        assert!(Reader::new(Cursor::new(vec![3, 0, 0])).is_err());
+    }
+}
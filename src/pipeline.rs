@@ -0,0 +1,366 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Compressors built from filters and a coder.
+//!
+//! A `Pipeline` applies a sequence of reversible transforms (delta,
+//! move-to-front, Burrows-Wheeler, x86 branch targets, record
+//! transposition, deduplication) to the data and then compresses
+//! the result with one of the methods in this crate.  For example,
+//! `Pipeline::new(Method::Huff).stage(Stage::Bwt(block_size))
+//! .stage(Stage::Mtf)` is a simple block-sorting compressor.
+//!
+//! The stages and the method are recorded in a header, so that
+//! `decompress` can undo them without being told.  The header is the
+//! magic bytes `CPIP`, a version byte, the method id, the number of
+//! stages and a description of each stage: a type byte followed by
+//! the parameters of the stage.
+//!
+//! The stages work on the whole input at once, so the input and the
+//! intermediate results are kept in memory.
+
+use std::io::{self, Cursor, Read, Write};
+
+use bwt;
+use error::Error;
+use filters::dedup;
+use filters::delta;
+use filters::mtf;
+use filters::transpose;
+use filters::x86;
+use method::Method;
+use model::{read_varint, write_varint};
+
+const MAGIC: [u8; 4] = *b"CPIP";
+const VERSION: u8 = 1;
+
+const STAGE_DELTA: u8 = 1;
+const STAGE_MTF: u8 = 2;
+const STAGE_BWT: u8 = 3;
+const STAGE_X86: u8 = 4;
+const STAGE_TRANSPOSE: u8 = 5;
+const STAGE_DEDUP: u8 = 6;
+
+/// Largest number of stages in a pipeline.
+pub const MAX_STAGES: usize = 16;
+
+fn invalid(msg: &'static str) -> io::Error {
+    Error::corrupt(msg).into()
+}
+
+/// A reversible transform applied before compression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Delta or XOR filter, see `filters::delta`.
+    Delta(delta::Filter),
+    /// Move-to-front transform, see `filters::mtf`.
+    Mtf,
+    /// Burrows-Wheeler transform with the given block size, see
+    /// `bwt`.
+    Bwt(usize),
+    /// Branch target filter for x86 code, see `filters::x86`.
+    X86,
+    /// Transposition of records of the given width, see
+    /// `filters::transpose`.
+    Transpose(usize),
+    /// Replacement of repeated chunks by references, see
+    /// `filters::dedup`.
+    Dedup,
+}
+
+impl Stage {
+    /// Return the stage described by `s`, such as `delta:2` or
+    /// `transpose:16`: the name of the stage, optionally followed by a
+    /// colon and a parameter.  Returns `None` for unknown names and
+    /// invalid parameters.
+    pub fn from_name(s: &str) -> Option<Stage> {
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().unwrap();
+        let arg = match parts.next() {
+            Some(a) => match a.parse::<usize>() {
+                Ok(n) if n > 0 => Some(n),
+                _ => return None,
+            },
+            None => None,
+        };
+        match (name, arg) {
+            ("delta", None) => Some(Stage::Delta(delta::Filter::Delta(1))),
+            ("delta", Some(n)) if n <= 255 => Some(Stage::Delta(delta::Filter::Delta(n))),
+            ("delta2", None) => Some(Stage::Delta(delta::Filter::Delta2)),
+            ("xor", Some(n)) if n <= 255 => Some(Stage::Delta(delta::Filter::Xor(n))),
+            ("mtf", None) => Some(Stage::Mtf),
+            ("bwt", None) => Some(Stage::Bwt(bwt::DEFAULT_BLOCK_SIZE)),
+            ("bwt", Some(n)) if n <= u32::max_value() as usize => Some(Stage::Bwt(n)),
+            ("x86", None) => Some(Stage::X86),
+            ("dedup", None) => Some(Stage::Dedup),
+            ("transpose", Some(n)) if n <= transpose::MAX_WIDTH => Some(Stage::Transpose(n)),
+            _ => None,
+        }
+    }
+
+    fn check(&self) {
+        match *self {
+            Stage::Delta(filter) => filter.check().unwrap(),
+            Stage::Mtf | Stage::X86 | Stage::Dedup => (),
+            Stage::Bwt(block_size) =>
+                assert!(block_size > 0 && block_size <= u32::max_value() as usize),
+            Stage::Transpose(width) => assert!(width > 0 && width <= transpose::MAX_WIDTH),
+        }
+    }
+
+    fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        match *self {
+            Stage::Delta(filter) => {
+                try!(output.write_all(&[STAGE_DELTA]));
+                output.write_all(&filter.header())
+            },
+            Stage::Mtf => output.write_all(&[STAGE_MTF]),
+            Stage::Bwt(block_size) => {
+                try!(output.write_all(&[STAGE_BWT]));
+                write_varint(output, block_size as u64)
+            },
+            Stage::X86 => output.write_all(&[STAGE_X86]),
+            Stage::Transpose(width) => {
+                try!(output.write_all(&[STAGE_TRANSPOSE]));
+                write_varint(output, width as u64)
+            },
+            Stage::Dedup => output.write_all(&[STAGE_DEDUP]),
+        }
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> io::Result<Stage> {
+        let mut kind = [0u8; 1];
+        try!(input.read_exact(&mut kind));
+        match kind[0] {
+            STAGE_DELTA => {
+                let mut hdr = [0u8; 2];
+                try!(input.read_exact(&mut hdr));
+                Ok(Stage::Delta(try!(delta::Filter::from_header(hdr))))
+            },
+            STAGE_MTF => Ok(Stage::Mtf),
+            STAGE_BWT => {
+                let block_size = try!(read_varint(input));
+                if block_size == 0 || block_size > u32::max_value() as u64 {
+                    return Err(invalid("invalid BWT block size"));
+                }
+                Ok(Stage::Bwt(block_size as usize))
+            },
+            STAGE_X86 => Ok(Stage::X86),
+            STAGE_TRANSPOSE => {
+                let width = try!(read_varint(input));
+                if width == 0 || width > transpose::MAX_WIDTH as u64 {
+                    return Err(invalid("invalid record width"));
+                }
+                Ok(Stage::Transpose(width as usize))
+            },
+            STAGE_DEDUP => Ok(Stage::Dedup),
+            _ => Err(invalid("unknown pipeline stage")),
+        }
+    }
+
+    fn encode(&self, mut data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        match *self {
+            Stage::Delta(filter) => delta::encode(filter, &mut data),
+            Stage::Mtf => mtf::encode(&mut data),
+            Stage::Bwt(block_size) => return bwt::encode(Cursor::new(data), Vec::new(), block_size),
+            Stage::X86 => x86::encode(&mut data),
+            Stage::Transpose(width) => return Ok(transpose::encode(width, &data)),
+            Stage::Dedup => return Ok(dedup::encode(&data)),
+        }
+        Ok(data)
+    }
+
+    fn decode(&self, mut data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        match *self {
+            Stage::Delta(filter) => delta::decode(filter, &mut data),
+            Stage::Mtf => mtf::decode(&mut data),
+            Stage::Bwt(_) => return bwt::decode(Cursor::new(data), Vec::new()),
+            Stage::X86 => x86::decode(&mut data),
+            Stage::Transpose(width) => return Ok(transpose::decode(width, &data)),
+            Stage::Dedup => return Ok(try!(dedup::decode(&data))),
+        }
+        Ok(data)
+    }
+}
+
+/// A sequence of stages followed by a compression method.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipeline {
+    stages: Vec<Stage>,
+    method: Method,
+}
+
+impl Pipeline {
+    /// Create a pipeline without stages, which compresses with
+    /// `method`.
+    pub fn new(method: Method) -> Pipeline {
+        Pipeline {
+            stages: Vec::new(),
+            method: method,
+        }
+    }
+
+    /// Add `stage` after the stages added so far.  Panics if the
+    /// parameters of the stage are invalid or the pipeline already
+    /// has `MAX_STAGES` stages.
+    pub fn stage(mut self, stage: Stage) -> Pipeline {
+        stage.check();
+        assert!(self.stages.len() < MAX_STAGES);
+        self.stages.push(stage);
+        self
+    }
+
+    /// Return the stages, in the order they are applied when
+    /// compressing.
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages
+    }
+
+    /// Return the compression method.
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    /// Write the header describing the pipeline to `output`.
+    fn write_header<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        try!(output.write_all(&MAGIC));
+        try!(output.write_all(&[VERSION, self.method.id(), self.stages.len() as u8]));
+        for stage in &self.stages {
+            try!(stage.write_to(output));
+        }
+        Ok(())
+    }
+
+    /// Read a header written by `write_header` from `input`.
+    pub fn read_header<R: Read>(input: &mut R) -> io::Result<Pipeline> {
+        let mut hdr = [0u8; 7];
+        try!(input.read_exact(&mut hdr));
+        if hdr[..4] != MAGIC {
+            return Err(Error::InvalidHeader("not a pipeline stream").into());
+        }
+        if hdr[4] != VERSION {
+            return Err(Error::UnsupportedVersion(hdr[4] as u32).into());
+        }
+        let method = match Method::from_id(hdr[5]) {
+            Some(m) => m,
+            None => return Err(invalid("unknown compression method")),
+        };
+        let count = hdr[6] as usize;
+        if count > MAX_STAGES {
+            return Err(Error::LimitExceeded("too many pipeline stages").into());
+        }
+        let mut stages = Vec::with_capacity(count);
+        for _ in 0..count {
+            stages.push(try!(Stage::read_from(input)));
+        }
+        Ok(Pipeline {
+            stages: stages,
+            method: method,
+        })
+    }
+
+    /// Apply the stages to all data from `input`, compress the result
+    /// and write it to `output`, preceded by the header.  On success,
+    /// the output is returned.
+    pub fn compress<R: Read, W: Write>(&self, mut input: R, mut output: W) -> Result<W, Error> {
+        let mut data = Vec::new();
+        try!(input.read_to_end(&mut data));
+        for stage in &self.stages {
+            data = try!(stage.encode(data));
+        }
+        try!(self.write_header(&mut output));
+        self.method.compress(Cursor::new(data), output)
+    }
+}
+
+/// Decompress a stream written by `Pipeline::compress`, undo the
+/// stages named in its header and write the result to `output`.  On
+/// success, the output is returned.
+pub fn decompress<R: Read, W: Write>(mut input: R, mut output: W) -> Result<W, Error> {
+    let pipeline = try!(Pipeline::read_header(&mut input));
+    let mut data = try!(pipeline.method.decompress(input, Vec::new()));
+    for stage in pipeline.stages.iter().rev() {
+        data = try!(stage.decode(data));
+    }
+    try!(output.write_all(&data));
+    Ok(output)
+}
+
+#[cfg(all(test, feature = "lz", feature = "nested"))]
+mod tests {
+    use std::io::Cursor;
+    use bwt;
+    use filters::delta::Filter;
+    use method::Method;
+    use super::{decompress, Pipeline, Stage};
+
+    fn roundtrip(p: &Pipeline, input: &[u8]) -> usize {
+        let compressed = p.compress(Cursor::new(input), vec![]).unwrap();
+        assert_eq!(*p, Pipeline::read_header(&mut Cursor::new(&compressed[..])).unwrap());
+        let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(input, &decompressed[..]);
+        compressed.len()
+    }
+
+    #[test]
+    fn compress_decompress() {
+        let input = include_bytes!("pipeline.rs");
+        roundtrip(&Pipeline::new(Method::Lzss), input);
+        roundtrip(&Pipeline::new(Method::Arith).stage(Stage::Delta(Filter::Delta(2))), input);
+        roundtrip(&Pipeline::new(Method::Store)
+                  .stage(Stage::Bwt(1000))
+                  .stage(Stage::Mtf)
+                  .stage(Stage::Delta(Filter::Xor(3))), input);
+        roundtrip(&Pipeline::new(Method::Lz77).stage(Stage::X86), input);
+        roundtrip(&Pipeline::new(Method::Lzss2)
+                  .stage(Stage::Transpose(16))
+                  .stage(Stage::Delta(Filter::Delta(1))), input);
+        roundtrip(&Pipeline::new(Method::Lzss).stage(Stage::Dedup), input);
+        roundtrip(&Pipeline::new(Method::Huff).stage(Stage::Mtf), b"");
+    }
+
+    #[test]
+    fn block_sorting() {
+        let input = include_bytes!("pipeline.rs");
+        let plain = roundtrip(&Pipeline::new(Method::Arith), input);
+        let sorted = roundtrip(&Pipeline::new(Method::Arith)
+                               .stage(Stage::Bwt(bwt::DEFAULT_BLOCK_SIZE))
+                               .stage(Stage::Mtf), input);
+        assert!(sorted < plain);
+    }
+
+    #[test]
+    fn bad_header() {
+        let read = |b: &[u8]| Pipeline::read_header(&mut Cursor::new(b));
+        assert!(read(b"CPIP\x01\x07\x00").is_ok());
+        assert!(read(b"CPIQ\x01\x07\x00").is_err());
+        assert!(read(b"CPIP\x02\x07\x00").is_err());
+        assert!(read(b"CPIP\x01\x00\x00").is_err());
+        assert!(read(b"CPIP\x01\x07\x11").is_err());
+        assert!(read(b"CPIP\x01\x07\x01\x09").is_err());
+        assert!(read(b"CPIP\x01\x07\x01\x03\x00").is_err());
+        assert!(read(b"CPIP\x01\x07\x01").is_err());
+        assert!(read(b"CPIP\x01\x07\x01\x05\x10").is_ok());
+        assert!(read(b"CPIP\x01\x07\x01\x05\x00").is_err());
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        let p = Pipeline::new(Method::Lzss).stage(Stage::Bwt(5000)).stage(Stage::Mtf);
+        ::testutil::roundtrip((|d: &[u8]| p.compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+    }
+
+    #[test]
+    fn stage_names() {
+        assert_eq!(Some(Stage::Delta(Filter::Delta(1))), Stage::from_name("delta"));
+        assert_eq!(Some(Stage::Delta(Filter::Xor(4))), Stage::from_name("xor:4"));
+        assert_eq!(Some(Stage::Bwt(bwt::DEFAULT_BLOCK_SIZE)), Stage::from_name("bwt"));
+        assert_eq!(Some(Stage::Transpose(16)), Stage::from_name("transpose:16"));
+        assert_eq!(None, Stage::from_name("xor"));
+        assert_eq!(None, Stage::from_name("delta:0"));
+        assert_eq!(None, Stage::from_name("delta:256"));
+        assert_eq!(None, Stage::from_name("mtf:2"));
+        assert_eq!(None, Stage::from_name("sort"));
+    }
+}
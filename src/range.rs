@@ -1,21 +1,46 @@
 // Copyright 2016 Martin Grabmueller. See the LICENSE file at the
 // top-level directory of this distribution for license information.
 
+//! Range coder.
+//!
 //! Adapted from https://github.com/kazuho/rangecoder/blob/master/range_coder.hpp
+//!
+//! A range coder is an arithmetic coder which outputs whole bytes
+//! instead of single bits.  When the low end of the range overflows,
+//! the carry is propagated into the bytes which have not been written
+//! yet: the last byte output is held back in a buffer, together with
+//! a count of the 0xff bytes following it.
+//!
+//! `RangeCoder` and `RangeDecoder` code symbols with caller-provided
+//! cumulative frequency tables.  `Writer` and `Reader` use them with
+//! a probability model from the `model` module, by default the
+//! adaptive order-0 model of the `arith` module.  The stream is
+//! terminated by coding the model's `EOF` symbol.
+//!
+//! `inspect` lists the literals of a stream compressed with the
+//! default model.  The stream has neither a header nor blocks.
 
-use std::io::{Read, Write};
-use std::num::Wrapping;
+use io::{Read, Write};
+use io;
+
+use arith::State;
+use error::{at_offset, Error};
+use inspect::{Event, Events, Inspect, Recorder};
+use counter::Counter;
+use input::ByteReader;
+use model::{Model, Symbol, EOF, MAX_TOTAL};
 
 const TOP: u32 = 1 << 24;
 
+/// Range encoder.
+#[derive(Clone)]
 pub struct RangeCoder<W> {
     ll: u32,
     rr: u32,
     buffer: u8,
     start: bool,
     carry_n: usize,
-    counter: usize,
-    inner: W,
+    inner: Counter<W>,
 }
 
 impl<W: Write> RangeCoder<W> {
@@ -25,51 +50,58 @@ impl<W: Write> RangeCoder<W> {
             rr: 0xffffffff,
             buffer: 0,
             carry_n: 0,
-            counter: 0,
             start: true,
-            inner: output,
+            inner: Counter::new(output),
         }
     }
-    
-    pub fn encode(&mut self, symbol: u32, cum_freq: &[u32]) {
+
+    /// Encode `symbol` with the cumulative frequencies `cum_freq`.
+    /// Symbol `i` occupies the range `cum_freq[i]..cum_freq[i + 1]`,
+    /// and the last entry of the table is the total frequency, which
+    /// must not exceed 2^24.
+    pub fn encode(&mut self, symbol: u32, cum_freq: &[u32]) -> io::Result<()> {
         let lo = cum_freq[symbol as usize];
         let hi = cum_freq[symbol as usize + 1];
         let total = cum_freq[cum_freq.len() - 1];
+        self.encode_range(lo, hi, total)
+    }
+
+    /// Encode a symbol which occupies the range `lo..hi` of the
+    /// total frequency `total`.
+    pub fn encode_range(&mut self, lo: u32, hi: u32, total: u32) -> io::Result<()> {
+        debug_assert!(lo < hi && hi <= total && total <= TOP);
+
         let r = self.rr / total;
-        println!("lo={} hi={} total={} r={}", lo, hi, total, r);
         if hi < total {
             self.rr = r * (hi - lo);
         } else {
             self.rr -= r * lo;
         }
-        println!("rr={:08x}", self.rr);
-        let new_ll = (Wrapping(self.ll) + Wrapping(r) * Wrapping(lo)).0;
+        let new_ll = self.ll.wrapping_add(r * lo);
         if new_ll < self.ll {
-            self.buffer += 1;
+            // Carry: increment the buffered byte, and turn the 0xff
+            // bytes behind it into zeros.
+            self.buffer = self.buffer.wrapping_add(1);
             while self.carry_n != 0 {
-                println!("{:2x}", self.buffer);
                 let buffer = self.buffer;
-                self.putc(buffer);
+                try!(self.putc(buffer));
                 self.buffer = 0;
                 self.carry_n -= 1;
             }
         }
         self.ll = new_ll;
-        println!("ll={:08x}", self.ll);
         while self.rr < TOP {
-            let new_buffer = (self.ll > 24) as u8;
+            let new_buffer = (self.ll >> 24) as u8;
             if self.start {
                 self.buffer = new_buffer;
                 self.start = false;
             } else if new_buffer == 0xff {
                 self.carry_n += 1;
             } else {
-                println!("{:2x}", self.buffer);
                 let buffer = self.buffer;
-                self.putc(buffer);
+                try!(self.putc(buffer));
                 while self.carry_n != 0 {
-                    println!("{:2x}", 0xffu8);
-                    self.putc(0xff);
+                    try!(self.putc(0xff));
                     self.carry_n -= 1;
                 }
                 self.buffer = new_buffer;
@@ -77,174 +109,409 @@ impl<W: Write> RangeCoder<W> {
             self.ll <<= 8;
             self.rr <<= 8;
         }
-        println!("final ll={:08x}", self.ll);
-        self.counter += 1;
+        Ok(())
     }
 
-    pub fn finish(&mut self) {
-        let buffer = self.buffer;
-        self.putc(buffer);
-        println!("{:2x}", self.buffer);
-        while self.carry_n != 0 {
-            println!("{:2x}", 0xffu8);
-            self.putc(0xff);
-            self.carry_n -= 1;
+    /// Write out the buffered bytes and as many bytes of the low end
+    /// of the range as are needed to identify it.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.start {
+            let buffer = self.buffer;
+            try!(self.putc(buffer));
+            while self.carry_n != 0 {
+                try!(self.putc(0xff));
+                self.carry_n -= 1;
+            }
         }
-        let mut t = self.ll + self.rr;
+        // The decoder fills the stream up with 0xff bytes, so we can
+        // stop at the first byte where the low and high end differ.
+        let mut t = self.ll.wrapping_add(self.rr);
         loop {
             let t8 = t >> 24;
             let l8 = self.ll >> 24;
-            
-            self.putc(l8 as u8);
-            println!("{:2x}", l8);
-            
+            try!(self.putc(l8 as u8));
             if t8 != l8 {
-	        break;
+                break;
             }
             t <<= 8;
             self.ll <<= 8;
         }
+        self.inner.flush()
     }
 
-    fn putc(&mut self, b: u8) {
-        let nwritten = self.inner.write(&[b]).expect("could not write");
-        assert_eq!(1, nwritten);
+    fn putc(&mut self, b: u8) -> io::Result<()> {
+        self.inner.write_all(&[b])
     }
-    
+
     pub fn to_inner(self) -> W {
-        self.inner
+        self.inner.into_inner()
     }
 }
 
+/// Range decoder.
 pub struct RangeDecoder<R> {
     rr: u32,
     dd: u32,
-    inner: R,
+    /// Range of the symbol being decoded, as computed by
+    /// `decode_freq`.
+    r: u32,
+    padding: usize,
+    inner: ByteReader<R>,
 }
 
 impl<R: Read> RangeDecoder<R> {
-    pub fn new(input: R) -> RangeDecoder<R> {
+    /// Create a new decoder.  This reads the first four bytes of the
+    /// input, therefore the result can be an error.
+    pub fn new(input: R) -> io::Result<RangeDecoder<R>> {
         let mut rd = RangeDecoder {
             rr: 0xffffffff,
             dd: 0,
-            inner: input,
+            r: 0,
+            padding: 4,
+            inner: ByteReader::new(input),
         };
         for _ in 0..4 {
-            rd.dd = (rd.dd << 8) | (rd.next() as u32);
+            rd.dd = (rd.dd << 8) | (try!(rd.next()) as u32);
         }
-        println!("dd primed: {:08x}", rd.dd);
-        rd
+        Ok(rd)
     }
 
-    pub fn next(&mut self) -> u8 {
-        let mut buf = [0u8; 1];
-        let nread = self.inner.read(&mut buf[..]).expect("cannot read");
-        if nread == 1 {
-            buf[0]
+    fn next(&mut self) -> io::Result<u8> {
+        if let Some(b) = try!(self.inner.next_byte()) {
+            Ok(b)
+        } else if self.padding > 0 {
+            // The encoder leaves off trailing 0xff bytes.  The
+            // decoder never reads more than four bytes past the end.
+            self.padding -= 1;
+            Ok(0xff)
         } else {
-            0xff
+            Err(Error::UnexpectedEof.into())
         }
     }
 
-    pub fn decode(&mut self, cum_freq: &[u32]) -> u32 {
+    /// Decode a symbol coded with the cumulative frequencies
+    /// `cum_freq`.
+    pub fn decode(&mut self, cum_freq: &[u32]) -> io::Result<u32> {
         let total = cum_freq[cum_freq.len() - 1];
-        
-        println!("in: rr={:08x}, dd={:08x}", self.rr, self.dd);
-        
-        let r = self.rr / total;
-        
-        println!("r={} (total={}), dd/r={}", r, total, self.dd / r);
-        
-        let target_pos = ::std::cmp::min(total - 1, self.dd / r);
-        
-        println!("target_pos={}", target_pos);
-        
+        let target_pos = self.decode_freq(total);
         let mut index = 0;
-        for i in 0..cum_freq.len() {
-            if target_pos < cum_freq[i] {
-                index = i - 1;
-                break;
-            }
+        while cum_freq[index + 1] <= target_pos {
+            index += 1;
         }
-        
-        println!("index={}", index);
-        
-        let lo = cum_freq[index];
-        
-        println!("lo={}", lo);
-        
-        let hi = cum_freq[index + 1];
-        
-        println!("hi={}", hi);
-
-        self.dd -= r * lo;
-        
-        println!("dd={:08x}", self.dd);
-        
+        try!(self.decode_update(cum_freq[index], cum_freq[index + 1], total));
+        Ok(index as u32)
+    }
+
+    /// Return the cumulative frequency of the next symbol, given the
+    /// total frequency `total`.  The caller must then look up the
+    /// symbol's range and pass it to `decode_update`.
+    pub fn decode_freq(&mut self, total: u32) -> u32 {
+        self.r = self.rr / total;
+        ::core::cmp::min(total - 1, self.dd / self.r)
+    }
+
+    /// Remove the symbol which occupies the range `lo..hi` from the
+    /// input.
+    pub fn decode_update(&mut self, lo: u32, hi: u32, total: u32) -> io::Result<()> {
+        self.dd -= self.r * lo;
         if hi != total {
-            self.rr = r * (hi - lo);
+            self.rr = self.r * (hi - lo);
         } else {
-            self.rr -= r * lo;
+            self.rr -= self.r * lo;
         }
-        
-        println!("rr={:08x}", self.rr);
-        
         while self.rr < TOP {
             self.rr <<= 8;
-            self.dd = (self.dd << 8) | (self.next() as u32);
-            
-            println!("reloaded rr={:08x}", self.rr);
+            self.dd = (self.dd << 8) | (try!(self.next()) as u32);
+        }
+        Ok(())
+    }
+}
+
+/// Streaming range compressor.  Calling `flush` encodes the end of
+/// the stream, so no more data may be written afterwards.
+#[derive(Clone)]
+pub struct Writer<W, M> {
+    coder: RangeCoder<W>,
+    model: M,
+    finished: bool,
+    total_in: u64,
+}
+
+impl<W: Write> Writer<W, State> {
+    /// Create a new writer with the adaptive order-0 model.
+    pub fn new(output: W) -> Writer<W, State> {
+        Writer::with_model(output, State::new())
+    }
+}
+
+impl<W: Write, M: Model> Writer<W, M> {
+    /// Create a new writer which takes probabilities from `model`.
+    pub fn with_model(output: W, model: M) -> Writer<W, M> {
+        Writer {
+            coder: RangeCoder::new(output),
+            model: model,
+            finished: false,
+            total_in: 0,
+        }
+    }
+
+    fn encode_symbol(&mut self, sym: Symbol) -> io::Result<()> {
+        let p = self.model.prob(sym);
+        self.model.update(sym);
+        debug_assert!(p.total <= MAX_TOTAL);
+        if p.low >= p.high {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "symbol has zero frequency in model"));
+        }
+        self.coder.encode_range(p.low as u32, p.high as u32, p.total as u32)
+    }
+
+    /// Encode the end of stream and write out all buffered bytes.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            try!(self.encode_symbol(EOF));
+            try!(self.coder.finish());
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    /// Return the number of uncompressed bytes written so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Return the number of compressed bytes written to the wrapped
+    /// writer so far.
+    pub fn total_out(&self) -> u64 {
+        self.coder.inner.count()
+    }
+
+    /// Move the wrapped writer out of the range writer.
+    pub fn into_inner(self) -> W {
+        self.coder.to_inner()
+    }
+}
+
+impl<W: Write, M: Model> Write for Writer<W, M> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        for b in data {
+            try!(self.encode_symbol(*b as Symbol));
+        }
+        self.total_in += data.len() as u64;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.finish()
+    }
+}
+
+/// Streaming range decompressor.
+pub struct Reader<R, M> {
+    decoder: RangeDecoder<R>,
+    model: M,
+    eof: bool,
+    total_out: u64,
+    recorder: Recorder,
+}
+
+impl<R: Read> Reader<R, State> {
+    /// Create a new reader with the adaptive order-0 model.
+    pub fn new(input: R) -> io::Result<Reader<R, State>> {
+        Reader::with_model(input, State::new())
+    }
+}
+
+impl<R: Read, M: Model> Reader<R, M> {
+    /// Create a new reader which takes probabilities from `model`.
+    pub fn with_model(input: R, model: M) -> io::Result<Reader<R, M>> {
+        Ok(Reader {
+            decoder: try!(RangeDecoder::new(input)),
+            model: model,
+            eof: false,
+            total_out: 0,
+            recorder: Recorder::new(),
+        })
+    }
+
+    /// Return the number of compressed bytes consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.decoder.inner.consumed()
+    }
+
+    /// Return the number of decompressed bytes read so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    fn decode_symbol(&mut self) -> io::Result<Symbol> {
+        let total = self.model.total();
+        let cum = self.decoder.decode_freq(total as u32);
+        let sym = self.model.symbol_for(cum as u64);
+        let p = self.model.prob(sym);
+        self.model.update(sym);
+        try!(self.decoder.decode_update(p.low as u32, p.high as u32, p.total as u32));
+        Ok(sym)
+    }
+}
+
+impl<R: Read, M: Model> Read for Reader<R, M> {
+    fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
+        let mut nread = 0;
+        while nread < output.len() && !self.eof {
+            let c = try!(self.decode_symbol().map_err(|e| at_offset(e, self.total_in())));
+            if c == EOF {
+                self.recorder.record(Event::End);
+                self.eof = true;
+            } else {
+                self.recorder.record(Event::Literal(c as u8));
+                output[nread] = c as u8;
+                nread += 1;
+            }
         }
-        
-        println!("out: symbol={}. rr={:08x}, dd={:08x}", index, self.rr, self.dd);
-        
-        index as u32
+        self.total_out += nread as u64;
+        Ok(nread)
+    }
+}
+
+impl<R: Read, M: Model> Inspect for Reader<R, M> {
+    fn recorder(&mut self) -> &mut Recorder {
+        &mut self.recorder
     }
 }
 
-#[cfg(test)]
+/// Compress all data from `input` with the range coder and the
+/// adaptive order-0 model and write it to `output`.  On success, the
+/// output is returned.
+pub fn compress<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error> {
+    let mut cw = Writer::new(output);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.into_inner())
+}
+
+/// Decompress data produced by `compress`.  On success, the output
+/// is returned.
+pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<W, Error> {
+    let mut cr = try!(Reader::new(input));
+    try!(io::copy(&mut cr, &mut output));
+    Ok(output)
+}
+
+/// Return an iterator over the literals of the stream in `input`,
+/// which was compressed by `compress`.  The first event is a header
+/// naming the model, which is not stored in the stream.  This reads
+/// the first bytes of the input, therefore the result can be an
+/// error.
+pub fn inspect<R: Read>(input: R) -> io::Result<Events<Reader<R, State>>> {
+    let mut reader = try!(Reader::new(input));
+    reader.recorder.enable();
+    reader.recorder.record(Event::Header("adaptive order-0 model".into()));
+    Ok(Events::new(reader))
+}
+
+#[cfg(all(test, feature = "std"))]
 mod test {
-    use super::{RangeCoder, RangeDecoder};
+    use super::{RangeCoder, RangeDecoder, compress, decompress, inspect};
     use ::std::io::Cursor;
+    use inspect::Event;
+
+    #[test]
+    fn inspect_events() {
+        let compressed = compress(Cursor::new(b"ab"), vec![]).unwrap();
+        let events: Vec<Event> = inspect(Cursor::new(compressed)).unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![Event::Header("adaptive order-0 model".into()), Event::Literal(b'a'),
+                        Event::Literal(b'b'), Event::End], events);
+    }
 
     // Text: 0,1,0,2,3
     // Symbols:   0,1,2,3
-    // Freq:      2,1,1,1
-    // Cum. Freq: 0,2,3,4,5
+    // Freq:      1,1,1,1
+    // Cum. Freq: 0,1,2,3,4
     static CUM_FREQ: [u32; 5] = [0,1,2,3,4];
 
     #[test]
     fn encode_1() {
         let mut rc = RangeCoder::new(vec![]);
 
-        rc.encode(0, &CUM_FREQ);
-        rc.encode(1, &CUM_FREQ);
-        rc.encode(0, &CUM_FREQ);
-        rc.encode(2, &CUM_FREQ);
-        rc.encode(3, &CUM_FREQ);
-        rc.finish();
+        rc.encode(0, &CUM_FREQ).unwrap();
+        rc.encode(1, &CUM_FREQ).unwrap();
+        rc.encode(0, &CUM_FREQ).unwrap();
+        rc.encode(2, &CUM_FREQ).unwrap();
+        rc.encode(3, &CUM_FREQ).unwrap();
+        rc.finish().unwrap();
 
         let coded = rc.to_inner();
-        let expected = [0x01u8, 0x2f];
+        let expected = [0x12u8, 0xbf];
         assert_eq!(&expected[..], &coded[..]);
-        assert!(false);
    }
 
     #[test]
     fn decode_1() {
-        let mut rc = RangeDecoder::new(Cursor::new(vec![0x01, 0x2f]));
+        let mut rc = RangeDecoder::new(Cursor::new(vec![0x12, 0xbf])).unwrap();
 
-        let c = rc.decode(&CUM_FREQ);
+        let c = rc.decode(&CUM_FREQ).unwrap();
         assert_eq!(0, c);
-        let c = rc.decode(&CUM_FREQ);
+        let c = rc.decode(&CUM_FREQ).unwrap();
         assert_eq!(1, c);
-        let c = rc.decode(&CUM_FREQ);
+        let c = rc.decode(&CUM_FREQ).unwrap();
         assert_eq!(0, c);
-        let c = rc.decode(&CUM_FREQ);
+        let c = rc.decode(&CUM_FREQ).unwrap();
         assert_eq!(2, c);
-        let c = rc.decode(&CUM_FREQ);
+        let c = rc.decode(&CUM_FREQ).unwrap();
         assert_eq!(3, c);
-
-        assert!(false);
    }
+
+    #[test]
+    fn carry() {
+        // Coding the last symbol of a skewed table repeatedly moves
+        // the low end up and produces carries.
+        let cum_freq = [0, 1, 1 << 20];
+        let mut symbols = vec![];
+        for i in 0..2000 {
+            symbols.push(if i % 97 == 0 { 0 } else { 1 });
+        }
+        let mut rc = RangeCoder::new(vec![]);
+        for &s in &symbols {
+            rc.encode(s, &cum_freq).unwrap();
+        }
+        rc.finish().unwrap();
+        let coded = rc.to_inner();
+
+        let mut rd = RangeDecoder::new(Cursor::new(coded)).unwrap();
+        for &s in &symbols {
+            assert_eq!(s, rd.decode(&cum_freq).unwrap());
+        }
+    }
+
+    #[test]
+    fn compress_empty() {
+        let compressed = compress(Cursor::new(&b""[..]), vec![]).unwrap();
+        let decompressed = decompress(Cursor::new(compressed), vec![]).unwrap();
+        assert_eq!(&b""[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn compress_decompress() {
+        let input = include_bytes!("range.rs");
+        let compressed = compress(Cursor::new(&input[..]), vec![]).unwrap();
+        assert!(compressed.len() < input.len());
+        let decompressed = decompress(Cursor::new(compressed), vec![]).unwrap();
+        assert_eq!(&input[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn truncated() {
+        let input = include_bytes!("range.rs");
+        let compressed = compress(Cursor::new(&input[..]), vec![]).unwrap();
+        let len = compressed.len() / 2;
+        assert!(decompress(Cursor::new(&compressed[..len]), vec![]).is_err());
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+    }
 }
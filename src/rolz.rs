@@ -0,0 +1,603 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! ROLZ (reduced offset Lempel-Ziv) compressor.
+//!
+//! Like LZ77, this replaces repeated strings by references to earlier
+//! occurrences, but instead of an arbitrary offset into the window, a
+//! match is identified by an index into a short list of recent
+//! positions which followed the same context byte.  The compressor
+//! and the decompressor maintain identical lists: for each byte value
+//! `c`, the table for context `c` holds the last 256 positions which
+//! were preceded by `c`, most recent first.  Since the index of a
+//! match is only 8 bits wide, even short matches pay off, which makes
+//! ROLZ do well on text.
+//!
+//! All symbols are coded with the binary arithmetic coder from the
+//! `binarith` module:
+//!
+//! - literal/match flags by the kind of the previous token,
+//!
+//! - literals bitwise along a binary tree, with the full previous
+//!   byte as context,
+//!
+//! - match lengths by a three-level length coder,
+//!
+//! - match indexes along a binary tree, using the match length as
+//!   context.
+//!
+//! The end of the stream is marked by a match with a reserved length.
+//!
+//! `inspect` lists the literals and matches of a stream, with the
+//! offsets the table indexes stand for.
+
+use std::io::{Read, Write};
+use std::io;
+
+use error::{at_offset, Error};
+use binarith::{Encoder, Decoder, Bit, Count};
+use inspect::{Event, Events, Inspect, Recorder};
+
+const WINDOW_BITS: usize = 20;
+const WINDOW_SIZE: usize = 1 << WINDOW_BITS;
+
+const TABLE_BITS: usize = 8;
+const TABLE_SIZE: usize = 1 << TABLE_BITS;
+const TABLE_MASK: usize = TABLE_SIZE - 1;
+
+const MIN_MATCH_LEN: usize = 2;
+const LEN_LOW_SYMBOLS: usize = 8;
+const LEN_MID_SYMBOLS: usize = 8;
+const LEN_HIGH_SYMBOLS: usize = 256;
+/// The largest length code is reserved for the end of stream marker.
+const END_LEN: usize =
+    MIN_MATCH_LEN + LEN_LOW_SYMBOLS + LEN_MID_SYMBOLS + LEN_HIGH_SYMBOLS - 1;
+const MAX_MATCH_LEN: usize = END_LEN - 1;
+
+/// Matches of minimal length are only used for indexes below this
+/// limit, because otherwise two literals are usually cheaper.
+const MAX_SHORT_INDEX: usize = 16;
+
+const LEN_STATES: usize = 4;
+
+/// Counts of an adaptive bit model are halved when their sum exceeds
+/// this limit, so that the model follows changing statistics.
+const MODEL_LIMIT: Count = 1 << 10;
+const MODEL_INC: Count = 24;
+
+#[derive(Clone, Copy)]
+struct BitModel {
+    c0: Count,
+    c1: Count,
+}
+
+impl BitModel {
+    fn new() -> BitModel {
+        BitModel { c0: 1, c1: 1 }
+    }
+
+    fn update(&mut self, bit: Bit) {
+        if bit == 0 {
+            self.c0 += MODEL_INC;
+        } else {
+            self.c1 += MODEL_INC;
+        }
+        if self.c0 + self.c1 > MODEL_LIMIT {
+            self.c0 = (self.c0 + 1) / 2;
+            self.c1 = (self.c1 + 1) / 2;
+        }
+    }
+}
+
+fn encode_bit<W: Write>(enc: &mut Encoder<W>, model: &mut BitModel, bit: Bit) -> io::Result<()> {
+    try!(enc.encode(bit, model.c0, model.c1));
+    model.update(bit);
+    Ok(())
+}
+
+fn decode_bit<R: Read>(dec: &mut Decoder<R>, model: &mut BitModel) -> io::Result<Bit> {
+    let bit = try!(dec.decode(model.c0, model.c1));
+    model.update(bit);
+    Ok(bit)
+}
+
+/// Encode the lowest `bits` bits of `value`, most significant bit
+/// first, along a binary tree of models.
+fn encode_tree<W: Write>(enc: &mut Encoder<W>, models: &mut [BitModel], bits: usize,
+                         value: usize) -> io::Result<()> {
+    let mut node = 1;
+    for i in (0..bits).rev() {
+        let bit = (value >> i) & 1;
+        try!(encode_bit(enc, &mut models[node], bit));
+        node = (node << 1) | bit;
+    }
+    Ok(())
+}
+
+fn decode_tree<R: Read>(dec: &mut Decoder<R>, models: &mut [BitModel],
+                        bits: usize) -> io::Result<usize> {
+    let mut node = 1;
+    for _ in 0..bits {
+        let bit = try!(decode_bit(dec, &mut models[node]));
+        node = (node << 1) | bit;
+    }
+    Ok(node - (1 << bits))
+}
+
+/// Per-context lists of recent positions.  Positions are absolute
+/// stream positions, stored off by one, so that 0 means "no
+/// position".
+struct Table {
+    positions: Vec<u64>,
+    heads: Vec<usize>,
+}
+
+impl Table {
+    fn new() -> Table {
+        Table {
+            positions: vec![0; 256 * TABLE_SIZE],
+            heads: vec![0; 256],
+        }
+    }
+
+    /// Return the position at index `idx` for context `ctx`, where
+    /// index 0 is the most recently inserted position.
+    #[inline(always)]
+    fn get(&self, ctx: u8, idx: usize) -> Option<u64> {
+        let c = ctx as usize;
+        let slot = (self.heads[c] + TABLE_SIZE - 1 - idx) & TABLE_MASK;
+        match self.positions[c * TABLE_SIZE + slot] {
+            0 => None,
+            p => Some(p - 1),
+        }
+    }
+
+    #[inline(always)]
+    fn insert(&mut self, ctx: u8, pos: u64) {
+        let c = ctx as usize;
+        self.positions[c * TABLE_SIZE + self.heads[c]] = pos + 1;
+        self.heads[c] = (self.heads[c] + 1) & TABLE_MASK;
+    }
+}
+
+/// Complete adaptive model shared by compressor and decompressor.
+struct Model {
+    prev_match: usize,
+    is_match: Vec<BitModel>,
+    literal: Vec<BitModel>,
+    len_choice: BitModel,
+    len_choice2: BitModel,
+    len_low: Vec<BitModel>,
+    len_mid: Vec<BitModel>,
+    len_high: Vec<BitModel>,
+    index: Vec<BitModel>,
+}
+
+impl Model {
+    fn new() -> Model {
+        Model {
+            prev_match: 0,
+            is_match: vec![BitModel::new(); 2],
+            literal: vec![BitModel::new(); 256 * 256],
+            len_choice: BitModel::new(),
+            len_choice2: BitModel::new(),
+            len_low: vec![BitModel::new(); LEN_LOW_SYMBOLS],
+            len_mid: vec![BitModel::new(); LEN_MID_SYMBOLS],
+            len_high: vec![BitModel::new(); LEN_HIGH_SYMBOLS],
+            index: vec![BitModel::new(); LEN_STATES << TABLE_BITS],
+        }
+    }
+
+    fn literal_models(&mut self, prev_byte: u8) -> &mut [BitModel] {
+        let ctx = prev_byte as usize;
+        &mut self.literal[ctx << 8..(ctx + 1) << 8]
+    }
+
+    fn index_models(&mut self, len: usize) -> &mut [BitModel] {
+        let len_state = ::std::cmp::min(len - MIN_MATCH_LEN, LEN_STATES - 1);
+        &mut self.index[len_state << TABLE_BITS..(len_state + 1) << TABLE_BITS]
+    }
+
+    fn encode_literal<W: Write>(&mut self, enc: &mut Encoder<W>,
+                                prev_byte: u8, lit: u8) -> io::Result<()> {
+        try!(encode_bit(enc, &mut self.is_match[self.prev_match], 0));
+        try!(encode_tree(enc, self.literal_models(prev_byte), 8, lit as usize));
+        self.prev_match = 0;
+        Ok(())
+    }
+
+    fn encode_len<W: Write>(&mut self, enc: &mut Encoder<W>, len: usize) -> io::Result<()> {
+        let l = len - MIN_MATCH_LEN;
+        if l < LEN_LOW_SYMBOLS {
+            try!(encode_bit(enc, &mut self.len_choice, 0));
+            encode_tree(enc, &mut self.len_low, 3, l)
+        } else if l < LEN_LOW_SYMBOLS + LEN_MID_SYMBOLS {
+            try!(encode_bit(enc, &mut self.len_choice, 1));
+            try!(encode_bit(enc, &mut self.len_choice2, 0));
+            encode_tree(enc, &mut self.len_mid, 3, l - LEN_LOW_SYMBOLS)
+        } else {
+            try!(encode_bit(enc, &mut self.len_choice, 1));
+            try!(encode_bit(enc, &mut self.len_choice2, 1));
+            encode_tree(enc, &mut self.len_high, 8, l - LEN_LOW_SYMBOLS - LEN_MID_SYMBOLS)
+        }
+    }
+
+    fn decode_len<R: Read>(&mut self, dec: &mut Decoder<R>) -> io::Result<usize> {
+        let l = if try!(decode_bit(dec, &mut self.len_choice)) == 0 {
+            try!(decode_tree(dec, &mut self.len_low, 3))
+        } else if try!(decode_bit(dec, &mut self.len_choice2)) == 0 {
+            LEN_LOW_SYMBOLS + try!(decode_tree(dec, &mut self.len_mid, 3))
+        } else {
+            LEN_LOW_SYMBOLS + LEN_MID_SYMBOLS + try!(decode_tree(dec, &mut self.len_high, 8))
+        };
+        Ok(l + MIN_MATCH_LEN)
+    }
+
+    fn encode_match<W: Write>(&mut self, enc: &mut Encoder<W>,
+                              len: usize, idx: usize) -> io::Result<()> {
+        try!(encode_bit(enc, &mut self.is_match[self.prev_match], 1));
+        try!(self.encode_len(enc, len));
+        try!(encode_tree(enc, self.index_models(len), TABLE_BITS, idx));
+        self.prev_match = 1;
+        Ok(())
+    }
+
+    fn encode_end<W: Write>(&mut self, enc: &mut Encoder<W>) -> io::Result<()> {
+        try!(encode_bit(enc, &mut self.is_match[self.prev_match], 1));
+        self.encode_len(enc, END_LEN)
+    }
+}
+
+/// Writer for ROLZ compression.
+pub struct Writer<W> {
+    encoder: Encoder<W>,
+    model: Model,
+    table: Table,
+    buf: Vec<u8>,
+    pos: usize,
+    /// Number of bytes dropped from the front of `buf`.
+    offset: u64,
+    finished: bool,
+}
+
+impl<W: Write> Writer<W> {
+    /// Create a new ROLZ writer.
+    pub fn new(inner: W) -> Writer<W> {
+        Writer {
+            encoder: Encoder::new(inner),
+            model: Model::new(),
+            table: Table::new(),
+            buf: Vec::new(),
+            pos: 0,
+            offset: 0,
+            finished: false,
+        }
+    }
+
+    fn context(&self, pos: usize) -> u8 {
+        if pos > 0 { self.buf[pos - 1] } else { 0 }
+    }
+
+    /// Find the longest match for `pos` in the position list of its
+    /// context.  Returns the match length and the list index.
+    fn find_match(&self, pos: usize) -> (usize, usize) {
+        let max_len = ::std::cmp::min(MAX_MATCH_LEN, self.buf.len() - pos);
+        let mut best_len = 0;
+        let mut best_idx = 0;
+        if max_len < MIN_MATCH_LEN {
+            return (0, 0);
+        }
+        let ctx = self.context(pos);
+        let abs = self.offset + pos as u64;
+        for idx in 0..TABLE_SIZE {
+            let c = match self.table.get(ctx, idx) {
+                Some(p) if abs - p <= WINDOW_SIZE as u64 => (p - self.offset) as usize,
+                _ => break,
+            };
+            if self.buf[c + best_len] == self.buf[pos + best_len] {
+                let mut len = 0;
+                while len < max_len && self.buf[c + len] == self.buf[pos + len] {
+                    len += 1;
+                }
+                if len > best_len && (len > MIN_MATCH_LEN || idx < MAX_SHORT_INDEX) {
+                    best_len = len;
+                    best_idx = idx;
+                    if len == max_len {
+                        break;
+                    }
+                }
+            }
+        }
+        (best_len, best_idx)
+    }
+
+    fn insert(&mut self, pos: usize) {
+        let ctx = self.context(pos);
+        self.table.insert(ctx, self.offset + pos as u64);
+    }
+
+    /// Drop the oldest window's worth of data from the buffer once
+    /// enough data has been processed.
+    fn slide(&mut self) {
+        if self.pos >= 2 * WINDOW_SIZE {
+            self.buf.drain(..WINDOW_SIZE);
+            self.pos -= WINDOW_SIZE;
+            self.offset += WINDOW_SIZE as u64;
+        }
+    }
+
+    /// Parse and encode input while at least `lookahead` bytes are
+    /// available, using one step of lazy matching.
+    fn process(&mut self, lookahead: usize) -> io::Result<()> {
+        while self.pos < self.buf.len() && self.buf.len() - self.pos >= lookahead {
+            let pos = self.pos;
+            let (len, idx) = self.find_match(pos);
+            self.insert(pos);
+            let prev_byte = self.context(pos);
+            if len >= MIN_MATCH_LEN {
+                let (next_len, _) = if pos + 1 < self.buf.len() {
+                    self.find_match(pos + 1)
+                } else {
+                    (0, 0)
+                };
+                if next_len > len + 1 {
+                    let lit = self.buf[pos];
+                    try!(self.model.encode_literal(&mut self.encoder, prev_byte, lit));
+                    self.pos += 1;
+                } else {
+                    try!(self.model.encode_match(&mut self.encoder, len, idx));
+                    for p in pos + 1..pos + len {
+                        self.insert(p);
+                    }
+                    self.pos += len;
+                }
+            } else {
+                let lit = self.buf[pos];
+                try!(self.model.encode_literal(&mut self.encoder, prev_byte, lit));
+                self.pos += 1;
+            }
+            self.slide();
+        }
+        Ok(())
+    }
+
+    /// Encode all pending data and the end of stream marker.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            try!(self.process(1));
+            try!(self.model.encode_end(&mut self.encoder));
+            try!(self.encoder.finish());
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    /// Return the number of uncompressed bytes written so far.
+    pub fn total_in(&self) -> u64 {
+        self.offset + self.buf.len() as u64
+    }
+
+    /// Return the number of compressed bytes written to the wrapped
+    /// writer so far.
+    pub fn total_out(&self) -> u64 {
+        self.encoder.bytes_written()
+    }
+
+    /// Move the wrapped writer out of the ROLZ writer.
+    pub fn into_inner(self) -> W {
+        self.encoder.into_inner()
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        try!(self.process(MAX_MATCH_LEN + 1));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.finish()
+    }
+}
+
+/// Reader for ROLZ compressed streams.
+pub struct Reader<R> {
+    decoder: Decoder<R>,
+    model: Model,
+    table: Table,
+    window: Vec<u8>,
+    returned: usize,
+    /// Number of bytes dropped from the front of `window`.
+    offset: u64,
+    eof: bool,
+    recorder: Recorder,
+}
+
+impl<R: Read> Reader<R> {
+    /// Create a new ROLZ reader.  This starts decoding, therefore the
+    /// result can be an error.
+    pub fn new(inner: R) -> io::Result<Reader<R>> {
+        Ok(Reader {
+            decoder: try!(Decoder::new(inner)),
+            model: Model::new(),
+            table: Table::new(),
+            window: Vec::new(),
+            returned: 0,
+            offset: 0,
+            eof: false,
+            recorder: Recorder::new(),
+        })
+    }
+
+    /// Return the number of compressed bytes consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.decoder.bytes_read()
+    }
+
+    /// Return the number of decompressed bytes read so far.
+    pub fn total_out(&self) -> u64 {
+        self.offset + self.returned as u64
+    }
+
+    /// Append `b` to the window and enter its position into the
+    /// table.
+    fn push(&mut self, b: u8) {
+        let ctx = self.window.last().cloned().unwrap_or(0);
+        let pos = self.offset + self.window.len() as u64;
+        self.table.insert(ctx, pos);
+        self.window.push(b);
+    }
+
+    fn decode_token(&mut self) -> io::Result<()> {
+        let prev_byte = self.window.last().cloned().unwrap_or(0);
+        let prev_match = self.model.prev_match;
+        if try!(decode_bit(&mut self.decoder, &mut self.model.is_match[prev_match])) == 0 {
+            let lit = try!(decode_tree(&mut self.decoder,
+                                       self.model.literal_models(prev_byte), 8));
+            self.recorder.record(Event::Literal(lit as u8));
+            self.push(lit as u8);
+            self.model.prev_match = 0;
+            return Ok(());
+        }
+
+        let len = try!(self.model.decode_len(&mut self.decoder));
+        if len == END_LEN {
+            self.recorder.record(Event::End);
+            self.eof = true;
+            return Ok(());
+        }
+        let idx = try!(decode_tree(&mut self.decoder, self.model.index_models(len), TABLE_BITS));
+        let start = match self.table.get(prev_byte, idx) {
+            Some(p) if p >= self.offset => (p - self.offset) as usize,
+            _ => return Err(Error::corrupt("ROLZ match index out of range").into()),
+        };
+        self.recorder.record(Event::Match { offset: self.window.len() - start, len: len });
+        for i in 0..len {
+            let b = self.window[start + i];
+            self.push(b);
+        }
+        self.model.prev_match = 1;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
+        while !self.eof && self.window.len() - self.returned < output.len() {
+            try!(self.decode_token().map_err(|e| at_offset(e, self.total_in())));
+        }
+        let n = ::std::cmp::min(output.len(), self.window.len() - self.returned);
+        output[..n].copy_from_slice(&self.window[self.returned..self.returned + n]);
+        self.returned += n;
+        if self.returned >= 2 * WINDOW_SIZE {
+            self.window.drain(..WINDOW_SIZE);
+            self.returned -= WINDOW_SIZE;
+            self.offset += WINDOW_SIZE as u64;
+        }
+        Ok(n)
+    }
+}
+
+impl<R: Read> Inspect for Reader<R> {
+    fn recorder(&mut self) -> &mut Recorder {
+        &mut self.recorder
+    }
+}
+
+/// Compress all data from `input` into `output`.  On success, the
+/// output is returned.
+pub fn compress<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error> {
+    let mut cw = Writer::new(output);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.into_inner())
+}
+
+/// Decompress the ROLZ stream in `input` and write the result to
+/// `output`.  On success, the output is returned.
+pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<W, Error> {
+    let mut cr = try!(Reader::new(input));
+    try!(io::copy(&mut cr, &mut output));
+    Ok(output)
+}
+
+/// Return an iterator over the literals and matches of the ROLZ
+/// stream in `input`.  This starts decoding, therefore the result can
+/// be an error.
+pub fn inspect<R: Read>(input: R) -> io::Result<Events<Reader<R>>> {
+    Ok(Events::new(try!(Reader::new(input))))
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::io::Cursor;
+    use super::{compress, decompress};
+
+    fn roundtrip(input: &[u8]) {
+        let compressed = compress(Cursor::new(input), vec![]).unwrap();
+        let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(input.len(), decompressed.len());
+        assert_eq!(&input[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn compress_empty() {
+        roundtrip(b"");
+    }
+
+    #[test]
+    fn compress_a() {
+        roundtrip(b"a");
+    }
+
+    #[test]
+    fn compress_aaa() {
+        let input = [b'a'; 1000];
+        let compressed = compress(Cursor::new(&input[..]), vec![]).unwrap();
+        assert!(compressed.len() < 30);
+        roundtrip(&input);
+    }
+
+    #[test]
+    fn compress_decompress() {
+        roundtrip(include_bytes!("rolz.rs"));
+    }
+
+    #[test]
+    fn compress_decompress_large() {
+        let f = include_bytes!("rolz.rs");
+        let mut input = Vec::new();
+        let mut x = 1u32;
+        while input.len() < 5 * (1 << 20) / 2 {
+            input.extend_from_slice(&f[..]);
+            for _ in 0..5000 {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345);
+                input.push((x >> 24) as u8);
+            }
+        }
+        roundtrip(&input);
+    }
+
+    #[test]
+    fn inspect_events() {
+        use inspect::Event;
+        let compressed = compress(Cursor::new(&b"abcabcabc"[..]), vec![]).unwrap();
+        let events: Vec<Event> =
+            super::inspect(Cursor::new(&compressed[..])).unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![Event::Literal(b'a'), Event::Literal(b'b'), Event::Literal(b'c'),
+                        Event::Literal(b'a'), Event::Match { offset: 3, len: 5 }, Event::End],
+                   events);
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+    }
+
+    #[test]
+    fn decompress_garbage() {
+        ::testutil::garbage(|d: &[u8]| super::decompress(d, vec![]));
+    }
+}
@@ -0,0 +1,392 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Snappy raw block format compressor and decompressor.
+//!
+//! A Snappy block starts with the uncompressed length as a varint,
+//! followed by a sequence of elements.  The low two bits of each tag
+//! byte select between a literal run and copies with one, two or four
+//! byte offsets.  This is the format used by LevelDB and Hadoop, not
+//! the framed format of the `snzip` tools.  `inspect` lists the
+//! literals and copies of a block.
+
+use std::cmp;
+use std::io::{Read, Write};
+use std::io;
+use std::vec;
+
+use error::Error;
+use inspect::{Event, Recorder};
+
+const TAG_LITERAL: u8 = 0;
+const TAG_COPY1: u8 = 1;
+const TAG_COPY2: u8 = 2;
+const TAG_COPY4: u8 = 3;
+
+const MIN_MATCH_LEN: usize = 4;
+
+/// Most output bytes a byte of a Snappy block can produce: a copy
+/// with a two-byte offset takes three bytes and copies up to 64.
+const MAX_EXPANSION: usize = 22;
+
+/// Input is compressed in fragments of this size, and matches never
+/// cross fragment boundaries.
+const FRAGMENT_SIZE: usize = 1 << 16;
+
+const HASH_BITS: usize = 14;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+
+const NO_POS: usize = usize::max_value();
+
+/// Return a `CorruptInput` error at offset `pos` of the block.
+fn corrupt(pos: usize, msg: &'static str) -> io::Error {
+    Error::corrupt_at(pos as u64, msg).into()
+}
+
+#[inline(always)]
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    (data[pos] as u32) | (data[pos + 1] as u32) << 8 |
+    (data[pos + 2] as u32) << 16 | (data[pos + 3] as u32) << 24
+}
+
+#[inline(always)]
+fn hash(v: u32) -> usize {
+    (v.wrapping_mul(0x1e35a7bd) >> (32 - HASH_BITS)) as usize
+}
+
+fn write_varint(output: &mut Vec<u8>, mut v: usize) {
+    while v >= 0x80 {
+        output.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    output.push(v as u8);
+}
+
+fn read_varint(input: &[u8], ip: &mut usize) -> io::Result<usize> {
+    let mut v: u64 = 0;
+    let mut shift = 0;
+    loop {
+        if *ip >= input.len() || shift > 28 {
+            return Err(corrupt(*ip, "invalid Snappy length"));
+        }
+        let b = input[*ip];
+        *ip += 1;
+        v |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    if v > 0xffffffff {
+        return Err(corrupt(*ip, "invalid Snappy length"));
+    }
+    Ok(v as usize)
+}
+
+fn emit_literal(output: &mut Vec<u8>, literals: &[u8]) {
+    if literals.is_empty() {
+        return;
+    }
+    let n = literals.len() - 1;
+    if n < 60 {
+        output.push(TAG_LITERAL | (n << 2) as u8);
+    } else {
+        let mut bytes = 0;
+        let mut v = n;
+        while v > 0 {
+            bytes += 1;
+            v >>= 8;
+        }
+        output.push(TAG_LITERAL | ((59 + bytes) << 2) as u8);
+        for i in 0..bytes {
+            output.push((n >> (8 * i)) as u8);
+        }
+    }
+    output.extend_from_slice(literals);
+}
+
+/// Emit a single copy element of length 4 to 64.
+fn emit_copy_upto64(output: &mut Vec<u8>, offset: usize, len: usize) {
+    if len < 12 && offset < 2048 {
+        output.push(TAG_COPY1 | ((len - 4) << 2) as u8 | ((offset >> 8) << 5) as u8);
+        output.push(offset as u8);
+    } else {
+        output.push(TAG_COPY2 | ((len - 1) << 2) as u8);
+        output.push(offset as u8);
+        output.push((offset >> 8) as u8);
+    }
+}
+
+fn emit_copy(output: &mut Vec<u8>, offset: usize, mut len: usize) {
+    // Split long matches so that the last piece is at least 4 bytes
+    // long.
+    while len >= 68 {
+        emit_copy_upto64(output, offset, 64);
+        len -= 64;
+    }
+    if len > 64 {
+        emit_copy_upto64(output, offset, 60);
+        len -= 60;
+    }
+    emit_copy_upto64(output, offset, len);
+}
+
+fn compress_fragment(input: &[u8], table: &mut [usize], output: &mut Vec<u8>) {
+    for t in table.iter_mut() {
+        *t = NO_POS;
+    }
+    let len = input.len();
+    let mut anchor = 0;
+    let mut pos = 0;
+    while pos + MIN_MATCH_LEN <= len {
+        let seq = read_u32(input, pos);
+        let h = hash(seq);
+        let cand = table[h];
+        table[h] = pos;
+        if cand == NO_POS || read_u32(input, cand) != seq {
+            pos += 1;
+            continue;
+        }
+        let mut end = pos + MIN_MATCH_LEN;
+        while end < len && input[end] == input[cand + (end - pos)] {
+            end += 1;
+        }
+        emit_literal(output, &input[anchor..pos]);
+        emit_copy(output, pos - cand, end - pos);
+        pos = end;
+        anchor = pos;
+    }
+    emit_literal(output, &input[anchor..]);
+}
+
+/// Compress `input` as a Snappy block and append it to `output`.
+pub fn compress_block(input: &[u8], output: &mut Vec<u8>) {
+    write_varint(output, input.len());
+    let mut table = vec![NO_POS; HASH_SIZE];
+    for fragment in input.chunks(FRAGMENT_SIZE) {
+        compress_fragment(fragment, &mut table, output);
+    }
+}
+
+/// Decompress the Snappy block `input`, appending the result to
+/// `output`.
+pub fn decompress_block(input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+    decode_block(input, output, &mut Recorder::new())
+}
+
+/// Decompress a block like `decompress_block`, recording its length,
+/// literals and copies in `recorder`.
+fn decode_block(input: &[u8], output: &mut Vec<u8>, recorder: &mut Recorder)
+                -> io::Result<()> {
+    let mut ip = 0;
+    let expected_len = try!(read_varint(input, &mut ip));
+    recorder.record(Event::Header(format!("uncompressed length {}", expected_len)));
+    let start = output.len();
+    let limit = start + expected_len;
+    // The length comes from the input, so only reserve what the rest
+    // of the block can produce.
+    output.reserve(cmp::min(expected_len, (input.len() - ip).saturating_mul(MAX_EXPANSION)));
+
+    while ip < input.len() {
+        let tag = input[ip];
+        ip += 1;
+        let (len, offset) = match tag & 3 {
+            TAG_LITERAL => {
+                let mut len = (tag >> 2) as usize;
+                if len >= 60 {
+                    let bytes = len - 59;
+                    if ip + bytes > input.len() {
+                        return Err(corrupt(ip, "truncated Snappy literal"));
+                    }
+                    len = 0;
+                    for i in 0..bytes {
+                        len |= (input[ip + i] as usize) << (8 * i);
+                    }
+                    ip += bytes;
+                }
+                len += 1;
+                if ip + len > input.len() || output.len() + len > limit {
+                    return Err(corrupt(ip, "Snappy literal exceeds block"));
+                }
+                for &b in &input[ip..ip + len] {
+                    recorder.record(Event::Literal(b));
+                }
+                output.extend_from_slice(&input[ip..ip + len]);
+                ip += len;
+                continue;
+            },
+            TAG_COPY1 => {
+                if ip + 1 > input.len() {
+                    return Err(corrupt(ip, "truncated Snappy copy"));
+                }
+                let len = ((tag >> 2) & 7) as usize + 4;
+                let offset = ((tag >> 5) as usize) << 8 | input[ip] as usize;
+                ip += 1;
+                (len, offset)
+            },
+            TAG_COPY2 => {
+                if ip + 2 > input.len() {
+                    return Err(corrupt(ip, "truncated Snappy copy"));
+                }
+                let len = (tag >> 2) as usize + 1;
+                let offset = input[ip] as usize | (input[ip + 1] as usize) << 8;
+                ip += 2;
+                (len, offset)
+            },
+            TAG_COPY4 => {
+                if ip + 4 > input.len() {
+                    return Err(corrupt(ip, "truncated Snappy copy"));
+                }
+                let len = (tag >> 2) as usize + 1;
+                let offset = read_u32(input, ip) as usize;
+                ip += 4;
+                (len, offset)
+            },
+            _ => unreachable!(),
+        };
+        if offset == 0 || offset > output.len() - start {
+            return Err(corrupt(ip, "invalid Snappy copy offset"));
+        }
+        if output.len() + len > limit {
+            return Err(corrupt(ip, "Snappy copy exceeds block"));
+        }
+        recorder.record(Event::Match { offset: offset, len: len });
+        let from = output.len() - offset;
+        for i in 0..len {
+            let b = output[from + i];
+            output.push(b);
+        }
+    }
+    if output.len() != limit {
+        return Err(corrupt(ip, "Snappy block length mismatch"));
+    }
+    recorder.record(Event::End);
+    Ok(())
+}
+
+/// Compress all data from `input` into a Snappy block written to
+/// `output`.  On success, the output is returned.
+pub fn compress<R: Read, W: Write>(mut input: R, mut output: W) -> Result<W, Error> {
+    let mut data = Vec::new();
+    try!(input.read_to_end(&mut data));
+    let mut compressed = Vec::new();
+    compress_block(&data, &mut compressed);
+    try!(output.write_all(&compressed));
+    Ok(output)
+}
+
+/// Decompress the Snappy block in `input` and write the result to
+/// `output`.  On success, the output is returned.
+pub fn decompress<R: Read, W: Write>(mut input: R, mut output: W) -> Result<W, Error> {
+    let mut data = Vec::new();
+    try!(input.read_to_end(&mut data));
+    let mut decompressed = Vec::new();
+    try!(decompress_block(&data, &mut decompressed));
+    try!(output.write_all(&decompressed));
+    Ok(output)
+}
+
+/// Return the header, literals and copies of the Snappy block in
+/// `input`.  The block is decoded at once, so an error in the block
+/// is the last item, after the events decoded before it.
+pub fn inspect<R: Read>(mut input: R) -> io::Result<vec::IntoIter<io::Result<Event>>> {
+    let mut data = Vec::new();
+    try!(input.read_to_end(&mut data));
+    let mut recorder = Recorder::enabled();
+    let result = decode_block(&data, &mut Vec::new(), &mut recorder);
+    let mut events: Vec<io::Result<Event>> = recorder.into_events().into_iter().map(Ok).collect();
+    if let Err(e) = result {
+        events.push(Err(e));
+    }
+    Ok(events.into_iter())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress_block, decompress_block};
+
+    fn cmp_test(input: &[u8], expected_output: &[u8]) {
+        let mut compressed = Vec::new();
+        compress_block(input, &mut compressed);
+        assert_eq!(&expected_output[..], &compressed[..]);
+    }
+
+    fn decmp_test(compressed: &[u8], expected_output: &[u8]) {
+        let mut decompressed = Vec::new();
+        decompress_block(compressed, &mut decompressed).unwrap();
+        assert_eq!(&expected_output[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn compress_empty() {
+        cmp_test(b"", &[0]);
+    }
+
+    #[test]
+    fn compress_a() {
+        cmp_test(b"a", &[1, 0, b'a']);
+    }
+
+    #[test]
+    fn compress_aaa() {
+        cmp_test(&[b'a'; 100], &[100, 0, b'a', 254, 1, 0, 138, 1, 0]);
+    }
+
+    #[test]
+    fn decompress_empty() {
+        decmp_test(&[0], b"");
+    }
+
+    #[test]
+    fn decompress_aaa() {
+        decmp_test(&[100, 0, b'a', 254, 1, 0, 138, 1, 0], &[b'a'; 100]);
+    }
+
+    #[test]
+    fn decompress_copy4() {
+        decmp_test(&[6, 4, b'a', b'b', 15, 2, 0, 0, 0], b"ababab");
+    }
+
+    #[test]
+    fn inspect_events() {
+        use inspect::Event;
+        let events: Vec<Event> = super::inspect(&[6, 4, b'a', b'b', 15, 2, 0, 0, 0][..])
+            .unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![Event::Header("uncompressed length 6".to_string()),
+                        Event::Literal(b'a'), Event::Literal(b'b'),
+                        Event::Match { offset: 2, len: 4 }, Event::End], events);
+        let last = super::inspect(&[6, 4, b'a', b'b', 15, 3, 0, 0, 0][..]).unwrap().last();
+        assert!(last.unwrap().is_err());
+    }
+
+    #[test]
+    fn decompress_bad_length() {
+        let mut decompressed = Vec::new();
+        assert!(decompress_block(&[2, 0, b'a'], &mut decompressed).is_err());
+        assert!(decompress_block(&[1, 0, b'a', 5, 1], &mut decompressed).is_err());
+    }
+
+    #[test]
+    fn decompress_huge_length() {
+        // A length no block of this size can produce must not be
+        // allocated up front.
+        let mut decompressed = Vec::new();
+        assert!(decompress_block(&[0xff, 0xff, 0xff, 0xff, 0x0f], &mut decompressed).is_err());
+        assert!(decompressed.capacity() < 1000);
+    }
+
+    #[test]
+    fn compress_decompress() {
+        let input = include_bytes!("snappy.rs");
+        let mut compressed = Vec::new();
+        compress_block(input, &mut compressed);
+        assert!(compressed.len() < input.len());
+        decmp_test(&compressed, input);
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+    }
+}
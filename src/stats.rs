@@ -0,0 +1,169 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Order-0 statistics of byte data.
+//!
+//! Byte histograms, the entropy computed from them, and cumulative
+//! frequencies as used by the semi-static coders.  The estimator in
+//! `auto` and the statistics output of `camp` are built on these.
+//!
+//! `entropy` uses the floating point logarithm of the standard
+//! library, whose last bits can differ between platforms.  Decisions
+//! which influence the compressed output use `entropy_fixed`
+//! instead, which computes in integers only.
+
+use alloc::vec::Vec;
+
+/// Return the number of occurrences of each byte value in `data`.
+pub fn byte_histogram(data: &[u8]) -> [u64; 256] {
+    let mut hist = [0; 256];
+    update_histogram(&mut hist, data);
+    hist
+}
+
+/// Add the byte values in `data` to `hist`, for computing a histogram
+/// of data which is read in pieces.
+pub fn update_histogram(hist: &mut [u64; 256], data: &[u8]) {
+    for &b in data {
+        hist[b as usize] += 1;
+    }
+}
+
+/// Return the Shannon entropy of the distribution given by `counts`,
+/// in bits per symbol.  Empty distributions have an entropy of zero.
+/// Needs the `std` feature, for the logarithm.
+#[cfg(feature = "std")]
+pub fn shannon_entropy(counts: &[u64]) -> f64 {
+    let total: u64 = counts.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+    let total = total as f64;
+    counts.iter().filter(|&&c| c > 0).map(|&c| {
+        let p = c as f64 / total;
+        -p * p.log2()
+    }).sum()
+}
+
+/// Return the order-0 entropy of `data` in bits per byte.  Needs the
+/// `std` feature.
+#[cfg(feature = "std")]
+pub fn entropy(data: &[u8]) -> f64 {
+    shannon_entropy(&byte_histogram(data))
+}
+
+/// Number of fractional bits of the results of `log2_fixed` and
+/// `entropy_fixed`.
+pub const FIXED_FRAC_BITS: u32 = 16;
+
+/// Return the base-2 logarithm of `x`, rounded down to a multiple of
+/// `1 / 2^FIXED_FRAC_BITS` and scaled by `2^FIXED_FRAC_BITS`.  `x`
+/// must not be zero.
+pub fn log2_fixed(x: u64) -> u64 {
+    debug_assert!(x > 0);
+    let int = 63 - x.leading_zeros() as u64;
+    // `m` is `x / 2^int`, between 1 and 2, with 63 fractional bits.
+    let mut m = (x as u128) << (63 - int);
+    let mut frac = 0;
+    for _ in 0..FIXED_FRAC_BITS {
+        m = (m * m) >> 63;
+        frac <<= 1;
+        if m >= 2 << 63 {
+            m >>= 1;
+            frac |= 1;
+        }
+    }
+    (int << FIXED_FRAC_BITS) | frac
+}
+
+/// Return the Shannon entropy of the distribution given by `counts`
+/// in bits per symbol, scaled by `2^FIXED_FRAC_BITS`.  Unlike
+/// `shannon_entropy`, the result is the same on all platforms.
+pub fn entropy_fixed(counts: &[u64]) -> u64 {
+    let total: u64 = counts.iter().sum();
+    if total == 0 {
+        return 0;
+    }
+    let log_total = log2_fixed(total) as u128;
+    let bits: u128 = counts.iter().filter(|&&c| c > 0)
+        .map(|&c| c as u128 * (log_total - log2_fixed(c) as u128))
+        .sum();
+    (bits / total as u128) as u64
+}
+
+/// Return the cumulative frequencies for `freqs`: element `i` of the
+/// result is the sum of the first `i` frequencies, so the result has
+/// one element more than `freqs` and ends with the total.
+pub fn cumulative_freqs(freqs: &[u64]) -> Vec<u64> {
+    let mut cum = Vec::with_capacity(freqs.len() + 1);
+    let mut sum = 0;
+    cum.push(0);
+    for &f in freqs {
+        sum += f;
+        cum.push(sum);
+    }
+    cum
+}
+
+/// Return the byte values occurring in `data` together with their
+/// counts, in the form taken by the `preload` methods of the
+/// arithmetic coder.
+pub fn symbol_counts(data: &[u8]) -> Vec<(u8, u64)> {
+    byte_histogram(data).iter().enumerate()
+        .filter(|&(_, &c)| c > 0)
+        .map(|(b, &c)| (b as u8, c))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{byte_histogram, cumulative_freqs, symbol_counts};
+    #[cfg(feature = "std")]
+    use super::{entropy, shannon_entropy};
+    use super::{entropy_fixed, log2_fixed, FIXED_FRAC_BITS};
+
+    #[test]
+    fn histogram() {
+        let hist = byte_histogram(b"abracadabra");
+        assert_eq!(5, hist[b'a' as usize]);
+        assert_eq!(2, hist[b'r' as usize]);
+        assert_eq!(11, hist.iter().sum::<u64>());
+        assert_eq!(vec![(b'a', 5), (b'b', 2), (b'c', 1), (b'd', 1), (b'r', 2)],
+                   symbol_counts(b"abracadabra"));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn entropies() {
+        assert_eq!(0.0, shannon_entropy(&[]));
+        assert_eq!(0.0, entropy(b"aaaa"));
+        assert_eq!(1.0, entropy(b"abab"));
+        assert_eq!(2.0, shannon_entropy(&[1, 1, 0, 1, 1]));
+        let all: Vec<u8> = (0..256).map(|i| i as u8).collect();
+        assert_eq!(8.0, entropy(&all));
+    }
+
+    #[test]
+    fn fixed_point() {
+        let one = 1 << FIXED_FRAC_BITS;
+        assert_eq!(0, log2_fixed(1));
+        assert_eq!(10 * one, log2_fixed(1024));
+        // log2(3) = 1.58496...
+        assert_eq!(103872, log2_fixed(3));
+        assert_eq!(0, entropy_fixed(&[]));
+        assert_eq!(one, entropy_fixed(&byte_histogram(b"abab")));
+        assert_eq!(2 * one, entropy_fixed(&[1, 1, 0, 1, 1]));
+        #[cfg(feature = "std")]
+        {
+            let text = byte_histogram(b"The banana goat in the banana boat.");
+            let exact = shannon_entropy(&text) * one as f64;
+            assert!((entropy_fixed(&text) as f64 - exact).abs() < 2.0);
+        }
+    }
+
+    #[test]
+    fn cumulative() {
+        assert_eq!(vec![0], cumulative_freqs(&[]));
+        assert_eq!(vec![0, 3, 3, 4, 9], cumulative_freqs(&[3, 0, 1, 5]));
+    }
+}
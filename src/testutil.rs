@@ -0,0 +1,227 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Shared helpers for the tests of the codecs.
+//!
+//! The generators produce inputs which are hard on one part or
+//! another of a compressor: long runs, short repeating patterns,
+//! incompressible bytes and text-like data, and mixtures of these.
+//! `roundtrip` compresses and decompresses a fixed set of edge cases
+//! and a number of generated inputs with a codec and checks that the
+//! data survives.  The generated inputs only depend on their seed, so
+//! a failing case can be reproduced from the seed in the message.
+//!
+//! This module is only compiled for the tests.
+
+use error::Error;
+use method::Method;
+
+/// Number of generated inputs `roundtrip` tries after the edge cases.
+pub const CASES: u64 = 24;
+
+/// Small xorshift generator, so that the tests do not need an
+/// external crate and always see the same data.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Create a generator.  Different seeds give different sequences.
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1 }
+    }
+
+    /// Return the next 64 random bits.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// Return a number below `n`, which must not be zero.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Return a random byte.
+    pub fn byte(&mut self) -> u8 {
+        self.next_u64() as u8
+    }
+}
+
+/// Return `len` bytes of runs of a single byte value.  Most runs are
+/// short, but some are longer than any match length.
+pub fn runs(rng: &mut Rng, len: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(len);
+    while data.len() < len {
+        let run = match rng.below(4) {
+            0 => 1 + rng.below(1000),
+            _ => 1 + rng.below(20),
+        };
+        let b = rng.byte();
+        for _ in 0..run {
+            data.push(b);
+        }
+    }
+    data.truncate(len);
+    data
+}
+
+/// Return `len` bytes of short patterns, each repeated a number of
+/// times, like `abababab` or `xyzxyzxyz`.
+pub fn alternations(rng: &mut Rng, len: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(len);
+    while data.len() < len {
+        let pattern: Vec<u8> = (0..1 + rng.below(8)).map(|_| rng.byte()).collect();
+        for _ in 0..1 + rng.below(200) {
+            data.extend_from_slice(&pattern);
+        }
+    }
+    data.truncate(len);
+    data
+}
+
+/// Return `len` uniformly random bytes.
+pub fn random(rng: &mut Rng, len: usize) -> Vec<u8> {
+    (0..len).map(|_| rng.byte()).collect()
+}
+
+/// Return `len` bytes of text-like data: words from a small
+/// vocabulary, numbers, punctuation and line breaks.
+pub fn structured(rng: &mut Rng, len: usize) -> Vec<u8> {
+    const WORDS: [&'static [u8]; 12] = [b"the", b"banana", b"goat", b"in", b"boat", b"can",
+                                         b"hand", b"bananas", b"to", b"man", b"compress",
+                                         b"window"];
+    let mut data = Vec::with_capacity(len);
+    while data.len() < len {
+        match rng.below(10) {
+            0 => {
+                let n = rng.next_u64() % 100000;
+                data.extend_from_slice(n.to_string().as_bytes());
+            },
+            1 => data.extend_from_slice(b".\n"),
+            _ => data.extend_from_slice(WORDS[rng.below(WORDS.len())]),
+        }
+        data.push(b' ');
+    }
+    data.truncate(len);
+    data
+}
+
+/// Return `len` bytes made of pieces from all the other generators,
+/// so that the codecs have to switch between them.
+pub fn mixed(rng: &mut Rng, len: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(len);
+    while data.len() < len {
+        let n = 1 + rng.below(4000);
+        let piece = match rng.below(4) {
+            0 => runs(rng, n),
+            1 => alternations(rng, n),
+            2 => random(rng, n),
+            _ => structured(rng, n),
+        };
+        data.extend_from_slice(&piece);
+    }
+    data.truncate(len);
+    data
+}
+
+/// Return the inputs which are tried before the generated ones.
+pub fn edge_cases() -> Vec<(String, Vec<u8>)> {
+    let all_bytes: Vec<u8> = (0..256).map(|b| b as u8).collect();
+    let descending: Vec<u8> = (0..256).rev().map(|b| b as u8).collect();
+    vec![
+        ("empty".to_string(), Vec::new()),
+        ("single zero".to_string(), vec![0]),
+        ("single 0xff".to_string(), vec![0xff]),
+        ("all bytes".to_string(), all_bytes.clone()),
+        ("all bytes twice".to_string(), [&all_bytes[..], &descending[..]].concat()),
+        ("4097 times a".to_string(), vec![b'a'; 4097]),
+        ("70000 zeros".to_string(), vec![0; 70000]),
+    ]
+}
+
+/// Return the generated input for `seed`, with a description.
+pub fn generate(seed: u64) -> (String, Vec<u8>) {
+    let mut rng = Rng::new(seed);
+    let len = match rng.below(3) {
+        0 => rng.below(64),
+        1 => rng.below(5000),
+        _ => rng.below(40000),
+    };
+    let (kind, data) = match rng.below(5) {
+        0 => ("runs", runs(&mut rng, len)),
+        1 => ("alternations", alternations(&mut rng, len)),
+        2 => ("random", random(&mut rng, len)),
+        3 => ("structured", structured(&mut rng, len)),
+        _ => ("mixed", mixed(&mut rng, len)),
+    };
+    (format!("seed {}: {} bytes of {}", seed, len, kind), data)
+}
+
+/// Something which compresses and decompresses whole buffers.
+pub trait Codec {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, Error>;
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+impl Codec for Method {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        Method::compress(self, data, Vec::new())
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        Method::decompress(self, data, Vec::new())
+    }
+}
+
+/// A pair of functions is a codec, for the `compress` and
+/// `decompress` functions of the modules.
+impl<C, D> Codec for (C, D)
+    where C: Fn(&[u8]) -> Result<Vec<u8>, Error>,
+          D: Fn(&[u8]) -> Result<Vec<u8>, Error> {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        (self.0)(data)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        (self.1)(data)
+    }
+}
+
+/// Check that `codec` restores the edge cases and `CASES` generated
+/// inputs.  Panics with the description of the first input which
+/// fails.
+pub fn roundtrip<C: Codec>(codec: C) {
+    let cases = edge_cases().into_iter().chain((0..CASES).map(generate));
+    for (name, data) in cases {
+        let compressed = match codec.compress(&data) {
+            Ok(c) => c,
+            Err(e) => panic!("{}: compression failed: {}", name, e),
+        };
+        let decompressed = match codec.decompress(&compressed) {
+            Ok(d) => d,
+            Err(e) => panic!("{}: decompression failed: {}", name, e),
+        };
+        assert!(data == decompressed, "{}: data differs after round trip", name);
+    }
+}
+
+/// Check that `decompress` rejects buffers of random bytes with an
+/// error instead of panicking.  Formats without a checksum accept a
+/// buffer now and then, when it happens to decode to an end marker,
+/// so this allows one in fifty to decompress.
+pub fn garbage<D>(decompress: D)
+    where D: Fn(&[u8]) -> Result<Vec<u8>, Error> {
+    let mut accepted = Vec::new();
+    for seed in 0..200 {
+        let mut rng = Rng::new(seed);
+        let len = rng.below(256);
+        let data = random(&mut rng, len);
+        if decompress(&data).is_ok() {
+            accepted.push(seed);
+        }
+    }
+    assert!(accepted.len() <= 4, "garbage decompressed for seeds {:?}", accepted);
+}
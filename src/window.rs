@@ -0,0 +1,678 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Match finding for the LZ compressors.
+//!
+//! The LZ compressors and decompressors keep their data in a
+//! `SlidingWindow`, a ring buffer whose size is a power of two, so
+//! that the wrap-around arithmetic lives in one place.  A `MatchFinder`
+//! indexes the positions of such a window by the hash of the three
+//! bytes starting at each position, using one of two search
+//! structures:
+//!
+//! - With `Search::HashChain`, `head` maps each hash to the most
+//!   recently inserted position, and every position is linked to the
+//!   one inserted before it with the same hash.  A search walks the
+//!   chain from the newest to the oldest position.
+//!
+//! - With `Search::BinaryTree`, the positions of each hash bucket
+//!   form a binary search tree ordered by the data following them,
+//!   with the newest position at the root.  Inserting a position
+//!   walks down the tree and re-roots it at the new position, and the
+//!   longest matches are found along that path.  This examines far
+//!   fewer candidates for long matches, but costs more per inserted
+//!   position, so it is meant for the best compression levels.
+//!   The trees order positions by their first `max_len` bytes, so
+//!   positions searched with a smaller `max_len` than earlier ones,
+//!   which happens near the end of the input, are not inserted.
+//!
+//! In both cases, searches give up after `depth` steps, so that the
+//! depth trades compression speed for ratio.  The compression levels
+//! from 1 to `MAX_LEVEL` select a search structure and depth.
+//!
+//! The match finder does not own the window, it is passed in on
+//! every call, and every window position has to be passed to either
+//! `find` or `skip` exactly once, in order.  Internally, positions are
+//! counted from the start of the stream, so that links to positions
+//! which have dropped out of the window are recognized by their
+//! distance and never followed.
+
+use core::cmp;
+use alloc::vec::Vec;
+
+/// Compression level used when none is given.
+pub const DEFAULT_LEVEL: u32 = 6;
+
+/// Highest compression level.
+pub const MAX_LEVEL: u32 = 9;
+
+/// Search structure used by a `MatchFinder`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Search {
+    /// Hash chains, fast to update.
+    HashChain,
+    /// Binary search trees per hash bucket, for (nearly) the longest
+    /// matches.
+    BinaryTree,
+}
+
+/// Return the search structure and depth for compression level
+/// `level`, which must be between 1 and `MAX_LEVEL`.
+pub fn level_params(level: u32) -> (Search, usize) {
+    match level {
+        1 => (Search::HashChain, 1),
+        2 => (Search::HashChain, 2),
+        3 => (Search::HashChain, 4),
+        4 => (Search::HashChain, 8),
+        5 => (Search::HashChain, 12),
+        6 => (Search::HashChain, 16),
+        7 => (Search::HashChain, 32),
+        8 => (Search::HashChain, 128),
+        9 => (Search::BinaryTree, 64),
+        _ => panic!("invalid compression level: {}", level),
+    }
+}
+
+/// Hash chain or binary tree match finder over a ring buffer window.
+#[derive(Clone)]
+pub struct MatchFinder {
+    search: Search,
+    /// Most recent stream position for each hash, stored off by one
+    /// so that 0 means "no position".
+    head: Vec<usize>,
+    /// For hash chains, the previous stream position with the same
+    /// hash for each window position.  For binary trees, the left and
+    /// right child of each window position.  Again off by one.
+    links: Vec<usize>,
+    /// Stream position of the next window position to be inserted.
+    next_pos: usize,
+    /// Number of bytes by which the binary trees are ordered.
+    tree_len: usize,
+    window_mask: usize,
+    hash_bits: usize,
+    depth: usize,
+}
+
+impl MatchFinder {
+    /// Create a hash chain match finder for a window of
+    /// `window_size` bytes, which must be a power of two, with a
+    /// hash table of `1 << hash_bits` entries.  Searches follow at
+    /// most `depth` chain entries.
+    pub fn new(window_size: usize, hash_bits: usize, depth: usize) -> MatchFinder {
+        MatchFinder::with_search(window_size, hash_bits, depth, Search::HashChain)
+    }
+
+    /// Create a match finder like `new`, but using the search
+    /// structure `search`.
+    pub fn with_search(window_size: usize, hash_bits: usize, depth: usize,
+                       search: Search) -> MatchFinder {
+        assert!(window_size.is_power_of_two());
+        assert!(hash_bits > 0 && hash_bits <= 24);
+        assert!(depth > 0);
+        let links = match search {
+            Search::HashChain => window_size,
+            Search::BinaryTree => 2 * window_size,
+        };
+        MatchFinder {
+            search: search,
+            head: vec![0; 1 << hash_bits],
+            links: vec![0; links],
+            next_pos: 0,
+            tree_len: 0,
+            window_mask: window_size - 1,
+            hash_bits: hash_bits,
+            depth: depth,
+        }
+    }
+
+    /// Create a match finder with the search structure and depth for
+    /// compression level `level`.
+    pub fn with_level(window_size: usize, hash_bits: usize, level: u32) -> MatchFinder {
+        let (search, depth) = level_params(level);
+        MatchFinder::with_search(window_size, hash_bits, depth, search)
+    }
+
+    /// Return the search structure.
+    pub fn search(&self) -> Search {
+        self.search
+    }
+
+    /// Return the maximum number of candidates examined per search.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    #[inline(always)]
+    fn hash_at(&self, window: &[u8], pos: usize) -> usize {
+        let v = (window[pos] as u32) << 16 |
+            (window[(pos + 1) & self.window_mask] as u32) << 8 |
+            window[(pos + 2) & self.window_mask] as u32;
+        (v.wrapping_mul(2654435761) >> (32 - self.hash_bits)) as usize
+    }
+
+    /// Find the longest match for the data at window position `pos`
+    /// among the positions inserted earlier, and insert `pos`.
+    /// Matches are at most `max_len` bytes long and at most
+    /// `max_dist` bytes back.  Returns the match length and distance,
+    /// or `(0, 0)` when no match was found.
+    ///
+    /// The `max_len` bytes starting at `pos` must already be in the
+    /// window, and `max_dist` must be smaller than the window size.
+    pub fn find(&mut self, window: &[u8], pos: usize, max_len: usize,
+                max_dist: usize) -> (usize, usize) {
+        debug_assert_eq!(window.len(), self.window_mask + 1);
+        debug_assert!(max_dist <= self.window_mask);
+        match self.search {
+            Search::HashChain => self.chain_find(window, pos, max_len, max_dist),
+            Search::BinaryTree => self.tree_find(window, pos, max_len, max_dist),
+        }
+    }
+
+    /// Insert window position `pos` without looking for a match.
+    /// The arguments are the same as for `find`.
+    pub fn skip(&mut self, window: &[u8], pos: usize, max_len: usize, max_dist: usize) {
+        debug_assert_eq!(window.len(), self.window_mask + 1);
+        match self.search {
+            Search::HashChain => {
+                let h = self.hash_at(window, pos);
+                let spos = self.advance(pos);
+                self.links[pos] = self.head[h];
+                self.head[h] = spos + 1;
+            },
+            Search::BinaryTree => {
+                self.tree_find(window, pos, max_len, max_dist);
+            },
+        }
+    }
+
+    /// Return the stream position for window position `pos` and
+    /// advance to the next one.
+    #[inline(always)]
+    fn advance(&mut self, pos: usize) -> usize {
+        let spos = self.next_pos;
+        debug_assert_eq!(pos, spos & self.window_mask);
+        self.next_pos += 1;
+        spos
+    }
+
+    fn chain_find(&mut self, window: &[u8], pos: usize, max_len: usize,
+                  max_dist: usize) -> (usize, usize) {
+        let mask = self.window_mask;
+        let h = self.hash_at(window, pos);
+        let spos = self.advance(pos);
+        let mut cand = self.head[h];
+        self.links[pos] = cand;
+        self.head[h] = spos + 1;
+
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        if max_len == 0 {
+            return (0, 0);
+        }
+        let mut chain = 0;
+        while cand > 0 && chain < self.depth {
+            let dist = spos - (cand - 1);
+            if dist > max_dist {
+                break;
+            }
+            let c = (cand - 1) & mask;
+            if window[(c + best_len) & mask] == window[(pos + best_len) & mask] {
+                let len = common_len(window, c, pos, 0, max_len);
+                if len > best_len {
+                    best_len = len;
+                    best_dist = dist;
+                    if len == max_len {
+                        break;
+                    }
+                }
+            }
+            cand = self.links[c];
+            chain += 1;
+        }
+        (best_len, best_dist)
+    }
+
+    fn tree_find(&mut self, window: &[u8], pos: usize, max_len: usize,
+                 max_dist: usize) -> (usize, usize) {
+        let spos = self.advance(pos);
+        if max_len < self.tree_len {
+            return self.tree_search(window, pos, spos, max_len, max_dist);
+        }
+        self.tree_len = max_len;
+        let mask = self.window_mask;
+        let h = self.hash_at(window, pos);
+        let mut cand = self.head[h];
+        self.head[h] = spos + 1;
+
+        // `left` and `right` are the link slots where the next
+        // candidate smaller or larger than `pos` has to be entered.
+        // `left_len` and `right_len` are the lengths of the common
+        // prefixes with the candidates entered there, and all
+        // candidates in between share at least the shorter prefix.
+        let mut left = 2 * pos;
+        let mut right = 2 * pos + 1;
+        let mut left_len = 0;
+        let mut right_len = 0;
+
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        let mut steps = 0;
+        loop {
+            if cand == 0 || steps == self.depth || spos - (cand - 1) > max_dist {
+                self.links[left] = 0;
+                self.links[right] = 0;
+                break;
+            }
+            let dist = spos - (cand - 1);
+            let c = (cand - 1) & mask;
+            steps += 1;
+
+            let len = common_len(window, c, pos, cmp::min(left_len, right_len), max_len);
+            if len > best_len {
+                best_len = len;
+                best_dist = dist;
+            }
+            if len == max_len {
+                // The candidate is equal to `pos` as far as we can
+                // tell, so `pos` takes over its subtrees.
+                self.links[left] = self.links[2 * c];
+                self.links[right] = self.links[2 * c + 1];
+                break;
+            }
+            if window[(c + len) & mask] < window[(pos + len) & mask] {
+                self.links[left] = cand;
+                left = 2 * c + 1;
+                cand = self.links[left];
+                left_len = len;
+            } else {
+                self.links[right] = cand;
+                right = 2 * c;
+                cand = self.links[right];
+                right_len = len;
+            }
+        }
+        (best_len, best_dist)
+    }
+
+    /// Search the binary tree for `pos` without inserting it.
+    fn tree_search(&self, window: &[u8], pos: usize, spos: usize, max_len: usize,
+                   max_dist: usize) -> (usize, usize) {
+        let mask = self.window_mask;
+        let mut cand = self.head[self.hash_at(window, pos)];
+        let mut left_len = 0;
+        let mut right_len = 0;
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        let mut steps = 0;
+        while cand > 0 && steps < self.depth && spos - (cand - 1) <= max_dist {
+            let dist = spos - (cand - 1);
+            let c = (cand - 1) & mask;
+            steps += 1;
+
+            let len = common_len(window, c, pos, cmp::min(left_len, right_len), max_len);
+            if len > best_len {
+                best_len = len;
+                best_dist = dist;
+            }
+            if len == max_len {
+                break;
+            }
+            if window[(c + len) & mask] < window[(pos + len) & mask] {
+                cand = self.links[2 * c + 1];
+                left_len = len;
+            } else {
+                cand = self.links[2 * c];
+                right_len = len;
+            }
+        }
+        (best_len, best_dist)
+    }
+}
+
+/// Return the length of the common prefix of the data at window
+/// positions `a` and `b`, up to `max_len` bytes, when the first `len`
+/// bytes are already known to be equal.  The window size must be a
+/// power of two.
+///
+/// Eight bytes are compared at a time, and the first differing byte
+/// is located from the trailing zeros of their XOR.  Near the end of
+/// the window, where a run of eight bytes would wrap around, the
+/// comparison falls back to single bytes.
+#[inline]
+fn common_len(window: &[u8], a: usize, b: usize, mut len: usize, max_len: usize) -> usize {
+    let mask = window.len() - 1;
+    while len + 8 <= max_len {
+        let i = (a + len) & mask;
+        let j = (b + len) & mask;
+        if i + 8 > window.len() || j + 8 > window.len() {
+            break;
+        }
+        let diff = load_u64(&window[i..i + 8]) ^ load_u64(&window[j..j + 8]);
+        if diff != 0 {
+            return len + (diff.trailing_zeros() / 8) as usize;
+        }
+        len += 8;
+    }
+    while len < max_len && window[(a + len) & mask] == window[(b + len) & mask] {
+        len += 1;
+    }
+    len
+}
+
+/// Load eight bytes as a little-endian word, so that the first byte
+/// is the least significant one.
+#[inline(always)]
+fn load_u64(bytes: &[u8]) -> u64 {
+    let mut word = [0u8; 8];
+    word.copy_from_slice(bytes);
+    u64::from_le_bytes(word)
+}
+
+/// Ring buffer window of the LZ compressors and decompressors.
+///
+/// The window holds the most recent data, followed by the bytes which
+/// have been added but not consumed yet: the look-ahead buffer of a
+/// compressor, or the decoded bytes which a decompressor has not
+/// returned yet.  Positions in the window are indices into `data`,
+/// and distances are counted back from the first unconsumed byte
+/// (for compressors) or from the end of the buffered data (for
+/// decompressors, in `copy_match`).
+#[derive(Clone)]
+pub struct SlidingWindow {
+    data: Vec<u8>,
+    mask: usize,
+    position: usize,
+    buffered: usize,
+    /// Number of bytes added so far, up to the window size.
+    filled: usize,
+}
+
+impl SlidingWindow {
+    /// Create a window of `size` bytes, which must be a power of two.
+    pub fn new(size: usize) -> SlidingWindow {
+        assert!(size.is_power_of_two());
+        SlidingWindow {
+            data: vec![0; size],
+            mask: size - 1,
+            position: 0,
+            buffered: 0,
+            filled: 0,
+        }
+    }
+
+    /// Create a window using `data`, whose length must be a power of
+    /// two, as its ring buffer.  The contents of `data` do not matter.
+    pub fn from_vec(data: Vec<u8>) -> SlidingWindow {
+        assert!(data.len().is_power_of_two());
+        SlidingWindow {
+            mask: data.len() - 1,
+            data: data,
+            position: 0,
+            buffered: 0,
+            filled: 0,
+        }
+    }
+
+    /// Return the ring buffer, for example for reuse with `from_vec`.
+    pub fn into_vec(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Return the size of the window in bytes.
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Return the window contents, for passing to a `MatchFinder`.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Return the window position of the first unconsumed byte.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Return the number of bytes added but not consumed yet.
+    pub fn buffered(&self) -> usize {
+        self.buffered
+    }
+
+    /// Return the number of bytes added so far, but at most the window
+    /// size.  Decoders check that match distances do not reach beyond
+    /// this, into the part of the window which was never written.
+    pub fn filled(&self) -> usize {
+        self.filled
+    }
+
+    /// Map `x` to a window position.
+    #[inline(always)]
+    pub fn wrap(&self, x: usize) -> usize {
+        x & self.mask
+    }
+
+    /// Return the unconsumed byte at index `i`.
+    #[inline(always)]
+    pub fn get(&self, i: usize) -> u8 {
+        debug_assert!(i < self.buffered);
+        self.data[(self.position + i) & self.mask]
+    }
+
+    /// Add `byte` after the buffered data.
+    #[inline(always)]
+    pub fn push(&mut self, byte: u8) {
+        debug_assert!(self.buffered < self.data.len());
+        self.data[(self.position + self.buffered) & self.mask] = byte;
+        self.buffered += 1;
+        if self.filled < self.data.len() {
+            self.filled += 1;
+        }
+    }
+
+    /// Add bytes from `buf` until `limit` bytes are buffered.
+    /// Returns the number of bytes taken from `buf`.
+    pub fn fill(&mut self, buf: &[u8], limit: usize) -> usize {
+        let n = cmp::min(buf.len(), limit.saturating_sub(self.buffered));
+        for &b in &buf[..n] {
+            self.push(b);
+        }
+        n
+    }
+
+    /// Add `len` bytes copied from `dist` bytes before the end of the
+    /// buffered data.  The copy may overlap the added bytes, so that
+    /// short distances repeat the data.  `dist` must be between 1 and
+    /// `filled()`.
+    pub fn copy_match(&mut self, dist: usize, len: usize) {
+        debug_assert!(dist > 0 && dist <= self.filled);
+        let end = self.position + self.buffered;
+        let from = end + self.data.len() - dist;
+        for i in 0..len {
+            self.data[(end + i) & self.mask] = self.data[(from + i) & self.mask];
+        }
+        self.buffered += len;
+        self.filled = cmp::min(self.filled + len, self.data.len());
+        debug_assert!(self.buffered <= self.data.len());
+    }
+
+    /// Consume `count` buffered bytes.
+    #[inline(always)]
+    pub fn consume(&mut self, count: usize) {
+        debug_assert!(count <= self.buffered);
+        self.position = (self.position + count) & self.mask;
+        self.buffered -= count;
+    }
+
+    /// Return the buffered bytes from the first unconsumed byte up to
+    /// the end of the buffered data or of the ring buffer, whichever
+    /// comes first.  Consuming them makes the rest visible.
+    pub fn buffered_data(&self) -> &[u8] {
+        let n = cmp::min(self.buffered, self.data.len() - self.position);
+        &self.data[self.position..self.position + n]
+    }
+
+    /// Consume the bytes returned by `buffered_data` and return them.
+    /// They stay in the ring buffer until the next bytes are added.
+    pub fn take_buffered(&mut self) -> &[u8] {
+        let start = self.position;
+        let n = cmp::min(self.buffered, self.data.len() - start);
+        self.consume(n);
+        &self.data[start..start + n]
+    }
+
+    /// Copy as many buffered bytes as fit into `output` and consume
+    /// them.  Returns the number of bytes copied.
+    pub fn read(&mut self, output: &mut [u8]) -> usize {
+        let n = cmp::min(output.len(), self.buffered);
+        for (i, o) in output[..n].iter_mut().enumerate() {
+            *o = self.data[(self.position + i) & self.mask];
+        }
+        self.consume(n);
+        n
+    }
+
+    /// Return the length of the match for the unconsumed data at
+    /// index `i` with the data `dist` bytes before it, up to
+    /// `max_len` bytes.
+    pub fn match_len(&self, i: usize, dist: usize, max_len: usize) -> usize {
+        let pos = self.position + i;
+        let match_pos = pos + self.data.len() - dist;
+        common_len(&self.data, match_pos, pos, 0, max_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{common_len, MatchFinder, Search, SlidingWindow};
+
+    #[test]
+    fn common_len_words() {
+        let mut window = [0u8; 64];
+        for (i, w) in window.iter_mut().enumerate() {
+            *w = (i % 21) as u8;
+        }
+        window[50] = 99;
+        for a in 0..64 {
+            for b in 0..64 {
+                for &max_len in &[0, 1, 7, 8, 9, 17, 40] {
+                    let mut len = 0;
+                    while len < max_len && window[(a + len) & 63] == window[(b + len) & 63] {
+                        len += 1;
+                    }
+                    assert_eq!(len, common_len(&window, a, b, 0, max_len),
+                               "a {} b {} max_len {}", a, b, max_len);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn find_longest() {
+        let mut window = [0u8; 64];
+        window[..20].copy_from_slice(b"abcdxabcdeyabcdefzab");
+        for &search in &[Search::HashChain, Search::BinaryTree] {
+            for &(max_len, max_dist, expected) in &[(16, 63, (5, 6)), (3, 63, (3, 6)),
+                                                    (16, 5, (0, 0))] {
+                let mut mf = MatchFinder::with_search(64, 16, 8, search);
+                for pos in 0..11 {
+                    mf.skip(&window, pos, 16, 63);
+                }
+                // At position 11, "abcdef" matches "abcde" at
+                // distance 6 and "abcd" at distance 11.
+                assert_eq!(expected, mf.find(&window, 11, max_len, max_dist));
+            }
+        }
+    }
+
+    #[test]
+    fn depth_limit() {
+        let mut window = [0u8; 64];
+        window[..21].copy_from_slice(b"abcdxabcyabcyabcabcdx");
+        for &(depth, expected) in &[(1, (3, 3)), (4, (5, 16))] {
+            let mut mf = MatchFinder::new(64, 16, depth);
+            for pos in 0..16 {
+                mf.skip(&window, pos, 5, 63);
+            }
+            assert_eq!(expected, mf.find(&window, 16, 5, 63));
+        }
+    }
+
+    #[test]
+    fn wrap_around() {
+        let data = b"abcxzzzzzzzzabcdzabc";
+        for &search in &[Search::HashChain, Search::BinaryTree] {
+            for &(max_dist, expected) in &[(15, (3, 5)), (4, (0, 0))] {
+                let mut window = [0u8; 16];
+                let mut mf = MatchFinder::with_search(16, 8, 8, search);
+                for pos in 0..data.len() - 2 {
+                    for p in pos..pos + 3 {
+                        window[p % 16] = data[p];
+                    }
+                    if pos < 17 {
+                        mf.skip(&window, pos % 16, 3, 15);
+                    } else {
+                        // The "abc" at stream position 0 has been
+                        // overwritten, only the one at 12 is found.
+                        assert_eq!(expected, mf.find(&window, pos % 16, 3, max_dist));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Compare the matches found for every position of `input` with
+    /// the longest matches found by brute force.
+    fn check_longest(input: &[u8], search: Search) {
+        const MAX_LEN: usize = 32;
+        let window_size = input.len().next_power_of_two();
+        let mut window = vec![0; window_size];
+        window[..input.len()].copy_from_slice(input);
+        let mut mf = MatchFinder::with_search(window_size, 16, 1 << 16, search);
+        for pos in 0..input.len() {
+            let max_len = ::core::cmp::min(MAX_LEN, input.len() - pos);
+            let (len, dist) = mf.find(&window, pos, max_len, window_size - 1);
+            let mut best = 0;
+            for c in 0..pos {
+                let l = (0..max_len).take_while(|&i| input[c + i] == input[pos + i]).count();
+                best = ::core::cmp::max(best, l);
+            }
+            if best >= 3 {
+                assert_eq!(best, len);
+                assert_eq!(&input[pos - dist..pos - dist + len], &input[pos..pos + len]);
+            }
+        }
+    }
+
+    #[test]
+    fn longest_matches() {
+        let input = &include_bytes!("window.rs")[..4000];
+        check_longest(input, Search::HashChain);
+        check_longest(input, Search::BinaryTree);
+    }
+
+    #[test]
+    fn sliding_window() {
+        let mut w = SlidingWindow::new(8);
+        assert_eq!(5, w.fill(b"abcdefgh", 5));
+        w.consume(4);
+        assert_eq!(3, w.fill(b"fgh", 8));
+        assert_eq!(b'e', w.get(0));
+        assert_eq!(b'h', w.get(3));
+        assert_eq!(b"efgh", w.buffered_data());
+        assert_eq!(8, w.filled());
+        // Overlapping copy across the end of the ring buffer.
+        w.copy_match(2, 3);
+        assert_eq!(3, w.match_len(4, 2, 3));
+        assert_eq!(0, w.match_len(0, 1, 3));
+        w.consume(3);
+        assert_eq!(b"h", w.buffered_data());
+        assert_eq!(b"h", w.take_buffered());
+        assert_eq!(b"ghg", w.buffered_data());
+        let mut out = [0u8; 10];
+        assert_eq!(3, w.read(&mut out));
+        assert_eq!(b"ghg", &out[..3]);
+        assert_eq!(0, w.buffered());
+        assert_eq!(w.wrap(11), w.position());
+        assert_eq!(8, w.into_vec().len());
+    }
+}
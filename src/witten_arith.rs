@@ -7,11 +7,27 @@
 //! Radford M. Neal and John G. Cleary: Arithmetic Coding for Data
 //! Compression, Communications of the ACM, Vol. 30, Number 6, June
 //! 1987.  Only the adaptive model is included.
+//!
+//! Besides the original order-0 model, an order-1 model is available,
+//! where the previous byte selects one of 256 frequency tables.  The
+//! tables are only allocated when their context occurs, so that
+//! memory use is bounded by the number of distinct bytes in the
+//! input.  Other implementations of `model::Model` can be used with
+//! `compress_with_model` and `decompress_with_model`.
+//!
+//! `inspect` lists the literals of a stream compressed with one of
+//! the adaptive models.  The stream has neither a header nor blocks.
 
-use std::io::{Read, Write, Bytes};
-use std::io;
+use io::{Read, Write};
+use io;
+use alloc::vec::Vec;
+use alloc::boxed::Box;
 
-use error::Error;
+use error::{at_offset, Error};
+use input::ByteReader;
+use inspect::{Event, Events, Inspect, Recorder};
+use model::{Model, Prob, Symbol, EOF, MAX_TOTAL};
+use model::{write_varint, read_varint, read_tag};
 
 // You can uncomment the following line and comment the line after to
 // try out compression with a smaller word size.  The difference will
@@ -29,7 +45,12 @@ const THIRD_QTR: CodeValue = 3 * FIRST_QTR;
 
 const NO_OF_CHARS: usize = 256;
 
-const EOF_SYMBOL: usize = NO_OF_CHARS + 1;
+/// Symbols are kept in the model as indexes into the frequency
+/// tables, which are sorted by decreasing frequency.  Index 0 is not
+/// used for a symbol.
+type Index = usize;
+
+const EOF_SYMBOL: Index = NO_OF_CHARS + 1;
 const NO_OF_SYMBOLS: usize = EOF_SYMBOL + 1;
 
 // Using a max frequency of 2^14 - 1 actually gives better compression
@@ -37,9 +58,13 @@ const NO_OF_SYMBOLS: usize = EOF_SYMBOL + 1;
 // locality.
 const MAX_FREQUENCY: usize = (1 << 14) - 1;
 
-type Symbol = usize;
+/// Tags of serialized models.
+const ADAPTIVE_MODEL_TAG: u8 = b'W';
+const ORDER1_MODEL_TAG: u8 = b'O';
 
-struct Model {
+/// The adaptive order-0 model from the paper.
+#[derive(Clone)]
+pub struct AdaptiveModel {
     char_to_index: [usize; NO_OF_CHARS],
     index_to_char: [usize; NO_OF_SYMBOLS + 1],
     cum_freq: [usize; NO_OF_SYMBOLS + 1],
@@ -47,9 +72,10 @@ struct Model {
 
 }
 
-impl Model {
-    fn new() -> Self {
-        let mut m = Model {
+impl AdaptiveModel {
+    /// Create a model in which all symbols are equally likely.
+    pub fn new() -> Self {
+        let mut m = AdaptiveModel {
             char_to_index: [0; NO_OF_CHARS],
             index_to_char: [0; NO_OF_SYMBOLS + 1],
             cum_freq: [0; NO_OF_SYMBOLS + 1],
@@ -67,7 +93,102 @@ impl Model {
         m
     }
 
-    fn update(&mut self, symbol: Symbol) {
+    /// Add the given byte counts to the model, as if the bytes had
+    /// been seen that often, and scale the model down until it is
+    /// within the frequency limit again.  The result does not depend
+    /// on the order of `counts`.
+    pub fn preload(&mut self, counts: &[(u8, u64)]) {
+        let mut f = [0u64; NO_OF_CHARS];
+        for ch in 0..NO_OF_CHARS {
+            f[ch] = self.freq[self.char_to_index[ch]] as u64;
+        }
+        for &(ch, n) in counts {
+            f[ch as usize] = f[ch as usize].saturating_add(n);
+        }
+        let mut rest = [self.freq[EOF_SYMBOL] as u64, self.freq[NO_OF_SYMBOLS] as u64];
+        while f.iter().chain(rest.iter()).fold(0, |t: u64, &c| t.saturating_add(c))
+            >= MAX_FREQUENCY as u64 {
+            for c in f.iter_mut().chain(rest.iter_mut()) {
+                *c = (*c + 1) / 2;
+            }
+        }
+
+        // Sort the bytes by decreasing frequency, which the update
+        // procedure relies on.  Ties are broken by byte value.
+        let mut chars: Vec<usize> = (0..NO_OF_CHARS).collect();
+        chars.sort_by(|&a, &b| f[b].cmp(&f[a]).then(a.cmp(&b)));
+        for (i, &ch) in chars.iter().enumerate() {
+            self.index_to_char[i + 1] = ch;
+            self.char_to_index[ch] = i + 1;
+            self.freq[i + 1] = f[ch] as usize;
+        }
+        self.freq[EOF_SYMBOL] = rest[0] as usize;
+        self.freq[NO_OF_SYMBOLS] = rest[1] as usize;
+
+        let mut cum = 0;
+        for i in (0..NO_OF_SYMBOLS + 1).rev() {
+            self.cum_freq[i] = cum;
+            cum += self.freq[i];
+        }
+    }
+
+    /// Write the model to `output`: the bytes in the order of their
+    /// indexes, followed by the frequencies of all indexes.
+    pub fn serialize_model<W: Write>(&self, mut output: W) -> io::Result<()> {
+        try!(output.write_all(&[ADAPTIVE_MODEL_TAG]));
+        let order: Vec<u8> = self.index_to_char[1..NO_OF_CHARS + 1].iter()
+            .map(|&c| c as u8).collect();
+        try!(output.write_all(&order));
+        for &f in self.freq[1..].iter() {
+            try!(write_varint(&mut output, f as u64));
+        }
+        Ok(())
+    }
+
+    /// Read a model written by `serialize_model`.
+    pub fn load_model<R: Read>(mut input: R) -> io::Result<AdaptiveModel> {
+        try!(read_tag(&mut input, ADAPTIVE_MODEL_TAG));
+        let mut order = [0u8; NO_OF_CHARS];
+        try!(input.read_exact(&mut order));
+        let mut m = AdaptiveModel::new();
+        let mut seen = [false; NO_OF_CHARS];
+        for (i, &c) in order.iter().enumerate() {
+            if seen[c as usize] {
+                return Err(Error::corrupt("invalid witten_arith model").into());
+            }
+            seen[c as usize] = true;
+            m.index_to_char[i + 1] = c as usize;
+            m.char_to_index[c as usize] = i + 1;
+        }
+        let mut total = 0;
+        for i in 1..NO_OF_SYMBOLS + 1 {
+            let f = try!(read_varint(&mut input));
+            total += f;
+            // Frequencies must be positive and sorted for the update
+            // procedure to work.
+            if f == 0 || total >= MAX_FREQUENCY as u64 ||
+                (i > 1 && i <= EOF_SYMBOL && f as usize > m.freq[i - 1]) {
+                return Err(Error::corrupt("invalid witten_arith model").into());
+            }
+            m.freq[i] = f as usize;
+        }
+        let mut cum = 0;
+        for i in (0..NO_OF_SYMBOLS + 1).rev() {
+            m.cum_freq[i] = cum;
+            cum += m.freq[i];
+        }
+        Ok(m)
+    }
+
+    fn index(&self, sym: Symbol) -> Index {
+        if sym == EOF {
+            EOF_SYMBOL
+        } else {
+            self.char_to_index[sym]
+        }
+    }
+
+    fn update_index(&mut self, symbol: Index) {
         if self.cum_freq[0] == MAX_FREQUENCY {
             let mut cum = 0;
             let mut i = NO_OF_SYMBOLS;
@@ -102,11 +223,146 @@ impl Model {
 
 }
 
+/// Order of the adaptive model, that is, the number of preceding
+/// bytes used to select the frequency table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// A single frequency table for all symbols.
+    Zero,
+    /// One frequency table per value of the previous byte.
+    One,
+}
+
+impl Model for AdaptiveModel {
+    fn prob(&self, sym: Symbol) -> Prob {
+        let index = self.index(sym);
+        Prob {
+            low: self.cum_freq[index] as u64,
+            high: self.cum_freq[index - 1] as u64,
+            total: self.cum_freq[0] as u64,
+        }
+    }
+
+    fn total(&self) -> u64 {
+        self.cum_freq[0] as u64
+    }
+
+    fn symbol_for(&self, cum: u64) -> Symbol {
+        // Find symbol with the cumulative frequency that matches the
+        // current interval.
+        let mut index = 1;
+        while self.cum_freq[index] as u64 > cum {
+            index += 1;
+        }
+        if index == EOF_SYMBOL {
+            EOF
+        } else {
+            self.index_to_char[index]
+        }
+    }
+
+    fn update(&mut self, sym: Symbol) {
+        let index = self.index(sym);
+        self.update_index(index);
+    }
+}
+
+/// Order-1 model: one `AdaptiveModel` per value of the previous byte.
+#[derive(Clone)]
+pub struct Order1Model {
+    tables: Vec<Option<Box<AdaptiveModel>>>,
+    context: usize,
+}
+
+impl Order1Model {
+    /// Create an order-1 model.  The tables for the individual
+    /// contexts are allocated when they are first used.
+    pub fn new() -> Order1Model {
+        let mut tables = Vec::with_capacity(NO_OF_CHARS);
+        for _ in 0..NO_OF_CHARS {
+            tables.push(None);
+        }
+        let mut m = Order1Model {
+            tables: tables,
+            context: 0,
+        };
+        m.enter(0);
+        m
+    }
+
+    /// Switch to context `context`, allocating its table if needed.
+    fn enter(&mut self, context: usize) {
+        self.context = context;
+        if self.tables[context].is_none() {
+            self.tables[context] = Some(Box::new(AdaptiveModel::new()));
+        }
+    }
+
+    fn current(&self) -> &AdaptiveModel {
+        self.tables[self.context].as_ref().unwrap()
+    }
+
+    /// Write the model to `output`: a bitmap of the contexts which
+    /// have been used, followed by the models for these contexts.
+    pub fn serialize_model<W: Write>(&self, mut output: W) -> io::Result<()> {
+        let mut bitmap = [0u8; NO_OF_CHARS / 8];
+        for (i, t) in self.tables.iter().enumerate() {
+            if t.is_some() {
+                bitmap[i / 8] |= 1 << (i % 8);
+            }
+        }
+        try!(output.write_all(&[ORDER1_MODEL_TAG]));
+        try!(output.write_all(&bitmap));
+        for t in self.tables.iter() {
+            if let Some(ref m) = *t {
+                try!(m.serialize_model(&mut output));
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a model written by `serialize_model`.  Coding starts in
+    /// context 0, regardless of the context the saved model was in.
+    pub fn load_model<R: Read>(mut input: R) -> io::Result<Order1Model> {
+        try!(read_tag(&mut input, ORDER1_MODEL_TAG));
+        let mut bitmap = [0u8; NO_OF_CHARS / 8];
+        try!(input.read_exact(&mut bitmap));
+        let mut m = Order1Model::new();
+        for i in 0..NO_OF_CHARS {
+            if bitmap[i / 8] & (1 << (i % 8)) != 0 {
+                m.tables[i] = Some(Box::new(try!(AdaptiveModel::load_model(&mut input))));
+            }
+        }
+        Ok(m)
+    }
+}
+
+impl Model for Order1Model {
+    fn prob(&self, sym: Symbol) -> Prob {
+        self.current().prob(sym)
+    }
+
+    fn total(&self) -> u64 {
+        self.current().total()
+    }
+
+    fn symbol_for(&self, cum: u64) -> Symbol {
+        self.current().symbol_for(cum)
+    }
+
+    fn update(&mut self, sym: Symbol) {
+        self.tables[self.context].as_mut().unwrap().update(sym);
+        if sym != EOF {
+            self.enter(sym);
+        }
+    }
+}
+
 /// Arithmetic encoder.
-struct Encoder<W> {
+struct Encoder<W, M> {
     inner: W,
 
-    model: Model,
+    model: M,
     
     low: CodeValue,
     high: CodeValue,
@@ -116,12 +372,12 @@ struct Encoder<W> {
     bits_to_go: usize,
 }
 
-impl<W: Write> Encoder<W> {
-    pub fn new(output: W) -> Self {
+impl<W: Write, M: Model> Encoder<W, M> {
+    pub fn new(output: W, model: M) -> Self {
         let enc = Encoder{
             inner: output,
 
-            model: Model::new(),
+            model: model,
             
             low: 0,
             high: TOP_VALUE,
@@ -135,12 +391,12 @@ impl<W: Write> Encoder<W> {
 
     fn encode_symbol(&mut self, symbol: Symbol) -> io::Result<()> {
         let range = (self.high - self.low) + 1;
-        let total = self.model.cum_freq[0] as CodeValue;
-
-        debug_assert!(total <= MAX_FREQUENCY as CodeValue);
+        let p = self.model.prob(symbol);
+        let total = p.total as CodeValue;
+        let hi_freq = p.high as CodeValue;
+        let lo_freq = p.low as CodeValue;
 
-        let hi_freq = self.model.cum_freq[symbol-1] as CodeValue;
-        let lo_freq = self.model.cum_freq[symbol] as CodeValue;
+        debug_assert!(total <= MAX_TOTAL as CodeValue && lo_freq < hi_freq);
 
         self.high = self.low + (range * hi_freq) / total - 1;
         self.low = self.low + (range * lo_freq) / total;
@@ -210,10 +466,10 @@ impl<W: Write> Encoder<W> {
     }
 }
 
-impl<W: Write> Write for Encoder<W> {
+impl<W: Write, M: Model> Write for Encoder<W, M> {
     fn write(&mut self, data: &[u8]) -> io::Result<usize> {
         for b in data {
-            let symbol = self.model.char_to_index[*b as usize];
+            let symbol = *b as Symbol;
             try!(self.encode_symbol(symbol));
             self.model.update(symbol);
         }
@@ -221,17 +477,17 @@ impl<W: Write> Write for Encoder<W> {
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        try!(self.encode_symbol(EOF_SYMBOL));
+        try!(self.encode_symbol(EOF));
         try!(self.done_encoding());
         self.done_outputting_bits()
     }
 }
 
 /// Arithmetic decoder.
-struct Decoder<R> {
-    inner: Bytes<R>,
+struct Decoder<R, M> {
+    inner: ByteReader<R>,
 
-    model: Model,
+    model: M,
     
     value: CodeValue,
     low: CodeValue,
@@ -239,17 +495,20 @@ struct Decoder<R> {
 
     buffer: u8,
     bits_to_go: usize,
+    /// Number of bits read after the end of the input.
     garbage_bits: usize,
+    past_end: bool,
 
     eof: bool,
+    recorder: Recorder,
 }
 
-impl<R: Read> Decoder<R> {
-    pub fn new(input: R) -> io::Result<Self> {
+impl<R: Read, M: Model> Decoder<R, M> {
+    pub fn new(input: R, model: M) -> io::Result<Self> {
         let mut dec = Decoder{
-            inner: input.bytes(),
+            inner: ByteReader::new(input),
 
-            model: Model::new(),
+            model: model,
             
             value: 0,
             low: 0,
@@ -258,8 +517,10 @@ impl<R: Read> Decoder<R> {
             buffer: 0,
             bits_to_go: 0,
             garbage_bits: 0,
+            past_end: false,
 
             eof: false,
+            recorder: Recorder::new(),
         };
         for _ in 0..CODE_VALUE_BITS {
             dec.value = (dec.value << 1) | (try!(dec.input_bit()) as CodeValue);
@@ -267,21 +528,28 @@ impl<R: Read> Decoder<R> {
         Ok(dec)
     }
 
+    /// Read the next bit.  The encoder writes only two bits of the
+    /// final code value, so that the decoder reads up to
+    /// `CODE_VALUE_BITS - 2` bits after the end of the input; any
+    /// more means that the input is truncated.
     fn input_bit(&mut self) -> io::Result<usize> {
         if self.bits_to_go == 0 {
-            if let Some(b) = self.inner.next() {
+            if self.past_end {
+                self.buffer = 0xff;
+            } else if let Some(b) = self.inner.next() {
                 self.buffer = try!(b);
             } else {
-                self.garbage_bits += 1;
-                if self.garbage_bits > CODE_VALUE_BITS - 2 {
-                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
-                                              "cannot read from bit stream"));
-                } else {
-                    self.buffer = 0xff;
-                }
+                self.past_end = true;
+                self.buffer = 0xff;
             }
             self.bits_to_go = 8;
         }
+        if self.past_end {
+            self.garbage_bits += 1;
+            if self.garbage_bits > CODE_VALUE_BITS - 2 {
+                return Err(Error::UnexpectedEof.into());
+            }
+        }
         let t = self.buffer & 1;
         self.buffer >>= 1;
         self.bits_to_go -= 1;
@@ -291,21 +559,19 @@ impl<R: Read> Decoder<R> {
     fn decode_symbol(&mut self) -> io::Result<Symbol> {
 
         let range = self.high - self.low + 1;
-        let total = self.model.cum_freq[0] as CodeValue;
+        let total = self.model.total() as CodeValue;
         let cum = ((self.value - self.low + 1) * total - 1) / range;
 
-        // Find symbol with the cumulative frequency that matches the
-        // current interval.
-        let mut symbol = 1;
-        while self.model.cum_freq[symbol] as CodeValue > cum {
-            symbol += 1;
-        }
-
-        let lo_freq = self.model.cum_freq[symbol] as CodeValue;
-        let hi_freq = self.model.cum_freq[symbol - 1] as CodeValue;
+        let symbol = self.model.symbol_for(cum as u64);
+        let p = self.model.prob(symbol);
+        let lo_freq = p.low as CodeValue;
+        let hi_freq = p.high as CodeValue;
         
         self.high = self.low + (range * hi_freq / total) - 1;
         self.low = self.low + (range * lo_freq / total);
+        if self.value < self.low || self.value > self.high {
+            return Err(Error::corrupt("code value outside of interval").into());
+        }
 
         loop {
             if self.high < HALF {
@@ -329,7 +595,7 @@ impl<R: Read> Decoder<R> {
     }
 }
 
-impl<R: Read> Read for Decoder<R> {
+impl<R: Read, M: Model> Read for Decoder<R, M> {
     fn read(&mut self, data: &mut [u8]) -> io::Result<usize> {
         if self.eof {
             return Ok(0);
@@ -337,13 +603,15 @@ impl<R: Read> Read for Decoder<R> {
        
         let mut written = 0;
         while written < data.len()  {
-            let symbol = try!(self.decode_symbol());
-            if symbol == EOF_SYMBOL {
+            let symbol = try!(self.decode_symbol()
+                              .map_err(|e| at_offset(e, self.inner.position())));
+            if symbol == EOF {
+                self.recorder.record(Event::End);
                 self.eof = true;
                 break;
             }
-            let ch = self.model.index_to_char[symbol as usize];
-            data[written] = ch as u8;
+            self.recorder.record(Event::Literal(symbol as u8));
+            data[written] = symbol as u8;
             written += 1;
             self.model.update(symbol);
         }
@@ -351,28 +619,119 @@ impl<R: Read> Read for Decoder<R> {
     }
 }
 
+impl<R: Read, M: Model> Inspect for Decoder<R, M> {
+    fn recorder(&mut self) -> &mut Recorder {
+        &mut self.recorder
+    }
+}
+
+/// Iterator over the events of a stream, see `inspect`.
+pub struct Inspector<R> {
+    events: Events<Decoder<R, Box<dyn Model>>>,
+}
+
+impl<R: Read> Iterator for Inspector<R> {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<io::Result<Event>> {
+        self.events.next()
+    }
+}
+
 /// Read all data from `input`, compress it using an order-0
 /// arithmetic encoder and write the compressed data to `output`.
-pub fn compress<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error> {
-    let mut cw = Encoder::new(output);
-    try!(io::copy(&mut input, &mut cw));
-    try!(cw.flush());
-    Ok(cw.into_inner())
+pub fn compress<R: Read, W: Write>(input: R, output: W) -> Result<W, Error> {
+    compress_with_order(input, output, Order::Zero)
 }
 
 /// Read all data from `input`, decompress it using an order-0
 /// arithmetic encoder and write the decompressed data to `output`.
 /// The data must be produced by the `compress` function.
-pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<W, Error> {
-    let mut cr = try!(Decoder::new(input));
+pub fn decompress<R: Read, W: Write>(input: R, output: W) -> Result<W, Error> {
+    decompress_with_order(input, output, Order::Zero)
+}
+
+/// Read all data from `input`, compress it using an arithmetic
+/// encoder with a model of the given order and write the compressed
+/// data to `output`.
+pub fn compress_with_order<R: Read, W: Write>(input: R, output: W,
+                                              order: Order) -> Result<W, Error> {
+    match order {
+        Order::Zero => compress_with_model(input, output, AdaptiveModel::new()),
+        Order::One => compress_with_model(input, output, Order1Model::new()),
+    }
+}
+
+/// Read all data from `input`, decompress it using an arithmetic
+/// decoder with a model of the given order and write the
+/// decompressed data to `output`.  The order must be the same as the
+/// one used for compression.
+pub fn decompress_with_order<R: Read, W: Write>(input: R, output: W,
+                                                order: Order) -> Result<W, Error> {
+    match order {
+        Order::Zero => decompress_with_model(input, output, AdaptiveModel::new()),
+        Order::One => decompress_with_model(input, output, Order1Model::new()),
+    }
+}
+
+/// Read all data from `input`, compress it using an arithmetic
+/// encoder with probabilities from `model` and write the compressed
+/// data to `output`.
+pub fn compress_with_model<R: Read, W: Write, M: Model>(mut input: R, output: W,
+                                                        model: M) -> Result<W, Error> {
+    let mut cw = Encoder::new(output, model);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.into_inner())
+}
+
+/// Read all data from `input`, decompress it using an arithmetic
+/// decoder with probabilities from `model` and write the
+/// decompressed data to `output`.  The model must start out in the
+/// same state as the one used for compression.
+pub fn decompress_with_model<R: Read, W: Write, M: Model>(input: R, mut output: W,
+                                                          model: M) -> Result<W, Error> {
+    let mut cr = try!(Decoder::new(input, model));
     try!(io::copy(&mut cr, &mut output));
     Ok(output)
 }
 
-#[cfg(test)]
+/// Return an iterator over the literals of the stream in `input`,
+/// which was compressed with a model of the given order.  The first
+/// event is a header naming the model, which is not stored in the
+/// stream.  This reads the first bytes of the input, therefore the
+/// result can be an error.
+pub fn inspect<R: Read>(input: R, order: Order) -> io::Result<Inspector<R>> {
+    let (model, name): (Box<dyn Model>, &str) = match order {
+        Order::Zero => (Box::new(AdaptiveModel::new()), "adaptive order-0 model"),
+        Order::One => (Box::new(Order1Model::new()), "adaptive order-1 model"),
+    };
+    let mut dec = try!(Decoder::new(input, model));
+    dec.recorder.enable();
+    dec.recorder.record(Event::Header(name.into()));
+    Ok(Inspector { events: Events::new(dec) })
+}
+
+#[cfg(all(test, feature = "std"))]
 mod test {
     use std::io::Cursor;
-    use super::{compress, decompress};
+    use super::{compress, decompress, compress_with_order, decompress_with_order, Order};
+    use super::{compress_with_model, decompress_with_model, AdaptiveModel, Order1Model};
+    use super::MAX_FREQUENCY;
+    use model::{Model, train};
+    use inspect::Event;
+
+    #[test]
+    fn inspect_events() {
+        for &(order, name) in &[(Order::Zero, "adaptive order-0 model"),
+                                (Order::One, "adaptive order-1 model")] {
+            let compressed = compress_with_order(Cursor::new(b"ab"), vec![], order).unwrap();
+            let events: Vec<Event> = super::inspect(Cursor::new(compressed), order).unwrap()
+                .map(|e| e.unwrap()).collect();
+            assert_eq!(vec![Event::Header(name.into()), Event::Literal(b'a'),
+                            Event::Literal(b'b'), Event::End], events);
+        }
+    }
 
     #[test]
     fn compress_empty() {
@@ -431,4 +790,94 @@ mod test {
             
         assert_eq!(&original[..], &decompressed[..]);
     }
+
+    #[test]
+    fn compress_decompress_order1() {
+        let f = include_bytes!("witten_arith.rs");
+        let original = &f[..];
+        let compressed0 = compress(Cursor::new(&original), vec![]).unwrap();
+        let compressed = compress_with_order(Cursor::new(&original), vec![], Order::One).unwrap();
+        assert!(compressed.len() < compressed0.len());
+        let decompressed = decompress_with_order(Cursor::new(compressed), vec![],
+                                                 Order::One).unwrap();
+        assert_eq!(&original[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn preload() {
+        let input = b"The banana goat in the banana boat can hand bananas to the banana man.";
+        let counts = [(b'a', 20000), (b'n', 9000), (b' ', 5000), (b'b', 100), (b'a', 3000)];
+        let mut reversed = counts.to_vec();
+        reversed.reverse();
+
+        let mut m = AdaptiveModel::new();
+        m.preload(&counts);
+        assert!(m.total() < MAX_FREQUENCY as u64);
+        assert!(m.prob(b'a' as usize).high - m.prob(b'a' as usize).low >
+                m.prob(b'n' as usize).high - m.prob(b'n' as usize).low);
+        let compressed = compress_with_model(Cursor::new(&input[..]), vec![], m).unwrap();
+        assert!(compressed.len() < compress(Cursor::new(&input[..]), vec![]).unwrap().len());
+
+        let mut m = AdaptiveModel::new();
+        m.preload(&reversed);
+        let compressed2 = compress_with_model(Cursor::new(&input[..]), vec![], m).unwrap();
+        assert_eq!(compressed, compressed2);
+
+        let mut m = AdaptiveModel::new();
+        m.preload(&counts);
+        let decompressed = decompress_with_model(Cursor::new(compressed), vec![], m).unwrap();
+        assert_eq!(&input[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn serialize_load_model() {
+        let sample = include_bytes!("witten_arith.rs");
+        let message = b"    /// Read a model written by `serialize_model`.";
+
+        let mut m = AdaptiveModel::new();
+        train(&mut m, &sample[..]);
+        let mut saved = vec![];
+        m.serialize_model(&mut saved).unwrap();
+        let loaded = AdaptiveModel::load_model(Cursor::new(&saved[..])).unwrap();
+        let compressed = compress_with_model(Cursor::new(&message[..]), vec![], loaded).unwrap();
+        assert!(compressed.len() < compress(Cursor::new(&message[..]), vec![]).unwrap().len());
+        let loaded = AdaptiveModel::load_model(Cursor::new(&saved[..])).unwrap();
+        let decompressed = decompress_with_model(Cursor::new(compressed), vec![], loaded).unwrap();
+        assert_eq!(&message[..], &decompressed[..]);
+
+        let mut m = Order1Model::new();
+        train(&mut m, &sample[..]);
+        let mut saved1 = vec![];
+        m.serialize_model(&mut saved1).unwrap();
+        let loaded = Order1Model::load_model(Cursor::new(&saved1[..])).unwrap();
+        let compressed1 = compress_with_model(Cursor::new(&message[..]), vec![], loaded).unwrap();
+        let loaded = Order1Model::load_model(Cursor::new(&saved1[..])).unwrap();
+        let decompressed = decompress_with_model(Cursor::new(compressed1), vec![], loaded).unwrap();
+        assert_eq!(&message[..], &decompressed[..]);
+
+        assert!(Order1Model::load_model(Cursor::new(&saved[..])).is_err());
+        saved[1] = saved[2];
+        assert!(AdaptiveModel::load_model(Cursor::new(&saved[..])).is_err());
+    }
+
+    #[test]
+    fn decompress_truncated() {
+        let input = b"The banana goat in the banana boat can hand bananas to the banana man.";
+        for order in [Order::Zero, Order::One].iter() {
+            let compressed = compress_with_order(Cursor::new(&input[..]), vec![],
+                                                 *order).unwrap();
+            for len in 0..compressed.len() {
+                let res = decompress_with_order(Cursor::new(&compressed[..len]), vec![], *order);
+                assert!(res.is_err(), "truncated to {} bytes", len);
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+        ::testutil::roundtrip((|d: &[u8]| super::compress_with_order(d, vec![], Order::One),
+                               |d: &[u8]| super::decompress_with_order(d, vec![], Order::One)));
+    }
 }
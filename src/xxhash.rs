@@ -0,0 +1,366 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! xxHash checksums.
+//!
+//! XXH32 and XXH64, fast non-cryptographic hash functions by Yann
+//! Collet, used as content checksums.  Both come as a streaming
+//! hasher, which can be updated with data as it passes through a
+//! writer or reader, and as a function hashing a slice in one go.
+//! The results match the reference implementation, which the LZ4
+//! frame format depends on.
+
+use core::cmp;
+
+const PRIME32_1: u32 = 2654435761;
+const PRIME32_2: u32 = 2246822519;
+const PRIME32_3: u32 = 3266489917;
+const PRIME32_4: u32 = 668265263;
+const PRIME32_5: u32 = 374761393;
+
+const PRIME64_1: u64 = 11400714785074694791;
+const PRIME64_2: u64 = 14029467366897019727;
+const PRIME64_3: u64 = 1609587929392839161;
+const PRIME64_4: u64 = 9650029242287828579;
+const PRIME64_5: u64 = 2870177450012600261;
+
+#[inline(always)]
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+#[inline(always)]
+fn read_u64(data: &[u8], pos: usize) -> u64 {
+    let mut b = [0u8; 8];
+    b.copy_from_slice(&data[pos..pos + 8]);
+    u64::from_le_bytes(b)
+}
+
+/// Streaming XXH32.
+#[derive(Clone)]
+pub struct Xxh32 {
+    seed: u32,
+    v: [u32; 4],
+    total_len: u64,
+    mem: [u8; 16],
+    mem_size: usize,
+}
+
+#[inline(always)]
+fn xxh32_round(acc: u32, input: u32) -> u32 {
+    acc.wrapping_add(input.wrapping_mul(PRIME32_2))
+        .rotate_left(13)
+        .wrapping_mul(PRIME32_1)
+}
+
+impl Xxh32 {
+    /// Create a hasher with seed 0.
+    pub fn new() -> Xxh32 {
+        Xxh32::with_seed(0)
+    }
+
+    pub fn with_seed(seed: u32) -> Xxh32 {
+        Xxh32 {
+            seed: seed,
+            v: [seed.wrapping_add(PRIME32_1).wrapping_add(PRIME32_2),
+                seed.wrapping_add(PRIME32_2),
+                seed,
+                seed.wrapping_sub(PRIME32_1)],
+            total_len: 0,
+            mem: [0; 16],
+            mem_size: 0,
+        }
+    }
+
+    fn stripe(&mut self, data: &[u8]) {
+        for i in 0..4 {
+            self.v[i] = xxh32_round(self.v[i], read_u32(data, i * 4));
+        }
+    }
+
+    /// Add `data` to the hash.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        if self.mem_size > 0 {
+            let n = cmp::min(16 - self.mem_size, data.len());
+            self.mem[self.mem_size..self.mem_size + n].copy_from_slice(&data[..n]);
+            self.mem_size += n;
+            data = &data[n..];
+            if self.mem_size < 16 {
+                return;
+            }
+            let mem = self.mem;
+            self.stripe(&mem);
+            self.mem_size = 0;
+        }
+        while data.len() >= 16 {
+            self.stripe(&data[..16]);
+            data = &data[16..];
+        }
+        self.mem[..data.len()].copy_from_slice(data);
+        self.mem_size = data.len();
+    }
+
+    /// Return the hash of the data added so far.
+    pub fn value(&self) -> u32 {
+        let mut h = if self.total_len >= 16 {
+            self.v[0].rotate_left(1)
+                .wrapping_add(self.v[1].rotate_left(7))
+                .wrapping_add(self.v[2].rotate_left(12))
+                .wrapping_add(self.v[3].rotate_left(18))
+        } else {
+            self.seed.wrapping_add(PRIME32_5)
+        };
+        h = h.wrapping_add(self.total_len as u32);
+
+        let mut rest = &self.mem[..self.mem_size];
+        while rest.len() >= 4 {
+            h = h.wrapping_add(read_u32(rest, 0).wrapping_mul(PRIME32_3))
+                .rotate_left(17)
+                .wrapping_mul(PRIME32_4);
+            rest = &rest[4..];
+        }
+        for &b in rest {
+            h = h.wrapping_add((b as u32).wrapping_mul(PRIME32_5))
+                .rotate_left(11)
+                .wrapping_mul(PRIME32_1);
+        }
+
+        h ^= h >> 15;
+        h = h.wrapping_mul(PRIME32_2);
+        h ^= h >> 13;
+        h = h.wrapping_mul(PRIME32_3);
+        h ^= h >> 16;
+        h
+    }
+}
+
+/// Return the XXH32 hash of `data` with seed 0.
+pub fn xxh32(data: &[u8]) -> u32 {
+    let mut x = Xxh32::new();
+    x.update(data);
+    x.value()
+}
+
+/// Streaming XXH64.
+#[derive(Clone)]
+pub struct Xxh64 {
+    seed: u64,
+    v: [u64; 4],
+    total_len: u64,
+    mem: [u8; 32],
+    mem_size: usize,
+}
+
+#[inline(always)]
+fn xxh64_round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME64_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME64_1)
+}
+
+#[inline(always)]
+fn xxh64_merge_round(acc: u64, val: u64) -> u64 {
+    (acc ^ xxh64_round(0, val))
+        .wrapping_mul(PRIME64_1)
+        .wrapping_add(PRIME64_4)
+}
+
+impl Xxh64 {
+    /// Create a hasher with seed 0.
+    pub fn new() -> Xxh64 {
+        Xxh64::with_seed(0)
+    }
+
+    pub fn with_seed(seed: u64) -> Xxh64 {
+        Xxh64 {
+            seed: seed,
+            v: [seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+                seed.wrapping_add(PRIME64_2),
+                seed,
+                seed.wrapping_sub(PRIME64_1)],
+            total_len: 0,
+            mem: [0; 32],
+            mem_size: 0,
+        }
+    }
+
+    fn stripe(&mut self, data: &[u8]) {
+        for i in 0..4 {
+            self.v[i] = xxh64_round(self.v[i], read_u64(data, i * 8));
+        }
+    }
+
+    /// Add `data` to the hash.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        if self.mem_size > 0 {
+            let n = cmp::min(32 - self.mem_size, data.len());
+            self.mem[self.mem_size..self.mem_size + n].copy_from_slice(&data[..n]);
+            self.mem_size += n;
+            data = &data[n..];
+            if self.mem_size < 32 {
+                return;
+            }
+            let mem = self.mem;
+            self.stripe(&mem);
+            self.mem_size = 0;
+        }
+        while data.len() >= 32 {
+            self.stripe(&data[..32]);
+            data = &data[32..];
+        }
+        self.mem[..data.len()].copy_from_slice(data);
+        self.mem_size = data.len();
+    }
+
+    /// Return the hash of the data added so far.
+    pub fn value(&self) -> u64 {
+        let mut h = if self.total_len >= 32 {
+            let v = &self.v;
+            let mut h = v[0].rotate_left(1)
+                .wrapping_add(v[1].rotate_left(7))
+                .wrapping_add(v[2].rotate_left(12))
+                .wrapping_add(v[3].rotate_left(18));
+            for &x in v {
+                h = xxh64_merge_round(h, x);
+            }
+            h
+        } else {
+            self.seed.wrapping_add(PRIME64_5)
+        };
+        h = h.wrapping_add(self.total_len);
+
+        let mut rest = &self.mem[..self.mem_size];
+        while rest.len() >= 8 {
+            h = (h ^ xxh64_round(0, read_u64(rest, 0)))
+                .rotate_left(27)
+                .wrapping_mul(PRIME64_1)
+                .wrapping_add(PRIME64_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            h = (h ^ (read_u32(rest, 0) as u64).wrapping_mul(PRIME64_1))
+                .rotate_left(23)
+                .wrapping_mul(PRIME64_2)
+                .wrapping_add(PRIME64_3);
+            rest = &rest[4..];
+        }
+        for &b in rest {
+            h = (h ^ (b as u64).wrapping_mul(PRIME64_5))
+                .rotate_left(11)
+                .wrapping_mul(PRIME64_1);
+        }
+
+        h ^= h >> 33;
+        h = h.wrapping_mul(PRIME64_2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(PRIME64_3);
+        h ^= h >> 32;
+        h
+    }
+}
+
+/// Length of the state saved by `Xxh64::save`.
+#[cfg(feature = "std")]
+pub(crate) const XXH64_STATE_LEN: usize = 6 * 8 + 1 + 32;
+
+#[cfg(feature = "std")]
+impl Xxh64 {
+    /// Append the state of the hasher to `out`, so that hashing can
+    /// be continued later with `restore`.
+    pub(crate) fn save(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        for v in &self.v {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        out.extend_from_slice(&self.total_len.to_le_bytes());
+        out.push(self.mem_size as u8);
+        out.extend_from_slice(&self.mem);
+    }
+
+    /// Return a hasher with the state saved by `save` in `state`, or
+    /// `None` if the state is inconsistent.
+    pub(crate) fn restore(state: &[u8]) -> Option<Xxh64> {
+        if state.len() != XXH64_STATE_LEN {
+            return None;
+        }
+        let mut x = Xxh64::new();
+        x.seed = read_u64(state, 0);
+        for i in 0..4 {
+            x.v[i] = read_u64(state, 8 + i * 8);
+        }
+        x.total_len = read_u64(state, 40);
+        x.mem_size = state[48] as usize;
+        x.mem.copy_from_slice(&state[49..]);
+        if x.mem_size as u64 != x.total_len % 32 {
+            return None;
+        }
+        Some(x)
+    }
+}
+
+/// Return the XXH64 hash of `data` with seed 0.
+pub fn xxh64(data: &[u8]) -> u64 {
+    let mut x = Xxh64::new();
+    x.update(data);
+    x.value()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{xxh32, xxh64, Xxh32, Xxh64};
+
+    const SPAM: &'static [u8] = b"Nobody inspects the spammish repetition";
+
+    #[test]
+    fn xxh32_values() {
+        assert_eq!(0x02cc5d05, xxh32(b""));
+        assert_eq!(0x32d153ff, xxh32(b"abc"));
+        assert_eq!(0x983b3cee,
+                   xxh32(b"abcdefghijklmnopqrstuvwxyz0123456789abcdefghijklmnopqrstuvwxyz"));
+        assert_eq!(0xe2293b2f, xxh32(SPAM));
+    }
+
+    #[test]
+    fn xxh64_values() {
+        assert_eq!(0xef46db3751d8e999, xxh64(b""));
+        assert_eq!(0x44bc2cf5ad770999, xxh64(b"abc"));
+        assert_eq!(0xfbcea83c8a378bf1, xxh64(SPAM));
+    }
+
+    #[test]
+    fn streaming() {
+        let data = include_bytes!("xxhash.rs");
+        for &piece in &[1, 3, 16, 31, 100] {
+            let mut h32 = Xxh32::with_seed(7);
+            let mut h64 = Xxh64::with_seed(7);
+            for p in data.chunks(piece) {
+                h32.update(p);
+                h64.update(p);
+            }
+            let mut one32 = Xxh32::with_seed(7);
+            one32.update(data);
+            let mut one64 = Xxh64::with_seed(7);
+            one64.update(data);
+            assert_eq!(one32.value(), h32.value());
+            assert_eq!(one64.value(), h64.value());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn save_restore() {
+        let data = include_bytes!("xxhash.rs");
+        let mut h = Xxh64::with_seed(3);
+        h.update(&data[..1001]);
+        let mut state = Vec::new();
+        h.save(&mut state);
+        let mut r = Xxh64::restore(&state).unwrap();
+        h.update(&data[1001..]);
+        r.update(&data[1001..]);
+        assert_eq!(h.value(), r.value());
+        state[48] ^= 1;
+        assert!(Xxh64::restore(&state).is_none());
+    }
+}
@@ -0,0 +1,320 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! zlib (RFC 1950) streams.
+//!
+//! A zlib stream is a two-byte header announcing the compression
+//! method and window size, a raw DEFLATE stream and a big-endian
+//! Adler-32 checksum of the uncompressed data.  The window size
+//! written by the compressor is honored by the decompressor, so
+//! streams produced with a small window can be decoded with a small
+//! amount of memory.  `inspect` lists the header and the events of
+//! the DEFLATE stream, see `deflate::inspect`.
+
+use std::io::{Read, Write};
+use std::io;
+
+use checksum::Adler32;
+use error::{relocated, Error};
+use deflate;
+use inspect::{Event, Events, Inspect, Recorder};
+
+const CM_DEFLATE: u8 = 8;
+const FDICT: u8 = 0x20;
+const DEFAULT_LEVEL: u8 = 2;
+
+fn invalid_header(msg: &'static str) -> io::Error {
+    Error::InvalidHeader(msg).into()
+}
+
+/// Writer for zlib streams.
+pub struct Writer<W> {
+    inner: deflate::Writer<W>,
+    adler: Adler32,
+    header_written: bool,
+    finished: bool,
+}
+
+impl<W: Write> Writer<W> {
+    /// Create a zlib writer using a 32 KiB window.
+    pub fn new(inner: W) -> Writer<W> {
+        Writer::with_window_bits(inner, 15)
+    }
+
+    /// Create a zlib writer using a window of `1 << window_bits`
+    /// bytes, where `window_bits` is between 8 and 15.  The window
+    /// size is recorded in the stream header.
+    pub fn with_window_bits(inner: W, window_bits: usize) -> Writer<W> {
+        Writer {
+            inner: deflate::Writer::with_window_bits(inner, window_bits),
+            adler: Adler32::new(),
+            header_written: false,
+            finished: false,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            let cinfo = (self.inner.window_bits() - 8) as u8;
+            let cmf = (cinfo << 4) | CM_DEFLATE;
+            let mut flg = DEFAULT_LEVEL << 6;
+            let check = ((cmf as u16) << 8 | flg as u16) % 31;
+            if check != 0 {
+                flg += (31 - check) as u8;
+            }
+            try!(self.inner_mut().write_all(&[cmf, flg]));
+            self.header_written = true;
+        }
+        Ok(())
+    }
+
+    fn inner_mut(&mut self) -> &mut W {
+        self.inner.get_mut()
+    }
+
+    /// Terminate the DEFLATE stream and append the checksum.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.finished {
+            try!(self.write_header());
+            try!(self.inner.finish());
+            let v = self.adler.value();
+            try!(self.inner_mut().write_all(&[(v >> 24) as u8, (v >> 16) as u8,
+                                              (v >> 8) as u8, v as u8]));
+            self.finished = true;
+        }
+        Ok(())
+    }
+
+    /// Return the number of uncompressed bytes written so far.
+    pub fn total_in(&self) -> u64 {
+        self.inner.total_in()
+    }
+
+    /// Return the number of bytes written to the wrapped writer so
+    /// far, including the zlib header and trailer.
+    pub fn total_out(&self) -> u64 {
+        let header = if self.header_written { 2 } else { 0 };
+        let trailer = if self.finished { 4 } else { 0 };
+        header + self.inner.total_out() + trailer
+    }
+
+    /// Move the wrapped writer out of the zlib writer.
+    pub fn into_inner(self) -> W {
+        self.inner.into_inner()
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        try!(self.write_header());
+        let n = try!(self.inner.write(buf));
+        self.adler.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.finish());
+        self.inner_mut().flush()
+    }
+}
+
+/// Reader for zlib streams.
+pub struct Reader<R> {
+    inner: deflate::Reader<R>,
+    adler: Adler32,
+    eof: bool,
+    window_bits: usize,
+    level: u8,
+}
+
+impl<R: Read> Reader<R> {
+    /// Create a new zlib reader.  This reads and validates the stream
+    /// header, therefore the result can be an error.
+    pub fn new(mut inner: R) -> io::Result<Reader<R>> {
+        let mut hdr = [0u8; 2];
+        try!(inner.read_exact(&mut hdr));
+        let (cmf, flg) = (hdr[0], hdr[1]);
+        if cmf & 0x0f != CM_DEFLATE {
+            return Err(invalid_header("unsupported zlib compression method"));
+        }
+        let cinfo = (cmf >> 4) as usize;
+        if cinfo > 7 {
+            return Err(invalid_header("invalid zlib window size"));
+        }
+        if ((cmf as u16) << 8 | flg as u16) % 31 != 0 {
+            return Err(invalid_header("zlib header check failed"));
+        }
+        if flg & FDICT != 0 {
+            return Err(invalid_header("preset dictionaries are not supported"));
+        }
+        Ok(Reader {
+            inner: deflate::Reader::with_window_bits(inner, cinfo + 8),
+            adler: Adler32::new(),
+            eof: false,
+            window_bits: cinfo + 8,
+            level: flg >> 6,
+        })
+    }
+
+    /// Return the number of compressed bytes read so far, including
+    /// the zlib header and trailer.
+    pub fn total_in(&self) -> u64 {
+        2 + self.inner.total_in()
+    }
+
+    /// Return the number of decompressed bytes read so far.
+    pub fn total_out(&self) -> u64 {
+        self.inner.total_out()
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
+        if self.eof {
+            return Ok(0);
+        }
+        let n = try!(self.inner.read(output).map_err(|e| relocated(e, 2)));
+        self.adler.update(&output[..n]);
+        if n == 0 && !output.is_empty() {
+            let mut trailer = [0u8; 4];
+            try!(self.inner.read_trailer(&mut trailer));
+            let expected = (trailer[0] as u32) << 24 | (trailer[1] as u32) << 16 |
+                (trailer[2] as u32) << 8 | trailer[3] as u32;
+            if expected != self.adler.value() {
+                return Err(Error::ChecksumMismatch {
+                    expected: expected as u64,
+                    actual: self.adler.value() as u64,
+                }.into());
+            }
+            self.eof = true;
+        }
+        Ok(n)
+    }
+}
+
+impl<R: Read> Inspect for Reader<R> {
+    fn recorder(&mut self) -> &mut Recorder {
+        self.inner.recorder()
+    }
+}
+
+/// Compress all data from `input` into a zlib stream written to
+/// `output`.  On success, the output is returned.
+pub fn compress<R: Read, W: Write>(mut input: R, output: W) -> Result<W, Error> {
+    let mut cw = Writer::new(output);
+    try!(io::copy(&mut input, &mut cw));
+    try!(cw.flush());
+    Ok(cw.into_inner())
+}
+
+/// Decompress the zlib stream in `input` and write the result to
+/// `output`.  On success, the output is returned.
+pub fn decompress<R: Read, W: Write>(input: R, mut output: W) -> Result<W, Error> {
+    let mut cr = try!(Reader::new(input));
+    try!(io::copy(&mut cr, &mut output));
+    Ok(output)
+}
+
+/// Return an iterator over the header and the DEFLATE events of the
+/// zlib stream in `input`.  This reads the header, therefore the
+/// result can be an error.
+pub fn inspect<R: Read>(input: R) -> io::Result<Events<Reader<R>>> {
+    let mut cr = try!(Reader::new(input));
+    let header = Event::Header(format!("window {} bits, level {}", cr.window_bits, cr.level));
+    cr.recorder().enable();
+    cr.recorder().record(header);
+    Ok(Events::new(cr))
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::io::{Cursor, Read, Write};
+    use super::{compress, decompress, Reader, Writer};
+
+    #[test]
+    fn compress_empty() {
+        let compressed = compress(Cursor::new(&b""[..]), vec![]).unwrap();
+        assert_eq!(&[120, 156, 3, 0, 0, 0, 0, 1][..], &compressed[..]);
+    }
+
+    #[test]
+    fn compress_small_window() {
+        let mut cw = Writer::with_window_bits(vec![], 10);
+        cw.write_all(b"aaaaaaaaa").unwrap();
+        cw.flush().unwrap();
+        let compressed = cw.into_inner();
+        assert_eq!(&[40, 145, 75, 132, 1, 0, 17, 22, 3, 106][..], &compressed[..]);
+    }
+
+    #[test]
+    fn decompress_zlib() {
+        // Produced by zlib at level 6.
+        let input = [120, 156, 75, 76, 74, 78, 73, 77, 75, 207, 200, 204, 202,
+                     206, 201, 205, 203, 47, 40, 44, 42, 46, 41, 45, 43, 175,
+                     168, 172, 74, 36, 67, 6, 0, 21, 79, 33, 94];
+        let decompressed = decompress(Cursor::new(&input[..]), vec![]).unwrap();
+        assert_eq!(&b"abcdefghijklmnopqrstuvwxyzabcdefghijklmnopqrstuvwxyz\
+                      abcdefghijklmnopqrstuvwxyz"[..],
+                   &decompressed[..]);
+    }
+
+    #[test]
+    fn inspect_events() {
+        use inspect::Event;
+        let compressed = compress(Cursor::new(&b"aaaa"[..]), vec![]).unwrap();
+        let events: Vec<Event> =
+            super::inspect(Cursor::new(&compressed[..])).unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(vec![Event::Header("window 15 bits, level 2".to_string()),
+                        Event::Block("fixed codes, final".to_string()), Event::Literal(b'a'),
+                        Event::Match { offset: 1, len: 3 }, Event::End],
+                   events);
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let mut compressed = compress(Cursor::new(&b"hello"[..]), vec![]).unwrap();
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        let mut cr = Reader::new(Cursor::new(compressed)).unwrap();
+        let mut decompressed = Vec::new();
+        assert!(cr.read_to_end(&mut decompressed).is_err());
+    }
+
+    #[test]
+    fn bad_header() {
+        assert!(Reader::new(Cursor::new(vec![120, 157, 3, 0])).is_err());
+        assert!(Reader::new(Cursor::new(vec![136, 152, 3, 0])).is_err());
+    }
+
+    #[test]
+    fn compress_decompress() {
+        let input = include_bytes!("zlib.rs");
+        let compressed = compress(Cursor::new(&input[..]), vec![]).unwrap();
+        let decompressed = decompress(Cursor::new(&compressed[..]), vec![]).unwrap();
+        assert_eq!(&input[..], &decompressed[..]);
+    }
+
+    #[test]
+    fn totals() {
+        let input = include_bytes!("zlib.rs");
+        let mut w = Writer::new(vec![]);
+        w.write_all(&input[..]).unwrap();
+        w.flush().unwrap();
+        assert_eq!(input.len() as u64, w.total_in());
+        let total_out = w.total_out();
+        let compressed = w.into_inner();
+        assert_eq!(compressed.len() as u64, total_out);
+
+        let mut r = Reader::new(Cursor::new(&compressed[..])).unwrap();
+        let mut decompressed = Vec::new();
+        r.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(compressed.len() as u64, r.total_in());
+        assert_eq!(input.len() as u64, r.total_out());
+    }
+
+    #[test]
+    fn roundtrip_generated() {
+        ::testutil::roundtrip((|d: &[u8]| super::compress(d, vec![]),
+                               |d: &[u8]| super::decompress(d, vec![])));
+    }
+}